@@ -0,0 +1,27 @@
+// Generates the C header for the extern "C" functions in src/lib.rs.
+// cbindgen only rewrites the checked-in header when the interface changes.
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        header: Some(
+            "/* C interface to the CO2 injection simulation. Generated by build.rs from src/lib.rs, do not edit. */"
+                .to_string(),
+        ),
+        include_guard: Some("CO2SIM_H".to_string()),
+        sys_includes: vec!["stddef.h".to_string(), "stdint.h".to_string()],
+        no_includes: true,
+        cpp_compat: true,
+        usize_is_size_t: true,
+        ..Default::default()
+    };
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src("src/lib.rs")
+        .generate()
+        .expect("Unable to generate the C header")
+        .write_to_file("include/co2sim.h");
+}
@@ -0,0 +1,74 @@
+/* C interface to the CO2 injection simulation. Generated by build.rs from src/lib.rs, do not edit. */
+
+#ifndef CO2SIM_H
+#define CO2SIM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * The function succeeded
+ */
+#define CO2SIM_OK 0
+
+/**
+ * A required pointer was null
+ */
+#define CO2SIM_NULL_POINTER 1
+
+/**
+ * The inputs failed validation. The message is available from co2sim_last_error.
+ */
+#define CO2SIM_INVALID_INPUT 2
+
+/**
+ * The simulation panicked. The message is available from co2sim_last_error.
+ */
+#define CO2SIM_PANIC 3
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Run the injection simulation and write the snapshot at which every cell was filled, or -1, to snapshots.
+ *
+ * reservoir_matrix holds the nx * ny * nz velocities, depths the nz layer depths and bedrock_indices the
+ * nx * ny indices of the final caprock layer. A max_column_height of 0 never breaks the caprock. Returns
+ * CO2SIM_OK, or one of the error statuses with snapshots left unspecified.
+ *
+ * # Safety
+ *
+ * Every pointer must be valid for the number of elements given by the dimensions, and snapshots for
+ * nx * ny * nz writes. The buffers must not overlap.
+ */
+int co2sim_run(const double *reservoir_matrix,
+               const double *depths,
+               const size_t *bedrock_indices,
+               size_t nx,
+               size_t ny,
+               size_t nz,
+               size_t source_x,
+               size_t source_y,
+               size_t source_z,
+               size_t max_column_height,
+               size_t total_snapshots,
+               int32_t *snapshots);
+
+/**
+ * Copy the message of the last failed call on this thread to buffer, truncated to length - 1 bytes and
+ * NUL-terminated. Returns the length of the full message, so a return value of length or more means it was
+ * truncated. buffer may be null to only query the length.
+ *
+ * # Safety
+ *
+ * buffer must be null or valid for length writes.
+ */
+size_t co2sim_last_error(char *buffer,
+                         size_t length);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CO2SIM_H */
@@ -0,0 +1,223 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::slice;
+
+use numpy::ndarray::{ArrayView1, ArrayView2, ArrayView3};
+
+use rust_backend::config::SimulationConfig;
+use rust_backend::error::SimulationError;
+use rust_backend::injection_simulation::run_injection_simulation;
+use rust_backend::validation::validate_inputs;
+
+// The C interface to the simulation, for reservoir tools in C, C++ or Fortran. build.rs generates the header,
+// include/co2sim.h, from this file.
+//
+// Arrays are passed as flat pointers with their dimensions and are read in C order: the index of the cell
+// (x, y, z) is (x * ny + y) * nz + z, the layout of a contiguous NumPy array. From Fortran pass arrays declared
+// with the dimensions reversed, e.g. reservoir(nz, ny, nx). The outputs are written to buffers allocated by the
+// caller, so no memory changes owner across the interface.
+
+/// The function succeeded
+pub const CO2SIM_OK: c_int = 0;
+/// A required pointer was null
+pub const CO2SIM_NULL_POINTER: c_int = 1;
+/// The inputs failed validation. The message is available from co2sim_last_error.
+pub const CO2SIM_INVALID_INPUT: c_int = 2;
+/// The simulation panicked. The message is available from co2sim_last_error.
+pub const CO2SIM_PANIC: c_int = 3;
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Record the message of a failed call on this thread and return its status
+fn fail(status: c_int, message: String) -> c_int {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+    status
+}
+
+/// Run the injection simulation and write the snapshot at which every cell was filled, or -1, to snapshots.
+///
+/// reservoir_matrix holds the nx * ny * nz velocities, depths the nz layer depths and bedrock_indices the
+/// nx * ny indices of the final caprock layer. A max_column_height of 0 never breaks the caprock. Returns
+/// CO2SIM_OK, or one of the error statuses with snapshots left unspecified.
+///
+/// # Safety
+///
+/// Every pointer must be valid for the number of elements given by the dimensions, and snapshots for
+/// nx * ny * nz writes. The buffers must not overlap.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn co2sim_run(
+    reservoir_matrix: *const f64,
+    depths: *const f64,
+    bedrock_indices: *const usize,
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    source_x: usize,
+    source_y: usize,
+    source_z: usize,
+    max_column_height: usize,
+    total_snapshots: usize,
+    snapshots: *mut i32,
+) -> c_int {
+    if reservoir_matrix.is_null()
+        || depths.is_null()
+        || bedrock_indices.is_null()
+        || snapshots.is_null()
+    {
+        return fail(
+            CO2SIM_NULL_POINTER,
+            "a required pointer is null".to_string(),
+        );
+    }
+    let Some(cells) = nx
+        .checked_mul(ny)
+        .and_then(|columns| columns.checked_mul(nz))
+    else {
+        return fail(
+            CO2SIM_INVALID_INPUT,
+            format!("the grid ({}, {}, {}) is too large", nx, ny, nz),
+        );
+    };
+
+    let reservoir_matrix = slice::from_raw_parts(reservoir_matrix, cells);
+    let depths = slice::from_raw_parts(depths, nz);
+    let bedrock_indices = slice::from_raw_parts(bedrock_indices, nx * ny);
+    let snapshots = slice::from_raw_parts_mut(snapshots, cells);
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let reservoir_matrix = ArrayView3::from_shape((nx, ny, nz), reservoir_matrix)
+            .expect("the length matches the shape");
+        let depths = ArrayView1::from(depths);
+        let bedrock_indices = ArrayView2::from_shape((nx, ny), bedrock_indices)
+            .expect("the length matches the shape");
+        let source = (source_x, source_y, source_z);
+        let config = SimulationConfig {
+            max_column_height: (max_column_height > 0).then_some(max_column_height),
+            total_snapshots,
+            ..Default::default()
+        };
+        validate_inputs(
+            &reservoir_matrix,
+            &depths,
+            &bedrock_indices,
+            source,
+            &config,
+        )?;
+        let result =
+            run_injection_simulation(reservoir_matrix, depths, bedrock_indices, source, &config);
+        for (out, &snapshot) in snapshots.iter_mut().zip(result.iter()) {
+            *out = snapshot;
+        }
+        Ok::<(), SimulationError>(())
+    }));
+    match result {
+        Ok(Ok(())) => CO2SIM_OK,
+        Ok(Err(error)) => fail(CO2SIM_INVALID_INPUT, error.to_string()),
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "the simulation panicked".to_string());
+            fail(CO2SIM_PANIC, message)
+        }
+    }
+}
+
+/// Copy the message of the last failed call on this thread to buffer, truncated to length - 1 bytes and
+/// NUL-terminated. Returns the length of the full message, so a return value of length or more means it was
+/// truncated. buffer may be null to only query the length.
+///
+/// # Safety
+///
+/// buffer must be null or valid for length writes.
+#[no_mangle]
+pub unsafe extern "C" fn co2sim_last_error(buffer: *mut c_char, length: usize) -> usize {
+    LAST_ERROR.with(|last_error| {
+        let message = last_error.borrow();
+        if !buffer.is_null() && length > 0 {
+            let copied = message.len().min(length - 1);
+            let buffer = slice::from_raw_parts_mut(buffer as *mut u8, length);
+            buffer[..copied].copy_from_slice(&message.as_bytes()[..copied]);
+            buffer[copied] = 0;
+        }
+        message.len()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use numpy::ndarray::{s, Array1, Array2, Array3};
+    use rust_backend::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+
+    #[test]
+    fn test_run_through_the_c_interface() {
+        let (nx, ny, nz) = (4, 3, 5);
+        let mut reservoir = Array3::from_elem((nx, ny, nz), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        let depths = Array1::from_iter((0..nz).map(|z| z as f64));
+        let bedrock_indices = Array2::<usize>::zeros((nx, ny));
+        let mut snapshots = vec![0; nx * ny * nz];
+
+        let status = unsafe {
+            co2sim_run(
+                reservoir.as_ptr(),
+                depths.as_ptr(),
+                bedrock_indices.as_ptr(),
+                nx,
+                ny,
+                nz,
+                1,
+                1,
+                1,
+                0,
+                10,
+                snapshots.as_mut_ptr(),
+            )
+        };
+        assert_eq!(status, CO2SIM_OK);
+        let config = SimulationConfig {
+            max_column_height: None,
+            total_snapshots: 10,
+            ..Default::default()
+        };
+        let expected = run_injection_simulation(
+            reservoir.view(),
+            depths.view(),
+            bedrock_indices.view(),
+            (1, 1, 1),
+            &config,
+        );
+        assert_eq!(snapshots, expected.iter().copied().collect::<Vec<_>>());
+
+        // A source outside the grid is reported through the last error
+        let status = unsafe {
+            co2sim_run(
+                reservoir.as_ptr(),
+                depths.as_ptr(),
+                bedrock_indices.as_ptr(),
+                nx,
+                ny,
+                nz,
+                9,
+                1,
+                1,
+                0,
+                10,
+                snapshots.as_mut_ptr(),
+            )
+        };
+        assert_eq!(status, CO2SIM_INVALID_INPUT);
+        let length = unsafe { co2sim_last_error(std::ptr::null_mut(), 0) };
+        let mut buffer = vec![0 as c_char; length + 1];
+        unsafe { co2sim_last_error(buffer.as_mut_ptr(), buffer.len()) };
+        let message = unsafe { std::ffi::CStr::from_ptr(buffer.as_ptr()) };
+        assert!(message.to_str().unwrap().contains("outside the grid"));
+        assert_eq!(message.to_bytes().len(), length);
+    }
+}
@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+
+use crate::injection_simulation::BreachEvent;
+use crate::sparse::SparseGrid;
+
+/// A pool of CO2 under a seal. The primary accumulation is fed by the wells, and every breach feeds a secondary
+/// accumulation with the CO2 that escapes up through the broken cells and pools under the next seal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Accumulation {
+    /// The index of the first breach event that fed the accumulation, or None for the primary accumulation.
+    /// Cells that broke together feed a single accumulation.
+    pub fed_by: Option<usize>,
+    /// The accumulation whose CO2 column broke the seal, or None for the primary accumulation
+    pub parent: Option<usize>,
+    /// Number of cells filled in the accumulation, including any of its broken caprock cells that filled
+    pub cells: usize,
+    /// The indices of the breach events in the seal above the accumulation, which feed accumulations of their own
+    pub breaches: Vec<usize>,
+}
+
+impl Accumulation {
+    /// Whether the accumulation broke through its own seal
+    pub fn breached(&self) -> bool {
+        !self.breaches.is_empty()
+    }
+}
+
+/// The accumulations of a run, followed fill by fill as the breaches happen, see `find_accumulations`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccumulationTracker {
+    directions: Vec<(i32, i32)>,
+    // The accumulation of every filled or broken cell, or -1
+    pools: SparseGrid<i32>,
+    counted: SparseGrid<bool>,
+    // The first breach event of every secondary accumulation, and the number of cells filled when it broke
+    groups: Vec<(usize, usize)>,
+    // The accumulation of every breach event
+    event_pools: Vec<usize>,
+    // The accumulation whose CO2 column broke into each accumulation, None for the primary accumulation
+    parents: Vec<Option<usize>>,
+    // Number of cells filled in every accumulation
+    cells: Vec<usize>,
+    // Number of cells filled in every accumulation and the accumulations it fed, directly or through others
+    fed_cells: Vec<usize>,
+}
+
+impl AccumulationTracker {
+    pub fn new(dims: (usize, usize, usize), directions: &[(i32, i32)]) -> Self {
+        AccumulationTracker {
+            directions: directions.to_vec(),
+            pools: SparseGrid::new(dims, -1),
+            counted: SparseGrid::new(dims, false),
+            groups: Vec::new(),
+            event_pools: Vec::new(),
+            parents: vec![None],
+            cells: vec![0],
+            fed_cells: vec![0],
+        }
+    }
+
+    // The accumulation of the cell, if it is on the grid and filled or broken
+    fn pool(&self, x: i32, y: i32, z: i32) -> Option<usize> {
+        let (nx, ny, nz) = self.pools.dim();
+        if x < 0 || y < 0 || z < 0 || x >= nx as i32 || y >= ny as i32 || z >= nz as i32 {
+            return None;
+        }
+        let pool = self.pools.get((x as usize, y as usize, z as usize));
+        (pool >= 0).then_some(pool as usize)
+    }
+
+    /// Record the next breach event. Events that broke together feed the same accumulation, which the broken
+    /// cells lead the CO2 into from the moment they broke. They are often passed through without being filled.
+    pub fn breach(&mut self, event: &BreachEvent) {
+        let index = self.event_pools.len();
+        if self.groups.last().map(|&(_, cells_filled)| cells_filled) != Some(event.cells_filled) {
+            // The CO2 column below the first broken cell of a group is in the accumulation that broke the seal
+            let (x, y, z) = event.cell;
+            let parent = self.pool(x as i32, y as i32, z as i32 + 1).unwrap_or(0);
+            self.groups.push((index, event.cells_filled));
+            self.parents.push(Some(parent));
+            self.cells.push(0);
+            self.fed_cells.push(0);
+        }
+        let pool = self.groups.len();
+        self.event_pools.push(pool);
+        self.pools.set(event.cell, pool as i32);
+    }
+
+    /// Record the next filled cell. It belongs to the accumulation of the filled or broken cell it was reached
+    /// from, the cell below it first, then a cell diagonally below it along one of the lateral directions, then
+    /// a lateral neighbour. Cells that were not reached from such a cell, like the sources, are primary.
+    pub fn fill(&mut self, cell: (usize, usize, usize)) {
+        if self.counted.get(cell) {
+            return;
+        }
+        self.counted.set(cell, true);
+
+        let (x, y, z) = (cell.0 as i32, cell.1 as i32, cell.2 as i32);
+        let pool = self
+            .pool(x, y, z)
+            .or_else(|| self.pool(x, y, z + 1))
+            .or_else(|| {
+                self.directions
+                    .iter()
+                    .find_map(|&(dx, dy)| self.pool(x - dx, y - dy, z + 1))
+            })
+            .or_else(|| {
+                self.directions
+                    .iter()
+                    .find_map(|&(dx, dy)| self.pool(x - dx, y - dy, z))
+            })
+            .unwrap_or(0);
+        self.pools.set(cell, pool as i32);
+        self.cells[pool] += 1;
+        let mut fed = Some(pool);
+        while let Some(pool) = fed {
+            self.fed_cells[pool] += 1;
+            fed = self.parents[pool];
+        }
+    }
+
+    /// Number of cells filled with CO2 that passed through the broken cell of the breach event, in its
+    /// accumulation and the accumulations above it that it fed
+    pub fn cells_fed_by(&self, event: usize) -> usize {
+        self.fed_cells[self.event_pools[event]]
+    }
+
+    /// The primary accumulation, at index 0, and one secondary accumulation per group of breach events
+    pub fn accumulations(&self) -> Vec<Accumulation> {
+        let mut accumulations: Vec<Accumulation> = (0..self.cells.len())
+            .map(|pool| Accumulation {
+                fed_by: pool.checked_sub(1).map(|group| self.groups[group].0),
+                parent: self.parents[pool],
+                cells: self.cells[pool],
+                breaches: Vec::new(),
+            })
+            .collect();
+        for (event, &pool) in self.event_pools.iter().enumerate() {
+            if let Some(parent) = self.parents[pool] {
+                accumulations[parent].breaches.push(event);
+            }
+        }
+        accumulations
+    }
+}
+
+/// Split the filled cells into the primary accumulation, at index 0, and one secondary accumulation per breach,
+/// following the cells in the order they were filled, see `AccumulationTracker`
+pub fn find_accumulations(
+    fill_order: &[(usize, usize, usize)],
+    breach_events: &[BreachEvent],
+    dims: (usize, usize, usize),
+    directions: &[(i32, i32)],
+) -> Vec<Accumulation> {
+    let mut tracker = AccumulationTracker::new(dims, directions);
+    let mut events = breach_events.iter().peekable();
+    for (index, &cell) in fill_order.iter().enumerate() {
+        while let Some(event) = events.next_if(|event| event.cells_filled <= index) {
+            tracker.breach(event);
+        }
+        tracker.fill(cell);
+    }
+    for event in events {
+        tracker.breach(event);
+    }
+    tracker.accumulations()
+}
+
+/// The accumulations the CO2 migrated through to reach the given accumulation, from the primary accumulation
+/// to the given one
+pub fn migration_chain(accumulations: &[Accumulation], index: usize) -> Vec<usize> {
+    let mut chain = vec![index];
+    while let Some(parent) = accumulations[*chain.last().unwrap()].parent {
+        chain.push(parent);
+    }
+    chain.reverse();
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accumulations_follow_the_breaches() {
+        let event = |cell, cells_filled| BreachEvent {
+            cell,
+            snapshot_index: 0,
+            cells_filled,
+            resealed_at: None,
+        };
+        // A column that breaks the seal at z = 2, and the pool above it that breaks the seal at z = 0
+        let fill_order = [
+            (1, 0, 4),
+            (0, 0, 3),
+            (1, 0, 3),
+            (2, 0, 3),
+            (1, 0, 1),
+            (0, 0, 1),
+            (1, 0, 0),
+        ];
+        // The CO2 passes through the broken cell at z = 2 without filling it
+        let breach_events = [event((1, 0, 2), 4), event((1, 0, 0), 6)];
+        let directions = [(1, 0), (-1, 0)];
+        let accumulations = find_accumulations(&fill_order, &breach_events, (3, 1, 5), &directions);
+
+        assert_eq!(accumulations.len(), 3);
+        assert_eq!(accumulations[0].cells, 4);
+        assert_eq!(accumulations[0].breaches, vec![0]);
+        assert_eq!(accumulations[1].fed_by, Some(0));
+        assert_eq!(accumulations[1].cells, 2);
+        assert!(accumulations[1].breached());
+        assert_eq!(accumulations[2].cells, 1);
+        assert!(!accumulations[2].breached());
+        assert_eq!(migration_chain(&accumulations, 2), vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,322 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+use numpy::ndarray::{Array1, Array2, Array3};
+use serde_json::{json, Value};
+
+use crate::config::SimulationConfig;
+use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+use crate::error::SimulationError;
+use crate::injection_simulation::Simulation;
+use crate::utils::compute_bedrock_indices;
+
+/// The coarsest resolution the validation runs at
+pub const MIN_RESOLUTION: usize = 16;
+
+/// The size of a sharp-interface plume injected at the axis of a radially symmetric seal, in cells laterally and
+/// layers vertically
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlumeGeometry {
+    /// The radius of the footprint of the plume
+    pub radius: f64,
+    /// The thickness of the plume at the well, from the seal down to the interface
+    pub thickness: f64,
+}
+
+/// A geometry with a closed-form sharp-interface solution, see `validate_analytic`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalyticCase {
+    /// A horizontal seal. In the buoyancy-dominated limit the CO2 spreads as a disc one layer thick under the
+    /// seal, so a volume V has the radius sqrt(V / π).
+    FlatSeal,
+    /// A paraboloid dome whose seal deepens by c r² layers at a radius of r cells. The CO2 fills the dome from
+    /// the crest down to a flat interface at D layers, which holds the volume π D² / 2c, so D = sqrt(2 c V / π)
+    /// and the radius is sqrt(D / c).
+    ParaboloidDome,
+}
+
+impl AnalyticCase {
+    pub const ALL: [AnalyticCase; 2] = [AnalyticCase::FlatSeal, AnalyticCase::ParaboloidDome];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnalyticCase::FlatSeal => "flat_seal",
+            AnalyticCase::ParaboloidDome => "paraboloid_dome",
+        }
+    }
+}
+
+impl fmt::Display for AnalyticCase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for AnalyticCase {
+    type Err = String;
+
+    /// A case by name: flat_seal or paraboloid_dome
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = name.to_lowercase().replace('-', "_");
+        AnalyticCase::ALL
+            .into_iter()
+            .find(|case| case.name() == name)
+            .ok_or_else(|| {
+                format!(
+                    "unknown analytic case {}, expected flat_seal or paraboloid_dome",
+                    name
+                )
+            })
+    }
+}
+
+/// The plume under a flat seal holding the given number of cells, see `AnalyticCase::FlatSeal`
+pub fn flat_seal_plume(volume: f64) -> PlumeGeometry {
+    PlumeGeometry {
+        radius: (volume / PI).sqrt(),
+        thickness: 1.0,
+    }
+}
+
+/// The plume under a paraboloid dome with the given curvature in layers per cell², holding the given number of
+/// cells, see `AnalyticCase::ParaboloidDome`
+pub fn paraboloid_dome_plume(volume: f64, curvature: f64) -> PlumeGeometry {
+    let thickness = (2.0 * curvature * volume / PI).sqrt();
+    PlumeGeometry {
+        radius: (thickness / curvature).sqrt(),
+        thickness,
+    }
+}
+
+/// The simulated plume measured like the analytic one, at a number of filled cells
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValidationPoint {
+    pub cells_filled: usize,
+    pub analytic: PlumeGeometry,
+    /// The radius of the circle with the area of the footprint, and the thickness of the well column
+    pub simulated: PlumeGeometry,
+    /// The distance from the well to the farthest filled column, which shows how round the footprint is
+    pub max_radius: f64,
+}
+
+/// Summary of the relative errors of a quantity over the validation points
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorMetrics {
+    pub mean_abs: f64,
+    pub max_abs: f64,
+    pub rms: f64,
+}
+
+impl ErrorMetrics {
+    fn of(errors: impl Iterator<Item = f64>) -> Self {
+        let errors: Vec<f64> = errors.collect();
+        let n = errors.len().max(1) as f64;
+        ErrorMetrics {
+            mean_abs: errors.iter().map(|e| e.abs()).sum::<f64>() / n,
+            max_abs: errors.iter().fold(0.0, |max, e| e.abs().max(max)),
+            rms: (errors.iter().map(|e| e * e).sum::<f64>() / n).sqrt(),
+        }
+    }
+
+    fn to_json(self) -> Value {
+        json!({"mean_abs": self.mean_abs, "max_abs": self.max_abs, "rms": self.rms})
+    }
+}
+
+/// The simulated plume against the analytic solution as it grows, see `validate_analytic`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationReport {
+    pub case: AnalyticCase,
+    pub resolution: usize,
+    pub points: Vec<ValidationPoint>,
+}
+
+impl ValidationReport {
+    /// Relative error of the footprint radius
+    pub fn radius_error(&self) -> ErrorMetrics {
+        ErrorMetrics::of(
+            self.points
+                .iter()
+                .map(|point| point.simulated.radius / point.analytic.radius - 1.0),
+        )
+    }
+
+    /// Relative error of the distance to the farthest filled column against the analytic radius
+    pub fn max_radius_error(&self) -> ErrorMetrics {
+        ErrorMetrics::of(
+            self.points
+                .iter()
+                .map(|point| point.max_radius / point.analytic.radius - 1.0),
+        )
+    }
+
+    /// Relative error of the thickness at the well
+    pub fn thickness_error(&self) -> ErrorMetrics {
+        ErrorMetrics::of(
+            self.points
+                .iter()
+                .map(|point| point.simulated.thickness / point.analytic.thickness - 1.0),
+        )
+    }
+
+    pub fn to_json(&self) -> Value {
+        let points: Vec<Value> = self
+            .points
+            .iter()
+            .map(|point| {
+                json!({
+                    "cells_filled": point.cells_filled,
+                    "analytic_radius": point.analytic.radius,
+                    "analytic_thickness": point.analytic.thickness,
+                    "radius": point.simulated.radius,
+                    "max_radius": point.max_radius,
+                    "thickness": point.simulated.thickness,
+                })
+            })
+            .collect();
+        json!({
+            "case": self.case.name(),
+            "resolution": self.resolution,
+            "radius_error": self.radius_error().to_json(),
+            "max_radius_error": self.max_radius_error().to_json(),
+            "thickness_error": self.thickness_error().to_json(),
+            "points": points,
+        })
+    }
+}
+
+/// Run the simulation on the geometry of the case, on a grid of resolution x resolution columns, and compare the
+/// plume with the analytic solution at the given number of points as it grows, up to two cells from the edge of
+/// the grid. The config sets the engine, e.g. the front ordering or stencil, but not the breach criteria, since
+/// the seals of the cases never break.
+pub fn validate_analytic(
+    case: AnalyticCase,
+    resolution: usize,
+    n_points: usize,
+    config: &SimulationConfig,
+) -> Result<ValidationReport, SimulationError> {
+    if resolution < MIN_RESOLUTION {
+        return Err(SimulationError::InvalidValue {
+            argument: "resolution".to_string(),
+            message: format!("must be at least {}, got {}", MIN_RESOLUTION, resolution),
+        });
+    }
+    if n_points == 0 {
+        return Err(SimulationError::InvalidValue {
+            argument: "n_points".to_string(),
+            message: "must be at least 1".to_string(),
+        });
+    }
+    let n = resolution;
+    let center = n / 2;
+    let max_radius = (center - 2) as f64;
+    // The dome deepens by a quarter of the resolution in layers at the edges of the grid
+    let curvature = 1.0 / n as f64;
+    let distance = |x: usize, y: usize| {
+        ((x as f64 - center as f64).powi(2) + (y as f64 - center as f64).powi(2)).sqrt()
+    };
+    // The lowest layer of the seal in every column
+    let (seal, max_volume) = match case {
+        AnalyticCase::FlatSeal => (Array2::zeros((n, n)), PI * max_radius.powi(2)),
+        AnalyticCase::ParaboloidDome => {
+            let seal = Array2::from_shape_fn((n, n), |(x, y)| {
+                (curvature * distance(x, y).powi(2)).round() as usize
+            });
+            let thickness = curvature * max_radius.powi(2);
+            (seal, PI * thickness.powi(2) / (2.0 * curvature))
+        }
+    };
+    let nz = seal.iter().max().unwrap() + 3;
+    let mut reservoir_matrix = Array3::from_elem((n, n, nz), VELOCITY_RESERVOIR);
+    for ((x, y), &base) in seal.indexed_iter() {
+        for z in 0..=base {
+            reservoir_matrix[[x, y, z]] = VELOCITY_CAPROCK;
+        }
+    }
+    let depths = Array1::from_iter((0..nz).map(|z| z as f64));
+    let bedrock_indices = compute_bedrock_indices(
+        &reservoir_matrix.view(),
+        SimulationConfig::default().velocity_classifier,
+    );
+    let config = SimulationConfig {
+        max_column_height: None,
+        ..config.clone()
+    };
+    let mut simulation = Simulation::try_new(
+        reservoir_matrix.view(),
+        depths.view(),
+        bedrock_indices.view(),
+        (center, center, 1),
+        &config,
+    )?;
+
+    let mut points = Vec::with_capacity(n_points);
+    let mut column_heights = Array2::<usize>::zeros((n, n));
+    let mut measured = 0;
+    for k in 1..=n_points {
+        let volume = (max_volume * k as f64 / n_points as f64).round() as usize;
+        simulation.advance(volume.saturating_sub(simulation.cells_filled()));
+        for &(x, y, _) in &simulation.fill_order()[measured..] {
+            column_heights[[x, y]] += 1;
+        }
+        measured = simulation.cells_filled();
+
+        let footprint = column_heights.iter().filter(|&&height| height > 0).count();
+        let farthest = column_heights
+            .indexed_iter()
+            .filter(|(_, &height)| height > 0)
+            .map(|((x, y), _)| distance(x, y))
+            .fold(0.0, f64::max);
+        let analytic = match case {
+            AnalyticCase::FlatSeal => flat_seal_plume(measured as f64),
+            AnalyticCase::ParaboloidDome => paraboloid_dome_plume(measured as f64, curvature),
+        };
+        points.push(ValidationPoint {
+            cells_filled: measured,
+            analytic,
+            simulated: PlumeGeometry {
+                radius: (footprint as f64 / PI).sqrt(),
+                thickness: column_heights[[center, center]] as f64,
+            },
+            max_radius: farthest,
+        });
+    }
+    Ok(ValidationReport {
+        case,
+        resolution,
+        points,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ordering::FrontOrdering;
+
+    #[test]
+    fn test_plumes_match_the_analytic_solutions() {
+        let flat = validate_analytic(AnalyticCase::FlatSeal, 32, 5, &Default::default()).unwrap();
+        assert_eq!(flat.points.len(), 5);
+        // The plume stays one layer thick and keeps its volume, but the 8-connected front spreads as a square
+        assert!(flat.radius_error().max_abs < 1e-9);
+        assert!(flat.thickness_error().max_abs < 1e-9);
+        assert!(flat.max_radius_error().mean_abs > 0.15);
+        // Ordering the front by the distance from the well makes it round
+        let config = SimulationConfig {
+            front_ordering: FrontOrdering::WellDistance,
+            ..Default::default()
+        };
+        let round = validate_analytic(AnalyticCase::FlatSeal, 32, 5, &config).unwrap();
+        assert!(round.max_radius_error().max_abs < 0.1);
+
+        let dome =
+            validate_analytic(AnalyticCase::ParaboloidDome, 48, 5, &Default::default()).unwrap();
+        assert!(dome.radius_error().rms < 0.15);
+        assert!(dome.thickness_error().rms < 0.15);
+        assert_eq!(dome.to_json()["points"].as_array().unwrap().len(), 5);
+
+        assert_eq!("paraboloid-dome".parse(), Ok(AnalyticCase::ParaboloidDome));
+        assert!(validate_analytic(AnalyticCase::FlatSeal, 8, 5, &Default::default()).is_err());
+    }
+}
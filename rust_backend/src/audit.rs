@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+/// The CO2 tallies at the end of a snapshot, in cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub snapshot_index: i32,
+    /// Cells filled by the injection so far
+    pub injected: usize,
+    /// Cells holding free CO2 in the grid, counted from the reservoir state
+    pub mobile: usize,
+    /// Cells of CO2 held by residual trapping. Not modelled yet, so always zero.
+    pub trapped: usize,
+    /// Cells of CO2 dissolved in brine. Not modelled yet, so always zero.
+    pub dissolved: usize,
+    /// Cells of CO2 that left the grid through open boundaries. The grid is closed, so always zero.
+    pub leaked: usize,
+}
+
+impl LedgerEntry {
+    /// Total CO2 accounted for in the grid and outside it
+    pub fn accounted(&self) -> usize {
+        self.mobile + self.trapped + self.dissolved + self.leaked
+    }
+
+    /// Injected minus accounted CO2. Zero when the mass balance holds.
+    pub fn imbalance(&self) -> i64 {
+        self.injected as i64 - self.accounted() as i64
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.imbalance() == 0
+    }
+}
+
+/// Mass-conservation ledger with one entry per snapshot
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MassLedger {
+    entries: Vec<LedgerEntry>,
+}
+
+impl MassLedger {
+    pub fn record(&mut self, entry: LedgerEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    /// Whether every snapshot balances
+    pub fn is_balanced(&self) -> bool {
+        self.entries.iter().all(LedgerEntry::is_balanced)
+    }
+}
@@ -0,0 +1,339 @@
+use std::error::Error;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::benchmarks::BenchmarkModel;
+use crate::config::SimulationConfig;
+use crate::error::SimulationError;
+use crate::injection_simulation::Simulation;
+
+/// The key outputs of a run on a benchmark model, which a rewrite of the engine should keep
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BaselineResult {
+    /// The name of the benchmark model, see `BenchmarkModel::name`
+    pub model: String,
+    /// Number of cells holding CO2 at the end of every snapshot
+    pub volume_curve: Vec<usize>,
+    /// Number of cells filled when each caprock cell broke, in the order they broke
+    pub breach_times: Vec<usize>,
+    /// Number of (x, y) columns holding CO2 at the end of the run
+    pub footprint: usize,
+}
+
+impl BaselineResult {
+    /// Run the simulation on the model and record its key outputs
+    pub fn measure(
+        model: BenchmarkModel,
+        resolution: usize,
+        config: &SimulationConfig,
+    ) -> Result<Self, SimulationError> {
+        let case = model.build(resolution)?;
+        let mut simulation = Simulation::try_new(
+            case.grid.reservoir_matrix.view(),
+            case.grid.depths.view(),
+            case.grid.bedrock_indices.view(),
+            case.source,
+            config,
+        )?;
+        simulation.run();
+        let volume_curve = simulation
+            .snapshot_cell_counts()
+            .iter()
+            .scan(0, |volume, &count| {
+                *volume += count;
+                Some(*volume)
+            })
+            .collect();
+        Ok(BaselineResult {
+            model: model.name().to_string(),
+            volume_curve,
+            breach_times: simulation
+                .breach_events()
+                .iter()
+                .map(|event| event.cells_filled)
+                .collect(),
+            footprint: simulation.footprint_cells(),
+        })
+    }
+}
+
+/// Reference results of the benchmark models, stored as JSON to check later versions of the engine against, see
+/// `compare_baseline`. The settings of the runs are stored with them, so they are repeated exactly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Baseline {
+    /// The version of the crate that recorded the baseline
+    pub crate_version: String,
+    pub resolution: usize,
+    pub max_column_height: Option<usize>,
+    pub total_snapshots: usize,
+    pub results: Vec<BaselineResult>,
+}
+
+impl Baseline {
+    /// Run every benchmark model at the resolution and record the results
+    pub fn record(
+        resolution: usize,
+        max_column_height: Option<usize>,
+        total_snapshots: usize,
+    ) -> Result<Self, SimulationError> {
+        let mut baseline = Baseline {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            resolution,
+            max_column_height,
+            total_snapshots,
+            results: Vec::new(),
+        };
+        let config = baseline.config();
+        for model in BenchmarkModel::ALL {
+            baseline
+                .results
+                .push(BaselineResult::measure(model, resolution, &config)?);
+        }
+        Ok(baseline)
+    }
+
+    /// The config the baseline was recorded with
+    pub fn config(&self) -> SimulationConfig {
+        SimulationConfig {
+            max_column_height: self.max_column_height,
+            total_snapshots: self.total_snapshots,
+            ..Default::default()
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+/// How far the results may drift from the baseline, as relative differences
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerances {
+    /// Of the volume at any snapshot
+    pub volume: f64,
+    /// Of the number of breaches, and of the number of cells filled at each breach
+    pub breach_time: f64,
+    /// Of the footprint at the end
+    pub footprint: f64,
+}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        Tolerances {
+            volume: 0.01,
+            breach_time: 0.05,
+            footprint: 0.05,
+        }
+    }
+}
+
+/// The largest difference of one output of one model from the baseline
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drift {
+    pub model: String,
+    /// volume_curve, breach_count, breach_times or footprint
+    pub metric: &'static str,
+    pub baseline: f64,
+    pub current: f64,
+    /// |current - baseline| / baseline, with a baseline of zero counting as one
+    pub relative: f64,
+    pub tolerance: f64,
+}
+
+impl Drift {
+    pub fn exceeds_tolerance(&self) -> bool {
+        self.relative > self.tolerance
+    }
+}
+
+/// Every output of every model against the baseline, see `compare_results`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaselineComparison {
+    pub drifts: Vec<Drift>,
+}
+
+impl BaselineComparison {
+    /// Whether every output is within its tolerance
+    pub fn passed(&self) -> bool {
+        !self.drifts.iter().any(Drift::exceeds_tolerance)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let drifts: Vec<Value> = self
+            .drifts
+            .iter()
+            .map(|drift| {
+                json!({
+                    "model": drift.model,
+                    "metric": drift.metric,
+                    "baseline": drift.baseline,
+                    "current": drift.current,
+                    "relative": drift.relative,
+                    "tolerance": drift.tolerance,
+                    "exceeds_tolerance": drift.exceeds_tolerance(),
+                })
+            })
+            .collect();
+        json!({"passed": self.passed(), "drifts": drifts})
+    }
+}
+
+/// The largest relative difference between pairs of values, with the pair of values it was found at, the last
+/// pair of a tie
+fn largest_difference(pairs: impl Iterator<Item = (usize, usize)>) -> (f64, f64, f64) {
+    pairs
+        .map(|(baseline, current)| {
+            let relative = baseline.abs_diff(current) as f64 / baseline.max(1) as f64;
+            (baseline as f64, current as f64, relative)
+        })
+        .reduce(|largest, pair| if pair.2 >= largest.2 { pair } else { largest })
+        .unwrap_or((0.0, 0.0, 0.0))
+}
+
+/// Compare results with the baseline. The volume curves are compared snapshot by snapshot, a curve that ended
+/// early keeping its last volume, and the breach times breach by breach as far as both have breaches. A model
+/// missing from the results drifts by the whole of every output.
+pub fn compare_results(
+    baseline: &Baseline,
+    results: &[BaselineResult],
+    tolerances: &Tolerances,
+) -> BaselineComparison {
+    let mut drifts = Vec::new();
+    for expected in &baseline.results {
+        let missing = BaselineResult {
+            model: expected.model.clone(),
+            volume_curve: Vec::new(),
+            breach_times: Vec::new(),
+            footprint: 0,
+        };
+        let current = results
+            .iter()
+            .find(|result| result.model == expected.model)
+            .unwrap_or(&missing);
+        let volume_at = |curve: &[usize], i: usize| curve.get(i).or(curve.last()).copied();
+        let n_snapshots = expected.volume_curve.len().max(current.volume_curve.len());
+        let metrics = [
+            (
+                "volume_curve",
+                largest_difference((0..n_snapshots).map(|i| {
+                    (
+                        volume_at(&expected.volume_curve, i).unwrap_or(0),
+                        volume_at(&current.volume_curve, i).unwrap_or(0),
+                    )
+                })),
+                tolerances.volume,
+            ),
+            (
+                "breach_count",
+                largest_difference(std::iter::once((
+                    expected.breach_times.len(),
+                    current.breach_times.len(),
+                ))),
+                tolerances.breach_time,
+            ),
+            (
+                "breach_times",
+                largest_difference(
+                    expected
+                        .breach_times
+                        .iter()
+                        .copied()
+                        .zip(current.breach_times.iter().copied()),
+                ),
+                tolerances.breach_time,
+            ),
+            (
+                "footprint",
+                largest_difference(std::iter::once((expected.footprint, current.footprint))),
+                tolerances.footprint,
+            ),
+        ];
+        for (metric, (baseline, current, relative), tolerance) in metrics {
+            drifts.push(Drift {
+                model: expected.model.clone(),
+                metric,
+                baseline,
+                current,
+                relative,
+                tolerance,
+            });
+        }
+    }
+    BaselineComparison { drifts }
+}
+
+/// Run the benchmark models with the settings of the baseline and compare the results with it
+pub fn compare_baseline(
+    baseline: &Baseline,
+    tolerances: &Tolerances,
+) -> Result<BaselineComparison, SimulationError> {
+    let config = baseline.config();
+    let mut results = Vec::new();
+    for expected in &baseline.results {
+        let model: BenchmarkModel =
+            expected
+                .model
+                .parse()
+                .map_err(|message| SimulationError::InvalidValue {
+                    argument: "baseline".to_string(),
+                    message,
+                })?;
+        results.push(BaselineResult::measure(
+            model,
+            baseline.resolution,
+            &config,
+        )?);
+    }
+    Ok(compare_results(baseline, &results, tolerances))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_benchmarks_match_the_stored_baseline() {
+        let baseline: Baseline =
+            serde_json::from_str(include_str!("../baselines/benchmarks.json")).unwrap();
+        let comparison = compare_baseline(&baseline, &Tolerances::default()).unwrap();
+        assert!(comparison.passed(), "{}", comparison.to_json());
+        assert_eq!(comparison.drifts.len(), 4 * BenchmarkModel::ALL.len());
+
+        // Drift past a tolerance is reported with the values it was found at
+        let mut results = baseline.results.clone();
+        let thief_zone = &mut results[3];
+        let last = thief_zone.volume_curve.len() - 1;
+        thief_zone.volume_curve[last] += thief_zone.volume_curve[last] / 50;
+        thief_zone.breach_times.truncate(10);
+        let comparison = compare_results(&baseline, &results[1..], &Tolerances::default());
+        assert!(!comparison.passed());
+        let drifted: Vec<(&str, &str)> = comparison
+            .drifts
+            .iter()
+            .filter(|drift| drift.exceeds_tolerance())
+            .map(|drift| (drift.model.as_str(), drift.metric))
+            .collect();
+        assert_eq!(
+            drifted,
+            [
+                ("flat_aquifer", "volume_curve"),
+                ("flat_aquifer", "footprint"),
+                ("layered_thief_zone", "volume_curve"),
+                ("layered_thief_zone", "breach_count"),
+            ]
+        );
+        let volume = &comparison.drifts[12];
+        assert_eq!(
+            volume.baseline as usize,
+            baseline.results[3].volume_curve[last]
+        );
+        assert!((volume.relative - 0.02).abs() < 1e-3);
+    }
+}
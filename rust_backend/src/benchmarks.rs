@@ -0,0 +1,246 @@
+use std::fmt;
+use std::str::FromStr;
+
+use numpy::ndarray::{Array1, Array3};
+
+use crate::config::SimulationConfig;
+use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+use crate::error::SimulationError;
+use crate::input_cache::GridInputs;
+use crate::utils::compute_bedrock_indices;
+
+/// Depth of the top of the grid in meters
+const TOP_DEPTH: f64 = 800.0;
+/// Thickness of the grid in meters, whatever the resolution
+const THICKNESS: f64 = 200.0;
+/// The coarsest resolution at which every model keeps its structure
+pub const MIN_RESOLUTION: usize = 16;
+
+/// A reference model for comparing the performance and results of runs, built at any resolution, see
+/// `BenchmarkModel::build`. The models are built from the same shapes at every resolution, so a finer grid
+/// resolves the same geometry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchmarkModel {
+    /// A horizontal seal over a uniform aquifer, with the well in the center
+    FlatAquifer,
+    /// A dome-shaped seal over an aquifer split by a thin shale with a window off the crest, like the
+    /// intra-reservoir shales at Sleipner. The well is under the shale at the crest.
+    SleipnerDome,
+    /// A seal dipping towards a sealing fault, with the block past the fault thrown down. The well is at the
+    /// deep end, so the plume migrates up-dip and collects against the fault.
+    TiltedFaultBlock,
+    /// A flat seal over sand layers separated by thin shales with windows at alternating ends, so the CO2
+    /// crosses every layer before it reaches the thief zone under the seal. The well is under the deepest shale.
+    LayeredThiefZone,
+}
+
+/// A benchmark model built at a resolution: the grid and the well
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkCase {
+    pub model: BenchmarkModel,
+    pub resolution: usize,
+    pub grid: GridInputs,
+    pub source: (usize, usize, usize),
+}
+
+impl BenchmarkModel {
+    pub const ALL: [BenchmarkModel; 4] = [
+        BenchmarkModel::FlatAquifer,
+        BenchmarkModel::SleipnerDome,
+        BenchmarkModel::TiltedFaultBlock,
+        BenchmarkModel::LayeredThiefZone,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BenchmarkModel::FlatAquifer => "flat_aquifer",
+            BenchmarkModel::SleipnerDome => "sleipner_dome",
+            BenchmarkModel::TiltedFaultBlock => "tilted_fault_block",
+            BenchmarkModel::LayeredThiefZone => "layered_thief_zone",
+        }
+    }
+
+    /// Build the model on a grid of resolution x resolution x resolution / 2 cells, at least
+    /// MIN_RESOLUTION. The grid spans the same 200 m below 800 m at every resolution.
+    pub fn build(&self, resolution: usize) -> Result<BenchmarkCase, SimulationError> {
+        if resolution < MIN_RESOLUTION {
+            return Err(SimulationError::InvalidValue {
+                argument: "resolution".to_string(),
+                message: format!("must be at least {}, got {}", MIN_RESOLUTION, resolution),
+            });
+        }
+        let n = resolution;
+        let nz = resolution / 2;
+        let mut reservoir_matrix = Array3::from_elem((n, n, nz), VELOCITY_RESERVOIR);
+        let center = n / 2;
+        let source = match self {
+            BenchmarkModel::FlatAquifer => {
+                let base = nz / 4;
+                for x in 0..n {
+                    for y in 0..n {
+                        seal(&mut reservoir_matrix, x, y, base);
+                    }
+                }
+                (center, center, base + 1)
+            }
+            BenchmarkModel::SleipnerDome => {
+                // The seal deepens with the square of the distance from the crest, by a quarter of the grid at
+                // the corners, and the shale follows it a third of the grid further down
+                let crest = nz / 8;
+                let relief = (nz / 4) as f64;
+                let shale_offset = nz / 3;
+                let window = (3 * n / 4, center);
+                let base = |x: usize, y: usize| {
+                    let distance = |a: usize| (a as f64 - center as f64) / center as f64;
+                    let r2 = (distance(x).powi(2) + distance(y).powi(2)) / 2.0;
+                    crest + (relief * r2).round() as usize
+                };
+                for x in 0..n {
+                    for y in 0..n {
+                        seal(&mut reservoir_matrix, x, y, base(x, y));
+                        let in_window = x.abs_diff(window.0).pow(2) + y.abs_diff(window.1).pow(2)
+                            <= (n / 8).pow(2);
+                        if !in_window {
+                            reservoir_matrix[[x, y, base(x, y) + shale_offset]] = VELOCITY_CAPROCK;
+                        }
+                    }
+                }
+                (center, center, base(center, center) + shale_offset + 1)
+            }
+            BenchmarkModel::TiltedFaultBlock => {
+                // The seal rises by a quarter of the grid from x = 0 to the fault at two thirds of the grid, and
+                // the block past the fault is thrown down by a quarter of the grid
+                let fault = 2 * n / 3;
+                let throw = nz / 4;
+                let base = |x: usize| nz / 2 - x * (nz / 4) / fault;
+                for x in 0..n {
+                    let column_base = if x < fault {
+                        base(x)
+                    } else {
+                        base(fault) + throw
+                    };
+                    for y in 0..n {
+                        seal(&mut reservoir_matrix, x, y, column_base);
+                    }
+                }
+                (n / 8, center, base(n / 8) + 1)
+            }
+            BenchmarkModel::LayeredThiefZone => {
+                let base = nz / 8;
+                let spacing = nz / 4;
+                let window = n / 8;
+                let shales: Vec<usize> = (1..)
+                    .map(|k| base + k * spacing)
+                    .take_while(|&z| z + 2 < nz)
+                    .collect();
+                for x in 0..n {
+                    for y in 0..n {
+                        seal(&mut reservoir_matrix, x, y, base);
+                        for (k, &z) in shales.iter().enumerate() {
+                            let in_window = if k % 2 == 0 {
+                                x < window
+                            } else {
+                                x >= n - window
+                            };
+                            if !in_window {
+                                reservoir_matrix[[x, y, z]] = VELOCITY_CAPROCK;
+                            }
+                        }
+                    }
+                }
+                (center, center, shales[shales.len() - 1] + 1)
+            }
+        };
+
+        let depths =
+            Array1::from_iter((0..nz).map(|z| TOP_DEPTH + z as f64 * THICKNESS / nz as f64));
+        let bedrock_indices = compute_bedrock_indices(
+            &reservoir_matrix.view(),
+            SimulationConfig::default().velocity_classifier,
+        );
+        Ok(BenchmarkCase {
+            model: *self,
+            resolution,
+            grid: GridInputs {
+                reservoir_matrix,
+                depths,
+                bedrock_indices,
+            },
+            source,
+        })
+    }
+}
+
+/// Make the column caprock down to and including the given layer
+fn seal(reservoir_matrix: &mut Array3<f64>, x: usize, y: usize, base: usize) {
+    for z in 0..=base {
+        reservoir_matrix[[x, y, z]] = VELOCITY_CAPROCK;
+    }
+}
+
+impl fmt::Display for BenchmarkModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for BenchmarkModel {
+    type Err = String;
+
+    /// A model by name: flat_aquifer, sleipner_dome, tilted_fault_block or layered_thief_zone
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = name.to_lowercase().replace('-', "_");
+        BenchmarkModel::ALL
+            .into_iter()
+            .find(|model| model.name() == name)
+            .ok_or_else(|| {
+                format!(
+                    "unknown benchmark model {}, expected flat_aquifer, sleipner_dome, tilted_fault_block or \
+                     layered_thief_zone",
+                    name
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::injection_simulation::Simulation;
+
+    #[test]
+    fn test_benchmark_models_run() {
+        for model in BenchmarkModel::ALL {
+            assert_eq!(model.name().parse::<BenchmarkModel>(), Ok(model));
+            for resolution in [16, 24] {
+                let case = model.build(resolution).unwrap();
+                assert_eq!(
+                    case.grid.reservoir_matrix.dim(),
+                    (resolution, resolution, resolution / 2)
+                );
+                let config = SimulationConfig {
+                    max_column_height: None,
+                    total_snapshots: 10,
+                    ..Default::default()
+                };
+                let mut simulation = Simulation::try_new(
+                    case.grid.reservoir_matrix.view(),
+                    case.grid.depths.view(),
+                    case.grid.bedrock_indices.view(),
+                    case.source,
+                    &config,
+                )
+                .unwrap();
+                simulation.run();
+                assert!(simulation.cells_filled() > 0, "{} at {}", model, resolution);
+            }
+        }
+        // The same model is built the same way every time
+        assert_eq!(
+            BenchmarkModel::SleipnerDome.build(16),
+            BenchmarkModel::SleipnerDome.build(16)
+        );
+        assert!(BenchmarkModel::FlatAquifer.build(8).is_err());
+        assert!("sleipner".parse::<BenchmarkModel>().is_err());
+    }
+}
@@ -0,0 +1,895 @@
+// Command line tools for working with simulation results without a Python stack.
+// Run using  cargo run --bin co2sim -- <command> --help
+
+use std::io::{Read, Write};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use clap::{Parser, Subcommand};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use ndarray_npy::{read_npy, write_npy};
+use numpy::ndarray::{Array1, Array3, ArrayView3};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Response, Server};
+
+use rust_backend::analytic::{validate_analytic, AnalyticCase};
+use rust_backend::baseline::{compare_baseline, Baseline, Tolerances};
+use rust_backend::benchmarks::BenchmarkModel;
+use rust_backend::cell_state::VelocityClassifier;
+use rust_backend::config::SimulationConfig;
+use rust_backend::datastucture::QueueKind;
+use rust_backend::eclipse::save_grdecl;
+use rust_backend::fingerprint::file_checksum;
+use rust_backend::golden::GoldenRun;
+use rust_backend::grid_repair::repair_grid;
+use rust_backend::injection_simulation::Simulation;
+use rust_backend::input_cache::{read_floats, GridInputs, InputCache};
+use rust_backend::matfile::save_mat;
+use rust_backend::metadata::RunMetadata;
+use rust_backend::ordering::FrontOrdering;
+use rust_backend::perf::run_perf;
+use rust_backend::render::{
+    render_frames, save_animation_gif, save_frame_sequence, save_slice_png, Colormap,
+    RenderOptions, Slice,
+};
+use rust_backend::replay::JournalReplay;
+use rust_backend::service::SimulationService;
+use rust_backend::traps::analyze_traps;
+use rust_backend::utils::compute_bedrock_indices;
+use rust_backend::viewer::{run_viewer, SliceViewer};
+
+#[derive(Parser)]
+#[command(name = "co2sim", about = "Tools for CO2 injection simulation results")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Render slices of a snapshots array to PNG
+    Render {
+        /// The snapshots array (.npy, int32 or int64)
+        snapshots: PathBuf,
+        /// Slices to render, e.g. z=10, x=5, y=3 or map
+        #[arg(long = "slice", required = true)]
+        slices: Vec<Slice>,
+        /// Directory for the images, named after the slices
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+        /// viridis, magma or gray
+        #[arg(long, default_value = "viridis")]
+        colormap: Colormap,
+        /// Pixels per cell
+        #[arg(long, default_value_t = 4)]
+        scale: u32,
+        /// Only show the cells filled up to this snapshot
+        #[arg(long)]
+        snapshot: Option<i32>,
+    },
+    /// Show a slice in the terminal as colored characters, and scrub through the snapshots with the arrow keys,
+    /// e.g. to check results on a cluster without X forwarding
+    View {
+        /// The snapshots array (.npy, int32 or int64)
+        snapshots: PathBuf,
+        /// The slice to show, e.g. z=10, x=5, y=3 or map
+        #[arg(long, default_value = "map")]
+        slice: Slice,
+        /// viridis, magma or gray
+        #[arg(long, default_value = "viridis")]
+        colormap: Colormap,
+        /// Cells per character along each side, for grids wider than the terminal
+        #[arg(long, default_value_t = 1)]
+        stride: usize,
+    },
+    /// Animate the plume growing snapshot by snapshot, as a GIF or a sequence of PNG frames
+    Animate {
+        /// The snapshots array (.npy, int32 or int64)
+        snapshots: PathBuf,
+        /// The slice to animate, e.g. z=10, x=5, y=3 or map
+        #[arg(long, default_value = "map")]
+        slice: Slice,
+        /// Write an animated GIF to this path
+        #[arg(long, required_unless_present = "frames_dir")]
+        out: Option<PathBuf>,
+        /// Write numbered PNG frames to this directory instead, e.g. for ffmpeg
+        #[arg(long, conflicts_with = "out")]
+        frames_dir: Option<PathBuf>,
+        /// viridis, magma or gray
+        #[arg(long, default_value = "viridis")]
+        colormap: Colormap,
+        /// Pixels per cell
+        #[arg(long, default_value_t = 4)]
+        scale: u32,
+        /// Show every n-th snapshot
+        #[arg(long, default_value_t = 1)]
+        every: usize,
+        /// Delay between GIF frames in milliseconds
+        #[arg(long, default_value_t = 100)]
+        delay_ms: u32,
+    },
+    /// Find the structural traps under the caprock and their spill tree, without simulating any injection
+    Traps {
+        /// The reservoir matrix (.npy, float32 or float64)
+        reservoir_matrix: PathBuf,
+        /// The depth of every layer (.npy, float32 or float64)
+        depths: PathBuf,
+        /// The bedrock index of every column (.npy, int32 or int64), computed from the reservoir matrix if not given
+        #[arg(long)]
+        bedrock_indices: Option<PathBuf>,
+        /// Write the traps as JSON to this path instead of printing them
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Save the innermost trap of every column to this .npy file
+        #[arg(long)]
+        trap_map: Option<PathBuf>,
+        /// Keep the grid read from the files in this directory, keyed by their content hash, so later commands on
+        /// the same files skip reading and converting them
+        #[arg(long)]
+        cache_dir: Option<PathBuf>,
+    },
+    /// Save a snapshots array with its headline statistics to a MATLAB v7.3 MAT-file
+    Mat {
+        /// The snapshots array (.npy, int32 or int64)
+        snapshots: PathBuf,
+        /// The MAT-file to write
+        out: PathBuf,
+    },
+    /// Save a snapshots array to a GRDECL file with the gas saturation of every report step for ResInsight,
+    /// with the geometry of a regular grid if the layer depths are given
+    Grdecl {
+        /// The snapshots array (.npy, int32 or int64)
+        snapshots: PathBuf,
+        /// The GRDECL file to write
+        out: PathBuf,
+        /// The depth of every layer (.npy, float32 or float64)
+        #[arg(long)]
+        depths: Option<PathBuf>,
+        /// The size of the cells along x and y in meters
+        #[arg(long, num_args = 2, value_names = ["DX", "DY"], default_values_t = [1.0, 1.0])]
+        cell_size: Vec<f64>,
+    },
+    /// Keep a grid in memory and answer simulation requests over HTTP: GET /grid gives the shape of the grid,
+    /// and POST /simulate with {"source": [x, y, z], "max_column_height": 10, "total_snapshots": 100} gives
+    /// the filled cells as sparse JSON, gzip compressed for clients that accept it. With --grid-dir, a request
+    /// can name another grid in that directory with {"grid": {"reservoir_matrix": "a.npy", "depths": "b.npy"}},
+    /// which is kept in memory for the next requests.
+    Serve {
+        /// The reservoir matrix (.npy, float32 or float64)
+        reservoir_matrix: PathBuf,
+        /// The depth of every layer (.npy, float32 or float64)
+        depths: PathBuf,
+        /// The bedrock index of every column (.npy, int32 or int64), computed from the reservoir matrix if not given
+        #[arg(long)]
+        bedrock_indices: Option<PathBuf>,
+        /// The address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+        /// Number of requests simulated at once
+        #[arg(long, default_value_t = 4)]
+        workers: usize,
+        /// Also keep the grids read from files in this directory, keyed by their content hash, for later runs of
+        /// the service
+        #[arg(long)]
+        cache_dir: Option<PathBuf>,
+        /// Accept requests that name other grids by their .npy files, relative to this directory. Without it,
+        /// requests only simulate on the grid of the service.
+        #[arg(long)]
+        grid_dir: Option<PathBuf>,
+    },
+    /// Write a benchmark model as reservoir_matrix.npy, depths.npy and bedrock_indices.npy, and print its
+    /// source, so runs can be compared on the same reference grid
+    Benchmark {
+        /// flat_aquifer, sleipner_dome, tilted_fault_block or layered_thief_zone
+        model: BenchmarkModel,
+        /// Directory for the arrays
+        out_dir: PathBuf,
+        /// Cells along x and y, with half as many layers
+        #[arg(long, default_value_t = 32)]
+        resolution: usize,
+        /// Also run the simulation on the model, print how long it took and save the snapshots.npy
+        #[arg(long)]
+        run: bool,
+        /// The max column height of the run, which never breaks the caprock if not given
+        #[arg(long)]
+        max_column_height: Option<usize>,
+        /// Number of snapshots of the run
+        #[arg(long, default_value_t = 100)]
+        total_snapshots: usize,
+    },
+    /// Compare the simulated plume with the closed-form sharp-interface solution for a radially symmetric
+    /// geometry, and print the errors of its radius and thickness as JSON
+    Analytic {
+        /// flat_seal or paraboloid_dome
+        case: AnalyticCase,
+        /// Cells along x and y
+        #[arg(long, default_value_t = 64)]
+        resolution: usize,
+        /// Number of plume sizes to compare at
+        #[arg(long, default_value_t = 10)]
+        points: usize,
+        /// The front ordering of the run: depth, seal_potential or well_distance
+        #[arg(long, default_value = "depth", value_parser = parse_front_ordering)]
+        front_ordering: FrontOrdering,
+    },
+    /// Run the benchmark models and compare the volume curves, breach times and footprints with a stored
+    /// baseline, printing the drift of every output as JSON. Fails if any output drifted past its tolerance.
+    CompareBaseline {
+        /// The baseline (.json), e.g. rust_backend/baselines/benchmarks.json
+        baseline: PathBuf,
+        /// Record the baseline instead, replacing the file
+        #[arg(long)]
+        record: bool,
+        /// Cells along x and y of the recorded models
+        #[arg(long, default_value_t = 24)]
+        resolution: usize,
+        /// The max column height of the recorded runs
+        #[arg(long, default_value_t = 3)]
+        max_column_height: usize,
+        /// Number of snapshots of the recorded runs
+        #[arg(long, default_value_t = 20)]
+        total_snapshots: usize,
+        /// Relative tolerance of the volume at every snapshot
+        #[arg(long, default_value_t = Tolerances::default().volume)]
+        volume_tolerance: f64,
+        /// Relative tolerance of the number of breaches and the cells filled at each
+        #[arg(long, default_value_t = Tolerances::default().breach_time)]
+        breach_tolerance: f64,
+        /// Relative tolerance of the footprint
+        #[arg(long, default_value_t = Tolerances::default().footprint)]
+        footprint_tolerance: f64,
+    },
+    /// Check a grid for inverted depths, unknown velocities, isolated reservoir cells and unsupported caprock, and
+    /// print the issues as JSON. With --out-dir, also write the repaired reservoir_matrix.npy, depths.npy and
+    /// bedrock_indices.npy.
+    Repair {
+        /// The velocity matrix (.npy, float32 or float64)
+        reservoir_matrix: PathBuf,
+        /// The layer depths (.npy, float32 or float64)
+        depths: PathBuf,
+        /// Directory for the repaired arrays
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+        /// Velocities within this tolerance of a rock velocity are of that rock
+        #[arg(long, default_value_t = 0.0)]
+        velocity_tolerance: f64,
+    },
+    /// Run a benchmark model and print the cells filled per second, the peak memory and the size of the front as
+    /// JSON, e.g. to measure the effect of build flags or hardware before a basin-scale run
+    Perf {
+        /// flat_aquifer, sleipner_dome, tilted_fault_block or layered_thief_zone
+        #[arg(long, default_value = "sleipner_dome")]
+        model: BenchmarkModel,
+        /// Cells along x and y, with half as many layers
+        #[arg(long, default_value_t = 128)]
+        resolution: usize,
+        /// The front queue of the engine: depth_ordered, bucket or binary_heap. Repeat to compare them.
+        #[arg(long = "queue", default_value = "depth_ordered", value_parser = parse_queue)]
+        queues: Vec<QueueKind>,
+        /// The front ordering of the engine: depth, seal_potential or well_distance
+        #[arg(long, default_value = "depth", value_parser = parse_front_ordering)]
+        front_ordering: FrontOrdering,
+        /// The max column height of the run, which never breaks the caprock if not given
+        #[arg(long)]
+        max_column_height: Option<usize>,
+    },
+    /// Run a simulation and record it as a golden run (.json): the checksums of the inputs, the config, the
+    /// checksum of the snapshots and the headline statistics, to verify later builds against
+    GoldenRecord {
+        /// The golden run to write
+        golden: PathBuf,
+        /// The velocity matrix (.npy, float32 or float64)
+        reservoir_matrix: PathBuf,
+        /// The layer depths (.npy, float32 or float64)
+        depths: PathBuf,
+        /// The bedrock indices (.npy, int32 or int64), computed from the reservoir matrix if not given
+        #[arg(long)]
+        bedrock_indices: Option<PathBuf>,
+        /// The source cell as x y z
+        #[arg(long, num_args = 3, required = true)]
+        source: Vec<usize>,
+        /// The max column height, which never breaks the caprock if not given
+        #[arg(long)]
+        max_column_height: Option<usize>,
+        /// Number of snapshots
+        #[arg(long, default_value_t = 100)]
+        total_snapshots: usize,
+        /// Round the depths so the run is reproducible on every platform, see the deterministic config option
+        #[arg(long)]
+        deterministic: bool,
+    },
+    /// Repeat a golden run with this build and check that the inputs, snapshots and statistics are the same.
+    /// Prints what differs as JSON and fails unless the run was reproduced exactly.
+    GoldenVerify {
+        /// The golden run written by golden-record
+        golden: PathBuf,
+        /// The velocity matrix (.npy, float32 or float64)
+        reservoir_matrix: PathBuf,
+        /// The layer depths (.npy, float32 or float64)
+        depths: PathBuf,
+        /// The bedrock indices (.npy, int32 or int64), computed from the reservoir matrix if not given
+        #[arg(long)]
+        bedrock_indices: Option<PathBuf>,
+    },
+    /// Rebuild the state of a run from its journal without the physics, at the end of the run or at the given
+    /// point, and print it as JSON. With --out, also write the snapshots as .npy.
+    Replay {
+        /// The journal written with journal_path
+        journal: PathBuf,
+        /// Stop once this many cells are filled
+        #[arg(long, conflicts_with = "snapshot")]
+        cells: Option<usize>,
+        /// Stop once the snapshot with this index is complete
+        #[arg(long)]
+        snapshot: Option<i32>,
+        /// Write the snapshots to this .npy file
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Write the well that filled each cell to this .npy file
+        #[arg(long)]
+        wells: Option<PathBuf>,
+    },
+}
+
+fn parse_front_ordering(name: &str) -> Result<FrontOrdering, String> {
+    FrontOrdering::from_name(name).ok_or_else(|| {
+        format!(
+            "unknown front ordering {}, expected depth, seal_potential or well_distance",
+            name
+        )
+    })
+}
+
+fn parse_queue(name: &str) -> Result<QueueKind, String> {
+    QueueKind::from_name(name).ok_or_else(|| {
+        format!(
+            "unknown queue {}, expected depth_ordered, bucket or binary_heap",
+            name
+        )
+    })
+}
+
+/// Read a snapshots array saved from Python, which may be int32 or int64
+fn read_snapshots(path: &Path) -> Result<Array3<i32>, Box<dyn std::error::Error>> {
+    if let Ok(snapshots) = read_npy::<_, Array3<i32>>(path) {
+        return Ok(snapshots);
+    }
+    let snapshots: Array3<i64> = read_npy(path)?;
+    Ok(snapshots.mapv(|s| s as i32))
+}
+
+/// Read the grid through the cache in the given directory, or straight from the files without one
+fn load_grid(
+    reservoir_matrix: &Path,
+    depths: &Path,
+    bedrock_indices: Option<&Path>,
+    cache_dir: Option<&Path>,
+) -> Result<Arc<GridInputs>, Box<dyn std::error::Error>> {
+    match cache_dir {
+        Some(cache_dir) => {
+            InputCache::on_disk(cache_dir)?.load(reservoir_matrix, depths, bedrock_indices)
+        }
+        None => Ok(Arc::new(GridInputs::load(
+            reservoir_matrix,
+            depths,
+            bedrock_indices,
+        )?)),
+    }
+}
+
+/// The file name of a rendered slice, e.g. slice_z10.png
+fn slice_file_name(slice: Slice) -> String {
+    match slice {
+        Slice::X(x) => format!("slice_x{}.png", x),
+        Slice::Y(y) => format!("slice_y{}.png", y),
+        Slice::Z(z) => format!("slice_z{}.png", z),
+        Slice::Map => "map.png".to_string(),
+    }
+}
+
+/// Metadata for results made from a snapshots file, with its checksum and the options used
+fn snapshots_metadata(
+    snapshots_path: &Path,
+    snapshots: &ArrayView3<i32>,
+    options: &RenderOptions,
+) -> std::io::Result<RunMetadata> {
+    let mut metadata = RunMetadata::for_snapshots(snapshots);
+    metadata.config = Some(format!("{:?}", options));
+    metadata.add_input_checksum("snapshots", file_checksum(snapshots_path)?);
+    Ok(metadata)
+}
+
+fn render(
+    snapshots: &ArrayView3<i32>,
+    slices: &[Slice],
+    out_dir: &Path,
+    options: &RenderOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(out_dir)?;
+    for &slice in slices {
+        let path = out_dir.join(slice_file_name(slice));
+        save_slice_png(&path, snapshots, slice, options)?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// The largest request body the service reads, in bytes. Requests are a few parameters and file names.
+const MAX_REQUEST_BODY: usize = 1 << 20;
+
+/// Answer a request to the simulation service with JSON, gzip compressed if the client accepts it
+fn respond(service: &SimulationService, mut request: tiny_http::Request) {
+    let error = |message: String| json!({ "error": message });
+    let too_large = || (413, error("the request body is too large".to_string()));
+    let (status, body) = match (request.method(), request.url()) {
+        (Method::Get, "/grid") => (200, service.grid_json()),
+        (Method::Post, "/simulate")
+            if request
+                .body_length()
+                .is_some_and(|length| length > MAX_REQUEST_BODY) =>
+        {
+            too_large()
+        }
+        (Method::Post, "/simulate") => {
+            let mut text = String::new();
+            // One byte more than the limit tells a body without a length that is too large
+            let read = request
+                .as_reader()
+                .take(MAX_REQUEST_BODY as u64 + 1)
+                .read_to_string(&mut text);
+            let parsed = read
+                .map_err(|e| e.to_string())
+                .and_then(|_| serde_json::from_str::<Value>(&text).map_err(|e| e.to_string()));
+            match parsed {
+                _ if text.len() > MAX_REQUEST_BODY => too_large(),
+                // A panic in the engine fails this request, and the worker goes on with the next one
+                Ok(parameters) => {
+                    match catch_unwind(AssertUnwindSafe(|| service.simulate(&parameters))) {
+                        Ok(Ok(result)) => (200, result),
+                        Ok(Err(e)) => (400, error(e.to_string())),
+                        Err(_) => (500, error("the simulation failed".to_string())),
+                    }
+                }
+                Err(e) => (400, error(format!("invalid request body: {}", e))),
+            }
+        }
+        _ => (404, error("use GET /grid or POST /simulate".to_string())),
+    };
+
+    let accepts_gzip = request.headers().iter().any(|header| {
+        header.field.equiv("Accept-Encoding") && header.value.as_str().contains("gzip")
+    });
+    let mut data = body.to_string().into_bytes();
+    let mut headers = vec![Header::from_bytes("Content-Type", "application/json").unwrap()];
+    if accepts_gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        if encoder.write_all(&data).is_ok() {
+            if let Ok(compressed) = encoder.finish() {
+                data = compressed;
+                headers.push(Header::from_bytes("Content-Encoding", "gzip").unwrap());
+            }
+        }
+    }
+    let mut response = Response::from_data(data).with_status_code(status);
+    for header in headers {
+        response.add_header(header);
+    }
+    if let Err(e) = request.respond(response) {
+        eprintln!("Failed to send a response: {}", e);
+    }
+}
+
+/// Answer requests on the given address with a pool of workers until the process is stopped
+fn serve(
+    service: SimulationService,
+    address: &str,
+    workers: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server = Arc::new(Server::http(address).map_err(|e| e.to_string())?);
+    let service = Arc::new(service);
+    println!("Listening on http://{}", address);
+    let handles: Vec<_> = (0..workers.max(1))
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let service = Arc::clone(&service);
+            thread::spawn(move || {
+                while let Ok(request) = server.recv() {
+                    respond(&service, request);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().map_err(|_| "a worker panicked")?;
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    match Cli::parse().command {
+        Command::Render {
+            snapshots,
+            slices,
+            out_dir,
+            colormap,
+            scale,
+            snapshot,
+        } => {
+            let snapshots_path = snapshots;
+            let snapshots = read_snapshots(&snapshots_path)?;
+            let options = RenderOptions {
+                colormap,
+                scale,
+                up_to_snapshot: snapshot,
+                ..Default::default()
+            };
+            render(&snapshots.view(), &slices, &out_dir, &options)?;
+            snapshots_metadata(&snapshots_path, &snapshots.view(), &options)?
+                .write_sidecar(&out_dir)?;
+            Ok(())
+        }
+        Command::View {
+            snapshots,
+            slice,
+            colormap,
+            stride,
+        } => {
+            let options = RenderOptions {
+                colormap,
+                ..Default::default()
+            };
+            let viewer = SliceViewer::new(read_snapshots(&snapshots)?, slice, stride, options)?;
+            run_viewer(viewer)
+        }
+        Command::Animate {
+            snapshots,
+            slice,
+            out,
+            frames_dir,
+            colormap,
+            scale,
+            every,
+            delay_ms,
+        } => {
+            let snapshots_path = snapshots;
+            let snapshots = read_snapshots(&snapshots_path)?;
+            let options = RenderOptions {
+                colormap,
+                scale,
+                ..Default::default()
+            };
+            let metadata = snapshots_metadata(&snapshots_path, &snapshots.view(), &options)?;
+            let frames = render_frames(&snapshots.view(), slice, &options, every)?;
+            let n_frames = frames.len();
+            match (out, frames_dir) {
+                (_, Some(frames_dir)) => {
+                    save_frame_sequence(&frames_dir, &frames)?;
+                    metadata.write_sidecar(&frames_dir)?;
+                    println!("Wrote {} frames to {}", n_frames, frames_dir.display());
+                }
+                (Some(out), None) => {
+                    save_animation_gif(&out, frames, delay_ms)?;
+                    metadata.write_sidecar(&out)?;
+                    println!("Wrote {} frames to {}", n_frames, out.display());
+                }
+                (None, None) => unreachable!("clap requires --out or --frames-dir"),
+            }
+            Ok(())
+        }
+        Command::Traps {
+            reservoir_matrix,
+            depths,
+            bedrock_indices,
+            out,
+            trap_map,
+            cache_dir,
+        } => {
+            let config = SimulationConfig::default();
+            let grid = load_grid(
+                &reservoir_matrix,
+                &depths,
+                bedrock_indices.as_deref(),
+                cache_dir.as_deref(),
+            )?;
+            let analysis = analyze_traps(
+                grid.reservoir_matrix.view(),
+                grid.depths.view(),
+                grid.bedrock_indices.view(),
+                &config,
+            )?;
+
+            let text = serde_json::to_string_pretty(&analysis.to_json())?;
+            match out {
+                Some(out) => {
+                    std::fs::write(&out, text + "\n")?;
+                    println!("Wrote {} traps to {}", analysis.traps.len(), out.display());
+                }
+                None => println!("{}", text),
+            }
+            if let Some(path) = trap_map {
+                write_npy(&path, &analysis.trap_map)?;
+                println!("Wrote {}", path.display());
+            }
+            Ok(())
+        }
+        Command::Mat { snapshots, out } => {
+            let snapshots_path = snapshots;
+            let snapshots = read_snapshots(&snapshots_path)?;
+            let mut metadata = RunMetadata::for_snapshots(&snapshots.view());
+            metadata.add_input_checksum("snapshots", file_checksum(&snapshots_path)?);
+            save_mat(&out, &snapshots.view(), &metadata)?;
+            println!("Wrote {}", out.display());
+            Ok(())
+        }
+        Command::Grdecl {
+            snapshots,
+            out,
+            depths,
+            cell_size,
+        } => {
+            let snapshots = read_snapshots(&snapshots)?;
+            let depths: Option<Array1<f64>> = depths.map(|path| read_floats(&path)).transpose()?;
+            save_grdecl(
+                &out,
+                &snapshots.view(),
+                depths.as_ref().map(|depths| depths.view()),
+                (cell_size[0], cell_size[1]),
+            )?;
+            println!("Wrote {}", out.display());
+            Ok(())
+        }
+        Command::Serve {
+            reservoir_matrix,
+            depths,
+            bedrock_indices,
+            address,
+            workers,
+            cache_dir,
+            grid_dir,
+        } => {
+            let input_cache = Arc::new(match &cache_dir {
+                Some(cache_dir) => InputCache::on_disk(cache_dir)?,
+                None => InputCache::in_memory(),
+            });
+            let grid = input_cache.load(&reservoir_matrix, &depths, bedrock_indices.as_deref())?;
+            let mut service = SimulationService::from_grid(grid);
+            if let Some(grid_dir) = &grid_dir {
+                service = service.with_grid_files(input_cache, grid_dir);
+            }
+            serve(service, &address, workers)
+        }
+        Command::Benchmark {
+            model,
+            out_dir,
+            resolution,
+            run,
+            max_column_height,
+            total_snapshots,
+        } => {
+            let case = model.build(resolution)?;
+            std::fs::create_dir_all(&out_dir)?;
+            write_npy(
+                out_dir.join("reservoir_matrix.npy"),
+                &case.grid.reservoir_matrix,
+            )?;
+            write_npy(out_dir.join("depths.npy"), &case.grid.depths)?;
+            write_npy(
+                out_dir.join("bedrock_indices.npy"),
+                &case.grid.bedrock_indices.mapv(|i| i as i64),
+            )?;
+            let (nx, ny, nz) = case.grid.reservoir_matrix.dim();
+            println!(
+                "Wrote {} ({}x{}x{}) to {}, source {:?}",
+                model,
+                nx,
+                ny,
+                nz,
+                out_dir.display(),
+                case.source
+            );
+
+            if run {
+                let config = SimulationConfig {
+                    max_column_height,
+                    total_snapshots,
+                    ..Default::default()
+                };
+                let start = Instant::now();
+                let mut simulation = Simulation::try_new(
+                    case.grid.reservoir_matrix.view(),
+                    case.grid.depths.view(),
+                    case.grid.bedrock_indices.view(),
+                    case.source,
+                    &config,
+                )?;
+                simulation.run();
+                let elapsed = start.elapsed().as_secs_f64();
+                let snapshots_path = out_dir.join("snapshots.npy");
+                write_npy(&snapshots_path, &simulation.snapshots())?;
+                RunMetadata::for_simulation(&simulation, Some(elapsed))
+                    .write_sidecar(&snapshots_path)?;
+                println!(
+                    "Filled {} cells in {:.3} s",
+                    simulation.cells_filled(),
+                    elapsed
+                );
+            }
+            Ok(())
+        }
+        Command::Analytic {
+            case,
+            resolution,
+            points,
+            front_ordering,
+        } => {
+            let config = SimulationConfig {
+                front_ordering,
+                ..Default::default()
+            };
+            let report = validate_analytic(case, resolution, points, &config)?;
+            println!("{}", serde_json::to_string_pretty(&report.to_json())?);
+            Ok(())
+        }
+        Command::CompareBaseline {
+            baseline,
+            record,
+            resolution,
+            max_column_height,
+            total_snapshots,
+            volume_tolerance,
+            breach_tolerance,
+            footprint_tolerance,
+        } => {
+            if record {
+                let recorded =
+                    Baseline::record(resolution, Some(max_column_height), total_snapshots)?;
+                recorded.save(&baseline)?;
+                println!(
+                    "Wrote {} models to {}",
+                    recorded.results.len(),
+                    baseline.display()
+                );
+                return Ok(());
+            }
+            let tolerances = Tolerances {
+                volume: volume_tolerance,
+                breach_time: breach_tolerance,
+                footprint: footprint_tolerance,
+            };
+            let comparison = compare_baseline(&Baseline::load(&baseline)?, &tolerances)?;
+            println!("{}", serde_json::to_string_pretty(&comparison.to_json())?);
+            if !comparison.passed() {
+                return Err(format!("results drifted from {}", baseline.display()).into());
+            }
+            Ok(())
+        }
+        Command::Repair {
+            reservoir_matrix,
+            depths,
+            out_dir,
+            velocity_tolerance,
+        } => {
+            let classifier = if velocity_tolerance == 0.0 {
+                VelocityClassifier::Exact
+            } else {
+                VelocityClassifier::Tolerance(velocity_tolerance)
+            };
+            let mut reservoir_matrix: Array3<f64> = read_floats(&reservoir_matrix)?;
+            let mut depths: Array1<f64> = read_floats(&depths)?;
+            let report = repair_grid(&mut reservoir_matrix, &mut depths, classifier);
+            println!("{}", serde_json::to_string_pretty(&report.to_json())?);
+            if let Some(out_dir) = out_dir {
+                std::fs::create_dir_all(&out_dir)?;
+                let bedrock_indices = compute_bedrock_indices(&reservoir_matrix.view(), classifier);
+                write_npy(out_dir.join("reservoir_matrix.npy"), &reservoir_matrix)?;
+                write_npy(out_dir.join("depths.npy"), &depths)?;
+                write_npy(
+                    out_dir.join("bedrock_indices.npy"),
+                    &bedrock_indices.mapv(|i| i as i64),
+                )?;
+                println!(
+                    "Repaired {} issues, wrote the grid to {}",
+                    report
+                        .issues
+                        .iter()
+                        .filter(|issue| issue.repairable)
+                        .count(),
+                    out_dir.display()
+                );
+            }
+            Ok(())
+        }
+        Command::Perf {
+            model,
+            resolution,
+            queues,
+            front_ordering,
+            max_column_height,
+        } => {
+            let mut reports = Vec::new();
+            for queue in queues {
+                let config = SimulationConfig {
+                    max_column_height,
+                    queue,
+                    front_ordering,
+                    ..Default::default()
+                };
+                reports.push(run_perf(model, resolution, &config)?.to_json());
+            }
+            println!("{}", serde_json::to_string_pretty(&reports)?);
+            Ok(())
+        }
+        Command::GoldenRecord {
+            golden,
+            reservoir_matrix,
+            depths,
+            bedrock_indices,
+            source,
+            max_column_height,
+            total_snapshots,
+            deterministic,
+        } => {
+            let grid = GridInputs::load(&reservoir_matrix, &depths, bedrock_indices.as_deref())?;
+            let config = SimulationConfig {
+                max_column_height,
+                total_snapshots,
+                deterministic,
+                ..Default::default()
+            };
+            let run = GoldenRun::record(
+                grid.reservoir_matrix.view(),
+                grid.depths.view(),
+                grid.bedrock_indices.view(),
+                &[(source[0], source[1], source[2])],
+                &config,
+            )?;
+            run.save(&golden)?;
+            println!("Wrote {}, snapshots {}", golden.display(), run.outputs_hash);
+            Ok(())
+        }
+        Command::GoldenVerify {
+            golden,
+            reservoir_matrix,
+            depths,
+            bedrock_indices,
+        } => {
+            let grid = GridInputs::load(&reservoir_matrix, &depths, bedrock_indices.as_deref())?;
+            let verification = GoldenRun::load(&golden)?.verify(
+                grid.reservoir_matrix.view(),
+                grid.depths.view(),
+                grid.bedrock_indices.view(),
+            )?;
+            println!("{}", serde_json::to_string_pretty(&verification.to_json())?);
+            if !verification.passed() {
+                return Err(format!("the run of {} was not reproduced", golden.display()).into());
+            }
+            Ok(())
+        }
+        Command::Replay {
+            journal,
+            cells,
+            snapshot,
+            out,
+            wells,
+        } => {
+            let mut replay = JournalReplay::load(&journal)?;
+            match (cells, snapshot) {
+                (Some(cells), _) => replay.replay_to_cells(cells),
+                (None, Some(snapshot)) => replay.replay_to_snapshot(snapshot),
+                (None, None) => replay.replay_all(),
+            };
+            println!("{}", serde_json::to_string_pretty(&replay.to_json())?);
+            if let Some(out) = out {
+                write_npy(&out, &replay.snapshots())?;
+            }
+            if let Some(wells) = wells {
+                write_npy(&wells, &replay.well_attribution())?;
+            }
+            Ok(())
+        }
+    }
+}
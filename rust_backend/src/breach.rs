@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+
+use crate::constants::GRAVITY;
+use crate::error::SimulationError;
+
+/// Decides when the CO2 column below a caprock cell breaks it
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum BreachCriterion {
+    /// Break when the column below the caprock reaches `max_column_height` cells
+    #[default]
+    ColumnHeight,
+    /// Break when the CO2 pressure at the caprock exceeds the minimum horizontal stress
+    Stress(StressCriterion),
+}
+
+impl BreachCriterion {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        match self {
+            BreachCriterion::ColumnHeight => Ok(()),
+            BreachCriterion::Stress(stress) => stress.validate(),
+        }
+    }
+}
+
+/// The caprock cells that break together with the caprock cell above a CO2 column. On fine grids a single
+/// broken cell throttles the upward flow far more than a real fracture zone would.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BreachGeometry {
+    /// Only the caprock cell above the column
+    #[default]
+    SingleCell,
+    /// The caprock cells of a width × width square around the broken cell in its layer, e.g. 3 for a 3×3 chimney
+    Chimney { width: usize },
+    /// The caprock cells in the layer of the broken cell, connected to it through each other, with a CO2 column of
+    /// at least `min_column_height` cells right below them. The whole seal above the plume fails at once.
+    Threshold { min_column_height: usize },
+}
+
+impl BreachGeometry {
+    /// Parse a geometry from its name and size: "single_cell", "chimney" with the width as the size (3 by
+    /// default), or "threshold" with the minimum column height as the size (1 by default)
+    pub fn from_name(name: &str, size: Option<usize>) -> Option<Self> {
+        match name {
+            "single_cell" => Some(BreachGeometry::SingleCell),
+            "chimney" => Some(BreachGeometry::Chimney {
+                width: size.unwrap_or(3),
+            }),
+            "threshold" => Some(BreachGeometry::Threshold {
+                min_column_height: size.unwrap_or(1),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        if let BreachGeometry::Chimney { width } = self {
+            if width % 2 == 0 {
+                return Err(SimulationError::InvalidValue {
+                    argument: "breach_geometry".to_string(),
+                    message: format!(
+                        "the chimney must be an odd number of cells wide, got {}",
+                        width
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Closure of the fractures in broken caprock. Without it a broken caprock cell stays open for good. With it
+/// the cell re-seals once the given number of cells have filled with CO2 that leaked through it, in the
+/// accumulation it feeds and the accumulations above that, and the seal holds until the column below breaks it
+/// again. Fills elsewhere in the plume do not count. A broken cell that filled with CO2 itself stays open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resealing {
+    /// Number of cells filled through a broken caprock cell before it re-seals
+    pub cells: usize,
+}
+
+impl Resealing {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        if self.cells == 0 {
+            return Err(SimulationError::InvalidValue {
+                argument: "resealing".to_string(),
+                message: "at least one cell must fill before a breach re-seals".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A simple geomechanical check of the caprock. The CO2 pressure at the caprock is the initial pressure
+/// plus the buoyancy of the CO2 column below it plus any overpressure from the injection, and the caprock
+/// fractures once this passes the minimum horizontal stress. Depths are taken from the grid, so they must be in metres.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StressCriterion {
+    /// Minimum horizontal stress per metre of depth in Pa/m
+    pub min_horizontal_stress_gradient: f64,
+    /// Initial (hydrostatic) pressure per metre of depth in Pa/m
+    pub hydrostatic_gradient: f64,
+    /// Density of brine minus density of CO2 in kg/m³
+    pub density_difference: f64,
+    /// Overpressure from the injection in Pa, added to the estimate of the pressure model if there is one
+    pub overpressure: f64,
+}
+
+impl StressCriterion {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        let invalid = |message: &str| {
+            Err(SimulationError::InvalidValue {
+                argument: "stress_criterion".to_string(),
+                message: message.to_string(),
+            })
+        };
+        if !(self.min_horizontal_stress_gradient.is_finite()
+            && self.min_horizontal_stress_gradient > 0.0)
+        {
+            return invalid("the minimum horizontal stress gradient must be positive");
+        }
+        if !(self.hydrostatic_gradient.is_finite() && self.hydrostatic_gradient >= 0.0) {
+            return invalid("the hydrostatic gradient must be non-negative");
+        }
+        if !(self.density_difference.is_finite() && self.density_difference >= 0.0) {
+            return invalid("the density difference must be non-negative");
+        }
+        if !self.overpressure.is_finite() {
+            return invalid("the overpressure must be finite");
+        }
+        Ok(())
+    }
+
+    /// The CO2 pressure at the caprock in Pa, for a CO2 column from the caprock depth down to the base depth
+    pub fn co2_pressure(&self, caprock_depth: f64, base_depth: f64, overpressure: f64) -> f64 {
+        let column_height = (base_depth - caprock_depth).max(0.0);
+        self.hydrostatic_gradient * caprock_depth
+            + self.density_difference * GRAVITY * column_height
+            + self.overpressure
+            + overpressure
+    }
+
+    /// Whether the caprock at the given depth breaks under a CO2 column down to the base depth
+    pub fn breaks(&self, caprock_depth: f64, base_depth: f64, overpressure: f64) -> bool {
+        self.co2_pressure(caprock_depth, base_depth, overpressure)
+            >= self.min_horizontal_stress_gradient * caprock_depth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stress_criterion() {
+        let criterion = StressCriterion {
+            min_horizontal_stress_gradient: 10500.0,
+            hydrostatic_gradient: 10000.0,
+            density_difference: 500.0,
+            overpressure: 0.0,
+        };
+        // At 1000 m the stress is 0.5 MPa above the initial pressure, which takes a column of about 102 m
+        assert!(!criterion.breaks(1000.0, 1100.0, 0.0));
+        assert!(criterion.breaks(1000.0, 1110.0, 0.0));
+        // Overpressure lowers the column the caprock can hold
+        assert!(criterion.breaks(1000.0, 1050.0, 3e5));
+        // Deeper caprock holds a taller column
+        assert!(!criterion.breaks(2000.0, 2110.0, 0.0));
+
+        assert!(BreachCriterion::Stress(StressCriterion {
+            density_difference: -1.0,
+            ..criterion
+        })
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use numpy::ndarray::{Array2, Array3, ArrayView1, ArrayView2, ArrayView3, Axis};
+
+use crate::config::SimulationConfig;
+use crate::injection_simulation::run_injection_simulation;
+
+/// Misfit measures between an observed and a simulated plume outline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misfit {
+    /// One minus the intersection over union of the two outlines
+    Jaccard,
+    /// Number of (x, y) cells where the outlines disagree, divided by the number of cells
+    Mismatch,
+}
+
+impl Misfit {
+    pub fn evaluate(&self, observed: &ArrayView2<bool>, simulated: &ArrayView2<bool>) -> f64 {
+        match self {
+            Misfit::Jaccard => jaccard_distance(observed, simulated),
+            Misfit::Mismatch => {
+                let n_differ = observed
+                    .iter()
+                    .zip(simulated.iter())
+                    .filter(|(&a, &b)| a != b)
+                    .count();
+                n_differ as f64 / observed.len().max(1) as f64
+            }
+        }
+    }
+}
+
+/// The parameter values to search over. Every combination is tried.
+#[derive(Debug, Clone)]
+pub struct CalibrationSpace {
+    pub max_column_heights: Vec<usize>,
+    pub anisotropies: Vec<(usize, usize)>,
+}
+
+/// A tried configuration together with its misfit against the observation
+#[derive(Debug, Clone)]
+pub struct CalibrationResult {
+    pub config: SimulationConfig,
+    pub misfit: f64,
+}
+
+/// Compute the map-view outline of the plume, i.e. every (x, y) with CO2 somewhere in the column.
+/// Only cells filled at or before `snapshot_index` are included if it is given.
+pub fn plume_outline(snapshots: &ArrayView3<i32>, snapshot_index: Option<i32>) -> Array2<bool> {
+    snapshots.map_axis(Axis(2), |column| {
+        column
+            .iter()
+            .any(|&s| s != -1 && snapshot_index.is_none_or(|t| s <= t))
+    })
+}
+
+/// Jaccard distance between two masks. Two empty masks have distance zero.
+pub fn jaccard_distance(a: &ArrayView2<bool>, b: &ArrayView2<bool>) -> f64 {
+    if a.dim() != b.dim() {
+        panic!("Masks must have the same shape");
+    }
+
+    let (intersection, union) = a
+        .iter()
+        .zip(b.iter())
+        .fold((0usize, 0usize), |(i, u), (&x, &y)| {
+            (i + (x && y) as usize, u + (x || y) as usize)
+        });
+
+    if union == 0 {
+        0.0
+    } else {
+        1.0 - intersection as f64 / union as f64
+    }
+}
+
+/// Grid search over the calibration space for the configurations that best reproduce the observed plume outline.
+/// The results are sorted with the best fit first.
+#[allow(clippy::too_many_arguments)]
+pub fn calibrate(
+    reservoir_matrix: ArrayView3<f64>,
+    depths: ArrayView1<f64>,
+    bedrock_indices: ArrayView2<usize>,
+    source: (usize, usize, usize),
+    observed_outline: ArrayView2<bool>,
+    observation_snapshot: Option<i32>,
+    space: &CalibrationSpace,
+    base_config: &SimulationConfig,
+    misfit: Misfit,
+) -> Vec<CalibrationResult> {
+    let (nx, ny, _) = reservoir_matrix.dim();
+    if observed_outline.dim() != (nx, ny) {
+        panic!("Observed outline must have shape (nx, ny)");
+    }
+
+    let mut results = Vec::new();
+    for &max_column_height in &space.max_column_heights {
+        for &anisotropy in &space.anisotropies {
+            let config = SimulationConfig {
+                max_column_height: Some(max_column_height),
+                anisotropy,
+                ..base_config.clone()
+            };
+
+            let snapshots: Array3<i32> = run_injection_simulation(
+                reservoir_matrix,
+                depths,
+                bedrock_indices,
+                source,
+                &config,
+            );
+            let simulated_outline = plume_outline(&snapshots.view(), observation_snapshot);
+
+            results.push(CalibrationResult {
+                misfit: misfit.evaluate(&observed_outline, &simulated_outline.view()),
+                config,
+            });
+        }
+    }
+
+    results.sort_by(|a, b| a.misfit.total_cmp(&b.misfit));
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use numpy::ndarray::array;
+
+    #[test]
+    fn test_jaccard_distance() {
+        let a = array![[true, true], [false, false]];
+        let b = array![[true, false], [true, false]];
+        // Intersection 1, union 3
+        assert!((jaccard_distance(&a.view(), &b.view()) - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(jaccard_distance(&a.view(), &a.view()), 0.0);
+
+        let empty = Array2::<bool>::default((2, 2));
+        assert_eq!(jaccard_distance(&empty.view(), &empty.view()), 0.0);
+    }
+
+    #[test]
+    fn test_plume_outline_respects_snapshot_index() {
+        let mut snapshots = Array3::<i32>::from_elem((2, 2, 2), -1);
+        snapshots[[0, 0, 1]] = 0;
+        snapshots[[1, 1, 0]] = 3;
+
+        let outline = plume_outline(&snapshots.view(), None);
+        assert!(outline[[0, 0]] && outline[[1, 1]]);
+
+        let early = plume_outline(&snapshots.view(), Some(1));
+        assert!(early[[0, 0]] && !early[[1, 1]]);
+    }
+
+    #[test]
+    fn test_calibrate_recovers_anisotropy() {
+        use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+
+        // Flat caprock at z = 0 with reservoir below
+        let mut reservoir = Array3::<f64>::from_elem((9, 9, 3), VELOCITY_RESERVOIR);
+        reservoir.index_axis_mut(Axis(2), 0).fill(VELOCITY_CAPROCK);
+        let depths = numpy::ndarray::Array1::from(vec![0.0, 1.0, 2.0]);
+        let bedrock_indices = Array2::<usize>::zeros((9, 9));
+        let source = (4, 4, 1);
+
+        let true_config = SimulationConfig {
+            total_snapshots: 20,
+            anisotropy: (2, 1),
+            ..Default::default()
+        };
+        let observed = run_injection_simulation(
+            reservoir.view(),
+            depths.view(),
+            bedrock_indices.view(),
+            source,
+            &true_config,
+        );
+        let observed_outline = plume_outline(&observed.view(), Some(2));
+
+        let space = CalibrationSpace {
+            max_column_heights: vec![10],
+            anisotropies: vec![(1, 1), (1, 2), (2, 1)],
+        };
+        let results = calibrate(
+            reservoir.view(),
+            depths.view(),
+            bedrock_indices.view(),
+            source,
+            observed_outline.view(),
+            Some(2),
+            &space,
+            &true_config,
+            Misfit::Jaccard,
+        );
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].config.anisotropy, (2, 1));
+        assert_eq!(results[0].misfit, 0.0);
+    }
+}
@@ -0,0 +1,420 @@
+use numpy::ndarray::{Array3, ArrayView3};
+use serde::{Deserialize, Serialize};
+
+use crate::constants::{VELOCITY_CAPROCK, VELOCITY_CO2, VELOCITY_RESERVOIR};
+use crate::error::SimulationError;
+use crate::sparse::SparseGrid;
+
+/// The state of a cell during the simulation, combining its rock type and fluid content.
+/// The velocity values are only used to read the input and to write the velocity model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CellState {
+    /// Impermeable rock that traps the CO2 until the column below it gets too high
+    Caprock,
+    /// Reservoir rock that can be filled with CO2
+    Reservoir,
+    /// Reservoir rock filled with CO2
+    Co2,
+    /// A cell that takes no part in the simulation, e.g. outside the active grid or with an unknown velocity
+    Inactive,
+}
+
+impl CellState {
+    /// Translate an input velocity. Velocities other than the caprock, reservoir and CO2 velocities are inactive.
+    pub fn from_velocity(velocity: f64) -> Self {
+        // NaN is used as padding around irregular survey areas in exported cubes
+        if velocity.is_nan() {
+            CellState::Inactive
+        } else if velocity == VELOCITY_CAPROCK {
+            CellState::Caprock
+        } else if velocity == VELOCITY_RESERVOIR {
+            CellState::Reservoir
+        } else if velocity == VELOCITY_CO2 {
+            CellState::Co2
+        } else {
+            CellState::Inactive
+        }
+    }
+
+    /// The velocity of the cell in the velocity model. Inactive cells are NaN.
+    pub fn to_velocity(self) -> f64 {
+        match self {
+            CellState::Caprock => VELOCITY_CAPROCK,
+            CellState::Reservoir => VELOCITY_RESERVOIR,
+            CellState::Co2 => VELOCITY_CO2,
+            CellState::Inactive => f64::NAN,
+        }
+    }
+}
+
+/// How input velocities are mapped to cell states.
+/// Velocity cubes that have been interpolated rarely contain the exact rock velocities, so these need a tolerance or thresholds.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum VelocityClassifier {
+    /// Only the exact caprock, reservoir and CO2 velocities are recognised
+    #[default]
+    Exact,
+    /// Velocities within this absolute tolerance of a rock velocity get that state.
+    /// Where bands overlap, caprock wins over reservoir and reservoir over CO2.
+    Tolerance(f64),
+    /// Velocities at or above `caprock_min` are caprock, at or above `reservoir_min` reservoir, and below it CO2
+    Thresholds {
+        reservoir_min: f64,
+        caprock_min: f64,
+    },
+}
+
+impl VelocityClassifier {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        let invalid = |message: &str| {
+            Err(SimulationError::InvalidValue {
+                argument: "velocity_classifier".to_string(),
+                message: message.to_string(),
+            })
+        };
+
+        match *self {
+            VelocityClassifier::Exact => Ok(()),
+            VelocityClassifier::Tolerance(tolerance) => {
+                if !(tolerance.is_finite() && tolerance >= 0.0) {
+                    return invalid("the tolerance must be finite and non-negative");
+                }
+                Ok(())
+            }
+            VelocityClassifier::Thresholds {
+                reservoir_min,
+                caprock_min,
+            } => {
+                if !(reservoir_min.is_finite() && caprock_min.is_finite())
+                    || reservoir_min >= caprock_min
+                {
+                    return invalid(
+                        "the thresholds must be finite with reservoir_min < caprock_min",
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// The state of a cell with the given input velocity. NaN and infinite velocities are always inactive.
+    pub fn classify(&self, velocity: f64) -> CellState {
+        let within = |tolerance: f64, target: f64| (velocity - target).abs() <= tolerance;
+
+        match *self {
+            VelocityClassifier::Exact => CellState::from_velocity(velocity),
+            _ if !velocity.is_finite() => CellState::Inactive,
+            VelocityClassifier::Tolerance(tolerance) => {
+                if within(tolerance, VELOCITY_CAPROCK) {
+                    CellState::Caprock
+                } else if within(tolerance, VELOCITY_RESERVOIR) {
+                    CellState::Reservoir
+                } else if within(tolerance, VELOCITY_CO2) {
+                    CellState::Co2
+                } else {
+                    CellState::Inactive
+                }
+            }
+            VelocityClassifier::Thresholds {
+                reservoir_min,
+                caprock_min,
+            } => {
+                if velocity >= caprock_min {
+                    CellState::Caprock
+                } else if velocity >= reservoir_min {
+                    CellState::Reservoir
+                } else {
+                    CellState::Co2
+                }
+            }
+        }
+    }
+}
+
+/// Translate a velocity matrix to cell states
+pub fn cell_states_from_velocities(
+    velocities: &ArrayView3<f64>,
+    classifier: VelocityClassifier,
+) -> Array3<CellState> {
+    velocities.mapv(|velocity| classifier.classify(velocity))
+}
+
+/// Translate cell states back to a velocity matrix
+pub fn velocities_from_cell_states(cells: &ArrayView3<CellState>) -> Array3<f64> {
+    cells.mapv(CellState::to_velocity)
+}
+
+/// The rock type of a cell. This never changes during the simulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RockType {
+    Caprock,
+    Reservoir,
+    Inactive,
+}
+
+/// Which saturation path a cell has followed. Cells the plume leaves again keep residual CO2,
+/// so a second pass of the front finds them in a different state than the first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum CellHistory {
+    /// Never reached by CO2
+    #[default]
+    Pristine,
+    /// Filled by the plume for the first time
+    Drainage,
+    /// Left by the plume, with brine back in and only residual CO2 remaining
+    Imbibition,
+    /// Filled again after imbibition
+    SecondaryDrainage,
+}
+
+/// The rock and fluid state of the whole grid.
+/// The rock types only change when the grid is edited, while the fluid content and the broken caprock cells change
+/// as the plume grows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReservoirState {
+    rock: Array3<RockType>,
+    // CO2 saturation of each cell, between 0 and 1. Stored sparsely since the plume is usually small.
+    saturation: SparseGrid<f32>,
+    // Caprock cells that have broken and now let CO2 through
+    breached: SparseGrid<bool>,
+    // The drainage and imbibition history of every cell that has held CO2
+    history: SparseGrid<CellHistory>,
+}
+
+impl ReservoirState {
+    /// Set up the state from cell states. CO2 cells are reservoir rock with full saturation.
+    pub fn from_cell_states(cells: &ArrayView3<CellState>) -> Self {
+        let dims = cells.dim();
+        let mut saturation = SparseGrid::new(dims, 0.0);
+        let mut history = SparseGrid::new(dims, CellHistory::Pristine);
+        let rock = Array3::from_shape_fn(dims, |cell| match cells[cell] {
+            CellState::Caprock => RockType::Caprock,
+            CellState::Reservoir => RockType::Reservoir,
+            CellState::Co2 => {
+                saturation.set(cell, 1.0);
+                history.set(cell, CellHistory::Drainage);
+                RockType::Reservoir
+            }
+            CellState::Inactive => RockType::Inactive,
+        });
+        ReservoirState {
+            rock,
+            saturation,
+            breached: SparseGrid::new(dims, false),
+            history,
+        }
+    }
+
+    /// Set up the state from a velocity matrix
+    pub fn from_velocities(velocities: &ArrayView3<f64>, classifier: VelocityClassifier) -> Self {
+        Self::from_cell_states(&cell_states_from_velocities(velocities, classifier).view())
+    }
+
+    pub fn dim(&self) -> (usize, usize, usize) {
+        self.rock.dim()
+    }
+
+    /// The current state of a cell
+    #[inline]
+    pub fn state(&self, cell: (usize, usize, usize)) -> CellState {
+        let (x, y, z) = cell;
+        match self.rock[[x, y, z]] {
+            RockType::Inactive => CellState::Inactive,
+            RockType::Caprock if !self.breached.get(cell) => CellState::Caprock,
+            _ => match self.saturation.get(cell) {
+                s if s >= 1.0 => CellState::Co2,
+                // Residual CO2 is immobile, so an imbibed cell is open to the plume again
+                s if s > 0.0 && self.history.get(cell) != CellHistory::Imbibition => CellState::Co2,
+                _ => CellState::Reservoir,
+            },
+        }
+    }
+
+    /// Number of cells in the given state
+    pub fn count(&self, state: CellState) -> usize {
+        self.rock
+            .indexed_iter()
+            .filter(|&(cell, _)| self.state(cell) == state)
+            .count()
+    }
+
+    /// Mark a cell as inactive while setting up the simulation, e.g. because it is outside the active grid
+    pub fn deactivate(&mut self, (x, y, z): (usize, usize, usize)) {
+        self.rock[[x, y, z]] = RockType::Inactive;
+        self.saturation.set((x, y, z), 0.0);
+        self.history.set((x, y, z), CellHistory::Pristine);
+    }
+
+    /// Change the rock of a cell, when the grid is edited between runs
+    pub fn set_rock_type(&mut self, cell: (usize, usize, usize), rock: RockType) {
+        if rock == RockType::Inactive {
+            self.deactivate(cell);
+        } else {
+            let (x, y, z) = cell;
+            self.rock[[x, y, z]] = rock;
+        }
+    }
+
+    /// Fill the cell with CO2
+    pub fn fill(&mut self, cell: (usize, usize, usize)) {
+        self.saturation.set(cell, 1.0);
+        let history = match self.history.get(cell) {
+            CellHistory::Imbibition | CellHistory::SecondaryDrainage => {
+                CellHistory::SecondaryDrainage
+            }
+            _ => CellHistory::Drainage,
+        };
+        self.history.set(cell, history);
+    }
+
+    /// Empty a filled cell as if the plume never reached it, when a simulation is rewound
+    pub fn unfill(&mut self, cell: (usize, usize, usize)) {
+        self.saturation.set(cell, 0.0);
+        self.history.set(cell, CellHistory::Pristine);
+    }
+
+    /// Let brine back into a cell the plume has left, keeping the residual saturation
+    pub fn imbibe(&mut self, cell: (usize, usize, usize), residual_saturation: f32) {
+        self.saturation.set(cell, residual_saturation);
+        self.history.set(cell, CellHistory::Imbibition);
+    }
+
+    /// Set the CO2 saturation of a cell without changing its history, e.g. after part of it has dissolved
+    pub fn set_saturation(&mut self, cell: (usize, usize, usize), saturation: f32) {
+        self.saturation.set(cell, saturation);
+    }
+
+    /// Break a caprock cell so that CO2 can enter it. The cell is left empty, ready to be filled.
+    pub fn breach(&mut self, cell: (usize, usize, usize)) {
+        self.breached.set(cell, true);
+        self.saturation.set(cell, 0.0);
+    }
+
+    /// Close a broken caprock cell again. The rock type was kept, so it is caprock once more.
+    pub fn reseal(&mut self, cell: (usize, usize, usize)) {
+        self.breached.set(cell, false);
+    }
+
+    /// The index of the closest caprock cell in the column at or above zi, or 0 if there is none
+    pub fn closest_caprock_idx(&self, (x, y): (usize, usize), zi: usize) -> usize {
+        (0..=zi)
+            .rev()
+            .find(|&z| self.state((x, y, z)) == CellState::Caprock)
+            .unwrap_or(0)
+    }
+
+    pub fn rock_types(&self) -> &Array3<RockType> {
+        &self.rock
+    }
+
+    pub fn saturation(&self) -> &SparseGrid<f32> {
+        &self.saturation
+    }
+
+    pub fn breached(&self) -> &SparseGrid<bool> {
+        &self.breached
+    }
+
+    pub fn history(&self) -> &SparseGrid<CellHistory> {
+        &self.history
+    }
+
+    /// The current state of every cell
+    pub fn cell_states(&self) -> Array3<CellState> {
+        Array3::from_shape_fn(self.dim(), |cell| self.state(cell))
+    }
+
+    /// The current velocity model. Inactive cells are NaN.
+    pub fn velocities(&self) -> Array3<f64> {
+        Array3::from_shape_fn(self.dim(), |cell| self.state(cell).to_velocity())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_velocity_round_trip() {
+        for state in [CellState::Caprock, CellState::Reservoir, CellState::Co2] {
+            assert_eq!(CellState::from_velocity(state.to_velocity()), state);
+        }
+        assert_eq!(CellState::from_velocity(1234.0), CellState::Inactive);
+        assert_eq!(CellState::from_velocity(f64::NAN), CellState::Inactive);
+        assert!(CellState::Inactive.to_velocity().is_nan());
+    }
+
+    #[test]
+    fn test_velocity_classifiers() {
+        let tolerance = VelocityClassifier::Tolerance(50.0);
+        assert_eq!(tolerance.classify(2580.3), CellState::Caprock);
+        assert_eq!(tolerance.classify(1512.0), CellState::Reservoir);
+        assert_eq!(tolerance.classify(2000.0), CellState::Inactive);
+        assert_eq!(tolerance.classify(f64::NAN), CellState::Inactive);
+        assert_eq!(
+            VelocityClassifier::Exact.classify(1512.0),
+            CellState::Inactive
+        );
+
+        let thresholds = VelocityClassifier::Thresholds {
+            reservoir_min: 1000.0,
+            caprock_min: 2000.0,
+        };
+        assert_eq!(thresholds.classify(2000.0), CellState::Caprock);
+        assert_eq!(thresholds.classify(1999.0), CellState::Reservoir);
+        assert_eq!(thresholds.classify(310.0), CellState::Co2);
+        assert_eq!(thresholds.classify(f64::INFINITY), CellState::Inactive);
+
+        assert!(VelocityClassifier::Tolerance(-1.0).validate().is_err());
+        assert!(VelocityClassifier::Thresholds {
+            reservoir_min: 2000.0,
+            caprock_min: 1000.0
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_reservoir_state_keeps_rock_types() {
+        let mut cells = Array3::from_elem((1, 1, 3), CellState::Reservoir);
+        cells[[0, 0, 0]] = CellState::Caprock;
+        cells[[0, 0, 2]] = CellState::Co2;
+        let mut state = ReservoirState::from_cell_states(&cells.view());
+        assert_eq!(state.cell_states(), cells);
+        assert_eq!(state.closest_caprock_idx((0, 0), 2), 0);
+
+        state.breach((0, 0, 0));
+        assert_eq!(state.state((0, 0, 0)), CellState::Reservoir);
+        state.fill((0, 0, 0));
+        state.fill((0, 0, 1));
+        assert_eq!(state.state((0, 0, 0)), CellState::Co2);
+        assert_eq!(state.state((0, 0, 1)), CellState::Co2);
+        // The rock type of the broken caprock is unchanged
+        assert_eq!(state.rock_types()[[0, 0, 0]], RockType::Caprock);
+        assert_eq!(state.velocities()[[0, 0, 0]], VELOCITY_CO2);
+    }
+
+    #[test]
+    fn test_imbibed_cells_can_be_invaded_again() {
+        let cells = Array3::from_elem((1, 1, 2), CellState::Reservoir);
+        let mut state = ReservoirState::from_cell_states(&cells.view());
+        state.fill((0, 0, 1));
+        assert_eq!(state.history().get((0, 0, 1)), CellHistory::Drainage);
+
+        state.imbibe((0, 0, 1), 0.25);
+        assert_eq!(state.state((0, 0, 1)), CellState::Reservoir);
+        assert_eq!(state.saturation().get((0, 0, 1)), 0.25);
+
+        state.fill((0, 0, 1));
+        assert_eq!(state.state((0, 0, 1)), CellState::Co2);
+        assert_eq!(
+            state.history().get((0, 0, 1)),
+            CellHistory::SecondaryDrainage
+        );
+        // A partly dissolved cell is still part of the plume
+        state.set_saturation((0, 0, 1), 0.5);
+        assert_eq!(state.state((0, 0, 1)), CellState::Co2);
+        assert_eq!(state.history().get((0, 0, 0)), CellHistory::Pristine);
+    }
+}
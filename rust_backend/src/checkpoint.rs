@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimulationError;
+use crate::injection_simulation::Simulation;
+use crate::metadata::OUTPUT_SCHEMA_VERSION;
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"CO2SIMCK";
+/// Bumped whenever the layout of the checkpoint header or body changes, including any type in the state of the
+/// simulation. The crate version in the header does not change with the layout, so it cannot tell them apart.
+///
+/// 1. The header holds the format version, the output schema version, see `OUTPUT_SCHEMA_VERSION`, and the crate
+///    version
+/// 2. The state no longer holds the number of CO2 cells in the input
+pub const CHECKPOINT_VERSION: u32 = 2;
+
+// Longer crate versions in a header are taken as a corrupt file, rather than allocated
+const MAX_CRATE_VERSION_LEN: usize = 64;
+
+/// The state restored from a checkpoint, see `save_checkpoint`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Checkpoint {
+    pub simulation: Simulation,
+    /// Time spent running the simulation before the checkpoint
+    pub elapsed_seconds: f64,
+    /// Checksums of the inputs the simulation was created from, see `RunMetadata`
+    pub input_checksums: BTreeMap<String, String>,
+}
+
+// Serialized in the same layout as `Checkpoint`, without copying the simulation
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    simulation: &'a Simulation,
+    elapsed_seconds: f64,
+    input_checksums: &'a BTreeMap<String, String>,
+}
+
+/// Write the full state of the simulation, with the grids, the front, the counters and the random state, so
+/// `read_checkpoint` can restore it and continue the run exactly where it stopped. The state is stored as
+/// gzipped bincode behind a header with the checkpoint format version, the output schema version and the crate
+/// version.
+pub fn write_checkpoint<W: Write>(
+    mut writer: W,
+    simulation: &Simulation,
+    elapsed_seconds: f64,
+    input_checksums: &BTreeMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    let crate_version = env!("CARGO_PKG_VERSION").as_bytes();
+    writer.write_all(CHECKPOINT_MAGIC)?;
+    writer.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+    writer.write_all(&OUTPUT_SCHEMA_VERSION.to_le_bytes())?;
+    writer.write_all(&(crate_version.len() as u32).to_le_bytes())?;
+    writer.write_all(crate_version)?;
+
+    let mut encoder = GzEncoder::new(writer, Compression::fast());
+    bincode::serialize_into(
+        &mut encoder,
+        &CheckpointRef {
+            simulation,
+            elapsed_seconds,
+            input_checksums,
+        },
+    )?;
+    encoder.finish()?.flush()?;
+    Ok(())
+}
+
+/// Restore a checkpoint written by `write_checkpoint` in the current format version. The crate version in the
+/// header is not checked, since the format version tells the layout.
+pub fn read_checkpoint<R: Read>(mut reader: R) -> Result<Checkpoint, Box<dyn Error>> {
+    let invalid = |message: String| SimulationError::InvalidValue {
+        argument: "checkpoint".to_string(),
+        message,
+    };
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != CHECKPOINT_MAGIC {
+        return Err(invalid("is not a simulation checkpoint".to_string()).into());
+    }
+    let mut word = [0u8; 4];
+    reader.read_exact(&mut word)?;
+    let version = u32::from_le_bytes(word);
+    if version != CHECKPOINT_VERSION {
+        return Err(invalid(format!(
+            "has format version {}, but this build reads version {}",
+            version, CHECKPOINT_VERSION
+        ))
+        .into());
+    }
+    reader.read_exact(&mut word)?;
+    let schema_version = u32::from_le_bytes(word);
+    if schema_version > OUTPUT_SCHEMA_VERSION {
+        return Err(invalid(format!(
+            "has output schema version {}, but this build reads up to version {}",
+            schema_version, OUTPUT_SCHEMA_VERSION
+        ))
+        .into());
+    }
+    reader.read_exact(&mut word)?;
+    let crate_version_len = u32::from_le_bytes(word) as usize;
+    if crate_version_len > MAX_CRATE_VERSION_LEN {
+        return Err(invalid(format!(
+            "has a crate version of {} bytes, more than the {} of a valid header",
+            crate_version_len, MAX_CRATE_VERSION_LEN
+        ))
+        .into());
+    }
+    reader.read_exact(&mut vec![0u8; crate_version_len])?;
+    let mut decoder = GzDecoder::new(reader);
+    let checkpoint = bincode::deserialize_from(&mut decoder)?;
+    // A body of another layout can decode without an error, but rarely to its exact length
+    if decoder.read(&mut [0u8; 1])? != 0 {
+        return Err(invalid("has bytes after the state of the simulation".to_string()).into());
+    }
+    Ok(checkpoint)
+}
+
+/// Save a checkpoint to a file, see `write_checkpoint`
+pub fn save_checkpoint(
+    path: &Path,
+    simulation: &Simulation,
+    elapsed_seconds: f64,
+    input_checksums: &BTreeMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    let writer = BufWriter::new(File::create(path)?);
+    write_checkpoint(writer, simulation, elapsed_seconds, input_checksums)
+}
+
+/// Load a checkpoint from a file, see `read_checkpoint`
+pub fn load_checkpoint(path: &Path) -> Result<Checkpoint, Box<dyn Error>> {
+    read_checkpoint(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SimulationConfig;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use crate::spreading::StochasticSpreading;
+    use numpy::ndarray::{s, Array1, Array2, Array3};
+
+    #[test]
+    fn test_checkpoint_restores_the_run() {
+        let mut reservoir = Array3::from_elem((8, 8, 4), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        // The stochastic spreading checks that the random state is restored as well
+        let config = SimulationConfig {
+            max_column_height: None,
+            total_snapshots: 6,
+            stochastic_spreading: Some(StochasticSpreading {
+                seed: 7,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut simulation = Simulation::try_new(
+            reservoir.view(),
+            Array1::from(vec![0.0, 1.0, 2.0, 3.0]).view(),
+            Array2::zeros((8, 8)).view(),
+            (4, 4, 1),
+            &config,
+        )
+        .unwrap();
+        simulation.advance(40);
+
+        let checksums = BTreeMap::from([("reservoir_matrix".to_string(), "abc".to_string())]);
+        let mut buffer = Vec::new();
+        write_checkpoint(&mut buffer, &simulation, 1.5, &checksums).unwrap();
+        let checkpoint = read_checkpoint(buffer.as_slice()).unwrap();
+        assert_eq!(checkpoint.elapsed_seconds, 1.5);
+        assert_eq!(checkpoint.input_checksums, checksums);
+
+        let mut restored = checkpoint.simulation;
+        assert_eq!(restored.cells_filled(), simulation.cells_filled());
+        simulation.run();
+        restored.run();
+        assert_eq!(restored.fill_order(), simulation.fill_order());
+        assert_eq!(restored.snapshots(), simulation.snapshots());
+
+        // Checkpoints of another format version, a corrupt header or extra bytes are rejected
+        let mut newer = buffer.clone();
+        newer[8] += 1;
+        let err = read_checkpoint(newer.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("format version 3"));
+        let mut corrupt = buffer.clone();
+        corrupt[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = read_checkpoint(corrupt.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("crate version"));
+        let header = 20 + env!("CARGO_PKG_VERSION").len();
+        let mut longer = buffer[..header].to_vec();
+        let mut encoder = GzEncoder::new(&mut longer, Compression::fast());
+        bincode::serialize_into(
+            &mut encoder,
+            &CheckpointRef {
+                simulation: &simulation,
+                elapsed_seconds: 1.5,
+                input_checksums: &checksums,
+            },
+        )
+        .unwrap();
+        encoder.write_all(&[0]).unwrap();
+        encoder.finish().unwrap();
+        let err = read_checkpoint(longer.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("bytes after"));
+        assert!(read_checkpoint(&b"not a checkpoint"[..]).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_of_the_current_format_version() {
+        // A saved run of two caprock layers, the upper one breaking above every column, stopped after 60 cells.
+        // Stops reading when the layout of the state changes, as a reminder to bump CHECKPOINT_VERSION.
+        let mut reservoir = Array3::from_elem((8, 8, 6), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        reservoir.slice_mut(s![.., .., 3]).fill(VELOCITY_CAPROCK);
+        let config = SimulationConfig {
+            max_column_height: Some(1),
+            total_snapshots: 6,
+            stochastic_spreading: Some(StochasticSpreading {
+                seed: 7,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut simulation = Simulation::try_new(
+            reservoir.view(),
+            Array1::from_iter((0..6).map(|z| z as f64)).view(),
+            Array2::zeros((8, 8)).view(),
+            (4, 4, 4),
+            &config,
+        )
+        .unwrap();
+        simulation.advance(60);
+
+        let checkpoint =
+            read_checkpoint(&include_bytes!("../checkpoints/format_v2.ckpt")[..]).unwrap();
+        assert_eq!(checkpoint.elapsed_seconds, 1.5);
+        let mut restored = checkpoint.simulation;
+        assert_eq!(restored.fill_order(), simulation.fill_order());
+        restored.run();
+        simulation.run();
+        assert_eq!(restored.fill_order(), simulation.fill_order());
+        assert_eq!(restored.snapshots(), simulation.snapshots());
+        assert_eq!(restored.breach_events(), simulation.breach_events());
+    }
+}
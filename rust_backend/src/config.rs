@@ -0,0 +1,143 @@
+use numpy::ndarray::{Array2, Array3};
+use serde::{Deserialize, Serialize};
+
+use crate::aquifer::AquiferFlow;
+use crate::breach::{BreachCriterion, BreachGeometry, Resealing};
+use crate::cell_state::VelocityClassifier;
+use crate::containment::{Containment, LateralLimit};
+use crate::datastucture::QueueKind;
+use crate::eos::MassAccounting;
+use crate::ordering::FrontOrdering;
+use crate::perforation::{Perforation, SweepOrder};
+use crate::pressure::{PressureLimit, PressureModel};
+use crate::smoothing::Smoothing;
+use crate::snapshot_policy::SnapshotPolicy;
+use crate::spreading::StochasticSpreading;
+use crate::stencil::Stencil;
+use crate::stream::Co2Stream;
+use crate::thermal::ThermalZone;
+use crate::time_axis::TimeAxis;
+
+/// Parameters controlling a single injection simulation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimulationConfig {
+    /// Number of CO2 cells below a caprock cell before the caprock breaks, with the column height criterion.
+    /// None never breaks the caprock, which also skips the column scan for every filled cell.
+    pub max_column_height: Option<usize>,
+    /// When the CO2 column below a caprock cell breaks it
+    pub breach_criterion: BreachCriterion,
+    /// Which caprock cells break together when the criterion breaks the caprock above a column
+    pub breach_geometry: BreachGeometry,
+    /// Re-seals broken caprock cells after some CO2 has passed through them, if given. Otherwise breaches
+    /// stay open.
+    pub resealing: Option<Resealing>,
+    /// Number of snapshots to capture during the filling process. Used by the uniform snapshot policy.
+    pub total_snapshots: usize,
+    /// When to move on to the next snapshot
+    pub snapshot_policy: SnapshotPolicy,
+    /// Number of cells the plume can advance laterally per step along (x, y).
+    /// (1, 1) gives the isotropic 8-connected stencil.
+    pub anisotropy: (usize, usize),
+    /// Data structure used for the front of cells waiting to be processed
+    pub queue: QueueKind,
+    /// The key the front orders its cells by. Orderings other than the depth are not supported by the
+    /// bucket queue, which orders by z index.
+    pub front_ordering: FrontOrdering,
+    /// The z index of the top of the basement in every column, if given, with nz for no basement. The cells
+    /// from there down are inactive, and the wells stop their descent above it instead of at the base of the
+    /// model.
+    pub basement_indices: Option<Array2<usize>>,
+    /// How the input velocities are mapped to caprock, reservoir and CO2 cells
+    pub velocity_classifier: VelocityClassifier,
+    /// Stop as soon as CO2 leaves the containment, if given
+    pub containment: Option<Containment>,
+    /// Flags, or stops at, the first cell beyond the largest lateral distance from the wells, if given
+    pub lateral_limit: Option<LateralLimit>,
+    /// Keep a mass-conservation ledger with the CO2 tallies of every snapshot
+    pub audit: bool,
+    /// Maps snapshots to calendar dates in the output, if given
+    pub time_axis: Option<TimeAxis>,
+    /// Estimates the pressure buildup around the wells for every snapshot, if given. Needs the time axis.
+    pub pressure_model: Option<PressureModel>,
+    /// Throttles the injection rate to keep the bottomhole pressure below the fracture pressure, if given.
+    /// Needs the pressure model and the time axis, and changes the snapshot dates.
+    pub pressure_limit: Option<PressureLimit>,
+    /// Converts filled cells to tonnes of CO2 with a depth-dependent density, if given
+    pub mass_accounting: Option<MassAccounting>,
+    /// Regional brine flow that biases the spreading downstream and drifts the plume after injection, if given.
+    /// Not supported by the bucket queue, which ignores depths.
+    pub aquifer_flow: Option<AquiferFlow>,
+    /// The cold zone around the wells, with denser CO2 and a different CO2 velocity, if given
+    pub thermal_zone: Option<ThermalZone>,
+    /// The impurities in the injected stream, which change the CO2 density used for buoyancy and mass,
+    /// and the CO2 velocity in the velocity model, if given
+    pub co2_stream: Option<Co2Stream>,
+    /// Spread laterally with random per-direction probabilities instead of to every neighbour, if given
+    pub stochastic_spreading: Option<StochasticSpreading>,
+    /// Smooths the plume edges in the velocity model, if given. The snapshots and saturation stay binary.
+    pub smoothing: Option<Smoothing>,
+    /// The layers the wells inject into, if given. Otherwise every layer from the source down is injected into.
+    pub perforation: Option<Perforation>,
+    /// The order in which the layers from the source down, or the perforated layers, are injected into
+    pub sweep_order: SweepOrder,
+    /// Offsets the front moves along instead of the built-in rules from the anisotropy, if given. Stencils with
+    /// nonzero weights are rejected with `QueueKind::Bucket`, which orders by z index instead of depth.
+    pub stencil: Option<Stencil>,
+    /// Bit-for-bit reproducible runs: the front keys are rounded to micrometres, so keys that differ only by
+    /// rounding error tie and the cells are filled in the order they were reached. The fill only uses IEEE
+    /// arithmetic and the portable libm functions, so identical inputs give identical snapshots on every platform.
+    pub deterministic: bool,
+    /// Cells holding CO2 before the injection starts, e.g. the plume of an earlier run or one interpreted from
+    /// seismic, to continue an injection or add a well on top of it. The new CO2 pools below and around it like
+    /// below the CO2 of this run, and caprock cells in the plume start out broken. The snapshots only hold the
+    /// cells filled in this run.
+    pub initial_plume: Option<Array3<bool>>,
+    /// Record the fills, breaches, snapshots and layer changes of the run as journal entries, see
+    /// `Simulation::take_journal`
+    pub journal: bool,
+    /// Check the physical invariants of the plume after every fill and at the end of every snapshot, and end the
+    /// run at the first broken one, see `Simulation::invariant_violation`. Slow, for debugging the engine.
+    pub paranoid: bool,
+    /// Keep a full copy of the state at the start of every snapshot, so `Simulation::rewound` and
+    /// `Simulation::with_edited_cells` clone the copy instead of replaying the run up to the snapshot. Takes
+    /// memory for one copy of the state per snapshot.
+    pub rewind_points: bool,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            max_column_height: Some(10),
+            breach_criterion: BreachCriterion::default(),
+            breach_geometry: BreachGeometry::default(),
+            resealing: None,
+            total_snapshots: 100,
+            snapshot_policy: SnapshotPolicy::default(),
+            anisotropy: (1, 1),
+            queue: QueueKind::default(),
+            front_ordering: FrontOrdering::default(),
+            basement_indices: None,
+            velocity_classifier: VelocityClassifier::default(),
+            containment: None,
+            lateral_limit: None,
+            audit: false,
+            time_axis: None,
+            pressure_model: None,
+            pressure_limit: None,
+            mass_accounting: None,
+            aquifer_flow: None,
+            thermal_zone: None,
+            co2_stream: None,
+            stochastic_spreading: None,
+            smoothing: None,
+            perforation: None,
+            sweep_order: SweepOrder::default(),
+            stencil: None,
+            deterministic: false,
+            initial_plume: None,
+            journal: false,
+            paranoid: false,
+            rewind_points: false,
+        }
+    }
+}
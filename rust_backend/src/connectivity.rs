@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+
+use numpy::ndarray::{Array3, ArrayView1, ArrayView2, ArrayView3};
+use serde::{Deserialize, Serialize};
+
+use crate::cell_state::{CellState, ReservoirState};
+use crate::config::SimulationConfig;
+use crate::error::SimulationError;
+use crate::grid::{Grid, RegularGrid};
+use crate::injection_simulation::{
+    check_initial_position, compute_snapshot_interval, initial_reservoir_state, lateral_directions,
+    next_snapshot_index,
+};
+use crate::sparse::SparseGrid;
+use crate::validation::validate_inputs;
+
+/// Warn when the compartment of the source holds less than this fraction of the reservoir cells
+pub const SEALED_COMPARTMENT_FRACTION: f64 = 0.5;
+
+/// The reservoir cells the plume can reach from the sources without breaking any caprock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompartmentReport {
+    /// Number of reservoir cells connected to any of the sources
+    pub compartment_cells: usize,
+    /// Number of reservoir cells in the whole grid
+    pub reservoir_cells: usize,
+}
+
+impl CompartmentReport {
+    /// Fraction of the reservoir cells inside the compartment of the source
+    pub fn fraction(&self) -> f64 {
+        if self.reservoir_cells == 0 {
+            return 1.0;
+        }
+        self.compartment_cells as f64 / self.reservoir_cells as f64
+    }
+
+    /// Whether most of the reservoir is unreachable without breaking caprock
+    pub fn is_sealed(&self) -> bool {
+        self.fraction() < SEALED_COMPARTMENT_FRACTION
+    }
+
+    /// A warning for a sealed compartment, where the plume will stay small, or None
+    pub fn warning(&self) -> Option<String> {
+        self.is_sealed().then(|| {
+            format!(
+                "the source compartment holds only {} of {} reservoir cells ({:.1}%). \
+                 The rest of the reservoir is unreachable without breaking caprock.",
+                self.compartment_cells,
+                self.reservoir_cells,
+                100.0 * self.fraction()
+            )
+        })
+    }
+}
+
+/// Find the compartment of reservoir cells connected to the sources, moving laterally along the given
+/// directions and vertically up and down. Caprock and inactive cells bound the compartment.
+pub fn source_compartment(
+    reservoir: &ReservoirState,
+    grid: &impl Grid,
+    sources: &[(usize, usize, usize)],
+    directions: &[(i32, i32)],
+) -> CompartmentReport {
+    let mut compartment_cells = 0;
+    flood_fill(reservoir, grid, sources, directions, |_| {
+        compartment_cells += 1
+    });
+
+    CompartmentReport {
+        compartment_cells,
+        reservoir_cells: reservoir.count(CellState::Reservoir),
+    }
+}
+
+/// Breadth-first search through the reservoir cells connected to the sources, calling `visit` on every cell
+/// in the order it is reached
+fn flood_fill(
+    reservoir: &ReservoirState,
+    grid: &impl Grid,
+    sources: &[(usize, usize, usize)],
+    directions: &[(i32, i32)],
+    mut visit: impl FnMut((usize, usize, usize)),
+) {
+    let (_, _, nz) = reservoir.dim();
+    // Sparse, since the compartment is often a small part of the grid
+    let mut seen = SparseGrid::new(reservoir.dim(), false);
+    let mut queue = VecDeque::new();
+
+    for &source in sources {
+        if !seen.get(source) && reservoir.state(source) == CellState::Reservoir {
+            seen.set(source, true);
+            queue.push_back(source);
+        }
+    }
+
+    while let Some(cell) = queue.pop_front() {
+        visit(cell);
+        let (x, y, z) = cell;
+
+        let above = (z > 0).then(|| (x, y, z - 1));
+        let below = (z + 1 < nz).then_some((x, y, z + 1));
+        for neighbor in grid
+            .lateral_neighbors(cell, directions)
+            .chain(above)
+            .chain(below)
+        {
+            if !seen.get(neighbor) && reservoir.state(neighbor) == CellState::Reservoir {
+                seen.set(neighbor, true);
+                queue.push_back(neighbor);
+            }
+        }
+    }
+}
+
+/// A fast baseline engine: fill every reservoir cell connected to the sources in breadth-first order,
+/// ignoring depth ordering and column heights. Takes the same inputs as `run_injection_simulation` and
+/// returns the snapshots in the same format, for connectivity and capacity screening.
+pub fn run_flood_fill(
+    reservoir_matrix: ArrayView3<f64>,
+    depths: ArrayView1<f64>,
+    bedrock_indices: ArrayView2<usize>,
+    sources: &[(usize, usize, usize)],
+    config: &SimulationConfig,
+) -> Result<Array3<i32>, SimulationError> {
+    if sources.is_empty() {
+        return Err(SimulationError::InvalidSource(
+            "At least one source is required".to_string(),
+        ));
+    }
+    for &source in sources {
+        validate_inputs(&reservoir_matrix, &depths, &bedrock_indices, source, config)?;
+    }
+
+    let dims = reservoir_matrix.dim();
+    let grid = RegularGrid::new(dims.0, dims.1, depths);
+    let reservoir = initial_reservoir_state(
+        &reservoir_matrix,
+        &grid,
+        &bedrock_indices,
+        config.velocity_classifier,
+    );
+    for &source in sources {
+        check_initial_position(&reservoir, source)?;
+    }
+    let snapshot_interval = compute_snapshot_interval(&reservoir, config.total_snapshots);
+
+    let mut snapshots = Array3::from_elem(dims, -1);
+    let mut snapshot_index = 0;
+    let mut cells_since_snapshot = 0;
+    let directions = lateral_directions(config.anisotropy);
+    flood_fill(&reservoir, &grid, sources, &directions, |cell| {
+        snapshots[cell] = snapshot_index;
+        cells_since_snapshot += 1;
+        if cells_since_snapshot >= snapshot_interval {
+            snapshot_index = next_snapshot_index(snapshot_index);
+            cells_since_snapshot = 0;
+        }
+    });
+    Ok(snapshots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use numpy::ndarray::{s, Array1, Array2};
+
+    #[test]
+    fn test_sealed_compartment() {
+        // A caprock wall at x = 1 seals the source into the first column of cells
+        let mut cells = Array3::from_elem((5, 2, 2), CellState::Reservoir);
+        cells.slice_mut(s![1, .., ..]).fill(CellState::Caprock);
+        let reservoir = ReservoirState::from_cell_states(&cells.view());
+        let grid = RegularGrid::new(5, 2, Array1::from(vec![0.0, 1.0]).view());
+        let directions = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+        let report = source_compartment(&reservoir, &grid, &[(0, 0, 1)], &directions);
+        assert_eq!(report.compartment_cells, 4);
+        assert_eq!(report.reservoir_cells, 16);
+        assert!(report.is_sealed());
+        assert!(report.warning().unwrap().contains("4 of 16"));
+
+        let report = source_compartment(&reservoir, &grid, &[(3, 1, 0)], &directions);
+        assert_eq!(report.compartment_cells, 12);
+        assert!(!report.is_sealed());
+        assert_eq!(report.warning(), None);
+
+        // Wells on both sides of the wall reach the whole reservoir
+        let report = source_compartment(&reservoir, &grid, &[(0, 0, 1), (3, 1, 0)], &directions);
+        assert_eq!(report.compartment_cells, 16);
+    }
+
+    #[test]
+    fn test_flood_fill_ignores_depth_order() {
+        // A caprock lid at z = 0 over x >= 1, with a deep pocket under it and a column open to the top at x = 0
+        let mut reservoir = Array3::from_elem((4, 1, 3), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![1.., .., 0]).fill(VELOCITY_CAPROCK);
+        reservoir[[3, 0, 1]] = VELOCITY_CAPROCK;
+        let depths = Array1::from(vec![0.0, 1.0, 2.0]);
+        let bedrock_indices = Array2::<usize>::zeros((4, 1));
+        let config = SimulationConfig {
+            total_snapshots: 100,
+            ..Default::default()
+        };
+
+        let snapshots = run_flood_fill(
+            reservoir.view(),
+            depths.view(),
+            bedrock_indices.view(),
+            &[(1, 0, 1)],
+            &config,
+        )
+        .unwrap();
+        // Every connected reservoir cell is filled, one per snapshot in breadth-first order
+        assert_eq!(snapshots[[1, 0, 1]], 0);
+        assert_eq!(snapshots.iter().filter(|&&s| s >= 0).count(), 8);
+        assert_eq!(snapshots[[3, 0, 1]], -1);
+        assert!(snapshots[[3, 0, 2]] > snapshots[[1, 0, 2]]);
+    }
+}
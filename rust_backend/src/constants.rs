@@ -1,3 +1,6 @@
 pub const VELOCITY_CAPROCK: f64 = 2607.0;
 pub const VELOCITY_RESERVOIR: f64 = 1500.0;
 pub const VELOCITY_CO2: f64 = 300.0;
+
+/// Gravitational acceleration in m/s²
+pub const GRAVITY: f64 = 9.81;
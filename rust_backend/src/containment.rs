@@ -0,0 +1,237 @@
+use numpy::ndarray::Array2;
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimulationError;
+
+/// Strict containment. The simulation stops as soon as CO2 is filled into a cell outside the containment,
+/// which is all screening workflows need to know.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Containment {
+    /// The z index of the top of the primary caprock for each (x, y). CO2 above it is a violation.
+    pub primary_caprock_indices: Option<Array2<usize>>,
+    /// CO2 in the surface layer (z = 0) is a violation
+    pub surface_layer: bool,
+    /// Polygon in (x, y) cell index coordinates that the plume must stay inside
+    pub polygon: Option<Vec<(f64, f64)>>,
+}
+
+/// Why the CO2 left the containment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ViolationKind {
+    AbovePrimaryCaprock,
+    SurfaceLayer,
+    OutsidePolygon,
+}
+
+impl ViolationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ViolationKind::AbovePrimaryCaprock => "above_primary_caprock",
+            ViolationKind::SurfaceLayer => "surface_layer",
+            ViolationKind::OutsidePolygon => "outside_polygon",
+        }
+    }
+}
+
+/// The first filled cell outside the containment, which ended the simulation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainmentViolation {
+    pub kind: ViolationKind,
+    pub cell: (usize, usize, usize),
+    /// The snapshot being recorded when the violation happened
+    pub snapshot_index: i32,
+    /// Number of cells filled, including the violating cell
+    pub cells_filled: usize,
+}
+
+impl Containment {
+    pub fn validate(&self, (nx, ny): (usize, usize)) -> Result<(), SimulationError> {
+        if let Some(indices) = &self.primary_caprock_indices {
+            if indices.dim() != (nx, ny) {
+                let (ix, iy) = indices.dim();
+                return Err(SimulationError::ShapeMismatch {
+                    argument: "primary_caprock_indices".to_string(),
+                    expected: format!("({}, {}) to match (nx, ny) of reservoir_matrix", nx, ny),
+                    actual: format!("({}, {})", ix, iy),
+                });
+            }
+        }
+        if let Some(polygon) = &self.polygon {
+            if polygon.len() < 3
+                || polygon
+                    .iter()
+                    .any(|&(x, y)| !(x.is_finite() && y.is_finite()))
+            {
+                return Err(SimulationError::InvalidValue {
+                    argument: "containment_polygon".to_string(),
+                    message: "must have at least 3 finite vertices".to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The kind of violation if CO2 in the given cell is outside the containment
+    pub fn violation_at(&self, (x, y, z): (usize, usize, usize)) -> Option<ViolationKind> {
+        if self.surface_layer && z == 0 {
+            return Some(ViolationKind::SurfaceLayer);
+        }
+        if let Some(indices) = &self.primary_caprock_indices {
+            if z < indices[[x, y]] {
+                return Some(ViolationKind::AbovePrimaryCaprock);
+            }
+        }
+        if let Some(polygon) = &self.polygon {
+            if !point_in_polygon((x as f64, y as f64), polygon) {
+                return Some(ViolationKind::OutsidePolygon);
+            }
+        }
+        None
+    }
+}
+
+/// The largest lateral distance the plume may migrate from the wells, e.g. to the boundary of a storage license.
+/// Unlike the strict containment the simulation only flags the first cell beyond it, unless it is asked to stop.
+/// A cell is within the limit if it is within it as seen from any of the wells.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LateralLimit {
+    /// The largest horizontal distance from a well in cells, if any
+    pub max_distance: Option<f64>,
+    /// The largest distance from a well along +x, -x, +y and -y in cells, if any
+    pub max_offsets: Option<[f64; 4]>,
+    /// Stop the simulation at the first cell beyond the limit
+    pub stop: bool,
+}
+
+/// The first filled cell beyond the lateral limit
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LateralExceedance {
+    pub cell: (usize, usize, usize),
+    /// The horizontal distance in cells from the closest well
+    pub distance: f64,
+    /// The snapshot being recorded when the plume passed the limit
+    pub snapshot_index: i32,
+    /// Number of cells filled, including the cell beyond the limit
+    pub cells_filled: usize,
+}
+
+impl LateralLimit {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        let limits = self
+            .max_distance
+            .iter()
+            .chain(self.max_offsets.iter().flatten());
+        if limits
+            .into_iter()
+            .any(|limit| limit.is_nan() || *limit < 0.0)
+        {
+            return Err(SimulationError::InvalidValue {
+                argument: "lateral_limit".to_string(),
+                message: "the distances must be non-negative".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether CO2 in the (x, y) column is within the limit of a well at the (x, y) column of the source
+    fn allows(&self, (x, y): (usize, usize), (sx, sy): (usize, usize)) -> bool {
+        let (dx, dy) = (x as f64 - sx as f64, y as f64 - sy as f64);
+        if self
+            .max_distance
+            .is_some_and(|max| libm::hypot(dx, dy) > max)
+        {
+            return false;
+        }
+        match self.max_offsets {
+            Some([px, mx, py, my]) => dx <= px && -dx <= mx && dy <= py && -dy <= my,
+            None => true,
+        }
+    }
+
+    /// The horizontal distance to the closest well if CO2 in the cell is beyond the limit of every well
+    pub fn exceeded_at(
+        &self,
+        (x, y, _): (usize, usize, usize),
+        sources: &[(usize, usize, usize)],
+    ) -> Option<f64> {
+        if sources
+            .iter()
+            .any(|&(sx, sy, _)| self.allows((x, y), (sx, sy)))
+        {
+            return None;
+        }
+        let distance = sources
+            .iter()
+            .map(|&(sx, sy, _)| libm::hypot(x as f64 - sx as f64, y as f64 - sy as f64))
+            .fold(f64::INFINITY, f64::min);
+        Some(distance)
+    }
+}
+
+/// Helper function for the even-odd rule: a point is inside if a ray from it crosses the boundary an odd number of times
+fn point_in_polygon((px, py): (f64, f64), polygon: &[(f64, f64)]) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+    for i in 0..polygon.len() {
+        let (xi, yi) = polygon[i];
+        let (xj, yj) = polygon[j];
+        if (yi > py) != (yj > py) && px < (xj - xi) * (py - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_violations() {
+        let containment = Containment {
+            primary_caprock_indices: Some(Array2::from_elem((4, 4), 2)),
+            surface_layer: true,
+            polygon: Some(vec![(-0.5, -0.5), (2.5, -0.5), (2.5, 2.5), (-0.5, 2.5)]),
+        };
+        assert!(containment.validate((4, 4)).is_ok());
+        assert!(containment.validate((3, 4)).is_err());
+
+        assert_eq!(containment.violation_at((1, 1, 3)), None);
+        assert_eq!(
+            containment.violation_at((1, 1, 0)),
+            Some(ViolationKind::SurfaceLayer)
+        );
+        assert_eq!(
+            containment.violation_at((1, 1, 1)),
+            Some(ViolationKind::AbovePrimaryCaprock)
+        );
+        assert_eq!(
+            containment.violation_at((3, 1, 3)),
+            Some(ViolationKind::OutsidePolygon)
+        );
+    }
+
+    #[test]
+    fn test_lateral_limit() {
+        let limit = LateralLimit {
+            max_distance: Some(3.0),
+            max_offsets: Some([f64::INFINITY, 1.0, f64::INFINITY, f64::INFINITY]),
+            stop: false,
+        };
+        let sources = [(5, 5, 2)];
+        assert_eq!(limit.exceeded_at((7, 7, 2), &sources), None);
+        assert_eq!(limit.exceeded_at((8, 5, 2), &sources), None);
+        assert_eq!(limit.exceeded_at((9, 5, 2), &sources), Some(4.0));
+        // Only one cell along -x
+        assert_eq!(limit.exceeded_at((3, 5, 2), &sources), Some(2.0));
+        // Within the limit of the second well
+        assert_eq!(limit.exceeded_at((9, 5, 2), &[(5, 5, 2), (8, 5, 2)]), None);
+        assert!(LateralLimit {
+            max_distance: Some(-1.0),
+            ..limit
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use numpy::ndarray::{Array2, ArrayView, ArrayView1, ArrayView2, Axis, Dimension, RemoveAxis};
+
+use crate::config::SimulationConfig;
+use crate::error::SimulationError;
+use crate::injection_simulation::Simulation;
+
+/// The horizontal axis a grid one cell wide runs along, see `section_axis`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionAxis {
+    X,
+    Y,
+}
+
+impl SectionAxis {
+    /// The axis of the 3D grid that is one cell wide
+    fn flat_axis(self) -> Axis {
+        match self {
+            SectionAxis::X => Axis(1),
+            SectionAxis::Y => Axis(0),
+        }
+    }
+}
+
+/// The axis of a cross-section: X for a grid with ny == 1, Y for one with nx == 1, and None for a 3D grid. A single
+/// column runs along X.
+pub fn section_axis((nx, ny, _): (usize, usize, usize)) -> Option<SectionAxis> {
+    if ny == 1 {
+        Some(SectionAxis::X)
+    } else if nx == 1 {
+        Some(SectionAxis::Y)
+    } else {
+        None
+    }
+}
+
+/// The lateral directions that stay in the plane of a cross-section. The others always leave the grid, so dropping
+/// them gives the same fill with a quarter of the moves.
+pub(crate) fn in_plane_directions(
+    directions: Vec<(i32, i32)>,
+    dims: (usize, usize, usize),
+) -> Vec<(i32, i32)> {
+    match section_axis(dims) {
+        Some(SectionAxis::X) => directions.into_iter().filter(|&(_, dy)| dy == 0).collect(),
+        Some(SectionAxis::Y) => directions.into_iter().filter(|&(dx, _)| dx == 0).collect(),
+        None => directions,
+    }
+}
+
+/// The cross-section of an output of a run on a grid one cell wide, without the flat axis, e.g. the (nx, nz)
+/// snapshots of a grid with ny == 1, or the (nx,) map of a grid with ny == 1. None for a 3D grid.
+pub fn section_view<'a, T, D: Dimension + RemoveAxis>(
+    array: ArrayView<'a, T, D>,
+    dims: (usize, usize, usize),
+) -> Option<ArrayView<'a, T, D::Smaller>> {
+    let axis = section_axis(dims)?;
+    Some(array.index_axis_move(axis.flat_axis(), 0))
+}
+
+/// Run the simulation on a vertical cross-section given as 2D arrays: the (n, nz) reservoir matrix, the (n,)
+/// bedrock indices and the source as (i, z). The section is simulated as a grid one cell wide along x, which the
+/// engine fills with the in-plane moves only, and the snapshots come back as (n, nz).
+pub fn simulate_cross_section(
+    reservoir_matrix: ArrayView2<f64>,
+    depths: ArrayView1<f64>,
+    bedrock_indices: ArrayView1<usize>,
+    (i, z): (usize, usize),
+    config: &SimulationConfig,
+) -> Result<Array2<i32>, SimulationError> {
+    let mut simulation = Simulation::try_new(
+        reservoir_matrix.insert_axis(Axis(1)),
+        depths,
+        bedrock_indices.insert_axis(Axis(1)),
+        (i, 0, z),
+        config,
+    )?;
+    simulation.run();
+    Ok(simulation.into_snapshots().index_axis_move(Axis(1), 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use crate::injection_simulation::lateral_directions;
+    use crate::maps::thickness_map;
+    use numpy::ndarray::{s, Array1, Array3};
+
+    #[test]
+    fn test_cross_section_matches_the_3d_run() {
+        // An anticline with a thin shale, in a section along x
+        let (n, nz) = (40, 12);
+        let mut section = Array2::from_elem((n, nz), VELOCITY_RESERVOIR);
+        for x in 0..n {
+            let crest = (x as f64 - 20.0).abs() as usize / 5;
+            section.slice_mut(s![x, ..=crest]).fill(VELOCITY_CAPROCK);
+            if !(24..28).contains(&x) {
+                section[[x, crest + 4]] = VELOCITY_CAPROCK;
+            }
+        }
+        let depths = Array1::from_iter((0..nz).map(|z| 1000.0 + 5.0 * z as f64));
+        let bedrock = Array1::<usize>::zeros(n);
+        let config = SimulationConfig {
+            max_column_height: Some(3),
+            total_snapshots: 10,
+            ..Default::default()
+        };
+        let snapshots = simulate_cross_section(
+            section.view(),
+            depths.view(),
+            bedrock.view(),
+            (20, 5),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(snapshots.dim(), (n, nz));
+        assert!(snapshots.iter().filter(|&&s| s >= 0).count() > 20);
+
+        // The same section along y, run as a 3D grid, gives the same fill
+        let along_y: Array3<f64> = section.clone().insert_axis(Axis(0));
+        let mut simulation = Simulation::new(
+            along_y.view(),
+            depths.view(),
+            Array2::zeros((1, n)).view(),
+            (0, 20, 5),
+            &config,
+        );
+        simulation.run();
+        let dims = along_y.dim();
+        assert_eq!(section_axis(dims), Some(SectionAxis::Y));
+        let full = simulation.snapshots();
+        assert_eq!(section_view(full.view(), dims).unwrap(), snapshots);
+        assert_eq!(
+            section_view(thickness_map(&simulation.plume()).view(), dims)
+                .unwrap()
+                .dim(),
+            n
+        );
+
+        assert_eq!(
+            in_plane_directions(lateral_directions((1, 1)), (n, 1, nz)),
+            [(-1, 0), (1, 0)]
+        );
+        assert_eq!(section_axis((4, 3, 2)), None);
+    }
+}
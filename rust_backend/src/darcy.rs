@@ -0,0 +1,600 @@
+use numpy::ndarray::{Array3, ArrayView1, ArrayView2, ArrayView3};
+
+use crate::cell_state::CellState;
+use crate::config::SimulationConfig;
+use crate::constants::GRAVITY;
+use crate::error::SimulationError;
+use crate::grid::{Grid, RegularGrid};
+use crate::injection_simulation::{check_initial_position, initial_reservoir_state};
+use crate::validation::validate_inputs;
+
+// A time step moves at most this much saturation in or out of any cell
+const MAX_SATURATION_CHANGE: f64 = 0.1;
+// Give up instead of stepping forever when the CFL limit gets tiny
+const MAX_TIME_STEPS: usize = 100_000;
+const PRESSURE_TOLERANCE: f64 = 1e-10;
+
+/// Fluid properties of the Darcy-flow engine at reservoir conditions, in SI units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FluidProperties {
+    /// Density of brine in kg/m³
+    pub brine_density: f64,
+    /// Density of CO2 in kg/m³
+    pub co2_density: f64,
+    /// Viscosity of brine in Pa s
+    pub brine_viscosity: f64,
+    /// Viscosity of CO2 in Pa s
+    pub co2_viscosity: f64,
+    /// Exponent of the Corey relative permeabilities, k_r = S^n for both phases
+    pub corey_exponent: f64,
+}
+
+impl Default for FluidProperties {
+    fn default() -> Self {
+        FluidProperties {
+            brine_density: 1030.0,
+            co2_density: 650.0,
+            brine_viscosity: 5e-4,
+            co2_viscosity: 5e-5,
+            corey_exponent: 2.0,
+        }
+    }
+}
+
+impl FluidProperties {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        let values = [
+            ("brine_density", self.brine_density),
+            ("co2_density", self.co2_density),
+            ("brine_viscosity", self.brine_viscosity),
+            ("co2_viscosity", self.co2_viscosity),
+        ];
+        for (name, value) in values {
+            if !(value.is_finite() && value > 0.0) {
+                return Err(SimulationError::InvalidValue {
+                    argument: "fluid_properties".to_string(),
+                    message: format!("{} must be positive, got {}", name, value),
+                });
+            }
+        }
+        if !(self.corey_exponent.is_finite() && self.corey_exponent >= 1.0) {
+            return Err(SimulationError::InvalidValue {
+                argument: "fluid_properties".to_string(),
+                message: format!(
+                    "corey_exponent must be at least 1, got {}",
+                    self.corey_exponent
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Mobility of brine and CO2 at the given CO2 saturation
+    fn mobilities(&self, saturation: f64) -> (f64, f64) {
+        (
+            (1.0 - saturation).powf(self.corey_exponent) / self.brine_viscosity,
+            saturation.powf(self.corey_exponent) / self.co2_viscosity,
+        )
+    }
+}
+
+/// A coarse Darcy-flow engine for pressure-driven behaviour: incompressible two-phase flow of CO2 and brine,
+/// solved with IMPES (implicit pressure, explicit saturation) and two-point fluxes between face neighbours.
+/// The displaced brine leaves through the lateral edges of the grid, which are held at hydrostatic pressure.
+/// Inactive cells and the bedrock are impermeable, while other caprock cells keep their permeability.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DarcyFlow {
+    /// Permeability of every cell in m²
+    pub permeability: Array3<f64>,
+    /// Porosity of every cell
+    pub porosity: Array3<f64>,
+    /// Cell size along (x, y, z) in m
+    pub cell_size: (f64, f64, f64),
+    pub fluids: FluidProperties,
+    /// Injection rate of CO2 in m³/s at reservoir conditions, shared evenly between the wells
+    pub injection_rate: f64,
+    /// Duration of the injection in s. The snapshots split it into equal intervals.
+    pub injection_time: f64,
+    /// A cell counts as filled in the snapshots once its CO2 saturation reaches this
+    pub saturation_threshold: f64,
+}
+
+/// The outcome of a Darcy-flow run
+#[derive(Debug, Clone, PartialEq)]
+pub struct DarcyResult {
+    /// The snapshot at which every cell was filled, or -1, in the same format as the percolation fill
+    pub snapshots: Array3<i32>,
+    /// CO2 saturation at the end of the injection
+    pub saturation: Array3<f64>,
+    /// Pressure in Pa at the end of the injection, or NaN for cells without flow
+    pub pressure: Array3<f64>,
+    pub time_steps: usize,
+}
+
+/// A connection between two cells, by their index among the flowing cells
+struct Face {
+    cells: (usize, usize),
+    transmissibility: f64,
+    /// Depth of the first cell minus the depth of the second
+    depth_difference: f64,
+}
+
+impl DarcyFlow {
+    pub fn validate(&self, dims: (usize, usize, usize)) -> Result<(), SimulationError> {
+        for (name, array) in [
+            ("permeability", &self.permeability),
+            ("porosity", &self.porosity),
+        ] {
+            if array.dim() != dims {
+                return Err(SimulationError::ShapeMismatch {
+                    argument: name.to_string(),
+                    expected: format!("{:?} to match reservoir_matrix", dims),
+                    actual: format!("{:?}", array.dim()),
+                });
+            }
+        }
+        let invalid = |message: String| {
+            Err(SimulationError::InvalidValue {
+                argument: "darcy_flow".to_string(),
+                message,
+            })
+        };
+        if let Some((cell, k)) = self
+            .permeability
+            .indexed_iter()
+            .find(|&(_, &k)| !(k.is_finite() && k >= 0.0))
+        {
+            return invalid(format!("permeability at {:?} is {}", cell, k));
+        }
+        if let Some((cell, phi)) = self
+            .porosity
+            .indexed_iter()
+            .find(|&(_, &phi)| !(0.0..=1.0).contains(&phi))
+        {
+            return invalid(format!("porosity at {:?} is {}", cell, phi));
+        }
+        let (dx, dy, dz) = self.cell_size;
+        if ![dx, dy, dz].iter().all(|&d| d.is_finite() && d > 0.0) {
+            return invalid("the cell size must be positive".to_string());
+        }
+        if !(self.injection_rate.is_finite() && self.injection_rate > 0.0) {
+            return invalid("the injection rate must be positive".to_string());
+        }
+        if !(self.injection_time.is_finite() && self.injection_time > 0.0) {
+            return invalid("the injection time must be positive".to_string());
+        }
+        if !(self.saturation_threshold > 0.0 && self.saturation_threshold <= 1.0) {
+            return invalid("the saturation threshold must be in (0, 1]".to_string());
+        }
+        self.fluids.validate()
+    }
+
+    /// Check the inputs of a run and find the cells that take part in the flow: the active cells with a positive
+    /// permeability and porosity, except the bedrock
+    pub fn flowing_cells(
+        &self,
+        reservoir_matrix: ArrayView3<f64>,
+        depths: ArrayView1<f64>,
+        bedrock_indices: ArrayView2<usize>,
+        sources: &[(usize, usize, usize)],
+        config: &SimulationConfig,
+    ) -> Result<Array3<bool>, SimulationError> {
+        if sources.is_empty() {
+            return Err(SimulationError::InvalidSource(
+                "At least one source is required".to_string(),
+            ));
+        }
+        for &source in sources {
+            validate_inputs(&reservoir_matrix, &depths, &bedrock_indices, source, config)?;
+        }
+        let dims = reservoir_matrix.dim();
+        self.validate(dims)?;
+
+        let grid = RegularGrid::new(dims.0, dims.1, depths);
+        let reservoir = initial_reservoir_state(
+            &reservoir_matrix,
+            &grid,
+            &bedrock_indices,
+            config.velocity_classifier,
+        );
+        for &source in sources {
+            check_initial_position(&reservoir, source)?;
+        }
+
+        Ok(Array3::from_shape_fn(dims, |(x, y, z)| {
+            let state = reservoir.state((x, y, z));
+            let is_bedrock = z == bedrock_indices[[x, y]] && state == CellState::Caprock;
+            state != CellState::Inactive
+                && !is_bedrock
+                && self.permeability[[x, y, z]] > 0.0
+                && self.porosity[[x, y, z]] > 0.0
+        }))
+    }
+
+    /// Inject from the sources and return the snapshots, saturation and pressure. Only the snapshot count and
+    /// velocity classifier of the config are used.
+    pub fn run(
+        &self,
+        reservoir_matrix: ArrayView3<f64>,
+        depths: ArrayView1<f64>,
+        bedrock_indices: ArrayView2<usize>,
+        sources: &[(usize, usize, usize)],
+        config: &SimulationConfig,
+    ) -> Result<DarcyResult, SimulationError> {
+        let flowing =
+            self.flowing_cells(reservoir_matrix, depths, bedrock_indices, sources, config)?;
+        let dims = reservoir_matrix.dim();
+        let grid = RegularGrid::new(dims.0, dims.1, depths);
+
+        // Number the cells that take part in the flow
+        let mut index = Array3::from_elem(dims, usize::MAX);
+        let mut cells = Vec::new();
+        for (cell, i) in index.indexed_iter_mut() {
+            if flowing[cell] {
+                *i = cells.len();
+                cells.push(cell);
+            }
+        }
+
+        let (dx, dy, dz) = self.cell_size;
+        let (faces, boundary) = self.connections(&cells, &index, &grid);
+        let reachable = reachable_from_boundary(cells.len(), &faces, &boundary);
+        for &source in sources {
+            if index[source] == usize::MAX || !reachable[index[source]] {
+                return Err(SimulationError::InvalidSource(format!(
+                    "Source {:?} is sealed off from the edges of the grid, where the displaced brine leaves",
+                    source
+                )));
+            }
+        }
+
+        let cell_depths: Vec<f64> = cells.iter().map(|&cell| grid.cell_depth(cell)).collect();
+        let pore_volumes: Vec<f64> = cells
+            .iter()
+            .map(|&(x, y, z)| self.porosity[[x, y, z]] * dx * dy * dz)
+            .collect();
+        let mut injection = vec![0.0; cells.len()];
+        for &source in sources {
+            injection[index[source]] += self.injection_rate / sources.len() as f64;
+        }
+
+        let fluids = &self.fluids;
+        let brine_head = |i: usize| fluids.brine_density * GRAVITY * cell_depths[i];
+        let mut pressure: Vec<f64> = (0..cells.len()).map(brine_head).collect();
+        let mut saturation = vec![0.0; cells.len()];
+        let mut snapshots = Array3::from_elem(dims, -1);
+        let mut snapshot_index = 0;
+        let mut time = 0.0;
+        let mut time_steps = 0;
+
+        while snapshot_index < config.total_snapshots {
+            let snapshot_end =
+                self.injection_time * (snapshot_index + 1) as f64 / config.total_snapshots as f64;
+            if time_steps >= MAX_TIME_STEPS {
+                return Err(SimulationError::InvalidValue {
+                    argument: "darcy_flow".to_string(),
+                    message: format!(
+                        "needed more than {} time steps, try a lower injection rate or a coarser grid",
+                        MAX_TIME_STEPS
+                    ),
+                });
+            }
+
+            // Phase mobilities are upwinded with the potentials of the previous pressure
+            let mobilities: Vec<(f64, f64)> =
+                saturation.iter().map(|&s| fluids.mobilities(s)).collect();
+            let upwind: Vec<(f64, f64)> = faces
+                .iter()
+                .map(|face| {
+                    let (i, j) = face.cells;
+                    let (brine, co2) = self.potential_differences(&pressure, face);
+                    (
+                        if brine >= 0.0 {
+                            mobilities[i].0
+                        } else {
+                            mobilities[j].0
+                        },
+                        if co2 >= 0.0 {
+                            mobilities[i].1
+                        } else {
+                            mobilities[j].1
+                        },
+                    )
+                })
+                .collect();
+            let boundary_mobility =
+                |i: usize| (mobilities[i].0 + mobilities[i].1 + 1.0 / fluids.brine_viscosity) / 2.0;
+
+            // Implicit pressure: the total flux out of every cell balances the injection
+            let mut diagonal = vec![0.0; cells.len()];
+            let mut rhs = injection.clone();
+            for (face, &(brine, co2)) in faces.iter().zip(&upwind) {
+                let (i, j) = face.cells;
+                let conductance = face.transmissibility * (brine + co2);
+                diagonal[i] += conductance;
+                diagonal[j] += conductance;
+                let gravity = face.transmissibility
+                    * (brine * fluids.brine_density + co2 * fluids.co2_density)
+                    * GRAVITY
+                    * face.depth_difference;
+                rhs[i] += gravity;
+                rhs[j] -= gravity;
+            }
+            for &(i, transmissibility) in &boundary {
+                let conductance = transmissibility * boundary_mobility(i);
+                diagonal[i] += conductance;
+                rhs[i] += conductance * brine_head(i);
+            }
+            for i in (0..cells.len()).filter(|&i| !reachable[i]) {
+                diagonal[i] = 1.0;
+                rhs[i] = pressure[i];
+            }
+            let apply = |p: &[f64], out: &mut [f64]| {
+                for i in 0..p.len() {
+                    out[i] = diagonal[i] * p[i];
+                }
+                for (face, &(brine, co2)) in faces.iter().zip(&upwind) {
+                    let (i, j) = face.cells;
+                    if reachable[i] {
+                        let conductance = face.transmissibility * (brine + co2);
+                        out[i] -= conductance * p[j];
+                        out[j] -= conductance * p[i];
+                    }
+                }
+            };
+            conjugate_gradient(apply, &diagonal, &rhs, &mut pressure);
+
+            // Explicit saturation: move the CO2 with the upwinded phase fluxes
+            let mut co2_rate = injection.clone();
+            for (face, &(_, co2)) in faces.iter().zip(&upwind) {
+                let (i, j) = face.cells;
+                let (_, potential) = self.potential_differences(&pressure, face);
+                let flux = face.transmissibility * co2 * potential;
+                co2_rate[i] -= flux;
+                co2_rate[j] += flux;
+            }
+            for &(i, transmissibility) in &boundary {
+                // Only brine flows in from outside the grid
+                let potential = pressure[i] - brine_head(i);
+                if potential > 0.0 {
+                    co2_rate[i] -= transmissibility * mobilities[i].1 * potential;
+                }
+            }
+            let max_change = co2_rate
+                .iter()
+                .zip(&pore_volumes)
+                .map(|(rate, volume)| rate.abs() / volume)
+                .fold(0.0, f64::max);
+            // Steps end on the snapshot times, so every snapshot gets at least one step
+            let stable_dt = MAX_SATURATION_CHANGE / max_change;
+            let reaches_snapshot = stable_dt >= snapshot_end - time;
+            let dt = if reaches_snapshot {
+                snapshot_end - time
+            } else {
+                stable_dt
+            };
+            for i in 0..cells.len() {
+                saturation[i] =
+                    (saturation[i] + dt * co2_rate[i] / pore_volumes[i]).clamp(0.0, 1.0);
+            }
+            time = if reaches_snapshot {
+                snapshot_end
+            } else {
+                time + dt
+            };
+            time_steps += 1;
+
+            for (i, &cell) in cells.iter().enumerate() {
+                if snapshots[cell] == -1 && saturation[i] >= self.saturation_threshold {
+                    snapshots[cell] = snapshot_index as i32;
+                }
+            }
+            if reaches_snapshot {
+                snapshot_index += 1;
+            }
+        }
+
+        let mut saturation_cube = Array3::zeros(dims);
+        let mut pressure_cube = Array3::from_elem(dims, f64::NAN);
+        for (i, &cell) in cells.iter().enumerate() {
+            saturation_cube[cell] = saturation[i];
+            if reachable[i] {
+                pressure_cube[cell] = pressure[i];
+            }
+        }
+        Ok(DarcyResult {
+            snapshots,
+            saturation: saturation_cube,
+            pressure: pressure_cube,
+            time_steps,
+        })
+    }
+
+    /// Brine and CO2 potential differences across the face, from the first cell to the second
+    fn potential_differences(&self, pressure: &[f64], face: &Face) -> (f64, f64) {
+        let (i, j) = face.cells;
+        let gravity = GRAVITY * face.depth_difference;
+        (
+            pressure[i] - pressure[j] - self.fluids.brine_density * gravity,
+            pressure[i] - pressure[j] - self.fluids.co2_density * gravity,
+        )
+    }
+
+    /// The faces between flowing cells, and the transmissibility of the flowing cells on the lateral edges
+    /// of the grid to the outside, using harmonic averages of the permeability
+    fn connections(
+        &self,
+        cells: &[(usize, usize, usize)],
+        index: &Array3<usize>,
+        grid: &RegularGrid,
+    ) -> (Vec<Face>, Vec<(usize, f64)>) {
+        let (nx, ny, nz) = index.dim();
+        let (dx, dy, dz) = self.cell_size;
+        let k = |cell: (usize, usize, usize)| self.permeability[cell];
+        let mut faces = Vec::new();
+        let mut boundary = Vec::new();
+        for (i, &(x, y, z)) in cells.iter().enumerate() {
+            let neighbors = [
+                (x + 1 < nx).then_some(((x + 1, y, z), dy * dz, dx)),
+                (y + 1 < ny).then_some(((x, y + 1, z), dx * dz, dy)),
+                (z + 1 < nz).then_some(((x, y, z + 1), dx * dy, dz)),
+            ];
+            for (neighbor, area, distance) in neighbors.into_iter().flatten() {
+                let j = index[neighbor];
+                if j == usize::MAX {
+                    continue;
+                }
+                let (ki, kj) = (k((x, y, z)), k(neighbor));
+                faces.push(Face {
+                    cells: (i, j),
+                    transmissibility: area * 2.0 * ki * kj / (ki + kj) / distance,
+                    depth_difference: grid.cell_depth((x, y, z)) - grid.cell_depth(neighbor),
+                });
+            }
+
+            // Edges along a dimension of a single cell are closed, so that cross-sections work
+            let mut transmissibility = 0.0;
+            if nx > 1 {
+                let sides = (x == 0) as usize + (x + 1 == nx) as usize;
+                transmissibility += sides as f64 * dy * dz * k((x, y, z)) / (dx / 2.0);
+            }
+            if ny > 1 {
+                let sides = (y == 0) as usize + (y + 1 == ny) as usize;
+                transmissibility += sides as f64 * dx * dz * k((x, y, z)) / (dy / 2.0);
+            }
+            if transmissibility > 0.0 {
+                boundary.push((i, transmissibility));
+            }
+        }
+        (faces, boundary)
+    }
+}
+
+/// Cells connected to the edges of the grid. Brine cannot leave the other cells, so they take no part
+/// in the incompressible flow.
+fn reachable_from_boundary(n_cells: usize, faces: &[Face], boundary: &[(usize, f64)]) -> Vec<bool> {
+    let mut neighbors = vec![Vec::new(); n_cells];
+    for face in faces {
+        let (i, j) = face.cells;
+        neighbors[i].push(j);
+        neighbors[j].push(i);
+    }
+    let mut reachable = vec![false; n_cells];
+    let mut stack: Vec<usize> = boundary.iter().map(|&(i, _)| i).collect();
+    for &i in &stack {
+        reachable[i] = true;
+    }
+    while let Some(i) = stack.pop() {
+        for &j in &neighbors[i] {
+            if !reachable[j] {
+                reachable[j] = true;
+                stack.push(j);
+            }
+        }
+    }
+    reachable
+}
+
+/// Solve the symmetric positive definite system with Jacobi-preconditioned conjugate gradients,
+/// starting from the given solution
+fn conjugate_gradient(
+    apply: impl Fn(&[f64], &mut [f64]),
+    diagonal: &[f64],
+    rhs: &[f64],
+    solution: &mut [f64],
+) {
+    let n = rhs.len();
+    let dot = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f64>();
+    let mut product = vec![0.0; n];
+    apply(solution, &mut product);
+    let mut residual: Vec<f64> = rhs.iter().zip(&product).map(|(b, ax)| b - ax).collect();
+    let mut z: Vec<f64> = residual.iter().zip(diagonal).map(|(r, d)| r / d).collect();
+    let mut direction = z.clone();
+    let mut rz = dot(&residual, &z);
+    let tolerance = PRESSURE_TOLERANCE * dot(rhs, rhs).sqrt().max(f64::MIN_POSITIVE);
+
+    for _ in 0..10 * n.max(10) {
+        if dot(&residual, &residual).sqrt() <= tolerance {
+            break;
+        }
+        apply(&direction, &mut product);
+        let alpha = rz / dot(&direction, &product);
+        for i in 0..n {
+            solution[i] += alpha * direction[i];
+            residual[i] -= alpha * product[i];
+            z[i] = residual[i] / diagonal[i];
+        }
+        let rz_new = dot(&residual, &z);
+        let beta = rz_new / rz;
+        rz = rz_new;
+        for i in 0..n {
+            direction[i] = z[i] + beta * direction[i];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use numpy::ndarray::{s, Array1, Array2};
+
+    fn run_layer(injection_time: f64) -> (DarcyFlow, DarcyResult) {
+        // A caprock layer over two reservoir layers, with the well under the caprock in the middle
+        let mut reservoir = Array3::from_elem((9, 1, 3), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        let depths = Array1::from(vec![1000.0, 1010.0, 1020.0]);
+        let bedrock_indices = Array2::<usize>::zeros((9, 1));
+        let model = DarcyFlow {
+            permeability: Array3::from_elem((9, 1, 3), 1e-13),
+            porosity: Array3::from_elem((9, 1, 3), 0.2),
+            cell_size: (50.0, 50.0, 10.0),
+            fluids: FluidProperties::default(),
+            injection_rate: 1e-4,
+            injection_time,
+            saturation_threshold: 0.05,
+        };
+        let config = SimulationConfig {
+            total_snapshots: 10,
+            ..Default::default()
+        };
+        let result = model
+            .run(
+                reservoir.view(),
+                depths.view(),
+                bedrock_indices.view(),
+                &[(4, 0, 1)],
+                &config,
+            )
+            .unwrap();
+        (model, result)
+    }
+
+    #[test]
+    fn test_injected_volume_is_conserved() {
+        // 5000 m³ of pore space per cell, and 1000 m³ of CO2 injected, far from the edges
+        let (model, result) = run_layer(1e7);
+        let pore_volume = 0.2 * 50.0 * 50.0 * 10.0;
+        let co2_volume = result.saturation.sum() * pore_volume;
+        let injected = model.injection_rate * model.injection_time;
+        assert!((co2_volume - injected).abs() < 1e-6 * injected);
+
+        // The caprock takes no part in the flow
+        assert!(result.pressure[[4, 0, 0]].is_nan());
+        assert_eq!(result.snapshots[[4, 0, 0]], -1);
+        assert!(result.snapshots[[4, 0, 1]] >= 0);
+        assert!(result.pressure[[4, 0, 1]] > 1030.0 * GRAVITY * 1010.0);
+    }
+
+    #[test]
+    fn test_buoyant_co2_spreads_under_the_caprock() {
+        let (_, result) = run_layer(1e8);
+        let saturation = &result.saturation;
+        assert!(saturation[[5, 0, 1]] > saturation[[4, 0, 2]]);
+        assert!(saturation[[3, 0, 1]] > 0.0);
+        // The plume grows outwards from the well over the snapshots
+        assert_eq!(result.snapshots[[4, 0, 1]], 0);
+        assert!(result.snapshots[[6, 0, 1]] > result.snapshots[[5, 0, 1]]);
+        assert_eq!(result.snapshots[[4, 0, 2]], -1);
+    }
+}
@@ -1,14 +1,68 @@
 use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
 use std::collections::{BinaryHeap, HashMap, VecDeque};
 
+/// The front of cells waiting to be processed. Cells are popped shallowest first,
+/// and cells at the same depth in the order they were pushed.
+///
+/// The order only depends on the sequence of pushes and pops, never on hashing or memory layout,
+/// so a simulation gives the same fill order on every run and platform.
+pub trait FrontQueue {
+    fn push(&mut self, depth: f64, loc: (usize, usize, usize));
+
+    fn pop(&mut self) -> Option<(usize, usize, usize)>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The available front queue implementations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum QueueKind {
+    /// Heap of depths with a FIFO queue per depth
+    #[default]
+    DepthOrdered,
+    /// One FIFO bucket per z index. Requires the depths to increase with z.
+    Bucket,
+    /// A single binary heap ordered by (depth, insertion order)
+    BinaryHeap,
+}
+
+impl QueueKind {
+    pub const ALL: [QueueKind; 3] = [
+        QueueKind::DepthOrdered,
+        QueueKind::Bucket,
+        QueueKind::BinaryHeap,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueueKind::DepthOrdered => "depth_ordered",
+            QueueKind::Bucket => "bucket",
+            QueueKind::BinaryHeap => "binary_heap",
+        }
+    }
+
+    /// Parse a queue from its name: "depth_ordered", "bucket" or "binary_heap"
+    pub fn from_name(name: &str) -> Option<Self> {
+        QueueKind::ALL
+            .into_iter()
+            .find(|queue| queue.name() == name)
+    }
+}
+
 // Optimized data structure for depth-ordered processing
 // Uses a heap for depth ordering and queues for cells at the same depth
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct DepthOrderedQueue {
     // Maps depth to queue of cells at that depth
     depth_queues: HashMap<OrderedFloat<f64>, VecDeque<(usize, usize, usize)>>,
     // Min-heap of depths (using Reverse for min-heap behavior)
-    depth_heap: BinaryHeap<std::cmp::Reverse<OrderedFloat<f64>>>,
+    depth_heap: BinaryHeap<Reverse<OrderedFloat<f64>>>,
 }
 
 impl DepthOrderedQueue {
@@ -19,7 +73,28 @@ impl DepthOrderedQueue {
         }
     }
 
-    pub fn push(&mut self, depth: f64, loc: (usize, usize, usize)) {
+    /// Pop the next cell with the depth it was pushed with
+    pub fn pop_with_depth(&mut self) -> Option<(f64, (usize, usize, usize))> {
+        while let Some(&Reverse(depth_key)) = self.depth_heap.peek() {
+            if let Some(queue) = self.depth_queues.get_mut(&depth_key) {
+                if let Some(cell) = queue.pop_front() {
+                    return Some((depth_key.0, cell));
+                } else {
+                    // Queue is empty, remove this depth
+                    self.depth_queues.remove(&depth_key);
+                    self.depth_heap.pop();
+                }
+            } else {
+                // Shouldn't happen, but handle gracefully
+                self.depth_heap.pop();
+            }
+        }
+        None
+    }
+}
+
+impl FrontQueue for DepthOrderedQueue {
+    fn push(&mut self, depth: f64, loc: (usize, usize, usize)) {
         let depth_key = OrderedFloat(depth);
 
         // Add to depth queue
@@ -29,35 +104,334 @@ impl DepthOrderedQueue {
             .push_back(loc);
 
         // Add depth to heap if not already present
-        let reverse_depth = std::cmp::Reverse(depth_key);
+        let reverse_depth = Reverse(depth_key);
         if !self.depth_heap.iter().any(|&d| d == reverse_depth) {
             self.depth_heap.push(reverse_depth);
         }
     }
 
-    pub fn pop(&mut self) -> Option<(usize, usize, usize)> {
-        while let Some(&std::cmp::Reverse(depth_key)) = self.depth_heap.peek() {
-            if let Some(queue) = self.depth_queues.get_mut(&depth_key) {
-                if let Some(cell) = queue.pop_front() {
-                    return Some(cell);
-                } else {
-                    // Queue is empty, remove this depth
-                    self.depth_queues.remove(&depth_key);
-                    self.depth_heap.pop();
+    fn pop(&mut self) -> Option<(usize, usize, usize)> {
+        self.pop_with_depth().map(|(_, cell)| cell)
+    }
+
+    fn len(&self) -> usize {
+        self.depth_queues.values().map(|q| q.len()).sum()
+    }
+}
+
+// Bucket entry: (depth, cell)
+type BucketEntry = (f64, (usize, usize, usize));
+
+// Bucket queue with one FIFO queue per z index. The depth is ignored, so this
+// gives the same order as DepthOrderedQueue only when the depths increase with z.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BucketQueue {
+    // The cells in every bucket with the depths they were pushed with
+    buckets: Vec<VecDeque<BucketEntry>>,
+    // Index of the shallowest bucket that may be non-empty
+    lowest: usize,
+    len: usize,
+}
+
+impl BucketQueue {
+    pub fn new() -> Self {
+        BucketQueue::default()
+    }
+
+    /// Pop the next cell with the depth it was pushed with
+    pub fn pop_with_depth(&mut self) -> Option<(f64, (usize, usize, usize))> {
+        if self.len == 0 {
+            return None;
+        }
+        while self.buckets[self.lowest].is_empty() {
+            self.lowest += 1;
+        }
+        self.len -= 1;
+        self.buckets[self.lowest].pop_front()
+    }
+}
+
+impl FrontQueue for BucketQueue {
+    fn push(&mut self, depth: f64, loc: (usize, usize, usize)) {
+        let zi = loc.2;
+        if zi >= self.buckets.len() {
+            self.buckets.resize_with(zi + 1, VecDeque::new);
+        }
+        self.buckets[zi].push_back((depth, loc));
+        if self.len == 0 || zi < self.lowest {
+            self.lowest = zi;
+        }
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<(usize, usize, usize)> {
+        self.pop_with_depth().map(|(_, cell)| cell)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+// Heap entry: (depth, insertion counter, cell)
+type HeapEntry = Reverse<(OrderedFloat<f64>, u64, (usize, usize, usize))>;
+
+// Plain binary heap. The insertion counter breaks ties so cells at the same depth are popped in FIFO order.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HeapQueue {
+    heap: BinaryHeap<HeapEntry>,
+    counter: u64,
+}
+
+impl HeapQueue {
+    pub fn new() -> Self {
+        HeapQueue::default()
+    }
+
+    /// Pop the next cell with the depth it was pushed with
+    pub fn pop_with_depth(&mut self) -> Option<(f64, (usize, usize, usize))> {
+        self.heap
+            .pop()
+            .map(|Reverse((depth, _, loc))| (depth.0, loc))
+    }
+}
+
+impl FrontQueue for HeapQueue {
+    fn push(&mut self, depth: f64, loc: (usize, usize, usize)) {
+        self.heap
+            .push(Reverse((OrderedFloat(depth), self.counter, loc)));
+        self.counter += 1;
+    }
+
+    fn pop(&mut self) -> Option<(usize, usize, usize)> {
+        self.pop_with_depth().map(|(_, cell)| cell)
+    }
+
+    fn len(&self) -> usize {
+        self.heap.len()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum QueueImpl {
+    DepthOrdered(DepthOrderedQueue),
+    Bucket(BucketQueue),
+    BinaryHeap(HeapQueue),
+}
+
+/// A front queue of the kind selected in the config.
+/// A cell waiting in the queue keeps the lowest key it was pushed with. Pushed again with the same or a higher
+/// key, e.g. when another neighbour reaches it, it is not queued again, which keeps the queue small. Pushed with
+/// a lower key, e.g. from a neighbour along a stencil offset with a smaller weight, it moves up the queue, and
+/// its earlier entry is skipped when popped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnyFrontQueue {
+    queue: QueueImpl,
+    // Cells currently waiting in the queue, with their lowest key
+    queued: HashMap<(usize, usize, usize), f64>,
+    // Subtracted from the depth per cell along x and y, to make the front prefer one lateral direction
+    tilt: Option<(f64, f64)>,
+    // Round the keys to KEY_RESOLUTION before ordering, see `with_rounded_keys`
+    round_keys: bool,
+}
+
+/// The resolution of the rounded keys, in m for depths
+pub const KEY_RESOLUTION: f64 = 1e-6;
+
+impl AnyFrontQueue {
+    pub fn new(kind: QueueKind) -> Self {
+        let queue = match kind {
+            QueueKind::DepthOrdered => QueueImpl::DepthOrdered(DepthOrderedQueue::new()),
+            QueueKind::Bucket => QueueImpl::Bucket(BucketQueue::new()),
+            QueueKind::BinaryHeap => QueueImpl::BinaryHeap(HeapQueue::new()),
+        };
+        AnyFrontQueue {
+            queue,
+            queued: HashMap::new(),
+            tilt: None,
+            round_keys: false,
+        }
+    }
+
+    /// A queue that orders the cells by their depth minus the tilt times their x and y index,
+    /// so the front spreads towards increasing x and y first for a positive tilt
+    pub fn with_tilt(kind: QueueKind, tilt: (f64, f64)) -> Self {
+        AnyFrontQueue {
+            tilt: Some(tilt),
+            ..AnyFrontQueue::new(kind)
+        }
+    }
+
+    /// The same queue with its keys rounded to multiples of KEY_RESOLUTION. Keys that are equal in theory but
+    /// were computed along different paths, like tilted depths or depths averaged from corner points, often
+    /// differ in the last bits. Rounded, they tie and are popped in the order they were pushed.
+    pub fn with_rounded_keys(self) -> Self {
+        AnyFrontQueue {
+            round_keys: true,
+            ..self
+        }
+    }
+
+    /// Add to the key of a queued cell, e.g. when the seal its key is measured from broke. The cell moves to
+    /// its new place in the queue, behind the cells already queued with the same key. Does nothing for a cell
+    /// that is not queued.
+    pub fn shift_key(&mut self, loc: (usize, usize, usize), delta: f64) {
+        let Some(&key) = self.queued.get(&loc) else {
+            return;
+        };
+        let key = if self.round_keys {
+            key + (delta / KEY_RESOLUTION).round()
+        } else {
+            key + delta
+        };
+        self.queued.insert(loc, key);
+        self.push_entry(key, loc);
+    }
+
+    fn push_entry(&mut self, key: f64, loc: (usize, usize, usize)) {
+        match &mut self.queue {
+            QueueImpl::DepthOrdered(queue) => queue.push(key, loc),
+            QueueImpl::Bucket(queue) => queue.push(key, loc),
+            QueueImpl::BinaryHeap(queue) => queue.push(key, loc),
+        }
+    }
+}
+
+impl FrontQueue for AnyFrontQueue {
+    fn push(&mut self, depth: f64, loc: (usize, usize, usize)) {
+        let depth = match self.tilt {
+            Some((tx, ty)) => depth - tx * loc.0 as f64 - ty * loc.1 as f64,
+            None => depth,
+        };
+        let depth = if self.round_keys {
+            (depth / KEY_RESOLUTION).round()
+        } else {
+            depth
+        };
+        if self.queued.get(&loc).is_some_and(|&queued| queued <= depth) {
+            return;
+        }
+        self.queued.insert(loc, depth);
+        self.push_entry(depth, loc);
+    }
+
+    fn pop(&mut self) -> Option<(usize, usize, usize)> {
+        loop {
+            let (depth, cell) = match &mut self.queue {
+                QueueImpl::DepthOrdered(queue) => queue.pop_with_depth(),
+                QueueImpl::Bucket(queue) => queue.pop_with_depth(),
+                QueueImpl::BinaryHeap(queue) => queue.pop_with_depth(),
+            }?;
+            // An entry whose cell was pushed again with a lower key is skipped
+            if self
+                .queued
+                .get(&cell)
+                .is_some_and(|queued| queued.to_bits() == depth.to_bits())
+            {
+                self.queued.remove(&cell);
+                return Some(cell);
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.queued.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queues_pop_in_the_same_order() {
+        let depths = [0.0, 10.0, 20.0, 30.0];
+        let pushes = [(2, 0), (1, 1), (3, 2), (1, 3), (0, 4), (2, 5)];
+
+        let mut orders = Vec::new();
+        for kind in [
+            QueueKind::DepthOrdered,
+            QueueKind::Bucket,
+            QueueKind::BinaryHeap,
+        ] {
+            let mut queue = AnyFrontQueue::new(kind);
+            let mut order = Vec::new();
+            for (i, &(z, x)) in pushes.iter().enumerate() {
+                queue.push(depths[z], (x, 0, z));
+                // Interleave pops with pushes
+                if i % 2 == 1 {
+                    order.push(queue.pop().unwrap());
                 }
-            } else {
-                // Shouldn't happen, but handle gracefully
-                self.depth_heap.pop();
             }
+            assert_eq!(queue.len(), 3);
+            while let Some(cell) = queue.pop() {
+                order.push(cell);
+            }
+            assert!(queue.is_empty());
+            orders.push(order);
         }
-        None
+
+        assert_eq!(orders[0][..3], [(1, 0, 1), (3, 0, 1), (4, 0, 0)]);
+        assert_eq!(orders[0], orders[1]);
+        assert_eq!(orders[0], orders[2]);
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.depth_queues.is_empty()
+    #[test]
+    fn test_duplicate_pushes_are_ignored_while_queued() {
+        let mut queue = AnyFrontQueue::new(QueueKind::default());
+        queue.push(1.0, (0, 0, 1));
+        queue.push(0.0, (1, 0, 0));
+        queue.push(1.0, (0, 0, 1));
+        assert_eq!(queue.len(), 2);
+
+        assert_eq!(queue.pop(), Some((1, 0, 0)));
+        assert_eq!(queue.pop(), Some((0, 0, 1)));
+        assert_eq!(queue.pop(), None);
+
+        // Once popped, the cell can be pushed again
+        queue.push(1.0, (0, 0, 1));
+        assert_eq!(queue.len(), 1);
+
+        // A lower key moves a queued cell up, and it is popped once
+        queue.push(0.5, (1, 0, 0));
+        queue.push(0.0, (0, 0, 1));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(), Some((0, 0, 1)));
+        assert_eq!(queue.pop(), Some((1, 0, 0)));
+        assert_eq!(queue.pop(), None);
+
+        // A shifted key moves a queued cell down
+        queue.push(0.0, (0, 0, 0));
+        queue.push(1.0, (1, 0, 0));
+        queue.shift_key((0, 0, 0), 2.0);
+        queue.shift_key((2, 0, 0), -2.0);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(), Some((1, 0, 0)));
+        assert_eq!(queue.pop(), Some((0, 0, 0)));
+        assert_eq!(queue.pop(), None);
     }
 
-    pub fn len(&self) -> usize {
-        self.depth_queues.values().map(|q| q.len()).sum()
+    #[test]
+    fn test_tilt_prefers_downstream_cells() {
+        let mut queue = AnyFrontQueue::with_tilt(QueueKind::default(), (0.5, 0.0));
+        queue.push(1.0, (0, 0, 1));
+        queue.push(1.0, (2, 0, 1));
+        queue.push(0.0, (0, 0, 0));
+        // The cell two steps downstream appears as shallow as the cell one layer up, and was pushed earlier
+        assert_eq!(queue.pop(), Some((2, 0, 1)));
+        assert_eq!(queue.pop(), Some((0, 0, 0)));
+        assert_eq!(queue.pop(), Some((0, 0, 1)));
+    }
+
+    #[test]
+    fn test_rounded_keys_tie() {
+        let mut queue = AnyFrontQueue::new(QueueKind::default()).with_rounded_keys();
+        queue.push(0.1 + 0.2, (0, 0, 0));
+        queue.push(0.3, (1, 0, 0));
+        queue.push(0.3 + 2.0 * KEY_RESOLUTION, (2, 0, 0));
+        // 0.1 + 0.2 is a little more than 0.3, but the cells are popped in the order they were pushed
+        assert_eq!(queue.pop(), Some((0, 0, 0)));
+        assert_eq!(queue.pop(), Some((1, 0, 0)));
+        assert_eq!(queue.pop(), Some((2, 0, 0)));
     }
 }
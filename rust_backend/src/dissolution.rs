@@ -0,0 +1,415 @@
+use numpy::ndarray::Array1;
+
+use crate::aquifer::AquiferFlow;
+use crate::cell_state::{CellHistory, ReservoirState, RockType};
+use crate::error::SimulationError;
+use crate::sparse::SparseGrid;
+
+/// Simplified convective mixing after injection stops. Brine below the plume takes up CO2, becomes denser
+/// and sinks, so fresh brine keeps reaching the plume. Every step dissolves a fixed amount of CO2 per unit
+/// of plume–brine contact area, which slowly thins the plume from its base upwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConvectiveDissolution {
+    /// CO2 dissolved per step for every cell face where the plume rests on brine, as a fraction of a cell
+    pub rate: f64,
+}
+
+impl ConvectiveDissolution {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        if !(self.rate.is_finite() && self.rate >= 0.0) {
+            return Err(SimulationError::InvalidValue {
+                argument: "rate".to_string(),
+                message: format!(
+                    "the dissolution rate must be non-negative, got {}",
+                    self.rate
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Dissolve CO2 for `n_steps` steps, starting from the state at the end of injection.
+    /// With mineral trapping, part of the dissolved CO2 turns into minerals after every step.
+    /// With hysteresis, cells the plume leaves keep their residual saturation, which does not dissolve.
+    /// With aquifer flow, the mobile CO2 drifts downstream after every step.
+    /// The reservoir itself is not changed.
+    pub fn run(
+        &self,
+        reservoir: &ReservoirState,
+        n_steps: usize,
+        mineral_trapping: Option<&MineralTrapping>,
+        hysteresis: Option<&Hysteresis>,
+        aquifer_flow: Option<&AquiferFlow>,
+    ) -> DissolutionHistory {
+        let mut saturation = reservoir.saturation().clone();
+        let mut cell_history = reservoir.history().clone();
+        let total: f64 = saturation.iter().map(|(_, s)| s as f64).sum();
+        let rate_constant = mineral_trapping.map_or(0.0, |m| m.rate_constant);
+        let residual_saturation = hysteresis.map_or(0.0, |h| h.residual_saturation);
+        let drift = aquifer_flow
+            .filter(|flow| flow.drift_rate > 0.0)
+            .and_then(|flow| Some((flow.drift_direction()?, flow.drift_rate)));
+
+        let mut mobile = Array1::zeros(n_steps + 1);
+        let mut residual = Array1::<f64>::zeros(n_steps + 1);
+        let mut dissolved = Array1::<f64>::zeros(n_steps + 1);
+        let mut mineralized = Array1::<f64>::zeros(n_steps + 1);
+        let mut contact_area = Array1::zeros(n_steps + 1);
+        // Cells imbibed in an earlier relaxation already hold only residual CO2
+        residual[0] = residual_co2(&saturation, &cell_history);
+        mobile[0] = total - residual[0];
+        contact_area[0] = plume_base_cells(reservoir, &saturation, &cell_history).len();
+
+        for step in 1..=n_steps {
+            let bases = plume_base_cells(reservoir, &saturation, &cell_history);
+            let mut dissolved_now = 0.0;
+            for &(x, y, z_base) in &bases {
+                // Take the CO2 from the base upwards, as long as the column of CO2 is unbroken
+                let mut remaining = self.rate;
+                for z in (0..=z_base).rev() {
+                    let cell = (x, y, z);
+                    let s = saturation.get(cell) as f64;
+                    if !in_plume(s, cell_history.get(cell)) || remaining <= 0.0 {
+                        break;
+                    }
+                    let available = (s - residual_saturation).max(0.0);
+                    let taken = available.min(remaining);
+                    remaining -= taken;
+                    dissolved_now += taken;
+                    if taken < available {
+                        saturation.set(cell, (s - taken) as f32);
+                    } else {
+                        // Only residual CO2 is left, and brine takes over the cell
+                        let trapped = s.min(residual_saturation);
+                        saturation.set(cell, trapped as f32);
+                        cell_history.set(cell, CellHistory::Imbibition);
+                    }
+                }
+            }
+            if let Some((direction, rate)) = drift {
+                drift_downstream(
+                    reservoir,
+                    &mut saturation,
+                    &mut cell_history,
+                    residual_saturation,
+                    direction,
+                    rate,
+                );
+            }
+            let in_solution = dissolved[step - 1] + dissolved_now;
+            let mineralized_now = rate_constant * in_solution;
+            dissolved[step] = in_solution - mineralized_now;
+            mineralized[step] = mineralized[step - 1] + mineralized_now;
+            residual[step] = residual_co2(&saturation, &cell_history);
+            mobile[step] = (total - residual[step] - dissolved[step] - mineralized[step]).max(0.0);
+            contact_area[step] = bases.len();
+        }
+
+        DissolutionHistory {
+            mobile,
+            residual,
+            dissolved,
+            mineralized,
+            contact_area,
+            saturation,
+            cell_history,
+        }
+    }
+}
+
+/// The CO2 in the grid during the post-injection period, in cells.
+/// Entry 0 is the state at the end of injection, and entry i the state after step i.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DissolutionHistory {
+    /// Free CO2 left in the plume
+    pub mobile: Array1<f64>,
+    /// CO2 left behind as residual saturation in cells the plume has left. Always zero without hysteresis.
+    pub residual: Array1<f64>,
+    /// CO2 dissolved in brine and not yet mineralized
+    pub dissolved: Array1<f64>,
+    /// CO2 turned into minerals so far. Always zero without mineral trapping.
+    pub mineralized: Array1<f64>,
+    /// Number of cell faces where the plume rests on brine, at the start of every step
+    pub contact_area: Array1<usize>,
+    /// The CO2 saturation after the last step
+    pub saturation: SparseGrid<f32>,
+    /// The drainage and imbibition history after the last step
+    pub cell_history: SparseGrid<CellHistory>,
+}
+
+impl DissolutionHistory {
+    pub fn n_steps(&self) -> usize {
+        self.mobile.len() - 1
+    }
+
+    /// All CO2 in the grid after every step. Stays at the amount at the end of injection.
+    pub fn total(&self) -> Array1<f64> {
+        &self.mobile + &self.residual + &self.dissolved + &self.mineralized
+    }
+}
+
+/// Slow conversion of dissolved CO2 into immobile carbonate minerals, as a first-order reaction.
+/// The reaction is far slower than dissolution, so it only matters for projections over centuries.
+/// Only dissolved CO2 is converted, residual CO2 stays as it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MineralTrapping {
+    /// Fraction of the dissolved CO2 mineralized per step, between 0 and 1
+    pub rate_constant: f64,
+}
+
+impl MineralTrapping {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        if !(0.0..=1.0).contains(&self.rate_constant) {
+            return Err(SimulationError::InvalidValue {
+                argument: "mineralization_rate".to_string(),
+                message: format!(
+                    "the rate constant must be between 0 and 1, got {}",
+                    self.rate_constant
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Residual trapping when brine moves back into cells the plume has left. Once the mobile CO2 of a cell
+/// has dissolved, the cell keeps the residual saturation and is open to the plume again, see `CellHistory`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hysteresis {
+    /// CO2 saturation trapped in a cell after imbibition, between 0 and 1
+    pub residual_saturation: f64,
+}
+
+impl Hysteresis {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        if !(0.0..1.0).contains(&self.residual_saturation) {
+            return Err(SimulationError::InvalidValue {
+                argument: "residual_saturation".to_string(),
+                message: format!(
+                    "the residual saturation must be at least 0 and below 1, got {}",
+                    self.residual_saturation
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Whether a cell holds mobile CO2. Imbibed cells only hold residual CO2 and count as brine.
+fn in_plume(saturation: f64, history: CellHistory) -> bool {
+    saturation > 0.0 && history != CellHistory::Imbibition
+}
+
+/// The CO2 held as residual saturation in imbibed cells
+fn residual_co2(saturation: &SparseGrid<f32>, cell_history: &SparseGrid<CellHistory>) -> f64 {
+    cell_history
+        .iter()
+        .filter(|&(_, history)| history == CellHistory::Imbibition)
+        .map(|(cell, _)| saturation.get(cell) as f64)
+        .sum()
+}
+
+/// Whether CO2 can enter the cell
+fn permeable(reservoir: &ReservoirState, cell: (usize, usize, usize)) -> bool {
+    let (x, y, z) = cell;
+    match reservoir.rock_types()[[x, y, z]] {
+        RockType::Reservoir => true,
+        RockType::Caprock => reservoir.breached().get(cell),
+        RockType::Inactive => false,
+    }
+}
+
+/// Move `rate` of the mobile CO2 of every plume cell one cell along the direction, into the neighbour at the
+/// same depth index. CO2 only moves where the neighbour is held down by caprock or CO2 above, since elsewhere
+/// it would rise instead, and only as much as fits in the neighbour. Cells left with only residual CO2 are imbibed.
+fn drift_downstream(
+    reservoir: &ReservoirState,
+    saturation: &mut SparseGrid<f32>,
+    cell_history: &mut SparseGrid<CellHistory>,
+    residual_saturation: f64,
+    (dx, dy): (i32, i32),
+    rate: f64,
+) {
+    let (nx, ny, _) = saturation.dim();
+    let sealed = |cell: (usize, usize, usize)| {
+        let (x, y, z) = cell;
+        match reservoir.rock_types()[[x, y, z]] {
+            RockType::Caprock if !reservoir.breached().get(cell) => true,
+            RockType::Inactive => true,
+            _ => in_plume(saturation.get(cell) as f64, cell_history.get(cell)),
+        }
+    };
+
+    // Work out all moves from the saturation at the start of the step, so the result does not depend on the order
+    let mut moves = Vec::new();
+    for ((x, y, z), s) in saturation.iter() {
+        if !in_plume(s as f64, cell_history.get((x, y, z))) {
+            continue;
+        }
+        let (tx, ty) = (x as i64 + dx as i64, y as i64 + dy as i64);
+        if tx < 0 || ty < 0 || tx >= nx as i64 || ty >= ny as i64 {
+            continue;
+        }
+        let target = (tx as usize, ty as usize, z);
+        if !permeable(reservoir, target) || (z > 0 && !sealed((target.0, target.1, z - 1))) {
+            continue;
+        }
+        let mobile = (s as f64 - residual_saturation).max(0.0);
+        let room = 1.0 - saturation.get(target) as f64;
+        let moved = (rate * mobile).min(room);
+        if moved > 0.0 {
+            moves.push(((x, y, z), target, moved));
+        }
+    }
+    moves.sort_unstable_by_key(|&(cell, _, _)| cell);
+
+    for (cell, target, moved) in moves {
+        let left = saturation.get(cell) as f64 - moved;
+        if left <= residual_saturation {
+            saturation.set(cell, left.max(0.0) as f32);
+            cell_history.set(cell, CellHistory::Imbibition);
+        } else {
+            saturation.set(cell, left as f32);
+        }
+        saturation.set(target, (saturation.get(target) as f64 + moved) as f32);
+        let history = match cell_history.get(target) {
+            CellHistory::Imbibition | CellHistory::SecondaryDrainage => {
+                CellHistory::SecondaryDrainage
+            }
+            _ => CellHistory::Drainage,
+        };
+        cell_history.set(target, history);
+    }
+}
+
+/// The CO2 cells with brine directly below them, sorted so the result does not depend on the storage order
+fn plume_base_cells(
+    reservoir: &ReservoirState,
+    saturation: &SparseGrid<f32>,
+    cell_history: &SparseGrid<CellHistory>,
+) -> Vec<(usize, usize, usize)> {
+    let (_, _, nz) = saturation.dim();
+    let mut bases: Vec<_> = saturation
+        .iter()
+        .filter(|&((x, y, z), s)| {
+            if !in_plume(s as f64, cell_history.get((x, y, z))) || z + 1 >= nz {
+                return false;
+            }
+            let below = (x, y, z + 1);
+            permeable(reservoir, below)
+                && !in_plume(saturation.get(below) as f64, cell_history.get(below))
+        })
+        .map(|(cell, _)| cell)
+        .collect();
+    bases.sort_unstable();
+    bases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell_state::CellState;
+    use numpy::ndarray::Array3;
+
+    #[test]
+    fn test_dissolution_thins_plume_from_below() {
+        // One column with caprock on top, three CO2 cells and brine below. A second column rests on caprock.
+        let mut cells = Array3::from_elem((2, 1, 6), CellState::Reservoir);
+        cells[[0, 0, 0]] = CellState::Caprock;
+        for z in 1..4 {
+            cells[[0, 0, z]] = CellState::Co2;
+        }
+        cells[[1, 0, 1]] = CellState::Co2;
+        cells[[1, 0, 2]] = CellState::Caprock;
+        let reservoir = ReservoirState::from_cell_states(&cells.view());
+
+        let model = ConvectiveDissolution { rate: 0.75 };
+        let history = model.run(&reservoir, 3, None, None, None);
+        assert_eq!(history.n_steps(), 3);
+        assert_eq!(history.mobile.to_vec(), vec![4.0, 3.25, 2.5, 1.75]);
+        assert_eq!(history.dissolved[3], 2.25);
+        assert_eq!(history.contact_area[0], 1);
+        // The base cell is used up first, then the cell above
+        assert_eq!(history.saturation.get((0, 0, 3)), 0.0);
+        assert_eq!(history.saturation.get((0, 0, 2)), 0.0);
+        assert_eq!(history.saturation.get((0, 0, 1)), 0.75);
+        assert_eq!(history.saturation.get((1, 0, 1)), 1.0);
+        // The reservoir is left untouched
+        assert_eq!(reservoir.saturation().get((0, 0, 3)), 1.0);
+
+        assert!(ConvectiveDissolution { rate: -1.0 }.validate().is_err());
+        assert!(history.mineralized.iter().all(|&m| m == 0.0));
+    }
+
+    #[test]
+    fn test_mineral_trapping() {
+        let mut cells = Array3::from_elem((1, 1, 4), CellState::Reservoir);
+        cells[[0, 0, 0]] = CellState::Caprock;
+        cells[[0, 0, 1]] = CellState::Co2;
+        let reservoir = ReservoirState::from_cell_states(&cells.view());
+
+        let model = ConvectiveDissolution { rate: 0.5 };
+        let mineral_trapping = MineralTrapping { rate_constant: 0.5 };
+        let history = model.run(&reservoir, 3, Some(&mineral_trapping), None, None);
+        assert_eq!(history.mobile.to_vec(), vec![1.0, 0.5, 0.0, 0.0]);
+        assert_eq!(history.dissolved.to_vec(), vec![0.0, 0.25, 0.375, 0.1875]);
+        assert_eq!(history.mineralized.to_vec(), vec![0.0, 0.25, 0.625, 0.8125]);
+        assert!(history.total().iter().all(|&t| t == 1.0));
+
+        assert!(MineralTrapping { rate_constant: 1.5 }.validate().is_err());
+    }
+
+    #[test]
+    fn test_hysteresis_leaves_residual_co2() {
+        let mut cells = Array3::from_elem((1, 1, 5), CellState::Reservoir);
+        cells[[0, 0, 0]] = CellState::Caprock;
+        cells[[0, 0, 1]] = CellState::Co2;
+        cells[[0, 0, 2]] = CellState::Co2;
+        let reservoir = ReservoirState::from_cell_states(&cells.view());
+
+        let model = ConvectiveDissolution { rate: 0.5 };
+        let hysteresis = Hysteresis {
+            residual_saturation: 0.25,
+        };
+        let history = model.run(&reservoir, 3, None, Some(&hysteresis), None);
+        // Only the mobile CO2 above the residual saturation dissolves
+        assert_eq!(history.mobile.to_vec(), vec![2.0, 1.5, 0.75, 0.0]);
+        assert_eq!(history.residual.to_vec(), vec![0.0, 0.0, 0.25, 0.5]);
+        assert_eq!(history.dissolved.to_vec(), vec![0.0, 0.5, 1.0, 1.5]);
+        assert_eq!(history.contact_area.to_vec(), vec![1, 1, 1, 1]);
+        for z in [1, 2] {
+            assert_eq!(history.saturation.get((0, 0, z)), 0.25);
+            assert_eq!(history.cell_history.get((0, 0, z)), CellHistory::Imbibition);
+        }
+        assert!(history.total().iter().all(|&t| t == 2.0));
+
+        assert!(Hysteresis {
+            residual_saturation: 1.0
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_aquifer_flow_drifts_plume_downstream() {
+        let mut cells = Array3::from_elem((3, 1, 3), CellState::Reservoir);
+        for x in 0..3 {
+            cells[[x, 0, 0]] = CellState::Caprock;
+        }
+        cells[[0, 0, 1]] = CellState::Co2;
+        let reservoir = ReservoirState::from_cell_states(&cells.view());
+
+        let flow = AquiferFlow {
+            hydraulic_gradient: (0.001, 0.0),
+            cell_size: (100.0, 100.0),
+            density_ratio: 3.0,
+            drift_rate: 0.5,
+        };
+        let model = ConvectiveDissolution { rate: 0.0 };
+        let history = model.run(&reservoir, 2, None, None, Some(&flow));
+        let drifted: Vec<f32> = (0..3).map(|x| history.saturation.get((x, 0, 1))).collect();
+        assert_eq!(drifted, vec![0.25, 0.5, 0.25]);
+        assert_eq!(history.cell_history.get((2, 0, 1)), CellHistory::Drainage);
+        assert!(history.mobile.iter().all(|&m| m == 1.0));
+    }
+}
@@ -0,0 +1,477 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use numpy::ndarray::{ArrayView1, ArrayView2, ArrayView3};
+
+use crate::config::SimulationConfig;
+use crate::constants::GRAVITY;
+use crate::darcy::DarcyFlow;
+
+// Results in the Eclipse GRDECL format, which ResInsight and most other reservoir modelling tools import. The
+// binary restart files (UNRST/EGRID) need the full set of Eclipse header keywords to open, so every report step is
+// written as a GRDECL property instead. Keywords are at most 8 characters and data is ordered with x varying fastest.
+
+/// The longest line of keyword data allowed by Eclipse
+const MAX_LINE_LENGTH: usize = 132;
+/// The report steps are numbered in 4 digits after the SGAS prefix of their keyword
+const MAX_REPORT_STEPS: usize = 10000;
+/// Permeability of one millidarcy in m²
+const MILLIDARCY: f64 = 9.869233e-16;
+/// Pore volume multiplier of the cells on the lateral edges of a deck, to keep them near their initial pressure
+const EDGE_PORE_VOLUME_MULTIPLIER: f64 = 1e6;
+/// Number of saturations in the relative permeability table of a deck
+const RELPERM_TABLE_ROWS: usize = 11;
+
+/// Write the snapshots as a GRDECL file with a gas saturation keyword for every report step: SGAS0000 for the
+/// first snapshot, SGAS0001 for the second and so on. A cell has a saturation of 1 from the report step it was
+/// filled in. With the layer depths of a regular grid the file starts with the grid geometry, with cells of
+/// cell_size (dx, dy) and layers reaching halfway to the next layer, so it can be opened on its own. Without them
+/// only the properties are written, e.g. to import onto the GRDECL file of a corner-point grid.
+pub fn save_grdecl(
+    path: &Path,
+    snapshots: &ArrayView3<i32>,
+    depths: Option<ArrayView1<f64>>,
+    cell_size: (f64, f64),
+) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_grdecl(&mut writer, snapshots, depths, cell_size)?;
+    writer.flush()
+}
+
+/// Write the GRDECL file of `save_grdecl`
+pub fn write_grdecl(
+    writer: &mut impl Write,
+    snapshots: &ArrayView3<i32>,
+    depths: Option<ArrayView1<f64>>,
+    cell_size: (f64, f64),
+) -> std::io::Result<()> {
+    let (nx, ny, nz) = snapshots.dim();
+    let n_report_steps = snapshots
+        .iter()
+        .max()
+        .map_or(0, |&last| (last + 1).max(0) as usize);
+    if n_report_steps > MAX_REPORT_STEPS {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "GRDECL keywords can hold at most {} report steps, got {}",
+                MAX_REPORT_STEPS, n_report_steps
+            ),
+        ));
+    }
+
+    writeln!(
+        writer,
+        "-- CO2 injection simulation {}, {} report steps",
+        env!("CARGO_PKG_VERSION"),
+        n_report_steps
+    )?;
+    if let Some(depths) = depths {
+        if depths.len() != nz {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} layer depths for {} layers", depths.len(), nz),
+            ));
+        }
+        write_regular_geometry(writer, (nx, ny), depths, cell_size)?;
+    }
+
+    for step in 0..n_report_steps {
+        let saturation = cell_values(snapshots, |snapshot| {
+            if snapshot >= 0 && snapshot as usize <= step {
+                1.0
+            } else {
+                0.0
+            }
+        });
+        write_keyword(writer, &format!("SGAS{:04}", step), &saturation)?;
+    }
+    Ok(())
+}
+
+/// The values of the cells in the GRDECL order, with x varying fastest, then y, then z
+fn cell_values<T: Copy>(cells: &ArrayView3<T>, value: impl Fn(T) -> f64) -> Vec<f64> {
+    // The reversed axes of the C-ordered array iterate with x varying fastest
+    cells.t().iter().map(|&cell| value(cell)).collect()
+}
+
+/// Write an OPM Flow input deck of the scenario of a Darcy-flow run, to compare the approximate engines with a full
+/// reservoir simulator on the same model. The deck has the same cells, porosity, permeability, Corey relative
+/// permeabilities and nearly incompressible fluids as `DarcyFlow`, with the cells it leaves out inactive. Every
+/// source is a gas injector perforated in its own cell, and the injection time is split into one report step per
+/// snapshot, so the restart file can be compared snapshot by snapshot. The lateral edges of the grid, which the
+/// Darcy-flow engine holds at hydrostatic pressure, get a large pore volume instead.
+pub fn save_opm_deck(
+    path: &Path,
+    model: &DarcyFlow,
+    reservoir_matrix: ArrayView3<f64>,
+    depths: ArrayView1<f64>,
+    bedrock_indices: ArrayView2<usize>,
+    sources: &[(usize, usize, usize)],
+    config: &SimulationConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_opm_deck(
+        &mut writer,
+        model,
+        reservoir_matrix,
+        depths,
+        bedrock_indices,
+        sources,
+        config,
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the deck of `save_opm_deck`
+pub fn write_opm_deck(
+    writer: &mut impl Write,
+    model: &DarcyFlow,
+    reservoir_matrix: ArrayView3<f64>,
+    depths: ArrayView1<f64>,
+    bedrock_indices: ArrayView2<usize>,
+    sources: &[(usize, usize, usize)],
+    config: &SimulationConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let flowing =
+        model.flowing_cells(reservoir_matrix, depths, bedrock_indices, sources, config)?;
+    let (nx, ny, nz) = flowing.dim();
+    let (dx, dy, dz) = model.cell_size;
+    let fluids = &model.fluids;
+    let n_cells = nx * ny * nz;
+    // The layer of every cell in the GRDECL order
+    let layer = |i: usize| i / (nx * ny);
+
+    writeln!(
+        writer,
+        "-- CO2 injection simulation {}",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(writer, "RUNSPEC\n\nTITLE\nCO2 injection benchmark\n")?;
+    writeln!(writer, "DIMENS\n {} {} {} /\n", nx, ny, nz)?;
+    writeln!(writer, "METRIC\n\nWATER\n\nGAS\n\nTABDIMS\n /\n")?;
+    writeln!(
+        writer,
+        "WELLDIMS\n {} 1 1 {} /\n",
+        sources.len(),
+        sources.len()
+    )?;
+    writeln!(writer, "START\n 1 'JAN' 2000 /\n\nUNIFOUT\n")?;
+
+    // Cells centered on the layer depths, like in the Darcy-flow engine
+    writeln!(writer, "GRID\n")?;
+    write_keyword(writer, "DX", &vec![dx; n_cells])?;
+    write_keyword(writer, "DY", &vec![dy; n_cells])?;
+    write_keyword(writer, "DZ", &vec![dz; n_cells])?;
+    let tops: Vec<f64> = (0..n_cells).map(|i| depths[layer(i)] - dz / 2.0).collect();
+    write_keyword(writer, "TOPS", &tops)?;
+    write_keyword(
+        writer,
+        "ACTNUM",
+        &cell_values(&flowing.view(), |flowing| f64::from(u8::from(flowing))),
+    )?;
+    write_keyword(
+        writer,
+        "PORO",
+        &cell_values(&model.porosity.view(), |phi| phi),
+    )?;
+    let permeability = cell_values(&model.permeability.view(), |k| k / MILLIDARCY);
+    for keyword in ["PERMX", "PERMY", "PERMZ"] {
+        write_keyword(writer, keyword, &permeability)?;
+    }
+    writeln!(writer, "INIT\n")?;
+
+    writeln!(writer, "EDIT\n")?;
+    let edge_multipliers: Vec<f64> = (0..n_cells)
+        .map(|i| {
+            let (x, y) = (i % nx, (i / nx) % ny);
+            if x == 0 || y == 0 || x + 1 == nx || y + 1 == ny {
+                EDGE_PORE_VOLUME_MULTIPLIER
+            } else {
+                1.0
+            }
+        })
+        .collect();
+    write_keyword(writer, "MULTPV", &edge_multipliers)?;
+
+    // Viscosities in cP, and a formation volume factor of about 1 so the surface densities hold in the reservoir
+    writeln!(writer, "PROPS\n\nSGWFN")?;
+    for row in 0..RELPERM_TABLE_ROWS {
+        let saturation = row as f64 / (RELPERM_TABLE_ROWS - 1) as f64;
+        writeln!(
+            writer,
+            " {} {} {} 0",
+            saturation,
+            saturation.powf(fluids.corey_exponent),
+            (1.0 - saturation).powf(fluids.corey_exponent)
+        )?;
+    }
+    writeln!(writer, "/\n")?;
+    writeln!(
+        writer,
+        "PVTW\n 1 1 0 {} 0 /\n",
+        fluids.brine_viscosity * 1e3
+    )?;
+    writeln!(
+        writer,
+        "PVDG\n 1 1 {}\n 1000 0.999 {} /\n",
+        fluids.co2_viscosity * 1e3,
+        fluids.co2_viscosity * 1e3
+    )?;
+    writeln!(
+        writer,
+        "DENSITY\n 800 {} {} /\n",
+        fluids.brine_density, fluids.co2_density
+    )?;
+    writeln!(writer, "ROCK\n 1 0 /\n")?;
+
+    // Hydrostatic brine pressure in bar
+    writeln!(writer, "SOLUTION\n")?;
+    let pressure: Vec<f64> = (0..n_cells)
+        .map(|i| fluids.brine_density * GRAVITY * depths[layer(i)] / 1e5)
+        .collect();
+    write_keyword(writer, "PRESSURE", &pressure)?;
+    write_keyword(writer, "SWAT", &vec![1.0; n_cells])?;
+
+    writeln!(writer, "SUMMARY\n\nFGIT\n\nFGIR\n\nFPR\n\nWBHP\n /\n")?;
+
+    writeln!(writer, "SCHEDULE\n\nRPTRST\n 'BASIC=2' /\n\nWELSPECS")?;
+    for (well, &(x, y, _)) in sources.iter().enumerate() {
+        writeln!(
+            writer,
+            " 'INJ{}' 'INJ' {} {} 1* 'GAS' /",
+            well + 1,
+            x + 1,
+            y + 1
+        )?;
+    }
+    writeln!(writer, "/\n\nCOMPDAT")?;
+    for (well, &(x, y, z)) in sources.iter().enumerate() {
+        writeln!(
+            writer,
+            " 'INJ{}' {} {} {} {} 'OPEN' /",
+            well + 1,
+            x + 1,
+            y + 1,
+            z + 1,
+            z + 1
+        )?;
+    }
+    // Reservoir volume rates in m³/day, with a bottomhole pressure limit no well should reach
+    writeln!(writer, "/\n\nWCONINJE")?;
+    let rate = model.injection_rate * 86400.0 / sources.len() as f64;
+    for well in 0..sources.len() {
+        writeln!(
+            writer,
+            " 'INJ{}' 'GAS' 'OPEN' 'RESV' 1* {} 1000 /",
+            well + 1,
+            rate
+        )?;
+    }
+    writeln!(writer, "/\n")?;
+    let step_days = model.injection_time / 86400.0 / config.total_snapshots as f64;
+    writeln!(
+        writer,
+        "TSTEP\n {}*{} /\n\nEND",
+        config.total_snapshots, step_days
+    )?;
+    Ok(())
+}
+
+/// The top and bottom depth of every layer, halfway to the layers above and below it. The outermost layers are
+/// as thick as their neighbours, and a single layer is 1 m thick.
+fn layer_boundaries(depths: ArrayView1<f64>) -> Vec<(f64, f64)> {
+    let nz = depths.len();
+    let half_thickness = |z: usize| {
+        if nz == 1 {
+            0.5
+        } else if z + 1 < nz {
+            (depths[z + 1] - depths[z]) / 2.0
+        } else {
+            (depths[z] - depths[z - 1]) / 2.0
+        }
+    };
+    (0..nz)
+        .map(|z| {
+            let top = if z == 0 {
+                depths[0] - half_thickness(0)
+            } else {
+                (depths[z - 1] + depths[z]) / 2.0
+            };
+            (top, depths[z] + half_thickness(z))
+        })
+        .collect()
+}
+
+/// SPECGRID, COORD, ZCORN and ACTNUM of a regular grid with vertical pillars
+fn write_regular_geometry(
+    writer: &mut impl Write,
+    (nx, ny): (usize, usize),
+    depths: ArrayView1<f64>,
+    (dx, dy): (f64, f64),
+) -> std::io::Result<()> {
+    let nz = depths.len();
+    let layers = layer_boundaries(depths);
+    writeln!(writer, "SPECGRID\n {} {} {} 1 F /\n", nx, ny, nz)?;
+
+    let (top, bottom) = (layers[0].0, layers[nz - 1].1);
+    let mut coord = Vec::with_capacity(6 * (nx + 1) * (ny + 1));
+    for j in 0..=ny {
+        for i in 0..=nx {
+            let (x, y) = (i as f64 * dx, j as f64 * dy);
+            coord.extend([x, y, top, x, y, bottom]);
+        }
+    }
+    write_keyword(writer, "COORD", &coord)?;
+
+    // Every cell has its own 8 corners, ordered with the x corner index varying fastest, then y, then z
+    let mut zcorn = Vec::with_capacity(8 * nx * ny * nz);
+    for &(top, bottom) in &layers {
+        for depth in [top, bottom] {
+            zcorn.extend(std::iter::repeat_n(depth, 4 * nx * ny));
+        }
+    }
+    write_keyword(writer, "ZCORN", &zcorn)?;
+    write_keyword(writer, "ACTNUM", &vec![1.0; nx * ny * nz])
+}
+
+/// Write the data of a keyword, with runs of equal values written as `count*value`
+fn write_keyword(writer: &mut impl Write, keyword: &str, values: &[f64]) -> std::io::Result<()> {
+    writeln!(writer, "{}", keyword)?;
+    let mut line = String::new();
+    let mut start = 0;
+    while start < values.len() {
+        let run = values[start..]
+            .iter()
+            .take_while(|&&value| value == values[start])
+            .count();
+        let token = match run {
+            1 => format!(" {}", values[start]),
+            _ => format!(" {}*{}", run, values[start]),
+        };
+        if line.len() + token.len() > MAX_LINE_LENGTH {
+            writeln!(writer, "{}", line)?;
+            line.clear();
+        }
+        line.push_str(&token);
+        start += run;
+    }
+    writeln!(writer, "{} /\n", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use crate::darcy::FluidProperties;
+    use crate::grid::{parse_values, read_grdecl_keywords, CornerPointGrid, Grid};
+    use numpy::ndarray::{array, s, Array2, Array3};
+
+    #[test]
+    fn test_grdecl_round_trip() {
+        let mut snapshots = Array3::from_elem((3, 2, 2), -1);
+        snapshots[[1, 0, 1]] = 0;
+        snapshots[[2, 0, 1]] = 1;
+        snapshots[[2, 1, 0]] = 2;
+        let depths = array![1000.0, 1010.0];
+        let mut buffer = Vec::new();
+        write_grdecl(
+            &mut buffer,
+            &snapshots.view(),
+            Some(depths.view()),
+            (50.0, 50.0),
+        )
+        .unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+
+        // The geometry opens as a grid with the cell centers at the layer depths
+        let grid = CornerPointGrid::from_grdecl_str(&text).unwrap();
+        assert_eq!(grid.dim(), (3, 2, 2));
+        assert_eq!(grid.cell_depth((0, 0, 0)), 1000.0);
+        assert_eq!(grid.cell_depth((2, 1, 1)), 1010.0);
+
+        let keywords = read_grdecl_keywords(&text, &["SGAS0000", "SGAS0001", "SGAS0002"]).unwrap();
+        assert_eq!(keywords.len(), 3);
+        let saturation = |step: usize| parse_values::<f64>(&keywords[step].1).unwrap();
+        // x varies fastest, so (1, 0, 1) is at index 1 + 3 * (0 + 2 * 1)
+        assert_eq!(saturation(0).len(), 12);
+        assert_eq!(saturation(0)[7], 1.0);
+        assert_eq!(saturation(0).iter().sum::<f64>(), 1.0);
+        assert_eq!(saturation(1)[8], 1.0);
+        assert_eq!(saturation(2).iter().sum::<f64>(), 3.0);
+        assert_eq!(saturation(2)[5], 1.0);
+
+        // Without depths only the properties are written
+        let mut buffer = Vec::new();
+        write_grdecl(&mut buffer, &snapshots.view(), None, (50.0, 50.0)).unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+        assert!(!text.contains("ZCORN"));
+        assert!(text.contains("SGAS0002"));
+    }
+
+    #[test]
+    fn test_opm_deck_matches_the_darcy_model() {
+        // A caprock layer over two reservoir layers, with the well under the caprock in the middle
+        let mut reservoir = Array3::from_elem((5, 1, 3), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        let model = DarcyFlow {
+            permeability: Array3::from_elem((5, 1, 3), 1e-13),
+            porosity: Array3::from_elem((5, 1, 3), 0.25),
+            cell_size: (10.0, 10.0, 10.0),
+            fluids: FluidProperties::default(),
+            injection_rate: 1e-3,
+            injection_time: 864000.0,
+            saturation_threshold: 0.05,
+        };
+        let config = SimulationConfig {
+            total_snapshots: 4,
+            ..Default::default()
+        };
+        let mut buffer = Vec::new();
+        write_opm_deck(
+            &mut buffer,
+            &model,
+            reservoir.view(),
+            array![1000.0, 1010.0, 1020.0].view(),
+            Array2::zeros((5, 1)).view(),
+            &[(2, 0, 1)],
+            &config,
+        )
+        .unwrap();
+        let text = String::from_utf8(buffer).unwrap();
+
+        let keywords =
+            read_grdecl_keywords(&text, &["DIMENS", "ACTNUM", "PERMX", "TOPS", "TSTEP"]).unwrap();
+        let values = |name: &str| {
+            let (_, values) = keywords
+                .iter()
+                .find(|(keyword, _)| keyword == name)
+                .unwrap();
+            parse_values::<f64>(values).unwrap()
+        };
+        assert_eq!(values("DIMENS"), vec![5.0, 1.0, 3.0]);
+        // The caprock at the bedrock index is inactive, like in the Darcy-flow engine
+        assert_eq!(values("ACTNUM")[..5].iter().sum::<f64>(), 0.0);
+        assert_eq!(values("ACTNUM")[5..].iter().sum::<f64>(), 10.0);
+        assert!((values("PERMX")[0] - 101.325).abs() < 1e-3);
+        assert_eq!(values("TOPS")[5], 1005.0);
+        assert_eq!(values("TSTEP"), vec![2.5; 4]);
+        assert!(text.contains("'INJ1' 3 1 2 2 'OPEN' /"));
+        assert!(text.contains("'RESV' 1* 86.4 1000 /"));
+
+        // The inputs are checked like for a run
+        let mut buffer = Vec::new();
+        assert!(write_opm_deck(
+            &mut buffer,
+            &model,
+            reservoir.view(),
+            array![1000.0, 1010.0, 1020.0].view(),
+            Array2::zeros((5, 1)).view(),
+            &[(2, 0, 0)],
+            &config,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,417 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use ndarray_npy::{read_npy, ReadNpyError};
+use numpy::ndarray::{Array1, Array2, Array3, ArrayView1, ArrayView2, ArrayView3, Zip};
+
+use crate::config::SimulationConfig;
+use crate::error::SimulationError;
+use crate::injection_simulation::run_injection_simulation;
+use crate::spreading::StochasticSpreading;
+
+/// Run the simulation once for every reservoir realization in the ensemble
+pub fn run_ensemble(
+    realizations: &[Array3<f64>],
+    depths: ArrayView1<f64>,
+    bedrock_indices: ArrayView2<usize>,
+    source: (usize, usize, usize),
+    config: &SimulationConfig,
+) -> Vec<Array3<i32>> {
+    realizations
+        .iter()
+        .map(|reservoir_matrix| {
+            run_injection_simulation(
+                reservoir_matrix.view(),
+                depths,
+                bedrock_indices,
+                source,
+                config,
+            )
+        })
+        .collect()
+}
+
+/// Run the simulation once for every seed of the stochastic spreading, for an ensemble of plume shapes from a
+/// single reservoir model. Uses the spreading rules of the config, or the default rules without them.
+pub fn run_spreading_ensemble(
+    reservoir_matrix: ArrayView3<f64>,
+    depths: ArrayView1<f64>,
+    bedrock_indices: ArrayView2<usize>,
+    source: (usize, usize, usize),
+    config: &SimulationConfig,
+    seeds: &[u64],
+) -> Vec<Array3<i32>> {
+    let rules = config.stochastic_spreading.clone().unwrap_or_default();
+    seeds
+        .iter()
+        .map(|&seed| {
+            let config = SimulationConfig {
+                stochastic_spreading: Some(StochasticSpreading {
+                    seed,
+                    ..rules.clone()
+                }),
+                ..config.clone()
+            };
+            run_injection_simulation(reservoir_matrix, depths, bedrock_indices, source, &config)
+        })
+        .collect()
+}
+
+/// Check that the (nx, ny) of a member matches the caprock indices
+fn check_member(
+    member: &ArrayView3<i32>,
+    primary_caprock_indices: &ArrayView2<usize>,
+) -> Result<(), SimulationError> {
+    let (nx, ny) = primary_caprock_indices.dim();
+    let (mx, my, mz) = member.dim();
+    if (mx, my) != (nx, ny) {
+        return Err(SimulationError::ShapeMismatch {
+            argument: "ensemble member".to_string(),
+            expected: format!("({}, {}, nz) of the caprock indices", nx, ny),
+            actual: format!("({}, {}, {})", mx, my, mz),
+        });
+    }
+    Ok(())
+}
+
+/// Find the first snapshot at which CO2 appears above the primary caprock in each column, or -1 if it never does.
+/// `primary_caprock_indices` holds the z index of the top of the primary caprock for each (x, y).
+pub fn first_breach_snapshot_map(
+    snapshots: &ArrayView3<i32>,
+    primary_caprock_indices: &ArrayView2<usize>,
+) -> Array2<i32> {
+    let (nx, ny, nz) = snapshots.dim();
+    Array2::from_shape_fn((nx, ny), |(x, y)| {
+        let top = primary_caprock_indices[[x, y]].min(nz);
+        (0..top)
+            .map(|z| snapshots[[x, y, z]])
+            .filter(|&s| s != -1)
+            .min()
+            .unwrap_or(-1)
+    })
+}
+
+/// Probability over the ensemble that CO2 appears above the primary caprock at each (x, y). Fails if a member
+/// does not match the (nx, ny) of the caprock indices.
+pub fn leakage_probability_map(
+    members: &[ArrayView3<i32>],
+    primary_caprock_indices: &ArrayView2<usize>,
+) -> Result<Array2<f64>, SimulationError> {
+    for member in members {
+        check_member(member, primary_caprock_indices)?;
+    }
+
+    let mut probability = Array2::<f64>::zeros(primary_caprock_indices.dim());
+    if members.is_empty() {
+        return Ok(probability);
+    }
+
+    for member in members {
+        let breach_map = first_breach_snapshot_map(member, primary_caprock_indices);
+        probability.zip_mut_with(&breach_map, |p, &s| {
+            if s != -1 {
+                *p += 1.0;
+            }
+        });
+    }
+    Ok(probability / members.len() as f64)
+}
+
+/// The first snapshot at which each ensemble member leaks through the primary caprock, or -1 if it never does.
+/// Fails if a member does not match the (nx, ny) of the caprock indices.
+pub fn first_breach_times(
+    members: &[ArrayView3<i32>],
+    primary_caprock_indices: &ArrayView2<usize>,
+) -> Result<Array1<i32>, SimulationError> {
+    members
+        .iter()
+        .map(|member| {
+            check_member(member, primary_caprock_indices)?;
+            Ok(first_breach_snapshot_map(member, primary_caprock_indices)
+                .iter()
+                .copied()
+                .filter(|&s| s != -1)
+                .min()
+                .unwrap_or(-1))
+        })
+        .collect()
+}
+
+/// Histogram of the first-breach times with one bin per snapshot index. Members that never breach are not counted.
+pub fn breach_time_histogram(
+    first_breach_times: &ArrayView1<i32>,
+    total_snapshots: usize,
+) -> Array1<usize> {
+    let mut histogram = Array1::<usize>::zeros(total_snapshots + 1);
+    for &t in first_breach_times {
+        if t >= 0 {
+            histogram[(t as usize).min(total_snapshots)] += 1;
+        }
+    }
+    histogram
+}
+
+/// Per-cell statistics of the arrival (snapshot index) over an ensemble
+#[derive(Debug, Clone)]
+pub struct ArrivalStatistics {
+    /// Number of members included
+    pub n_members: usize,
+    /// Mean arrival snapshot over the members that filled the cell. NaN if no member filled it.
+    pub mean_arrival: Array3<f64>,
+    /// Variance of the arrival snapshot over the members that filled the cell. NaN if no member filled it.
+    pub arrival_variance: Array3<f64>,
+    /// Fraction of the members that filled the cell at some point
+    pub fill_probability: Array3<f64>,
+    /// Fraction of the members that filled the cell at or before each of the exceedance snapshots
+    pub exceedance_probabilities: Vec<(i32, Array3<f64>)>,
+}
+
+/// Accumulates arrival statistics one member at a time, so the ensemble never has to be held in memory.
+/// Uses Welford's algorithm for the mean and variance.
+#[derive(Debug, Clone)]
+pub struct EnsembleAccumulator {
+    n_members: usize,
+    count: Array3<f64>,
+    mean: Array3<f64>,
+    m2: Array3<f64>,
+    exceedance_counts: Vec<(i32, Array3<f64>)>,
+}
+
+impl EnsembleAccumulator {
+    pub fn new(dims: (usize, usize, usize), exceedance_snapshots: &[i32]) -> Self {
+        EnsembleAccumulator {
+            n_members: 0,
+            count: Array3::zeros(dims),
+            mean: Array3::zeros(dims),
+            m2: Array3::zeros(dims),
+            exceedance_counts: exceedance_snapshots
+                .iter()
+                .map(|&t| (t, Array3::zeros(dims)))
+                .collect(),
+        }
+    }
+
+    pub fn add(&mut self, snapshots: &ArrayView3<i32>) {
+        if snapshots.dim() != self.count.dim() {
+            panic!("All ensemble members must have the same shape");
+        }
+
+        Zip::from(&mut self.count)
+            .and(&mut self.mean)
+            .and(&mut self.m2)
+            .and(snapshots)
+            .for_each(|count, mean, m2, &s| {
+                if s != -1 {
+                    *count += 1.0;
+                    let delta = s as f64 - *mean;
+                    *mean += delta / *count;
+                    *m2 += delta * (s as f64 - *mean);
+                }
+            });
+
+        for (t, counts) in &mut self.exceedance_counts {
+            Zip::from(counts).and(snapshots).for_each(|c, &s| {
+                if s != -1 && s <= *t {
+                    *c += 1.0;
+                }
+            });
+        }
+
+        self.n_members += 1;
+    }
+
+    pub fn finish(self) -> ArrivalStatistics {
+        let n = self.n_members.max(1) as f64;
+
+        let mean_arrival = Zip::from(&self.count)
+            .and(&self.mean)
+            .map_collect(|&c, &m| if c > 0.0 { m } else { f64::NAN });
+        let arrival_variance = Zip::from(&self.count).and(&self.m2).map_collect(|&c, &m2| {
+            if c > 0.0 {
+                m2 / c
+            } else {
+                f64::NAN
+            }
+        });
+
+        ArrivalStatistics {
+            n_members: self.n_members,
+            mean_arrival,
+            arrival_variance,
+            fill_probability: self.count / n,
+            exceedance_probabilities: self
+                .exceedance_counts
+                .into_iter()
+                .map(|(t, counts)| (t, counts / n))
+                .collect(),
+        }
+    }
+}
+
+/// Compute arrival statistics for members already in memory
+pub fn arrival_statistics(
+    members: &[ArrayView3<i32>],
+    exceedance_snapshots: &[i32],
+) -> ArrivalStatistics {
+    let dims = members.first().map_or((0, 0, 0), |m| m.dim());
+    let mut accumulator = EnsembleAccumulator::new(dims, exceedance_snapshots);
+    for member in members {
+        accumulator.add(member);
+    }
+    accumulator.finish()
+}
+
+/// List the .npy snapshot files in a directory, sorted by file name
+pub fn list_snapshot_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "npy"))
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Load previously saved snapshot arrays (int32 .npy files) from disk
+pub fn load_snapshot_files(paths: &[PathBuf]) -> Result<Vec<Array3<i32>>, ReadNpyError> {
+    paths.iter().map(read_npy).collect()
+}
+
+/// Compute arrival statistics from saved snapshot files without rerunning any simulation.
+/// The files are read one at a time, so memory use does not grow with the ensemble size.
+pub fn aggregate_snapshot_files(
+    paths: &[PathBuf],
+    exceedance_snapshots: &[i32],
+) -> Result<ArrivalStatistics, ReadNpyError> {
+    let mut accumulator: Option<EnsembleAccumulator> = None;
+    for path in paths {
+        let snapshots: Array3<i32> = read_npy(path)?;
+        accumulator
+            .get_or_insert_with(|| EnsembleAccumulator::new(snapshots.dim(), exceedance_snapshots))
+            .add(&snapshots.view());
+    }
+    Ok(accumulator
+        .unwrap_or_else(|| EnsembleAccumulator::new((0, 0, 0), exceedance_snapshots))
+        .finish())
+}
+
+/// Compute the leakage probability map and first-breach times from saved snapshot files. Fails if a file cannot
+/// be read or its snapshots do not match the caprock indices.
+pub fn leakage_from_snapshot_files(
+    paths: &[PathBuf],
+    primary_caprock_indices: &ArrayView2<usize>,
+) -> Result<(Array2<f64>, Array1<i32>), Box<dyn Error>> {
+    let mut probability = Array2::<f64>::zeros(primary_caprock_indices.dim());
+    let mut breach_times = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        let snapshots: Array3<i32> = read_npy(path)?;
+        check_member(&snapshots.view(), primary_caprock_indices)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+        let breach_map = first_breach_snapshot_map(&snapshots.view(), primary_caprock_indices);
+        probability.zip_mut_with(&breach_map, |p, &s| {
+            if s != -1 {
+                *p += 1.0;
+            }
+        });
+        breach_times.push(
+            breach_map
+                .iter()
+                .copied()
+                .filter(|&s| s != -1)
+                .min()
+                .unwrap_or(-1),
+        );
+    }
+
+    Ok((
+        probability / paths.len().max(1) as f64,
+        Array1::from(breach_times),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leakage_probability_and_first_breach_times() {
+        // Primary caprock top at z = 2 everywhere, so z = 0 and z = 1 are above it
+        let caprock = Array2::<usize>::from_elem((2, 2), 2);
+
+        let mut leaking = Array3::<i32>::from_elem((2, 2, 4), -1);
+        leaking[[0, 0, 3]] = 0;
+        leaking[[0, 0, 1]] = 5;
+        leaking[[0, 0, 0]] = 7;
+        leaking[[1, 1, 1]] = 3;
+
+        let mut contained = Array3::<i32>::from_elem((2, 2, 4), -1);
+        contained[[0, 0, 3]] = 0;
+
+        let members = vec![leaking.view(), contained.view()];
+
+        let probability = leakage_probability_map(&members, &caprock.view()).unwrap();
+        assert_eq!(probability[[0, 0]], 0.5);
+        assert_eq!(probability[[1, 1]], 0.5);
+        assert_eq!(probability[[0, 1]], 0.0);
+
+        let times = first_breach_times(&members, &caprock.view()).unwrap();
+        assert_eq!(times, Array1::from(vec![3, -1]));
+
+        // Members that do not match the caprock indices are an error, not a panic
+        let narrow = Array3::<i32>::from_elem((1, 2, 4), -1);
+        let members = vec![leaking.view(), narrow.view()];
+        assert!(leakage_probability_map(&members, &caprock.view()).is_err());
+        assert!(first_breach_times(&members, &caprock.view()).is_err());
+
+        let histogram = breach_time_histogram(&times.view(), 5);
+        assert_eq!(histogram[3], 1);
+        assert_eq!(histogram.sum(), 1);
+    }
+
+    #[test]
+    fn test_arrival_statistics_from_files() {
+        let mut a = Array3::<i32>::from_elem((1, 1, 3), -1);
+        a[[0, 0, 0]] = 1;
+        a[[0, 0, 1]] = 2;
+        let mut b = Array3::<i32>::from_elem((1, 1, 3), -1);
+        b[[0, 0, 0]] = 3;
+
+        let dir = std::env::temp_dir().join("co2_ensemble_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        ndarray_npy::write_npy(dir.join("run_0.npy"), &a).unwrap();
+        ndarray_npy::write_npy(dir.join("run_1.npy"), &b).unwrap();
+        let paths = list_snapshot_files(&dir).unwrap();
+        let stats = aggregate_snapshot_files(&paths, &[2]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(stats.n_members, 2);
+        assert_eq!(stats.mean_arrival[[0, 0, 0]], 2.0);
+        assert_eq!(stats.arrival_variance[[0, 0, 0]], 1.0);
+        assert_eq!(stats.mean_arrival[[0, 0, 1]], 2.0);
+        assert!(stats.mean_arrival[[0, 0, 2]].is_nan());
+        assert_eq!(stats.fill_probability[[0, 0, 1]], 0.5);
+
+        let (t, exceedance) = &stats.exceedance_probabilities[0];
+        assert_eq!(*t, 2);
+        assert_eq!(exceedance[[0, 0, 0]], 0.5);
+
+        // Same result as computing in memory
+        let in_memory = arrival_statistics(&[a.view(), b.view()], &[2]);
+        assert_eq!(in_memory.fill_probability, stats.fill_probability);
+
+        // Snapshots that do not match the caprock indices are an error, not a panic
+        let dir = std::env::temp_dir().join("co2_ensemble_leakage_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        ndarray_npy::write_npy(dir.join("run_0.npy"), &a).unwrap();
+        let paths = list_snapshot_files(&dir).unwrap();
+        let caprock = Array2::<usize>::from_elem((1, 1), 1);
+        let (probability, times) = leakage_from_snapshot_files(&paths, &caprock.view()).unwrap();
+        assert_eq!(probability[[0, 0]], 1.0);
+        assert_eq!(times, Array1::from(vec![1]));
+        let caprock = Array2::<usize>::from_elem((2, 1), 1);
+        let result = leakage_from_snapshot_files(&paths, &caprock.view());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result.unwrap_err().to_string().contains("run_0.npy"));
+    }
+}
@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimulationError;
+
+/// CO2 density as a function of depth, interpolated linearly between the entries of a table.
+/// Depths outside the table take the density of the closest entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DensityTable {
+    // (depth in m, density in kg/m³), sorted by depth
+    entries: Vec<(f64, f64)>,
+}
+
+impl DensityTable {
+    /// A table from (depth, density) entries with strictly increasing depths
+    pub fn new(entries: Vec<(f64, f64)>) -> Result<Self, SimulationError> {
+        let invalid = |message: &str| {
+            Err(SimulationError::InvalidValue {
+                argument: "density_table".to_string(),
+                message: message.to_string(),
+            })
+        };
+        if entries.is_empty() {
+            return invalid("the table needs at least one entry");
+        }
+        if entries
+            .iter()
+            .any(|&(depth, density)| !(depth.is_finite() && density.is_finite() && density > 0.0))
+        {
+            return invalid("depths must be finite and densities positive");
+        }
+        if entries.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return invalid("depths must be strictly increasing");
+        }
+        Ok(DensityTable { entries })
+    }
+
+    /// A rough table for a hydrostatic pressure gradient and a geothermal gradient of about 30 °C/km.
+    /// The density rises steeply around the critical point at 700–900 m. Good enough for screening,
+    /// but a table from a proper equation of state for the site should be used when it is available.
+    pub fn typical() -> Self {
+        DensityTable {
+            entries: vec![
+                (0.0, 2.0),
+                (300.0, 60.0),
+                (500.0, 150.0),
+                (700.0, 250.0),
+                (800.0, 450.0),
+                (900.0, 580.0),
+                (1000.0, 630.0),
+                (1500.0, 680.0),
+                (2000.0, 700.0),
+                (3000.0, 720.0),
+            ],
+        }
+    }
+
+    pub fn entries(&self) -> &[(f64, f64)] {
+        &self.entries
+    }
+
+    /// The CO2 density in kg/m³ at the given depth in m
+    pub fn density(&self, depth: f64) -> f64 {
+        let i = self.entries.partition_point(|&(d, _)| d <= depth);
+        if i == 0 {
+            return self.entries[0].1;
+        }
+        if i == self.entries.len() {
+            return self.entries[i - 1].1;
+        }
+        let (d0, rho0) = self.entries[i - 1];
+        let (d1, rho1) = self.entries[i];
+        rho0 + (depth - d0) / (d1 - d0) * (rho1 - rho0)
+    }
+}
+
+/// Converts filled cells to CO2 mass using the density at the depth of every cell
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MassAccounting {
+    /// Volume of CO2 in a filled cell in m³
+    pub cell_volume: f64,
+    pub density: DensityTable,
+    /// Stop the simulation once this many tonnes have been injected, if given
+    pub max_injected_mass: Option<f64>,
+}
+
+impl MassAccounting {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        if !(self.cell_volume.is_finite() && self.cell_volume > 0.0) {
+            return Err(SimulationError::InvalidValue {
+                argument: "cell_volume".to_string(),
+                message: format!("must be positive, got {}", self.cell_volume),
+            });
+        }
+        if let Some(max) = self.max_injected_mass {
+            if !(max.is_finite() && max > 0.0) {
+                return Err(SimulationError::InvalidValue {
+                    argument: "max_injected_mass".to_string(),
+                    message: format!("must be positive, got {}", max),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The mass in tonnes of a filled cell at the given depth
+    pub fn cell_mass(&self, depth: f64) -> f64 {
+        self.cell_volume * self.density.density(depth) / 1000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_density_table() {
+        let table = DensityTable::new(vec![(800.0, 200.0), (1000.0, 600.0)]).unwrap();
+        assert_eq!(table.density(500.0), 200.0);
+        assert_eq!(table.density(900.0), 400.0);
+        assert_eq!(table.density(2000.0), 600.0);
+
+        let accounting = MassAccounting {
+            cell_volume: 1000.0,
+            density: table,
+            max_injected_mass: None,
+        };
+        assert_eq!(accounting.cell_mass(1000.0), 600.0);
+
+        assert!(DensityTable::new(vec![(1.0, 1.0), (1.0, 2.0)]).is_err());
+        assert!(DensityTable::new(vec![]).is_err());
+        assert!(DensityTable::typical().density(1200.0) > DensityTable::typical().density(600.0));
+    }
+}
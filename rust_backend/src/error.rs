@@ -0,0 +1,38 @@
+use std::fmt;
+
+/// Errors caused by invalid simulation inputs
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimulationError {
+    /// An input array does not have the shape implied by the other inputs
+    ShapeMismatch {
+        argument: String,
+        expected: String,
+        actual: String,
+    },
+    /// An input contains a value outside its valid range
+    InvalidValue { argument: String, message: String },
+    /// The source is not a valid injection point
+    InvalidSource(String),
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulationError::ShapeMismatch {
+                argument,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} has shape {}, expected {}",
+                argument, actual, expected
+            ),
+            SimulationError::InvalidValue { argument, message } => {
+                write!(f, "Invalid {}: {}", argument, message)
+            }
+            SimulationError::InvalidSource(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for SimulationError {}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::{
+    Array, ArrayRef, Float64Array, Int16Array, Int32Array, RecordBatch, StringArray, UInt32Array,
+    UInt64Array,
+};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+
+use crate::error::SimulationError;
+use crate::metadata::RunMetadata;
+use crate::wells::NO_WELL;
+
+/// Rows per record batch, which the Parquet writer turns into row groups
+const BATCH_ROWS: usize = 1 << 20;
+
+/// How the CO2 in a filled cell is held at the end of the run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrappingState {
+    /// Free CO2 below a seal
+    Structural,
+    /// Residual CO2 left behind by the plume, see `Hysteresis`
+    Residual,
+    /// All CO2 of the cell has dissolved in the brine, see `ConvectiveDissolution`
+    Dissolved,
+}
+
+impl TrappingState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrappingState::Structural => "structural",
+            TrappingState::Residual => "residual",
+            TrappingState::Dissolved => "dissolved",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "structural" => Some(TrappingState::Structural),
+            "residual" => Some(TrappingState::Residual),
+            "dissolved" => Some(TrappingState::Dissolved),
+            _ => None,
+        }
+    }
+}
+
+/// A cell filled during a run, see `Simulation::cell_events`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellEvent {
+    pub cell: (usize, usize, usize),
+    /// Depth of the cell center
+    pub depth: f64,
+    /// The well whose front filled the cell, or MERGED_WELLS
+    pub well_id: i16,
+    /// The position of the cell in the fill order, starting at 0
+    pub fill_order: usize,
+    pub snapshot: i32,
+    pub trapping_state: TrappingState,
+}
+
+/// The schema of the cell event table in long format, with one row per filled cell and the columns x, y, z,
+/// depth, well_id, fill_order, snapshot and trapping_state. The run metadata is stored as JSON in the
+/// `run_metadata` entry of the schema metadata.
+pub fn cell_event_schema(metadata: &RunMetadata) -> SchemaRef {
+    let fields = vec![
+        Field::new("x", DataType::UInt32, false),
+        Field::new("y", DataType::UInt32, false),
+        Field::new("z", DataType::UInt32, false),
+        Field::new("depth", DataType::Float64, false),
+        Field::new("well_id", DataType::Int16, false),
+        Field::new("fill_order", DataType::UInt64, false),
+        Field::new("snapshot", DataType::Int32, false),
+        Field::new("trapping_state", DataType::Utf8, false),
+    ];
+    Arc::new(Schema::new_with_metadata(
+        fields,
+        HashMap::from([("run_metadata".to_string(), metadata.to_json().to_string())]),
+    ))
+}
+
+/// The events as record batches of the given schema, see `cell_event_schema`
+pub fn cell_event_batches(
+    events: &[CellEvent],
+    schema: &SchemaRef,
+) -> Result<Vec<RecordBatch>, ArrowError> {
+    events
+        .chunks(BATCH_ROWS)
+        .map(|chunk| {
+            let index = |axis: fn(&CellEvent) -> usize| -> ArrayRef {
+                Arc::new(UInt32Array::from_iter_values(
+                    chunk.iter().map(|event| axis(event) as u32),
+                ))
+            };
+            let columns: Vec<ArrayRef> = vec![
+                index(|event| event.cell.0),
+                index(|event| event.cell.1),
+                index(|event| event.cell.2),
+                Arc::new(Float64Array::from_iter_values(
+                    chunk.iter().map(|event| event.depth),
+                )),
+                Arc::new(Int16Array::from_iter_values(
+                    chunk.iter().map(|event| event.well_id),
+                )),
+                Arc::new(UInt64Array::from_iter_values(
+                    chunk.iter().map(|event| event.fill_order as u64),
+                )),
+                Arc::new(Int32Array::from_iter_values(
+                    chunk.iter().map(|event| event.snapshot),
+                )),
+                Arc::new(StringArray::from_iter_values(
+                    chunk.iter().map(|event| event.trapping_state.as_str()),
+                )),
+            ];
+            RecordBatch::try_new(schema.clone(), columns)
+        })
+        .collect()
+}
+
+/// Write the events as a Parquet file with the table of `cell_event_schema`, so the filled cells can be queried
+/// without loading the 3D cubes
+pub fn write_cell_events_parquet(
+    path: &Path,
+    events: &[CellEvent],
+    metadata: &RunMetadata,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let schema = cell_event_schema(metadata);
+    let file = File::create(path)?;
+    let mut writer = ArrowWriter::try_new(file, schema.clone(), None)?;
+    for batch in cell_event_batches(events, &schema)? {
+        writer.write(&batch)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+/// A column of the batch of the given type, or None if the batch has no such column
+fn optional_column<'a, T: Array + 'static>(
+    batch: &'a RecordBatch,
+    name: &str,
+) -> Result<Option<&'a T>, SimulationError> {
+    let Some(column) = batch.column_by_name(name) else {
+        return Ok(None);
+    };
+    column
+        .as_any()
+        .downcast_ref::<T>()
+        .map(Some)
+        .ok_or_else(|| SimulationError::InvalidValue {
+            argument: "cell events".to_string(),
+            message: format!("has a column {} of type {}", name, column.data_type()),
+        })
+}
+
+fn required_column<'a, T: Array + 'static>(
+    batch: &'a RecordBatch,
+    name: &str,
+) -> Result<&'a T, SimulationError> {
+    optional_column(batch, name)?.ok_or_else(|| SimulationError::InvalidValue {
+        argument: "cell events".to_string(),
+        message: format!("has no column {}", name),
+    })
+}
+
+/// Read a file written by `write_cell_events_parquet` with any output schema version up to the current one, see
+/// `OUTPUT_SCHEMA_VERSION`. Columns this build does not know are ignored, and the optional well and trapping state
+/// columns default to NO_WELL and structural trapping when missing.
+pub fn read_cell_events_parquet(
+    path: &Path,
+) -> Result<(Vec<CellEvent>, RunMetadata), Box<dyn std::error::Error>> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    let metadata = match builder.schema().metadata().get("run_metadata") {
+        Some(metadata) => RunMetadata::from_json(serde_json::from_str(metadata)?)?,
+        None => RunMetadata::default(),
+    };
+    let mut events = Vec::new();
+    for batch in builder.build()? {
+        let batch = batch?;
+        let x = required_column::<UInt32Array>(&batch, "x")?;
+        let y = required_column::<UInt32Array>(&batch, "y")?;
+        let z = required_column::<UInt32Array>(&batch, "z")?;
+        let depth = required_column::<Float64Array>(&batch, "depth")?;
+        let fill_order = required_column::<UInt64Array>(&batch, "fill_order")?;
+        let snapshot = required_column::<Int32Array>(&batch, "snapshot")?;
+        let well_id = optional_column::<Int16Array>(&batch, "well_id")?;
+        let trapping_state = optional_column::<StringArray>(&batch, "trapping_state")?;
+        for row in 0..batch.num_rows() {
+            let trapping_state = match trapping_state {
+                Some(states) => TrappingState::from_name(states.value(row)).ok_or_else(|| {
+                    SimulationError::InvalidValue {
+                        argument: "cell events".to_string(),
+                        message: format!("has an unknown trapping state {}", states.value(row)),
+                    }
+                })?,
+                None => TrappingState::Structural,
+            };
+            events.push(CellEvent {
+                cell: (
+                    x.value(row) as usize,
+                    y.value(row) as usize,
+                    z.value(row) as usize,
+                ),
+                depth: depth.value(row),
+                well_id: well_id.map_or(NO_WELL, |wells| wells.value(row)),
+                fill_order: fill_order.value(row) as usize,
+                snapshot: snapshot.value(row),
+                trapping_state,
+            });
+        }
+    }
+    Ok((events, metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SimulationConfig;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use crate::injection_simulation::Simulation;
+    use crate::wells::MERGED_WELLS;
+    use arrow_array::ffi_stream::{ArrowArrayStreamReader, FFI_ArrowArrayStream};
+    use arrow_array::{RecordBatchIterator, RecordBatchReader};
+    use numpy::ndarray::{s, Array1, Array2, Array3};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_cell_events_round_trip() {
+        // Two wells at the ends of a row under a caprock
+        let mut reservoir = Array3::from_elem((5, 1, 3), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        let config = SimulationConfig {
+            max_column_height: None,
+            total_snapshots: 5,
+            ..Default::default()
+        };
+        let mut simulation = Simulation::try_new_with_wells(
+            reservoir.view(),
+            Array1::from(vec![100.0, 101.0, 102.0]).view(),
+            Array2::zeros((5, 1)).view(),
+            &[(0, 0, 1), (4, 0, 1)],
+            &config,
+        )
+        .unwrap();
+        simulation.run();
+
+        let events = simulation.cell_events();
+        assert_eq!(events.len(), simulation.cells_filled());
+        assert_eq!(events[0].cell, (0, 0, 1));
+        assert_eq!(events[0].depth, 101.0);
+        assert_eq!(events[0].snapshot, 0);
+        assert!(events
+            .iter()
+            .enumerate()
+            .all(|(i, event)| event.fill_order == i));
+        assert!(events
+            .iter()
+            .all(|event| event.trapping_state == TrappingState::Structural));
+        let merged = events
+            .iter()
+            .filter(|event| event.well_id == MERGED_WELLS)
+            .count();
+        assert_eq!(merged, 2);
+
+        let path = std::env::temp_dir().join("co2_cell_events_test.parquet");
+        write_cell_events_parquet(&path, &events, &RunMetadata::default()).unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        assert!(builder.schema().metadata().contains_key("run_metadata"));
+        let batch = builder.build().unwrap().next().unwrap().unwrap();
+        assert_eq!(batch.num_rows(), events.len());
+        let column = |name: &str| batch.column(batch.schema().index_of(name).unwrap()).clone();
+        let x = column("x");
+        let x = x.as_any().downcast_ref::<UInt32Array>().unwrap();
+        assert_eq!(x.value(0), 0);
+        let states = column("trapping_state");
+        let states = states.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(states.value(events.len() - 1), "structural");
+        let (read, _) = read_cell_events_parquet(&path).unwrap();
+        assert_eq!(read, events);
+
+        // A table without the optional columns, and with a column this build does not know
+        let older = RecordBatch::try_from_iter(
+            ["x", "y", "z", "depth", "fill_order", "snapshot"]
+                .into_iter()
+                .map(|name| (name, column(name)))
+                .chain([(
+                    "saturation",
+                    Arc::new(Float64Array::from(vec![1.0; events.len()])) as ArrayRef,
+                )]),
+        )
+        .unwrap();
+        let mut writer =
+            ArrowWriter::try_new(File::create(&path).unwrap(), older.schema(), None).unwrap();
+        writer.write(&older).unwrap();
+        writer.close().unwrap();
+        let (read, metadata) = read_cell_events_parquet(&path).unwrap();
+        assert_eq!(metadata, RunMetadata::default());
+        assert_eq!(read.len(), events.len());
+        assert_eq!(read[0].cell, events[0].cell);
+        assert!(read.iter().all(|event| event.well_id == NO_WELL));
+        std::fs::remove_file(&path).unwrap();
+
+        // The batches cross the Arrow C stream interface intact
+        let schema = cell_event_schema(&RunMetadata::default());
+        let batches = cell_event_batches(&events, &schema).unwrap();
+        let reader = RecordBatchIterator::new(batches.into_iter().map(Ok), schema.clone());
+        let stream = FFI_ArrowArrayStream::new(Box::new(reader));
+        let mut reader = ArrowArrayStreamReader::try_new(stream).unwrap();
+        assert_eq!(reader.schema(), schema);
+        assert_eq!(reader.next().unwrap().unwrap(), batch);
+        assert!(reader.next().is_none());
+    }
+}
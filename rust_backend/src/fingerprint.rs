@@ -0,0 +1,207 @@
+use std::fs::File;
+use std::path::Path;
+
+use numpy::ndarray::{ArrayView1, ArrayView2, ArrayView3};
+use sha2::{Digest, Sha256};
+
+use crate::config::SimulationConfig;
+
+/// Incremental SHA-256 hash of the inputs of a run. Every part is prefixed with its name and length,
+/// so different inputs cannot produce the same byte stream.
+pub struct Fingerprint {
+    hasher: Sha256,
+}
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fingerprint {
+    /// A fingerprint starting with the crate version, since the same inputs may give other results in another version
+    pub fn new() -> Self {
+        let mut fingerprint = Fingerprint {
+            hasher: Sha256::new(),
+        };
+        fingerprint.add_bytes("version", env!("CARGO_PKG_VERSION").as_bytes());
+        fingerprint
+    }
+
+    pub fn add_bytes(&mut self, name: &str, bytes: &[u8]) -> &mut Self {
+        self.hasher.update((name.len() as u64).to_le_bytes());
+        self.hasher.update(name.as_bytes());
+        self.hasher.update((bytes.len() as u64).to_le_bytes());
+        self.hasher.update(bytes);
+        self
+    }
+
+    /// Add a float array with its shape. The values are hashed in logical (C) order, whatever the memory layout.
+    pub fn add_floats<'a>(
+        &mut self,
+        name: &str,
+        shape: &[usize],
+        values: impl Iterator<Item = &'a f64>,
+    ) -> &mut Self {
+        let bytes: Vec<u8> = shape
+            .iter()
+            .flat_map(|&n| (n as u64).to_le_bytes())
+            .chain(values.flat_map(|v| v.to_le_bytes()))
+            .collect();
+        self.add_bytes(name, &bytes)
+    }
+
+    pub fn add_indices<'a>(
+        &mut self,
+        name: &str,
+        shape: &[usize],
+        values: impl Iterator<Item = &'a usize>,
+    ) -> &mut Self {
+        let bytes: Vec<u8> = shape
+            .iter()
+            .copied()
+            .chain(values.copied())
+            .flat_map(|n| (n as u64).to_le_bytes())
+            .collect();
+        self.add_bytes(name, &bytes)
+    }
+
+    /// The fingerprint as a lowercase hex string
+    pub fn finish(self) -> String {
+        to_hex(&self.hasher.finalize())
+    }
+}
+
+/// SHA-256 of the values of a float array in logical (C) order, as a lowercase hex string.
+/// Unlike the fingerprint this depends only on the values, so it can be checked with other tools.
+pub fn float_checksum<'a>(values: impl Iterator<Item = &'a f64>) -> String {
+    let mut hasher = Sha256::new();
+    for value in values {
+        hasher.update(value.to_le_bytes());
+    }
+    to_hex(&hasher.finalize())
+}
+
+/// SHA-256 of the values of an index array in logical (C) order, stored as u64
+pub fn index_checksum<'a>(values: impl Iterator<Item = &'a usize>) -> String {
+    let mut hasher = Sha256::new();
+    for &value in values {
+        hasher.update((value as u64).to_le_bytes());
+    }
+    to_hex(&hasher.finalize())
+}
+
+/// SHA-256 of the values of a snapshots array in logical (C) order, e.g. to compare the result of a deterministic
+/// run with a golden output
+pub fn snapshot_checksum<'a>(values: impl Iterator<Item = &'a i32>) -> String {
+    let mut hasher = Sha256::new();
+    for value in values {
+        hasher.update(value.to_le_bytes());
+    }
+    to_hex(&hasher.finalize())
+}
+
+/// SHA-256 of bytes, as a lowercase hex string
+pub fn bytes_checksum(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+/// SHA-256 of the contents of a file, as a lowercase hex string
+pub fn file_checksum(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Fingerprint of everything that determines the result of a simulation: the input arrays, source, config,
+/// the seed used to generate the inputs (if any) and the crate version
+pub fn simulation_fingerprint(
+    reservoir_matrix: &ArrayView3<f64>,
+    depths: &ArrayView1<f64>,
+    bedrock_indices: &ArrayView2<usize>,
+    sources: &[(usize, usize, usize)],
+    config: &SimulationConfig,
+    seed: Option<u64>,
+) -> String {
+    let source_indices: Vec<usize> = sources.iter().flat_map(|&(x, y, z)| [x, y, z]).collect();
+    let mut fingerprint = Fingerprint::new();
+    fingerprint
+        .add_floats(
+            "reservoir_matrix",
+            reservoir_matrix.shape(),
+            reservoir_matrix.iter(),
+        )
+        .add_floats("depths", depths.shape(), depths.iter())
+        .add_indices(
+            "bedrock_indices",
+            bedrock_indices.shape(),
+            bedrock_indices.iter(),
+        )
+        .add_indices(
+            "source",
+            // A single source keeps the shape it had before multi-well runs
+            &if sources.len() == 1 {
+                vec![3]
+            } else {
+                vec![sources.len(), 3]
+            },
+            source_indices.iter(),
+        )
+        // The Debug output holds every config field and is stable within a crate version
+        .add_bytes("config", format!("{:?}", config).as_bytes());
+    if let Some(seed) = seed {
+        fingerprint.add_bytes("seed", &seed.to_le_bytes());
+    }
+    fingerprint.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use numpy::ndarray::{Array1, Array2, Array3};
+
+    #[test]
+    fn test_fingerprint_changes_with_inputs() {
+        let reservoir = Array3::from_shape_fn((2, 3, 4), |(x, y, z)| (100 * x + 10 * y + z) as f64);
+        let depths = Array1::from(vec![0.0, 1.0, 2.0, 3.0]);
+        let bedrock_indices = Array2::<usize>::zeros((2, 3));
+        let config = SimulationConfig::default();
+        let fingerprint = |reservoir: &Array3<f64>, config: &SimulationConfig, seed| {
+            simulation_fingerprint(
+                &reservoir.view(),
+                &depths.view(),
+                &bedrock_indices.view(),
+                &[(0, 0, 1)],
+                config,
+                seed,
+            )
+        };
+
+        let reference = fingerprint(&reservoir, &config, None);
+        assert_eq!(reference.len(), 64);
+        assert_eq!(reference, fingerprint(&reservoir, &config, None));
+        // The same values in another memory layout give the same fingerprint
+        let fortran_order = reservoir.t().as_standard_layout().t().to_owned();
+        assert_eq!(reference, fingerprint(&fortran_order, &config, None));
+
+        let mut changed = reservoir.clone();
+        changed[[1, 2, 3]] = 1.0;
+        assert_ne!(reference, fingerprint(&changed, &config, None));
+        let other_config = SimulationConfig {
+            total_snapshots: 7,
+            ..Default::default()
+        };
+        assert_ne!(reference, fingerprint(&reservoir, &other_config, None));
+        assert_ne!(reference, fingerprint(&reservoir, &config, Some(1)));
+
+        // The checksum of the values alone is layout independent too
+        assert_eq!(
+            float_checksum(reservoir.iter()),
+            float_checksum(fortran_order.iter())
+        );
+    }
+}
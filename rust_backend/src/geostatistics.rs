@@ -0,0 +1,234 @@
+use numpy::ndarray::{Array1, Array3, Axis};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Distribution, StandardNormal};
+
+use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+
+/// Facies code for the low-permeability background (shale)
+pub const FACIES_BACKGROUND: u8 = 0;
+/// Facies code for the high-permeability channel sand
+pub const FACIES_CHANNEL: u8 = 1;
+
+/// Parameters for the indicator simulation of channel/background facies
+#[derive(Debug, Clone)]
+pub struct FaciesParameters {
+    /// Fraction of the cells that should be channel facies (between 0 and 1)
+    pub channel_proportion: f64,
+    /// Correlation lengths in number of cells along (x, y, z)
+    pub correlation_lengths: (f64, f64, f64),
+    /// Seed for the random number generator. The same seed gives the same cube.
+    pub seed: u64,
+}
+
+impl Default for FaciesParameters {
+    fn default() -> Self {
+        FaciesParameters {
+            channel_proportion: 0.5,
+            correlation_lengths: (10.0, 10.0, 2.0),
+            seed: 0,
+        }
+    }
+}
+
+/// Parameters for spatially correlated random thresholds, e.g. the capillary entry pressures of the
+/// invasion-percolation engine. The thresholds are lognormal around the median.
+#[derive(Debug, Clone)]
+pub struct ThresholdParameters {
+    /// Median threshold
+    pub median: f64,
+    /// Standard deviation of the log of the thresholds. Zero gives a uniform field.
+    pub log_std: f64,
+    /// Correlation lengths in number of cells along (x, y, z)
+    pub correlation_lengths: (f64, f64, f64),
+    /// Seed for the random number generator. The same seed gives the same cube.
+    pub seed: u64,
+}
+
+impl Default for ThresholdParameters {
+    fn default() -> Self {
+        ThresholdParameters {
+            median: 1.0,
+            log_std: 1.0,
+            correlation_lengths: (10.0, 10.0, 2.0),
+            seed: 0,
+        }
+    }
+}
+
+/// Build a normalized 1D Gaussian kernel truncated at three standard deviations
+fn gaussian_kernel(sigma: f64) -> Array1<f64> {
+    let half_width = (3.0 * sigma).ceil() as i64;
+    let mut kernel = Array1::from_iter(
+        (-half_width..=half_width).map(|i| (-0.5 * (i as f64 / sigma).powi(2)).exp()),
+    );
+    let sum = kernel.sum();
+    kernel /= sum;
+    kernel
+}
+
+/// Convolve every lane along the given axis with a Gaussian kernel. Boundaries are clamped.
+fn smooth_along_axis(field: &mut Array3<f64>, axis: usize, sigma: f64) {
+    if sigma <= 0.0 {
+        return;
+    }
+    let kernel = gaussian_kernel(sigma);
+    let half_width = (kernel.len() / 2) as i64;
+
+    for mut lane in field.lanes_mut(Axis(axis)) {
+        let n = lane.len() as i64;
+        let original = lane.to_owned();
+        for i in 0..n {
+            lane[i as usize] = kernel
+                .iter()
+                .enumerate()
+                .map(|(k, &w)| {
+                    let j = (i + k as i64 - half_width).clamp(0, n - 1);
+                    w * original[j as usize]
+                })
+                .sum();
+        }
+    }
+}
+
+/// Generate a standardized Gaussian random field by smoothing white noise.
+/// The correlation lengths are given in number of cells along (x, y, z).
+pub fn gaussian_random_field(
+    dims: (usize, usize, usize),
+    correlation_lengths: (f64, f64, f64),
+    seed: u64,
+) -> Array3<f64> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut field = Array3::<f64>::zeros(dims);
+    field
+        .iter_mut()
+        .for_each(|val| *val = StandardNormal.sample(&mut rng));
+
+    // A Gaussian kernel with standard deviation L/2 gives a correlation length of roughly L
+    let (lx, ly, lz) = correlation_lengths;
+    smooth_along_axis(&mut field, 0, lx / 2.0);
+    smooth_along_axis(&mut field, 1, ly / 2.0);
+    smooth_along_axis(&mut field, 2, lz / 2.0);
+
+    // Standardize to zero mean and unit variance
+    let n = field.len() as f64;
+    let mean = field.sum() / n;
+    let std = (field.iter().map(|&v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+    if std > 0.0 {
+        field.mapv_inplace(|v| (v - mean) / std);
+    }
+    field
+}
+
+/// Generate a channel/background facies cube by truncating a Gaussian random field.
+/// The threshold is taken from the empirical quantile, so the channel proportion is honoured exactly.
+pub fn generate_facies(dims: (usize, usize, usize), params: &FaciesParameters) -> Array3<u8> {
+    if !(0.0..=1.0).contains(&params.channel_proportion) {
+        panic!("Channel proportion must be between 0 and 1");
+    }
+
+    let field = gaussian_random_field(dims, params.correlation_lengths, params.seed);
+
+    let mut sorted: Vec<f64> = field.iter().copied().collect();
+    sorted.sort_by(|a, b| b.total_cmp(a));
+    let n_channel = (params.channel_proportion * sorted.len() as f64).round() as usize;
+
+    if n_channel == 0 {
+        return Array3::from_elem(dims, FACIES_BACKGROUND);
+    }
+    let threshold = sorted[n_channel - 1];
+
+    // Ties at the threshold are broken in iteration order to hit the target count exactly
+    let mut remaining = n_channel;
+    field.mapv(|v| {
+        if v >= threshold && remaining > 0 {
+            remaining -= 1;
+            FACIES_CHANNEL
+        } else {
+            FACIES_BACKGROUND
+        }
+    })
+}
+
+/// Generate lognormal thresholds from a Gaussian random field
+pub fn generate_thresholds(
+    dims: (usize, usize, usize),
+    params: &ThresholdParameters,
+) -> Array3<f64> {
+    if !(params.median >= 0.0 && params.log_std >= 0.0) {
+        panic!("Median and log standard deviation must be non-negative");
+    }
+
+    let field = gaussian_random_field(dims, params.correlation_lengths, params.seed);
+    field.mapv(|v| params.median * (params.log_std * v).exp())
+}
+
+/// Convert a facies cube into a reservoir matrix. Channels become reservoir and the background becomes caprock.
+pub fn facies_to_reservoir_matrix(facies: &Array3<u8>) -> Array3<f64> {
+    facies.mapv(|f| {
+        if f == FACIES_CHANNEL {
+            VELOCITY_RESERVOIR
+        } else {
+            VELOCITY_CAPROCK
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_facies_honours_proportion() {
+        let params = FaciesParameters {
+            channel_proportion: 0.3,
+            correlation_lengths: (4.0, 2.0, 1.0),
+            seed: 42,
+        };
+        let facies = generate_facies((10, 10, 5), &params);
+        let n_channel = facies.iter().filter(|&&f| f == FACIES_CHANNEL).count();
+        assert_eq!(n_channel, 150);
+    }
+
+    #[test]
+    fn test_generate_facies_is_reproducible() {
+        let params = FaciesParameters::default();
+        let a = generate_facies((8, 8, 4), &params);
+        let b = generate_facies((8, 8, 4), &params);
+        assert_eq!(a, b);
+
+        let other = generate_facies((8, 8, 4), &FaciesParameters { seed: 1, ..params });
+        assert_ne!(a, other);
+    }
+
+    #[test]
+    fn test_generate_thresholds() {
+        let params = ThresholdParameters {
+            median: 1e4,
+            log_std: 0.5,
+            correlation_lengths: (3.0, 3.0, 1.0),
+            seed: 7,
+        };
+        let thresholds = generate_thresholds((10, 10, 4), &params);
+        assert!(thresholds.iter().all(|&t| t > 0.0));
+        assert_eq!(thresholds, generate_thresholds((10, 10, 4), &params));
+
+        let uniform = generate_thresholds(
+            (4, 4, 2),
+            &ThresholdParameters {
+                log_std: 0.0,
+                ..params
+            },
+        );
+        assert!(uniform.iter().all(|&t| t == 1e4));
+    }
+
+    #[test]
+    fn test_facies_to_reservoir_matrix() {
+        let mut facies = Array3::from_elem((2, 2, 2), FACIES_BACKGROUND);
+        facies[[0, 1, 1]] = FACIES_CHANNEL;
+        let reservoir = facies_to_reservoir_matrix(&facies);
+        assert_eq!(reservoir[[0, 1, 1]], VELOCITY_RESERVOIR);
+        assert_eq!(reservoir[[0, 0, 0]], VELOCITY_CAPROCK);
+    }
+}
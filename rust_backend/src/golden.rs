@@ -0,0 +1,269 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+use numpy::ndarray::{ArrayView1, ArrayView2, ArrayView3};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::SimulationConfig;
+use crate::error::SimulationError;
+use crate::fingerprint::{bytes_checksum, float_checksum, index_checksum, snapshot_checksum};
+use crate::injection_simulation::Simulation;
+use crate::metadata::RunMetadata;
+
+/// A canonical run, recorded to check that later builds reproduce it exactly: the checksums of its inputs, the
+/// config and sources, the checksum of its snapshots and its headline statistics. Unlike the fingerprint of a
+/// result, the hashes leave out the crate version, so a run recorded with one version can be verified with
+/// another.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GoldenRun {
+    /// The version of the crate that recorded the run
+    pub crate_version: String,
+    pub sources: Vec<(usize, usize, usize)>,
+    pub config: SimulationConfig,
+    /// SHA-256 checksums of the input arrays, by name, see `float_checksum` and `index_checksum`
+    pub input_checksums: BTreeMap<String, String>,
+    /// SHA-256 of the input checksums, sources and config
+    pub inputs_hash: String,
+    /// SHA-256 of the snapshots, see `snapshot_checksum`
+    pub outputs_hash: String,
+    /// The headline statistics of the run, see `RunMetadata::for_simulation`
+    pub summary: BTreeMap<String, Value>,
+}
+
+/// What a verification found different from the golden run, see `GoldenRun::verify`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenVerification {
+    /// The inputs whose checksums differ, which means the run was not repeated on the same inputs
+    pub input_mismatches: Vec<String>,
+    /// Whether the sources and config still give the recorded inputs hash, which fails if the record was edited
+    pub inputs_hash_matches: bool,
+    /// The checksum of the snapshots of the repeated run
+    pub outputs_hash: String,
+    pub outputs_hash_matches: bool,
+    /// The statistics that differ, with the recorded and the repeated value
+    pub summary_mismatches: Vec<(String, Value, Value)>,
+}
+
+impl GoldenVerification {
+    /// Whether the build reproduced the golden run exactly
+    pub fn passed(&self) -> bool {
+        self.input_mismatches.is_empty()
+            && self.inputs_hash_matches
+            && self.outputs_hash_matches
+            && self.summary_mismatches.is_empty()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let summary_mismatches: Vec<Value> = self
+            .summary_mismatches
+            .iter()
+            .map(|(name, recorded, current)| {
+                json!({"statistic": name, "recorded": recorded, "current": current})
+            })
+            .collect();
+        json!({
+            "passed": self.passed(),
+            "input_mismatches": self.input_mismatches,
+            "inputs_hash_matches": self.inputs_hash_matches,
+            "outputs_hash": self.outputs_hash,
+            "outputs_hash_matches": self.outputs_hash_matches,
+            "summary_mismatches": summary_mismatches,
+        })
+    }
+}
+
+fn input_checksums(
+    reservoir_matrix: &ArrayView3<f64>,
+    depths: &ArrayView1<f64>,
+    bedrock_indices: &ArrayView2<usize>,
+) -> BTreeMap<String, String> {
+    BTreeMap::from([
+        (
+            "reservoir_matrix".to_string(),
+            float_checksum(reservoir_matrix.iter()),
+        ),
+        ("depths".to_string(), float_checksum(depths.iter())),
+        (
+            "bedrock_indices".to_string(),
+            index_checksum(bedrock_indices.iter()),
+        ),
+    ])
+}
+
+/// The hash of everything that determines the run, apart from the engine
+fn inputs_hash(
+    input_checksums: &BTreeMap<String, String>,
+    sources: &[(usize, usize, usize)],
+    config: &SimulationConfig,
+) -> String {
+    let inputs = json!({"inputs": input_checksums, "sources": sources, "config": config});
+    bytes_checksum(inputs.to_string().as_bytes())
+}
+
+/// Run the simulation to the end and hash its snapshots
+fn run(
+    reservoir_matrix: &ArrayView3<f64>,
+    depths: &ArrayView1<f64>,
+    bedrock_indices: &ArrayView2<usize>,
+    sources: &[(usize, usize, usize)],
+    config: &SimulationConfig,
+) -> Result<(String, BTreeMap<String, Value>), SimulationError> {
+    let mut simulation = Simulation::try_new_with_wells(
+        reservoir_matrix.view(),
+        depths.view(),
+        bedrock_indices.view(),
+        sources,
+        config,
+    )?;
+    simulation.run();
+    let outputs_hash = snapshot_checksum(simulation.snapshots().iter());
+    Ok((
+        outputs_hash,
+        RunMetadata::for_simulation(&simulation, None).statistics,
+    ))
+}
+
+impl GoldenRun {
+    /// Run the simulation and record it
+    pub fn record(
+        reservoir_matrix: ArrayView3<f64>,
+        depths: ArrayView1<f64>,
+        bedrock_indices: ArrayView2<usize>,
+        sources: &[(usize, usize, usize)],
+        config: &SimulationConfig,
+    ) -> Result<Self, SimulationError> {
+        let input_checksums = input_checksums(&reservoir_matrix, &depths, &bedrock_indices);
+        let (outputs_hash, summary) = run(
+            &reservoir_matrix,
+            &depths,
+            &bedrock_indices,
+            sources,
+            config,
+        )?;
+        Ok(GoldenRun {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            sources: sources.to_vec(),
+            config: config.clone(),
+            inputs_hash: inputs_hash(&input_checksums, sources, config),
+            input_checksums,
+            outputs_hash,
+            summary,
+        })
+    }
+
+    /// Repeat the run on the inputs with this build, and compare the inputs, the snapshots and the statistics with
+    /// the recorded ones
+    pub fn verify(
+        &self,
+        reservoir_matrix: ArrayView3<f64>,
+        depths: ArrayView1<f64>,
+        bedrock_indices: ArrayView2<usize>,
+    ) -> Result<GoldenVerification, SimulationError> {
+        let checksums = input_checksums(&reservoir_matrix, &depths, &bedrock_indices);
+        let input_mismatches = self
+            .input_checksums
+            .iter()
+            .filter(|&(name, checksum)| checksums.get(name) != Some(checksum))
+            .map(|(name, _)| name.clone())
+            .collect();
+        let (outputs_hash, summary) = run(
+            &reservoir_matrix,
+            &depths,
+            &bedrock_indices,
+            &self.sources,
+            &self.config,
+        )?;
+        let summary_mismatches = self
+            .summary
+            .iter()
+            .filter_map(|(name, recorded)| {
+                let current = summary.get(name).cloned().unwrap_or(Value::Null);
+                (current != *recorded).then(|| (name.clone(), recorded.clone(), current))
+            })
+            .collect();
+        Ok(GoldenVerification {
+            input_mismatches,
+            inputs_hash_matches: inputs_hash(&self.input_checksums, &self.sources, &self.config)
+                == self.inputs_hash,
+            outputs_hash_matches: outputs_hash == self.outputs_hash,
+            outputs_hash,
+            summary_mismatches,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmarks::BenchmarkModel;
+    use crate::constants::VELOCITY_CAPROCK;
+
+    #[test]
+    fn test_golden_run_is_reproduced() {
+        let case = BenchmarkModel::SleipnerDome.build(16).unwrap();
+        let grid = &case.grid;
+        let config = SimulationConfig {
+            max_column_height: Some(2),
+            total_snapshots: 10,
+            deterministic: true,
+            ..Default::default()
+        };
+        let golden = GoldenRun::record(
+            grid.reservoir_matrix.view(),
+            grid.depths.view(),
+            grid.bedrock_indices.view(),
+            &[case.source],
+            &config,
+        )
+        .unwrap();
+        // The record survives a round trip through JSON
+        let golden: GoldenRun =
+            serde_json::from_str(&serde_json::to_string(&golden).unwrap()).unwrap();
+        let verification = golden
+            .verify(
+                grid.reservoir_matrix.view(),
+                grid.depths.view(),
+                grid.bedrock_indices.view(),
+            )
+            .unwrap();
+        assert!(verification.passed(), "{}", verification.to_json());
+
+        // Other inputs are caught, and so is a config edited in the record
+        let mut reservoir_matrix = grid.reservoir_matrix.clone();
+        let (x, y, z) = case.source;
+        reservoir_matrix[[x, y, z + 1]] = VELOCITY_CAPROCK;
+        let verification = golden
+            .verify(
+                reservoir_matrix.view(),
+                grid.depths.view(),
+                grid.bedrock_indices.view(),
+            )
+            .unwrap();
+        assert_eq!(verification.input_mismatches, ["reservoir_matrix"]);
+        assert!(!verification.outputs_hash_matches);
+        assert!(!verification.summary_mismatches.is_empty());
+
+        let mut edited = golden.clone();
+        edited.config.total_snapshots = 20;
+        let verification = edited
+            .verify(
+                grid.reservoir_matrix.view(),
+                grid.depths.view(),
+                grid.bedrock_indices.view(),
+            )
+            .unwrap();
+        assert!(!verification.inputs_hash_matches);
+        assert!(!verification.passed());
+    }
+}
@@ -0,0 +1,354 @@
+use numpy::ndarray::{Array1, Array3, ArrayView1};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::SimulationError;
+use crate::utils::safe_indices;
+
+/// The geometry of the simulation grid. The fill algorithm only accesses the grid through this trait.
+/// Cells are indexed (x, y, z) with z increasing downwards, so (x, y, z - 1) is the cell above.
+pub trait Grid {
+    fn dim(&self) -> (usize, usize, usize);
+
+    /// Depth of the cell center, used to order the front
+    fn cell_depth(&self, cell: (usize, usize, usize)) -> f64;
+
+    /// Inactive cells are never filled
+    fn is_active(&self, cell: (usize, usize, usize)) -> bool;
+
+    /// The cells at the given (dx, dy) offsets in the same layer that are inside the grid, in the order of the offsets
+    fn lateral_neighbors<'a>(
+        &'a self,
+        (x, y, z): (usize, usize, usize),
+        directions: &'a [(i32, i32)],
+    ) -> impl Iterator<Item = (usize, usize, usize)> + 'a {
+        let (nx, ny, nz) = self.dim();
+        directions.iter().filter_map(move |&(dx, dy)| {
+            safe_indices(x as i32 + dx, y as i32 + dy, z as i32, nx, ny, nz)
+        })
+    }
+}
+
+/// A regular grid where every layer has a single depth and all cells are active
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegularGrid {
+    dims: (usize, usize, usize),
+    depths: Array1<f64>,
+}
+
+impl RegularGrid {
+    pub fn new(nx: usize, ny: usize, depths: ArrayView1<f64>) -> Self {
+        RegularGrid {
+            dims: (nx, ny, depths.len()),
+            depths: depths.to_owned(),
+        }
+    }
+
+    pub fn depths(&self) -> &Array1<f64> {
+        &self.depths
+    }
+}
+
+impl Grid for RegularGrid {
+    fn dim(&self) -> (usize, usize, usize) {
+        self.dims
+    }
+
+    #[inline]
+    fn cell_depth(&self, (_, _, z): (usize, usize, usize)) -> f64 {
+        self.depths[z]
+    }
+
+    #[inline]
+    fn is_active(&self, _cell: (usize, usize, usize)) -> bool {
+        true
+    }
+}
+
+/// A corner-point grid, where every cell has its own depth and may be inactive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CornerPointGrid {
+    // Depth of each cell center, the average of its eight corners
+    cell_depths: Array3<f64>,
+    active: Array3<bool>,
+}
+
+impl CornerPointGrid {
+    pub fn new(cell_depths: Array3<f64>, active: Array3<bool>) -> Result<Self, SimulationError> {
+        if cell_depths.dim() != active.dim() {
+            return Err(SimulationError::ShapeMismatch {
+                argument: "active".to_string(),
+                expected: format!("{:?} to match cell_depths", cell_depths.dim()),
+                actual: format!("{:?}", active.dim()),
+            });
+        }
+        if let Some((cell, depth)) = cell_depths
+            .indexed_iter()
+            .find(|&(cell, depth)| active[cell] && !depth.is_finite())
+        {
+            return Err(SimulationError::InvalidValue {
+                argument: "cell_depths".to_string(),
+                message: format!("active cell {:?} has depth {}", cell, depth),
+            });
+        }
+        Ok(CornerPointGrid {
+            cell_depths,
+            active,
+        })
+    }
+
+    /// Read a corner-point grid from an Eclipse GRDECL file
+    pub fn from_grdecl_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(Self::from_grdecl_str(&text)?)
+    }
+
+    /// Parse a corner-point grid from the contents of a GRDECL file.
+    /// SPECGRID (or DIMENS) and ZCORN are required, and ACTNUM is used if present.
+    /// The pillars in COORD are not needed to order the front and are ignored.
+    pub fn from_grdecl_str(text: &str) -> Result<Self, SimulationError> {
+        let grdecl_error = |message: String| SimulationError::InvalidValue {
+            argument: "GRDECL".to_string(),
+            message,
+        };
+
+        let keywords = read_grdecl_keywords(text, &["SPECGRID", "DIMENS", "ZCORN", "ACTNUM"])
+            .map_err(grdecl_error)?;
+        let find = |name: &str| keywords.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+
+        let spec = find("SPECGRID")
+            .or_else(|| find("DIMENS"))
+            .ok_or_else(|| grdecl_error("missing SPECGRID or DIMENS".to_string()))?;
+        if spec.len() < 3 {
+            return Err(grdecl_error("SPECGRID must give nx, ny and nz".to_string()));
+        }
+        let dims: Vec<usize> = spec[..3]
+            .iter()
+            .map(|v| v.parse::<usize>())
+            .collect::<Result<_, _>>()
+            .map_err(|err| grdecl_error(format!("invalid grid dimensions: {}", err)))?;
+        let (nx, ny, nz) = (dims[0], dims[1], dims[2]);
+
+        let zcorn = parse_values::<f64>(
+            find("ZCORN").ok_or_else(|| grdecl_error("missing ZCORN".to_string()))?,
+        )
+        .map_err(grdecl_error)?;
+        if zcorn.len() != 8 * nx * ny * nz {
+            return Err(grdecl_error(format!(
+                "ZCORN has {} values, expected {} for a ({}, {}, {}) grid",
+                zcorn.len(),
+                8 * nx * ny * nz,
+                nx,
+                ny,
+                nz
+            )));
+        }
+
+        // ZCORN is ordered with the x corner index varying fastest, then y, then z
+        let cell_depths = Array3::from_shape_fn((nx, ny, nz), |(i, j, k)| {
+            let mut sum = 0.0;
+            for dk in 0..2 {
+                for dj in 0..2 {
+                    for di in 0..2 {
+                        sum += zcorn[((2 * k + dk) * 2 * ny + 2 * j + dj) * 2 * nx + 2 * i + di];
+                    }
+                }
+            }
+            sum / 8.0
+        });
+
+        let active = match find("ACTNUM") {
+            Some(values) => {
+                let actnum = parse_values::<i64>(values).map_err(grdecl_error)?;
+                if actnum.len() != nx * ny * nz {
+                    return Err(grdecl_error(format!(
+                        "ACTNUM has {} values, expected {}",
+                        actnum.len(),
+                        nx * ny * nz
+                    )));
+                }
+                Array3::from_shape_fn((nx, ny, nz), |(i, j, k)| actnum[(k * ny + j) * nx + i] != 0)
+            }
+            None => Array3::from_elem((nx, ny, nz), true),
+        };
+
+        CornerPointGrid::new(cell_depths, active)
+    }
+
+    pub fn cell_depths(&self) -> &Array3<f64> {
+        &self.cell_depths
+    }
+}
+
+impl Grid for CornerPointGrid {
+    fn dim(&self) -> (usize, usize, usize) {
+        self.cell_depths.dim()
+    }
+
+    #[inline]
+    fn cell_depth(&self, (x, y, z): (usize, usize, usize)) -> f64 {
+        self.cell_depths[[x, y, z]]
+    }
+
+    #[inline]
+    fn is_active(&self, (x, y, z): (usize, usize, usize)) -> bool {
+        self.active[[x, y, z]]
+    }
+}
+
+/// The grid a simulation runs on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AnyGrid {
+    Regular(RegularGrid),
+    CornerPoint(CornerPointGrid),
+}
+
+impl From<RegularGrid> for AnyGrid {
+    fn from(grid: RegularGrid) -> Self {
+        AnyGrid::Regular(grid)
+    }
+}
+
+impl From<CornerPointGrid> for AnyGrid {
+    fn from(grid: CornerPointGrid) -> Self {
+        AnyGrid::CornerPoint(grid)
+    }
+}
+
+impl Grid for AnyGrid {
+    fn dim(&self) -> (usize, usize, usize) {
+        match self {
+            AnyGrid::Regular(grid) => grid.dim(),
+            AnyGrid::CornerPoint(grid) => grid.dim(),
+        }
+    }
+
+    #[inline]
+    fn cell_depth(&self, cell: (usize, usize, usize)) -> f64 {
+        match self {
+            AnyGrid::Regular(grid) => grid.cell_depth(cell),
+            AnyGrid::CornerPoint(grid) => grid.cell_depth(cell),
+        }
+    }
+
+    #[inline]
+    fn is_active(&self, cell: (usize, usize, usize)) -> bool {
+        match self {
+            AnyGrid::Regular(grid) => grid.is_active(cell),
+            AnyGrid::CornerPoint(grid) => grid.is_active(cell),
+        }
+    }
+}
+
+/// Collect the data of the given keywords in a GRDECL file. Other keywords are skipped.
+/// Comments start with `--` and the data of a keyword is terminated by `/`.
+pub(crate) fn read_grdecl_keywords(
+    text: &str,
+    wanted: &[&str],
+) -> Result<Vec<(String, Vec<String>)>, String> {
+    let mut tokens = text
+        .lines()
+        .map(|line| line.split("--").next().unwrap_or(""))
+        .flat_map(|line| line.split_whitespace());
+
+    let mut keywords = Vec::new();
+    while let Some(token) = tokens.next() {
+        if !wanted.contains(&token) {
+            continue;
+        }
+
+        let mut values = Vec::new();
+        let mut terminated = false;
+        for value in tokens.by_ref() {
+            if let Some(value) = value.strip_suffix('/') {
+                if !value.is_empty() {
+                    values.push(value.to_string());
+                }
+                terminated = true;
+                break;
+            }
+            values.push(value.to_string());
+        }
+        if !terminated {
+            return Err(format!("{} is not terminated by /", token));
+        }
+        keywords.push((token.to_string(), values));
+    }
+    Ok(keywords)
+}
+
+/// Parse keyword data, expanding repeats written as `count*value`
+pub(crate) fn parse_values<T: std::str::FromStr + Clone>(
+    tokens: &[String],
+) -> Result<Vec<T>, String> {
+    let parse = |s: &str| {
+        s.parse::<T>()
+            .map_err(|_| format!("could not parse value {}", s))
+    };
+
+    let mut values = Vec::new();
+    for token in tokens {
+        match token.split_once('*') {
+            Some((count, value)) => {
+                let count = count
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid repeat count in {}", token))?;
+                values.extend(std::iter::repeat_n(parse(value)?, count));
+            }
+            None => values.push(parse(token)?),
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regular_grid_neighbors_stay_inside() {
+        let depths = Array1::from(vec![0.0, 1.0]);
+        let grid = RegularGrid::new(3, 2, depths.view());
+        let directions = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+        let neighbors: Vec<_> = grid.lateral_neighbors((0, 0, 1), &directions).collect();
+        assert_eq!(neighbors, vec![(1, 0, 1), (0, 1, 1)]);
+        assert_eq!(grid.cell_depth((2, 1, 1)), 1.0);
+    }
+
+    #[test]
+    fn test_corner_point_grid_from_grdecl() {
+        // A 2 x 1 x 2 grid where the second column is shifted 10 m down and the bottom left cell is inactive
+        let mut zcorn = Vec::new();
+        for (top, bottom) in [(1000.0, 1010.0), (1010.0, 1020.0)] {
+            for depth in [top, bottom] {
+                for _ in 0..2 {
+                    zcorn.extend([depth, depth, depth + 10.0, depth + 10.0]);
+                }
+            }
+        }
+        let zcorn: Vec<String> = zcorn.iter().map(|z| z.to_string()).collect();
+        let text = format!(
+            "-- Test grid\nSPECGRID\n 2 1 2 1 F /\nCOORD\n 12*0.0 /\nZCORN\n{} /\nACTNUM\n 2*1 0 1 /\n",
+            zcorn.join(" ")
+        );
+
+        let grid = CornerPointGrid::from_grdecl_str(&text).unwrap();
+        assert_eq!(grid.dim(), (2, 1, 2));
+        assert_eq!(grid.cell_depth((0, 0, 0)), 1005.0);
+        assert_eq!(grid.cell_depth((1, 0, 0)), 1015.0);
+        assert_eq!(grid.cell_depth((1, 0, 1)), 1025.0);
+        assert!(grid.is_active((1, 0, 0)));
+        assert!(!grid.is_active((0, 0, 1)));
+    }
+
+    #[test]
+    fn test_grdecl_errors_are_descriptive() {
+        let err =
+            CornerPointGrid::from_grdecl_str("SPECGRID\n 2 2 2 /\nZCORN\n 8*1.0 /\n").unwrap_err();
+        assert!(err.to_string().contains("ZCORN has 8 values, expected 64"));
+
+        let err = CornerPointGrid::from_grdecl_str("ZCORN\n 8*1.0").unwrap_err();
+        assert!(err.to_string().contains("not terminated"));
+    }
+}
@@ -0,0 +1,296 @@
+use numpy::ndarray::{s, Array1, Array3, ArrayView1, ArrayView3, Axis};
+use serde_json::{json, Value};
+
+use crate::cell_state::{CellState, VelocityClassifier};
+use crate::constants::{VELOCITY_CAPROCK, VELOCITY_CO2, VELOCITY_RESERVOIR};
+
+/// A pathology of an input grid, see `repair_grid`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GridIssueKind {
+    /// The depths decrease with z, so the grid is upside down. Repaired by reversing the layers.
+    InvertedDepths,
+    /// A finite velocity that is not one of the rock velocities, which the simulation would treat as an inactive
+    /// cell. Repaired by snapping it to the nearest of the caprock, reservoir and CO2 velocities.
+    UnknownVelocity,
+    /// A reservoir cell whose face neighbors are all caprock, which can hold no CO2 and only breaks up the seal.
+    /// Repaired by making it caprock.
+    IsolatedReservoir,
+    /// A caprock cell with no reservoir anywhere below it in the column, which seals nothing. Repaired by making
+    /// it inactive.
+    UnsupportedCaprock,
+}
+
+impl GridIssueKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GridIssueKind::InvertedDepths => "inverted_depths",
+            GridIssueKind::UnknownVelocity => "unknown_velocity",
+            GridIssueKind::IsolatedReservoir => "isolated_reservoir",
+            GridIssueKind::UnsupportedCaprock => "unsupported_caprock",
+        }
+    }
+}
+
+/// One pathology found in the grid, and what was or would be changed to repair it
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridIssue {
+    pub kind: GridIssueKind,
+    /// The cell, in the layer order of the input, or None for the depths
+    pub cell: Option<(usize, usize, usize)>,
+    /// The velocity of the cell before and after the repair. NaN is inactive.
+    pub before: f64,
+    pub after: f64,
+    /// Whether the grid can be repaired for this issue. Depths that are neither increasing nor decreasing cannot.
+    pub repairable: bool,
+}
+
+/// Every pathology found in a grid, in the order the repairs are made, see `repair_grid`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GridReport {
+    pub issues: Vec<GridIssue>,
+}
+
+impl GridReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Number of issues of the kind
+    pub fn count(&self, kind: GridIssueKind) -> usize {
+        self.issues
+            .iter()
+            .filter(|issue| issue.kind == kind)
+            .count()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let kinds = [
+            GridIssueKind::InvertedDepths,
+            GridIssueKind::UnknownVelocity,
+            GridIssueKind::IsolatedReservoir,
+            GridIssueKind::UnsupportedCaprock,
+        ];
+        let counts: serde_json::Map<String, Value> = kinds
+            .iter()
+            .map(|kind| (kind.as_str().to_string(), json!(self.count(*kind))))
+            .collect();
+        // serde_json writes NaN as null, which is how inactive cells are reported
+        let issues: Vec<Value> = self
+            .issues
+            .iter()
+            .map(|issue| {
+                json!({
+                    "kind": issue.kind.as_str(),
+                    "cell": issue.cell,
+                    "before": issue.before,
+                    "after": issue.after,
+                    "repairable": issue.repairable,
+                })
+            })
+            .collect();
+        json!({"counts": counts, "issues": issues})
+    }
+}
+
+/// The velocity of the rock class nearest to the velocity
+fn nearest_rock_velocity(velocity: f64) -> f64 {
+    [VELOCITY_CAPROCK, VELOCITY_RESERVOIR, VELOCITY_CO2]
+        .into_iter()
+        .min_by(|a, b| (a - velocity).abs().total_cmp(&(b - velocity).abs()))
+        .unwrap()
+}
+
+/// Find the pathologies of a grid without changing it, see `repair_grid`
+pub fn check_grid(
+    reservoir_matrix: ArrayView3<f64>,
+    depths: ArrayView1<f64>,
+    classifier: VelocityClassifier,
+) -> GridReport {
+    repair_grid(
+        &mut reservoir_matrix.to_owned(),
+        &mut depths.to_owned(),
+        classifier,
+    )
+}
+
+/// Find and repair the common pathologies of an input grid, see `GridIssueKind`, and report every change.
+/// Inverted depths are repaired first, since the other checks look down the columns, then unknown velocities,
+/// isolated reservoir cells and unsupported caprock, each on the grid as repaired so far. The cells of the report
+/// are in the layer order of the input. The bedrock indices of the repaired grid must be computed again.
+pub fn repair_grid(
+    reservoir_matrix: &mut Array3<f64>,
+    depths: &mut Array1<f64>,
+    classifier: VelocityClassifier,
+) -> GridReport {
+    let mut report = GridReport::default();
+    let (nx, ny, nz) = reservoir_matrix.dim();
+    let increasing = (1..depths.len()).all(|z| depths[z] > depths[z - 1]);
+    let decreasing = (1..depths.len()).all(|z| depths[z] < depths[z - 1]);
+    let inverted = decreasing && depths.len() > 1;
+    if !increasing {
+        report.issues.push(GridIssue {
+            kind: GridIssueKind::InvertedDepths,
+            cell: None,
+            before: depths[0],
+            after: depths[depths.len() - 1],
+            repairable: inverted,
+        });
+        if inverted {
+            depths.invert_axis(Axis(0));
+            reservoir_matrix.invert_axis(Axis(2));
+            *depths = depths.as_standard_layout().to_owned();
+            *reservoir_matrix = reservoir_matrix.as_standard_layout().to_owned();
+        }
+    }
+    let input_cell = |(x, y, z): (usize, usize, usize)| {
+        if inverted {
+            (x, y, nz - 1 - z)
+        } else {
+            (x, y, z)
+        }
+    };
+    let mut change = |reservoir_matrix: &mut Array3<f64>,
+                      kind: GridIssueKind,
+                      cell: (usize, usize, usize),
+                      after: f64| {
+        let velocity = &mut reservoir_matrix[[cell.0, cell.1, cell.2]];
+        report.issues.push(GridIssue {
+            kind,
+            cell: Some(input_cell(cell)),
+            before: *velocity,
+            after,
+            repairable: true,
+        });
+        *velocity = after;
+    };
+
+    for ((x, y, z), velocity) in reservoir_matrix.clone().indexed_iter() {
+        if velocity.is_finite() && classifier.classify(*velocity) == CellState::Inactive {
+            change(
+                reservoir_matrix,
+                GridIssueKind::UnknownVelocity,
+                (x, y, z),
+                nearest_rock_velocity(*velocity),
+            );
+        }
+    }
+
+    let states = reservoir_matrix.mapv(|velocity| classifier.classify(velocity));
+    for ((x, y, z), &state) in states.indexed_iter() {
+        if state != CellState::Reservoir {
+            continue;
+        }
+        let mut neighbors = [
+            (-1, 0, 0),
+            (1, 0, 0),
+            (0, -1, 0),
+            (0, 1, 0),
+            (0, 0, -1),
+            (0, 0, 1),
+        ]
+        .into_iter()
+        .filter_map(|(dx, dy, dz)| {
+            let neighbor = (
+                x.checked_add_signed(dx)?,
+                y.checked_add_signed(dy)?,
+                z.checked_add_signed(dz)?,
+            );
+            (neighbor.0 < nx && neighbor.1 < ny && neighbor.2 < nz).then_some(neighbor)
+        })
+        .peekable();
+        if neighbors.peek().is_some()
+            && neighbors
+                .all(|neighbor| states[[neighbor.0, neighbor.1, neighbor.2]] == CellState::Caprock)
+        {
+            change(
+                reservoir_matrix,
+                GridIssueKind::IsolatedReservoir,
+                (x, y, z),
+                VELOCITY_CAPROCK,
+            );
+        }
+    }
+
+    let states = reservoir_matrix.mapv(|velocity| classifier.classify(velocity));
+    for x in 0..nx {
+        for y in 0..ny {
+            let column = states.slice(s![x, y, ..]);
+            // Cells below the deepest reservoir or CO2 cell seal nothing
+            let deepest = column
+                .iter()
+                .rposition(|&state| state == CellState::Reservoir || state == CellState::Co2);
+            let first_unsupported = deepest.map_or(0, |z| z + 1);
+            for z in first_unsupported..nz {
+                if column[z] == CellState::Caprock {
+                    change(
+                        reservoir_matrix,
+                        GridIssueKind::UnsupportedCaprock,
+                        (x, y, z),
+                        f64::NAN,
+                    );
+                }
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repair_grid() {
+        // A seal over a reservoir with a basal shale, upside down
+        let mut reservoir = Array3::from_elem((4, 3, 6), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 3..]).fill(VELOCITY_CAPROCK);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        reservoir[[1, 1, 4]] = VELOCITY_RESERVOIR;
+        reservoir[[2, 1, 2]] = 1510.0;
+        reservoir[[3, 2, 1]] = f64::NAN;
+        let depths = Array1::from_iter((0..6).rev().map(|z| 1000.0 + 10.0 * z as f64));
+
+        let report = check_grid(reservoir.view(), depths.view(), VelocityClassifier::Exact);
+        let mut repaired = reservoir.clone();
+        let mut repaired_depths = depths.clone();
+        // NaN never equals itself, so the reports are compared as JSON
+        let repair = repair_grid(
+            &mut repaired,
+            &mut repaired_depths,
+            VelocityClassifier::Exact,
+        );
+        assert_eq!(repair.to_json(), report.to_json());
+        assert_eq!(report.count(GridIssueKind::InvertedDepths), 1);
+        assert_eq!(report.count(GridIssueKind::UnknownVelocity), 1);
+        assert_eq!(report.count(GridIssueKind::IsolatedReservoir), 1);
+        // The basal shale, under the deepest reservoir layer once the grid is the right way up
+        assert_eq!(report.count(GridIssueKind::UnsupportedCaprock), 12);
+        assert_eq!(
+            report.issues[1],
+            GridIssue {
+                kind: GridIssueKind::UnknownVelocity,
+                cell: Some((2, 1, 2)),
+                before: 1510.0,
+                after: VELOCITY_RESERVOIR,
+                repairable: true,
+            }
+        );
+        assert_eq!(report.issues[2].cell, Some((1, 1, 4)));
+        assert!(report.issues[3..]
+            .iter()
+            .all(|issue| issue.cell.unwrap().2 == 0 && issue.after.is_nan()));
+
+        assert_eq!(repaired_depths[0], 1000.0);
+        assert_eq!(repaired[[1, 1, 1]], VELOCITY_CAPROCK);
+        assert!(repaired[[0, 0, 5]].is_nan());
+        // The NaN padding is left alone
+        assert!(repaired[[3, 2, 4]].is_nan());
+        let mut again = repaired_depths.clone();
+        assert!(repair_grid(&mut repaired, &mut again, VelocityClassifier::Exact).is_clean());
+
+        let mut unordered = Array1::from(vec![0.0, 2.0, 1.0, 3.0, 4.0, 5.0]);
+        let report = repair_grid(&mut reservoir, &mut unordered, VelocityClassifier::Exact);
+        assert!(!report.issues[0].repairable);
+        assert_eq!(unordered[1], 2.0);
+    }
+}
@@ -1,11 +1,43 @@
-use numpy::ndarray::{s, Array3, ArrayView1, ArrayView2, ArrayView3};
+use std::borrow::Cow;
+use std::collections::HashSet;
 
-use crate::constants::{VELOCITY_CAPROCK, VELOCITY_CO2, VELOCITY_RESERVOIR};
-use crate::datastucture::DepthOrderedQueue;
+use chrono::NaiveDate;
+use numpy::ndarray::{Array1, Array2, Array3, ArrayView1, ArrayView2, ArrayView3, Axis};
+use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
+
+use crate::accumulation::{Accumulation, AccumulationTracker};
+use crate::audit::{LedgerEntry, MassLedger};
+use crate::breach::{BreachCriterion, BreachGeometry, StressCriterion};
+use crate::cell_state::{CellHistory, CellState, ReservoirState, RockType, VelocityClassifier};
+use crate::config::SimulationConfig;
+use crate::connectivity::{source_compartment, CompartmentReport};
+use crate::constants::VELOCITY_CO2;
+use crate::containment::{ContainmentViolation, LateralExceedance};
+use crate::cross_section::in_plane_directions;
+use crate::datastucture::{AnyFrontQueue, FrontQueue, QueueKind};
+use crate::dissolution::{ConvectiveDissolution, DissolutionHistory, Hysteresis, MineralTrapping};
+use crate::eos::{DensityTable, MassAccounting};
+use crate::error::SimulationError;
+use crate::events::{CellEvent, TrappingState};
+use crate::grid::{AnyGrid, Grid, RegularGrid};
+use crate::invariants::{InvariantKind, InvariantViolation};
+use crate::journal::JournalEntry;
+use crate::maps::{max_saturation_map, top_of_plume_depth_map};
+use crate::mesh::{plume_mesh, TriangleMesh};
+use crate::ordering::FrontOrdering;
+use crate::perforation::{injection_layers, SweepOrder};
+use crate::plume::{LayerStatistics, Plume};
+use crate::sparse::SparseGrid;
+use crate::spreading::SpreadingState;
+use crate::stencil::Stencil;
+use crate::time_axis::{RateChange, TimeAxis};
+use crate::trapping::TrappingInventory;
 use crate::utils::{
-    find_closest_caprock_idx, find_height_to_caprock, is_bedrock, is_empty, is_inside_bounds,
-    safe_indices,
+    find_height_to_caprock, is_bedrock, is_caprock, is_empty, is_inside_bounds, safe_indices,
 };
+use crate::validation::{validate_grid_inputs, validate_inputs};
+use crate::wells::WellAttribution;
 
 // Spread directions for 8-connectivity
 const SPREAD_DIRECTIONS: [(i32, i32); 8] = [
@@ -19,117 +51,286 @@ const SPREAD_DIRECTIONS: [(i32, i32); 8] = [
     (1, 1),
 ];
 
-/// Validate that the initial source position is in the reservoir and just below caprock.
-fn validate_initial_position(reservoir_matrix: &Array3<f64>, source: (usize, usize, usize)) {
+/// Build the lateral spreading directions for the given anisotropy.
+/// An anisotropy of (1, 1) gives the 8-connected SPREAD_DIRECTIONS.
+pub(crate) fn lateral_directions(anisotropy: (usize, usize)) -> Vec<(i32, i32)> {
+    if anisotropy == (1, 1) {
+        return SPREAD_DIRECTIONS.to_vec();
+    }
+
+    let (reach_x, reach_y) = (anisotropy.0 as i32, anisotropy.1 as i32);
+    let mut directions = Vec::new();
+    for dx in -reach_x..=reach_x {
+        for dy in -reach_y..=reach_y {
+            if (dx, dy) != (0, 0) {
+                directions.push((dx, dy));
+            }
+        }
+    }
+    directions
+}
+
+/// Check that the initial source position is in the reservoir and just below caprock.
+pub(crate) fn check_initial_position(
+    reservoir: &ReservoirState,
+    source: (usize, usize, usize),
+) -> Result<(), SimulationError> {
     let (xi, yi, zi) = source;
 
-    if reservoir_matrix[[xi, yi, zi]] != VELOCITY_RESERVOIR {
-        panic!("Source must be in reservoir");
+    if reservoir.state((xi, yi, zi)) != CellState::Reservoir {
+        return Err(SimulationError::InvalidSource(
+            "Source must be in reservoir".to_string(),
+        ));
     }
-    if zi > 0 && reservoir_matrix[[xi, yi, zi - 1]] != VELOCITY_CAPROCK {
-        panic!("Source must be just below caprock");
+    if zi > 0 && reservoir.state((xi, yi, zi - 1)) != CellState::Caprock {
+        return Err(SimulationError::InvalidSource(
+            "Source must be just below caprock".to_string(),
+        ));
     }
+    Ok(())
 }
 
 /// Compute the snapshot interval based on the total number of reservoir cells and desired total snapshots.
-fn compute_snapshot_interval(reservoir_matrix: &Array3<f64>, total_snapshots: usize) -> usize {
-    let n_total_reservoir_cells: usize = reservoir_matrix
-        .iter()
-        .filter(|&&val| val == VELOCITY_RESERVOIR)
-        .count();
+pub(crate) fn compute_snapshot_interval(
+    reservoir: &ReservoirState,
+    total_snapshots: usize,
+) -> usize {
+    let n_total_reservoir_cells = reservoir.count(CellState::Reservoir);
     std::cmp::max(1, n_total_reservoir_cells / total_snapshots)
 }
 
+/// Translate the velocities to the initial reservoir state. NaN cells, cells outside the active grid
+/// and cells above the bedrock are inactive.
+pub(crate) fn initial_reservoir_state(
+    reservoir_matrix: &ArrayView3<f64>,
+    grid: &impl Grid,
+    bedrock_indices: &ArrayView2<usize>,
+    classifier: VelocityClassifier,
+) -> ReservoirState {
+    let (nx, ny, nz) = reservoir_matrix.dim();
+    let mut reservoir = ReservoirState::from_velocities(reservoir_matrix, classifier);
+    for x in 0..nx {
+        for y in 0..ny {
+            for z in 0..nz {
+                if !grid.is_active((x, y, z)) {
+                    reservoir.deactivate((x, y, z));
+                }
+            }
+        }
+    }
+
+    // The bedrock is the final seal, so the cells above it can never be reached, not even by spreading
+    // in from a neighboring column with a deeper bedrock
+    for ((x, y), &bedrock) in bedrock_indices.indexed_iter() {
+        for z in 0..bedrock {
+            reservoir.deactivate((x, y, z));
+        }
+    }
+    reservoir
+}
+
+/// Helper function to move on to the next snapshot. The snapshot policy is checked against the i32 range
+/// when the simulation is set up, so this only fails if that check is wrong.
+#[inline]
+pub(crate) fn next_snapshot_index(snapshots_counter: i32) -> i32 {
+    snapshots_counter
+        .checked_add(1)
+        .expect("Snapshot index overflowed i32")
+}
+
 /// Try to fill the cell with CO2 if it is empty and the cell below is not empty.
-/// Update snapshots and counters accordingly.
+/// Update snapshots and counters accordingly. Returns true if the cell was filled.
 fn try_to_fill_cell_with_co2(
-    reservoir_matrix: &mut Array3<f64>,
-    snapshots: &mut Array3<i32>,
+    reservoir: &mut ReservoirState,
+    snapshots: &mut SparseGrid<i32>,
     cell: (usize, usize, usize),
     snapshots_counter: &mut i32,
     cells_filled_since_snapshot: &mut usize,
     snapshot_interval: usize,
-) {
+) -> bool {
     let (xi, yi, zi) = cell;
 
     // Check if the cell can be filled with CO2
-    if is_empty(reservoir_matrix[[xi, yi, zi]])
-        && (zi == 0 || !is_empty(reservoir_matrix[[xi, yi, zi - 1]]))
+    if is_empty(reservoir.state((xi, yi, zi)))
+        && (zi == 0 || !is_empty(reservoir.state((xi, yi, zi - 1))))
     {
-        reservoir_matrix[[xi, yi, zi]] = VELOCITY_CO2;
-        snapshots[[xi, yi, zi]] = *snapshots_counter;
+        reservoir.fill((xi, yi, zi));
+        snapshots.set((xi, yi, zi), *snapshots_counter);
         *cells_filled_since_snapshot += 1;
 
         // Take snapshot based on number of cells filled
         if *cells_filled_since_snapshot >= snapshot_interval {
-            *snapshots_counter += 1;
+            *snapshots_counter = next_snapshot_index(*snapshots_counter);
             *cells_filled_since_snapshot = 0;
         }
+        return true;
     }
+    false
 }
 
-/// Add 8-connected neighbors to the queue if they are empty. Set cell_added to true if any cell is added.
-fn add_to_8_connected_neighbors(
-    queue: &mut DepthOrderedQueue,
-    reservoir_matrix: &Array3<f64>,
-    depths: &ArrayView1<f64>,
+/// An empty front, tilted downstream with aquifer flow in the config and with rounded keys in deterministic mode
+fn front_queue(config: &SimulationConfig) -> AnyFrontQueue {
+    let queue = match &config.aquifer_flow {
+        Some(aquifer_flow) => AnyFrontQueue::with_tilt(config.queue, aquifer_flow.tilt()),
+        None => AnyFrontQueue::new(config.queue),
+    };
+    if config.deterministic {
+        queue.with_rounded_keys()
+    } else {
+        queue
+    }
+}
+
+/// The front of one perforated layer, when the injection is split between the layers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LayerFront {
+    queue: AnyFrontQueue,
+    // The fraction of the filled cells this layer should take
+    fraction: f64,
+    cells_filled: usize,
+}
+
+/// Add lateral neighbors to the queue if they are empty, with the given front key.
+/// Calls on_added for every cell that is added.
+fn add_to_lateral_neighbors(
+    queue: &mut impl FrontQueue,
+    reservoir: &ReservoirState,
+    grid: &impl Grid,
     current_cell: (usize, usize, usize),
-    dims: (usize, usize, usize),
-    cell_added: &mut bool,
+    directions: &[(i32, i32)],
+    key: &impl Fn((usize, usize, usize)) -> f64,
+    on_added: &mut impl FnMut((usize, usize, usize)),
 ) {
-    let (xi_curr, yi_curr, zi_curr) = current_cell;
-    let (nx, ny, nz) = dims;
-
-    for &(dx, dy) in &SPREAD_DIRECTIONS {
-        if let Some((x_new, y_new, z_new)) = safe_indices(
-            xi_curr as i32 + dx,
-            yi_curr as i32 + dy,
-            zi_curr as i32,
-            nx,
-            ny,
-            nz,
-        ) {
-            if is_empty(reservoir_matrix[[x_new, y_new, z_new]]) {
-                queue.push(depths[z_new], (x_new, y_new, z_new));
-                *cell_added = true;
-            }
+    for neighbor in grid.lateral_neighbors(current_cell, directions) {
+        if is_empty(reservoir.state(neighbor)) {
+            queue.push(key(neighbor), neighbor);
+            on_added(neighbor);
         }
     }
 }
 
-/// Check if the caprock breaks based on the column height of CO2. If it does, change the caprock cell to reservoir and add it to the queue.
+/// Check if the caprock breaks based on the column height of CO2. If it does, change the caprock cell, and the cells
+/// that break with it for the geometry, to reservoir and add them to the queue. The broken cells join the front with
+/// the key of the broken state. Returns the broken caprock cells, starting with the one above the column.
 fn try_to_break_caprock(
-    queue: &mut DepthOrderedQueue,
-    reservoir_matrix: &mut Array3<f64>,
-    depths: &ArrayView1<f64>,
+    queue: &mut impl FrontQueue,
+    reservoir: &mut ReservoirState,
+    key: impl Fn(&ReservoirState, (usize, usize, usize)) -> f64,
     bedrock_indices: &ArrayView2<usize>,
     current_cell: (usize, usize, usize),
-    max_column_height: usize,
-) {
+    geometry: BreachGeometry,
+    breaks: impl FnOnce(usize, (usize, usize, usize)) -> bool,
+) -> Vec<(usize, usize, usize)> {
     let (xi_curr, yi_curr, zi_curr) = current_cell;
 
-    let closest_caprock_idx = find_closest_caprock_idx(
-        reservoir_matrix.slice(s![xi_curr, yi_curr, ..]), // Slice to get the z indices for (xi_curr, yi_curr)
-        zi_curr,
-    );
+    let closest_caprock_idx = reservoir.closest_caprock_idx((xi_curr, yi_curr), zi_curr);
 
-    // Check if the column height has reached the threshold where the caprock breaks
-    if find_height_to_caprock(zi_curr, closest_caprock_idx) >= max_column_height {
+    // Check if the column below the caprock is enough to break it, given its height in cells and the caprock cell
+    let column_height = find_height_to_caprock(zi_curr, closest_caprock_idx);
+    if breaks(column_height, (xi_curr, yi_curr, closest_caprock_idx)) {
         if is_bedrock(bedrock_indices, (xi_curr, yi_curr, closest_caprock_idx)) {
-            return;
+            return Vec::new();
+        }
+        let cell = (xi_curr, yi_curr, closest_caprock_idx);
+        let was_caprock = is_caprock(reservoir.state(cell));
+        let mut broken = vec![cell];
+        if was_caprock {
+            broken.extend(breach_companions(
+                reservoir,
+                bedrock_indices,
+                cell,
+                geometry,
+            ));
         }
 
-        // Change the caprock cell from VELOCITY_CAPROCK to VELOCITY_RESERVOIR
-        reservoir_matrix[[xi_curr, yi_curr, closest_caprock_idx]] = VELOCITY_RESERVOIR;
+        for &cell in &broken {
+            // Break the caprock so the cell can be filled. The rock type itself is kept.
+            reservoir.breach(cell);
 
-        // Add this cell to the heap
-        queue.push(
-            depths[closest_caprock_idx],
-            (xi_curr, yi_curr, closest_caprock_idx),
-        );
+            // Add this cell to the heap
+            queue.push(key(reservoir, cell), cell);
+        }
+
+        if was_caprock {
+            return broken;
+        }
+    }
+    Vec::new()
+}
+
+/// The caprock cells that break together with the broken cell for the geometry, without the broken cell itself
+fn breach_companions(
+    reservoir: &ReservoirState,
+    bedrock_indices: &ArrayView2<usize>,
+    broken: (usize, usize, usize),
+    geometry: BreachGeometry,
+) -> Vec<(usize, usize, usize)> {
+    let (nx, ny, nz) = reservoir.dim();
+    let (x, y, z) = broken;
+    let can_break = |cell| is_caprock(reservoir.state(cell)) && !is_bedrock(bedrock_indices, cell);
+    match geometry {
+        BreachGeometry::SingleCell => Vec::new(),
+        BreachGeometry::Chimney { width } => {
+            let radius = (width / 2) as i32;
+            let mut cells = Vec::new();
+            for dx in -radius..=radius {
+                for dy in -radius..=radius {
+                    let Some(cell) =
+                        safe_indices(x as i32 + dx, y as i32 + dy, z as i32, nx, ny, nz)
+                    else {
+                        continue;
+                    };
+                    if cell != broken && can_break(cell) {
+                        cells.push(cell);
+                    }
+                }
+            }
+            cells
+        }
+        BreachGeometry::Threshold { min_column_height } => {
+            // The height of the CO2 column right below a caprock cell
+            let column_height = |(x, y, z): (usize, usize, usize)| {
+                (z + 1..nz)
+                    .take_while(|&zi| reservoir.state((x, y, zi)) == CellState::Co2)
+                    .count()
+            };
+            let mut seen = HashSet::from([broken]);
+            let mut pending = vec![broken];
+            let mut cells = Vec::new();
+            while let Some((x, y, z)) = pending.pop() {
+                for &(dx, dy) in &SPREAD_DIRECTIONS {
+                    let Some(cell) =
+                        safe_indices(x as i32 + dx, y as i32 + dy, z as i32, nx, ny, nz)
+                    else {
+                        continue;
+                    };
+                    if !seen.insert(cell) || !can_break(cell) {
+                        continue;
+                    }
+                    if column_height(cell) >= min_column_height {
+                        cells.push(cell);
+                        pending.push(cell);
+                    }
+                }
+            }
+            cells
+        }
     }
 }
 
+/// A caprock cell that broke during the simulation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BreachEvent {
+    /// The caprock cell that was converted to reservoir
+    pub cell: (usize, usize, usize),
+    /// The snapshot being recorded when the caprock broke
+    pub snapshot_index: i32,
+    /// Number of cells filled when the caprock broke
+    pub cells_filled: usize,
+    /// Number of cells filled when the cell re-sealed, if it did
+    pub resealed_at: Option<usize>,
+}
+
 pub fn _injection_simulation_rust(
     reservoir_matrix: ArrayView3<f64>,
     depths: ArrayView1<f64>,
@@ -138,52 +339,644 @@ pub fn _injection_simulation_rust(
     source: (usize, usize, usize),
     total_snapshots: usize,
 ) -> Array3<i32> {
-    // Getting the dimensions
-    let (nx, ny, nz) = reservoir_matrix.dim();
-    let (xi, yi, zi) = source;
-    let mut zi = zi;
+    let config = SimulationConfig {
+        max_column_height: Some(max_column_height),
+        total_snapshots,
+        ..Default::default()
+    };
+    run_injection_simulation(reservoir_matrix, depths, bedrock_indices, source, &config)
+}
+
+/// Run the injection simulation with the parameters given in the config
+pub fn run_injection_simulation(
+    reservoir_matrix: ArrayView3<f64>,
+    depths: ArrayView1<f64>,
+    bedrock_indices: ArrayView2<usize>, // The indices of the final caprock layer. This layer is impermeable.
+    source: (usize, usize, usize),
+    config: &SimulationConfig,
+) -> Array3<i32> {
+    let mut simulation = Simulation::new(reservoir_matrix, depths, bedrock_indices, source, config);
+    simulation.run();
+    simulation.into_snapshots()
+}
+
+/// Run the injection simulation and return the fill order, saturation and final velocity model together
+pub fn run_injection_simulation_outputs(
+    reservoir_matrix: ArrayView3<f64>,
+    depths: ArrayView1<f64>,
+    bedrock_indices: ArrayView2<usize>, // The indices of the final caprock layer. This layer is impermeable.
+    source: (usize, usize, usize),
+    config: &SimulationConfig,
+) -> SimulationOutputs {
+    let mut simulation = Simulation::new(reservoir_matrix, depths, bedrock_indices, source, config);
+    simulation.run();
+    simulation.into_outputs()
+}
+
+/// The dense outputs of a simulation
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationOutputs {
+    /// The snapshot at which every cell was filled, or -1
+    pub snapshots: Array3<i32>,
+    /// CO2 saturation of every cell, between 0 and 1
+    pub saturation: Array3<f32>,
+    /// The final velocity model, with CO2 filled cells and broken caprock. Inactive cells are NaN.
+    pub velocity_model: Array3<f64>,
+}
+
+/// The state of an injection simulation that can be advanced step by step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Simulation {
+    // The rock type and fluid content of every cell. Velocities are only used for the input and the velocity model.
+    reservoir: ReservoirState,
+    grid: AnyGrid,
+    bedrock_indices: Array2<usize>,
+    // The injection wells. Most runs have a single source.
+    sources: Vec<(usize, usize, usize)>,
+    config: SimulationConfig,
+    directions: Vec<(i32, i32)>,
+    // Plume state is stored sparsely, since the plume usually occupies a small part of the grid
+    visited: SparseGrid<bool>,
+    snapshots: SparseGrid<i32>,
+    queue: AnyFrontQueue,
+    // The z index at which each well is currently injecting. Past the base of the grid once a well is done.
+    current_zi: Vec<usize>,
+    // The layers every well injects into, in the order of the sweep, and the current step of the sweep
+    injection_plans: Vec<Vec<usize>>,
+    plan_step: usize,
+    // Which well's front reached each cell
+    wells: WellAttribution,
+    // Number of cells to fill in the current snapshot, as given by the snapshot policy
+    snapshot_interval: usize,
+    // The interval used by the uniform snapshot policy
+    uniform_snapshot_interval: usize,
+    snapshots_counter: i32,
+    cells_filled_since_snapshot: usize,
+    cells_filled: usize,
+    // The filled cells in the order they were filled
+    fill_order: Vec<(usize, usize, usize)>,
+    breach_events: Vec<BreachEvent>,
+    // The accumulations fed by the breaches, which decide when the broken cells re-seal
+    accumulations: AccumulationTracker,
+    // The breach events whose cells are still open, when they re-seal
+    open_breaches: Vec<usize>,
+    // Number of reservoir cells at the start, used to report progress
+    n_reservoir_cells: usize,
+    // Number of NaN cells in the input, which are treated as inactive
+    n_nan_cells: usize,
+    containment_violation: Option<ContainmentViolation>,
+    // The first cell beyond the lateral limit, if the plume passed it
+    lateral_exceedance: Option<LateralExceedance>,
+    // The first broken invariant, in paranoid mode
+    invariant_violation: Option<InvariantViolation>,
+    // CO2 tallies of every snapshot, in audit mode
+    ledger: MassLedger,
+    // Tonnes of CO2 injected so far, with mass accounting
+    injected_mass: f64,
+    // The fronts of the perforated layers when the injection is split between them, and otherwise empty.
+    // The front of the layer that takes the next cell is swapped into the queue while it is processed.
+    layer_fronts: Vec<LayerFront>,
+    // The random state of the stochastic spreading, if used
+    spreading: Option<SpreadingState>,
+    // The journal entries not yet taken, when the journal is recorded
+    journal: Vec<JournalEntry>,
+    // Copies of the simulation at the start of every snapshot so far, with rewind points in the config. Not
+    // saved in checkpoints, so a restored run rewinds by replaying.
+    #[serde(skip)]
+    rewind_points: Vec<Simulation>,
+    finished: bool,
+}
 
-    // Create mutable copy of reservoir_matrix matrix
-    let mut reservoir_matrix = reservoir_matrix.to_owned();
-    let mut visited = Array3::<bool>::default((nx, ny, nz));
-    let mut snapshots = Array3::<i32>::from_elem((nx, ny, nz), -1);
+impl Simulation {
+    /// Set up the simulation. Panics if the inputs are invalid, see `try_new` for a non-panicking version.
+    pub fn new(
+        reservoir_matrix: ArrayView3<f64>,
+        depths: ArrayView1<f64>,
+        bedrock_indices: ArrayView2<usize>, // The indices of the final caprock layer. This layer is impermeable.
+        source: (usize, usize, usize),
+        config: &SimulationConfig,
+    ) -> Self {
+        match Self::try_new(reservoir_matrix, depths, bedrock_indices, source, config) {
+            Ok(simulation) => simulation,
+            Err(err) => panic!("{}", err),
+        }
+    }
 
-    // Calculate snapshot interval
-    let snapshot_interval = compute_snapshot_interval(&reservoir_matrix, total_snapshots);
+    /// Set up the simulation, returning an error if the inputs are inconsistent or the source is invalid
+    pub fn try_new(
+        reservoir_matrix: ArrayView3<f64>,
+        depths: ArrayView1<f64>,
+        bedrock_indices: ArrayView2<usize>, // The indices of the final caprock layer. This layer is impermeable.
+        source: (usize, usize, usize),
+        config: &SimulationConfig,
+    ) -> Result<Self, SimulationError> {
+        Self::try_new_with_wells(reservoir_matrix, depths, bedrock_indices, &[source], config)
+    }
 
-    // Validate source position
-    validate_initial_position(&reservoir_matrix, source);
+    /// Set up a simulation injecting from several wells at once. The fronts of the wells spread together,
+    /// and `well_attribution` tells which well filled each cell.
+    pub fn try_new_with_wells(
+        reservoir_matrix: ArrayView3<f64>,
+        depths: ArrayView1<f64>,
+        bedrock_indices: ArrayView2<usize>, // The indices of the final caprock layer. This layer is impermeable.
+        sources: &[(usize, usize, usize)],
+        config: &SimulationConfig,
+    ) -> Result<Self, SimulationError> {
+        for &source in sources {
+            validate_inputs(&reservoir_matrix, &depths, &bedrock_indices, source, config)?;
+        }
 
-    let mut snapshots_counter = 0;
-    let mut cells_filled_since_snapshot = 0;
+        let (nx, ny, _) = reservoir_matrix.dim();
+        let grid = RegularGrid::new(nx, ny, depths);
+        Self::try_with_grid_and_wells(reservoir_matrix, grid, bedrock_indices, sources, config)
+    }
 
-    while zi < nz {
-        println!("Current zi: {}", zi);
+    /// Set up the simulation on any grid, e.g. a corner-point grid read from a GRDECL file.
+    /// The reservoir matrix must have the same dimensions as the grid.
+    pub fn try_with_grid(
+        reservoir_matrix: ArrayView3<f64>,
+        grid: impl Into<AnyGrid>,
+        bedrock_indices: ArrayView2<usize>, // The indices of the final caprock layer. This layer is impermeable.
+        source: (usize, usize, usize),
+        config: &SimulationConfig,
+    ) -> Result<Self, SimulationError> {
+        Self::try_with_grid_and_wells(reservoir_matrix, grid, bedrock_indices, &[source], config)
+    }
 
-        let mut queue = DepthOrderedQueue::new();
+    /// Set up a simulation injecting from several wells on any grid
+    pub fn try_with_grid_and_wells(
+        reservoir_matrix: ArrayView3<f64>,
+        grid: impl Into<AnyGrid>,
+        bedrock_indices: ArrayView2<usize>, // The indices of the final caprock layer. This layer is impermeable.
+        sources: &[(usize, usize, usize)],
+        config: &SimulationConfig,
+    ) -> Result<Self, SimulationError> {
+        let grid = grid.into();
+        if sources.is_empty() {
+            return Err(SimulationError::InvalidSource(
+                "At least one source is required".to_string(),
+            ));
+        }
+        // The well attribution is stored as i16, with negative values as markers
+        if sources.len() > i16::MAX as usize {
+            return Err(SimulationError::InvalidSource(format!(
+                "At most {} sources are supported, got {}",
+                i16::MAX,
+                sources.len()
+            )));
+        }
+        for &source in sources {
+            validate_grid_inputs(
+                &reservoir_matrix,
+                grid.dim(),
+                &bedrock_indices,
+                source,
+                config,
+            )?;
+            if !grid.is_active(source) {
+                return Err(SimulationError::InvalidSource(
+                    "Source must be in an active cell".to_string(),
+                ));
+            }
+            let source_bedrock = bedrock_indices[[source.0, source.1]];
+            if source.2 <= source_bedrock && source_bedrock > 0 {
+                return Err(SimulationError::InvalidSource(format!(
+                    "Source must be below the bedrock of its column at z = {}",
+                    source_bedrock
+                )));
+            }
+        }
+        // The depths of a corner point grid vary within a layer, which the bucket queue cannot order
+        if config.queue == QueueKind::Bucket && matches!(grid, AnyGrid::CornerPoint(_)) {
+            return Err(SimulationError::InvalidValue {
+                argument: "queue".to_string(),
+                message: "the bucket queue orders by z index and needs a regular grid".to_string(),
+            });
+        }
+
+        // Getting the dimensions
+        let (nx, ny, nz) = reservoir_matrix.dim();
+
+        // Translate the velocities to cell states. NaN cells, cells outside the active grid and cells above
+        // the bedrock are inactive.
+        let n_nan_cells = reservoir_matrix.iter().filter(|v| v.is_nan()).count();
+        let mut reservoir = initial_reservoir_state(
+            &reservoir_matrix,
+            &grid,
+            &bedrock_indices,
+            config.velocity_classifier,
+        );
+        // Like the cells above the bedrock, the basement can never be reached
+        if let Some(basement_indices) = &config.basement_indices {
+            for ((x, y), &basement) in basement_indices.indexed_iter() {
+                for z in basement..nz {
+                    reservoir.deactivate((x, y, z));
+                }
+            }
+        }
+
+        // Validate source positions
+        for &source in sources {
+            check_initial_position(&reservoir, source)?;
+        }
+
+        // Warm start from an earlier plume. A well inside it injects below the plume.
+        if let Some(initial_plume) = &config.initial_plume {
+            for (cell, _) in initial_plume.indexed_iter().filter(|&(_, &filled)| filled) {
+                match reservoir.state(cell) {
+                    CellState::Reservoir => reservoir.fill(cell),
+                    CellState::Caprock => {
+                        reservoir.breach(cell);
+                        reservoir.fill(cell);
+                    }
+                    CellState::Co2 | CellState::Inactive => {}
+                }
+            }
+        }
+
+        // Calculate snapshot interval
+        let uniform_snapshot_interval =
+            compute_snapshot_interval(&reservoir, config.total_snapshots);
+        let snapshot_interval = config
+            .snapshot_policy
+            .cells_in_snapshot(0, uniform_snapshot_interval);
+
+        let n_reservoir_cells = reservoir.count(CellState::Reservoir);
+
+        // Snapshot indices are stored as i32, so make sure the policy cannot produce more snapshots than that
+        let fillable_cells = n_reservoir_cells + reservoir.count(CellState::Caprock);
+        let max_snapshots = config
+            .snapshot_policy
+            .max_snapshots(fillable_cells, uniform_snapshot_interval);
+        if max_snapshots > i32::MAX as usize {
+            return Err(SimulationError::InvalidValue {
+                argument: "snapshot_policy".to_string(),
+                message: format!(
+                    "could produce up to {} snapshots on this grid, more than the {} that fit in the i32 snapshot indices",
+                    max_snapshots,
+                    i32::MAX
+                ),
+            });
+        }
+
+        let directions =
+            in_plane_directions(lateral_directions(config.anisotropy), reservoir.dim());
+
+        // The layers every well injects into, in the order of the sweep. The descent ends at the basement.
+        let injection_plans: Vec<Vec<usize>> = sources
+            .iter()
+            .map(|&(xi, yi, zi)| {
+                let base = config
+                    .basement_indices
+                    .as_ref()
+                    .map_or(nz, |basement_indices| basement_indices[[xi, yi]]);
+                injection_layers(config.perforation.as_ref(), zi, base, config.sweep_order)
+            })
+            .collect();
+
+        let accumulations = AccumulationTracker::new((nx, ny, nz), &directions);
+        let mut simulation = Simulation {
+            reservoir,
+            grid,
+            bedrock_indices: bedrock_indices.to_owned(),
+            sources: sources.to_vec(),
+            config: config.clone(),
+            directions,
+            visited: SparseGrid::new((nx, ny, nz), false),
+            snapshots: SparseGrid::new((nx, ny, nz), -1),
+            queue: front_queue(config),
+            layer_fronts: Vec::new(),
+            current_zi: injection_plans.iter().map(|plan| plan[0]).collect(),
+            injection_plans,
+            plan_step: 0,
+            wells: WellAttribution::new((nx, ny, nz), sources.len()),
+            snapshot_interval,
+            uniform_snapshot_interval,
+            snapshots_counter: 0,
+            cells_filled_since_snapshot: 0,
+            cells_filled: 0,
+            fill_order: Vec::new(),
+            breach_events: Vec::new(),
+            accumulations,
+            open_breaches: Vec::new(),
+            n_reservoir_cells,
+            n_nan_cells,
+            containment_violation: None,
+            lateral_exceedance: None,
+            invariant_violation: None,
+            ledger: MassLedger::default(),
+            injected_mass: 0.0,
+            spreading: config
+                .stochastic_spreading
+                .as_ref()
+                .map(SpreadingState::new),
+            journal: Vec::new(),
+            rewind_points: Vec::new(),
+            finished: false,
+        };
+        simulation.start_injection();
+        simulation.save_rewind_points();
+        Ok(simulation)
+    }
+
+    /// Seed the front at the wells, or a front per layer for an injection split between the layers
+    fn start_injection(&mut self) {
+        self.record(JournalEntry::Start {
+            shape: self.reservoir.dim(),
+            sources: self.sources.clone(),
+        });
+        let split = self
+            .config
+            .perforation
+            .as_ref()
+            .and_then(|perforation| perforation.fractions().map(|f| (perforation.layers(), f)));
+        match (split, self.config.sweep_order) {
+            (Some((layers, fractions)), _) => {
+                let (layers, fractions) = (layers.to_vec(), fractions.to_vec());
+                self.start_split_injection(&layers, &fractions)
+            }
+            (None, SweepOrder::Interleaved) => {
+                // Every layer of any well gets an equal share
+                let mut layers: Vec<usize> =
+                    self.injection_plans.iter().flatten().copied().collect();
+                layers.sort_unstable();
+                layers.dedup();
+                let fractions = vec![1.0 / layers.len() as f64; layers.len()];
+                self.start_split_injection(&layers, &fractions)
+            }
+            (None, _) => self.start_injection_at_current_depth(),
+        }
+    }
 
-        if is_inside_bounds(xi as i32, yi as i32, zi as i32, nx, ny, nz) {
-            queue.push(depths[zi], (xi, yi, zi));
+    /// Seed a front in every layer, for an injection split between the layers with the given fractions.
+    /// Wells are only seeded in the layers of their injection plan.
+    fn start_split_injection(&mut self, layers: &[usize], fractions: &[f64]) {
+        self.record(JournalEntry::Layer {
+            layers: layers.to_vec(),
+        });
+        for (&zi, &fraction) in layers.iter().zip(fractions) {
+            let mut queue = front_queue(&self.config);
+            for (well, &(xi, yi, _)) in self.sources.iter().enumerate() {
+                if !self.injection_plans[well].contains(&zi) {
+                    continue;
+                }
+                queue.push(self.front_key((xi, yi, zi)), (xi, yi, zi));
+                self.wells.claim((xi, yi, zi), well as i16);
+            }
+            self.layer_fronts.push(LayerFront {
+                queue,
+                fraction,
+                cells_filled: 0,
+            });
+        }
+    }
+
+    /// Process a cell from the front of the layer that is furthest behind its share of the filled cells.
+    /// Once the fronts of all layers with a share are exhausted the injection stops.
+    fn step_split_layers(&mut self) {
+        let next = self
+            .layer_fronts
+            .iter()
+            .enumerate()
+            .filter(|(_, front)| front.fraction > 0.0 && !front.queue.is_empty())
+            .min_by_key(|(_, front)| OrderedFloat((front.cells_filled + 1) as f64 / front.fraction))
+            .map(|(layer, _)| layer);
+        let Some(layer) = next else {
+            // Failed stochastic spreading attempts are still retried from the shared queue
+            self.layer_fronts.clear();
+            let nz = self.reservoir.dim().2;
+            self.current_zi.fill(nz);
+            return;
+        };
+
+        std::mem::swap(&mut self.queue, &mut self.layer_fronts[layer].queue);
+        let cells_filled = self.cells_filled;
+        if let Some(cell) = self.queue.pop() {
+            self.process_cell(cell);
+        }
+        self.layer_fronts[layer].cells_filled += self.cells_filled - cells_filled;
+        std::mem::swap(&mut self.queue, &mut self.layer_fronts[layer].queue);
+    }
+
+    /// Seed the queue with every well at its current z index. Finishes once all wells are below the grid.
+    fn start_injection_at_current_depth(&mut self) {
+        let (nx, ny, nz) = self.reservoir.dim();
+
+        if self.current_zi.iter().all(|&zi| zi >= nz) {
+            self.finish();
+            return;
         }
+        self.record(JournalEntry::Layer {
+            layers: self.current_zi.clone(),
+        });
 
-        while let Some((xi_curr, yi_curr, zi_curr)) = queue.pop() {
-            // Skip if already visited
-            if visited[[xi_curr, yi_curr, zi_curr]] {
+        for (well, (&(xi, yi, _), &zi)) in self.sources.iter().zip(&self.current_zi).enumerate() {
+            if zi >= nz {
                 continue;
             }
+            if is_inside_bounds(xi as i32, yi as i32, zi as i32, nx, ny, nz) {
+                self.queue.push(self.front_key((xi, yi, zi)), (xi, yi, zi));
+                self.wells.claim((xi, yi, zi), well as i16);
+            }
+        }
+    }
+
+    /// Process a single cell from the front. When the front is exhausted the wells move on to the next layer
+    /// of the sweep, by default one layer deeper.
+    /// Returns false when the simulation is finished.
+    pub fn step(&mut self) -> bool {
+        if self.finished {
+            return false;
+        }
+
+        if !self.layer_fronts.is_empty() {
+            self.step_split_layers();
+        } else {
+            match self.queue.pop() {
+                Some(cell) => self.process_cell(cell),
+                // With stochastic spreading the failed attempts get another round before the injection moves on
+                None if self.spreading.as_ref().is_some_and(|s| s.has_deferred()) => {
+                    self.retry_spreading()
+                }
+                None => {
+                    // The wells move on to the next layer of their plan
+                    let nz = self.reservoir.dim().2;
+                    self.plan_step += 1;
+                    for (zi, plan) in self.current_zi.iter_mut().zip(&self.injection_plans) {
+                        *zi = plan.get(self.plan_step).copied().unwrap_or(nz);
+                    }
+                    self.start_injection_at_current_depth();
+                }
+            }
+        }
+        self.save_rewind_points();
+        !self.finished
+    }
+
+    // Keep a full copy of the state for every snapshot started since the last one kept, with rewind points in
+    // the config. The copy is the state `replay` stops at for the snapshot. Rewinding restores a copy or replays
+    // the run, it never steps the state back along the fill order.
+    fn save_rewind_points(&mut self) {
+        if !self.config.rewind_points || self.rewind_points.len() as i32 > self.snapshots_counter {
+            return;
+        }
+        let points = std::mem::take(&mut self.rewind_points);
+        let point = self.clone();
+        self.rewind_points = points;
+        while self.rewind_points.len() as i32 <= self.snapshots_counter {
+            self.rewind_points.push(point.clone());
+        }
+    }
 
-            // Mark as visited
-            visited[[xi_curr, yi_curr, zi_curr]] = true;
+    // The copy of the simulation kept at the start of the snapshot, with the copies before it, if there is one
+    fn restore_rewind_point(&self, snapshot_index: i32) -> Option<Simulation> {
+        let mut simulation = self.rewind_points.get(snapshot_index as usize)?.clone();
+        simulation.rewind_points = self.rewind_points[..=snapshot_index as usize].to_vec();
+        Some(simulation)
+    }
 
-            // Check if the cell can be filled with CO2, and fill it if possible
-            try_to_fill_cell_with_co2(
-                &mut reservoir_matrix,
-                &mut snapshots,
-                (xi_curr, yi_curr, zi_curr),
-                &mut snapshots_counter,
-                &mut cells_filled_since_snapshot,
-                snapshot_interval,
-            );
+    // Change the rock of the cells, in the kept copies as well
+    fn apply_rock_edits(
+        &mut self,
+        rock_edits: &[((usize, usize, usize), RockType)],
+        n_reservoir_cells: usize,
+    ) {
+        let edit = |simulation: &mut Simulation| {
+            for &(cell, rock) in rock_edits {
+                simulation.reservoir.set_rock_type(cell, rock);
+            }
+            simulation.n_reservoir_cells = n_reservoir_cells;
+        };
+        self.rewind_points.iter_mut().for_each(edit);
+        edit(self);
+    }
+
+    /// Advance until `n_cells` more cells are filled or the simulation finishes.
+    /// Returns false when the simulation is finished.
+    pub fn advance(&mut self, n_cells: usize) -> bool {
+        let target = self.cells_filled + n_cells;
+        while self.cells_filled < target {
+            if !self.step() {
+                return false;
+            }
+        }
+        !self.finished
+    }
+
+    /// Run the simulation to completion
+    pub fn run(&mut self) {
+        while self.step() {}
+    }
+
+    fn process_cell(&mut self, (xi_curr, yi_curr, zi_curr): (usize, usize, usize)) {
+        // Skip if already visited or inactive
+        if self.visited.get((xi_curr, yi_curr, zi_curr)) {
+            return;
+        }
+
+        // Mark as visited
+        self.visited.set((xi_curr, yi_curr, zi_curr), true);
+
+        // Imbibed cells already hold residual CO2, which counts towards the mass when they are refilled
+        let residual_saturation = self.reservoir.saturation().get((xi_curr, yi_curr, zi_curr));
+
+        // Check if the cell can be filled with CO2, and fill it if possible
+        if try_to_fill_cell_with_co2(
+            &mut self.reservoir,
+            &mut self.snapshots,
+            (xi_curr, yi_curr, zi_curr),
+            &mut self.snapshots_counter,
+            &mut self.cells_filled_since_snapshot,
+            self.snapshot_interval,
+        ) {
+            self.cells_filled += 1;
+            self.fill_order.push((xi_curr, yi_curr, zi_curr));
+            self.accumulations.fill((xi_curr, yi_curr, zi_curr));
+            self.record(JournalEntry::Fill {
+                cell: (xi_curr, yi_curr, zi_curr),
+                snapshot: self.snapshots.get((xi_curr, yi_curr, zi_curr)),
+                well: self.wells.owner((xi_curr, yi_curr, zi_curr)),
+            });
+            if self.config.paranoid {
+                if let Some(violation) = self.fill_violation((xi_curr, yi_curr, zi_curr)) {
+                    self.report_violation(violation);
+                    return;
+                }
+            }
+            self.reseal_breaches();
+
+            // A new snapshot started, so ask the policy how long it should be
+            if self.cells_filled_since_snapshot == 0 {
+                self.record(JournalEntry::Snapshot {
+                    index: self.snapshots_counter - 1,
+                    cells_filled: self.cells_filled,
+                });
+                self.record_audit(self.snapshots_counter - 1);
+                self.update_snapshot_interval();
+                if self.config.paranoid {
+                    if let Err(violation) = self.check_invariants() {
+                        self.report_violation(violation);
+                        return;
+                    }
+                }
+            }
+
+            // In strict containment mode the first cell outside the containment ends the simulation
+            let violation = self
+                .config
+                .containment
+                .as_ref()
+                .and_then(|containment| containment.violation_at((xi_curr, yi_curr, zi_curr)));
+            if let Some(kind) = violation {
+                self.containment_violation = Some(ContainmentViolation {
+                    kind,
+                    cell: (xi_curr, yi_curr, zi_curr),
+                    snapshot_index: self.snapshots.get((xi_curr, yi_curr, zi_curr)),
+                    cells_filled: self.cells_filled,
+                });
+                self.finish();
+                return;
+            }
+
+            // Flag the first cell beyond the lateral limit, and stop there if asked to
+            if let (Some(limit), None) = (&self.config.lateral_limit, &self.lateral_exceedance) {
+                let cell = (xi_curr, yi_curr, zi_curr);
+                if let Some(distance) = limit.exceeded_at(cell, &self.sources) {
+                    self.lateral_exceedance = Some(LateralExceedance {
+                        cell,
+                        distance,
+                        snapshot_index: self.snapshots.get(cell),
+                        cells_filled: self.cells_filled,
+                    });
+                    if limit.stop {
+                        self.finish();
+                        return;
+                    }
+                }
+            }
+
+            // Stop once the mass limit is reached
+            if let Some(mass_accounting) = &self.config.mass_accounting {
+                self.injected_mass += self.cell_mass(mass_accounting, (xi_curr, yi_curr, zi_curr))
+                    * (1.0 - residual_saturation as f64);
+                if mass_accounting
+                    .max_injected_mass
+                    .is_some_and(|max| self.injected_mass >= max)
+                {
+                    self.finish();
+                    return;
+                }
+            }
+        }
+
+        // The cells this cell adds to the front are claimed by the same well
+        let well = self.wells.owner((xi_curr, yi_curr, zi_curr));
+        if self.config.stencil.is_some() {
+            self.spread_with_stencil((xi_curr, yi_curr, zi_curr), well);
+        } else {
+            let wells = &mut self.wells;
+            let ordering = self.config.front_ordering;
+            let key = |cell| ordering.key(&self.grid, &self.reservoir, &self.sources, cell);
 
             // Check if CO2 can move upward (9-connectivity neighbors above)
             let mut added_above = false;
@@ -191,121 +984,1398 @@ pub fn _injection_simulation_rust(
             // Check directly above first
             if zi_curr > 0 {
                 let zi_above = zi_curr - 1;
-                if is_empty(reservoir_matrix[[xi_curr, yi_curr, zi_above]]) {
-                    queue.push(depths[zi_above], (xi_curr, yi_curr, zi_above));
+                let above = (xi_curr, yi_curr, zi_above);
+                if is_empty(self.reservoir.state(above)) {
+                    self.queue.push(key(above), above);
+                    wells.claim(above, well);
                     added_above = true;
                 }
 
-                add_to_8_connected_neighbors(
-                    &mut queue,
-                    &reservoir_matrix,
-                    &depths,
-                    (xi_curr, yi_curr, zi_above),
-                    (nx, ny, nz),
-                    &mut added_above,
+                add_to_lateral_neighbors(
+                    &mut self.queue,
+                    &self.reservoir,
+                    &self.grid,
+                    above,
+                    &self.directions,
+                    &key,
+                    &mut |cell| {
+                        wells.claim(cell, well);
+                        added_above = true;
+                    },
                 );
             }
 
             // If can't move up, spread horizontally
-            if !added_above {
-                let mut temp = false;
-                add_to_8_connected_neighbors(
-                    &mut queue,
-                    &reservoir_matrix,
-                    &depths,
+            if !added_above && self.spreading.is_some() {
+                self.spread_stochastically((xi_curr, yi_curr, zi_curr), well);
+            } else if !added_above {
+                add_to_lateral_neighbors(
+                    &mut self.queue,
+                    &self.reservoir,
+                    &self.grid,
                     (xi_curr, yi_curr, zi_curr),
-                    (nx, ny, nz),
-                    &mut temp,
+                    &self.directions,
+                    &key,
+                    &mut |cell| wells.claim(cell, well),
                 );
             }
+        }
 
-            // Check the column height to see if the caprock breaks.
-            try_to_break_caprock(
-                &mut queue,
-                &mut reservoir_matrix,
-                &depths,
-                &bedrock_indices,
-                (xi_curr, yi_curr, zi_curr),
-                max_column_height,
-            );
+        // Check the CO2 column to see if the caprock breaks
+        let grid = &self.grid;
+        let ordering = self.config.front_ordering;
+        let sources = &self.sources;
+        let key = |reservoir: &ReservoirState, cell| ordering.key(grid, reservoir, sources, cell);
+        let geometry = self.config.breach_geometry;
+        let broken = match &self.config.breach_criterion {
+            // Without a max column height the caprock never breaks
+            BreachCriterion::ColumnHeight => {
+                let Some(max_column_height) = self.config.max_column_height else {
+                    return;
+                };
+                try_to_break_caprock(
+                    &mut self.queue,
+                    &mut self.reservoir,
+                    key,
+                    &self.bedrock_indices.view(),
+                    (xi_curr, yi_curr, zi_curr),
+                    geometry,
+                    |column_height, _| column_height >= max_column_height,
+                )
+            }
+            BreachCriterion::Stress(stress) => {
+                let overpressure = self.estimated_overpressure((xi_curr, yi_curr));
+                // Impurities make the stream lighter than pure CO2, which adds buoyancy
+                let co2_stream = self.config.co2_stream.as_ref();
+                let density_table = self.config.mass_accounting.as_ref().map(|m| &m.density);
+                try_to_break_caprock(
+                    &mut self.queue,
+                    &mut self.reservoir,
+                    key,
+                    &self.bedrock_indices.view(),
+                    (xi_curr, yi_curr, zi_curr),
+                    geometry,
+                    |column_height, caprock| {
+                        let (x, y, z) = caprock;
+                        let caprock_depth = grid.cell_depth(caprock);
+                        let base_depth = grid.cell_depth((x, y, z + column_height));
+                        match co2_stream {
+                            None => stress.breaks(caprock_depth, base_depth, overpressure),
+                            Some(co2_stream) => {
+                                let co2_density = match density_table {
+                                    Some(table) => table.density(caprock_depth),
+                                    None => DensityTable::typical().density(caprock_depth),
+                                };
+                                let stress = StressCriterion {
+                                    density_difference: co2_stream
+                                        .density_difference(stress.density_difference, co2_density),
+                                    ..stress.clone()
+                                };
+                                stress.breaks(caprock_depth, base_depth, overpressure)
+                            }
+                        }
+                    },
+                )
+            }
+        };
+        for &cell in &broken {
+            self.wells.claim(cell, well);
+            self.record(JournalEntry::Breach {
+                cell,
+                snapshot: self.snapshots_counter,
+                cells_filled: self.cells_filled,
+            });
+            let event = BreachEvent {
+                cell,
+                snapshot_index: self.snapshots_counter,
+                cells_filled: self.cells_filled,
+                resealed_at: None,
+            };
+            self.accumulations.breach(&event);
+            self.breach_events.push(event);
+            if self.config.resealing.is_some() {
+                self.open_breaches.push(self.breach_events.len() - 1);
+            }
         }
+        self.update_seal_potentials(&broken);
+        // Cells that break together close a single snapshot
+        if !broken.is_empty() && self.config.snapshot_policy.snapshot_on_breach() {
+            self.record(JournalEntry::Snapshot {
+                index: self.snapshots_counter,
+                cells_filled: self.cells_filled,
+            });
+            self.record_audit(self.snapshots_counter);
+            self.snapshots_counter = next_snapshot_index(self.snapshots_counter);
+            self.cells_filled_since_snapshot = 0;
+            self.update_snapshot_interval();
+        }
+    }
 
-        zi += 1;
+    /// Re-seal the broken caprock cells that have let through enough CO2, unless they filled themselves.
+    /// A re-sealed cell can be broken and passed through again.
+    fn reseal_breaches(&mut self) {
+        let Some(resealing) = self.config.resealing else {
+            return;
+        };
+        let cells_filled = self.cells_filled;
+        let events = &mut self.breach_events;
+        let accumulations = &self.accumulations;
+        let reservoir = &mut self.reservoir;
+        let visited = &mut self.visited;
+        let mut journal = self.config.journal.then_some(&mut self.journal);
+        let mut resealed = Vec::new();
+        self.open_breaches.retain(|&index| {
+            let event = &mut events[index];
+            // Only the CO2 that went up through the broken cell counts, not the fills elsewhere in the plume
+            if accumulations.cells_fed_by(index) < resealing.cells {
+                return true;
+            }
+            if reservoir.state(event.cell) != CellState::Co2 {
+                reservoir.reseal(event.cell);
+                visited.set(event.cell, false);
+                resealed.push(event.cell);
+                event.resealed_at = Some(cells_filled);
+                if let Some(journal) = &mut journal {
+                    journal.push(JournalEntry::Reseal {
+                        cell: event.cell,
+                        cells_filled,
+                    });
+                }
+            }
+            false
+        });
+        self.update_seal_potentials(&resealed);
     }
 
-    // Return the snapshots array
-    snapshots
-}
+    /// With the seal potential ordering, the keys of the cells below a caprock cell are measured from it. When
+    /// caprock cells break or re-seal together, the queued cells below them, down to the next caprock, get the
+    /// keys of their new seal.
+    fn update_seal_potentials(&mut self, changed: &[(usize, usize, usize)]) {
+        if self.config.front_ordering != FrontOrdering::SealPotential {
+            return;
+        }
+        let nz = self.reservoir.dim().2;
+        for &(x, y, z) in changed {
+            // The seal above the cell that is not changing, or the top of the model
+            let seal_above = (0..z)
+                .rev()
+                .find(|&zi| {
+                    self.reservoir.state((x, y, zi)) == CellState::Caprock
+                        && !changed.contains(&(x, y, zi))
+                })
+                .unwrap_or(0);
+            let (old_seal, new_seal) = if self.reservoir.state((x, y, z)) == CellState::Caprock {
+                (seal_above, z)
+            } else {
+                (z, seal_above)
+            };
+            let delta =
+                self.grid.cell_depth((x, y, old_seal)) - self.grid.cell_depth((x, y, new_seal));
+            for zi in z + 1..nz {
+                let cell = (x, y, zi);
+                if self.reservoir.state(cell) == CellState::Caprock || changed.contains(&cell) {
+                    break;
+                }
+                self.queue.shift_key(cell, delta);
+                for front in &mut self.layer_fronts {
+                    front.queue.shift_key(cell, delta);
+                }
+            }
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::datastucture::DepthOrderedQueue;
-    use numpy::ndarray::{Array1, Array2, Array3};
+    /// The key of the cell in the front, for the ordering in the config
+    fn front_key(&self, cell: (usize, usize, usize)) -> f64 {
+        self.config
+            .front_ordering
+            .key(&self.grid, &self.reservoir, &self.sources, cell)
+    }
 
-    fn make_test_reservoir(nx: usize, ny: usize, nz: usize, fill: f64) -> Array3<f64> {
-        Array3::<f64>::from_elem((nx, ny, nz), fill)
+    /// Spread to the empty lateral neighbours of the cell that win their roll. The others are retried later.
+    fn spread_stochastically(&mut self, cell: (usize, usize, usize), well: i16) {
+        let Some(spreading) = &mut self.spreading else {
+            return;
+        };
+        let (x, y, _) = cell;
+        for neighbor in self.grid.lateral_neighbors(cell, &self.directions) {
+            if !is_empty(self.reservoir.state(neighbor)) {
+                continue;
+            }
+            let direction = (neighbor.0 as i32 - x as i32, neighbor.1 as i32 - y as i32);
+            if spreading.attempt(neighbor, direction, well) {
+                let key = self.config.front_ordering.key(
+                    &self.grid,
+                    &self.reservoir,
+                    &self.sources,
+                    neighbor,
+                );
+                self.queue.push(key, neighbor);
+                self.wells.claim(neighbor, well);
+            }
+        }
     }
 
-    #[test]
-    #[should_panic(expected = "Source must be in reservoir")]
-    fn test_validate_initial_position_panics_if_not_reservoir() {
-        let reservoir = make_test_reservoir(3, 3, 3, VELOCITY_CAPROCK);
-        validate_initial_position(&reservoir, (1, 1, 1));
+    /// Move the front along the offsets of the stencil in the config. The upward offsets are tried first, and
+    /// the others only if none of them reached an empty cell.
+    fn spread_with_stencil(&mut self, cell: (usize, usize, usize), well: i16) {
+        let Some(stencil) = &self.config.stencil else {
+            return;
+        };
+        let (nx, ny, nz) = self.reservoir.dim();
+        let (x, y, z) = (cell.0 as i32, cell.1 as i32, cell.2 as i32);
+        let mut added_above = false;
+        for rising in [true, false] {
+            if !rising && added_above {
+                break;
+            }
+            for &((dx, dy, dz), weight) in stencil.offsets() {
+                if (dz < 0) != rising {
+                    continue;
+                }
+                let Some(neighbor) = safe_indices(x + dx, y + dy, z + dz, nx, ny, nz) else {
+                    continue;
+                };
+                if is_empty(self.reservoir.state(neighbor)) {
+                    self.queue.push(self.front_key(neighbor) + weight, neighbor);
+                    self.wells.claim(neighbor, well);
+                    added_above |= rising;
+                }
+            }
+        }
     }
 
-    #[test]
-    #[should_panic(expected = "Source must be just below caprock")]
-    fn test_validate_initial_position_panics_if_not_below_caprock() {
-        let mut reservoir = make_test_reservoir(3, 3, 3, VELOCITY_RESERVOIR);
-        reservoir[[1, 1, 0]] = VELOCITY_RESERVOIR; // not caprock above
-        validate_initial_position(&reservoir, (1, 1, 1));
+    /// Give every failed spreading attempt another roll, and add the cells that were reached to the front
+    fn retry_spreading(&mut self) {
+        let Some(spreading) = &mut self.spreading else {
+            return;
+        };
+        let reservoir = &self.reservoir;
+        let visited = &self.visited;
+        let reached = spreading.retry(|cell| is_empty(reservoir.state(cell)) && !visited.get(cell));
+        for (cell, well) in reached {
+            self.queue.push(self.front_key(cell), cell);
+            self.wells.claim(cell, well);
+        }
     }
 
-    #[test]
-    fn test_compute_snapshot_interval() {
-        let reservoir = make_test_reservoir(2, 2, 2, VELOCITY_RESERVOIR);
-        assert_eq!(compute_snapshot_interval(&reservoir, 4), 2); // 8/4 = 2
-        assert_eq!(compute_snapshot_interval(&reservoir, 20), 1); // max(1, ..)
+    /// The overpressure from the pressure model in the given column after the cells filled so far.
+    /// Zero without a pressure model and a time axis. Uses the scheduled rates, not the throttled ones.
+    fn estimated_overpressure(&self, (x, y): (usize, usize)) -> f64 {
+        let (Some(pressure_model), Some(time_axis)) =
+            (&self.config.pressure_model, &self.config.time_axis)
+        else {
+            return 0.0;
+        };
+        let Some(days) = time_axis.days_to_fill(self.cells_filled) else {
+            return 0.0;
+        };
+        let wells: Vec<(usize, usize)> = self.sources.iter().map(|&(x, y, _)| (x, y)).collect();
+        pressure_model.overpressure_at(time_axis, &wells, (x, y), days)
     }
 
-    #[test]
-    fn test_try_to_fill_cell_with_co2_fills_correctly() {
-        let mut reservoir = make_test_reservoir(2, 2, 2, VELOCITY_RESERVOIR);
-        reservoir[[0, 0, 0]] = VELOCITY_CAPROCK; // caprock above (0,0,1)
-        let mut snapshots = Array3::<i32>::from_elem((2, 2, 2), -1);
-        let mut snapshots_counter = 0;
-        let mut cells_filled_since_snapshot = 0;
+    /// The tonnes of CO2 in a filled cell. The colder CO2 in the thermal zone is denser,
+    /// and impurities make the stream lighter.
+    fn cell_mass(&self, mass_accounting: &MassAccounting, cell: (usize, usize, usize)) -> f64 {
+        let mut mass = mass_accounting.cell_mass(self.grid.cell_depth(cell));
+        if let Some(co2_stream) = &self.config.co2_stream {
+            mass *= co2_stream.density_factor();
+        }
+        match &self.config.thermal_zone {
+            Some(zone) if zone.contains((cell.0, cell.1), &self.sources) => {
+                mass * zone.density_factor
+            }
+            _ => mass,
+        }
+    }
 
-        try_to_fill_cell_with_co2(
-            &mut reservoir,
-            &mut snapshots,
-            (0, 0, 1),
-            &mut snapshots_counter,
-            &mut cells_filled_since_snapshot,
-            1,
-        );
+    fn finish(&mut self) {
+        self.finished = true;
+        self.record(JournalEntry::Finish {
+            cells_filled: self.cells_filled,
+            snapshots: self.snapshots_counter,
+        });
+        self.record_audit(self.snapshots_counter);
+        if self.config.paranoid && self.invariant_violation.is_none() {
+            if let Err(violation) = self.check_invariants() {
+                self.invariant_violation = Some(violation);
+            }
+        }
+    }
 
-        assert_eq!(reservoir[[0, 0, 1]], VELOCITY_CO2);
-        assert_eq!(snapshots[[0, 0, 1]], 0);
-        assert_eq!(snapshots_counter, 1); // snapshot interval hit
+    /// Check the invariants of the whole plume, see `InvariantKind`. Runs at the end of every snapshot in
+    /// paranoid mode, and can be called at any time, e.g. in a debug assertion.
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        for &cell in &self.fill_order {
+            // Cells the CO2 has left again are no longer part of the plume
+            if self.reservoir.state(cell) != CellState::Co2 {
+                continue;
+            }
+            if !self.visited.get(cell) {
+                return Err(self.violation(
+                    InvariantKind::VisitedNotProcessed,
+                    cell,
+                    "the cell was filled but is not marked as visited".to_string(),
+                ));
+            }
+            let (x, y, z) = cell;
+            if z == 0 {
+                continue;
+            }
+            let above = (x, y, z - 1);
+            if self.reservoir.state(above) == CellState::Reservoir
+                && self.reservoir.history().get(above) == CellHistory::Pristine
+                && !self.reservoir.breached().get(above)
+            {
+                return Err(self.violation(
+                    InvariantKind::FilledBelowEmpty,
+                    cell,
+                    format!(
+                        "the cell above, {:?}, is empty reservoir that never held CO2",
+                        above
+                    ),
+                ));
+            }
+        }
+        for (cell, visited) in self.visited.iter() {
+            if !visited {
+                continue;
+            }
+            let rock = self.reservoir.rock_types()[[cell.0, cell.1, cell.2]];
+            let broke = self.reservoir.breached().get(cell)
+                || self.breach_events.iter().any(|event| event.cell == cell);
+            if rock == RockType::Inactive || (rock == RockType::Caprock && !broke) {
+                return Err(self.violation(
+                    InvariantKind::VisitedNotProcessed,
+                    cell,
+                    format!(
+                        "the cell is marked as visited, but the front cannot enter {:?} rock",
+                        rock
+                    ),
+                ));
+            }
+        }
+        Ok(())
     }
 
-    #[test]
+    /// The broken invariant of a cell that was just filled, if the front could not have reached it. The cells
+    /// the front could have moved in from are those the built-in rules or the stencil move from into the cell.
+    fn fill_violation(&self, cell: (usize, usize, usize)) -> Option<InvariantViolation> {
+        let (x, y, z) = cell;
+        // The wells, broken caprock and imbibed cells picked up again join the front directly
+        if self
+            .sources
+            .iter()
+            .any(|source| (source.0, source.1) == (x, y))
+            || self.reservoir.breached().get(cell)
+            || self.reservoir.history().get(cell) == CellHistory::SecondaryDrainage
+        {
+            return None;
+        }
+        let built_in;
+        let offsets = match &self.config.stencil {
+            Some(stencil) => stencil.offsets(),
+            None => {
+                built_in = Stencil::from_directions(&self.directions);
+                built_in.offsets()
+            }
+        };
+        let (nx, ny, nz) = self.reservoir.dim();
+        let mut origins = offsets.iter().filter_map(|&((dx, dy, dz), _)| {
+            safe_indices(x as i32 - dx, y as i32 - dy, z as i32 - dz, nx, ny, nz)
+        });
+        if origins.any(|origin| self.visited.get(origin)) {
+            return None;
+        }
+        Some(self.violation(
+            InvariantKind::Co2AboveCaprock,
+            cell,
+            "none of the cells the front could have moved in from has been processed".to_string(),
+        ))
+    }
+
+    fn violation(
+        &self,
+        kind: InvariantKind,
+        cell: (usize, usize, usize),
+        context: String,
+    ) -> InvariantViolation {
+        InvariantViolation {
+            kind,
+            cell,
+            snapshot_index: self.snapshots_counter,
+            cells_filled: self.cells_filled,
+            context,
+        }
+    }
+
+    /// Keep the first broken invariant and end the run there, in paranoid mode
+    fn report_violation(&mut self, violation: InvariantViolation) {
+        self.invariant_violation = Some(violation);
+        self.finish();
+    }
+
+    /// Add an entry to the journal, if it is recorded
+    fn record(&mut self, entry: JournalEntry) {
+        if self.config.journal {
+            self.journal.push(entry);
+        }
+    }
+
+    /// Add the tallies at the end of the given snapshot to the ledger, in audit mode
+    fn record_audit(&mut self, snapshot_index: i32) {
+        if !self.config.audit {
+            return;
+        }
+        // Count the CO2 in the grid independently of the fill counter. CO2 in the input was not injected, and
+        // only the cells the front filled have a snapshot index.
+        let mobile = self
+            .reservoir
+            .saturation()
+            .iter()
+            .filter(|&(cell, saturation)| saturation > 0.0 && self.snapshots.get(cell) >= 0)
+            .count();
+        self.ledger.record(LedgerEntry {
+            snapshot_index,
+            injected: self.cells_filled,
+            mobile,
+            trapped: 0,
+            dissolved: 0,
+            leaked: 0,
+        });
+    }
+
+    fn update_snapshot_interval(&mut self) {
+        self.snapshot_interval = self.config.snapshot_policy.cells_in_snapshot(
+            self.snapshots_counter as usize,
+            self.uniform_snapshot_interval,
+        );
+    }
+
+    /// The fill order so far as a dense array. Cells not yet filled are -1.
+    pub fn snapshots(&self) -> Array3<i32> {
+        self.snapshots.to_dense()
+    }
+
+    /// The fill order so far, in the sparse representation used during the simulation
+    pub fn sparse_snapshots(&self) -> &SparseGrid<i32> {
+        &self.snapshots
+    }
+
+    /// The filled cells in the order they were filled
+    pub fn fill_order(&self) -> &[(usize, usize, usize)] {
+        &self.fill_order
+    }
+
+    /// The caprock cells that have broken so far, in order
+    pub fn breach_events(&self) -> &[BreachEvent] {
+        &self.breach_events
+    }
+
+    /// The primary accumulation and the secondary accumulations fed by the breaches so far
+    pub fn accumulations(&self) -> Vec<Accumulation> {
+        self.accumulations.accumulations()
+    }
+
+    /// The date on which each snapshot so far was complete, if the config has a time axis.
+    /// A snapshot is None if the rate schedule stops before it is complete.
+    pub fn snapshot_dates(&self) -> Option<Vec<Option<NaiveDate>>> {
+        let time_axis = self.effective_time_axis()?;
+        Some(time_axis.snapshot_dates(&self.snapshot_cell_counts().to_vec()))
+    }
+
+    /// The time axis the injection actually follows. With a pressure limit this is the schedule throttled to keep
+    /// the bottomhole pressure below the limit, otherwise the time axis of the config.
+    pub fn effective_time_axis(&self) -> Option<Cow<'_, TimeAxis>> {
+        let time_axis = self.config.time_axis.as_ref()?;
+        match (&self.config.pressure_model, &self.config.pressure_limit) {
+            (Some(pressure_model), Some(pressure_limit)) => {
+                let wells: Vec<((usize, usize), f64)> = self
+                    .sources
+                    .iter()
+                    .map(|&source| ((source.0, source.1), self.grid.cell_depth(source)))
+                    .collect();
+                Some(Cow::Owned(pressure_model.throttled_time_axis(
+                    time_axis,
+                    pressure_limit,
+                    &wells,
+                    self.cells_filled,
+                )))
+            }
+            _ => Some(Cow::Borrowed(time_axis)),
+        }
+    }
+
+    /// Number of cells filled during each snapshot so far
+    pub fn snapshot_cell_counts(&self) -> Array1<usize> {
+        let mut counts = Array1::<usize>::zeros(self.snapshots_counter as usize + 1);
+        for &(x, y, z) in &self.fill_order {
+            counts[self.snapshots.get((x, y, z)) as usize] += 1;
+        }
+        counts
+    }
+
+    /// The estimated overpressure in Pa in every (x, y) column at the end of every snapshot so far,
+    /// with shape (n_snapshots, nx, ny). None unless the config has both a pressure model and a time axis.
+    pub fn overpressure_maps(&self) -> Option<Array3<f64>> {
+        let pressure_model = self.config.pressure_model.as_ref()?;
+        let time_axis = self.effective_time_axis()?;
+        let mut cells_filled = 0;
+        let snapshot_days: Vec<Option<f64>> = self
+            .snapshot_cell_counts()
+            .iter()
+            .map(|&count| {
+                cells_filled += count;
+                time_axis.days_to_fill(cells_filled)
+            })
+            .collect();
+        let wells: Vec<(usize, usize)> = self.sources.iter().map(|&(x, y, _)| (x, y)).collect();
+        let (nx, ny, _) = self.grid.dim();
+        Some(pressure_model.overpressure_maps(&time_axis, &wells, (nx, ny), &snapshot_days))
+    }
+
+    /// Tonnes of CO2 injected so far, if the config has mass accounting
+    pub fn injected_mass(&self) -> Option<f64> {
+        self.config
+            .mass_accounting
+            .as_ref()
+            .map(|_| self.injected_mass)
+    }
+
+    /// Tonnes of CO2 injected during each snapshot so far, if the config has mass accounting.
+    /// Every cell is weighted by the CO2 density at its depth, and in the thermal zone by its density factor.
+    pub fn snapshot_masses(&self) -> Option<Array1<f64>> {
+        let mass_accounting = self.config.mass_accounting.as_ref()?;
+        let mut masses = Array1::<f64>::zeros(self.snapshots_counter as usize + 1);
+        for &cell in &self.fill_order {
+            masses[self.snapshots.get(cell) as usize] += self.cell_mass(mass_accounting, cell);
+        }
+        Some(masses)
+    }
+
+    /// The CO2 held by each trapping mechanism at the end of every snapshot so far
+    pub fn trapping_inventory(&self) -> TrappingInventory {
+        TrappingInventory::from_snapshot_cell_counts(&self.snapshot_cell_counts().view())
+    }
+
+    /// Dissolve CO2 from the base of the plume for `n_steps` post-injection steps, see `ConvectiveDissolution`.
+    /// With mineral trapping, part of the dissolved CO2 is mineralized after every step,
+    /// and with hysteresis the cells the plume leaves keep residual CO2. With aquifer flow in the config
+    /// the mobile CO2 also drifts downstream. The simulation itself is not changed, see `relax` for that.
+    pub fn convective_dissolution(
+        &self,
+        model: &ConvectiveDissolution,
+        n_steps: usize,
+        mineral_trapping: Option<&MineralTrapping>,
+        hysteresis: Option<&Hysteresis>,
+    ) -> Result<DissolutionHistory, SimulationError> {
+        model.validate()?;
+        if let Some(mineral_trapping) = mineral_trapping {
+            mineral_trapping.validate()?;
+        }
+        if let Some(hysteresis) = hysteresis {
+            hysteresis.validate()?;
+        }
+        Ok(model.run(
+            &self.reservoir,
+            n_steps,
+            mineral_trapping,
+            hysteresis,
+            self.config.aquifer_flow.as_ref(),
+        ))
+    }
+
+    /// Like `convective_dissolution`, but keeps the result in the reservoir. Cells the plume has left are
+    /// imbibed and hold only residual CO2, and if the injection continues the front can invade them again.
+    /// A refilled cell only takes up the CO2 that fits on top of its residual saturation.
+    pub fn relax(
+        &mut self,
+        model: &ConvectiveDissolution,
+        n_steps: usize,
+        mineral_trapping: Option<&MineralTrapping>,
+        hysteresis: Option<&Hysteresis>,
+    ) -> Result<DissolutionHistory, SimulationError> {
+        let history = self.convective_dissolution(model, n_steps, mineral_trapping, hysteresis)?;
+        let mut imbibed = Vec::new();
+        for (cell, saturation) in self.reservoir.saturation().clone().iter() {
+            if history.cell_history.get(cell) == CellHistory::Imbibition {
+                imbibed.push(cell);
+            } else if history.saturation.get(cell) != saturation {
+                self.reservoir
+                    .set_saturation(cell, history.saturation.get(cell));
+            }
+        }
+        // Sorted so the refill order does not depend on the storage order
+        imbibed.sort_unstable();
+        for &cell in &imbibed {
+            self.reservoir.imbibe(cell, history.saturation.get(cell));
+            self.visited.set(cell, false);
+        }
+        // The front picks the imbibed cells up again where the plume still rests on them
+        if !self.finished {
+            for &(x, y, z) in &imbibed {
+                if z > 0 && !is_empty(self.reservoir.state((x, y, z - 1))) {
+                    self.queue.push(self.front_key((x, y, z)), (x, y, z));
+                }
+            }
+        }
+        Ok(history)
+    }
+
+    /// A copy of the simulation at the start of the given snapshot, e.g. for what-if runs from just before a
+    /// breach with `update_config`. The cell that started the snapshot has been processed, with any breach it
+    /// caused. This is not an incremental rewind: with rewind points in the config it is a clone of the copy
+    /// kept at the snapshot. Otherwise only the initial rock is recovered from the fill order, and the run is
+    /// replayed from scratch up to the snapshot, which takes as long as the run did. Relaxed simulations cannot be rewound, since the
+    /// dissolution is not part of the fill order.
+    pub fn rewound(&self, snapshot_index: i32) -> Result<Simulation, SimulationError> {
+        if snapshot_index < 0 || snapshot_index > self.snapshots_counter {
+            return Err(SimulationError::InvalidValue {
+                argument: "snapshot_index".to_string(),
+                message: format!(
+                    "must be between 0 and the current snapshot {}, got {}",
+                    self.snapshots_counter, snapshot_index
+                ),
+            });
+        }
+        self.check_rewindable("snapshot_index")?;
+        if let Some(simulation) = self.restore_rewind_point(snapshot_index) {
+            return Ok(simulation);
+        }
+        let simulation = self.replay(self.initial_reservoir(), snapshot_index);
+
+        // The run is deterministic, so the replay follows the original fill order
+        if !self.fill_order.starts_with(&simulation.fill_order) {
+            return Err(SimulationError::InvalidValue {
+                argument: "snapshot_index".to_string(),
+                message: "the replay diverged from the fill order".to_string(),
+            });
+        }
+        Ok(simulation)
+    }
+
+    /// A copy of the simulation on a grid with some cells changed, e.g. to close a fault or raise a seal
+    /// locally, given as (cell, velocity) pairs like the reservoir matrix. Only the part of the fill order the
+    /// edits can affect is invalidated: the copy is rewound to the snapshot before the first filled cell in a
+    /// column within spreading or breaching reach of an edited cell, and can be run on from there. Edits away from
+    /// the plume are applied without rewinding. With rewind points in the config the copy kept at that snapshot
+    /// is edited, and otherwise the run up to it is repeated on the edited grid, see `rewound`.
+    pub fn with_edited_cells(
+        &self,
+        edits: &[((usize, usize, usize), f64)],
+    ) -> Result<Simulation, SimulationError> {
+        let invalid = |message: String| SimulationError::InvalidValue {
+            argument: "edits".to_string(),
+            message,
+        };
+        let (nx, ny, nz) = self.reservoir.dim();
+        let base = |(x, y): (usize, usize)| {
+            self.config
+                .basement_indices
+                .as_ref()
+                .map_or(nz, |basement_indices| basement_indices[[x, y]])
+        };
+        let mut rock_edits = Vec::with_capacity(edits.len());
+        for &(cell, velocity) in edits {
+            let (x, y, z) = cell;
+            if x >= nx || y >= ny || z >= nz {
+                return Err(invalid(format!(
+                    "cell {:?} is outside the grid of shape {:?}",
+                    cell,
+                    (nx, ny, nz)
+                )));
+            }
+            if !self.grid.is_active(cell) || z < self.bedrock_indices[[x, y]] || z >= base((x, y)) {
+                return Err(invalid(format!(
+                    "cell {:?} is outside the active part of the grid",
+                    cell
+                )));
+            }
+            let rock = match self.config.velocity_classifier.classify(velocity) {
+                CellState::Caprock => RockType::Caprock,
+                CellState::Reservoir => RockType::Reservoir,
+                CellState::Inactive => RockType::Inactive,
+                CellState::Co2 => {
+                    return Err(invalid(format!(
+                        "the velocity {} of cell {:?} is CO2, but only the rock can be edited",
+                        velocity, cell
+                    )))
+                }
+            };
+            rock_edits.push((cell, rock));
+        }
+
+        let mut initial = self.initial_reservoir();
+        for &(cell, rock) in &rock_edits {
+            initial.set_rock_type(cell, rock);
+        }
+        for &source in &self.sources {
+            check_initial_position(&initial, source)?;
+        }
+
+        // The first filled cell the edits can reach, within the longest lateral move of the front. The threshold
+        // geometry breaks connected caprock of any extent, and a new snapshot interval renumbers every snapshot,
+        // so then the whole run is affected.
+        let stencil_reach = self.config.stencil.as_ref().map_or(0, |stencil| {
+            stencil
+                .offsets()
+                .iter()
+                .map(|&((dx, dy, _), _)| dx.unsigned_abs().max(dy.unsigned_abs()) as usize)
+                .max()
+                .unwrap_or(0)
+        });
+        let reach = match self.config.breach_geometry {
+            BreachGeometry::SingleCell => 1,
+            BreachGeometry::Chimney { width } => (width / 2).max(1),
+            BreachGeometry::Threshold { .. } => usize::MAX,
+        }
+        .max(self.config.anisotropy.0)
+        .max(self.config.anisotropy.1)
+        .max(stencil_reach);
+        let same_interval = compute_snapshot_interval(&initial, self.config.total_snapshots)
+            == self.uniform_snapshot_interval;
+        let first_affected = if !same_interval {
+            self.fill_order.first().copied()
+        } else {
+            self.fill_order
+                .iter()
+                .find(|&&(x, y, _)| {
+                    rock_edits
+                        .iter()
+                        .any(|&((ex, ey, _), _)| x.abs_diff(ex) <= reach && y.abs_diff(ey) <= reach)
+                })
+                .copied()
+        };
+
+        match first_affected {
+            None => {
+                let mut simulation = self.clone();
+                simulation.apply_rock_edits(&rock_edits, initial.count(CellState::Reservoir));
+                Ok(simulation)
+            }
+            Some(cell) => {
+                self.check_rewindable("edits")?;
+                // The cell that starts a snapshot is processed in the snapshot before
+                let snapshot_index = (self.snapshots.get(cell) - 1).max(0);
+                // The edits do not reach the run up to the snapshot, so a copy kept there only needs the edits.
+                // With a new snapshot interval the copies are numbered differently.
+                match self.restore_rewind_point(snapshot_index) {
+                    Some(mut simulation) if same_interval => {
+                        simulation
+                            .apply_rock_edits(&rock_edits, initial.count(CellState::Reservoir));
+                        Ok(simulation)
+                    }
+                    _ => Ok(self.replay(initial, snapshot_index)),
+                }
+            }
+        }
+    }
+
+    // Relaxation changes saturations outside the fill order, so the fill order cannot undo it
+    fn check_rewindable(&self, argument: &str) -> Result<(), SimulationError> {
+        let relaxed = self.reservoir.history().iter().any(|(_, history)| {
+            matches!(
+                history,
+                CellHistory::Imbibition | CellHistory::SecondaryDrainage
+            )
+        }) || self
+            .reservoir
+            .saturation()
+            .iter()
+            .any(|(_, saturation)| saturation > 0.0 && saturation < 1.0);
+        if relaxed {
+            return Err(SimulationError::InvalidValue {
+                argument: argument.to_string(),
+                message: "the simulation was relaxed and cannot be rewound".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // The reservoir before the first cell was filled, with the fills and breaches undone along the fill order
+    fn initial_reservoir(&self) -> ReservoirState {
+        let mut reservoir = self.reservoir.clone();
+        for &cell in &self.fill_order {
+            reservoir.unfill(cell);
+        }
+        for event in &self.breach_events {
+            reservoir.reseal(event.cell);
+        }
+        reservoir
+    }
+
+    // Start a fresh run from the initial reservoir with the wells and config of this one, and run it to the
+    // start of the given snapshot
+    fn replay(&self, reservoir: ReservoirState, snapshot_index: i32) -> Simulation {
+        let dims = reservoir.dim();
+        let uniform_snapshot_interval =
+            compute_snapshot_interval(&reservoir, self.config.total_snapshots);
+        let mut simulation = Simulation {
+            n_reservoir_cells: reservoir.count(CellState::Reservoir),
+            reservoir,
+            grid: self.grid.clone(),
+            bedrock_indices: self.bedrock_indices.clone(),
+            sources: self.sources.clone(),
+            config: self.config.clone(),
+            directions: self.directions.clone(),
+            visited: SparseGrid::new(dims, false),
+            snapshots: SparseGrid::new(dims, -1),
+            queue: front_queue(&self.config),
+            layer_fronts: Vec::new(),
+            current_zi: self.injection_plans.iter().map(|plan| plan[0]).collect(),
+            injection_plans: self.injection_plans.clone(),
+            plan_step: 0,
+            wells: WellAttribution::new(dims, self.sources.len()),
+            snapshot_interval: self
+                .config
+                .snapshot_policy
+                .cells_in_snapshot(0, uniform_snapshot_interval),
+            uniform_snapshot_interval,
+            snapshots_counter: 0,
+            cells_filled_since_snapshot: 0,
+            cells_filled: 0,
+            fill_order: Vec::new(),
+            breach_events: Vec::new(),
+            accumulations: AccumulationTracker::new(dims, &self.directions),
+            open_breaches: Vec::new(),
+            n_nan_cells: self.n_nan_cells,
+            containment_violation: None,
+            lateral_exceedance: None,
+            invariant_violation: None,
+            ledger: MassLedger::default(),
+            injected_mass: 0.0,
+            spreading: self
+                .config
+                .stochastic_spreading
+                .as_ref()
+                .map(SpreadingState::new),
+            journal: Vec::new(),
+            rewind_points: Vec::new(),
+            finished: false,
+        };
+        simulation.start_injection();
+        simulation.save_rewind_points();
+        while simulation.snapshots_counter < snapshot_index && simulation.step() {}
+        simulation
+    }
+
+    /// Change the parameters for the rest of the run, e.g. after `rewound`. Only the parameters the fill reads as
+    /// it goes can change: the max column height, the breach criterion and geometry, the resealing, which applies
+    /// to later breaches, the containment and the lateral and pressure limits.
+    pub fn update_config(&mut self, config: SimulationConfig) -> Result<(), SimulationError> {
+        let fixed = SimulationConfig {
+            max_column_height: self.config.max_column_height,
+            breach_criterion: self.config.breach_criterion.clone(),
+            breach_geometry: self.config.breach_geometry,
+            resealing: self.config.resealing,
+            containment: self.config.containment.clone(),
+            lateral_limit: self.config.lateral_limit.clone(),
+            pressure_limit: self.config.pressure_limit.clone(),
+            ..config.clone()
+        };
+        if fixed != self.config {
+            return Err(SimulationError::InvalidValue {
+                argument: "config".to_string(),
+                message:
+                    "only the max column height, breach criterion, breach geometry, resealing, \
+                          containment, lateral limit and pressure limit can change during a run"
+                        .to_string(),
+            });
+        }
+        validate_grid_inputs(
+            &self.reservoir.velocities().view(),
+            self.grid.dim(),
+            &self.bedrock_indices.view(),
+            self.sources[0],
+            &config,
+        )?;
+        self.config = config;
+        Ok(())
+    }
+
+    /// Start injecting from another well at the current point of the run, e.g. in a branched scenario. The well
+    /// joins the sweep of the other wells at its current layer. Not supported for an injection split between the
+    /// layers.
+    pub fn add_well(&mut self, source: (usize, usize, usize)) -> Result<(), SimulationError> {
+        if self.finished {
+            return Err(SimulationError::InvalidSource(
+                "Wells cannot be added once the injection has finished".to_string(),
+            ));
+        }
+        if !self.layer_fronts.is_empty() {
+            return Err(SimulationError::InvalidSource(
+                "Wells cannot be added to an injection split between the layers".to_string(),
+            ));
+        }
+        if self.sources.len() >= i16::MAX as usize {
+            return Err(SimulationError::InvalidSource(format!(
+                "At most {} sources are supported",
+                i16::MAX
+            )));
+        }
+        let (nx, ny, nz) = self.reservoir.dim();
+        let (xi, yi, zi) = source;
+        if xi >= nx || yi >= ny || zi >= nz {
+            return Err(SimulationError::InvalidSource(format!(
+                "Source {:?} is outside the grid of shape {:?}",
+                source,
+                (nx, ny, nz)
+            )));
+        }
+        check_initial_position(&self.reservoir, source)?;
+
+        let base = self
+            .config
+            .basement_indices
+            .as_ref()
+            .map_or(nz, |basement_indices| basement_indices[[xi, yi]]);
+        let layers = injection_layers(
+            self.config.perforation.as_ref(),
+            zi,
+            base,
+            self.config.sweep_order,
+        );
+        // The steps of the sweep already done are past for the new well
+        let plan: Vec<usize> = std::iter::repeat_n(nz, self.plan_step)
+            .chain(layers)
+            .collect();
+        let well = self.sources.len() as i16;
+        self.record(JournalEntry::Well { source });
+        self.wells.add_well(&self.visited);
+        self.sources.push(source);
+        self.current_zi.push(plan[self.plan_step]);
+        self.injection_plans.push(plan);
+        self.queue.push(self.front_key(source), source);
+        self.wells.claim(source, well);
+        Ok(())
+    }
+
+    /// Change the injection rates from the given rate changes on, replacing the later part of the rate schedule,
+    /// e.g. in a branched scenario. The changes must start after the date the run has reached.
+    pub fn reschedule(&mut self, rate_changes: &[RateChange]) -> Result<(), SimulationError> {
+        let invalid = |message: String| SimulationError::InvalidValue {
+            argument: "rate_changes".to_string(),
+            message,
+        };
+        let Some(time_axis) = &self.config.time_axis else {
+            return Err(invalid(
+                "the simulation has no time axis to change the rates of".to_string(),
+            ));
+        };
+        let Some(first) = rate_changes.first() else {
+            return Ok(());
+        };
+        if let Some(current_date) = time_axis.date_at_fill_count(self.cells_filled) {
+            if first.date <= current_date {
+                return Err(invalid(format!(
+                    "must start after {}, the date the run has reached, got {}",
+                    current_date, first.date
+                )));
+            }
+        }
+        self.config.time_axis = Some(time_axis.with_changes_from(rate_changes)?);
+        Ok(())
+    }
+
+    /// The journal entries recorded since the last call, to append to the journal as the run goes. Empty
+    /// unless `journal` is set in the config.
+    pub fn take_journal(&mut self) -> Vec<JournalEntry> {
+        std::mem::take(&mut self.journal)
+    }
+
+    pub fn into_snapshots(self) -> Array3<i32> {
+        self.snapshots.to_dense()
+    }
+
+    /// The snapshots, saturation and velocity model so far
+    pub fn outputs(&self) -> SimulationOutputs {
+        SimulationOutputs {
+            snapshots: self.snapshots(),
+            saturation: self.saturation(),
+            velocity_model: self.reservoir_matrix(),
+        }
+    }
+
+    pub fn into_outputs(self) -> SimulationOutputs {
+        let saturation = self.saturation();
+        let velocity_model = self.reservoir_matrix();
+        SimulationOutputs {
+            snapshots: self.into_snapshots(),
+            saturation,
+            velocity_model,
+        }
+    }
+
+    /// The CO2 saturation of every cell, between 0 and 1
+    pub fn saturation(&self) -> Array3<f32> {
+        self.reservoir.saturation().to_dense()
+    }
+
+    /// The plume so far, for spatial queries without building dense arrays
+    pub fn plume(&self) -> Plume {
+        Plume::new(self.snapshots.clone())
+    }
+
+    pub fn into_plume(self) -> Plume {
+        Plume::new(self.snapshots)
+    }
+
+    /// The highest CO2 saturation in every (x, y) column
+    pub fn max_saturation_map(&self) -> Array2<f32> {
+        max_saturation_map(self.reservoir.saturation())
+    }
+
+    /// The depth of the shallowest CO2 cell in every (x, y) column, or NaN for columns without CO2
+    pub fn top_of_plume_depth_map(&self) -> Array2<f64> {
+        top_of_plume_depth_map(&self.plume(), &self.grid)
+    }
+
+    /// The boundary of the plume at the given snapshot as a triangle mesh in (x index, y index, depth) coordinates
+    pub fn plume_mesh(&self, snapshot: i32) -> TriangleMesh {
+        plume_mesh(&self.plume(), &self.grid, snapshot)
+    }
+
+    /// Fill statistics for every z layer so far
+    pub fn layer_statistics(&self) -> Vec<LayerStatistics> {
+        self.plume()
+            .layer_statistics(&self.reservoir.rock_types().view())
+    }
+
+    /// The current velocity model, with CO2 filled cells and broken caprock. Inactive cells are NaN.
+    /// CO2 cells get the velocity of the injected stream, and in the thermal zone the velocity of the cold CO2.
+    /// With smoothing in the config the plume edges are smoothed last.
+    pub fn reservoir_matrix(&self) -> Array3<f64> {
+        let mut velocities = self.reservoir.velocities();
+        if let Some(co2_stream) = &self.config.co2_stream {
+            let co2_velocity = co2_stream.co2_velocity();
+            velocities.mapv_inplace(|v| if v == VELOCITY_CO2 { co2_velocity } else { v });
+        }
+        if let Some(zone) = &self.config.thermal_zone {
+            zone.apply_to_velocities(&mut velocities, &self.cell_states(), &self.sources);
+        }
+        if let Some(smoothing) = &self.config.smoothing {
+            smoothing.smooth(&mut velocities, &self.cell_states().view());
+        }
+        velocities
+    }
+
+    /// The current state of every cell
+    pub fn cell_states(&self) -> Array3<CellState> {
+        self.reservoir.cell_states()
+    }
+
+    /// The rock types and fluid content of the grid
+    pub fn reservoir_state(&self) -> &ReservoirState {
+        &self.reservoir
+    }
+
+    pub fn grid(&self) -> &AnyGrid {
+        &self.grid
+    }
+
+    /// The depth of every layer. In a corner-point grid, where the depth varies within a layer, this is the mean
+    /// depth of the cells of the layer.
+    pub fn layer_depths(&self) -> Array1<f64> {
+        match &self.grid {
+            AnyGrid::Regular(grid) => grid.depths().clone(),
+            AnyGrid::CornerPoint(grid) => grid
+                .cell_depths()
+                .mean_axis(Axis(0))
+                .and_then(|depths| depths.mean_axis(Axis(0)))
+                .expect("the grid is not empty"),
+        }
+    }
+
+    pub fn config(&self) -> &SimulationConfig {
+        &self.config
+    }
+
+    /// The source of the first well
+    pub fn source(&self) -> (usize, usize, usize) {
+        self.sources[0]
+    }
+
+    pub fn sources(&self) -> &[(usize, usize, usize)] {
+        &self.sources
+    }
+
+    /// The well that filled each cell so far, MERGED_WELLS where the fronts of several wells met and
+    /// NO_WELL where there is no CO2. With a single well every filled cell is 0.
+    pub fn well_attribution(&self) -> Array3<i16> {
+        self.wells.to_dense(&self.snapshots)
+    }
+
+    /// Every filled cell in the order it was first filled, with its depth, well, snapshot and how its CO2 is held
+    pub fn cell_events(&self) -> Vec<CellEvent> {
+        let mut seen = SparseGrid::new(self.reservoir.dim(), false);
+        let mut events = Vec::new();
+        for (fill_order, &cell) in self.fill_order.iter().enumerate() {
+            let snapshot = self.snapshots.get(cell);
+            if seen.get(cell) || snapshot < 0 {
+                continue;
+            }
+            seen.set(cell, true);
+            let trapping_state = if self.reservoir.history().get(cell) == CellHistory::Imbibition {
+                TrappingState::Residual
+            } else if self.reservoir.saturation().get(cell) == 0.0 {
+                TrappingState::Dissolved
+            } else {
+                TrappingState::Structural
+            };
+            events.push(CellEvent {
+                cell,
+                depth: self.grid.cell_depth(cell),
+                well_id: self.wells.owner(cell),
+                fill_order,
+                snapshot,
+                trapping_state,
+            });
+        }
+        events
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn cells_filled(&self) -> usize {
+        self.cells_filled
+    }
+
+    /// Fraction of the initial reservoir cells that have been filled, between 0 and 1
+    pub fn fraction_filled(&self) -> f64 {
+        if self.n_reservoir_cells == 0 {
+            return 1.0;
+        }
+        (self.cells_filled as f64 / self.n_reservoir_cells as f64).min(1.0)
+    }
+
+    /// Number of NaN cells in the input reservoir matrix. These are inactive and never filled.
+    pub fn n_nan_cells(&self) -> usize {
+        self.n_nan_cells
+    }
+
+    /// The size of the compartment around the sources in the reservoir before the first fill. Searches the
+    /// whole grid on every call, so it is a diagnosis to run when a plume stays small, see
+    /// `CompartmentReport::warning`.
+    pub fn source_compartment(&self) -> CompartmentReport {
+        source_compartment(
+            &self.initial_reservoir(),
+            &self.grid,
+            &self.sources,
+            &self.directions,
+        )
+    }
+
+    /// The first cell beyond the lateral limit in the config, if the plume passed it
+    pub fn lateral_exceedance(&self) -> Option<&LateralExceedance> {
+        self.lateral_exceedance.as_ref()
+    }
+
+    /// The cell that left the containment and ended the simulation, in strict containment mode
+    pub fn containment_violation(&self) -> Option<&ContainmentViolation> {
+        self.containment_violation.as_ref()
+    }
+
+    /// The first broken invariant, which ended the simulation, in paranoid mode
+    pub fn invariant_violation(&self) -> Option<&InvariantViolation> {
+        self.invariant_violation.as_ref()
+    }
+
+    /// The mass-conservation ledger. Empty unless the config enables the audit.
+    pub fn mass_ledger(&self) -> &MassLedger {
+        &self.ledger
+    }
+
+    /// The index of the snapshot currently being recorded
+    pub fn snapshot_index(&self) -> i32 {
+        self.snapshots_counter
+    }
+
+    /// The z index at which the first well is currently injecting
+    pub fn current_depth_index(&self) -> usize {
+        self.current_zi[0]
+    }
+
+    /// Number of (x, y) columns containing CO2
+    pub fn footprint_cells(&self) -> usize {
+        self.fill_order
+            .iter()
+            .map(|&(x, y, _)| (x, y))
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    /// Number of cells holding CO2 so far. Unlike `cells_filled`, a cell filled again after the plume left it
+    /// counts once.
+    pub fn plume_cells(&self) -> usize {
+        self.snapshots
+            .iter()
+            .filter(|&(_, snapshot)| snapshot >= 0)
+            .count()
+    }
+
+    /// Number of cells waiting in the front, summed over the fronts of the perforated layers
+    pub fn frontier_size(&self) -> usize {
+        self.queue.len()
+            + self
+                .layer_fronts
+                .iter()
+                .map(|front| front.queue.len())
+                .sum::<usize>()
+    }
+
+    /// Fraction of the reservoir cells of every z layer that hold CO2 so far, see `LayerStatistics::fraction_used`
+    pub fn layer_fill_fractions(&self) -> Array1<f64> {
+        self.layer_statistics()
+            .iter()
+            .map(LayerStatistics::fraction_used)
+            .collect()
+    }
+
+    /// Whether the cell holds CO2. Panics if the cell is outside the grid.
+    pub fn is_filled(&self, cell: (usize, usize, usize)) -> bool {
+        let (nx, ny, nz) = self.snapshots.dim();
+        assert!(
+            cell.0 < nx && cell.1 < ny && cell.2 < nz,
+            "cell {:?} is outside the grid",
+            cell
+        );
+        self.snapshots.get(cell) >= 0
+    }
+
+    /// The shallowest and deepest z index containing CO2, if any
+    pub fn plume_depth_range(&self) -> Option<(usize, usize)> {
+        self.fill_order
+            .iter()
+            .fold(None, |range, &(_, _, z)| match range {
+                None => Some((z, z)),
+                Some((top, bottom)) => Some((top.min(z), bottom.max(z))),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aquifer::AquiferFlow;
+    use crate::breach::StressCriterion;
+    use crate::cell_state::RockType;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_CO2, VELOCITY_RESERVOIR};
+    use crate::datastucture::{DepthOrderedQueue, FrontQueue};
+    use crate::eos::DensityTable;
+    use crate::thermal::ThermalZone;
+    use numpy::ndarray::{s, Array1, Array2, Array3};
+
+    fn make_test_reservoir(nx: usize, ny: usize, nz: usize, fill: f64) -> Array3<f64> {
+        Array3::<f64>::from_elem((nx, ny, nz), fill)
+    }
+
+    fn make_test_cells(nx: usize, ny: usize, nz: usize, fill: CellState) -> Array3<CellState> {
+        Array3::<CellState>::from_elem((nx, ny, nz), fill)
+    }
+
+    fn make_test_state(nx: usize, ny: usize, nz: usize, fill: CellState) -> ReservoirState {
+        ReservoirState::from_cell_states(&make_test_cells(nx, ny, nz, fill).view())
+    }
+
+    /// A reservoir of the given shape under a caprock at z = 0
+    fn layered_reservoir((nx, ny, nz): (usize, usize, usize)) -> Array3<f64> {
+        let mut reservoir = make_test_reservoir(nx, ny, nz, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        reservoir
+    }
+
+    /// A simulation of the wells in the reservoir, with its layers 1 m apart from a depth of 0 and no bedrock
+    fn try_layered(
+        reservoir: &Array3<f64>,
+        sources: &[(usize, usize, usize)],
+        config: &SimulationConfig,
+    ) -> Result<Simulation, SimulationError> {
+        let (nx, ny, nz) = reservoir.dim();
+        Simulation::try_new_with_wells(
+            reservoir.view(),
+            Array1::from_iter((0..nz).map(|z| z as f64)).view(),
+            Array2::zeros((nx, ny)).view(),
+            sources,
+            config,
+        )
+    }
+
+    /// The simulation of the source in the reservoir run to the end, see `try_layered`
+    fn run_layered(
+        reservoir: &Array3<f64>,
+        source: (usize, usize, usize),
+        config: &SimulationConfig,
+    ) -> Simulation {
+        let mut simulation = try_layered(reservoir, &[source], config).unwrap();
+        simulation.run();
+        simulation
+    }
+
+    #[test]
+    #[should_panic(expected = "Source must be in reservoir")]
+    fn test_validate_initial_position_panics_if_not_reservoir() {
+        let reservoir = make_test_state(3, 3, 3, CellState::Caprock);
+        check_initial_position(&reservoir, (1, 1, 1)).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Source must be just below caprock")]
+    fn test_validate_initial_position_panics_if_not_below_caprock() {
+        let mut reservoir = make_test_cells(3, 3, 3, CellState::Reservoir);
+        reservoir[[1, 1, 0]] = CellState::Reservoir; // not caprock above
+        let reservoir = ReservoirState::from_cell_states(&reservoir.view());
+        check_initial_position(&reservoir, (1, 1, 1)).unwrap();
+    }
+
+    #[test]
+    fn test_compute_snapshot_interval() {
+        let reservoir = make_test_state(2, 2, 2, CellState::Reservoir);
+        assert_eq!(compute_snapshot_interval(&reservoir, 4), 2); // 8/4 = 2
+        assert_eq!(compute_snapshot_interval(&reservoir, 20), 1); // max(1, ..)
+    }
+
+    #[test]
+    fn test_try_to_fill_cell_with_co2_fills_correctly() {
+        let mut reservoir = make_test_cells(2, 2, 2, CellState::Reservoir);
+        reservoir[[0, 0, 0]] = CellState::Caprock; // caprock above (0,0,1)
+        let mut reservoir = ReservoirState::from_cell_states(&reservoir.view());
+        let mut snapshots = SparseGrid::new((2, 2, 2), -1);
+        let mut snapshots_counter = 0;
+        let mut cells_filled_since_snapshot = 0;
+
+        try_to_fill_cell_with_co2(
+            &mut reservoir,
+            &mut snapshots,
+            (0, 0, 1),
+            &mut snapshots_counter,
+            &mut cells_filled_since_snapshot,
+            1,
+        );
+
+        assert_eq!(reservoir.state((0, 0, 1)), CellState::Co2);
+        assert_eq!(snapshots.get((0, 0, 1)), 0);
+        assert_eq!(snapshots_counter, 1); // snapshot interval hit
+    }
+
+    #[test]
     fn test_add_to_8_connected_neighbors() {
-        let mut reservoir = make_test_reservoir(3, 3, 1, VELOCITY_RESERVOIR);
-        reservoir[[1, 1, 0]] = VELOCITY_CO2; // already filled
+        let mut reservoir = make_test_cells(3, 3, 1, CellState::Reservoir);
+        reservoir[[1, 1, 0]] = CellState::Co2; // already filled
+        let reservoir = ReservoirState::from_cell_states(&reservoir.view());
         let depths = Array1::from(vec![0.0]);
         let mut queue = DepthOrderedQueue::new();
         let mut added = false;
+        let grid = RegularGrid::new(3, 3, depths.view());
 
-        add_to_8_connected_neighbors(
+        add_to_lateral_neighbors(
             &mut queue,
             &reservoir,
-            &depths.view(),
+            &grid,
             (1, 1, 0),
-            (3, 3, 1),
-            &mut added,
+            &SPREAD_DIRECTIONS,
+            &|cell| grid.cell_depth(cell),
+            &mut |_| added = true,
         );
 
         // Should add some neighbors (all empty)
@@ -315,27 +2385,1369 @@ mod tests {
     }
 
     #[test]
-    fn test_try_to_break_caprock() {
-        let mut reservoir = make_test_reservoir(2, 2, 3, VELOCITY_RESERVOIR);
+    fn test_warm_start_fills_below_the_initial_plume() {
+        let reservoir = layered_reservoir((5, 1, 4));
+        let mut initial_plume = Array3::from_elem((5, 1, 4), false);
+        initial_plume.slice_mut(s![.., .., 1]).fill(true);
+        // The earlier run broke through the caprock above the well
+        initial_plume[[2, 0, 0]] = true;
+        let config = SimulationConfig {
+            max_column_height: None,
+            initial_plume: Some(initial_plume),
+            ..Default::default()
+        };
+        let simulation = run_layered(&reservoir, (2, 0, 1), &config);
+
+        let snapshots = simulation.snapshots();
+        assert_eq!(simulation.cells_filled(), 10);
+        assert!(snapshots.slice(s![.., .., ..2]).iter().all(|&s| s == -1));
+        assert!(snapshots.slice(s![.., .., 2..]).iter().all(|&s| s >= 0));
+        assert_eq!(simulation.fill_order()[0], (2, 0, 2));
+        let velocities = simulation.reservoir_matrix();
+        assert_eq!(velocities[[2, 0, 0]], VELOCITY_CO2);
+        assert_eq!(velocities[[1, 0, 0]], VELOCITY_CAPROCK);
+        assert_eq!(velocities[[0, 0, 1]], VELOCITY_CO2);
+
+        let mismatched = SimulationConfig {
+            initial_plume: Some(Array3::from_elem((5, 1, 3), false)),
+            ..Default::default()
+        };
+        assert!(matches!(
+            try_layered(&reservoir, &[(2, 0, 1)], &mismatched),
+            Err(SimulationError::ShapeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_mid_run_inspection() {
+        let mut simulation = try_layered(
+            &layered_reservoir((5, 5, 4)),
+            &[(2, 2, 1)],
+            &SimulationConfig::default(),
+        )
+        .unwrap();
+        simulation.advance(10);
+        assert_eq!(simulation.plume_cells(), 10);
+        // The front holds the rest of the top layer
+        assert_eq!(simulation.frontier_size(), 15);
+        assert_eq!(
+            simulation.layer_fill_fractions(),
+            Array1::from(vec![0.0, 0.4, 0.0, 0.0])
+        );
+        assert!(simulation.is_filled((2, 2, 1)));
+        assert!(!simulation.is_filled((2, 2, 3)));
+
+        simulation.run();
+        assert_eq!(simulation.plume_cells(), 75);
+        assert_eq!(simulation.frontier_size(), 0);
+        assert_eq!(
+            simulation.layer_fill_fractions(),
+            Array1::from(vec![0.0, 1.0, 1.0, 1.0])
+        );
+    }
+
+    #[test]
+    fn test_simulation_step_matches_run() {
+        let reservoir = layered_reservoir((5, 5, 4));
+        let depths = Array1::from(vec![0.0, 1.0, 2.0, 3.0]);
+        let bedrock_indices = Array2::<usize>::zeros((5, 5));
+        let config = SimulationConfig::default();
+
+        let expected = run_injection_simulation(
+            reservoir.view(),
+            depths.view(),
+            bedrock_indices.view(),
+            (2, 2, 1),
+            &config,
+        );
+
+        let mut simulation = try_layered(&reservoir, &[(2, 2, 1)], &config).unwrap();
+        assert!(simulation.advance(10));
+        assert_eq!(simulation.cells_filled(), 10);
+        assert_eq!(simulation.footprint_cells(), 10);
+
+        simulation.run();
+        assert!(simulation.is_finished());
+        assert!(!simulation.step());
+        assert_eq!(simulation.cells_filled(), 75);
+        assert_eq!(simulation.plume_depth_range(), Some((1, 3)));
+        assert_eq!(simulation.snapshots(), expected);
+        assert_eq!(simulation.fill_order().len(), 75);
+        assert_eq!(simulation.fill_order()[0], (2, 2, 1));
+        assert_eq!(simulation.plume(), Plume::from_dense(&expected.view()));
+        assert_eq!(simulation.plume().volume(), 75);
+
+        let outputs = simulation.into_outputs();
+        assert_eq!(outputs.snapshots, expected);
+        assert_eq!(outputs.saturation.iter().filter(|&&s| s == 1.0).count(), 75);
+        assert_eq!(outputs.velocity_model[[2, 2, 1]], VELOCITY_CO2);
+    }
+
+    #[test]
+    fn test_queue_kinds_give_the_same_result() {
+        use crate::datastucture::QueueKind;
+
+        let mut reservoir = make_test_reservoir(6, 5, 6, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        reservoir.slice_mut(s![1..5, .., 3]).fill(VELOCITY_CAPROCK);
+        let depths = Array1::from_iter((0..6).map(|z| z as f64 * 2.5));
+        let bedrock_indices = Array2::<usize>::zeros((6, 5));
+
+        let results: Vec<Array3<i32>> = [
+            QueueKind::DepthOrdered,
+            QueueKind::Bucket,
+            QueueKind::BinaryHeap,
+        ]
+        .into_iter()
+        .map(|queue| {
+            let config = SimulationConfig {
+                max_column_height: Some(2),
+                total_snapshots: 10,
+                queue,
+                ..Default::default()
+            };
+            run_injection_simulation(
+                reservoir.view(),
+                depths.view(),
+                bedrock_indices.view(),
+                (2, 2, 4),
+                &config,
+            )
+        })
+        .collect();
+
+        assert_eq!(results[0], results[1]);
+        assert_eq!(results[0], results[2]);
+    }
+
+    #[test]
+    fn test_simulation_on_corner_point_grid() {
+        use crate::grid::CornerPointGrid;
+
+        let mut reservoir = make_test_reservoir(4, 3, 3, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        // The layers dip towards larger x, and column x = 3 is inactive
+        let cell_depths = Array3::from_shape_fn((4, 3, 3), |(x, _, z)| (10 * z + x) as f64);
+        let mut active = Array3::from_elem((4, 3, 3), true);
+        active.slice_mut(s![3, .., ..]).fill(false);
+        let grid = CornerPointGrid::new(cell_depths, active).unwrap();
+
+        let mut simulation = Simulation::try_with_grid(
+            reservoir.view(),
+            grid,
+            Array2::<usize>::zeros((4, 3)).view(),
+            (1, 1, 1),
+            &SimulationConfig::default(),
+        )
+        .unwrap();
+        simulation.run();
+
+        // Every active reservoir cell is filled, shallowest first within the top layer
+        assert_eq!(simulation.cells_filled(), 3 * 3 * 2);
+        assert!(simulation.fill_order().iter().all(|&(x, _, _)| x < 3));
+        assert_eq!(
+            simulation.fill_order()[..4]
+                .iter()
+                .filter(|c| c.0 == 0)
+                .count(),
+            3
+        );
+
+        let config = SimulationConfig {
+            queue: QueueKind::Bucket,
+            ..Default::default()
+        };
+        assert!(Simulation::try_with_grid(
+            reservoir.view(),
+            simulation.grid.clone(),
+            Array2::<usize>::zeros((4, 3)).view(),
+            (1, 1, 1),
+            &config,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_simulation_records_breach_events() {
+        let mut reservoir = make_test_reservoir(3, 3, 6, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 1]).fill(VELOCITY_CAPROCK);
+        let config = SimulationConfig {
+            max_column_height: Some(2),
+            ..Default::default()
+        };
+        let simulation = run_layered(&reservoir, (1, 1, 2), &config);
+
+        let events = simulation.breach_events();
+        assert!(!events.is_empty());
+        assert!(events.iter().all(|e| e.cell.2 == 1));
+        assert_eq!(
+            simulation.snapshot_cell_counts().sum(),
+            simulation.cells_filled()
+        );
+    }
+
+    #[test]
+    fn test_rewind_before_a_breach() {
+        let mut reservoir = make_test_reservoir(3, 3, 6, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 1]).fill(VELOCITY_CAPROCK);
+        let config = SimulationConfig {
+            max_column_height: Some(2),
+            total_snapshots: 20,
+            ..Default::default()
+        };
+        let simulation = run_layered(&reservoir, (1, 1, 2), &config);
+        // The cell that starts a snapshot can break the caprock, so go back one more snapshot
+        let before_breach = simulation.breach_events()[0].snapshot_index - 1;
+
+        let mut rewound = simulation.rewound(before_breach).unwrap();
+        assert_eq!(rewound.snapshot_index(), before_breach);
+        assert!(rewound.breach_events().is_empty());
+        assert!(simulation.fill_order().starts_with(rewound.fill_order()));
+        let expected = simulation
+            .snapshots()
+            .mapv(|s| if s < before_breach { s } else { -1 });
+        assert_eq!(rewound.snapshots(), expected);
+
+        // Running on with the same parameters gives the original run, and without breaking the caprock none breaks
+        let mut replay = rewound.clone();
+        replay.run();
+        assert_eq!(replay.snapshots(), simulation.snapshots());
+        rewound
+            .update_config(SimulationConfig {
+                max_column_height: None,
+                ..config.clone()
+            })
+            .unwrap();
+        rewound.run();
+        assert!(rewound.breach_events().is_empty());
+        assert!(rewound.cells_filled() < simulation.cells_filled());
+
+        let other_queue = SimulationConfig {
+            total_snapshots: 5,
+            ..config
+        };
+        assert!(rewound.update_config(other_queue).is_err());
+        assert!(simulation.rewound(simulation.snapshot_index() + 1).is_err());
+    }
+
+    #[test]
+    fn test_edited_cells_match_a_full_rerun() {
+        let mut reservoir = layered_reservoir((8, 8, 4));
+        let config = SimulationConfig {
+            max_column_height: None,
+            total_snapshots: 20,
+            ..Default::default()
+        };
+        let full_run = |reservoir: &Array3<f64>| run_layered(reservoir, (1, 1, 1), &config);
+        let mut simulation = try_layered(&reservoir, &[(1, 1, 1)], &config).unwrap();
+
+        // An edit away from the plume is applied in place
+        simulation.advance(10);
+        let mut edited = simulation
+            .with_edited_cells(&[((7, 7, 3), VELOCITY_CAPROCK)])
+            .unwrap();
+        assert_eq!(edited.fill_order(), simulation.fill_order());
+        edited.run();
+        reservoir[[7, 7, 3]] = VELOCITY_CAPROCK;
+        let expected = full_run(&reservoir);
+        assert_eq!(edited.fill_order(), expected.fill_order());
+        assert_eq!(edited.snapshots(), expected.snapshots());
+
+        // An edit the plume has passed invalidates the fill order from the snapshot before it reached the edit
+        let mut edited = expected
+            .with_edited_cells(&[((6, 6, 2), VELOCITY_CAPROCK)])
+            .unwrap();
+        assert!(edited.cells_filled() > 0);
+        assert!(edited.cells_filled() < expected.cells_filled());
+        assert!(expected.fill_order().starts_with(edited.fill_order()));
+        edited.run();
+        reservoir[[6, 6, 2]] = VELOCITY_CAPROCK;
+        let expected = full_run(&reservoir);
+        assert_eq!(edited.fill_order(), expected.fill_order());
+        assert_eq!(edited.snapshots(), expected.snapshots());
+
+        assert!(edited
+            .with_edited_cells(&[((8, 0, 1), VELOCITY_CAPROCK)])
+            .is_err());
+        assert!(edited
+            .with_edited_cells(&[((3, 3, 1), VELOCITY_CO2)])
+            .is_err());
+        // The source must stay below caprock
+        assert!(edited
+            .with_edited_cells(&[((1, 1, 0), VELOCITY_RESERVOIR)])
+            .is_err());
+    }
+
+    #[test]
+    fn test_edited_cells_within_a_stencil_jump_of_the_plume() {
+        use crate::stencil::Stencil;
+
+        // The front jumps three cells along x and y, so the caprock cell at (4, 1) is no closer than that to
+        // any filled cell, but opening it lets the front reach it from the source
+        let mut reservoir = layered_reservoir((8, 8, 2));
+        reservoir[[4, 1, 1]] = VELOCITY_CAPROCK;
+        let config = SimulationConfig {
+            max_column_height: None,
+            total_snapshots: 5,
+            stencil: Some(Stencil::weighted(vec![
+                ((0, 0, -1), 0.0),
+                ((3, 0, 0), 0.5),
+                ((-3, 0, 0), 0.5),
+                ((0, 3, 0), 0.5),
+                ((0, -3, 0), 0.5),
+            ])),
+            ..Default::default()
+        };
+        let simulation = run_layered(&reservoir, (1, 1, 1), &config);
+        assert!(!simulation.fill_order().contains(&(4, 1, 1)));
+
+        let mut edited = simulation
+            .with_edited_cells(&[((4, 1, 1), VELOCITY_RESERVOIR)])
+            .unwrap();
+        edited.run();
+        reservoir[[4, 1, 1]] = VELOCITY_RESERVOIR;
+        let expected = run_layered(&reservoir, (1, 1, 1), &config);
+        assert!(expected.fill_order().contains(&(4, 1, 1)));
+        assert_eq!(edited.fill_order(), expected.fill_order());
+        assert_eq!(edited.snapshots(), expected.snapshots());
+    }
+
+    #[test]
+    fn test_rewind_points_restore_the_run_without_replaying_it() {
+        let reservoir = layered_reservoir((8, 8, 4));
+        let config = SimulationConfig {
+            max_column_height: None,
+            total_snapshots: 20,
+            rewind_points: true,
+            ..Default::default()
+        };
+        let new_simulation = || try_layered(&reservoir, &[(1, 1, 1)], &config).unwrap();
+
+        // A well that joins the run is there from the start of a replay, which then diverges, so only the kept
+        // copies can rewind past it
+        let mut simulation = new_simulation();
+        simulation.advance(20);
+        simulation.add_well((6, 6, 1)).unwrap();
+        simulation.run();
+        let mut replayed = simulation.clone();
+        replayed.rewind_points.clear();
+        assert!(replayed.rewound(1).is_err());
+
+        let mut rewound = simulation.rewound(1).unwrap();
+        assert_eq!(rewound.snapshot_index(), 1);
+        assert_eq!(rewound.sources(), [(1, 1, 1)]);
+        assert!(simulation.fill_order().starts_with(rewound.fill_order()));
+        assert_eq!(rewound.rewound(0).unwrap().cells_filled(), 0);
+        rewound.run();
+        let single_well = run_layered(&reservoir, (1, 1, 1), &config);
+        assert_eq!(rewound.snapshots(), single_well.snapshots());
+
+        // The copies are edited like a replay on the edited grid
+        let mut edited = single_well
+            .with_edited_cells(&[((6, 6, 2), VELOCITY_CAPROCK)])
+            .unwrap();
+        let mut replayed = single_well.clone();
+        replayed.rewind_points.clear();
+        let mut replayed = replayed
+            .with_edited_cells(&[((6, 6, 2), VELOCITY_CAPROCK)])
+            .unwrap();
+        assert_eq!(edited.fill_order(), replayed.fill_order());
+        assert_eq!(
+            edited.reservoir.count(CellState::Caprock),
+            replayed.reservoir.count(CellState::Caprock)
+        );
+        edited.run();
+        replayed.run();
+        assert_eq!(edited.snapshots(), replayed.snapshots());
+    }
+
+    #[test]
+    fn test_breach_geometry() {
+        let mut reservoir = make_test_reservoir(5, 5, 6, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 1]).fill(VELOCITY_CAPROCK);
+        // The cells that broke together with the first one
+        let first_breach = |breach_geometry| {
+            let config = SimulationConfig {
+                max_column_height: Some(2),
+                breach_geometry,
+                ..Default::default()
+            };
+            let simulation = run_layered(&reservoir, (2, 2, 2), &config);
+            let events = simulation.breach_events();
+            events
+                .iter()
+                .filter(|e| e.cells_filled == events[0].cells_filled)
+                .map(|e| e.cell)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(first_breach(BreachGeometry::SingleCell), vec![(2, 2, 1)]);
+        let chimney = first_breach(BreachGeometry::Chimney { width: 3 });
+        assert_eq!(chimney.len(), 9);
+        assert!(chimney
+            .iter()
+            .all(|&(x, y, _)| (1..=3).contains(&x) && (1..=3).contains(&y)));
+        // The layer below the seal is full, so the whole seal fails
+        let threshold = first_breach(BreachGeometry::Threshold {
+            min_column_height: 1,
+        });
+        assert_eq!(threshold.len(), 25);
+        assert_eq!(
+            first_breach(BreachGeometry::Threshold {
+                min_column_height: 2
+            }),
+            vec![(2, 2, 1)]
+        );
+        assert!(BreachGeometry::Chimney { width: 2 }.validate().is_err());
+    }
+
+    #[test]
+    fn test_secondary_accumulations() {
+        // Two seals above the source, so the CO2 that breaks the lower seal pools under the upper one
+        let mut reservoir = layered_reservoir((3, 3, 6));
+        reservoir.slice_mut(s![.., .., 2]).fill(VELOCITY_CAPROCK);
+        let config = SimulationConfig {
+            max_column_height: Some(2),
+            ..Default::default()
+        };
+        let simulation = run_layered(&reservoir, (1, 1, 3), &config);
+
+        let accumulations = simulation.accumulations();
+        assert_eq!(accumulations.len(), simulation.breach_events().len() + 1);
+        assert!(accumulations[0].breached());
+        let secondary = &accumulations[1];
+        assert_eq!(secondary.fed_by, Some(0));
+        assert_eq!(secondary.parent, Some(0));
+        assert!(secondary.cells > 1);
+        assert_eq!(
+            accumulations.iter().map(|a| a.cells).sum::<usize>(),
+            simulation.cells_filled()
+        );
+    }
+
+    #[test]
+    fn test_breaches_reseal() {
+        use crate::breach::Resealing;
+
+        let mut reservoir = layered_reservoir((3, 3, 6));
+        reservoir.slice_mut(s![.., .., 2]).fill(VELOCITY_CAPROCK);
+        let run = |resealing| {
+            let config = SimulationConfig {
+                max_column_height: Some(2),
+                resealing,
+                ..Default::default()
+            };
+            run_layered(&reservoir, (1, 1, 3), &config)
+        };
+
+        let open = run(None);
+        assert!(open.breach_events().iter().all(|e| e.resealed_at.is_none()));
+
+        // The first breach closes two cells later, and the growing column below breaks it again
+        let resealing = run(Some(Resealing { cells: 2 }));
+        let events = resealing.breach_events();
+        assert_eq!(events[0].resealed_at, Some(events[0].cells_filled + 2));
+        let rebroken = events.iter().filter(|e| e.cell == events[0].cell).count();
+        assert_eq!(rebroken, 2);
+        // The broken cells that filled with CO2 stay open
+        assert!(events[1..].iter().all(|e| e.resealed_at.is_none()));
+        assert!(Resealing { cells: 0 }.validate().is_err());
+    }
+
+    #[test]
+    fn test_breaches_reseal_on_the_co2_through_them() {
+        use crate::breach::Resealing;
+
+        // Two wells in compartments split by a wall at x = 1. The seal at z = 2 has a pocket of two cells above
+        // the first well and three cells above the second.
+        let mut reservoir = layered_reservoir((7, 1, 5));
+        reservoir.slice_mut(s![.., .., 2]).fill(VELOCITY_CAPROCK);
+        reservoir.slice_mut(s![3, .., ..]).fill(VELOCITY_CAPROCK);
+        reservoir[[2, 0, 1]] = VELOCITY_CAPROCK;
+        let config = SimulationConfig {
+            max_column_height: Some(2),
+            resealing: Some(Resealing { cells: 3 }),
+            ..Default::default()
+        };
+        let mut simulation = try_layered(&reservoir, &[(1, 0, 3), (5, 0, 3)], &config).unwrap();
+        simulation.run();
+
+        // The second seal breaks three fills after the first, while the first is open
+        let events = simulation.breach_events();
+        assert_eq!(events[0].cell, (1, 0, 2));
+        assert_eq!(events[1].cell, (5, 0, 2));
+        assert_eq!(events[1].cells_filled, events[0].cells_filled + 3);
+        // The second breach closes once the three cells above it are full, but the first only led two cells
+        // into its pocket, so the fills under the other well do not close it
+        assert_eq!(events[1].resealed_at, Some(events[1].cells_filled + 3));
+        assert_eq!(events[0].resealed_at, None);
+        let accumulations = simulation.accumulations();
+        assert_eq!(accumulations[1].cells, 2);
+        assert_eq!(accumulations[2].cells, 3);
+    }
+
+    #[test]
+    fn test_stress_breach_criterion() {
+        let mut reservoir = make_test_reservoir(3, 3, 6, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 1]).fill(VELOCITY_CAPROCK);
+        let depths = Array1::from_iter((0..6).map(|z| 1000.0 + 10.0 * z as f64));
+        let bedrock_indices = Array2::<usize>::zeros((3, 3));
+        let run = |config: &SimulationConfig| {
+            let mut simulation = Simulation::new(
+                reservoir.view(),
+                depths.view(),
+                bedrock_indices.view(),
+                (1, 1, 2),
+                config,
+            );
+            simulation.run();
+            simulation.breach_events().to_vec()
+        };
+
+        // Caprock at 1010 m holds a CO2 column of about 15 m, so it breaks at two cells like the column height criterion
+        let stress = StressCriterion {
+            min_horizontal_stress_gradient: 10072.8,
+            hydrostatic_gradient: 10000.0,
+            density_difference: 500.0,
+            overpressure: 0.0,
+        };
+        let stress_events = run(&SimulationConfig {
+            max_column_height: None,
+            breach_criterion: BreachCriterion::Stress(stress.clone()),
+            ..Default::default()
+        });
+        let column_height_events = run(&SimulationConfig {
+            max_column_height: Some(2),
+            ..Default::default()
+        });
+        assert!(!stress_events.is_empty());
+        assert_eq!(stress_events, column_height_events);
+
+        // A strong caprock never breaks
+        let strong = run(&SimulationConfig {
+            breach_criterion: BreachCriterion::Stress(StressCriterion {
+                min_horizontal_stress_gradient: 20000.0,
+                ..stress
+            }),
+            ..Default::default()
+        });
+        assert!(strong.is_empty());
+    }
+
+    #[test]
+    fn test_mass_accounting_stops_at_mass_limit() {
+        let mut reservoir = make_test_reservoir(3, 3, 4, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        let depths = Array1::from(vec![1000.0, 1010.0, 1020.0, 1030.0]);
+        let bedrock_indices = Array2::<usize>::zeros((3, 3));
+        // 2 m³ per cell, 500 kg/m³ in the top layer and 1000 kg/m³ below
+        let density = DensityTable::new(vec![(1010.0, 500.0), (1020.0, 1000.0)]).unwrap();
+        let config = SimulationConfig {
+            max_column_height: None,
+            mass_accounting: Some(MassAccounting {
+                cell_volume: 2.0,
+                density,
+                max_injected_mass: Some(12.0),
+            }),
+            ..Default::default()
+        };
+
+        let mut simulation = Simulation::new(
+            reservoir.view(),
+            depths.view(),
+            bedrock_indices.view(),
+            (1, 1, 1),
+            &config,
+        );
+        simulation.run();
+
+        // The nine cells of the top layer hold 9 t, so the limit is reached in the layer below
+        assert_eq!(simulation.cells_filled(), 11);
+        assert_eq!(simulation.injected_mass(), Some(13.0));
+        assert_eq!(simulation.snapshot_masses().unwrap().sum(), 13.0);
+    }
+
+    #[test]
+    fn test_aquifer_flow_biases_spreading_downstream() {
+        use crate::datastucture::QueueKind;
+
+        let reservoir = layered_reservoir((5, 5, 3));
+        let config = SimulationConfig {
+            max_column_height: None,
+            aquifer_flow: Some(AquiferFlow {
+                hydraulic_gradient: (0.001, 0.0),
+                cell_size: (50.0, 50.0),
+                density_ratio: 2.0,
+                drift_rate: 0.0,
+            }),
+            ..Default::default()
+        };
+        let mut simulation = try_layered(&reservoir, &[(2, 2, 1)], &config).unwrap();
+        simulation.advance(4);
+        // The front runs downstream before it spreads anywhere else
+        assert!(simulation.fill_order()[1..].iter().all(|&(x, _, _)| x > 2));
+
+        let bucket = SimulationConfig {
+            queue: QueueKind::Bucket,
+            ..config
+        };
+        assert!(try_layered(&reservoir, &[(2, 2, 1)], &bucket).is_err());
+    }
+
+    #[test]
+    fn test_stochastic_spreading_varies_the_front() {
+        use crate::ensemble::run_spreading_ensemble;
+        use crate::spreading::StochasticSpreading;
+
+        let reservoir = layered_reservoir((9, 9, 2));
+        let depths = Array1::from(vec![0.0, 1.0]);
+        let bedrock_indices = Array2::<usize>::zeros((9, 9));
+        let config = SimulationConfig {
+            max_column_height: None,
+            total_snapshots: 81,
+            stochastic_spreading: Some(StochasticSpreading {
+                probabilities: [0.3, 0.3, 0.3, 0.3],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let members = run_spreading_ensemble(
+            reservoir.view(),
+            depths.view(),
+            bedrock_indices.view(),
+            (4, 4, 1),
+            &config,
+            &[1, 2, 1],
+        );
+        // Failed attempts are retried, so every member fills the layer, but in a different order
+        for member in &members {
+            assert!(member.slice(s![.., .., 1]).iter().all(|&s| s >= 0));
+        }
+        assert_ne!(members[0], members[1]);
+        assert_eq!(members[0], members[2]);
+
+        // The plume never spreads in a direction with zero probability
+        let config = SimulationConfig {
+            stochastic_spreading: Some(StochasticSpreading {
+                probabilities: [1.0, 0.0, 0.5, 0.5],
+                ..Default::default()
+            }),
+            ..config
+        };
+        let snapshots = run_layered(&reservoir, (4, 4, 1), &config).snapshots();
+        assert!(snapshots.slice(s![..4, .., 1]).iter().all(|&s| s == -1));
+        assert!(snapshots.slice(s![4.., .., 1]).iter().all(|&s| s >= 0));
+    }
+
+    #[test]
+    fn test_perforation_limits_the_injected_layers() {
+        use crate::perforation::Perforation;
+
+        let mut reservoir = layered_reservoir((3, 1, 6));
+        reservoir[[0, 0, 3]] = VELOCITY_CAPROCK;
+        let run = |perforation| {
+            let config = SimulationConfig {
+                max_column_height: None,
+                perforation,
+                ..Default::default()
+            };
+            run_layered(&reservoir, (1, 0, 1), &config).snapshots()
+        };
+
+        // Without a perforation every layer below the source is injected into, and only the caprock stays empty
+        let filled = |snapshots: &Array3<i32>| snapshots.iter().filter(|&&s| s >= 0).count();
+        assert_eq!(filled(&run(None)), 14);
+
+        // The perforated layers fill from their well, but the front does not move below the interval
+        let snapshots = run(Some(Perforation::interval(1, 2)));
+        assert!(snapshots.slice(s![.., .., 1..3]).iter().all(|&s| s >= 0));
+        assert!(snapshots.slice(s![.., .., 3..]).iter().all(|&s| s == -1));
+
+        // The CO2 from a deeper perforation rises into the trap above, but nothing is injected below it
+        let top_only = run(Some(Perforation::new(vec![1])));
+        let skipped = run(Some(Perforation::new(vec![4, 1])));
+        assert_eq!(filled(&top_only), 3);
+        assert!(filled(&skipped) > 3);
+        assert!(skipped.slice(s![.., .., 5]).iter().all(|&s| s == -1));
+
+        let config = SimulationConfig {
+            perforation: Some(Perforation::new(vec![2, 3])),
+            ..Default::default()
+        };
+        assert!(try_layered(&reservoir, &[(1, 0, 1)], &config).is_err());
+    }
+
+    #[test]
+    fn test_split_injection_between_layers() {
+        use crate::perforation::Perforation;
+
+        let mut reservoir = layered_reservoir((6, 1, 4));
+        reservoir.slice_mut(s![.., .., 2]).fill(VELOCITY_CAPROCK);
+        let config = SimulationConfig {
+            max_column_height: None,
+            perforation: Some(Perforation::split(vec![(1, 0.25), (3, 0.75)])),
+            ..Default::default()
+        };
+        let mut simulation = try_layered(&reservoir, &[(2, 0, 1)], &config).unwrap();
+
+        // The thief zone below takes three of every four cells, instead of waiting for the upper layer to fill
+        simulation.advance(8);
+        let lower = simulation.fill_order().iter().filter(|&&(_, _, z)| z == 3);
+        assert_eq!(lower.count(), 6);
+
+        // Once the lower layer is full the upper layer takes the rest
+        simulation.run();
+        let snapshots = simulation.snapshots();
+        assert!(snapshots.slice(s![.., .., 1]).iter().all(|&s| s >= 0));
+        assert!(snapshots.slice(s![.., .., 3]).iter().all(|&s| s >= 0));
+    }
+
+    #[test]
+    fn test_sweep_order_of_the_source_column() {
+        use crate::perforation::{Perforation, SweepOrder};
+
+        let mut reservoir = layered_reservoir((6, 1, 4));
+        reservoir.slice_mut(s![.., .., 2]).fill(VELOCITY_CAPROCK);
+        let fill_order = |sweep_order| {
+            let config = SimulationConfig {
+                max_column_height: None,
+                sweep_order,
+                ..Default::default()
+            };
+            run_layered(&reservoir, (2, 0, 1), &config)
+                .fill_order()
+                .iter()
+                .map(|&(_, _, z)| z)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(fill_order(SweepOrder::TopDown)[..6], [1; 6]);
+        assert_eq!(fill_order(SweepOrder::BottomUp)[..6], [3; 6]);
+        // Both layers grow together instead of one after the other
+        let interleaved = fill_order(SweepOrder::Interleaved);
+        assert!(interleaved[..4].contains(&3));
+        assert!(interleaved[6..].contains(&1));
+        assert_eq!(interleaved.len(), 12);
+
+        let config = SimulationConfig {
+            perforation: Some(Perforation::split(vec![(1, 0.5), (3, 0.5)])),
+            sweep_order: SweepOrder::BottomUp,
+            ..Default::default()
+        };
+        assert!(try_layered(&reservoir, &[(2, 0, 1)], &config).is_err());
+    }
+
+    #[test]
+    fn test_custom_stencil() {
+        use crate::stencil::Stencil;
+
+        let mut reservoir = layered_reservoir((5, 3, 4));
+        reservoir[[1, 1, 2]] = VELOCITY_CAPROCK;
+        // A caprock wall at x = 2 that the built-in rules cannot pass
+        reservoir.slice_mut(s![2, .., 1..]).fill(VELOCITY_CAPROCK);
+        let fill_order = |stencil| {
+            let config = SimulationConfig {
+                max_column_height: None,
+                stencil,
+                ..Default::default()
+            };
+            run_layered(&reservoir, (0, 1, 1), &config)
+                .fill_order()
+                .to_vec()
+        };
+
+        // The stencil of the built-in directions fills the cells in the same order as the built-in rules
+        let default = fill_order(None);
+        assert_eq!(
+            fill_order(Some(Stencil::from_directions(&SPREAD_DIRECTIONS))),
+            default
+        );
+        assert!(default.iter().all(|&(x, _, _)| x < 2));
+
+        // Longer steps along x jump the wall
+        let mut offsets = vec![(0, 0, -1), (1, 0, 0), (-1, 0, 0), (2, 0, 0), (-2, 0, 0)];
+        offsets.extend([(0, 1, 0), (0, -1, 0)]);
+        let long_range = fill_order(Some(Stencil::new(offsets)));
+        assert!(long_range.iter().any(|&(x, _, _)| x > 2));
+
+        assert!(Stencil::new(vec![(0, 0, 0)]).validate().is_err());
+        assert!(Stencil::weighted(vec![((1, 0, 0), f64::NAN)])
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_stencil_cells_keep_their_lowest_weight() {
+        use crate::stencil::Stencil;
+
+        let reservoir = layered_reservoir((4, 2, 2));
+        // Long steps along x cost more than a step along y, and short steps along x cost nothing
+        let stencil =
+            Stencil::weighted(vec![((1, 0, 0), 0.0), ((2, 0, 0), 10.0), ((0, 1, 0), 5.0)]);
+        let config = SimulationConfig {
+            max_column_height: None,
+            stencil: Some(stencil),
+            ..Default::default()
+        };
+        let simulation = run_layered(&reservoir, (0, 0, 1), &config);
+
+        // The long step from the source reaches (2, 0) first, but the short step from (1, 0) reaches it for free,
+        // so it is filled before the row at y = 1
+        assert_eq!(
+            simulation.fill_order()[..4],
+            [(0, 0, 1), (1, 0, 1), (2, 0, 1), (3, 0, 1)]
+        );
+        assert_eq!(simulation.cells_filled(), 8);
+
+        // The bucket queue orders by z index alone, so it would drop the weights
+        let config = SimulationConfig {
+            queue: crate::datastucture::QueueKind::Bucket,
+            ..config
+        };
+        assert!(try_layered(&reservoir, &[(0, 0, 1)], &config).is_err());
+    }
+
+    #[test]
+    fn test_front_ordering_changes_the_fill_order() {
+        use crate::datastucture::QueueKind;
+        use crate::ordering::FrontOrdering;
+
+        // A seal that steps down from x = 2, with the source under its deeper part
+        let mut reservoir = layered_reservoir((12, 1, 4));
+        reservoir.slice_mut(s![2.., .., 1]).fill(VELOCITY_CAPROCK);
+        let fills_first = |front_ordering, first, second| {
+            let config = SimulationConfig {
+                max_column_height: None,
+                front_ordering,
+                ..Default::default()
+            };
+            let simulation = run_layered(&reservoir, (5, 0, 2), &config);
+            let position = |cell| simulation.fill_order().iter().position(|&c| c == cell);
+            position(first) < position(second)
+        };
+
+        // By depth the CO2 rises under the shallow part of the seal as soon as it reaches it
+        assert!(fills_first(FrontOrdering::Depth, (1, 0, 1), (8, 0, 2)));
+        // From the wells it keeps spreading radially
+        assert!(fills_first(
+            FrontOrdering::WellDistance,
+            (9, 0, 2),
+            (1, 0, 1)
+        ));
+        // Under the deeper part of the seal is as favourable as under the shallow part
+        assert!(fills_first(
+            FrontOrdering::SealPotential,
+            (8, 0, 2),
+            (1, 0, 1)
+        ));
+
+        let config = SimulationConfig {
+            front_ordering: FrontOrdering::WellDistance,
+            queue: QueueKind::Bucket,
+            ..Default::default()
+        };
+        assert!(try_layered(&reservoir, &[(5, 0, 2)], &config).is_err());
+    }
+
+    #[test]
+    fn test_seal_potentials_follow_a_breach() {
+        // The seal of the last column is shallower, so its cells are reached while still under it
+        let mut reservoir = layered_reservoir((3, 1, 8));
+        reservoir.slice_mut(s![..2, .., 4]).fill(VELOCITY_CAPROCK);
+        reservoir[[2, 0, 2]] = VELOCITY_CAPROCK;
+        let config = SimulationConfig {
+            max_column_height: Some(2),
+            front_ordering: FrontOrdering::SealPotential,
+            ..Default::default()
+        };
+        let mut simulation = try_layered(&reservoir, &[(0, 0, 5)], &config).unwrap();
+        while simulation.breach_events().is_empty() {
+            simulation.step();
+        }
+        assert_eq!(simulation.breach_events()[0].cell, (2, 0, 2));
+
+        // Below the broken seal the queued cell is measured from the top of the model, so it comes after the
+        // broken cell above it rather than before
+        let mut queued = Vec::new();
+        while let Some(cell) = simulation.queue.pop() {
+            queued.push(cell);
+        }
+        assert_eq!(queued, [(2, 0, 2), (1, 0, 3), (2, 0, 3)]);
+    }
+
+    #[test]
+    fn test_descent_stops_at_the_basement() {
+        let mut reservoir = layered_reservoir((3, 1, 6));
+        reservoir.slice_mut(s![.., .., 2]).fill(VELOCITY_CAPROCK);
+        let run = |basement_indices| {
+            let config = SimulationConfig {
+                max_column_height: None,
+                basement_indices,
+                ..Default::default()
+            };
+            try_layered(&reservoir, &[(1, 0, 1)], &config).map(|mut simulation| {
+                simulation.run();
+                simulation.fill_order().to_vec()
+            })
+        };
+
+        assert!(run(None).unwrap().iter().any(|&(_, _, z)| z >= 3));
+        let fill_order = run(Some(Array2::from_elem((3, 1), 3))).unwrap();
+        assert_eq!(fill_order.len(), 3);
+        assert!(fill_order.iter().all(|&(_, _, z)| z == 1));
+        assert!(run(Some(Array2::from_elem((3, 1), 1))).is_err());
+        assert!(run(Some(Array2::from_elem((2, 1), 3))).is_err());
+    }
+
+    #[test]
+    fn test_thermal_zone_around_the_well() {
+        let config = SimulationConfig {
+            max_column_height: None,
+            mass_accounting: Some(MassAccounting {
+                cell_volume: 1.0,
+                density: DensityTable::new(vec![(0.0, 1000.0)]).unwrap(),
+                max_injected_mass: None,
+            }),
+            thermal_zone: Some(ThermalZone {
+                radius: 0.5,
+                density_factor: 1.5,
+                co2_velocity: 450.0,
+            }),
+            ..Default::default()
+        };
+        let simulation = run_layered(&layered_reservoir((3, 1, 3)), (1, 0, 1), &config);
+
+        // The two cells in the well column hold the denser cold CO2
+        assert_eq!(simulation.cells_filled(), 6);
+        assert_eq!(simulation.injected_mass(), Some(7.0));
+        let velocities = simulation.reservoir_matrix();
+        assert_eq!(velocities[[1, 0, 2]], 450.0);
+        assert_eq!(velocities[[0, 0, 2]], VELOCITY_CO2);
+        assert_eq!(velocities[[1, 0, 0]], VELOCITY_CAPROCK);
+    }
+
+    #[test]
+    fn test_relaxed_cells_are_invaded_again() {
+        let config = SimulationConfig {
+            max_column_height: None,
+            mass_accounting: Some(MassAccounting {
+                cell_volume: 1.0,
+                density: DensityTable::new(vec![(0.0, 1000.0)]).unwrap(),
+                max_injected_mass: None,
+            }),
+            ..Default::default()
+        };
+        let mut simulation =
+            try_layered(&layered_reservoir((1, 1, 4)), &[(0, 0, 1)], &config).unwrap();
+        simulation.advance(2);
+        assert_eq!(simulation.injected_mass(), Some(2.0));
+
+        // The base cell gives up its mobile CO2 and keeps the residual
+        let hysteresis = Hysteresis {
+            residual_saturation: 0.25,
+        };
+        let history = simulation
+            .relax(
+                &ConvectiveDissolution { rate: 0.5 },
+                2,
+                None,
+                Some(&hysteresis),
+            )
+            .unwrap();
+        assert_eq!(history.residual[2], 0.25);
+        let state = simulation.reservoir_state();
+        assert_eq!(state.history().get((0, 0, 2)), CellHistory::Imbibition);
+        assert_eq!(state.state((0, 0, 2)), CellState::Reservoir);
+
+        // The front fills it again, adding only the CO2 above the residual
+        simulation.advance(1);
+        let state = simulation.reservoir_state();
+        assert_eq!(
+            state.history().get((0, 0, 2)),
+            CellHistory::SecondaryDrainage
+        );
+        assert_eq!(state.saturation().get((0, 0, 2)), 1.0);
+        assert_eq!(simulation.injected_mass(), Some(2.75));
+    }
+
+    #[test]
+    fn test_snapshot_policies() {
+        use crate::snapshot_policy::SnapshotPolicy;
+
+        let mut reservoir = make_test_reservoir(3, 3, 6, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 1]).fill(VELOCITY_CAPROCK);
+        let run = |snapshot_policy: SnapshotPolicy| {
+            let config = SimulationConfig {
+                max_column_height: Some(2),
+                snapshot_policy,
+                ..Default::default()
+            };
+            run_layered(&reservoir, (1, 1, 2), &config)
+        };
+
+        let simulation = run(SnapshotPolicy::FillCounts(vec![4, 10]));
+        let counts = simulation.snapshot_cell_counts();
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[0], 4);
+        assert_eq!(counts[1], 6);
+        assert_eq!(counts.sum(), simulation.cells_filled());
+
+        let simulation = run(SnapshotPolicy::OnBreach);
+        let events = simulation.breach_events();
+        assert_eq!(simulation.snapshot_index() as usize, events.len());
+        for (i, event) in events.iter().enumerate() {
+            assert_eq!(event.snapshot_index as usize, i);
+        }
+    }
+
+    #[test]
+    fn test_nothing_leaks_above_the_bedrock() {
+        // The first column is sealed by two caprock cells, while the bedrock of the second is a reservoir cell
+        let mut reservoir = make_test_reservoir(2, 1, 4, VELOCITY_RESERVOIR);
+        reservoir[[0, 0, 0]] = VELOCITY_CAPROCK;
         reservoir[[0, 0, 1]] = VELOCITY_CAPROCK;
+        reservoir[[1, 0, 0]] = VELOCITY_CAPROCK;
+        let depths = Array1::from(vec![0.0, 1.0, 2.0, 3.0]);
+        let bedrock_indices = Array2::from_shape_vec((2, 1), vec![1, 2]).unwrap();
+
+        let mut simulation = Simulation::new(
+            reservoir.view(),
+            depths.view(),
+            bedrock_indices.view(),
+            (0, 0, 2),
+            &SimulationConfig::default(),
+        );
+        simulation.run();
+
+        let snapshots = simulation.snapshots();
+        assert_eq!(snapshots[[1, 0, 1]], -1);
+        assert!(snapshots[[1, 0, 2]] >= 0);
+        assert_eq!(simulation.cell_states()[[1, 0, 1]], CellState::Inactive);
+
+        let err = Simulation::try_new(
+            reservoir.view(),
+            depths.view(),
+            bedrock_indices.view(),
+            (1, 0, 1),
+            &SimulationConfig::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("below the bedrock"));
+    }
+
+    #[test]
+    fn test_well_attribution() {
+        use crate::wells::{MERGED_WELLS, NO_WELL};
+
+        // Two wells at the ends of a row under a caprock. Their fronts meet in the middle.
+        let reservoir = layered_reservoir((5, 1, 3));
+        let config = SimulationConfig {
+            max_column_height: None,
+            ..Default::default()
+        };
+
+        let mut simulation = try_layered(&reservoir, &[(0, 0, 1), (4, 0, 1)], &config).unwrap();
+        simulation.run();
+
+        let attribution = simulation.well_attribution();
+        for z in 1..3 {
+            let row: Vec<i16> = (0..5).map(|x| attribution[[x, 0, z]]).collect();
+            assert_eq!(row, vec![0, 0, MERGED_WELLS, 1, 1]);
+        }
+        assert!(attribution
+            .slice(s![.., .., 0])
+            .iter()
+            .all(|&w| w == NO_WELL));
+        assert_eq!(simulation.cells_filled(), 10);
+
+        // A single well owns everything it fills
+        let simulation = run_layered(&reservoir, (0, 0, 1), &config);
+        assert_eq!(
+            simulation
+                .well_attribution()
+                .iter()
+                .filter(|&&w| w == 0)
+                .count(),
+            10
+        );
+    }
+
+    #[test]
+    fn test_strict_containment_stops_at_first_violation() {
+        use crate::containment::{Containment, ViolationKind};
+
+        let mut reservoir = make_test_reservoir(3, 3, 6, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 1]).fill(VELOCITY_CAPROCK);
+        reservoir.slice_mut(s![.., .., 3]).fill(VELOCITY_CAPROCK);
+        let config = SimulationConfig {
+            max_column_height: Some(1),
+            containment: Some(Containment {
+                primary_caprock_indices: Some(Array2::from_elem((3, 3), 3)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let simulation = run_layered(&reservoir, (1, 1, 4), &config);
+
+        let violation = simulation.containment_violation().unwrap();
+        assert_eq!(violation.kind, ViolationKind::AbovePrimaryCaprock);
+        assert!(violation.cell.2 < 3);
+        assert_eq!(violation.cells_filled, simulation.cells_filled());
+        assert_eq!(*simulation.fill_order().last().unwrap(), violation.cell);
+        assert!(simulation.is_finished());
+    }
+
+    #[test]
+    fn test_paranoid_mode_reports_broken_invariants() {
+        let mut reservoir = make_test_reservoir(5, 5, 8, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 1]).fill(VELOCITY_CAPROCK);
+        reservoir.slice_mut(s![.., .., 4]).fill(VELOCITY_CAPROCK);
+        let config = SimulationConfig {
+            max_column_height: Some(2),
+            total_snapshots: 10,
+            paranoid: true,
+            ..Default::default()
+        };
+        let new_simulation = || try_layered(&reservoir, &[(2, 2, 5)], &config).unwrap();
+
+        // The engine keeps its invariants through breaches
+        let simulation = run_layered(&reservoir, (2, 2, 5), &config);
+        assert!(!simulation.breach_events().is_empty());
+        assert_eq!(simulation.invariant_violation(), None);
+        assert_eq!(simulation.check_invariants(), Ok(()));
+
+        // A filled cell under an emptied one
+        let mut simulation = new_simulation();
+        simulation.advance(40);
+        let &(x, y, z) = simulation
+            .fill_order()
+            .iter()
+            .find(|&&(x, y, z)| z > 0 && simulation.fill_order().contains(&(x, y, z - 1)))
+            .unwrap();
+        simulation.reservoir.unfill((x, y, z - 1));
+        let violation = simulation.check_invariants().unwrap_err();
+        assert_eq!(violation.kind, InvariantKind::FilledBelowEmpty);
+        assert_eq!(violation.cell, (x, y, z));
+
+        // A front that no processed cell could have moved in ends the run at the first fill
+        let mut simulation = new_simulation();
+        simulation.advance(20);
+        simulation.visited = SparseGrid::new(simulation.reservoir.dim(), false);
+        simulation.run();
+        let violation = simulation.invariant_violation().unwrap();
+        assert_eq!(violation.kind, InvariantKind::Co2AboveCaprock);
+        assert_eq!(violation.cells_filled, 21);
+        assert!(simulation.is_finished());
+    }
+
+    #[test]
+    fn test_lateral_limit_flags_or_stops() {
+        use crate::containment::LateralLimit;
+
+        let reservoir = layered_reservoir((9, 1, 3));
+        let run = |stop| {
+            let config = SimulationConfig {
+                max_column_height: None,
+                lateral_limit: Some(LateralLimit {
+                    max_distance: Some(2.0),
+                    max_offsets: None,
+                    stop,
+                }),
+                ..Default::default()
+            };
+            run_layered(&reservoir, (4, 0, 1), &config)
+        };
+
+        let flagged = run(false);
+        let exceedance = flagged.lateral_exceedance().unwrap();
+        assert_eq!(exceedance.distance, 3.0);
+        assert!(exceedance.cells_filled < flagged.cells_filled());
+        assert_eq!(flagged.cells_filled(), 18);
+
+        let stopped = run(true);
+        assert_eq!(stopped.lateral_exceedance(), Some(exceedance));
+        assert_eq!(stopped.cells_filled(), exceedance.cells_filled);
+        assert!(stopped.is_finished());
+    }
+
+    #[test]
+    fn test_audit_ledger_balances() {
+        let mut reservoir = make_test_reservoir(3, 3, 6, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 1]).fill(VELOCITY_CAPROCK);
+        let config = SimulationConfig {
+            max_column_height: Some(2),
+            total_snapshots: 5,
+            audit: true,
+            ..Default::default()
+        };
+        let simulation = run_layered(&reservoir, (1, 1, 2), &config);
+
+        let ledger = simulation.mass_ledger();
+        assert!(!simulation.breach_events().is_empty());
+        assert_eq!(
+            ledger.entries().len(),
+            simulation.snapshot_index() as usize + 1
+        );
+        assert!(ledger.is_balanced());
+        let last = ledger.entries().last().unwrap();
+        assert_eq!(last.injected, simulation.cells_filled());
+    }
+
+    #[test]
+    fn test_audit_counts_only_injected_cells() {
+        // A warm start from a plume along the top layer, which dissolves before the injection goes on
+        let reservoir = layered_reservoir((3, 1, 4));
+        let mut initial_plume = Array3::from_elem((3, 1, 4), false);
+        initial_plume.slice_mut(s![.., .., 1]).fill(true);
+        let config = SimulationConfig {
+            max_column_height: None,
+            total_snapshots: 2,
+            initial_plume: Some(initial_plume),
+            audit: true,
+            ..Default::default()
+        };
+        let mut simulation = try_layered(&reservoir, &[(1, 0, 1)], &config).unwrap();
+        simulation.advance(1);
+        simulation
+            .relax(&ConvectiveDissolution { rate: 1.0 }, 1, None, None)
+            .unwrap();
+        simulation.run();
+
+        // Dissolved cells the front filled again are in the fill order twice
+        let injected_with_co2 = simulation
+            .fill_order()
+            .iter()
+            .filter(|&&cell| simulation.reservoir_state().saturation().get(cell) > 0.0)
+            .collect::<HashSet<_>>()
+            .len();
+        let last = simulation.mass_ledger().entries().last().unwrap();
+        assert_eq!(last.mobile, injected_with_co2);
+    }
+
+    #[test]
+    fn test_unbreakable_caprock() {
+        let mut reservoir = make_test_reservoir(3, 3, 6, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 1]).fill(VELOCITY_CAPROCK);
+        let config = SimulationConfig {
+            max_column_height: None,
+            ..Default::default()
+        };
+        let simulation = run_layered(&reservoir, (1, 1, 2), &config);
+
+        assert!(simulation.breach_events().is_empty());
+        assert_eq!(simulation.cells_filled(), 36);
+        assert!(simulation
+            .snapshots()
+            .slice(s![.., .., ..2])
+            .iter()
+            .all(|&s| s == -1));
+    }
+
+    #[test]
+    fn test_nan_cells_are_inactive() {
+        let mut reservoir = layered_reservoir((4, 4, 3));
+        // NaN padding along one edge of the survey
+        reservoir.slice_mut(s![3, .., ..]).fill(f64::NAN);
+        let simulation = run_layered(&reservoir, (1, 1, 1), &SimulationConfig::default());
+
+        assert_eq!(simulation.n_nan_cells(), 12);
+        assert_eq!(simulation.cells_filled(), 24);
+        assert_eq!(simulation.fraction_filled(), 1.0);
+        let snapshots = simulation.snapshots();
+        assert!(snapshots.slice(s![3, .., ..]).iter().all(|&s| s == -1));
+    }
+
+    #[test]
+    fn test_lateral_directions() {
+        assert_eq!(lateral_directions((1, 1)), SPREAD_DIRECTIONS.to_vec());
+
+        let directions = lateral_directions((2, 1));
+        assert_eq!(directions.len(), 14); // 5 * 3 - 1
+        assert!(directions.contains(&(2, -1)));
+        assert!(!directions.contains(&(1, 2)));
+    }
+
+    #[test]
+    fn test_try_to_break_caprock() {
+        let mut reservoir = make_test_cells(2, 2, 3, CellState::Reservoir);
+        reservoir[[0, 0, 1]] = CellState::Caprock;
         let depths = Array1::from(vec![0.0, 1.0, 2.0]);
         let bedrock_indices = Array2::from_elem((2, 2), 0); // bedrock at z=0 for all (x,y)
         let mut queue = DepthOrderedQueue::new();
 
         // Place CO2 below caprock
-        reservoir[[0, 0, 2]] = VELOCITY_CO2;
+        reservoir[[0, 0, 2]] = CellState::Co2;
+        let mut reservoir = ReservoirState::from_cell_states(&reservoir.view());
+        let grid = RegularGrid::new(2, 2, depths.view());
 
-        try_to_break_caprock(
+        let broken = try_to_break_caprock(
             &mut queue,
             &mut reservoir,
-            &depths.view(),
+            |_, cell| grid.cell_depth(cell),
             &bedrock_indices.view(),
             (0, 0, 2),
-            1,
+            BreachGeometry::SingleCell,
+            |column_height, _| column_height >= 1,
         );
 
         // Caprock at [0,0,1] should have turned into reservoir
-        assert_eq!(reservoir[[0, 0, 1]], VELOCITY_RESERVOIR);
+        assert_eq!(broken, vec![(0, 0, 1)]);
+        assert_eq!(reservoir.state((0, 0, 1)), CellState::Reservoir);
+        assert_eq!(reservoir.rock_types()[[0, 0, 1]], RockType::Caprock);
         assert!(!queue.is_empty());
     }
+
+    #[test]
+    fn test_deterministic_run_matches_golden_output() {
+        use crate::datastucture::QueueKind;
+        use crate::fingerprint::snapshot_checksum;
+        use crate::spreading::StochasticSpreading;
+
+        // A dome with a breakable seal, drift and random spreading, which exercise the tilted keys and the libm
+        // functions. The checksum was recorded on x86_64 Linux and must match on every platform.
+        const GOLDEN_CHECKSUM: &str =
+            "79ba850f335bc86cb27f906a86a30aae177c50f4a788e1d76f0d5b5cf32d25e8";
+        let mut reservoir = layered_reservoir((12, 10, 8));
+        for ((x, y, z), velocity) in reservoir.indexed_iter_mut() {
+            let crest = (x as f64 - 6.0).abs() / 3.0 + (y as f64 - 5.0).abs() / 4.0;
+            if z == (3 + crest as usize).min(6) {
+                *velocity = VELOCITY_CAPROCK;
+            }
+        }
+        let depths = Array1::from_iter((0..8).map(|z| 1000.0 + 0.1 * z as f64));
+        let config = SimulationConfig {
+            max_column_height: Some(2),
+            total_snapshots: 20,
+            aquifer_flow: Some(AquiferFlow {
+                hydraulic_gradient: (0.0003, 0.0001),
+                cell_size: (50.0, 50.0),
+                density_ratio: 3.0,
+                drift_rate: 0.0,
+            }),
+            stochastic_spreading: Some(StochasticSpreading {
+                probabilities: [0.7, 0.4, 0.6, 0.5],
+                seed: 11,
+                ..Default::default()
+            }),
+            deterministic: true,
+            ..Default::default()
+        };
+        let run = |config: &SimulationConfig| {
+            let mut simulation = Simulation::new(
+                reservoir.view(),
+                depths.view(),
+                Array2::zeros((12, 10)).view(),
+                (6, 5, 4),
+                config,
+            );
+            simulation.run();
+            snapshot_checksum(simulation.snapshots().iter())
+        };
+        assert_eq!(run(&config), GOLDEN_CHECKSUM);
+        let heap = SimulationConfig {
+            queue: QueueKind::BinaryHeap,
+            ..config
+        };
+        assert_eq!(run(&heap), GOLDEN_CHECKSUM);
+    }
 }
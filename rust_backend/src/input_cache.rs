@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use ndarray_npy::read_npy;
+use numpy::ndarray::{Array, Array1, Array2, Array3, Dimension};
+use serde::{Deserialize, Serialize};
+
+use crate::config::SimulationConfig;
+use crate::fingerprint::{file_checksum, Fingerprint};
+use crate::utils::compute_bedrock_indices;
+
+/// Read a float array saved from Python, which may be float32 or float64
+pub fn read_floats<D: Dimension>(path: &Path) -> Result<Array<f64, D>, Box<dyn Error>> {
+    if let Ok(array) = read_npy(path) {
+        return Ok(array);
+    }
+    let array: Array<f32, D> = read_npy(path)?;
+    Ok(array.mapv(f64::from))
+}
+
+/// Read bedrock indices saved from Python, which may be int32 or int64
+pub fn read_indices(path: &Path) -> Result<Array2<usize>, Box<dyn Error>> {
+    if let Ok(indices) = read_npy::<_, Array2<i64>>(path) {
+        return Ok(indices.mapv(|i| i as usize));
+    }
+    let indices: Array2<i32> = read_npy(path)?;
+    Ok(indices.mapv(|i| i as usize))
+}
+
+/// The arrays of a grid as the simulation takes them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GridInputs {
+    pub reservoir_matrix: Array3<f64>,
+    pub depths: Array1<f64>,
+    pub bedrock_indices: Array2<usize>,
+}
+
+impl GridInputs {
+    /// Read the grid from .npy files. The bedrock indices are computed from the reservoir matrix if not given.
+    pub fn load(
+        reservoir_matrix: &Path,
+        depths: &Path,
+        bedrock_indices: Option<&Path>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let reservoir_matrix: Array3<f64> = read_floats(reservoir_matrix)?;
+        let depths: Array1<f64> = read_floats(depths)?;
+        let bedrock_indices = match bedrock_indices {
+            Some(path) => read_indices(path)?,
+            None => compute_bedrock_indices(
+                &reservoir_matrix.view(),
+                SimulationConfig::default().velocity_classifier,
+            ),
+        };
+        Ok(GridInputs {
+            reservoir_matrix,
+            depths,
+            bedrock_indices,
+        })
+    }
+}
+
+/// Number of grids an `InputCache` keeps in memory unless given, see `InputCache::with_capacity`
+pub const DEFAULT_CACHED_GRIDS: usize = 4;
+
+// Numbers the temporary files of the cache, so writers in one process do not share them
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// The grids in memory with the time they were last used, counted in loads
+#[derive(Debug, Default)]
+struct CachedGrids {
+    grids: HashMap<String, (u64, Arc<GridInputs>)>,
+    loads: u64,
+}
+
+/// Grids read from .npy files, kept by the content hash of the files so the same grid is only parsed and
+/// converted once, e.g. for many scenarios on one model. The most recently used grids are kept in memory, and
+/// all of them in a directory if given, where later processes find them. Files are hashed on every load, so an
+/// edited file is read again.
+#[derive(Debug)]
+pub struct InputCache {
+    dir: Option<PathBuf>,
+    capacity: usize,
+    grids: Mutex<CachedGrids>,
+}
+
+impl Default for InputCache {
+    fn default() -> Self {
+        InputCache {
+            dir: None,
+            capacity: DEFAULT_CACHED_GRIDS,
+            grids: Mutex::default(),
+        }
+    }
+}
+
+impl InputCache {
+    /// A cache that only keeps the grids in memory
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// A cache that also keeps the grids in the given directory, which is created if needed
+    pub fn on_disk(dir: &Path) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(InputCache {
+            dir: Some(dir.to_path_buf()),
+            ..Self::default()
+        })
+    }
+
+    /// The same cache keeping at most the given number of grids in memory, at least one. The least recently
+    /// used grid is dropped to make room for a new one.
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        InputCache {
+            capacity: capacity.max(1),
+            ..self
+        }
+    }
+
+    /// The key of a grid: a hash of the contents of its files and the crate version, since the stored layout
+    /// may change between versions
+    pub fn key(
+        reservoir_matrix: &Path,
+        depths: &Path,
+        bedrock_indices: Option<&Path>,
+    ) -> std::io::Result<String> {
+        let mut fingerprint = Fingerprint::new();
+        fingerprint
+            .add_bytes(
+                "reservoir_matrix",
+                file_checksum(reservoir_matrix)?.as_bytes(),
+            )
+            .add_bytes("depths", file_checksum(depths)?.as_bytes());
+        match bedrock_indices {
+            Some(path) => fingerprint.add_bytes("bedrock_indices", file_checksum(path)?.as_bytes()),
+            None => fingerprint.add_bytes("bedrock_indices", b"computed"),
+        };
+        Ok(fingerprint.finish())
+    }
+
+    /// The grid read from the files, from the cache if the same contents were read before, see `GridInputs::load`
+    pub fn load(
+        &self,
+        reservoir_matrix: &Path,
+        depths: &Path,
+        bedrock_indices: Option<&Path>,
+    ) -> Result<Arc<GridInputs>, Box<dyn Error>> {
+        let key = Self::key(reservoir_matrix, depths, bedrock_indices)?;
+        {
+            let mut cached = self.grids.lock().unwrap();
+            cached.loads += 1;
+            let loads = cached.loads;
+            if let Some((last_used, grid)) = cached.grids.get_mut(&key) {
+                *last_used = loads;
+                return Ok(Arc::clone(grid));
+            }
+        }
+
+        let cached_path = self
+            .dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.bin", key)));
+        // A cache file that cannot be read, e.g. after an interrupted write, is replaced
+        let from_disk = cached_path.as_ref().and_then(|path| {
+            let file = File::open(path).ok()?;
+            bincode::deserialize_from::<_, GridInputs>(BufReader::new(file)).ok()
+        });
+        let grid = match from_disk {
+            Some(grid) => grid,
+            None => {
+                let grid = GridInputs::load(reservoir_matrix, depths, bedrock_indices)?;
+                if let Some(path) = &cached_path {
+                    // Written beside the cache file and renamed over it, so readers never see a partial file
+                    let temp_path = path.with_extension(format!(
+                        "{}.{}.tmp",
+                        std::process::id(),
+                        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+                    ));
+                    let mut writer = BufWriter::new(File::create(&temp_path)?);
+                    bincode::serialize_into(&mut writer, &grid)?;
+                    writer.into_inner().map_err(|err| err.into_error())?;
+                    std::fs::rename(&temp_path, path)?;
+                }
+                grid
+            }
+        };
+        let grid = Arc::new(grid);
+        let mut cached = self.grids.lock().unwrap();
+        if cached.grids.len() >= self.capacity {
+            let least_recent = cached
+                .grids
+                .iter()
+                .min_by_key(|(_, (last_used, _))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(least_recent) = least_recent {
+                cached.grids.remove(&least_recent);
+            }
+        }
+        let loads = cached.loads;
+        cached.grids.insert(key, (loads, Arc::clone(&grid)));
+        Ok(grid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use ndarray_npy::write_npy;
+    use numpy::ndarray::s;
+
+    #[test]
+    fn test_cache_reads_each_grid_once() {
+        let dir = std::env::temp_dir().join(format!("co2_input_cache_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut reservoir = Array3::<f32>::from_elem((3, 2, 4), VELOCITY_RESERVOIR as f32);
+        reservoir
+            .slice_mut(s![.., .., 0])
+            .fill(VELOCITY_CAPROCK as f32);
+        let reservoir_path = dir.join("reservoir.npy");
+        let depths_path = dir.join("depths.npy");
+        write_npy(&reservoir_path, &reservoir).unwrap();
+        write_npy(&depths_path, &Array1::from(vec![0.0, 1.0, 2.0, 3.0])).unwrap();
+
+        let cache_dir = dir.join("cache");
+        let cache = InputCache::on_disk(&cache_dir).unwrap();
+        let grid = cache.load(&reservoir_path, &depths_path, None).unwrap();
+        assert_eq!(grid.reservoir_matrix[[0, 0, 0]], VELOCITY_CAPROCK);
+        assert_eq!(grid.bedrock_indices.dim(), (3, 2));
+        let again = cache.load(&reservoir_path, &depths_path, None).unwrap();
+        assert!(Arc::ptr_eq(&grid, &again));
+
+        // Another process finds the grid on disk
+        let key = InputCache::key(&reservoir_path, &depths_path, None).unwrap();
+        assert!(cache_dir.join(format!("{}.bin", key)).exists());
+        let other = InputCache::on_disk(&cache_dir).unwrap();
+        assert_eq!(
+            *other.load(&reservoir_path, &depths_path, None).unwrap(),
+            *grid
+        );
+
+        // An edited file is read again
+        reservoir[[1, 1, 2]] = VELOCITY_CAPROCK as f32;
+        write_npy(&reservoir_path, &reservoir).unwrap();
+        let edited = cache.load(&reservoir_path, &depths_path, None).unwrap();
+        assert_eq!(edited.reservoir_matrix[[1, 1, 2]], VELOCITY_CAPROCK);
+        assert_ne!(
+            InputCache::key(&reservoir_path, &depths_path, None).unwrap(),
+            key
+        );
+        assert!(std::fs::read_dir(&cache_dir).unwrap().all(|entry| entry
+            .unwrap()
+            .path()
+            .extension()
+            .unwrap()
+            == "bin"));
+
+        // Past its capacity, the cache drops the grid used least recently
+        let cache = InputCache::in_memory().with_capacity(1);
+        let first = cache.load(&reservoir_path, &depths_path, None).unwrap();
+        write_npy(&depths_path, &Array1::from(vec![0.0, 2.0, 4.0, 6.0])).unwrap();
+        cache.load(&reservoir_path, &depths_path, None).unwrap();
+        assert_eq!(cache.grids.lock().unwrap().grids.len(), 1);
+        write_npy(&depths_path, &Array1::from(vec![0.0, 1.0, 2.0, 3.0])).unwrap();
+        let reread = cache.load(&reservoir_path, &depths_path, None).unwrap();
+        assert_eq!(*reread, *first);
+        assert!(!Arc::ptr_eq(&reread, &first));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
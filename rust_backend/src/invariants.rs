@@ -0,0 +1,54 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// A physical rule of the fill that the state of a run must obey, checked in paranoid mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InvariantKind {
+    /// A cell was filled that no processed cell could have moved the CO2 into, so the CO2 got past a seal.
+    /// Cells in the column of a well and broken caprock cells join the front directly and are exempt.
+    Co2AboveCaprock,
+    /// A cell filled by the run is right below an empty reservoir cell that never held CO2. Broken caprock
+    /// waiting to be filled and cells the CO2 has left again are exempt.
+    FilledBelowEmpty,
+    /// A cell is marked as visited without having been processed by the front: it is inactive or intact caprock
+    /// that never broke, or it was filled by the run but is not marked as visited.
+    VisitedNotProcessed,
+}
+
+impl InvariantKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvariantKind::Co2AboveCaprock => "co2_above_caprock",
+            InvariantKind::FilledBelowEmpty => "filled_below_empty",
+            InvariantKind::VisitedNotProcessed => "visited_not_processed",
+        }
+    }
+}
+
+/// The first broken invariant of a run in paranoid mode, see `Simulation::check_invariants`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InvariantViolation {
+    pub kind: InvariantKind,
+    pub cell: (usize, usize, usize),
+    /// The snapshot being recorded when the violation was found
+    pub snapshot_index: i32,
+    /// Number of cells filled when the violation was found
+    pub cells_filled: usize,
+    /// What was wrong with the cell and its neighbors
+    pub context: String,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invariant {} broken at {:?} after {} cells in snapshot {}: {}",
+            self.kind.as_str(),
+            self.cell,
+            self.cells_filled,
+            self.snapshot_index,
+            self.context
+        )
+    }
+}
@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// An action of the simulation, written as one line of the journal. Recorded when `journal` is set in the
+/// config, see `Simulation::take_journal`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalEntry {
+    /// The injection starts on a grid of the given shape
+    Start {
+        shape: (usize, usize, usize),
+        sources: Vec<(usize, usize, usize)>,
+    },
+    /// The wells start injecting into the given layers, one per well, or one per front for an injection split
+    /// between the layers. Wells past the base of the grid are done.
+    Layer { layers: Vec<usize> },
+    /// A well joined the run
+    Well { source: (usize, usize, usize) },
+    /// A cell was filled in the given snapshot by the front of the given well, see `WellAttribution`
+    Fill {
+        cell: (usize, usize, usize),
+        snapshot: i32,
+        well: i16,
+    },
+    /// A caprock cell broke
+    Breach {
+        cell: (usize, usize, usize),
+        snapshot: i32,
+        cells_filled: usize,
+    },
+    /// A broken caprock cell closed again
+    Reseal {
+        cell: (usize, usize, usize),
+        cells_filled: usize,
+    },
+    /// The snapshot is complete
+    Snapshot { index: i32, cells_filled: usize },
+    /// The injection finished
+    Finish { cells_filled: usize, snapshots: i32 },
+}
+
+/// Writes journal entries as line-delimited JSON
+pub struct JournalWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JournalWriter<W> {
+    pub fn new(writer: W) -> Self {
+        JournalWriter { writer }
+    }
+
+    /// Append the entries and flush them, so the journal is complete up to the last entry if the run is stopped
+    pub fn append(&mut self, entries: &[JournalEntry]) -> io::Result<()> {
+        for entry in entries {
+            serde_json::to_writer(&mut self.writer, entry)?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()
+    }
+}
+
+/// Create a journal file, replacing an existing one
+pub fn create_journal(path: &Path) -> io::Result<JournalWriter<BufWriter<File>>> {
+    Ok(JournalWriter::new(BufWriter::new(File::create(path)?)))
+}
+
+/// Read the entries of a journal written by `JournalWriter`
+pub fn read_journal<R: BufRead>(reader: R) -> Result<Vec<JournalEntry>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            entries.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(entries)
+}
+
+/// Load a journal file, see `read_journal`
+pub fn load_journal(path: &Path) -> Result<Vec<JournalEntry>, Box<dyn Error>> {
+    read_journal(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SimulationConfig;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use crate::injection_simulation::Simulation;
+    use numpy::ndarray::{s, Array1, Array2, Array3};
+
+    #[test]
+    fn test_journal_records_the_run() {
+        let mut reservoir = Array3::from_elem((3, 3, 6), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 1]).fill(VELOCITY_CAPROCK);
+        let config = SimulationConfig {
+            max_column_height: Some(2),
+            total_snapshots: 10,
+            journal: true,
+            ..Default::default()
+        };
+        let mut simulation = Simulation::try_new(
+            reservoir.view(),
+            Array1::from_iter((0..6).map(|z| z as f64)).view(),
+            Array2::zeros((3, 3)).view(),
+            (1, 1, 2),
+            &config,
+        )
+        .unwrap();
+        let mut buffer = Vec::new();
+        let mut writer = JournalWriter::new(&mut buffer);
+        while simulation.advance(7) {
+            writer.append(&simulation.take_journal()).unwrap();
+        }
+        writer.append(&simulation.take_journal()).unwrap();
+        assert!(simulation.take_journal().is_empty());
+
+        let first_line = buffer.split(|&b| b == b'\n').next().unwrap();
+        assert!(std::str::from_utf8(first_line)
+            .unwrap()
+            .starts_with(r#"{"event":"start","shape":[3,3,6]"#));
+        let entries = read_journal(buffer.as_slice()).unwrap();
+        let fills: Vec<_> = entries
+            .iter()
+            .filter_map(|entry| match entry {
+                JournalEntry::Fill { cell, .. } => Some(*cell),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fills, simulation.fill_order());
+        let breaches = entries
+            .iter()
+            .filter(|entry| matches!(entry, JournalEntry::Breach { .. }))
+            .count();
+        assert_eq!(breaches, simulation.breach_events().len());
+        assert!(breaches > 0);
+        assert!(entries.contains(&JournalEntry::Layer { layers: vec![2] }));
+        assert_eq!(
+            entries.last(),
+            Some(&JournalEntry::Finish {
+                cells_filled: simulation.cells_filled(),
+                snapshots: simulation.snapshot_index(),
+            })
+        );
+    }
+}
@@ -1,50 +1,1911 @@
+pub mod accumulation;
+pub mod analytic;
+pub mod aquifer;
+pub mod audit;
+pub mod baseline;
+pub mod benchmarks;
+pub mod breach;
+pub mod calibration;
+pub mod cell_state;
+pub mod checkpoint;
+pub mod column_fill;
+pub mod config;
+pub mod connectivity;
 pub mod constants;
+pub mod containment;
+pub mod cross_section;
+pub mod darcy;
 pub mod datastucture;
+pub mod dissolution;
+pub mod eclipse;
+pub mod ensemble;
+pub mod eos;
+pub mod error;
+pub mod events;
+pub mod fingerprint;
+pub mod geostatistics;
+pub mod golden;
+pub mod grid;
+pub mod grid_repair;
+pub mod input_cache;
+pub mod invariants;
+pub mod journal;
+pub mod maps;
+pub mod matfile;
+pub mod mesh;
+pub mod metadata;
+pub mod ordering;
+pub mod particles;
+pub mod percolation;
+pub mod perf;
+pub mod perforation;
+pub mod plume;
+pub mod pressure;
+pub mod render;
+pub mod replay;
+pub mod scenarios;
+pub mod service;
+pub mod smoothing;
+pub mod snapshot_policy;
+pub mod sparse;
+pub mod spreading;
+pub mod stencil;
+pub mod stream;
+pub mod thermal;
+pub mod time_axis;
+pub mod training_data;
+pub mod trapping;
+pub mod traps;
 pub mod utils;
+pub mod validation;
+pub mod viewer;
+pub mod wells;
 
 pub mod injection_simulation;
-use injection_simulation::_injection_simulation_rust;
+use accumulation::migration_chain;
+use analytic::{validate_analytic, AnalyticCase};
+use benchmarks::BenchmarkModel;
+use breach::{BreachCriterion, Resealing};
+use checkpoint::{load_checkpoint, save_checkpoint};
+use column_fill::ColumnFill;
+use config::SimulationConfig;
+use connectivity::run_flood_fill;
+use containment::{Containment, LateralLimit};
+use cross_section::simulate_cross_section;
+use darcy::DarcyFlow;
+use dissolution::{ConvectiveDissolution, DissolutionHistory, Hysteresis, MineralTrapping};
+use eclipse::{save_grdecl, save_opm_deck};
+use error::SimulationError;
+use events::{cell_event_batches, cell_event_schema, write_cell_events_parquet};
+use fingerprint::{float_checksum, index_checksum, simulation_fingerprint};
+use grid::AnyGrid;
+use grid_repair::repair_grid;
+use injection_simulation::Simulation;
+use journal::{create_journal, JournalWriter};
+use maps::{first_arrival_map, thickness_map};
+use matfile::save_mat;
+use metadata::RunMetadata;
+use particles::ParticleTracking;
+use percolation::InvasionPercolation;
+use replay::JournalReplay;
+use scenarios::Scenario;
+use traps::analyze_traps;
+use utils::compute_bedrock_indices;
 
-use numpy::{PyArray3, PyReadonlyArray1, PyReadonlyArray2, PyReadonlyArray3};
+mod python_utils;
+use python_utils::{
+    parse_aquifer_flow, parse_breach_geometry, parse_co2_stream, parse_fluid_properties,
+    parse_front_ordering, parse_initial_plume, parse_injection_schedule, parse_mass_accounting,
+    parse_perforation, parse_pressure_limit, parse_pressure_model, parse_random_thresholds,
+    parse_rate_changes, parse_smoothing, parse_stencil, parse_stochastic_spreading,
+    parse_stress_criterion, parse_sweep_order, parse_thermal_zone, resolve_bedrock_indices,
+    velocity_classifier, FloatArray, IndexArray, Sources,
+};
+
+use arrow_array::ffi_stream::FFI_ArrowArrayStream;
+use arrow_array::{RecordBatch, RecordBatchIterator};
+use arrow_schema::ffi::FFI_ArrowSchema;
+use arrow_schema::SchemaRef;
+use numpy::ndarray::{Array1, Array2, Axis, Ix1, Ix2, Ix3};
+use numpy::{PyArray1, PyArray2, PyArray3};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyIOError, PyIndexError, PyKeyboardInterrupt, PyValueError};
 use pyo3::prelude::*;
+use pyo3::types::{PyCapsule, PyDict, PyList};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::time::Instant;
+
+impl From<SimulationError> for PyErr {
+    fn from(err: SimulationError) -> PyErr {
+        PyValueError::new_err(err.to_string())
+    }
+}
+
+// Exception raised when a simulation is interrupted with Ctrl+C. It carries the partial result.
+create_exception!(
+    rust_backend,
+    SimulationInterrupted,
+    PyKeyboardInterrupt,
+    "Raised when a simulation is interrupted. The `snapshots`, `cells_filled` and `snapshot_index` attributes hold the partial result."
+);
+
+// Number of filled cells between checks for Python signals such as Ctrl+C
+const SIGNAL_CHECK_INTERVAL: usize = 10_000;
+
+/// Build the SimulationInterrupted error carrying the partial result of the simulation
+fn interrupted_error(py: Python<'_>, simulation: &Simulation, cause: PyErr) -> PyErr {
+    let err = SimulationInterrupted::new_err(format!(
+        "Simulation interrupted after {} filled cells",
+        simulation.cells_filled()
+    ));
+    let value = err.value(py);
+    let attributes = [
+        (
+            "snapshots",
+            PyArray3::from_array(py, &simulation.snapshots()).into_any(),
+        ),
+        (
+            "cells_filled",
+            simulation
+                .cells_filled()
+                .into_pyobject(py)
+                .unwrap()
+                .into_any(),
+        ),
+        (
+            "snapshot_index",
+            simulation
+                .snapshot_index()
+                .into_pyobject(py)
+                .unwrap()
+                .into_any(),
+        ),
+    ];
+    for (name, attribute) in attributes {
+        if let Err(setattr_err) = value.setattr(name, attribute) {
+            return setattr_err;
+        }
+    }
+    err.set_cause(py, Some(cause));
+    err
+}
+
+/// Run the simulation to completion without holding the GIL.
+/// The progress callback is called with (fraction_done, cells_filled, snapshot_index) every `progress_interval` filled cells,
+/// reacquiring the GIL only for the duration of the call. An exception raised by the callback stops the run.
+/// Python signals are checked regularly, so Ctrl+C raises SimulationInterrupted with the partial result.
+/// The journal entries, if recorded, are appended to the journal as the run goes.
+fn run_with_progress(
+    py: Python<'_>,
+    simulation: &mut Simulation,
+    progress_callback: Option<Py<PyAny>>,
+    progress_interval: usize,
+    mut journal: Option<&mut JournalWriter<BufWriter<File>>>,
+) -> PyResult<()> {
+    if progress_interval == 0 {
+        return Err(PyValueError::new_err(
+            "progress_interval must be at least 1",
+        ));
+    }
+
+    py.detach(|| {
+        let mut next_progress = simulation.cells_filled() + progress_interval;
+
+        loop {
+            let chunk = match progress_callback {
+                Some(_) => SIGNAL_CHECK_INTERVAL.min(next_progress - simulation.cells_filled()),
+                None => SIGNAL_CHECK_INTERVAL,
+            };
+            let running = simulation.advance(chunk);
+            if let Some(journal) = journal.as_deref_mut() {
+                journal
+                    .append(&simulation.take_journal())
+                    .map_err(|err| PyIOError::new_err(err.to_string()))?;
+            }
+
+            Python::attach(|py| -> PyResult<()> {
+                if let Err(err) = py.check_signals() {
+                    return Err(interrupted_error(py, simulation, err));
+                }
+
+                if let Some(callback) = &progress_callback {
+                    if simulation.cells_filled() >= next_progress || !running {
+                        callback.call1(
+                            py,
+                            (
+                                simulation.fraction_filled(),
+                                simulation.cells_filled(),
+                                simulation.snapshot_index(),
+                            ),
+                        )?;
+                        next_progress = simulation.cells_filled() + progress_interval;
+                    }
+                }
+                Ok(())
+            })?;
+
+            if !running {
+                return Ok(());
+            }
+        }
+    })
+}
+
+/// Collect the results of a finished simulation into a Python dict
+fn simulation_results_dict(
+    py: Python<'_>,
+    simulation: &Simulation,
+    elapsed_seconds: f64,
+) -> PyResult<Py<PyAny>> {
+    let results = PyDict::new(py);
+    results.set_item(
+        "snapshots",
+        PyArray3::from_array(py, &simulation.snapshots()),
+    )?;
+    results.set_item(
+        "velocity_model",
+        PyArray3::from_array(py, &simulation.reservoir_matrix()),
+    )?;
+    results.set_item(
+        "saturation",
+        PyArray3::from_owned_array(py, simulation.saturation()),
+    )?;
+    results.set_item(
+        "well_attribution",
+        PyArray3::from_owned_array(py, simulation.well_attribution()),
+    )?;
+    results.set_item(
+        "snapshot_volumes",
+        PyArray1::from_owned_array(py, simulation.snapshot_cell_counts().mapv(|c| c as u64)),
+    )?;
+    let inventory = simulation.trapping_inventory();
+    let inventory_dict = PyDict::new(py);
+    for (mechanism, cells) in [
+        ("structural", &inventory.structural),
+        ("residual", &inventory.residual),
+        ("dissolved", &inventory.dissolved),
+        ("mineralized", &inventory.mineralized),
+    ] {
+        inventory_dict.set_item(
+            mechanism,
+            PyArray1::from_owned_array(py, cells.mapv(|c| c as u64)),
+        )?;
+    }
+    results.set_item("trapping_inventory", inventory_dict)?;
+
+    // One entry per z layer, with -1 as the snapshot of layers without CO2
+    let layers = simulation.layer_statistics();
+    let layers_dict = PyDict::new(py);
+    layers_dict.set_item(
+        "cells_filled",
+        PyArray1::from_vec(py, layers.iter().map(|l| l.cells_filled as u64).collect()),
+    )?;
+    layers_dict.set_item(
+        "first_snapshot",
+        PyArray1::from_vec(
+            py,
+            layers
+                .iter()
+                .map(|l| l.first_snapshot.unwrap_or(-1))
+                .collect(),
+        ),
+    )?;
+    layers_dict.set_item(
+        "last_snapshot",
+        PyArray1::from_vec(
+            py,
+            layers
+                .iter()
+                .map(|l| l.last_snapshot.unwrap_or(-1))
+                .collect(),
+        ),
+    )?;
+    layers_dict.set_item(
+        "fraction_used",
+        PyArray1::from_vec(py, layers.iter().map(|l| l.fraction_used()).collect()),
+    )?;
+    results.set_item("layer_statistics", layers_dict)?;
+
+    // Map-view products of the (nx, ny) columns
+    let maps_dict = PyDict::new(py);
+    maps_dict.set_item(
+        "max_saturation",
+        PyArray2::from_owned_array(py, simulation.max_saturation_map()),
+    )?;
+    maps_dict.set_item(
+        "top_depth",
+        PyArray2::from_owned_array(py, simulation.top_of_plume_depth_map()),
+    )?;
+    maps_dict.set_item(
+        "first_arrival",
+        PyArray2::from_owned_array(py, first_arrival_map(&simulation.plume())),
+    )?;
+    maps_dict.set_item(
+        "thickness",
+        PyArray2::from_owned_array(py, thickness_map(&simulation.plume()).mapv(|c| c as u64)),
+    )?;
+    results.set_item("maps", maps_dict)?;
+
+    let breach_events = PyList::empty(py);
+    for event in simulation.breach_events() {
+        let event_dict = PyDict::new(py);
+        event_dict.set_item("cell", event.cell)?;
+        event_dict.set_item("snapshot_index", event.snapshot_index)?;
+        event_dict.set_item("cells_filled", event.cells_filled)?;
+        event_dict.set_item("resealed_at", event.resealed_at)?;
+        breach_events.append(event_dict)?;
+    }
+    results.set_item("breach_events", breach_events)?;
+
+    // The pools of CO2 under every seal, and which breach fed them from which pool
+    let accumulations = simulation.accumulations();
+    let accumulations_list = PyList::empty(py);
+    for (index, accumulation) in accumulations.iter().enumerate() {
+        let accumulation_dict = PyDict::new(py);
+        accumulation_dict.set_item("fed_by", accumulation.fed_by)?;
+        accumulation_dict.set_item("parent", accumulation.parent)?;
+        accumulation_dict.set_item("cells", accumulation.cells)?;
+        accumulation_dict.set_item("breaches", accumulation.breaches.clone())?;
+        accumulation_dict.set_item("migration_chain", migration_chain(&accumulations, index))?;
+        accumulations_list.append(accumulation_dict)?;
+    }
+    results.set_item("accumulations", accumulations_list)?;
+
+    // Dates as ISO strings, with None for snapshots the injection schedule never completes
+    if let Some(dates) = simulation.snapshot_dates() {
+        let dates: Vec<Option<String>> = dates
+            .iter()
+            .map(|date| date.map(|date| date.to_string()))
+            .collect();
+        results.set_item("snapshot_dates", dates)?;
+    }
+    if let (Some(masses), Some(total)) = (simulation.snapshot_masses(), simulation.injected_mass())
+    {
+        results.set_item("snapshot_masses", PyArray1::from_owned_array(py, masses))?;
+        results.set_item("injected_mass", total)?;
+    }
+    // The schedule after throttling, as (ISO date, cells per day)
+    if simulation.config().pressure_limit.is_some() {
+        if let Some(time_axis) = simulation.effective_time_axis() {
+            let schedule: Vec<(String, f64)> = time_axis
+                .schedule()
+                .iter()
+                .map(|change| (change.date.to_string(), change.cells_per_day))
+                .collect();
+            results.set_item("effective_schedule", schedule)?;
+        }
+    }
+    if let Some(overpressure) = simulation.overpressure_maps() {
+        results.set_item("overpressure", PyArray3::from_owned_array(py, overpressure))?;
+    }
+    let violation = match simulation.containment_violation() {
+        Some(violation) => {
+            let violation_dict = PyDict::new(py);
+            violation_dict.set_item("kind", violation.kind.as_str())?;
+            violation_dict.set_item("cell", violation.cell)?;
+            violation_dict.set_item("snapshot_index", violation.snapshot_index)?;
+            violation_dict.set_item("cells_filled", violation.cells_filled)?;
+            Some(violation_dict)
+        }
+        None => None,
+    };
+    results.set_item("containment_violation", violation)?;
+    if simulation.config().lateral_limit.is_some() {
+        let exceedance = match simulation.lateral_exceedance() {
+            Some(exceedance) => {
+                let exceedance_dict = PyDict::new(py);
+                exceedance_dict.set_item("cell", exceedance.cell)?;
+                exceedance_dict.set_item("distance", exceedance.distance)?;
+                exceedance_dict.set_item("snapshot_index", exceedance.snapshot_index)?;
+                exceedance_dict.set_item("cells_filled", exceedance.cells_filled)?;
+                Some(exceedance_dict)
+            }
+            None => None,
+        };
+        results.set_item("lateral_exceedance", exceedance)?;
+    }
+    if simulation.config().paranoid {
+        let violation = match simulation.invariant_violation() {
+            Some(violation) => {
+                let violation_dict = PyDict::new(py);
+                violation_dict.set_item("kind", violation.kind.as_str())?;
+                violation_dict.set_item("cell", violation.cell)?;
+                violation_dict.set_item("snapshot_index", violation.snapshot_index)?;
+                violation_dict.set_item("cells_filled", violation.cells_filled)?;
+                violation_dict.set_item("context", &violation.context)?;
+                Some(violation_dict)
+            }
+            None => None,
+        };
+        results.set_item("invariant_violation", violation)?;
+    }
+    if simulation.config().audit {
+        let ledger = PyList::empty(py);
+        for entry in simulation.mass_ledger().entries() {
+            let entry_dict = PyDict::new(py);
+            entry_dict.set_item("snapshot_index", entry.snapshot_index)?;
+            entry_dict.set_item("injected", entry.injected)?;
+            entry_dict.set_item("mobile", entry.mobile)?;
+            entry_dict.set_item("trapped", entry.trapped)?;
+            entry_dict.set_item("dissolved", entry.dissolved)?;
+            entry_dict.set_item("leaked", entry.leaked)?;
+            entry_dict.set_item("balanced", entry.is_balanced())?;
+            ledger.append(entry_dict)?;
+        }
+        results.set_item("mass_ledger", ledger)?;
+    }
+    results.set_item("nan_cells", simulation.n_nan_cells())?;
+    let compartment = simulation.source_compartment();
+    results.set_item("compartment_cells", compartment.compartment_cells)?;
+    results.set_item("compartment_warning", compartment.warning())?;
+    results.set_item("elapsed_seconds", elapsed_seconds)?;
+
+    Ok(results.into_any().unbind())
+}
+
+/// Set up a simulation from the arrays passed from Python. All Python entry points go through here,
+/// so they share the input conversion and validation of the core engine.
+fn build_simulation(
+    reservoir_matrix: FloatArray<'_, Ix3>,
+    depths: FloatArray<'_, Ix1>,
+    bedrock_indices: Option<IndexArray<'_, Ix2>>,
+    sources: &[(usize, usize, usize)],
+    config: &SimulationConfig,
+) -> PyResult<Simulation> {
+    let reservoir_matrix = reservoir_matrix.as_f64();
+
+    // Convert bedrock_indices to usize, or compute them if they were not given
+    let bedrock_indices = resolve_bedrock_indices(
+        bedrock_indices,
+        &reservoir_matrix.view(),
+        config.velocity_classifier,
+    )?;
+
+    // Invalid inputs raise a ValueError
+    Ok(Simulation::try_new_with_wells(
+        reservoir_matrix.view(),
+        depths.as_f64().view(),
+        bedrock_indices.view(),
+        sources,
+        config,
+    )?)
+}
+
+/// The coordinates of the cells along an axis of n cells, or their indices if not given
+fn axis_coordinates(
+    name: &str,
+    coordinates: Option<FloatArray<'_, Ix1>>,
+    n: usize,
+) -> PyResult<Array1<f64>> {
+    match coordinates {
+        Some(coordinates) if coordinates.as_f64().len() != n => {
+            Err(SimulationError::ShapeMismatch {
+                argument: name.to_string(),
+                expected: format!("({},) to match the grid", n),
+                actual: format!("({},)", coordinates.as_f64().len()),
+            }
+            .into())
+        }
+        Some(coordinates) => Ok(coordinates.as_f64().into_owned()),
+        None => Ok(Array1::from_iter((0..n).map(|i| i as f64))),
+    }
+}
+
+/// Add the coordinates of the x, y, depth and snapshot axes, and the dims of the arrays along them, so the
+/// results can be labelled without knowing the inputs, e.g. as an xarray.Dataset
+fn set_coordinates(
+    py: Python<'_>,
+    results: &Bound<'_, PyDict>,
+    simulation: &Simulation,
+    x_coordinates: Array1<f64>,
+    y_coordinates: Array1<f64>,
+) -> PyResult<()> {
+    let n_snapshots = simulation.snapshot_cell_counts().len() as i32;
+    let coordinates = PyDict::new(py);
+    coordinates.set_item("x", PyArray1::from_owned_array(py, x_coordinates))?;
+    coordinates.set_item("y", PyArray1::from_owned_array(py, y_coordinates))?;
+    coordinates.set_item(
+        "depth",
+        PyArray1::from_owned_array(py, simulation.layer_depths()),
+    )?;
+    coordinates.set_item(
+        "snapshot",
+        PyArray1::from_owned_array(py, Array1::from_iter(0..n_snapshots)),
+    )?;
+    results.set_item("coordinates", coordinates)?;
+
+    let dims = PyDict::new(py);
+    let cells = ("x", "y", "depth");
+    for name in [
+        "snapshots",
+        "velocity_model",
+        "saturation",
+        "well_attribution",
+    ] {
+        dims.set_item(name, cells)?;
+    }
+    dims.set_item("snapshot_volumes", ("snapshot",))?;
+    if results.contains("snapshot_masses")? {
+        dims.set_item("snapshot_masses", ("snapshot",))?;
+    }
+    if results.contains("overpressure")? {
+        dims.set_item("overpressure", ("snapshot", "x", "y"))?;
+    }
+    results.set_item("dims", dims)
+}
+
+/// Fingerprint of the inputs passed from Python, see `simulation_fingerprint`
+fn python_fingerprint(
+    reservoir_matrix: &FloatArray<'_, Ix3>,
+    depths: &FloatArray<'_, Ix1>,
+    bedrock_indices: Option<&IndexArray<'_, Ix2>>,
+    sources: &[(usize, usize, usize)],
+    config: &SimulationConfig,
+) -> PyResult<String> {
+    let reservoir_matrix = reservoir_matrix.as_f64();
+    let bedrock_indices = match bedrock_indices {
+        Some(bedrock_indices) => bedrock_indices.to_usize("bedrock_indices")?,
+        None => compute_bedrock_indices(&reservoir_matrix.view(), config.velocity_classifier),
+    };
+    Ok(simulation_fingerprint(
+        &reservoir_matrix.view(),
+        &depths.as_f64().view(),
+        &bedrock_indices.view(),
+        sources,
+        config,
+        None,
+    ))
+}
+
+/// Checksums of the inputs passed from Python, for the metadata sidecar of exported results
+fn python_input_checksums(
+    reservoir_matrix: &FloatArray<'_, Ix3>,
+    depths: &FloatArray<'_, Ix1>,
+    bedrock_indices: Option<&IndexArray<'_, Ix2>>,
+) -> PyResult<BTreeMap<String, String>> {
+    let mut checksums = BTreeMap::from([
+        (
+            "reservoir_matrix".to_string(),
+            float_checksum(reservoir_matrix.as_f64().iter()),
+        ),
+        ("depths".to_string(), float_checksum(depths.as_f64().iter())),
+    ]);
+    if let Some(bedrock_indices) = bedrock_indices {
+        checksums.insert(
+            "bedrock_indices".to_string(),
+            index_checksum(bedrock_indices.to_usize("bedrock_indices")?.iter()),
+        );
+    }
+    Ok(checksums)
+}
 
 /// Wrap the injection simulation function to be accessible from Python
 #[pyfunction]
-#[pyo3(signature = (reservoir_matrix, depths, bedrock_indices, max_column_height, source, total_snapshots = 100))]
+#[pyo3(signature = (reservoir_matrix, depths, bedrock_indices, max_column_height, source, total_snapshots = 100, progress_callback = None, progress_interval = 1000, return_extras = false, injection_schedule = None, velocity_tolerance = 0.0, primary_caprock_indices = None, containment_polygon = None, stop_at_surface = false, audit = false, pressure_model = None, pressure_limit = None, stress_criterion = None, mass_accounting = None, aquifer_flow = None, thermal_zone = None, co2_stream = None, stochastic_spreading = None, smoothing = None, perforation = None, sweep_order = "top_down", stencil = None, front_ordering = "depth", basement_indices = None, breach_geometry = None, reseal_after = None, max_lateral_distance = None, max_lateral_offsets = None, stop_at_lateral_limit = false, x_coordinates = None, y_coordinates = None, deterministic = false, initial_plume = None, journal_path = None, paranoid = false))]
 #[allow(clippy::too_many_arguments)] // TODO: Handle this later
 pub fn _injection_simulation_python_wrapper(
     py: Python<'_>,
-    reservoir_matrix: PyReadonlyArray3<f64>,
-    depths: PyReadonlyArray1<f64>,
-    bedrock_indices: PyReadonlyArray2<i32>,
-    max_column_height: usize,
-    source: (usize, usize, usize),
+    reservoir_matrix: FloatArray<'_, Ix3>,
+    depths: FloatArray<'_, Ix1>,
+    bedrock_indices: Option<IndexArray<'_, Ix2>>,
+    max_column_height: Option<usize>,
+    source: Sources,
     total_snapshots: usize,
-) -> PyResult<Py<PyArray3<i32>>> {
-    let reservoir_matrix = reservoir_matrix.as_array();
-    let depths = depths.as_array();
-    let bedrock_indices = bedrock_indices.as_array();
+    progress_callback: Option<Py<PyAny>>,
+    progress_interval: usize,
+    return_extras: bool,
+    injection_schedule: Option<Vec<(String, f64)>>,
+    velocity_tolerance: f64,
+    primary_caprock_indices: Option<IndexArray<'_, Ix2>>,
+    containment_polygon: Option<Vec<(f64, f64)>>,
+    stop_at_surface: bool,
+    audit: bool,
+    pressure_model: Option<Bound<'_, PyDict>>,
+    pressure_limit: Option<Bound<'_, PyDict>>,
+    stress_criterion: Option<Bound<'_, PyDict>>,
+    mass_accounting: Option<Bound<'_, PyDict>>,
+    aquifer_flow: Option<Bound<'_, PyDict>>,
+    thermal_zone: Option<Bound<'_, PyDict>>,
+    co2_stream: Option<Bound<'_, PyDict>>,
+    stochastic_spreading: Option<Bound<'_, PyDict>>,
+    smoothing: Option<Bound<'_, PyDict>>,
+    perforation: Option<Bound<'_, PyAny>>,
+    sweep_order: &str,
+    stencil: Option<Bound<'_, PyAny>>,
+    front_ordering: &str,
+    basement_indices: Option<IndexArray<'_, Ix2>>,
+    breach_geometry: Option<Bound<'_, PyAny>>,
+    reseal_after: Option<usize>,
+    max_lateral_distance: Option<f64>,
+    max_lateral_offsets: Option<(f64, f64, f64, f64)>,
+    stop_at_lateral_limit: bool,
+    x_coordinates: Option<FloatArray<'_, Ix1>>,
+    y_coordinates: Option<FloatArray<'_, Ix1>>,
+    deterministic: bool,
+    initial_plume: Option<Bound<'_, PyAny>>,
+    journal_path: Option<PathBuf>,
+    paranoid: bool,
+) -> PyResult<Py<PyAny>> {
+    // Strict containment is used if any of its criteria is given
+    let containment =
+        if primary_caprock_indices.is_some() || containment_polygon.is_some() || stop_at_surface {
+            Some(Containment {
+                primary_caprock_indices: primary_caprock_indices
+                    .map(|indices| indices.to_usize("primary_caprock_indices"))
+                    .transpose()?,
+                surface_layer: stop_at_surface,
+                polygon: containment_polygon,
+            })
+        } else {
+            None
+        };
 
-    // Convert bedrock_indices to usize
-    let bedrock_indices = bedrock_indices.mapv(|x| x as usize);
+    let lateral_limit = if max_lateral_distance.is_some() || max_lateral_offsets.is_some() {
+        Some(LateralLimit {
+            max_distance: max_lateral_distance,
+            max_offsets: max_lateral_offsets.map(|(px, mx, py, my)| [px, mx, py, my]),
+            stop: stop_at_lateral_limit,
+        })
+    } else {
+        None
+    };
 
     // Call the Rust implementation of the injection simulation
-    let snapshots = _injection_simulation_rust(
+    let breach_criterion = match stress_criterion {
+        Some(properties) => BreachCriterion::Stress(parse_stress_criterion(&properties)?),
+        None => BreachCriterion::ColumnHeight,
+    };
+    let config = SimulationConfig {
+        max_column_height,
+        breach_criterion,
+        breach_geometry: breach_geometry
+            .map(|value| parse_breach_geometry(&value))
+            .transpose()?
+            .unwrap_or_default(),
+        resealing: reseal_after.map(|cells| Resealing { cells }),
+        lateral_limit,
+        total_snapshots,
+        time_axis: injection_schedule
+            .map(parse_injection_schedule)
+            .transpose()?,
+        velocity_classifier: velocity_classifier(velocity_tolerance),
+        containment,
+        audit,
+        pressure_model: pressure_model
+            .map(|properties| parse_pressure_model(&properties))
+            .transpose()?,
+        pressure_limit: pressure_limit
+            .map(|properties| parse_pressure_limit(&properties))
+            .transpose()?,
+        mass_accounting: mass_accounting
+            .map(|properties| parse_mass_accounting(&properties))
+            .transpose()?,
+        aquifer_flow: aquifer_flow
+            .map(|properties| parse_aquifer_flow(&properties))
+            .transpose()?,
+        thermal_zone: thermal_zone
+            .map(|properties| parse_thermal_zone(&properties))
+            .transpose()?,
+        co2_stream: co2_stream
+            .map(|impurities| parse_co2_stream(&impurities))
+            .transpose()?,
+        stochastic_spreading: stochastic_spreading
+            .map(|properties| parse_stochastic_spreading(&properties))
+            .transpose()?,
+        smoothing: smoothing
+            .map(|properties| parse_smoothing(&properties))
+            .transpose()?,
+        perforation: perforation
+            .map(|value| parse_perforation(&value))
+            .transpose()?,
+        sweep_order: parse_sweep_order(sweep_order)?,
+        stencil: stencil.map(|offsets| parse_stencil(&offsets)).transpose()?,
+        front_ordering: parse_front_ordering(front_ordering)?,
+        basement_indices: basement_indices
+            .map(|indices| indices.to_usize("basement_indices"))
+            .transpose()?,
+        deterministic,
+        initial_plume: initial_plume
+            .map(|value| parse_initial_plume(&value))
+            .transpose()?,
+        journal: journal_path.is_some(),
+        paranoid,
+        ..Default::default()
+    };
+    let fingerprint = if return_extras {
+        Some(python_fingerprint(
+            &reservoir_matrix,
+            &depths,
+            bedrock_indices.as_ref(),
+            &source.0,
+            &config,
+        )?)
+    } else {
+        None
+    };
+    let mut simulation = build_simulation(
         reservoir_matrix,
         depths,
-        bedrock_indices.view(), // Pass as view
+        bedrock_indices,
+        &source.0,
+        &config,
+    )?;
+    let (nx, ny, _) = simulation.sparse_snapshots().dim();
+    let x_coordinates = axis_coordinates("x_coordinates", x_coordinates, nx)?;
+    let y_coordinates = axis_coordinates("y_coordinates", y_coordinates, ny)?;
+    let start = Instant::now();
+    let mut journal = journal_path
+        .map(|path| create_journal(&path))
+        .transpose()
+        .map_err(|err| PyIOError::new_err(err.to_string()))?;
+    run_with_progress(
+        py,
+        &mut simulation,
+        progress_callback,
+        progress_interval,
+        journal.as_mut(),
+    )?;
+    let elapsed_seconds = start.elapsed().as_secs_f64();
+
+    if let Some(fingerprint) = fingerprint {
+        let results = simulation_results_dict(py, &simulation, elapsed_seconds)?;
+        let results_dict = results.bind(py).downcast::<PyDict>()?;
+        set_coordinates(py, results_dict, &simulation, x_coordinates, y_coordinates)?;
+        results_dict.set_item("fingerprint", fingerprint)?;
+        return Ok(results);
+    }
+
+    // Return the snapshots as a Python array
+    let snapshots = simulation.into_snapshots();
+    Ok(PyArray3::from_array(py, &snapshots).into_any().unbind())
+}
+
+/// A stateful injection simulation that can be advanced step by step from Python
+/// The optional post-injection models, which are off when their rate or saturation is zero
+fn post_injection_models(
+    mineralization_rate: f64,
+    residual_saturation: f64,
+) -> (Option<MineralTrapping>, Option<Hysteresis>) {
+    let mineral_trapping = (mineralization_rate != 0.0).then_some(MineralTrapping {
+        rate_constant: mineralization_rate,
+    });
+    let hysteresis = (residual_saturation != 0.0).then_some(Hysteresis {
+        residual_saturation,
+    });
+    (mineral_trapping, hysteresis)
+}
+
+fn dissolution_history_dict(py: Python<'_>, history: DissolutionHistory) -> PyResult<Py<PyDict>> {
+    let results = PyDict::new(py);
+    results.set_item("mobile", PyArray1::from_owned_array(py, history.mobile))?;
+    results.set_item("residual", PyArray1::from_owned_array(py, history.residual))?;
+    results.set_item(
+        "dissolved",
+        PyArray1::from_owned_array(py, history.dissolved),
+    )?;
+    results.set_item(
+        "mineralized",
+        PyArray1::from_owned_array(py, history.mineralized),
+    )?;
+    results.set_item(
+        "contact_area",
+        PyArray1::from_owned_array(py, history.contact_area.mapv(|c| c as u64)),
+    )?;
+    results.set_item(
+        "saturation",
+        PyArray3::from_owned_array(py, history.saturation.to_dense()),
+    )?;
+    results.set_item(
+        "cell_history",
+        PyArray3::from_owned_array(py, history.cell_history.to_dense().mapv(|h| h as u8)),
+    )?;
+    Ok(results.unbind())
+}
+
+#[pyclass(name = "Simulation")]
+pub struct PySimulation {
+    inner: Simulation,
+    input_checksums: BTreeMap<String, String>,
+    // Time spent in run() and step() so far
+    elapsed_seconds: f64,
+}
+
+impl PySimulation {
+    fn metadata(&self) -> RunMetadata {
+        let mut metadata = RunMetadata::for_simulation(&self.inner, Some(self.elapsed_seconds));
+        metadata.input_checksums = self.input_checksums.clone();
+        metadata
+    }
+}
+
+#[pymethods]
+impl PySimulation {
+    #[new]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (reservoir_matrix, depths, bedrock_indices, source, max_column_height = 10, total_snapshots = 100, anisotropy = (1, 1), velocity_tolerance = 0.0, aquifer_flow = None, rewind_points = false))]
+    fn new(
+        reservoir_matrix: FloatArray<'_, Ix3>,
+        depths: FloatArray<'_, Ix1>,
+        bedrock_indices: Option<IndexArray<'_, Ix2>>,
+        source: (usize, usize, usize),
+        max_column_height: Option<usize>,
+        total_snapshots: usize,
+        anisotropy: (usize, usize),
+        velocity_tolerance: f64,
+        aquifer_flow: Option<Bound<'_, PyDict>>,
+        rewind_points: bool,
+    ) -> PyResult<Self> {
+        let config = SimulationConfig {
+            max_column_height,
+            total_snapshots,
+            anisotropy,
+            velocity_classifier: velocity_classifier(velocity_tolerance),
+            aquifer_flow: aquifer_flow
+                .map(|properties| parse_aquifer_flow(&properties))
+                .transpose()?,
+            rewind_points,
+            ..Default::default()
+        };
+
+        let input_checksums =
+            python_input_checksums(&reservoir_matrix, &depths, bedrock_indices.as_ref())?;
+        Ok(PySimulation {
+            input_checksums,
+            elapsed_seconds: 0.0,
+            inner: build_simulation(
+                reservoir_matrix,
+                depths,
+                bedrock_indices,
+                &[source],
+                &config,
+            )?,
+        })
+    }
+
+    /// Run the simulation to completion and return the snapshots.
+    /// If interrupted with Ctrl+C the state is kept, so calling run() again resumes the simulation.
+    /// The optional progress callback is called with (fraction_done, cells_filled, snapshot_index) every `progress_interval` filled cells.
+    #[pyo3(signature = (progress_callback = None, progress_interval = 1000))]
+    fn run(
+        &mut self,
+        py: Python<'_>,
+        progress_callback: Option<Py<PyAny>>,
+        progress_interval: usize,
+    ) -> PyResult<Py<PyArray3<i32>>> {
+        let start = Instant::now();
+        let result = run_with_progress(
+            py,
+            &mut self.inner,
+            progress_callback,
+            progress_interval,
+            None,
+        );
+        self.elapsed_seconds += start.elapsed().as_secs_f64();
+        result?;
+        Ok(self.result(py))
+    }
+
+    /// Advance until `n_cells` more cells are filled. Returns False when the simulation is finished.
+    #[pyo3(signature = (n_cells = 1))]
+    fn step(&mut self, n_cells: usize) -> bool {
+        let start = Instant::now();
+        let running = self.inner.advance(n_cells);
+        self.elapsed_seconds += start.elapsed().as_secs_f64();
+        running
+    }
+
+    /// Save the full state of the simulation, so a long run can be continued later with
+    /// `Simulation.restore(path)`, e.g. after a crash or in stages on a batch system
+    fn save_checkpoint(&self, path: PathBuf) -> PyResult<()> {
+        save_checkpoint(
+            &path,
+            &self.inner,
+            self.elapsed_seconds,
+            &self.input_checksums,
+        )
+        .map_err(|err| PyIOError::new_err(err.to_string()))
+    }
+
+    /// Restore a simulation saved with `save_checkpoint`. Checkpoints of other versions are rejected.
+    #[staticmethod]
+    fn restore(path: PathBuf) -> PyResult<Self> {
+        let checkpoint =
+            load_checkpoint(&path).map_err(|err| match err.downcast::<SimulationError>() {
+                Ok(err) => PyErr::from(*err),
+                Err(err) => PyIOError::new_err(err.to_string()),
+            })?;
+        Ok(PySimulation {
+            inner: checkpoint.simulation,
+            input_checksums: checkpoint.input_checksums,
+            elapsed_seconds: checkpoint.elapsed_seconds,
+        })
+    }
+
+    /// A copy of the simulation rewound to the start of the given snapshot, to run on from there with other
+    /// parameters, e.g. another max_column_height just before a breach. The run up to the snapshot is replayed
+    /// from the start, unless the simulation was created with rewind_points=True and keeps a full copy of the
+    /// state at every snapshot.
+    fn rewound(&self, snapshot_index: i32) -> PyResult<Self> {
+        Ok(PySimulation {
+            inner: self.inner.rewound(snapshot_index)?,
+            input_checksums: self.input_checksums.clone(),
+            elapsed_seconds: 0.0,
+        })
+    }
+
+    /// A copy of the simulation with the given ((x, y, z), velocity) edits of the grid, rewound to before the
+    /// first snapshot the edits can affect, to run on from there instead of re-running on the edited grid
+    fn with_edited_cells(&self, edits: Vec<((usize, usize, usize), f64)>) -> PyResult<Self> {
+        // The reservoir matrix is no longer the input
+        let mut input_checksums = self.input_checksums.clone();
+        input_checksums.remove("reservoir_matrix");
+        Ok(PySimulation {
+            inner: self.inner.with_edited_cells(&edits)?,
+            input_checksums,
+            elapsed_seconds: 0.0,
+        })
+    }
+
+    /// A copy of the simulation to run a what-if scenario on from the current point, with new wells starting here
+    /// and rate changes as (ISO date, cells per day) replacing the rest of the injection schedule
+    #[pyo3(signature = (new_wells = None, rate_changes = None))]
+    fn branch(
+        &self,
+        new_wells: Option<Vec<(usize, usize, usize)>>,
+        rate_changes: Option<Vec<(String, f64)>>,
+    ) -> PyResult<Self> {
+        let scenario = Scenario {
+            new_wells: new_wells.unwrap_or_default(),
+            rate_changes: rate_changes
+                .map(|changes| parse_rate_changes(changes, "rate_changes"))
+                .transpose()?
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        Ok(PySimulation {
+            inner: scenario.branch(&self.inner)?,
+            input_checksums: self.input_checksums.clone(),
+            elapsed_seconds: self.elapsed_seconds,
+        })
+    }
+
+    /// The max column height below the caprock, or None if the caprock never breaks. Can be changed during a run.
+    #[getter]
+    fn get_max_column_height(&self) -> Option<usize> {
+        self.inner.config().max_column_height
+    }
+
+    #[setter]
+    fn set_max_column_height(&mut self, max_column_height: Option<usize>) -> PyResult<()> {
+        let config = SimulationConfig {
+            max_column_height,
+            ..self.inner.config().clone()
+        };
+        Ok(self.inner.update_config(config)?)
+    }
+
+    /// The snapshots so far. Cells not yet filled are -1.
+    fn result(&self, py: Python<'_>) -> Py<PyArray3<i32>> {
+        PyArray3::from_array(py, &self.inner.snapshots()).into()
+    }
+
+    /// The current CO2 saturation of every cell
+    fn saturation(&self, py: Python<'_>) -> Py<PyArray3<f32>> {
+        PyArray3::from_owned_array(py, self.inner.saturation()).into()
+    }
+
+    /// The current velocity model with CO2 filled cells and broken caprock
+    fn velocity_model(&self, py: Python<'_>) -> Py<PyArray3<f64>> {
+        PyArray3::from_array(py, &self.inner.reservoir_matrix()).into()
+    }
+
+    /// Write the boundary of the plume at the given snapshot (default: the current one) as a triangle mesh.
+    /// The format is picked from the extension of the path: .obj, .ply or .vtk.
+    /// The run metadata is written next to it, e.g. plume.vtk.json.
+    #[pyo3(signature = (path, snapshot = None))]
+    fn export_plume_mesh(&self, path: PathBuf, snapshot: Option<i32>) -> PyResult<()> {
+        let snapshot = snapshot.unwrap_or(self.inner.snapshot_index());
+        self.inner
+            .plume_mesh(snapshot)
+            .write_file(&path)
+            .map_err(|err| PyIOError::new_err(err.to_string()))?;
+        let mut metadata = self.metadata();
+        metadata.add_statistic("mesh_snapshot", serde_json::json!(snapshot));
+        metadata
+            .write_sidecar(&path)
+            .map_err(|err| PyIOError::new_err(err.to_string()))?;
+        Ok(())
+    }
+
+    /// Save the snapshots so far, the headline statistics and the config to a MATLAB v7.3 MAT-file
+    fn export_mat(&self, path: PathBuf) -> PyResult<()> {
+        save_mat(&path, &self.inner.snapshots().view(), &self.metadata())
+            .map_err(|err| PyIOError::new_err(err.to_string()))
+    }
+
+    /// Save every filled cell so far as a row of a Parquet table with the columns x, y, z, depth, well_id,
+    /// fill_order, snapshot and trapping_state, e.g. to query large results with pandas or SQL
+    fn export_cell_events(&self, path: PathBuf) -> PyResult<()> {
+        write_cell_events_parquet(&path, &self.inner.cell_events(), &self.metadata())
+            .map_err(|err| PyIOError::new_err(err.to_string()))
+    }
+
+    /// The filled cells so far as Arrow record batches with the columns of `export_cell_events`, handed over
+    /// without copying, e.g. `pyarrow.table(simulation.cell_events_arrow())`
+    fn cell_events_arrow(&self) -> PyResult<ArrowStream> {
+        let schema = cell_event_schema(&self.metadata());
+        let batches = cell_event_batches(&self.inner.cell_events(), &schema)
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(ArrowStream::new(schema, batches))
+    }
+
+    /// Save the snapshots so far to a GRDECL file with the gas saturation of every report step, SGAS0000 for the
+    /// first snapshot and so on, for ResInsight. On a regular grid the grid geometry is written as well, with cells
+    /// of cell_size (dx, dy) in meters. On a corner-point grid the properties can be imported onto the original grid.
+    #[pyo3(signature = (path, cell_size = (1.0, 1.0)))]
+    fn export_grdecl(&self, path: PathBuf, cell_size: (f64, f64)) -> PyResult<()> {
+        let depths = match self.inner.grid() {
+            AnyGrid::Regular(grid) => Some(grid.depths().view()),
+            AnyGrid::CornerPoint(_) => None,
+        };
+        save_grdecl(&path, &self.inner.snapshots().view(), depths, cell_size)
+            .map_err(|err| PyIOError::new_err(err.to_string()))
+    }
+
+    /// Dissolve CO2 from the base of the plume for `n_steps` post-injection steps, dissolving `rate` of a cell per step
+    /// for every cell face where the plume rests on brine. A positive `mineralization_rate` turns that fraction of the
+    /// dissolved CO2 into minerals every step, and a positive `residual_saturation` is left behind in the cells the
+    /// plume gives up. Returns a dict with the mobile, residual, dissolved and mineralized CO2 (in cells) and the
+    /// contact area at the end of injection and after every step, and the saturation and cell history after the last step.
+    /// The simulation itself is not changed.
+    #[pyo3(signature = (rate, n_steps, mineralization_rate = 0.0, residual_saturation = 0.0))]
+    fn convective_dissolution(
+        &self,
+        py: Python<'_>,
+        rate: f64,
+        n_steps: usize,
+        mineralization_rate: f64,
+        residual_saturation: f64,
+    ) -> PyResult<Py<PyDict>> {
+        let (mineral_trapping, hysteresis) =
+            post_injection_models(mineralization_rate, residual_saturation);
+        let history = self.inner.convective_dissolution(
+            &ConvectiveDissolution { rate },
+            n_steps,
+            mineral_trapping.as_ref(),
+            hysteresis.as_ref(),
+        )?;
+        dissolution_history_dict(py, history)
+    }
+
+    /// Like `convective_dissolution`, but keeps the result in the simulation. The cells the plume has left hold
+    /// only residual CO2, and stepping on afterwards lets the front invade them again.
+    #[pyo3(signature = (rate, n_steps, mineralization_rate = 0.0, residual_saturation = 0.0))]
+    fn relax(
+        &mut self,
+        py: Python<'_>,
+        rate: f64,
+        n_steps: usize,
+        mineralization_rate: f64,
+        residual_saturation: f64,
+    ) -> PyResult<Py<PyDict>> {
+        let (mineral_trapping, hysteresis) =
+            post_injection_models(mineralization_rate, residual_saturation);
+        let history = self.inner.relax(
+            &ConvectiveDissolution { rate },
+            n_steps,
+            mineral_trapping.as_ref(),
+            hysteresis.as_ref(),
+        )?;
+        dissolution_history_dict(py, history)
+    }
+
+    /// The drainage and imbibition history of every cell: 0 pristine, 1 drainage, 2 imbibition, 3 secondary drainage
+    fn cell_history(&self, py: Python<'_>) -> Py<PyArray3<u8>> {
+        let history = self.inner.reservoir_state().history().to_dense();
+        PyArray3::from_owned_array(py, history.mapv(|h| h as u8)).into()
+    }
+
+    /// The run metadata as a JSON string: config, input checksums, crate version, timings and headline statistics
+    fn metadata_json(&self) -> String {
+        self.metadata().to_json().to_string()
+    }
+
+    #[getter]
+    fn finished(&self) -> bool {
+        self.inner.is_finished()
+    }
+
+    #[getter]
+    fn cells_filled(&self) -> usize {
+        self.inner.cells_filled()
+    }
+
+    /// Number of NaN cells in the input, which are treated as inactive
+    #[getter]
+    fn nan_cells(&self) -> usize {
+        self.inner.n_nan_cells()
+    }
+
+    /// Number of reservoir cells reachable from the source without breaking caprock
+    #[getter]
+    fn compartment_cells(&self) -> usize {
+        self.inner.source_compartment().compartment_cells
+    }
+
+    /// A warning when the source is sealed off from most of the reservoir, or None
+    #[getter]
+    fn compartment_warning(&self) -> Option<String> {
+        self.inner.source_compartment().warning()
+    }
+
+    #[getter]
+    fn fraction_filled(&self) -> f64 {
+        self.inner.fraction_filled()
+    }
+
+    #[getter]
+    fn snapshot_index(&self) -> i32 {
+        self.inner.snapshot_index()
+    }
+
+    #[getter]
+    fn current_depth_index(&self) -> usize {
+        self.inner.current_depth_index()
+    }
+
+    #[getter]
+    fn footprint_cells(&self) -> usize {
+        self.inner.footprint_cells()
+    }
+
+    #[getter]
+    fn plume_depth_range(&self) -> Option<(usize, usize)> {
+        self.inner.plume_depth_range()
+    }
+
+    /// Number of cells holding CO2 so far, each counted once
+    #[getter]
+    fn plume_cells(&self) -> usize {
+        self.inner.plume_cells()
+    }
+
+    /// Number of cells waiting in the front
+    #[getter]
+    fn frontier_size(&self) -> usize {
+        self.inner.frontier_size()
+    }
+
+    /// Fraction of the reservoir cells of every z layer that hold CO2 so far
+    fn layer_fill_fractions(&self, py: Python<'_>) -> Py<PyArray1<f64>> {
+        PyArray1::from_owned_array(py, self.inner.layer_fill_fractions()).into()
+    }
+
+    /// Whether the cell (x, y, z) holds CO2. Raises an IndexError for cells outside the grid.
+    fn is_filled(&self, cell: (usize, usize, usize)) -> PyResult<bool> {
+        let (nx, ny, nz) = self.inner.sparse_snapshots().dim();
+        if cell.0 >= nx || cell.1 >= ny || cell.2 >= nz {
+            return Err(PyIndexError::new_err(format!(
+                "cell {:?} is outside the grid of shape ({}, {}, {})",
+                cell, nx, ny, nz
+            )));
+        }
+        Ok(self.inner.is_filled(cell))
+    }
+}
+
+/// Iterator over the snapshots of a simulation, advancing the simulation lazily.
+/// Each item is (snapshot_index, cells) where cells is either an (n, 3) array of the cells filled in that snapshot,
+/// or the full fill-order array so far if `dense` is set.
+#[pyclass]
+pub struct SnapshotIterator {
+    simulation: Simulation,
+    dense: bool,
+    // Number of cells in the fill order already yielded
+    emitted: usize,
+}
+
+#[pymethods]
+impl SnapshotIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> Option<(i32, Py<PyAny>)> {
+        let snapshot_index = self.simulation.snapshot_index();
+
+        // Advance until the current snapshot is complete or the simulation is finished
+        let simulation = &mut self.simulation;
+        py.detach(|| while simulation.snapshot_index() == snapshot_index && simulation.step() {});
+
+        let new_cells = &self.simulation.fill_order()[self.emitted..];
+        if new_cells.is_empty() {
+            return None;
+        }
+
+        let frame = if self.dense {
+            PyArray3::from_array(py, &self.simulation.snapshots())
+                .into_any()
+                .unbind()
+        } else {
+            let cells = Array2::from_shape_fn((new_cells.len(), 3), |(i, j)| {
+                let (x, y, z) = new_cells[i];
+                [x, y, z][j] as i64
+            });
+            PyArray2::from_owned_array(py, cells).into_any().unbind()
+        };
+        self.emitted = self.simulation.fill_order().len();
+
+        Some((snapshot_index, frame))
+    }
+}
+
+/// Arrow record batches that Python libraries read through the Arrow PyCapsule interface, e.g. `pyarrow.table`,
+/// `polars.from_arrow` or `duckdb`. The buffers are shared with the consumer instead of serialized. Like any
+/// Arrow stream it can only be read once.
+#[pyclass]
+pub struct ArrowStream {
+    schema: SchemaRef,
+    // None once the stream has been read
+    batches: Option<Vec<RecordBatch>>,
+}
+
+impl ArrowStream {
+    fn new(schema: SchemaRef, batches: Vec<RecordBatch>) -> Self {
+        ArrowStream {
+            schema,
+            batches: Some(batches),
+        }
+    }
+}
+
+#[pymethods]
+impl ArrowStream {
+    /// The schema as an `arrow_schema` capsule
+    fn __arrow_c_schema__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyCapsule>> {
+        let schema = FFI_ArrowSchema::try_from(self.schema.as_ref())
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        PyCapsule::new(py, schema, Some(CString::new("arrow_schema")?))
+    }
+
+    /// The record batches as an `arrow_array_stream` capsule. The consumer takes the stream over, and the
+    /// capsule releases it if it is never read. Casting to a requested schema is left to the consumer.
+    #[pyo3(signature = (requested_schema = None))]
+    fn __arrow_c_stream__<'py>(
+        &mut self,
+        py: Python<'py>,
+        requested_schema: Option<Bound<'py, PyAny>>,
+    ) -> PyResult<Bound<'py, PyCapsule>> {
+        let _ = requested_schema;
+        let batches = self
+            .batches
+            .take()
+            .ok_or_else(|| PyValueError::new_err("the Arrow stream has already been read"))?;
+        let reader = RecordBatchIterator::new(batches.into_iter().map(Ok), self.schema.clone());
+        PyCapsule::new(
+            py,
+            FFI_ArrowArrayStream::new(Box::new(reader)),
+            Some(CString::new("arrow_array_stream")?),
+        )
+    }
+}
+
+/// Create a lazy iterator over the snapshots of an injection simulation
+#[pyfunction]
+#[pyo3(signature = (reservoir_matrix, depths, bedrock_indices, max_column_height, source, total_snapshots = 100, dense = false, velocity_tolerance = 0.0))]
+#[allow(clippy::too_many_arguments)]
+pub fn _injection_simulation_iterator(
+    reservoir_matrix: FloatArray<'_, Ix3>,
+    depths: FloatArray<'_, Ix1>,
+    bedrock_indices: Option<IndexArray<'_, Ix2>>,
+    max_column_height: Option<usize>,
+    source: (usize, usize, usize),
+    total_snapshots: usize,
+    dense: bool,
+    velocity_tolerance: f64,
+) -> PyResult<SnapshotIterator> {
+    let config = SimulationConfig {
         max_column_height,
+        total_snapshots,
+        velocity_classifier: velocity_classifier(velocity_tolerance),
+        ..Default::default()
+    };
+    let simulation = build_simulation(
+        reservoir_matrix,
+        depths,
+        bedrock_indices,
+        &[source],
+        &config,
+    )?;
+
+    Ok(SnapshotIterator {
+        simulation,
+        dense,
+        emitted: 0,
+    })
+}
+
+/// Run the flood-fill baseline engine, see `run_flood_fill`
+#[pyfunction]
+#[pyo3(signature = (reservoir_matrix, depths, bedrock_indices, source, total_snapshots = 100, velocity_tolerance = 0.0))]
+pub fn _flood_fill_python_wrapper(
+    py: Python<'_>,
+    reservoir_matrix: FloatArray<'_, Ix3>,
+    depths: FloatArray<'_, Ix1>,
+    bedrock_indices: Option<IndexArray<'_, Ix2>>,
+    source: Sources,
+    total_snapshots: usize,
+    velocity_tolerance: f64,
+) -> PyResult<Py<PyArray3<i32>>> {
+    let config = SimulationConfig {
+        total_snapshots,
+        velocity_classifier: velocity_classifier(velocity_tolerance),
+        ..Default::default()
+    };
+    let reservoir_matrix = reservoir_matrix.as_f64();
+    let bedrock_indices = resolve_bedrock_indices(
+        bedrock_indices,
+        &reservoir_matrix.view(),
+        config.velocity_classifier,
+    )?;
+    let snapshots = run_flood_fill(
+        reservoir_matrix.view(),
+        depths.as_f64().view(),
+        bedrock_indices.view(),
+        &source.0,
+        &config,
+    )?;
+    Ok(PyArray3::from_owned_array(py, snapshots).unbind())
+}
+
+/// Run the simulation on a vertical cross-section, see `simulate_cross_section`. The bedrock indices are computed
+/// from the section if not given.
+#[pyfunction]
+#[pyo3(signature = (reservoir_matrix, depths, bedrock_indices, max_column_height, source, total_snapshots = 100, velocity_tolerance = 0.0))]
+#[allow(clippy::too_many_arguments)]
+pub fn _cross_section_python_wrapper(
+    py: Python<'_>,
+    reservoir_matrix: FloatArray<'_, Ix2>,
+    depths: FloatArray<'_, Ix1>,
+    bedrock_indices: Option<IndexArray<'_, Ix1>>,
+    max_column_height: Option<usize>,
+    source: (usize, usize),
+    total_snapshots: usize,
+    velocity_tolerance: f64,
+) -> PyResult<Py<PyArray2<i32>>> {
+    let config = SimulationConfig {
+        max_column_height,
+        total_snapshots,
+        velocity_classifier: velocity_classifier(velocity_tolerance),
+        ..Default::default()
+    };
+    let reservoir_matrix = reservoir_matrix.as_f64();
+    let bedrock_indices = match bedrock_indices {
+        Some(bedrock_indices) => bedrock_indices.to_usize("bedrock_indices")?,
+        None => compute_bedrock_indices(
+            &reservoir_matrix.view().insert_axis(Axis(1)),
+            config.velocity_classifier,
+        )
+        .remove_axis(Axis(1)),
+    };
+    let snapshots = simulate_cross_section(
+        reservoir_matrix.view(),
+        depths.as_f64().view(),
+        bedrock_indices.view(),
         source,
+        &config,
+    )?;
+    Ok(PyArray2::from_owned_array(py, snapshots).unbind())
+}
+
+/// Run the Darcy-flow engine, see `DarcyFlow`. Returns the snapshots, or a dict with the final saturation
+/// and pressure as well if return_extras is set.
+#[pyfunction]
+#[pyo3(signature = (reservoir_matrix, depths, bedrock_indices, source, permeability, porosity, cell_size, injection_rate, injection_time, total_snapshots = 100, fluid_properties = None, saturation_threshold = 0.05, velocity_tolerance = 0.0, return_extras = false))]
+#[allow(clippy::too_many_arguments)]
+pub fn _darcy_flow_python_wrapper(
+    py: Python<'_>,
+    reservoir_matrix: FloatArray<'_, Ix3>,
+    depths: FloatArray<'_, Ix1>,
+    bedrock_indices: Option<IndexArray<'_, Ix2>>,
+    source: Sources,
+    permeability: FloatArray<'_, Ix3>,
+    porosity: FloatArray<'_, Ix3>,
+    cell_size: (f64, f64, f64),
+    injection_rate: f64,
+    injection_time: f64,
+    total_snapshots: usize,
+    fluid_properties: Option<Bound<'_, PyDict>>,
+    saturation_threshold: f64,
+    velocity_tolerance: f64,
+    return_extras: bool,
+) -> PyResult<Py<PyAny>> {
+    let config = SimulationConfig {
         total_snapshots,
-    );
+        velocity_classifier: velocity_classifier(velocity_tolerance),
+        ..Default::default()
+    };
+    let reservoir_matrix = reservoir_matrix.as_f64();
+    let bedrock_indices = resolve_bedrock_indices(
+        bedrock_indices,
+        &reservoir_matrix.view(),
+        config.velocity_classifier,
+    )?;
+    let model = DarcyFlow {
+        permeability: permeability.as_f64().into_owned(),
+        porosity: porosity.as_f64().into_owned(),
+        cell_size,
+        fluids: fluid_properties
+            .map(|properties| parse_fluid_properties(&properties))
+            .transpose()?
+            .unwrap_or_default(),
+        injection_rate,
+        injection_time,
+        saturation_threshold,
+    };
+    let result = model.run(
+        reservoir_matrix.view(),
+        depths.as_f64().view(),
+        bedrock_indices.view(),
+        &source.0,
+        &config,
+    )?;
 
-    // Return the snapshots as a Python array
-    Ok(PyArray3::from_array(py, &snapshots).into())
+    let snapshots = PyArray3::from_owned_array(py, result.snapshots);
+    if !return_extras {
+        return Ok(snapshots.into_any().unbind());
+    }
+    let results = PyDict::new(py);
+    results.set_item("snapshots", snapshots)?;
+    results.set_item(
+        "saturation",
+        PyArray3::from_owned_array(py, result.saturation),
+    )?;
+    results.set_item("pressure", PyArray3::from_owned_array(py, result.pressure))?;
+    results.set_item("time_steps", result.time_steps)?;
+    Ok(results.into_any().unbind())
+}
+
+/// Write an OPM Flow deck of the scenario of a Darcy-flow run with the same arguments, see `save_opm_deck`
+#[pyfunction]
+#[pyo3(signature = (path, reservoir_matrix, depths, bedrock_indices, source, permeability, porosity, cell_size, injection_rate, injection_time, total_snapshots = 100, fluid_properties = None, velocity_tolerance = 0.0))]
+#[allow(clippy::too_many_arguments)]
+pub fn _opm_deck_python_wrapper(
+    path: PathBuf,
+    reservoir_matrix: FloatArray<'_, Ix3>,
+    depths: FloatArray<'_, Ix1>,
+    bedrock_indices: Option<IndexArray<'_, Ix2>>,
+    source: Sources,
+    permeability: FloatArray<'_, Ix3>,
+    porosity: FloatArray<'_, Ix3>,
+    cell_size: (f64, f64, f64),
+    injection_rate: f64,
+    injection_time: f64,
+    total_snapshots: usize,
+    fluid_properties: Option<Bound<'_, PyDict>>,
+    velocity_tolerance: f64,
+) -> PyResult<()> {
+    let config = SimulationConfig {
+        total_snapshots,
+        velocity_classifier: velocity_classifier(velocity_tolerance),
+        ..Default::default()
+    };
+    let reservoir_matrix = reservoir_matrix.as_f64();
+    let bedrock_indices = resolve_bedrock_indices(
+        bedrock_indices,
+        &reservoir_matrix.view(),
+        config.velocity_classifier,
+    )?;
+    let model = DarcyFlow {
+        permeability: permeability.as_f64().into_owned(),
+        porosity: porosity.as_f64().into_owned(),
+        cell_size,
+        fluids: fluid_properties
+            .map(|properties| parse_fluid_properties(&properties))
+            .transpose()?
+            .unwrap_or_default(),
+        injection_rate,
+        injection_time,
+        // Only used for the snapshots of a run
+        saturation_threshold: 1.0,
+    };
+    save_opm_deck(
+        &path,
+        &model,
+        reservoir_matrix.view(),
+        depths.as_f64().view(),
+        bedrock_indices.view(),
+        &source.0,
+        &config,
+    )
+    .map_err(|err| match err.downcast::<SimulationError>() {
+        Ok(err) => PyErr::from(*err),
+        Err(err) => PyIOError::new_err(err.to_string()),
+    })
+}
+
+/// Run the invasion-percolation engine, see `InvasionPercolation`. The entry pressures are either given
+/// or generated as a correlated random field.
+#[pyfunction]
+#[pyo3(signature = (reservoir_matrix, depths, bedrock_indices, source, entry_pressures = None, density_difference = 0.0, total_snapshots = 100, velocity_tolerance = 0.0, random_thresholds = None))]
+#[allow(clippy::too_many_arguments)]
+pub fn _invasion_percolation_python_wrapper(
+    py: Python<'_>,
+    reservoir_matrix: FloatArray<'_, Ix3>,
+    depths: FloatArray<'_, Ix1>,
+    bedrock_indices: Option<IndexArray<'_, Ix2>>,
+    source: (usize, usize, usize),
+    entry_pressures: Option<FloatArray<'_, Ix3>>,
+    density_difference: f64,
+    total_snapshots: usize,
+    velocity_tolerance: f64,
+    random_thresholds: Option<Bound<'_, PyDict>>,
+) -> PyResult<Py<PyArray3<i32>>> {
+    let config = SimulationConfig {
+        total_snapshots,
+        velocity_classifier: velocity_classifier(velocity_tolerance),
+        ..Default::default()
+    };
+    let reservoir_matrix = reservoir_matrix.as_f64();
+    let bedrock_indices = resolve_bedrock_indices(
+        bedrock_indices,
+        &reservoir_matrix.view(),
+        config.velocity_classifier,
+    )?;
+    let model = match (entry_pressures, random_thresholds) {
+        (Some(entry_pressures), None) => InvasionPercolation {
+            entry_pressures: entry_pressures.as_f64().into_owned(),
+            density_difference,
+        },
+        (None, Some(properties)) => InvasionPercolation::with_random_thresholds(
+            reservoir_matrix.dim(),
+            &parse_random_thresholds(&properties)?,
+            density_difference,
+        ),
+        _ => {
+            return Err(PyValueError::new_err(
+                "give exactly one of entry_pressures and random_thresholds",
+            ))
+        }
+    };
+    let snapshots = model.run(
+        reservoir_matrix.view(),
+        depths.as_f64().view(),
+        bedrock_indices.view(),
+        source,
+        &config,
+    )?;
+    Ok(PyArray3::from_owned_array(py, snapshots).unbind())
+}
+
+/// Run the particle-tracking engine, see `ParticleTracking`. Returns the snapshots, or a dict with the
+/// saturation of every snapshot as well if return_extras is set.
+#[pyfunction]
+#[pyo3(signature = (reservoir_matrix, depths, bedrock_indices, source, n_particles = 10000, particles_per_cell = 10, buoyancy = 0.9, dispersion = 0.5, max_steps = 1000, seed = 0, total_snapshots = 100, velocity_tolerance = 0.0, return_extras = false))]
+#[allow(clippy::too_many_arguments)]
+pub fn _particle_tracking_python_wrapper(
+    py: Python<'_>,
+    reservoir_matrix: FloatArray<'_, Ix3>,
+    depths: FloatArray<'_, Ix1>,
+    bedrock_indices: Option<IndexArray<'_, Ix2>>,
+    source: (usize, usize, usize),
+    n_particles: usize,
+    particles_per_cell: u32,
+    buoyancy: f64,
+    dispersion: f64,
+    max_steps: usize,
+    seed: u64,
+    total_snapshots: usize,
+    velocity_tolerance: f64,
+    return_extras: bool,
+) -> PyResult<Py<PyAny>> {
+    let config = SimulationConfig {
+        total_snapshots,
+        velocity_classifier: velocity_classifier(velocity_tolerance),
+        ..Default::default()
+    };
+    let reservoir_matrix = reservoir_matrix.as_f64();
+    let bedrock_indices = resolve_bedrock_indices(
+        bedrock_indices,
+        &reservoir_matrix.view(),
+        config.velocity_classifier,
+    )?;
+    let model = ParticleTracking {
+        n_particles,
+        particles_per_cell,
+        buoyancy,
+        dispersion,
+        max_steps,
+        seed,
+    };
+    let result = model.run(
+        reservoir_matrix.view(),
+        depths.as_f64().view(),
+        bedrock_indices.view(),
+        source,
+        &config,
+    )?;
+
+    let snapshots = PyArray3::from_owned_array(py, result.snapshots);
+    if !return_extras {
+        return Ok(snapshots.into_any().unbind());
+    }
+    let saturation = PyList::empty(py);
+    for grid in &result.saturation {
+        saturation.append(PyArray3::from_owned_array(py, grid.to_dense()))?;
+    }
+    let results = PyDict::new(py);
+    results.set_item("snapshots", snapshots)?;
+    results.set_item("saturation", saturation)?;
+    results.set_item("particles_settled", result.particles_settled)?;
+    Ok(results.into_any().unbind())
+}
+
+/// Run the column fill, see `ColumnFill`. Returns the snapshots, or a dict with the saturation of every snapshot
+/// and the stored volume as well if return_extras is set.
+#[pyfunction]
+#[pyo3(signature = (reservoir_matrix, depths, bedrock_indices, source, injected_volume, residual_brine_saturation = 0.2, entry_pressure = 5e3, pore_size_index = 2.0, density_difference = 400.0, total_snapshots = 100, velocity_tolerance = 0.0, return_extras = false))]
+#[allow(clippy::too_many_arguments)]
+pub fn _column_fill_python_wrapper(
+    py: Python<'_>,
+    reservoir_matrix: FloatArray<'_, Ix3>,
+    depths: FloatArray<'_, Ix1>,
+    bedrock_indices: Option<IndexArray<'_, Ix2>>,
+    source: (usize, usize, usize),
+    injected_volume: f64,
+    residual_brine_saturation: f64,
+    entry_pressure: f64,
+    pore_size_index: f64,
+    density_difference: f64,
+    total_snapshots: usize,
+    velocity_tolerance: f64,
+    return_extras: bool,
+) -> PyResult<Py<PyAny>> {
+    let config = SimulationConfig {
+        total_snapshots,
+        velocity_classifier: velocity_classifier(velocity_tolerance),
+        ..Default::default()
+    };
+    let reservoir_matrix = reservoir_matrix.as_f64();
+    let bedrock_indices = resolve_bedrock_indices(
+        bedrock_indices,
+        &reservoir_matrix.view(),
+        config.velocity_classifier,
+    )?;
+    let model = ColumnFill {
+        injected_volume,
+        residual_brine_saturation,
+        entry_pressure,
+        pore_size_index,
+        density_difference,
+    };
+    let result = model.run(
+        reservoir_matrix.view(),
+        depths.as_f64().view(),
+        bedrock_indices.view(),
+        source,
+        &config,
+    )?;
+
+    let snapshots = PyArray3::from_owned_array(py, result.snapshots);
+    if !return_extras {
+        return Ok(snapshots.into_any().unbind());
+    }
+    let saturation = PyList::empty(py);
+    for grid in &result.saturation {
+        saturation.append(PyArray3::from_owned_array(py, grid.to_dense()))?;
+    }
+    let results = PyDict::new(py);
+    results.set_item("snapshots", snapshots)?;
+    results.set_item("saturation", saturation)?;
+    results.set_item("stored_volume", result.stored_volume)?;
+    Ok(results.into_any().unbind())
+}
+
+/// Run the static trap analysis, see `analyze_traps`. Returns the top surface, the trap map and a list of
+/// traps as dicts.
+#[pyfunction]
+#[pyo3(signature = (reservoir_matrix, depths, bedrock_indices = None, velocity_tolerance = 0.0))]
+pub fn _trap_analysis_python_wrapper(
+    py: Python<'_>,
+    reservoir_matrix: FloatArray<'_, Ix3>,
+    depths: FloatArray<'_, Ix1>,
+    bedrock_indices: Option<IndexArray<'_, Ix2>>,
+    velocity_tolerance: f64,
+) -> PyResult<Py<PyAny>> {
+    let config = SimulationConfig {
+        velocity_classifier: velocity_classifier(velocity_tolerance),
+        ..Default::default()
+    };
+    let reservoir_matrix = reservoir_matrix.as_f64();
+    let bedrock_indices = resolve_bedrock_indices(
+        bedrock_indices,
+        &reservoir_matrix.view(),
+        config.velocity_classifier,
+    )?;
+    let analysis = analyze_traps(
+        reservoir_matrix.view(),
+        depths.as_f64().view(),
+        bedrock_indices.view(),
+        &config,
+    )?;
+
+    let traps = PyList::empty(py);
+    for trap in &analysis.traps {
+        let item = PyDict::new(py);
+        item.set_item("top_depth", trap.top_depth)?;
+        item.set_item("spill_depth", trap.spill_depth)?;
+        item.set_item("spill_point", trap.spill_point)?;
+        item.set_item("columns", trap.columns.clone())?;
+        item.set_item("volume", trap.volume)?;
+        item.set_item("spills_into", trap.spills_into)?;
+        item.set_item("sub_traps", trap.sub_traps.clone())?;
+        traps.append(item)?;
+    }
+    let results = PyDict::new(py);
+    results.set_item(
+        "top_surface",
+        PyArray2::from_owned_array(py, analysis.top_surface),
+    )?;
+    results.set_item(
+        "trap_map",
+        PyArray2::from_owned_array(py, analysis.trap_map),
+    )?;
+    results.set_item("traps", traps)?;
+    Ok(results.into_any().unbind())
+}
+
+/// Build a benchmark model, see `BenchmarkModel::build`. Returns the grid and the source as a dict with the
+/// arguments of `_injection_simulation_python_wrapper`.
+#[pyfunction]
+#[pyo3(signature = (name, resolution = 32))]
+pub fn _benchmark_model_python_wrapper(
+    py: Python<'_>,
+    name: &str,
+    resolution: usize,
+) -> PyResult<Py<PyAny>> {
+    let model: BenchmarkModel = name.parse().map_err(PyValueError::new_err)?;
+    let case = model.build(resolution)?;
+    let results = PyDict::new(py);
+    results.set_item(
+        "reservoir_matrix",
+        PyArray3::from_owned_array(py, case.grid.reservoir_matrix),
+    )?;
+    results.set_item("depths", PyArray1::from_owned_array(py, case.grid.depths))?;
+    results.set_item(
+        "bedrock_indices",
+        PyArray2::from_owned_array(py, case.grid.bedrock_indices),
+    )?;
+    results.set_item("source", case.source)?;
+    Ok(results.into_any().unbind())
+}
+
+/// Compare the simulated plume with a closed-form solution, see `validate_analytic`. Returns the report as JSON.
+#[pyfunction]
+#[pyo3(signature = (case, resolution = 64, n_points = 10, front_ordering = "depth"))]
+pub fn _analytic_validation_python_wrapper(
+    case: &str,
+    resolution: usize,
+    n_points: usize,
+    front_ordering: &str,
+) -> PyResult<String> {
+    let case: AnalyticCase = case.parse().map_err(PyValueError::new_err)?;
+    let config = SimulationConfig {
+        front_ordering: parse_front_ordering(front_ordering)?,
+        ..Default::default()
+    };
+    let report = validate_analytic(case, resolution, n_points, &config)?;
+    Ok(report.to_json().to_string())
+}
+
+/// Find and repair the pathologies of a grid, see `repair_grid`. Returns the repaired grid with its bedrock indices,
+/// and the issues as dicts.
+#[pyfunction]
+#[pyo3(signature = (reservoir_matrix, depths, velocity_tolerance = 0.0))]
+pub fn _repair_grid_python_wrapper(
+    py: Python<'_>,
+    reservoir_matrix: FloatArray<'_, Ix3>,
+    depths: FloatArray<'_, Ix1>,
+    velocity_tolerance: f64,
+) -> PyResult<Py<PyAny>> {
+    let classifier = velocity_classifier(velocity_tolerance);
+    let mut reservoir_matrix = reservoir_matrix.as_f64().into_owned();
+    let mut depths = depths.as_f64().into_owned();
+    let report = repair_grid(&mut reservoir_matrix, &mut depths, classifier);
+
+    let issues = PyList::empty(py);
+    for issue in &report.issues {
+        let item = PyDict::new(py);
+        item.set_item("kind", issue.kind.as_str())?;
+        item.set_item("cell", issue.cell)?;
+        item.set_item("before", issue.before)?;
+        item.set_item("after", issue.after)?;
+        item.set_item("repairable", issue.repairable)?;
+        issues.append(item)?;
+    }
+    let bedrock_indices = compute_bedrock_indices(&reservoir_matrix.view(), classifier);
+    let results = PyDict::new(py);
+    results.set_item(
+        "reservoir_matrix",
+        PyArray3::from_owned_array(py, reservoir_matrix),
+    )?;
+    results.set_item("depths", PyArray1::from_owned_array(py, depths))?;
+    results.set_item(
+        "bedrock_indices",
+        PyArray2::from_owned_array(py, bedrock_indices),
+    )?;
+    results.set_item("issues", issues)?;
+    Ok(results.into_any().unbind())
+}
+
+/// Replay a journal, see `JournalReplay`, to the given number of cells filled or the end of the given snapshot,
+/// or to the end. Returns the snapshots and well attribution at that point, with the state of the run.
+#[pyfunction]
+#[pyo3(signature = (journal_path, cells_filled = None, snapshot = None))]
+pub fn _replay_journal_python_wrapper(
+    py: Python<'_>,
+    journal_path: PathBuf,
+    cells_filled: Option<usize>,
+    snapshot: Option<i32>,
+) -> PyResult<Py<PyAny>> {
+    let mut replay = JournalReplay::load(&journal_path).map_err(|err| {
+        match err.downcast::<SimulationError>() {
+            Ok(err) => PyErr::from(*err),
+            Err(err) => PyIOError::new_err(err.to_string()),
+        }
+    })?;
+    match (cells_filled, snapshot) {
+        (Some(cells_filled), _) => replay.replay_to_cells(cells_filled),
+        (None, Some(snapshot)) => replay.replay_to_snapshot(snapshot),
+        (None, None) => replay.replay_all(),
+    };
+    let results = PyDict::new(py);
+    results.set_item(
+        "snapshots",
+        PyArray3::from_owned_array(py, replay.snapshots()),
+    )?;
+    results.set_item(
+        "well_attribution",
+        PyArray3::from_owned_array(py, replay.well_attribution()),
+    )?;
+    results.set_item("cells_filled", replay.cells_filled())?;
+    results.set_item("snapshot_index", replay.snapshot_index())?;
+    results.set_item("finished", replay.is_finished())?;
+    results.set_item("sources", replay.sources().to_vec())?;
+    results.set_item("layers", replay.layers().to_vec())?;
+    results.set_item("breaches", replay.n_breaches())?;
+    results.set_item("open_breaches", replay.open_breaches().to_vec())?;
+    Ok(results.into_any().unbind())
 }
 
 /// A Python module implemented in Rust.
+#[cfg(feature = "python")]
 #[pymodule]
 fn rust_backend(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(_injection_simulation_python_wrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(_injection_simulation_iterator, m)?)?;
+    m.add_function(wrap_pyfunction!(_darcy_flow_python_wrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(_opm_deck_python_wrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(_flood_fill_python_wrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(_cross_section_python_wrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(_invasion_percolation_python_wrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(_particle_tracking_python_wrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(_column_fill_python_wrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(_trap_analysis_python_wrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(_benchmark_model_python_wrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(_analytic_validation_python_wrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(_repair_grid_python_wrapper, m)?)?;
+    m.add_function(wrap_pyfunction!(_replay_journal_python_wrapper, m)?)?;
+    m.add_class::<PySimulation>()?;
+    m.add_class::<SnapshotIterator>()?;
+    m.add_class::<ArrowStream>()?;
+    m.add(
+        "SimulationInterrupted",
+        m.py().get_type::<SimulationInterrupted>(),
+    )?;
     Ok(())
 }
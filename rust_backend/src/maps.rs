@@ -0,0 +1,93 @@
+use numpy::ndarray::Array2;
+
+use crate::grid::Grid;
+use crate::plume::Plume;
+use crate::sparse::SparseGrid;
+
+/// The highest CO2 saturation in every (x, y) column. Columns without CO2 are 0.
+pub fn max_saturation_map(saturation: &SparseGrid<f32>) -> Array2<f32> {
+    let (nx, ny, _) = saturation.dim();
+    let mut map = Array2::zeros((nx, ny));
+    for ((x, y, _), value) in saturation.iter() {
+        let max = &mut map[[x, y]];
+        if value > *max {
+            *max = value;
+        }
+    }
+    map
+}
+
+/// The depth of the shallowest CO2 cell in every (x, y) column, or NaN for columns without CO2
+pub fn top_of_plume_depth_map(plume: &Plume, grid: &impl Grid) -> Array2<f64> {
+    let (nx, ny, _) = plume.dim();
+    let mut map = Array2::from_elem((nx, ny), f64::NAN);
+    for (cell, _) in plume.cells() {
+        let depth = grid.cell_depth(cell);
+        let top = &mut map[[cell.0, cell.1]];
+        if top.is_nan() || depth < *top {
+            *top = depth;
+        }
+    }
+    map
+}
+
+/// The snapshot at which CO2 first arrived in every (x, y) column, or -1 for columns without CO2
+pub fn first_arrival_map(plume: &Plume) -> Array2<i32> {
+    let (nx, ny, _) = plume.dim();
+    let mut map = Array2::from_elem((nx, ny), -1);
+    for ((x, y, _), snapshot) in plume.cells() {
+        let first = &mut map[[x, y]];
+        if *first < 0 || snapshot < *first {
+            *first = snapshot;
+        }
+    }
+    map
+}
+
+/// The number of CO2 cells in every (x, y) column
+pub fn thickness_map(plume: &Plume) -> Array2<usize> {
+    let (nx, ny, _) = plume.dim();
+    let mut map = Array2::zeros((nx, ny));
+    for ((x, y, _), _) in plume.cells() {
+        map[[x, y]] += 1;
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::RegularGrid;
+    use numpy::ndarray::{Array1, Array3};
+
+    #[test]
+    fn test_maps() {
+        let mut snapshots = Array3::from_elem((2, 2, 3), -1);
+        snapshots[[0, 0, 2]] = 0;
+        snapshots[[0, 0, 1]] = 3;
+        snapshots[[1, 1, 2]] = 1;
+        let plume = Plume::from_dense(&snapshots.view());
+        let grid = RegularGrid::new(2, 2, Array1::from(vec![100.0, 110.0, 120.0]).view());
+
+        let top = top_of_plume_depth_map(&plume, &grid);
+        assert_eq!(top[[0, 0]], 110.0);
+        assert_eq!(top[[1, 1]], 120.0);
+        assert!(top[[0, 1]].is_nan());
+
+        let first = first_arrival_map(&plume);
+        assert_eq!(first[[0, 0]], 0);
+        assert_eq!(first[[1, 1]], 1);
+        assert_eq!(first[[1, 0]], -1);
+
+        let thickness = thickness_map(&plume);
+        assert_eq!(thickness[[0, 0]], 2);
+        assert_eq!(thickness[[0, 1]], 0);
+
+        let mut saturation = SparseGrid::new((2, 2, 3), 0.0);
+        saturation.set((1, 0, 0), 0.4);
+        saturation.set((1, 0, 2), 0.9);
+        let max_saturation = max_saturation_map(&saturation);
+        assert_eq!(max_saturation[[1, 0]], 0.9);
+        assert_eq!(max_saturation[[0, 0]], 0.0);
+    }
+}
@@ -0,0 +1,592 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::DateTime;
+use numpy::ndarray::ArrayView3;
+use serde_json::Value;
+
+use crate::metadata::{RunMetadata, OUTPUT_SCHEMA_VERSION};
+
+// MATLAB v7.3 MAT-files are HDF5 files behind a 512 byte user block with the MAT-file header. There is no HDF5
+// library in the dependencies, so this writes the small subset of the HDF5 file format needed for a flat set of
+// variables: a version 0 superblock, version 1 object headers, old-style groups (a local heap, a version 1 B-tree
+// and symbol table nodes) and contiguous datasets. See the HDF5 file format specification for the layouts.
+
+const USER_BLOCK_SIZE: usize = 512;
+const SUPERBLOCK_SIZE: usize = 96;
+const UNDEFINED_ADDRESS: u64 = u64::MAX;
+/// The local heap marks an empty free list with offset 1
+const NO_FREE_BLOCK: u64 = 1;
+/// Half the entries of a symbol table node, and half the children of a group B-tree node
+const GROUP_LEAF_K: usize = 4;
+const GROUP_INTERNAL_K: usize = 16;
+const SYMBOL_TABLE_ENTRY_SIZE: usize = 40;
+/// Header, 2K children and 2K + 1 keys
+const GROUP_BTREE_NODE_SIZE: usize = 24 + 2 * GROUP_INTERNAL_K * 8 + (2 * GROUP_INTERNAL_K + 1) * 8;
+
+const MESSAGE_DATASPACE: u16 = 0x0001;
+const MESSAGE_DATATYPE: u16 = 0x0003;
+const MESSAGE_FILL_VALUE: u16 = 0x0005;
+const MESSAGE_LAYOUT: u16 = 0x0008;
+const MESSAGE_ATTRIBUTE: u16 = 0x000C;
+const MESSAGE_SYMBOL_TABLE: u16 = 0x0011;
+
+/// A MATLAB variable. Arrays hold their elements in MATLAB's column-major order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatValue {
+    Int32 {
+        dims: Vec<usize>,
+        data: Vec<i32>,
+    },
+    Double {
+        dims: Vec<usize>,
+        data: Vec<f64>,
+    },
+    Logical(bool),
+    /// A char row vector. Must not be empty.
+    Char(String),
+    /// A scalar struct of named fields
+    Struct(Vec<(String, MatValue)>),
+}
+
+impl MatValue {
+    pub fn scalar(value: f64) -> Self {
+        MatValue::Double {
+            dims: vec![1, 1],
+            data: vec![value],
+        }
+    }
+
+    /// The MATLAB value of a JSON statistic: numbers and arrays of numbers become doubles, booleans logicals and
+    /// strings chars. Other values are kept as their JSON text. Null and empty values have no MATLAB value here.
+    pub fn from_json(value: &Value) -> Option<Self> {
+        match value {
+            Value::Null => None,
+            Value::Bool(b) => Some(MatValue::Logical(*b)),
+            Value::Number(number) => number.as_f64().map(MatValue::scalar),
+            Value::String(text) if text.is_empty() => None,
+            Value::String(text) => Some(MatValue::Char(text.clone())),
+            Value::Array(items) if items.is_empty() => None,
+            Value::Array(items) if items.iter().all(Value::is_number) => Some(MatValue::Double {
+                dims: vec![1, items.len()],
+                data: items.iter().filter_map(Value::as_f64).collect(),
+            }),
+            other => Some(MatValue::Char(other.to_string())),
+        }
+    }
+
+    /// The MATLAB int32 array of a snapshots array, with the same (x, y, z) indices. The values are unchanged, so
+    /// unfilled cells are -1 and snapshots count from 0.
+    pub fn from_snapshots(snapshots: &ArrayView3<i32>) -> Self {
+        MatValue::Int32 {
+            dims: snapshots.shape().to_vec(),
+            // Iterating the transposed array gives column-major order
+            data: snapshots.t().iter().copied().collect(),
+        }
+    }
+}
+
+/// Write the snapshots, headline statistics and config of a run to a MATLAB v7.3 MAT-file with the variables
+/// `snapshots` (int32, nx x ny x nz), `statistics` (a struct), `config` (char), `sources` (n x 3 double, 0-based
+/// indices like the snapshots), `crate_version` (char) and `schema_version` (double), see `OUTPUT_SCHEMA_VERSION`
+pub fn save_mat(
+    path: &Path,
+    snapshots: &ArrayView3<i32>,
+    metadata: &RunMetadata,
+) -> std::io::Result<()> {
+    let statistics = metadata
+        .statistics
+        .iter()
+        .filter_map(|(name, value)| Some((name.clone(), MatValue::from_json(value)?)))
+        .collect();
+    let mut variables = vec![
+        ("snapshots".to_string(), MatValue::from_snapshots(snapshots)),
+        ("statistics".to_string(), MatValue::Struct(statistics)),
+        (
+            "crate_version".to_string(),
+            MatValue::Char(env!("CARGO_PKG_VERSION").to_string()),
+        ),
+        (
+            "schema_version".to_string(),
+            MatValue::scalar(OUTPUT_SCHEMA_VERSION as f64),
+        ),
+    ];
+    if let Some(config) = metadata.config.as_ref().filter(|config| !config.is_empty()) {
+        variables.push(("config".to_string(), MatValue::Char(config.clone())));
+    }
+    if !metadata.sources.is_empty() {
+        let n = metadata.sources.len();
+        let column = |axis: fn(&(usize, usize, usize)) -> usize| {
+            metadata
+                .sources
+                .iter()
+                .map(move |source| axis(source) as f64)
+        };
+        variables.push((
+            "sources".to_string(),
+            MatValue::Double {
+                dims: vec![n, 3],
+                data: column(|s| s.0)
+                    .chain(column(|s| s.1))
+                    .chain(column(|s| s.2))
+                    .collect(),
+            },
+        ));
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&mat_file_bytes(&variables))?;
+    writer.flush()
+}
+
+/// The bytes of a v7.3 MAT-file holding the variables
+pub fn mat_file_bytes(variables: &[(String, MatValue)]) -> Vec<u8> {
+    let mut builder = Hdf5Builder {
+        buffer: vec![0; SUPERBLOCK_SIZE],
+    };
+    let root = builder.write_group(variables, &[]);
+    let mut hdf5 = builder.buffer;
+
+    // The superblock, with every address but the end of file relative to the start of the HDF5 data
+    let mut superblock = Vec::with_capacity(SUPERBLOCK_SIZE);
+    superblock.extend_from_slice(b"\x89HDF\r\n\x1a\n");
+    // Versions of the superblock, free-space storage, root group symbol table entry and shared header formats,
+    // with the sizes of offsets and lengths
+    superblock.extend_from_slice(&[0, 0, 0, 0, 0, 8, 8, 0]);
+    push_u16(&mut superblock, GROUP_LEAF_K as u16);
+    push_u16(&mut superblock, GROUP_INTERNAL_K as u16);
+    push_u32(&mut superblock, 0);
+    push_u64(&mut superblock, USER_BLOCK_SIZE as u64);
+    push_u64(&mut superblock, UNDEFINED_ADDRESS);
+    push_u64(&mut superblock, (USER_BLOCK_SIZE + hdf5.len()) as u64);
+    push_u64(&mut superblock, UNDEFINED_ADDRESS);
+    // The root group entry caches the addresses of its B-tree and heap
+    push_u64(&mut superblock, 0);
+    push_u64(&mut superblock, root.header);
+    push_u32(&mut superblock, 1);
+    push_u32(&mut superblock, 0);
+    push_u64(&mut superblock, root.btree);
+    push_u64(&mut superblock, root.heap);
+    hdf5[..SUPERBLOCK_SIZE].copy_from_slice(&superblock);
+
+    let mut bytes = mat_header();
+    bytes.extend_from_slice(&hdf5);
+    bytes
+}
+
+/// The user block: the text of the MAT-file header, the version and the endian indicator
+fn mat_header() -> Vec<u8> {
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|d| DateTime::from_timestamp(d.as_secs() as i64, 0))
+        .map(|date| date.format("%a %b %e %H:%M:%S %Y").to_string())
+        .unwrap_or_default();
+    let text = format!(
+        "MATLAB 7.3 MAT-file, Platform: GLNXA64, Created on: {} HDF5 schema 1.00 .",
+        created
+    );
+    let mut header = vec![0; USER_BLOCK_SIZE];
+    header[..116].fill(b' ');
+    header[..text.len().min(116)].copy_from_slice(&text.as_bytes()[..text.len().min(116)]);
+    header[124..128].copy_from_slice(&[0x00, 0x02, b'I', b'M']);
+    header
+}
+
+/// The addresses of a written group
+struct GroupAddresses {
+    header: u64,
+    btree: u64,
+    heap: u64,
+}
+
+/// Appends HDF5 objects to a buffer that starts with space for the superblock. Addresses are offsets in the buffer.
+struct Hdf5Builder {
+    buffer: Vec<u8>,
+}
+
+impl Hdf5Builder {
+    fn address(&self) -> u64 {
+        self.buffer.len() as u64
+    }
+
+    fn align(&mut self) {
+        let padding = (8 - self.buffer.len() % 8) % 8;
+        self.buffer.extend(std::iter::repeat_n(0, padding));
+    }
+
+    fn write_value(&mut self, value: &MatValue) -> u64 {
+        match value {
+            MatValue::Int32 { dims, data } => {
+                let bytes: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes()).collect();
+                self.write_dataset(fixed_point_type(4, true), dims, &bytes, "int32", None)
+            }
+            MatValue::Double { dims, data } => {
+                let bytes: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes()).collect();
+                self.write_dataset(double_type(), dims, &bytes, "double", None)
+            }
+            MatValue::Logical(b) => self.write_dataset(
+                fixed_point_type(1, false),
+                &[1, 1],
+                &[*b as u8],
+                "logical",
+                Some(1),
+            ),
+            MatValue::Char(text) => {
+                let units: Vec<u16> = text.encode_utf16().collect();
+                let bytes: Vec<u8> = units.iter().flat_map(|v| v.to_le_bytes()).collect();
+                let dims = [1, units.len()];
+                self.write_dataset(fixed_point_type(2, false), &dims, &bytes, "char", Some(2))
+            }
+            MatValue::Struct(fields) => {
+                self.write_group(fields, &[string_attribute("MATLAB_class", "struct")])
+                    .header
+            }
+        }
+    }
+
+    /// Write the data and then the object header of a contiguous dataset. MATLAB dims are stored reversed, as
+    /// HDF5 is row-major.
+    fn write_dataset(
+        &mut self,
+        datatype: Vec<u8>,
+        dims: &[usize],
+        data: &[u8],
+        class: &str,
+        int_decode: Option<i32>,
+    ) -> u64 {
+        self.align();
+        let data_address = self.address();
+        self.buffer.extend_from_slice(data);
+
+        let hdf5_dims: Vec<usize> = dims.iter().rev().copied().collect();
+        let mut layout = vec![3, 1];
+        push_u64(&mut layout, data_address);
+        push_u64(&mut layout, data.len() as u64);
+        let mut messages = vec![
+            (MESSAGE_DATASPACE, dataspace(&hdf5_dims)),
+            (MESSAGE_DATATYPE, datatype),
+            // Version 2, allocated late, written if set, no fill value defined
+            (MESSAGE_FILL_VALUE, vec![2, 2, 2, 0]),
+            (MESSAGE_LAYOUT, layout),
+            string_attribute("MATLAB_class", class),
+        ];
+        if let Some(decode) = int_decode {
+            messages.push(attribute(
+                "MATLAB_int_decode",
+                fixed_point_type(4, true),
+                &decode.to_le_bytes(),
+            ));
+        }
+        self.write_object_header(&messages)
+    }
+
+    /// Write the members, the local heap with their names, the symbol table nodes and B-tree indexing them, and
+    /// finally the object header of the group
+    fn write_group(
+        &mut self,
+        members: &[(String, MatValue)],
+        attributes: &[(u16, Vec<u8>)],
+    ) -> GroupAddresses {
+        assert!(
+            members.len() <= 2 * GROUP_LEAF_K * 2 * GROUP_INTERNAL_K,
+            "too many members for a single B-tree node"
+        );
+        let mut entries: Vec<(&str, u64)> = members
+            .iter()
+            .map(|(name, value)| (name.as_str(), self.write_value(value)))
+            .collect();
+        entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+        // Offset 0 of the heap is the empty name, which is the first key of the B-tree
+        let mut heap_data = vec![0; 8];
+        let mut name_offsets = Vec::with_capacity(entries.len());
+        for (name, _) in &entries {
+            name_offsets.push(heap_data.len() as u64);
+            heap_data.extend_from_slice(name.as_bytes());
+            heap_data.push(0);
+            heap_data.resize(heap_data.len().div_ceil(8) * 8, 0);
+        }
+        self.align();
+        let heap = self.address();
+        self.buffer.extend_from_slice(b"HEAP\x00\x00\x00\x00");
+        push_u64(&mut self.buffer, heap_data.len() as u64);
+        push_u64(&mut self.buffer, NO_FREE_BLOCK);
+        push_u64(&mut self.buffer, heap + 32);
+        self.buffer.extend_from_slice(&heap_data);
+
+        // Symbol table nodes of up to 2K entries, each with the offset of its last name as the key
+        let mut nodes = Vec::new();
+        for (chunk, offsets) in entries
+            .chunks(2 * GROUP_LEAF_K)
+            .zip(name_offsets.chunks(2 * GROUP_LEAF_K))
+        {
+            self.align();
+            let node = self.address();
+            self.buffer.extend_from_slice(b"SNOD\x01\x00");
+            push_u16(&mut self.buffer, chunk.len() as u16);
+            for (&(_, header), &offset) in chunk.iter().zip(offsets) {
+                push_u64(&mut self.buffer, offset);
+                push_u64(&mut self.buffer, header);
+                // No cached scratch-pad data
+                self.buffer
+                    .extend_from_slice(&[0; SYMBOL_TABLE_ENTRY_SIZE - 16]);
+            }
+            let unused = (2 * GROUP_LEAF_K - chunk.len()) * SYMBOL_TABLE_ENTRY_SIZE;
+            self.buffer.extend(std::iter::repeat_n(0, unused));
+            nodes.push((node, *offsets.last().unwrap()));
+        }
+
+        self.align();
+        let btree = self.address();
+        let start = self.buffer.len();
+        // A leaf node of the group B-tree without siblings
+        self.buffer.extend_from_slice(b"TREE\x00\x00");
+        push_u16(&mut self.buffer, nodes.len() as u16);
+        push_u64(&mut self.buffer, UNDEFINED_ADDRESS);
+        push_u64(&mut self.buffer, UNDEFINED_ADDRESS);
+        push_u64(&mut self.buffer, 0);
+        for &(node, key) in &nodes {
+            push_u64(&mut self.buffer, node);
+            push_u64(&mut self.buffer, key);
+        }
+        self.buffer.resize(start + GROUP_BTREE_NODE_SIZE, 0);
+
+        let mut symbol_table = Vec::new();
+        push_u64(&mut symbol_table, btree);
+        push_u64(&mut symbol_table, heap);
+        let mut messages = vec![(MESSAGE_SYMBOL_TABLE, symbol_table)];
+        messages.extend_from_slice(attributes);
+        GroupAddresses {
+            header: self.write_object_header(&messages),
+            btree,
+            heap,
+        }
+    }
+
+    /// Write a version 1 object header with the messages, padding every message to 8 bytes
+    fn write_object_header(&mut self, messages: &[(u16, Vec<u8>)]) -> u64 {
+        let mut body = Vec::new();
+        for (kind, data) in messages {
+            let size = data.len().div_ceil(8) * 8;
+            push_u16(&mut body, *kind);
+            push_u16(&mut body, size as u16);
+            body.extend_from_slice(&[0; 4]);
+            body.extend_from_slice(data);
+            body.resize(body.len() + size - data.len(), 0);
+        }
+        self.align();
+        let address = self.address();
+        self.buffer.extend_from_slice(&[1, 0]);
+        push_u16(&mut self.buffer, messages.len() as u16);
+        // Reference count, size of the messages and padding to align them
+        push_u32(&mut self.buffer, 1);
+        push_u32(&mut self.buffer, body.len() as u32);
+        push_u32(&mut self.buffer, 0);
+        self.buffer.extend_from_slice(&body);
+        address
+    }
+}
+
+fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// A version 1 dataspace message of the dims, or of a scalar without dims
+fn dataspace(dims: &[usize]) -> Vec<u8> {
+    let mut message = vec![1, dims.len() as u8, 0, 0, 0, 0, 0, 0];
+    for &dim in dims {
+        push_u64(&mut message, dim as u64);
+    }
+    message
+}
+
+/// A little-endian integer datatype of the given size in bytes
+fn fixed_point_type(size: u32, signed: bool) -> Vec<u8> {
+    let mut message = vec![0x10, if signed { 0x08 } else { 0x00 }, 0, 0];
+    push_u32(&mut message, size);
+    push_u16(&mut message, 0);
+    push_u16(&mut message, (size * 8) as u16);
+    message
+}
+
+/// The little-endian IEEE 754 double datatype
+fn double_type() -> Vec<u8> {
+    let mut message = vec![0x11, 0x20, 0x3f, 0x00];
+    push_u32(&mut message, 8);
+    push_u16(&mut message, 0);
+    push_u16(&mut message, 64);
+    // Exponent location and size, mantissa location and size, and the exponent bias
+    message.extend_from_slice(&[52, 11, 0, 52]);
+    push_u32(&mut message, 1023);
+    message
+}
+
+/// A version 1 attribute message with a scalar value
+fn attribute(name: &str, datatype: Vec<u8>, value: &[u8]) -> (u16, Vec<u8>) {
+    let space = dataspace(&[]);
+    let mut message = vec![1, 0];
+    push_u16(&mut message, name.len() as u16 + 1);
+    push_u16(&mut message, datatype.len() as u16);
+    push_u16(&mut message, space.len() as u16);
+    for part in [&[name.as_bytes(), &[0]].concat(), &datatype, &space] {
+        message.extend_from_slice(part);
+        message.resize(message.len().div_ceil(8) * 8, 0);
+    }
+    message.extend_from_slice(value);
+    (MESSAGE_ATTRIBUTE, message)
+}
+
+/// An attribute holding a fixed-length ASCII string, like MATLAB_class
+fn string_attribute(name: &str, value: &str) -> (u16, Vec<u8>) {
+    let mut datatype = vec![0x13, 0x00, 0, 0];
+    push_u32(&mut datatype, value.len() as u32);
+    attribute(name, datatype, value.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use numpy::ndarray::Array3;
+    use serde_json::json;
+
+    fn read_u16(bytes: &[u8], at: usize) -> usize {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap()) as usize
+    }
+
+    fn read_u64(bytes: &[u8], at: usize) -> usize {
+        u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap()) as usize
+    }
+
+    /// Whether the data of a message holds the text, as messages are padded with zeros
+    fn holds(message: &[u8], text: &str) -> bool {
+        message
+            .windows(text.len())
+            .any(|window| window == text.as_bytes())
+    }
+
+    /// The messages of the object header at the address, as (type, data)
+    fn messages(hdf5: &[u8], address: usize) -> Vec<(u16, &[u8])> {
+        assert_eq!(hdf5[address], 1);
+        let mut at = address + 16;
+        (0..read_u16(hdf5, address + 2))
+            .map(|_| {
+                let size = read_u16(hdf5, at + 2);
+                let message = (read_u16(hdf5, at) as u16, &hdf5[at + 8..at + 8 + size]);
+                at += 8 + size;
+                message
+            })
+            .collect()
+    }
+
+    /// The (name, object header address) of the members of a group, by following its B-tree and heap
+    fn members(hdf5: &[u8], btree: usize, heap: usize) -> Vec<(String, usize)> {
+        assert_eq!(&hdf5[heap..heap + 4], b"HEAP");
+        let heap_data = read_u64(hdf5, heap + 24);
+        let name = |offset: usize| {
+            let start = heap_data + offset;
+            let end = start + hdf5[start..].iter().position(|&b| b == 0).unwrap();
+            String::from_utf8(hdf5[start..end].to_vec()).unwrap()
+        };
+        assert_eq!(&hdf5[btree..btree + 4], b"TREE");
+        let mut members = Vec::new();
+        for child in 0..read_u16(hdf5, btree + 6) {
+            let node = read_u64(hdf5, btree + 32 + 16 * child);
+            let key = read_u64(hdf5, btree + 40 + 16 * child);
+            assert_eq!(&hdf5[node..node + 4], b"SNOD");
+            let entries: Vec<(String, usize)> = (0..read_u16(hdf5, node + 6))
+                .map(|i| {
+                    let entry = node + 8 + SYMBOL_TABLE_ENTRY_SIZE * i;
+                    (name(read_u64(hdf5, entry)), read_u64(hdf5, entry + 8))
+                })
+                .collect();
+            assert_eq!(entries.last().unwrap().0, name(key));
+            members.extend(entries);
+        }
+        members
+    }
+
+    #[test]
+    fn test_mat_file_layout() {
+        let snapshots = Array3::from_shape_fn((2, 3, 4), |(x, y, z)| (x * 12 + y * 4 + z) as i32);
+        let statistics: Vec<(String, MatValue)> = (0..10)
+            .map(|i| (format!("statistic_{:02}", i), MatValue::scalar(i as f64)))
+            .collect();
+        let variables = vec![
+            (
+                "snapshots".to_string(),
+                MatValue::from_snapshots(&snapshots.view()),
+            ),
+            ("statistics".to_string(), MatValue::Struct(statistics)),
+            ("config".to_string(), MatValue::Char("abc".to_string())),
+        ];
+        let bytes = mat_file_bytes(&variables);
+        assert!(bytes.starts_with(b"MATLAB 7.3 MAT-file"));
+        assert_eq!(&bytes[126..128], b"IM");
+
+        let hdf5 = &bytes[USER_BLOCK_SIZE..];
+        assert_eq!(&hdf5[..8], b"\x89HDF\r\n\x1a\n");
+        assert_eq!(read_u64(hdf5, 24), USER_BLOCK_SIZE);
+        assert_eq!(read_u64(hdf5, 40), bytes.len());
+        let root = members(hdf5, read_u64(hdf5, 80), read_u64(hdf5, 88));
+        let names: Vec<&str> = root.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["config", "snapshots", "statistics"]);
+
+        // The snapshots have reversed dims and column-major data, so MATLAB sees the same (x, y, z) indices
+        let snapshot_messages = messages(hdf5, root[1].1);
+        let (_, space) = snapshot_messages[0];
+        assert_eq!(space[1], 3);
+        assert_eq!(
+            (read_u64(space, 8), read_u64(space, 16), read_u64(space, 24)),
+            (4, 3, 2)
+        );
+        let (kind, layout) = snapshot_messages[3];
+        assert_eq!(kind, MESSAGE_LAYOUT);
+        let data = &hdf5[read_u64(layout, 2)..read_u64(layout, 2) + read_u64(layout, 10)];
+        let values: Vec<i32> = data
+            .chunks(4)
+            .map(|b| i32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(values.len(), 24);
+        assert_eq!(&values[..3], &[0, 12, 4]);
+        let (kind, class) = snapshot_messages[4];
+        assert_eq!(kind, MESSAGE_ATTRIBUTE);
+        assert!(holds(class, "MATLAB_class") && holds(class, "int32"));
+
+        // The struct spans two symbol table nodes
+        let statistics = messages(hdf5, root[2].1);
+        let (kind, symbol_table) = statistics[0];
+        assert_eq!(kind, MESSAGE_SYMBOL_TABLE);
+        let fields = members(hdf5, read_u64(symbol_table, 0), read_u64(symbol_table, 8));
+        assert_eq!(fields.len(), 10);
+        assert_eq!(fields[9].0, "statistic_09");
+        assert!(holds(statistics[1].1, "struct"));
+    }
+
+    #[test]
+    fn test_statistics_to_mat_values() {
+        assert_eq!(MatValue::from_json(&json!(3)), Some(MatValue::scalar(3.0)));
+        assert_eq!(
+            MatValue::from_json(&json!(true)),
+            Some(MatValue::Logical(true))
+        );
+        assert_eq!(MatValue::from_json(&Value::Null), None);
+        assert_eq!(
+            MatValue::from_json(&json!([2, 5])),
+            Some(MatValue::Double {
+                dims: vec![1, 2],
+                data: vec![2.0, 5.0]
+            })
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::grid::Grid;
+use crate::plume::Plume;
+
+/// The file formats a mesh can be written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshFormat {
+    Obj,
+    Ply,
+    /// Legacy VTK polydata, readable by ParaView
+    Vtk,
+}
+
+impl MeshFormat {
+    /// Pick the format from the file extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "obj" => Some(MeshFormat::Obj),
+            "ply" => Some(MeshFormat::Ply),
+            "vtk" => Some(MeshFormat::Vtk),
+            _ => None,
+        }
+    }
+}
+
+/// A triangle mesh with vertices in (x index, y index, depth) coordinates.
+/// Triangles are wound counter-clockwise seen from outside the plume.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TriangleMesh {
+    pub vertices: Vec<[f64; 3]>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+// The six tetrahedra of a cube sharing the diagonal from corner 0 to corner 7. Corner i is at
+// (i & 1, (i >> 1) & 1, (i >> 2) & 1). Every cube is split the same way, so the faces of neighboring
+// cubes match and the surface has no cracks.
+const CUBE_TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 1, 3, 7],
+    [0, 1, 5, 7],
+    [0, 2, 3, 7],
+    [0, 2, 6, 7],
+    [0, 4, 5, 7],
+    [0, 4, 6, 7],
+];
+
+type Node = (i64, i64, i64);
+
+/// Extract the boundary of the plume at the given snapshot as a triangle mesh, using marching tetrahedra
+/// on the cell centers. The surface passes halfway between cells with and without CO2, and is closed
+/// along the edges of the grid.
+pub fn plume_mesh(plume: &Plume, grid: &impl Grid, snapshot: i32) -> TriangleMesh {
+    let (nx, ny, nz) = plume.dim();
+    let inside = |(x, y, z): Node| {
+        x >= 0
+            && y >= 0
+            && z >= 0
+            && plume
+                .snapshot_of(x as usize, y as usize, z as usize)
+                .is_some_and(|s| s <= snapshot)
+    };
+    // Nodes outside the grid take the position of the closest cell, so the surface is capped at the grid edges
+    let position = |(x, y, z): Node| {
+        let clamp = |v: i64, n: usize| v.clamp(0, n as i64 - 1) as usize;
+        let cell = (clamp(x, nx), clamp(y, ny), clamp(z, nz));
+        [cell.0 as f64, cell.1 as f64, grid.cell_depth(cell)]
+    };
+
+    // Only the cubes with a filled corner can contain the surface
+    let mut cubes = HashSet::new();
+    for ((x, y, z), filled_at) in plume.cells() {
+        if filled_at > snapshot {
+            continue;
+        }
+        for corner in 0..8 {
+            cubes.insert((
+                x as i64 - (corner & 1),
+                y as i64 - ((corner >> 1) & 1),
+                z as i64 - ((corner >> 2) & 1),
+            ));
+        }
+    }
+    // Sorted, so the mesh is the same on every run
+    let mut cubes: Vec<Node> = cubes.into_iter().collect();
+    cubes.sort_unstable();
+
+    let mut mesh = TriangleMesh::default();
+    // Vertices on the edge between two nodes are shared by all triangles using that edge
+    let mut edge_vertices: HashMap<(Node, Node), usize> = HashMap::new();
+    let mut vertex_on_edge = |mesh: &mut TriangleMesh, a: Node, b: Node| {
+        let key = if a < b { (a, b) } else { (b, a) };
+        *edge_vertices.entry(key).or_insert_with(|| {
+            let (pa, pb) = (position(a), position(b));
+            mesh.vertices.push([
+                0.5 * (pa[0] + pb[0]),
+                0.5 * (pa[1] + pb[1]),
+                0.5 * (pa[2] + pb[2]),
+            ]);
+            mesh.vertices.len() - 1
+        })
+    };
+
+    for (cx, cy, cz) in cubes {
+        let corners: Vec<Node> = (0..8)
+            .map(|i| (cx + (i & 1), cy + ((i >> 1) & 1), cz + ((i >> 2) & 1)))
+            .collect();
+        let filled: Vec<bool> = corners.iter().map(|&node| inside(node)).collect();
+        if filled.iter().all(|&f| f) {
+            continue;
+        }
+
+        for tetrahedron in CUBE_TETRAHEDRA {
+            let (mut ins, mut outs) = (Vec::new(), Vec::new());
+            for i in tetrahedron {
+                if filled[i] {
+                    ins.push(corners[i]);
+                } else {
+                    outs.push(corners[i]);
+                }
+            }
+            let polygon: Vec<(Node, Node)> = match (ins.len(), outs.len()) {
+                (1, 3) => outs.iter().map(|&o| (ins[0], o)).collect(),
+                (3, 1) => ins.iter().map(|&i| (i, outs[0])).collect(),
+                // The quad around the edge between the two filled nodes
+                (2, 2) => vec![
+                    (ins[0], outs[0]),
+                    (ins[0], outs[1]),
+                    (ins[1], outs[1]),
+                    (ins[1], outs[0]),
+                ],
+                _ => continue,
+            };
+
+            // Orient the triangles to face from the filled nodes to the empty ones, in index space
+            let centroid = |nodes: &[Node]| {
+                let n = nodes.len() as f64;
+                nodes.iter().fold([0.0; 3], |c, &(x, y, z)| {
+                    [
+                        c[0] + x as f64 / n,
+                        c[1] + y as f64 / n,
+                        c[2] + z as f64 / n,
+                    ]
+                })
+            };
+            let (ci, co) = (centroid(&ins), centroid(&outs));
+            let outward = [co[0] - ci[0], co[1] - ci[1], co[2] - ci[2]];
+            let midpoint = |(a, b): (Node, Node)| {
+                [
+                    0.5 * (a.0 + b.0) as f64,
+                    0.5 * (a.1 + b.1) as f64,
+                    0.5 * (a.2 + b.2) as f64,
+                ]
+            };
+
+            let ids: Vec<usize> = polygon
+                .iter()
+                .map(|&(a, b)| vertex_on_edge(&mut mesh, a, b))
+                .collect();
+            for k in 1..polygon.len() - 1 {
+                let (p0, p1, p2) = (
+                    midpoint(polygon[0]),
+                    midpoint(polygon[k]),
+                    midpoint(polygon[k + 1]),
+                );
+                let normal = cross(sub(p1, p0), sub(p2, p0));
+                let mut triangle = [ids[0], ids[k], ids[k + 1]];
+                if dot(normal, outward) < 0.0 {
+                    triangle.swap(1, 2);
+                }
+                // Triangles on the grid edges can collapse where the nodes outside share a position
+                if triangle[0] != triangle[1]
+                    && triangle[1] != triangle[2]
+                    && triangle[0] != triangle[2]
+                {
+                    mesh.triangles.push(triangle);
+                }
+            }
+        }
+    }
+    mesh
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+impl TriangleMesh {
+    pub fn write_obj(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        for [x, y, z] in &self.vertices {
+            writeln!(writer, "v {} {} {}", x, y, z)?;
+        }
+        // OBJ indices start at 1
+        for [a, b, c] in &self.triangles {
+            writeln!(writer, "f {} {} {}", a + 1, b + 1, c + 1)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_ply(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writeln!(writer, "ply")?;
+        writeln!(writer, "format ascii 1.0")?;
+        writeln!(writer, "element vertex {}", self.vertices.len())?;
+        writeln!(writer, "property double x")?;
+        writeln!(writer, "property double y")?;
+        writeln!(writer, "property double z")?;
+        writeln!(writer, "element face {}", self.triangles.len())?;
+        writeln!(writer, "property list uchar int vertex_indices")?;
+        writeln!(writer, "end_header")?;
+        for [x, y, z] in &self.vertices {
+            writeln!(writer, "{} {} {}", x, y, z)?;
+        }
+        for [a, b, c] in &self.triangles {
+            writeln!(writer, "3 {} {} {}", a, b, c)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_vtk(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writeln!(writer, "# vtk DataFile Version 3.0")?;
+        writeln!(writer, "CO2 plume boundary")?;
+        writeln!(writer, "ASCII")?;
+        writeln!(writer, "DATASET POLYDATA")?;
+        writeln!(writer, "POINTS {} double", self.vertices.len())?;
+        for [x, y, z] in &self.vertices {
+            writeln!(writer, "{} {} {}", x, y, z)?;
+        }
+        writeln!(
+            writer,
+            "POLYGONS {} {}",
+            self.triangles.len(),
+            4 * self.triangles.len()
+        )?;
+        for [a, b, c] in &self.triangles {
+            writeln!(writer, "3 {} {} {}", a, b, c)?;
+        }
+        Ok(())
+    }
+
+    /// Write the mesh to a file, in the format given by its extension (.obj, .ply or .vtk)
+    pub fn write_file(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let format = MeshFormat::from_path(path).ok_or_else(|| {
+            format!(
+                "Unknown mesh format for {}, expected .obj, .ply or .vtk",
+                path.display()
+            )
+        })?;
+        let mut writer = BufWriter::new(File::create(path)?);
+        match format {
+            MeshFormat::Obj => self.write_obj(&mut writer)?,
+            MeshFormat::Ply => self.write_ply(&mut writer)?,
+            MeshFormat::Vtk => self.write_vtk(&mut writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::RegularGrid;
+    use numpy::ndarray::{Array1, Array3};
+
+    #[test]
+    fn test_mesh_is_closed_and_outward() {
+        let mut snapshots = Array3::from_elem((4, 4, 4), -1);
+        snapshots[[1, 1, 1]] = 0;
+        snapshots[[2, 1, 1]] = 0;
+        snapshots[[1, 2, 2]] = 1;
+        let plume = Plume::from_dense(&snapshots.view());
+        let grid = RegularGrid::new(4, 4, Array1::from_iter((0..4).map(|z| z as f64)).view());
+
+        let mesh = plume_mesh(&plume, &grid, 1);
+        assert!(!mesh.triangles.is_empty());
+
+        // Every edge of a closed, consistently oriented mesh is used once in each direction
+        let mut edges = HashMap::new();
+        for [a, b, c] in &mesh.triangles {
+            for edge in [(*a, *b), (*b, *c), (*c, *a)] {
+                *edges.entry(edge).or_insert(0) += 1;
+            }
+        }
+        assert!(edges
+            .iter()
+            .all(|(&(a, b), &count)| count == 1 && edges.get(&(b, a)) == Some(&1)));
+
+        // The signed volume is positive when the triangles face outward
+        let volume: f64 = mesh
+            .triangles
+            .iter()
+            .map(|&[a, b, c]| {
+                dot(mesh.vertices[a], cross(mesh.vertices[b], mesh.vertices[c])) / 6.0
+            })
+            .sum();
+        assert!(volume > 0.0);
+
+        // The earlier snapshot has a smaller plume
+        let earlier = plume_mesh(&plume, &grid, 0);
+        assert!(earlier.triangles.len() < mesh.triangles.len());
+
+        let mut obj = Vec::new();
+        mesh.write_obj(&mut obj).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        assert_eq!(
+            obj.lines().filter(|l| l.starts_with("f ")).count(),
+            mesh.triangles.len()
+        );
+        assert_eq!(
+            MeshFormat::from_path(Path::new("plume.VTK")),
+            Some(MeshFormat::Vtk)
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use numpy::ndarray::ArrayView3;
+use serde_json::{json, Value};
+
+use crate::error::SimulationError;
+use crate::injection_simulation::Simulation;
+use crate::plume::Plume;
+
+/// The version of the layout of the saved results, recorded in the metadata of every result and checkpoint.
+/// Adding a field does not change the version, since the loaders ignore fields they do not know and fill in
+/// the ones an older result lacks. Renaming or removing one does, with a migration in `migrate_metadata_json`.
+///
+/// 1. Results written before the version was recorded
+/// 2. The metadata holds `schema_version`
+pub const OUTPUT_SCHEMA_VERSION: u32 = 2;
+
+/// Bring the metadata JSON of a result of any earlier schema version up to the current one. Results of a newer
+/// version than this build knows are rejected, since their fields may have changed meaning.
+pub fn migrate_metadata_json(mut metadata: Value) -> Result<Value, SimulationError> {
+    let invalid = |message: String| SimulationError::InvalidValue {
+        argument: "metadata".to_string(),
+        message,
+    };
+    if !metadata.is_object() {
+        return Err(invalid("must be a JSON object".to_string()));
+    }
+    let version = match metadata.get("schema_version") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| invalid("has a schema_version that is not an integer".to_string()))?,
+    };
+    if version > OUTPUT_SCHEMA_VERSION as u64 {
+        return Err(invalid(format!(
+            "has output schema version {}, but this build reads up to version {}",
+            version, OUTPUT_SCHEMA_VERSION
+        )));
+    }
+    // Version 1 only lacks the version itself
+    metadata["schema_version"] = json!(OUTPUT_SCHEMA_VERSION);
+    Ok(metadata)
+}
+
+/// Everything needed to interpret a result file months later: the config, input checksums, crate version,
+/// timings and headline statistics. Written as a JSON sidecar next to the result.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunMetadata {
+    /// The resolved config, in its Debug form
+    pub config: Option<String>,
+    pub sources: Vec<(usize, usize, usize)>,
+    /// SHA-256 checksums of the inputs, by name
+    pub input_checksums: BTreeMap<String, String>,
+    pub elapsed_seconds: Option<f64>,
+    pub statistics: BTreeMap<String, Value>,
+}
+
+impl RunMetadata {
+    /// Metadata with the config, sources and headline statistics of a simulation
+    pub fn for_simulation(simulation: &Simulation, elapsed_seconds: Option<f64>) -> Self {
+        let mut metadata = RunMetadata {
+            config: Some(format!("{:?}", simulation.config())),
+            sources: simulation.sources().to_vec(),
+            elapsed_seconds,
+            ..Default::default()
+        };
+        let compartment = simulation.source_compartment();
+        let statistics = [
+            ("finished", json!(simulation.is_finished())),
+            ("cells_filled", json!(simulation.cells_filled())),
+            ("fraction_filled", json!(simulation.fraction_filled())),
+            ("snapshots", json!(simulation.snapshot_index() + 1)),
+            ("breach_events", json!(simulation.breach_events().len())),
+            ("footprint_cells", json!(simulation.footprint_cells())),
+            ("plume_depth_range", json!(simulation.plume_depth_range())),
+            ("compartment_cells", json!(compartment.compartment_cells)),
+            ("reservoir_cells", json!(compartment.reservoir_cells)),
+            ("compartment_warning", json!(compartment.warning())),
+            ("nan_cells", json!(simulation.n_nan_cells())),
+            (
+                "containment_violation",
+                json!(simulation
+                    .containment_violation()
+                    .map(|violation| violation.kind.as_str())),
+            ),
+        ];
+        for (name, value) in statistics {
+            metadata.statistics.insert(name.to_string(), value);
+        }
+        if let Some(mass) = simulation.injected_mass() {
+            metadata.add_statistic("injected_mass_tonnes", json!(mass));
+        }
+        metadata
+    }
+
+    /// Metadata with the headline statistics of a snapshots array, e.g. one read from a file
+    pub fn for_snapshots(snapshots: &ArrayView3<i32>) -> Self {
+        let plume = Plume::from_dense(snapshots);
+        let mut metadata = RunMetadata::default();
+        metadata.add_statistic("shape", json!(snapshots.shape()));
+        metadata.add_statistic("cells_filled", json!(plume.volume()));
+        metadata.add_statistic("snapshots", json!(plume.n_snapshots()));
+        metadata
+    }
+
+    pub fn add_input_checksum(&mut self, name: &str, checksum: String) -> &mut Self {
+        self.input_checksums.insert(name.to_string(), checksum);
+        self
+    }
+
+    pub fn add_statistic(&mut self, name: &str, value: Value) -> &mut Self {
+        self.statistics.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        json!({
+            "schema_version": OUTPUT_SCHEMA_VERSION,
+            "crate_version": env!("CARGO_PKG_VERSION"),
+            "created_unix_seconds": created,
+            "config": self.config,
+            "sources": self.sources,
+            "input_checksums": self.input_checksums,
+            "elapsed_seconds": self.elapsed_seconds,
+            "statistics": self.statistics,
+        })
+    }
+
+    /// Read metadata written by `to_json` with any schema version up to the current one, see
+    /// `migrate_metadata_json`. Fields missing from older results are left empty.
+    pub fn from_json(metadata: Value) -> Result<Self, SimulationError> {
+        let metadata = migrate_metadata_json(metadata)?;
+        let object = |name: &str| {
+            metadata
+                .get(name)
+                .and_then(Value::as_object)
+                .into_iter()
+                .flatten()
+        };
+        Ok(RunMetadata {
+            config: metadata
+                .get("config")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            sources: metadata
+                .get("sources")
+                .and_then(|sources| serde_json::from_value(sources.clone()).ok())
+                .unwrap_or_default(),
+            input_checksums: object("input_checksums")
+                .filter_map(|(name, checksum)| Some((name.clone(), checksum.as_str()?.to_string())))
+                .collect(),
+            elapsed_seconds: metadata.get("elapsed_seconds").and_then(Value::as_f64),
+            statistics: object("statistics")
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        })
+    }
+
+    /// Write the metadata next to the result file, see `sidecar_path`. Returns the path of the sidecar.
+    pub fn write_sidecar(&self, result_path: &Path) -> std::io::Result<PathBuf> {
+        let path = sidecar_path(result_path);
+        let text = serde_json::to_string_pretty(&self.to_json())?;
+        std::fs::write(&path, text + "\n")?;
+        Ok(path)
+    }
+}
+
+/// Read the sidecar of a result file, see `RunMetadata::from_json`
+pub fn read_sidecar(result_path: &Path) -> Result<RunMetadata, Box<dyn Error>> {
+    let text = std::fs::read_to_string(sidecar_path(result_path))?;
+    Ok(RunMetadata::from_json(serde_json::from_str(&text)?)?)
+}
+
+/// The sidecar of a result file has `.json` appended to its name, e.g. `plume.vtk.json`.
+/// For a directory of results it is `metadata.json` inside the directory.
+pub fn sidecar_path(result_path: &Path) -> PathBuf {
+    if result_path.is_dir() {
+        return result_path.join("metadata.json");
+    }
+    let mut name = result_path.as_os_str().to_owned();
+    name.push(".json");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SimulationConfig;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use numpy::ndarray::{s, Array1, Array2, Array3};
+
+    #[test]
+    fn test_simulation_metadata() {
+        let mut reservoir = Array3::from_elem((3, 3, 3), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        let depths = Array1::from(vec![0.0, 1.0, 2.0]);
+        let bedrock_indices = Array2::<usize>::zeros((3, 3));
+        let mut simulation = Simulation::new(
+            reservoir.view(),
+            depths.view(),
+            bedrock_indices.view(),
+            (1, 1, 1),
+            &SimulationConfig::default(),
+        );
+        simulation.run();
+
+        let mut metadata = RunMetadata::for_simulation(&simulation, Some(0.5));
+        metadata.add_input_checksum("depths", "abc".to_string());
+        let json = metadata.to_json();
+        assert_eq!(json["crate_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["statistics"]["cells_filled"], 18);
+        assert_eq!(json["statistics"]["containment_violation"], Value::Null);
+        assert_eq!(json["input_checksums"]["depths"], "abc");
+        assert_eq!(json["sources"][0], json!([1, 1, 1]));
+        assert_eq!(json["schema_version"], OUTPUT_SCHEMA_VERSION);
+        assert_eq!(RunMetadata::from_json(json.clone()).unwrap(), metadata);
+
+        // Results from before the schema version was recorded, and with fields this build does not know
+        let mut older = json.clone();
+        older.as_object_mut().unwrap().remove("schema_version");
+        older.as_object_mut().unwrap().remove("input_checksums");
+        older["statistics"]["trapping_inventory"] = json!({"structural": 3});
+        let read = RunMetadata::from_json(older).unwrap();
+        assert!(read.input_checksums.is_empty());
+        assert_eq!(read.statistics["cells_filled"], 18);
+        let mut newer = json;
+        newer["schema_version"] = json!(OUTPUT_SCHEMA_VERSION + 1);
+        assert!(RunMetadata::from_json(newer).is_err());
+
+        assert_eq!(
+            sidecar_path(Path::new("results/plume.vtk")),
+            PathBuf::from("results/plume.vtk.json")
+        );
+    }
+}
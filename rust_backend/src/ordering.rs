@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+use crate::cell_state::ReservoirState;
+use crate::grid::Grid;
+
+/// The key the front orders its cells by, smallest first. Different keys emulate different flow regimes:
+/// ordering by structural depth gives the buoyancy-dominated fill-and-spill of the default engine, ordering
+/// by the height below the local seal lets the CO2 migrate along every seal before filling deeper below it,
+/// and ordering by the distance from the wells gives the radial spreading of a viscous-dominated injection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FrontOrdering {
+    /// The depth of the cell
+    #[default]
+    Depth,
+    /// The depth of the cell below the closest caprock above it in its column, or below the top of the model.
+    /// The cells waiting below a seal that breaks or re-seals are moved to the keys of their new seal.
+    SealPotential,
+    /// The distance in cells from the closest source
+    WellDistance,
+}
+
+impl FrontOrdering {
+    /// Parse an ordering from its name: "depth", "seal_potential" or "well_distance"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "depth" => Some(FrontOrdering::Depth),
+            "seal_potential" => Some(FrontOrdering::SealPotential),
+            "well_distance" => Some(FrontOrdering::WellDistance),
+            _ => None,
+        }
+    }
+
+    /// The key of the cell in the current state of the reservoir
+    pub fn key(
+        &self,
+        grid: &impl Grid,
+        reservoir: &ReservoirState,
+        sources: &[(usize, usize, usize)],
+        cell: (usize, usize, usize),
+    ) -> f64 {
+        match self {
+            FrontOrdering::Depth => grid.cell_depth(cell),
+            FrontOrdering::SealPotential => {
+                let (x, y, z) = cell;
+                let seal = reservoir.closest_caprock_idx((x, y), z);
+                grid.cell_depth(cell) - grid.cell_depth((x, y, seal))
+            }
+            FrontOrdering::WellDistance => sources
+                .iter()
+                .map(|&source| {
+                    let offset = |a: usize, b: usize| (a as f64 - b as f64).powi(2);
+                    (offset(cell.0, source.0) + offset(cell.1, source.1) + offset(cell.2, source.2))
+                        .sqrt()
+                })
+                .fold(f64::INFINITY, f64::min),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cell_state::CellState;
+    use crate::grid::RegularGrid;
+    use numpy::ndarray::{Array1, Array3};
+
+    #[test]
+    fn test_front_ordering_keys() {
+        let mut states = Array3::from_elem((3, 1, 4), CellState::Reservoir);
+        states[[0, 0, 0]] = CellState::Caprock;
+        states[[2, 0, 2]] = CellState::Caprock;
+        let reservoir = ReservoirState::from_cell_states(&states.view());
+        let depths = Array1::from(vec![100.0, 110.0, 120.0, 130.0]);
+        let grid = RegularGrid::new(3, 1, depths.view());
+        let key =
+            |ordering: FrontOrdering, cell| ordering.key(&grid, &reservoir, &[(0, 0, 1)], cell);
+
+        assert_eq!(key(FrontOrdering::Depth, (2, 0, 3)), 130.0);
+        // Just below the deeper seal is as favourable as just below the shallow one
+        assert_eq!(key(FrontOrdering::SealPotential, (2, 0, 3)), 10.0);
+        assert_eq!(key(FrontOrdering::SealPotential, (0, 0, 1)), 10.0);
+        assert_eq!(key(FrontOrdering::SealPotential, (1, 0, 3)), 30.0);
+        assert_eq!(key(FrontOrdering::WellDistance, (0, 0, 1)), 0.0);
+        assert_eq!(key(FrontOrdering::WellDistance, (2, 0, 1)), 2.0);
+        assert_eq!(
+            FrontOrdering::from_name("seal_potential"),
+            Some(FrontOrdering::SealPotential)
+        );
+    }
+}
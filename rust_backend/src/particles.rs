@@ -0,0 +1,291 @@
+use numpy::ndarray::{Array3, ArrayView1, ArrayView2, ArrayView3};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::cell_state::{CellState, ReservoirState};
+use crate::config::SimulationConfig;
+use crate::connectivity::source_compartment;
+use crate::error::SimulationError;
+use crate::grid::{Grid, RegularGrid};
+use crate::injection_simulation::{
+    check_initial_position, initial_reservoir_state, lateral_directions,
+};
+use crate::sparse::SparseGrid;
+use crate::validation::validate_inputs;
+
+/// An engine that releases particles at the well and moves them with buoyancy-dominated random walks.
+/// A particle rises whenever the cell above is open and not full, and otherwise wanders through the plume
+/// until it settles in a cell that is not full under a full cell or the caprock. The particle density gives
+/// the saturation, and the random walks give a dispersed, smooth front on coarse grids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParticleTracking {
+    /// Number of particles released over the whole injection, evenly between the snapshots
+    pub n_particles: usize,
+    /// Number of particles in a cell at full CO2 saturation
+    pub particles_per_cell: u32,
+    /// Probability that a wandering particle steps upwards, or sideways under a seal, instead of a random
+    /// step sideways or down. Must be below 1 so the plume can grow downwards when a trap is full.
+    pub buoyancy: f64,
+    /// Probability that a particle keeps wandering instead of settling where it could, which spreads the front
+    pub dispersion: f64,
+    /// After this many steps a particle settles in the first cell that is not full
+    pub max_steps: usize,
+    /// Seed for the random number generator. The same seed gives the same plume.
+    pub seed: u64,
+}
+
+impl Default for ParticleTracking {
+    fn default() -> Self {
+        ParticleTracking {
+            n_particles: 10_000,
+            particles_per_cell: 10,
+            buoyancy: 0.9,
+            dispersion: 0.5,
+            max_steps: 1000,
+            seed: 0,
+        }
+    }
+}
+
+/// The outcome of a particle-tracking run
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParticleResult {
+    /// The snapshot at which every cell received its first particle, or -1, in the same format as the
+    /// percolation fill
+    pub snapshots: Array3<i32>,
+    /// CO2 saturation at the end of every snapshot. Ends early if the compartment of the source is full.
+    pub saturation: Vec<SparseGrid<f32>>,
+    pub particles_settled: usize,
+}
+
+impl ParticleTracking {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        let invalid = |message: &str| {
+            Err(SimulationError::InvalidValue {
+                argument: "particle_tracking".to_string(),
+                message: message.to_string(),
+            })
+        };
+        if self.particles_per_cell == 0 {
+            return invalid("particles_per_cell must be at least 1");
+        }
+        if !(0.0..1.0).contains(&self.buoyancy) {
+            return invalid("the buoyancy must be in [0, 1)");
+        }
+        if !(0.0..1.0).contains(&self.dispersion) {
+            return invalid("the dispersion must be in [0, 1)");
+        }
+        Ok(())
+    }
+
+    /// Release the particles from the source and return the snapshots and saturations. Only the snapshot
+    /// count, anisotropy and velocity classifier of the config are used.
+    pub fn run(
+        &self,
+        reservoir_matrix: ArrayView3<f64>,
+        depths: ArrayView1<f64>,
+        bedrock_indices: ArrayView2<usize>,
+        source: (usize, usize, usize),
+        config: &SimulationConfig,
+    ) -> Result<ParticleResult, SimulationError> {
+        validate_inputs(&reservoir_matrix, &depths, &bedrock_indices, source, config)?;
+        self.validate()?;
+        let dims = reservoir_matrix.dim();
+        let grid = RegularGrid::new(dims.0, dims.1, depths);
+        let reservoir = initial_reservoir_state(
+            &reservoir_matrix,
+            &grid,
+            &bedrock_indices,
+            config.velocity_classifier,
+        );
+        check_initial_position(&reservoir, source)?;
+        let directions = lateral_directions(config.anisotropy);
+
+        // Particles only move through the compartment of the source, so they stop once it is full
+        let compartment = source_compartment(&reservoir, &grid, &[source], &directions);
+        let capacity = compartment.compartment_cells * self.particles_per_cell as usize;
+
+        let mut walk = Walk {
+            reservoir: &reservoir,
+            grid: &grid,
+            directions: &directions,
+            counts: Array3::zeros(dims),
+            capacity: self.particles_per_cell,
+            rng: ChaCha8Rng::seed_from_u64(self.seed),
+        };
+        let mut snapshots = Array3::from_elem(dims, -1);
+        let mut saturation = Vec::new();
+        let mut particles_settled = 0;
+        for snapshot_index in 0..config.total_snapshots {
+            let released = self.n_particles * (snapshot_index + 1) / config.total_snapshots;
+            while particles_settled < released.min(capacity) {
+                let cell = walk.settle(source, self);
+                walk.counts[cell] += 1;
+                if snapshots[cell] == -1 {
+                    snapshots[cell] = snapshot_index as i32;
+                }
+                particles_settled += 1;
+            }
+            let density = walk
+                .counts
+                .mapv(|count| count as f32 / self.particles_per_cell as f32);
+            saturation.push(SparseGrid::from_dense(&density.view(), 0.0));
+            if particles_settled == capacity {
+                break;
+            }
+        }
+
+        Ok(ParticleResult {
+            snapshots,
+            saturation,
+            particles_settled,
+        })
+    }
+}
+
+/// The state shared by the random walks of all particles
+struct Walk<'a, G: Grid> {
+    reservoir: &'a ReservoirState,
+    grid: &'a G,
+    directions: &'a [(i32, i32)],
+    counts: Array3<u32>,
+    capacity: u32,
+    rng: ChaCha8Rng,
+}
+
+impl<G: Grid> Walk<'_, G> {
+    fn is_open(&self, cell: (usize, usize, usize)) -> bool {
+        self.reservoir.state(cell) == CellState::Reservoir
+    }
+
+    fn is_full(&self, cell: (usize, usize, usize)) -> bool {
+        self.counts[cell] >= self.capacity
+    }
+
+    /// Walk a particle from the source until it settles, and return its cell. The compartment must not be full.
+    fn settle(
+        &mut self,
+        source: (usize, usize, usize),
+        params: &ParticleTracking,
+    ) -> (usize, usize, usize) {
+        let (_, _, nz) = self.counts.dim();
+        let mut cell = source;
+        let mut steps = 0;
+        loop {
+            let (x, y, z) = cell;
+            let above = (z > 0)
+                .then(|| (x, y, z - 1))
+                .filter(|&above| self.is_open(above));
+            let below = (z + 1 < nz)
+                .then_some((x, y, z + 1))
+                .filter(|&below| self.is_open(below));
+
+            // Buoyancy always lifts the particle into an open cell above that has room
+            if let Some(above) = above.filter(|&above| !self.is_full(above)) {
+                cell = above;
+                steps += 1;
+                continue;
+            }
+            if !self.is_full(cell)
+                && (steps >= params.max_steps || self.rng.random::<f64>() >= params.dispersion)
+            {
+                return cell;
+            }
+
+            let lateral: Vec<_> = self
+                .grid
+                .lateral_neighbors(cell, self.directions)
+                .filter(|&neighbor| self.is_open(neighbor))
+                .collect();
+            let buoyant = self.rng.random::<f64>() < params.buoyancy;
+            let next = match (buoyant, above) {
+                (true, Some(above)) => Some(above),
+                (true, None) => self.pick(&lateral),
+                (false, _) => {
+                    let mut candidates = lateral;
+                    candidates.extend(below);
+                    self.pick(&candidates)
+                }
+            };
+            if let Some(next) = next {
+                cell = next;
+            }
+            steps += 1;
+        }
+    }
+
+    fn pick(&mut self, cells: &[(usize, usize, usize)]) -> Option<(usize, usize, usize)> {
+        (!cells.is_empty()).then(|| cells[self.rng.random_range(0..cells.len())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use numpy::ndarray::{s, Array1, Array2};
+
+    fn run_under_caprock(dims: (usize, usize, usize), params: &ParticleTracking) -> ParticleResult {
+        let mut reservoir = Array3::from_elem(dims, VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        let depths = Array1::from_iter((0..dims.2).map(|z| z as f64));
+        let bedrock_indices = Array2::<usize>::zeros((dims.0, dims.1));
+        let config = SimulationConfig {
+            total_snapshots: 4,
+            ..Default::default()
+        };
+        params
+            .run(
+                reservoir.view(),
+                depths.view(),
+                bedrock_indices.view(),
+                (dims.0 / 2, dims.1 / 2, 1),
+                &config,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_particles_spread_under_the_caprock() {
+        let params = ParticleTracking {
+            n_particles: 400,
+            particles_per_cell: 4,
+            seed: 1,
+            ..Default::default()
+        };
+        let result = run_under_caprock((15, 15, 4), &params);
+        assert_eq!(result.particles_settled, 400);
+        assert_eq!(result.saturation.len(), 4);
+
+        let saturation = result.saturation[3].to_dense();
+        assert!((saturation.sum() - 100.0).abs() < 1e-3);
+        assert!(saturation.iter().all(|&s| s <= 1.0));
+        let top = saturation.slice(s![.., .., 1]).sum();
+        let below = saturation.slice(s![.., .., 2..]).sum();
+        assert!(top > 2.0 * below);
+
+        // The plume grows outwards from the well
+        assert_eq!(result.snapshots[[7, 7, 1]], 0);
+        assert!(result.snapshots.iter().any(|&s| s == 3));
+    }
+
+    #[test]
+    fn test_particles_stop_when_the_compartment_is_full() {
+        let params = ParticleTracking {
+            n_particles: 100,
+            particles_per_cell: 2,
+            ..Default::default()
+        };
+        let result = run_under_caprock((2, 1, 3), &params);
+        // Four reservoir cells hold eight particles, which fit in the first snapshot
+        assert_eq!(result.particles_settled, 8);
+        assert_eq!(result.saturation.len(), 1);
+        assert_eq!(result.saturation[0].to_dense().sum(), 4.0);
+        assert!(ParticleTracking {
+            buoyancy: 1.0,
+            ..params
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::Instant;
+
+use serde_json::{json, Value};
+
+use crate::benchmarks::BenchmarkModel;
+use crate::config::SimulationConfig;
+use crate::error::SimulationError;
+use crate::injection_simulation::Simulation;
+
+/// Number of cells filled between samples of the front
+const SAMPLE_INTERVAL: usize = 1000;
+
+/// The speed and footprint of a run on a benchmark model, see `run_perf`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PerfReport {
+    pub model: BenchmarkModel,
+    pub resolution: usize,
+    pub config: SimulationConfig,
+    pub cells_filled: usize,
+    /// Time of the run, without building the model
+    pub seconds: f64,
+    /// Peak resident memory of the process in bytes, where the platform reports it
+    pub peak_memory_bytes: Option<u64>,
+    /// Largest number of cells waiting in the front, sampled every thousand filled cells
+    pub peak_queue_len: usize,
+    /// Mean number of cells waiting in the front over the samples
+    pub mean_queue_len: f64,
+}
+
+impl PerfReport {
+    pub fn cells_per_second(&self) -> f64 {
+        self.cells_filled as f64 / self.seconds.max(f64::MIN_POSITIVE)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "model": self.model.name(),
+            "resolution": self.resolution,
+            "queue": self.config.queue.name(),
+            "cells_filled": self.cells_filled,
+            "seconds": self.seconds,
+            "cells_per_second": self.cells_per_second(),
+            "peak_memory_bytes": self.peak_memory_bytes,
+            "peak_queue_len": self.peak_queue_len,
+            "mean_queue_len": self.mean_queue_len,
+            "build": {
+                "version": env!("CARGO_PKG_VERSION"),
+                "profile": if cfg!(debug_assertions) { "debug" } else { "release" },
+                "arch": std::env::consts::ARCH,
+                "os": std::env::consts::OS,
+            },
+        })
+    }
+}
+
+/// Peak resident memory of the process in bytes, from /proc on Linux. None on other platforms.
+pub fn peak_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Run the simulation on a benchmark model with the config, e.g. to compare front queues, and measure how fast it
+/// fills cells, the peak memory and the size of the front. The peak memory is that of the whole process, so
+/// it includes earlier runs in the same process.
+pub fn run_perf(
+    model: BenchmarkModel,
+    resolution: usize,
+    config: &SimulationConfig,
+) -> Result<PerfReport, SimulationError> {
+    let case = model.build(resolution)?;
+    let start = Instant::now();
+    let mut simulation = Simulation::try_new(
+        case.grid.reservoir_matrix.view(),
+        case.grid.depths.view(),
+        case.grid.bedrock_indices.view(),
+        case.source,
+        config,
+    )?;
+    let mut peak_queue_len = 0;
+    let mut queue_len_sum = 0;
+    let mut n_samples = 0;
+    loop {
+        let running = simulation.advance(SAMPLE_INTERVAL);
+        let queue_len = simulation.frontier_size();
+        peak_queue_len = peak_queue_len.max(queue_len);
+        queue_len_sum += queue_len;
+        n_samples += 1;
+        if !running {
+            break;
+        }
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    Ok(PerfReport {
+        model,
+        resolution,
+        config: config.clone(),
+        cells_filled: simulation.cells_filled(),
+        seconds,
+        peak_memory_bytes: peak_memory_bytes(),
+        peak_queue_len,
+        mean_queue_len: queue_len_sum as f64 / n_samples as f64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastucture::QueueKind;
+
+    #[test]
+    fn test_perf_report() {
+        let config = SimulationConfig {
+            max_column_height: Some(3),
+            queue: QueueKind::Bucket,
+            ..Default::default()
+        };
+        let report = run_perf(BenchmarkModel::SleipnerDome, 32, &config).unwrap();
+        assert!(report.cells_filled > SAMPLE_INTERVAL);
+        assert!(report.peak_queue_len > 0);
+        assert!(report.mean_queue_len <= report.peak_queue_len as f64);
+        assert!(report.cells_per_second() > 0.0);
+        if cfg!(target_os = "linux") {
+            assert!(report.peak_memory_bytes.unwrap() > 0);
+        }
+        let json = report.to_json();
+        assert_eq!(json["queue"], "bucket");
+        assert_eq!(QueueKind::from_name("bucket"), Some(QueueKind::Bucket));
+    }
+}
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimulationError;
+
+/// The layers where the wells are open to the formation. Without a perforation every layer from the source
+/// down to the base of the model is injected into in turn, as if the whole column below the source were
+/// perforated. With a perforation the wells move on to the next perforated layer below instead, and stop
+/// after the deepest one.
+///
+/// With split fractions all perforated layers inject at once, and every layer takes its fraction of the
+/// filled cells, e.g. to represent a high-permeability thief zone. A layer whose front is exhausted stops,
+/// and the others take over its share.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Perforation {
+    /// The perforated z indices, sorted and without duplicates
+    layers: Vec<usize>,
+    /// The fraction of the injected volume entering every layer, in the order of the layers, if split
+    fractions: Option<Vec<f64>>,
+}
+
+impl Perforation {
+    /// A perforation of the given layers, in any order
+    pub fn new(mut layers: Vec<usize>) -> Self {
+        layers.sort_unstable();
+        layers.dedup();
+        Perforation {
+            layers,
+            fractions: None,
+        }
+    }
+
+    /// A perforation that splits the injected volume between the layers, given as (z index, fraction).
+    /// The fractions must add up to 1.
+    pub fn split(mut layers: Vec<(usize, f64)>) -> Self {
+        layers.sort_by_key(|&(layer, _)| layer);
+        Perforation {
+            layers: layers.iter().map(|&(layer, _)| layer).collect(),
+            fractions: Some(layers.iter().map(|&(_, fraction)| fraction).collect()),
+        }
+    }
+
+    /// A perforation of every layer from top to bottom, both included
+    pub fn interval(top: usize, bottom: usize) -> Self {
+        Perforation {
+            layers: (top..=bottom).collect(),
+            fractions: None,
+        }
+    }
+
+    pub fn layers(&self) -> &[usize] {
+        &self.layers
+    }
+
+    /// The fraction of every layer, or None if the layers are injected into one after the other
+    pub fn fractions(&self) -> Option<&[f64]> {
+        self.fractions.as_deref()
+    }
+
+    /// Check that the perforation is within the nz layers of the grid and that every source is perforated
+    pub fn validate(
+        &self,
+        nz: usize,
+        sources: &[(usize, usize, usize)],
+    ) -> Result<(), SimulationError> {
+        let invalid = |message: String| {
+            Err(SimulationError::InvalidValue {
+                argument: "perforation".to_string(),
+                message,
+            })
+        };
+        let Some(&deepest) = self.layers.last() else {
+            return invalid("at least one layer must be perforated".to_string());
+        };
+        if deepest >= nz {
+            return invalid(format!(
+                "layer {} is outside the {} layers of the grid",
+                deepest, nz
+            ));
+        }
+        if let Some(fractions) = &self.fractions {
+            if self.layers.windows(2).any(|pair| pair[0] == pair[1]) {
+                return invalid("every layer can only be split once".to_string());
+            }
+            if fractions.iter().any(|f| !(f.is_finite() && *f >= 0.0)) {
+                return invalid("the split fractions must be non-negative".to_string());
+            }
+            let total: f64 = fractions.iter().sum();
+            if (total - 1.0).abs() > 1e-6 {
+                return invalid(format!(
+                    "the split fractions must add up to 1, got {}",
+                    total
+                ));
+            }
+        }
+        if let Some(&(x, y, z)) = sources
+            .iter()
+            .find(|&&(_, _, z)| self.layers.binary_search(&z).is_err())
+        {
+            return invalid(format!(
+                "the source ({}, {}, {}) is not in a perforated layer",
+                x, y, z
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The order in which the layers of the perforated interval are injected into. The order decides which traps
+/// fill first, and so the snapshot sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SweepOrder {
+    /// From the source down, every layer until its front is exhausted
+    #[default]
+    TopDown,
+    /// From the deepest layer up to the source, every layer until its front is exhausted
+    BottomUp,
+    /// All layers at once, with an equal share of the filled cells each
+    Interleaved,
+}
+
+impl SweepOrder {
+    /// Parse a sweep order from its name: "top_down", "bottom_up" or "interleaved"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "top_down" => Some(SweepOrder::TopDown),
+            "bottom_up" => Some(SweepOrder::BottomUp),
+            "interleaved" => Some(SweepOrder::Interleaved),
+            _ => None,
+        }
+    }
+}
+
+/// The layers a well with its source at z index source_z injects into, in the order of the sweep: the perforated
+/// layers from the source down, or every layer from the source down without a perforation. Only the layers above
+/// base are used, which is nz or the top of the basement.
+pub fn injection_layers(
+    perforation: Option<&Perforation>,
+    source_z: usize,
+    base: usize,
+    sweep_order: SweepOrder,
+) -> Vec<usize> {
+    let mut layers: Vec<usize> = match perforation {
+        Some(perforation) => perforation
+            .layers()
+            .iter()
+            .copied()
+            .filter(|&z| z >= source_z && z < base)
+            .collect(),
+        None => (source_z..base).collect(),
+    };
+    if sweep_order == SweepOrder::BottomUp {
+        layers.reverse();
+    }
+    layers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_injection_layers() {
+        let perforation = Perforation::new(vec![7, 1, 3, 4, 3]);
+        assert_eq!(perforation.layers(), &[1, 3, 4, 7]);
+        let layers = |perforation, sweep_order| injection_layers(perforation, 3, 8, sweep_order);
+        assert_eq!(
+            layers(Some(&perforation), SweepOrder::TopDown),
+            vec![3, 4, 7]
+        );
+        assert_eq!(
+            layers(Some(&perforation), SweepOrder::BottomUp),
+            vec![7, 4, 3]
+        );
+        assert_eq!(layers(None, SweepOrder::TopDown), vec![3, 4, 5, 6, 7]);
+        assert_eq!(
+            injection_layers(Some(&perforation), 3, 5, SweepOrder::TopDown),
+            vec![3, 4]
+        );
+        assert_eq!(
+            SweepOrder::from_name("bottom_up"),
+            Some(SweepOrder::BottomUp)
+        );
+
+        assert_eq!(Perforation::interval(2, 4).layers(), &[2, 3, 4]);
+        assert!(perforation.validate(8, &[(0, 0, 3)]).is_ok());
+        assert!(perforation.validate(8, &[(0, 0, 5)]).is_err());
+        assert!(perforation.validate(7, &[(0, 0, 3)]).is_err());
+
+        let split = Perforation::split(vec![(5, 0.75), (2, 0.25)]);
+        assert_eq!(split.layers(), &[2, 5]);
+        assert_eq!(split.fractions(), Some(&[0.25, 0.75][..]));
+        assert!(split.validate(8, &[(0, 0, 2)]).is_ok());
+        assert!(Perforation::split(vec![(2, 0.5), (5, 0.2)])
+            .validate(8, &[(0, 0, 2)])
+            .is_err());
+    }
+}
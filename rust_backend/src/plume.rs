@@ -0,0 +1,202 @@
+use numpy::ndarray::{Array2, ArrayView3};
+
+use crate::cell_state::RockType;
+use crate::sparse::SparseGrid;
+
+/// How much CO2 went into one z layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayerStatistics {
+    pub z: usize,
+    pub cells_filled: usize,
+    /// The snapshots at which the first and last cell of the layer were filled, if any
+    pub first_snapshot: Option<i32>,
+    pub last_snapshot: Option<i32>,
+    /// Number of reservoir rock cells in the layer
+    pub reservoir_cells: usize,
+    /// Number of reservoir rock cells filled. Broken caprock cells count as filled, but not as reservoir.
+    pub reservoir_cells_filled: usize,
+}
+
+impl LayerStatistics {
+    /// Fraction of the reservoir cells of the layer that hold CO2, between 0 and 1
+    pub fn fraction_used(&self) -> f64 {
+        if self.reservoir_cells == 0 {
+            return 0.0;
+        }
+        self.reservoir_cells_filled as f64 / self.reservoir_cells as f64
+    }
+}
+
+/// The result of an injection simulation: the snapshot at which every cell was filled.
+/// Backed by the sparse snapshots, so queries only touch the cells containing CO2.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plume {
+    // Snapshot index of every filled cell. Cells without CO2 are -1.
+    snapshots: SparseGrid<i32>,
+}
+
+impl Plume {
+    /// Wrap sparse snapshots where cells without CO2 are -1
+    pub fn new(snapshots: SparseGrid<i32>) -> Self {
+        Plume { snapshots }
+    }
+
+    /// Build a plume from dense snapshots where cells without CO2 are -1
+    pub fn from_dense(snapshots: &ArrayView3<i32>) -> Self {
+        Plume::new(SparseGrid::from_dense(snapshots, -1))
+    }
+
+    pub fn dim(&self) -> (usize, usize, usize) {
+        self.snapshots.dim()
+    }
+
+    /// Whether the cell contains CO2 at the end of the simulation. Cells outside the grid do not.
+    pub fn contains(&self, x: usize, y: usize, z: usize) -> bool {
+        self.snapshot_of(x, y, z).is_some()
+    }
+
+    /// The snapshot at which the cell was filled, if it was
+    pub fn snapshot_of(&self, x: usize, y: usize, z: usize) -> Option<i32> {
+        let (nx, ny, nz) = self.dim();
+        if x >= nx || y >= ny || z >= nz {
+            return None;
+        }
+        let snapshot = self.snapshots.get((x, y, z));
+        (snapshot >= 0).then_some(snapshot)
+    }
+
+    /// The cells containing CO2 at the end of the given snapshot, sorted by (x, y, z)
+    pub fn cells_at_snapshot(&self, snapshot: i32) -> Vec<(usize, usize, usize)> {
+        let mut cells: Vec<_> = self
+            .snapshots
+            .iter()
+            .filter(|&(_, filled_at)| filled_at >= 0 && filled_at <= snapshot)
+            .map(|(cell, _)| cell)
+            .collect();
+        cells.sort_unstable();
+        cells
+    }
+
+    /// The z index of the shallowest CO2 cell in every (x, y) column, or -1 for columns without CO2
+    pub fn top_surface(&self) -> Array2<i32> {
+        let (nx, ny, _) = self.dim();
+        let mut surface = Array2::from_elem((nx, ny), -1);
+        for ((x, y, z), _) in self.cells() {
+            let top = &mut surface[[x, y]];
+            if *top < 0 || (z as i32) < *top {
+                *top = z as i32;
+            }
+        }
+        surface
+    }
+
+    /// Fill statistics for every z layer, given the rock types of the grid
+    pub fn layer_statistics(&self, rock: &ArrayView3<RockType>) -> Vec<LayerStatistics> {
+        let (_, _, nz) = self.dim();
+        let mut layers: Vec<LayerStatistics> = (0..nz)
+            .map(|z| LayerStatistics {
+                z,
+                cells_filled: 0,
+                first_snapshot: None,
+                last_snapshot: None,
+                reservoir_cells: 0,
+                reservoir_cells_filled: 0,
+            })
+            .collect();
+
+        for ((_, _, z), &rock_type) in rock.indexed_iter() {
+            if rock_type == RockType::Reservoir {
+                layers[z].reservoir_cells += 1;
+            }
+        }
+        for ((x, y, z), snapshot) in self.cells() {
+            let layer = &mut layers[z];
+            layer.cells_filled += 1;
+            if rock[[x, y, z]] == RockType::Reservoir {
+                layer.reservoir_cells_filled += 1;
+            }
+            layer.first_snapshot = Some(layer.first_snapshot.map_or(snapshot, |s| s.min(snapshot)));
+            layer.last_snapshot = Some(layer.last_snapshot.map_or(snapshot, |s| s.max(snapshot)));
+        }
+        layers
+    }
+
+    /// Number of cells containing CO2
+    pub fn volume(&self) -> usize {
+        self.cells().count()
+    }
+
+    /// Number of snapshots in the plume, i.e. the last snapshot index plus one
+    pub fn n_snapshots(&self) -> usize {
+        self.cells()
+            .map(|(_, snapshot)| snapshot as usize + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The filled cells and their snapshot index, in no particular order
+    pub fn cells(&self) -> impl Iterator<Item = ((usize, usize, usize), i32)> + '_ {
+        self.snapshots.iter().filter(|&(_, snapshot)| snapshot >= 0)
+    }
+
+    pub fn sparse_snapshots(&self) -> &SparseGrid<i32> {
+        &self.snapshots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use numpy::ndarray::Array3;
+
+    #[test]
+    fn test_plume_queries() {
+        let mut snapshots = Array3::from_elem((3, 2, 4), -1);
+        snapshots[[1, 1, 2]] = 0;
+        snapshots[[1, 1, 3]] = 1;
+        snapshots[[0, 1, 3]] = 1;
+        snapshots[[2, 0, 1]] = 2;
+        let plume = Plume::from_dense(&snapshots.view());
+
+        assert!(plume.contains(1, 1, 2));
+        assert!(!plume.contains(0, 0, 0));
+        assert!(!plume.contains(5, 0, 0));
+        assert_eq!(plume.snapshot_of(2, 0, 1), Some(2));
+        assert_eq!(plume.volume(), 4);
+        assert_eq!(plume.n_snapshots(), 3);
+
+        assert_eq!(plume.cells_at_snapshot(0), vec![(1, 1, 2)]);
+        assert_eq!(
+            plume.cells_at_snapshot(1),
+            vec![(0, 1, 3), (1, 1, 2), (1, 1, 3)]
+        );
+
+        let surface = plume.top_surface();
+        assert_eq!(surface[[1, 1]], 2);
+        assert_eq!(surface[[0, 1]], 3);
+        assert_eq!(surface[[2, 0]], 1);
+        assert_eq!(surface[[0, 0]], -1);
+    }
+
+    #[test]
+    fn test_layer_statistics() {
+        let mut snapshots = Array3::from_elem((2, 1, 3), -1);
+        snapshots[[0, 0, 1]] = 2;
+        snapshots[[0, 0, 2]] = 0;
+        snapshots[[1, 0, 2]] = 1;
+        let mut rock = Array3::from_elem((2, 1, 3), RockType::Reservoir);
+        // A broken caprock cell
+        rock[[0, 0, 1]] = RockType::Caprock;
+        let plume = Plume::from_dense(&snapshots.view());
+
+        let layers = plume.layer_statistics(&rock.view());
+        assert_eq!(layers.len(), 3);
+        assert_eq!(layers[0].cells_filled, 0);
+        assert_eq!(layers[0].first_snapshot, None);
+        assert_eq!(layers[1].cells_filled, 1);
+        assert_eq!(layers[1].fraction_used(), 0.0);
+        assert_eq!(layers[2].first_snapshot, Some(0));
+        assert_eq!(layers[2].last_snapshot, Some(1));
+        assert_eq!(layers[2].fraction_used(), 1.0);
+    }
+}
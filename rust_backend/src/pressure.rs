@@ -0,0 +1,426 @@
+use numpy::ndarray::{Array2, Array3};
+
+use chrono::Days;
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimulationError;
+use crate::time_axis::{RateChange, TimeAxis};
+
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+// Throttling stops looking further ahead than this, and keeps the last rate from then on
+const MAX_THROTTLING_DAYS: u64 = 200 * 365;
+
+/// Aquifer and fluid properties for the analytic pressure buildup estimate, in SI units.
+/// The injected CO2 pushes brine away from the wells, which is modelled with the Theis solution for a
+/// single phase in an infinite, homogeneous and confined aquifer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PressureModel {
+    /// Permeability of the aquifer in m²
+    pub permeability: f64,
+    /// Thickness of the aquifer in m
+    pub thickness: f64,
+    pub porosity: f64,
+    /// Total compressibility of rock and brine in 1/Pa
+    pub total_compressibility: f64,
+    /// Viscosity of the brine in Pa s
+    pub brine_viscosity: f64,
+    /// Lateral size of a cell along (x, y) in m, to turn cell indices into distances
+    pub cell_size: (f64, f64),
+    /// Volume of CO2 in a filled cell in m³, to turn the injection rate in cells per day into a volume rate
+    pub cell_volume: f64,
+    /// Radius of the wells in m. The pressure at a well is evaluated at this distance.
+    pub well_radius: f64,
+}
+
+impl PressureModel {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        let values = [
+            ("permeability", self.permeability),
+            ("thickness", self.thickness),
+            ("porosity", self.porosity),
+            ("total_compressibility", self.total_compressibility),
+            ("brine_viscosity", self.brine_viscosity),
+            ("cell_size", self.cell_size.0),
+            ("cell_size", self.cell_size.1),
+            ("cell_volume", self.cell_volume),
+            ("well_radius", self.well_radius),
+        ];
+        for (name, value) in values {
+            if !(value.is_finite() && value > 0.0) {
+                return Err(SimulationError::InvalidValue {
+                    argument: "pressure_model".to_string(),
+                    message: format!("{} must be positive, got {}", name, value),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Overpressure in Pa at the given distance from a well injecting the given share of the rate schedule,
+    /// the given number of days after the injection start. Rate changes are superposed in time.
+    pub fn overpressure(&self, time_axis: &TimeAxis, share: f64, distance: f64, days: f64) -> f64 {
+        let distance = distance.max(self.well_radius);
+        let start = time_axis.start_date();
+        let mut previous_rate = 0.0;
+        let mut pressure = 0.0;
+        for change in time_axis.schedule() {
+            let since_change = days - (change.date - start).num_days() as f64;
+            if since_change <= 0.0 {
+                break;
+            }
+            let rate = share * change.cells_per_day * self.cell_volume / SECONDS_PER_DAY;
+            pressure +=
+                (rate - previous_rate) * self.theis(distance, since_change * SECONDS_PER_DAY);
+            previous_rate = rate;
+        }
+        pressure
+    }
+
+    /// Overpressure per unit injection rate (m³/s) at the given distance and time since the start of injection
+    fn theis(&self, distance: f64, seconds: f64) -> f64 {
+        let u =
+            distance * distance * self.porosity * self.brine_viscosity * self.total_compressibility
+                / (4.0 * self.permeability * seconds);
+        self.brine_viscosity / (4.0 * std::f64::consts::PI * self.permeability * self.thickness)
+            * exponential_integral(u)
+    }
+
+    /// Overpressure in Pa in every (x, y) column, see `overpressure_at`
+    pub fn overpressure_map(
+        &self,
+        time_axis: &TimeAxis,
+        wells: &[(usize, usize)],
+        (nx, ny): (usize, usize),
+        days: f64,
+    ) -> Array2<f64> {
+        Array2::from_shape_fn((nx, ny), |column| {
+            self.overpressure_at(time_axis, wells, column, days)
+        })
+    }
+
+    /// Overpressure in Pa in the given (x, y) column, summed over the wells. The rate is split evenly between the wells.
+    pub fn overpressure_at(
+        &self,
+        time_axis: &TimeAxis,
+        wells: &[(usize, usize)],
+        (x, y): (usize, usize),
+        days: f64,
+    ) -> f64 {
+        let share = 1.0 / wells.len().max(1) as f64;
+        wells
+            .iter()
+            .map(|&(wx, wy)| {
+                let dx = (x as f64 - wx as f64) * self.cell_size.0;
+                let dy = (y as f64 - wy as f64) * self.cell_size.1;
+                self.overpressure(time_axis, share, libm::hypot(dx, dy), days)
+            })
+            .sum()
+    }
+
+    /// One overpressure map per snapshot, with shape (n_snapshots, nx, ny).
+    /// Snapshots without a time, because the schedule stops before they are complete, are NaN.
+    pub fn overpressure_maps(
+        &self,
+        time_axis: &TimeAxis,
+        wells: &[(usize, usize)],
+        dims: (usize, usize),
+        snapshot_days: &[Option<f64>],
+    ) -> Array3<f64> {
+        let mut maps = Array3::from_elem((snapshot_days.len(), dims.0, dims.1), f64::NAN);
+        for (mut map, days) in maps.outer_iter_mut().zip(snapshot_days) {
+            if let Some(days) = days {
+                map.assign(&self.overpressure_map(time_axis, wells, dims, *days));
+            }
+        }
+        maps
+    }
+}
+
+/// The pressure the wells must stay below, from the fracture pressure at their depth.
+/// Depths are taken from the grid, so they must be in metres.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PressureLimit {
+    /// Fracture pressure per metre of depth in Pa/m
+    pub fracture_gradient: f64,
+    /// Initial (hydrostatic) pressure per metre of depth in Pa/m
+    pub hydrostatic_gradient: f64,
+    /// Fraction of the fracture pressure the bottomhole pressure may reach, e.g. 0.9
+    pub safety_factor: f64,
+    /// Days between updates of the injection rate
+    pub step_days: u64,
+}
+
+impl PressureLimit {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        let invalid = |message: String| {
+            Err(SimulationError::InvalidValue {
+                argument: "pressure_limit".to_string(),
+                message,
+            })
+        };
+        if !(self.hydrostatic_gradient.is_finite() && self.hydrostatic_gradient >= 0.0) {
+            return invalid("the hydrostatic gradient must be non-negative".to_string());
+        }
+        if !(self.safety_factor > 0.0 && self.safety_factor <= 1.0) {
+            return invalid(format!(
+                "the safety factor must be in (0, 1], got {}",
+                self.safety_factor
+            ));
+        }
+        if !(self.fracture_gradient.is_finite()
+            && self.safety_factor * self.fracture_gradient > self.hydrostatic_gradient)
+        {
+            return invalid(
+                "the allowed fracture pressure must be above the hydrostatic pressure".to_string(),
+            );
+        }
+        if self.step_days == 0 {
+            return invalid("the step must be at least 1 day".to_string());
+        }
+        Ok(())
+    }
+
+    /// The highest overpressure allowed at the given depth
+    pub fn max_overpressure(&self, depth: f64) -> f64 {
+        (self.safety_factor * self.fracture_gradient - self.hydrostatic_gradient) * depth
+    }
+}
+
+impl PressureModel {
+    /// The injection schedule the wells can actually follow without the bottomhole pressure passing the limit.
+    /// Every step the rate is the scheduled one, or lower if that would push the pressure at any well past the
+    /// limit by the end of the step. The pressure at each well includes the interference from the other wells.
+    /// Wells are given as ((x, y), depth), and the schedule is followed until `total_cells` cells are filled.
+    pub fn throttled_time_axis(
+        &self,
+        time_axis: &TimeAxis,
+        limit: &PressureLimit,
+        wells: &[((usize, usize), f64)],
+        total_cells: usize,
+    ) -> TimeAxis {
+        let start = time_axis.start_date();
+        let share = 1.0 / wells.len().max(1) as f64;
+        let cells_to_rate = self.cell_volume / SECONDS_PER_DAY;
+        // Overpressure at every well per unit total rate, after the given number of days
+        let unit_response = |well: usize, days: u64| -> f64 {
+            let ((x, y), _) = wells[well];
+            wells
+                .iter()
+                .map(|&((wx, wy), _)| {
+                    let dx = (x as f64 - wx as f64) * self.cell_size.0;
+                    let dy = (y as f64 - wy as f64) * self.cell_size.1;
+                    share
+                        * self.theis(
+                            libm::hypot(dx, dy).max(self.well_radius),
+                            days as f64 * SECONDS_PER_DAY,
+                        )
+                })
+                .sum()
+        };
+        let max_overpressure: Vec<f64> = wells
+            .iter()
+            .map(|&(_, depth)| limit.max_overpressure(depth))
+            .collect();
+        let schedule_days: Vec<u64> = time_axis
+            .schedule()
+            .iter()
+            .map(|change| (change.date - start).num_days() as u64)
+            .collect();
+
+        // (day, change of the total rate in cells per day)
+        let mut rate_changes: Vec<(u64, f64)> = Vec::new();
+        let mut schedule = Vec::new();
+        let mut rate = 0.0;
+        let mut filled = 0.0;
+        let mut day = 0;
+        while filled < total_cells as f64 && day < MAX_THROTTLING_DAYS {
+            let current = schedule_days.iter().rposition(|&d| d <= day).unwrap_or(0);
+            let scheduled = time_axis.schedule()[current].cells_per_day;
+            let next_change = schedule_days.get(current + 1).copied();
+            if scheduled == 0.0 && next_change.is_none() {
+                break;
+            }
+            let end = next_change.map_or(day + limit.step_days, |d| d.min(day + limit.step_days));
+
+            // Pressure is linear in the rate, so solve for the rate that just reaches the limit at the end of the step
+            let mut allowed = scheduled;
+            for (well, &max) in max_overpressure.iter().enumerate() {
+                let held: f64 = rate_changes
+                    .iter()
+                    .map(|&(d, change)| change * cells_to_rate * unit_response(well, end - d))
+                    .sum();
+                let headroom = (max - held) / (cells_to_rate * unit_response(well, end - day));
+                allowed = allowed.min(rate + headroom);
+            }
+            let allowed = allowed.max(0.0);
+
+            if schedule.is_empty() || allowed != rate {
+                rate_changes.push((day, allowed - rate));
+                schedule.push(RateChange {
+                    date: start + Days::new(day),
+                    cells_per_day: allowed,
+                });
+                rate = allowed;
+            }
+            filled += rate * (end - day) as f64;
+            day = end;
+        }
+        if schedule.is_empty() {
+            return time_axis.clone();
+        }
+        TimeAxis::with_schedule(schedule)
+            .expect("throttled rates are non-negative with increasing dates")
+    }
+}
+
+/// The exponential integral E1(u), which is the Theis well function W(u)
+pub fn exponential_integral(u: f64) -> f64 {
+    if u <= 0.0 {
+        return f64::INFINITY;
+    }
+    if u < 1.0 {
+        // Power series
+        const EULER_GAMMA: f64 = 0.577_215_664_901_532_9;
+        let mut sum = 0.0;
+        let mut term = 1.0;
+        for k in 1..100 {
+            term *= -u / k as f64;
+            let next = -term / k as f64;
+            sum += next;
+            if next.abs() < 1e-16 * sum.abs() {
+                break;
+            }
+        }
+        -EULER_GAMMA - libm::log(u) + sum
+    } else {
+        // Continued fraction, evaluated with the modified Lentz method
+        let tiny = 1e-300;
+        let mut b = u + 1.0;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..100 {
+            let a = -(i * i) as f64;
+            b += 2.0;
+            d = 1.0 / (a * d + b);
+            c = b + a / c;
+            let delta = c * d;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-16 {
+                break;
+            }
+        }
+        h * libm::exp(-u)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time_axis::RateChange;
+    use chrono::NaiveDate;
+
+    fn model() -> PressureModel {
+        PressureModel {
+            permeability: 1e-13,
+            thickness: 50.0,
+            porosity: 0.2,
+            total_compressibility: 1e-9,
+            brine_viscosity: 5e-4,
+            cell_size: (100.0, 100.0),
+            cell_volume: 1000.0,
+            well_radius: 0.1,
+        }
+    }
+
+    #[test]
+    fn test_throttling() {
+        let start = NaiveDate::from_ymd_opt(2030, 1, 1).unwrap();
+        let model = PressureModel {
+            permeability: 1e-12,
+            ..model()
+        };
+        let axis = TimeAxis::constant_rate(start, 10.0).unwrap();
+        let limit = PressureLimit {
+            fracture_gradient: 13660.0,
+            hydrostatic_gradient: 10000.0,
+            safety_factor: 0.9,
+            step_days: 30,
+        };
+        let wells = [((2, 2), 1000.0)];
+
+        let throttled = model.throttled_time_axis(&axis, &limit, &wells, 10000);
+        // The rate starts out as scheduled and is cut back once the pressure reaches the limit
+        assert_eq!(throttled.schedule()[0].cells_per_day, 10.0);
+        let last = throttled.schedule().last().unwrap().cells_per_day;
+        assert!(last < 10.0 && last > 0.0);
+        assert!(throttled.days_to_fill(10000).unwrap() > axis.days_to_fill(10000).unwrap());
+
+        // The bottomhole pressure stays below the limit
+        let pressure = model.overpressure(&throttled, 1.0, 0.0, 900.0);
+        assert!(pressure <= limit.max_overpressure(1000.0) * 1.000001);
+
+        // A loose limit leaves the schedule as it is
+        let loose = PressureLimit {
+            fracture_gradient: 1e9,
+            ..limit.clone()
+        };
+        let unthrottled = model.throttled_time_axis(&axis, &loose, &wells, 10000);
+        assert_eq!(unthrottled.days_to_fill(10000), axis.days_to_fill(10000));
+
+        assert!(PressureLimit {
+            safety_factor: 0.5,
+            ..limit
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_exponential_integral() {
+        assert!((exponential_integral(0.1) - 1.822_923_958).abs() < 1e-8);
+        assert!((exponential_integral(1.0) - 0.219_383_934).abs() < 1e-8);
+        assert!((exponential_integral(5.0) - 0.001_148_296).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_overpressure_maps() {
+        let start = NaiveDate::from_ymd_opt(2030, 1, 1).unwrap();
+        let model = model();
+        let axis = TimeAxis::constant_rate(start, 10.0).unwrap();
+
+        let maps =
+            model.overpressure_maps(&axis, &[(2, 2)], (5, 5), &[Some(10.0), Some(100.0), None]);
+        assert_eq!(maps.dim(), (3, 5, 5));
+        // Highest at the well, falling off with distance and rising with time
+        assert!(maps[[1, 2, 2]] > maps[[1, 2, 3]]);
+        assert!(maps[[1, 2, 3]] > maps[[1, 0, 0]]);
+        assert!(maps[[1, 2, 2]] > maps[[0, 2, 2]]);
+        assert_eq!(maps[[1, 1, 2]], maps[[1, 3, 2]]);
+        assert!(maps[[2, 2, 2]].is_nan());
+
+        // Stopping the injection lets the pressure fall off again
+        let stopped = TimeAxis::with_schedule(vec![
+            RateChange {
+                date: start,
+                cells_per_day: 10.0,
+            },
+            RateChange {
+                date: start + chrono::Days::new(10),
+                cells_per_day: 0.0,
+            },
+        ])
+        .unwrap();
+        let at_stop = model.overpressure(&stopped, 1.0, 100.0, 10.0);
+        let later = model.overpressure(&stopped, 1.0, 100.0, 100.0);
+        assert!(later < at_stop && later > 0.0);
+
+        assert!(PressureModel {
+            porosity: 0.0,
+            ..model
+        }
+        .validate()
+        .is_err());
+    }
+}
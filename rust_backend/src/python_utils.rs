@@ -0,0 +1,533 @@
+use chrono::NaiveDate;
+use numpy::ndarray::{Array, Array2, Array3, ArrayView3, CowArray, Dimension, Ix2, Ix3};
+use numpy::{PyReadonlyArray, PyReadonlyArray3, PyUntypedArray, PyUntypedArrayMethods};
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyTuple};
+
+use crate::aquifer::AquiferFlow;
+use crate::breach::{BreachGeometry, StressCriterion};
+use crate::cell_state::VelocityClassifier;
+use crate::constants::VELOCITY_CO2;
+use crate::darcy::FluidProperties;
+use crate::eos::{DensityTable, MassAccounting};
+use crate::geostatistics::ThresholdParameters;
+use crate::ordering::FrontOrdering;
+use crate::perforation::{Perforation, SweepOrder};
+use crate::pressure::{PressureLimit, PressureModel};
+use crate::smoothing::Smoothing;
+use crate::spreading::StochasticSpreading;
+use crate::stencil::Stencil;
+use crate::stream::{Co2Stream, Impurity};
+use crate::thermal::ThermalZone;
+use crate::time_axis::{RateChange, TimeAxis};
+use crate::utils::compute_bedrock_indices;
+
+/// Helper function to describe what was passed instead of a supported array
+fn describe_input(ob: &Bound<'_, PyAny>) -> String {
+    match ob.downcast::<PyUntypedArray>() {
+        Ok(array) => format!(
+            "{}-dimensional array with dtype {}",
+            array.ndim(),
+            array.dtype()
+        ),
+        Err(_) => match ob.get_type().name() {
+            Ok(name) => format!("object of type {}", name),
+            Err(_) => "unknown object".to_string(),
+        },
+    }
+}
+
+/// Helper function to describe the expected dimensionality
+fn describe_ndim<D: Dimension>() -> String {
+    match D::NDIM {
+        Some(ndim) => format!("{}-dimensional", ndim),
+        None => "numpy".to_string(),
+    }
+}
+
+/// A float array passed from Python. Both float32 and float64 are accepted.
+pub enum FloatArray<'py, D: Dimension> {
+    F32(PyReadonlyArray<'py, f32, D>),
+    F64(PyReadonlyArray<'py, f64, D>),
+}
+
+impl<'py, D: Dimension> FloatArray<'py, D> {
+    /// View the array as f64. Only float32 input is copied.
+    pub fn as_f64(&self) -> CowArray<'_, f64, D> {
+        match self {
+            FloatArray::F32(array) => CowArray::from(array.as_array().mapv(f64::from)),
+            FloatArray::F64(array) => CowArray::from(array.as_array()),
+        }
+    }
+}
+
+impl<'py, D: Dimension> FromPyObject<'py> for FloatArray<'py, D> {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        if let Ok(array) = ob.extract::<PyReadonlyArray<'py, f64, D>>() {
+            return Ok(FloatArray::F64(array));
+        }
+        if let Ok(array) = ob.extract::<PyReadonlyArray<'py, f32, D>>() {
+            return Ok(FloatArray::F32(array));
+        }
+        Err(PyTypeError::new_err(format!(
+            "expected a {} array with dtype float32 or float64, got {}",
+            describe_ndim::<D>(),
+            describe_input(ob)
+        )))
+    }
+}
+
+/// An integer index array passed from Python. int32, int64 and uint64 are accepted.
+pub enum IndexArray<'py, D: Dimension> {
+    I32(PyReadonlyArray<'py, i32, D>),
+    I64(PyReadonlyArray<'py, i64, D>),
+    U64(PyReadonlyArray<'py, u64, D>),
+}
+
+impl<'py, D: Dimension> IndexArray<'py, D> {
+    /// Convert to usize indices. Negative indices give a ValueError naming the argument.
+    pub fn to_usize(&self, name: &str) -> PyResult<Array<usize, D>> {
+        let negative_error =
+            |v: i64| PyValueError::new_err(format!("{} must be non-negative, found {}", name, v));
+
+        match self {
+            IndexArray::I32(array) => {
+                if let Some(&v) = array.as_array().iter().find(|&&v| v < 0) {
+                    return Err(negative_error(v as i64));
+                }
+                Ok(array.as_array().mapv(|x| x as usize))
+            }
+            IndexArray::I64(array) => {
+                if let Some(&v) = array.as_array().iter().find(|&&v| v < 0) {
+                    return Err(negative_error(v));
+                }
+                Ok(array.as_array().mapv(|x| x as usize))
+            }
+            IndexArray::U64(array) => Ok(array.as_array().mapv(|x| x as usize)),
+        }
+    }
+}
+
+impl<'py, D: Dimension> FromPyObject<'py> for IndexArray<'py, D> {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        if let Ok(array) = ob.extract::<PyReadonlyArray<'py, i32, D>>() {
+            return Ok(IndexArray::I32(array));
+        }
+        if let Ok(array) = ob.extract::<PyReadonlyArray<'py, i64, D>>() {
+            return Ok(IndexArray::I64(array));
+        }
+        if let Ok(array) = ob.extract::<PyReadonlyArray<'py, u64, D>>() {
+            return Ok(IndexArray::U64(array));
+        }
+        Err(PyTypeError::new_err(format!(
+            "expected a {} array with dtype int32, int64 or uint64, got {}",
+            describe_ndim::<D>(),
+            describe_input(ob)
+        )))
+    }
+}
+
+/// The source passed from Python, either a single (x, y, z) tuple or a list of them for a multi-well run
+pub struct Sources(pub Vec<(usize, usize, usize)>);
+
+impl<'py> FromPyObject<'py> for Sources {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        if let Ok(source) = ob.extract::<(usize, usize, usize)>() {
+            return Ok(Sources(vec![source]));
+        }
+        if let Ok(sources) = ob.extract::<Vec<(usize, usize, usize)>>() {
+            return Ok(Sources(sources));
+        }
+        Err(PyTypeError::new_err(format!(
+            "expected source as an (x, y, z) tuple or a list of them, got {}",
+            describe_input(ob)
+        )))
+    }
+}
+
+/// Use the bedrock indices passed from Python, or compute them from the reservoir matrix if None was passed
+pub fn resolve_bedrock_indices(
+    bedrock_indices: Option<IndexArray<'_, Ix2>>,
+    reservoir_matrix: &ArrayView3<f64>,
+    classifier: VelocityClassifier,
+) -> PyResult<Array2<usize>> {
+    match bedrock_indices {
+        Some(bedrock_indices) => bedrock_indices.to_usize("bedrock_indices"),
+        None => Ok(compute_bedrock_indices(reservoir_matrix, classifier)),
+    }
+}
+
+/// Build the time axis from an injection schedule passed from Python as a list of (ISO date, cells per day).
+/// The first entry is the injection start.
+pub fn parse_injection_schedule(schedule: Vec<(String, f64)>) -> PyResult<TimeAxis> {
+    Ok(TimeAxis::with_schedule(parse_rate_changes(
+        schedule,
+        "injection_schedule",
+    )?)?)
+}
+
+/// Parse rate changes passed from Python as a list of (ISO date, cells per day)
+pub fn parse_rate_changes(
+    schedule: Vec<(String, f64)>,
+    argument: &str,
+) -> PyResult<Vec<RateChange>> {
+    schedule
+        .into_iter()
+        .map(|(date, cells_per_day)| {
+            let date = date.parse::<NaiveDate>().map_err(|err| {
+                PyValueError::new_err(format!("invalid date {:?} in {}: {}", date, argument, err))
+            })?;
+            Ok(RateChange {
+                date,
+                cells_per_day,
+            })
+        })
+        .collect()
+}
+
+/// The velocity classifier for a tolerance passed from Python. A tolerance of zero only accepts the exact velocities.
+pub fn velocity_classifier(velocity_tolerance: f64) -> VelocityClassifier {
+    if velocity_tolerance == 0.0 {
+        VelocityClassifier::Exact
+    } else {
+        VelocityClassifier::Tolerance(velocity_tolerance)
+    }
+}
+
+/// Build the pressure model from a dict passed from Python. Every property is required except well_radius,
+/// which defaults to 0.1 m.
+pub fn parse_pressure_model(properties: &Bound<'_, PyDict>) -> PyResult<PressureModel> {
+    let get = |name: &str| -> PyResult<Bound<'_, PyAny>> {
+        properties
+            .get_item(name)?
+            .ok_or_else(|| PyValueError::new_err(format!("pressure_model is missing {:?}", name)))
+    };
+    Ok(PressureModel {
+        permeability: get("permeability")?.extract()?,
+        thickness: get("thickness")?.extract()?,
+        porosity: get("porosity")?.extract()?,
+        total_compressibility: get("total_compressibility")?.extract()?,
+        brine_viscosity: get("brine_viscosity")?.extract()?,
+        cell_size: get("cell_size")?.extract()?,
+        cell_volume: get("cell_volume")?.extract()?,
+        well_radius: match properties.get_item("well_radius")? {
+            Some(radius) => radius.extract()?,
+            None => 0.1,
+        },
+    })
+}
+
+/// Build the pressure limit from a dict passed from Python. The fracture gradient is required, the hydrostatic
+/// gradient defaults to 10000 Pa/m, the safety factor to 0.9 and the step to 30 days.
+pub fn parse_pressure_limit(properties: &Bound<'_, PyDict>) -> PyResult<PressureLimit> {
+    let fracture_gradient = properties
+        .get_item("fracture_gradient")?
+        .ok_or_else(|| PyValueError::new_err("pressure_limit is missing \"fracture_gradient\""))?
+        .extract()?;
+    let get_or = |name: &str, default: f64| -> PyResult<f64> {
+        match properties.get_item(name)? {
+            Some(value) => value.extract(),
+            None => Ok(default),
+        }
+    };
+    Ok(PressureLimit {
+        fracture_gradient,
+        hydrostatic_gradient: get_or("hydrostatic_gradient", 10000.0)?,
+        safety_factor: get_or("safety_factor", 0.9)?,
+        step_days: match properties.get_item("step_days")? {
+            Some(days) => days.extract()?,
+            None => 30,
+        },
+    })
+}
+
+/// Build the stress breach criterion from a dict passed from Python. The minimum horizontal stress gradient and the
+/// density difference are required, the hydrostatic gradient defaults to 10000 Pa/m and the overpressure to 0.
+pub fn parse_stress_criterion(properties: &Bound<'_, PyDict>) -> PyResult<StressCriterion> {
+    let get = |name: &str, default: Option<f64>| -> PyResult<f64> {
+        match (properties.get_item(name)?, default) {
+            (Some(value), _) => value.extract(),
+            (None, Some(default)) => Ok(default),
+            (None, None) => Err(PyValueError::new_err(format!(
+                "stress_criterion is missing {:?}",
+                name
+            ))),
+        }
+    };
+    Ok(StressCriterion {
+        min_horizontal_stress_gradient: get("min_horizontal_stress_gradient", None)?,
+        hydrostatic_gradient: get("hydrostatic_gradient", Some(10000.0))?,
+        density_difference: get("density_difference", None)?,
+        overpressure: get("overpressure", Some(0.0))?,
+    })
+}
+
+/// Build the aquifer flow from a dict passed from Python. The hydraulic gradient and cell size are required as
+/// (x, y) pairs, the density ratio defaults to 3 and the drift rate to 0, which turns the post-injection drift off.
+pub fn parse_aquifer_flow(properties: &Bound<'_, PyDict>) -> PyResult<AquiferFlow> {
+    let pair = |name: &str| -> PyResult<(f64, f64)> {
+        properties
+            .get_item(name)?
+            .ok_or_else(|| PyValueError::new_err(format!("aquifer_flow is missing {:?}", name)))?
+            .extract()
+    };
+    let get_or = |name: &str, default: f64| -> PyResult<f64> {
+        match properties.get_item(name)? {
+            Some(value) => value.extract(),
+            None => Ok(default),
+        }
+    };
+    Ok(AquiferFlow {
+        hydraulic_gradient: pair("hydraulic_gradient")?,
+        cell_size: pair("cell_size")?,
+        density_ratio: get_or("density_ratio", 3.0)?,
+        drift_rate: get_or("drift_rate", 0.0)?,
+    })
+}
+
+/// Build the thermal zone from a dict passed from Python. The radius in cells is required, the density factor
+/// defaults to 1 and the CO2 velocity to the usual CO2 velocity.
+pub fn parse_thermal_zone(properties: &Bound<'_, PyDict>) -> PyResult<ThermalZone> {
+    let radius = properties
+        .get_item("radius")?
+        .ok_or_else(|| PyValueError::new_err("thermal_zone is missing \"radius\""))?
+        .extract()?;
+    let get_or = |name: &str, default: f64| -> PyResult<f64> {
+        match properties.get_item(name)? {
+            Some(value) => value.extract(),
+            None => Ok(default),
+        }
+    };
+    Ok(ThermalZone {
+        radius,
+        density_factor: get_or("density_factor", 1.0)?,
+        co2_velocity: get_or("co2_velocity", VELOCITY_CO2)?,
+    })
+}
+
+/// Build the fluid properties of the Darcy-flow engine from a dict passed from Python. Missing values keep
+/// their defaults.
+pub fn parse_fluid_properties(properties: &Bound<'_, PyDict>) -> PyResult<FluidProperties> {
+    let defaults = FluidProperties::default();
+    let get_or = |name: &str, default: f64| -> PyResult<f64> {
+        match properties.get_item(name)? {
+            Some(value) => value.extract(),
+            None => Ok(default),
+        }
+    };
+    Ok(FluidProperties {
+        brine_density: get_or("brine_density", defaults.brine_density)?,
+        co2_density: get_or("co2_density", defaults.co2_density)?,
+        brine_viscosity: get_or("brine_viscosity", defaults.brine_viscosity)?,
+        co2_viscosity: get_or("co2_viscosity", defaults.co2_viscosity)?,
+        corey_exponent: get_or("corey_exponent", defaults.corey_exponent)?,
+    })
+}
+
+/// Build the random threshold parameters from a dict passed from Python. The median is required, the log
+/// standard deviation defaults to 1, the correlation lengths to (10, 10, 2) cells and the seed to 0.
+pub fn parse_random_thresholds(properties: &Bound<'_, PyDict>) -> PyResult<ThresholdParameters> {
+    let median = properties
+        .get_item("median")?
+        .ok_or_else(|| PyValueError::new_err("random_thresholds is missing \"median\""))?
+        .extract()?;
+    let defaults = ThresholdParameters::default();
+    let log_std = match properties.get_item("log_std")? {
+        Some(value) => value.extract()?,
+        None => defaults.log_std,
+    };
+    let correlation_lengths = match properties.get_item("correlation_lengths")? {
+        Some(value) => value.extract()?,
+        None => defaults.correlation_lengths,
+    };
+    let seed = match properties.get_item("seed")? {
+        Some(value) => value.extract()?,
+        None => defaults.seed,
+    };
+    if !(median >= 0.0 && log_std >= 0.0) {
+        return Err(PyValueError::new_err(
+            "random_thresholds must have a non-negative median and log_std",
+        ));
+    }
+    Ok(ThresholdParameters {
+        median,
+        log_std,
+        correlation_lengths,
+        seed,
+    })
+}
+
+/// Build the stochastic spreading rules from a dict passed from Python. "probabilities" is either a single
+/// probability for every direction or a list for +x, -x, +y and -y, and defaults to 0.5. The permeability
+/// array is optional and the seed defaults to 0.
+pub fn parse_stochastic_spreading(properties: &Bound<'_, PyDict>) -> PyResult<StochasticSpreading> {
+    let defaults = StochasticSpreading::default();
+    let probabilities = match properties.get_item("probabilities")? {
+        Some(value) => match value.extract::<f64>() {
+            Ok(probability) => [probability; 4],
+            Err(_) => value.extract()?,
+        },
+        None => defaults.probabilities,
+    };
+    let permeability = properties
+        .get_item("permeability")?
+        .map(|value| {
+            let array: FloatArray<'_, Ix3> = value.extract()?;
+            Ok::<_, PyErr>(array.as_f64().into_owned())
+        })
+        .transpose()?;
+    let seed = match properties.get_item("seed")? {
+        Some(value) => value.extract()?,
+        None => defaults.seed,
+    };
+    Ok(StochasticSpreading {
+        probabilities,
+        permeability,
+        seed,
+    })
+}
+
+/// Build the velocity-model smoothing from a dict passed from Python. Missing values keep their defaults.
+pub fn parse_smoothing(properties: &Bound<'_, PyDict>) -> PyResult<Smoothing> {
+    let defaults = Smoothing::default();
+    Ok(Smoothing {
+        iterations: match properties.get_item("iterations")? {
+            Some(value) => value.extract()?,
+            None => defaults.iterations,
+        },
+        strength: match properties.get_item("strength")? {
+            Some(value) => value.extract()?,
+            None => defaults.strength,
+        },
+    })
+}
+
+/// Read the initial plume from Python: a boolean mask of the cells holding CO2, or the snapshots of an earlier
+/// run, where the filled cells are non-negative
+pub fn parse_initial_plume(value: &Bound<'_, PyAny>) -> PyResult<Array3<bool>> {
+    if let Ok(mask) = value.extract::<PyReadonlyArray3<'_, bool>>() {
+        return Ok(mask.as_array().to_owned());
+    }
+    if let Ok(snapshots) = value.extract::<PyReadonlyArray3<'_, i32>>() {
+        return Ok(snapshots.as_array().mapv(|snapshot| snapshot >= 0));
+    }
+    if let Ok(snapshots) = value.extract::<PyReadonlyArray3<'_, i64>>() {
+        return Ok(snapshots.as_array().mapv(|snapshot| snapshot >= 0));
+    }
+    Err(PyTypeError::new_err(format!(
+        "expected initial_plume as a 3-dimensional array with dtype bool, int32 or int64, got {}",
+        describe_input(value)
+    )))
+}
+
+/// Build the perforation from Python: a (top, bottom) tuple is an interval with both layers included, a list
+/// gives the perforated layers, and a dict of layer to fraction splits the injection between the layers
+pub fn parse_perforation(value: &Bound<'_, PyAny>) -> PyResult<Perforation> {
+    if let Ok(fractions) = value.downcast::<PyDict>() {
+        let layers = fractions
+            .iter()
+            .map(|(layer, fraction)| Ok((layer.extract()?, fraction.extract()?)))
+            .collect::<PyResult<_>>()?;
+        return Ok(Perforation::split(layers));
+    }
+    if value.is_instance_of::<PyTuple>() {
+        let (top, bottom): (usize, usize) = value.extract()?;
+        if top > bottom {
+            return Err(PyValueError::new_err(format!(
+                "the perforation interval ({}, {}) has its top below its bottom",
+                top, bottom
+            )));
+        }
+        return Ok(Perforation::interval(top, bottom));
+    }
+    Ok(Perforation::new(value.extract()?))
+}
+
+/// Parse the breach geometry from a name, "single_cell", "chimney" or "threshold", or a (name, size) tuple
+/// with the chimney width or the minimum column height of the threshold
+pub fn parse_breach_geometry(value: &Bound<'_, PyAny>) -> PyResult<BreachGeometry> {
+    let (name, size): (String, Option<usize>) = if value.is_instance_of::<PyTuple>() {
+        let (name, size) = value.extract()?;
+        (name, Some(size))
+    } else {
+        (value.extract()?, None)
+    };
+    BreachGeometry::from_name(&name, size).ok_or_else(|| {
+        PyValueError::new_err(format!(
+            "unknown breach_geometry {:?}, expected \"single_cell\", \"chimney\" or \"threshold\"",
+            name
+        ))
+    })
+}
+
+/// Parse the key the front orders its cells by: "depth", "seal_potential" or "well_distance"
+pub fn parse_front_ordering(name: &str) -> PyResult<FrontOrdering> {
+    FrontOrdering::from_name(name).ok_or_else(|| {
+        PyValueError::new_err(format!(
+            "unknown front_ordering {:?}, expected \"depth\", \"seal_potential\" or \"well_distance\"",
+            name
+        ))
+    })
+}
+
+/// Parse the sweep order of the perforated interval: "top_down", "bottom_up" or "interleaved"
+pub fn parse_sweep_order(name: &str) -> PyResult<SweepOrder> {
+    SweepOrder::from_name(name).ok_or_else(|| {
+        PyValueError::new_err(format!(
+            "unknown sweep_order {:?}, expected \"top_down\", \"bottom_up\" or \"interleaved\"",
+            name
+        ))
+    })
+}
+
+/// Build the spreading stencil from a list of (dx, dy, dz) offsets, each with an optional fourth weight
+pub fn parse_stencil(offsets: &Bound<'_, PyAny>) -> PyResult<Stencil> {
+    let offsets = offsets
+        .try_iter()?
+        .map(|offset| {
+            let offset = offset?;
+            if let Ok((dx, dy, dz, weight)) = offset.extract::<(i32, i32, i32, f64)>() {
+                return Ok(((dx, dy, dz), weight));
+            }
+            Ok((offset.extract::<(i32, i32, i32)>()?, 0.0))
+        })
+        .collect::<PyResult<_>>()?;
+    Ok(Stencil::weighted(offsets))
+}
+
+/// Build the stream composition from a dict of impurity names (N2, CH4, Ar, O2, H2) to mole fractions
+pub fn parse_co2_stream(impurities: &Bound<'_, PyDict>) -> PyResult<Co2Stream> {
+    let impurities = impurities
+        .iter()
+        .map(|(name, fraction)| {
+            let name: String = name.extract()?;
+            let impurity = Impurity::from_name(&name).ok_or_else(|| {
+                PyValueError::new_err(format!("co2_stream has unknown impurity {:?}", name))
+            })?;
+            Ok((impurity, fraction.extract()?))
+        })
+        .collect::<PyResult<_>>()?;
+    Ok(Co2Stream { impurities })
+}
+
+/// Build the mass accounting from a dict passed from Python. The cell volume is required, the density table
+/// (a list of (depth, density)) defaults to `DensityTable::typical` and max_injected_mass to no limit.
+pub fn parse_mass_accounting(properties: &Bound<'_, PyDict>) -> PyResult<MassAccounting> {
+    let cell_volume = properties
+        .get_item("cell_volume")?
+        .ok_or_else(|| PyValueError::new_err("mass_accounting is missing \"cell_volume\""))?
+        .extract()?;
+    let density = match properties.get_item("density_table")? {
+        Some(table) => DensityTable::new(table.extract()?)?,
+        None => DensityTable::typical(),
+    };
+    Ok(MassAccounting {
+        cell_volume,
+        density,
+        max_injected_mass: properties
+            .get_item("max_injected_mass")?
+            .map(|mass| mass.extract())
+            .transpose()?,
+    })
+}
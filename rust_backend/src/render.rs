@@ -0,0 +1,356 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::str::FromStr;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, Rgb, RgbImage};
+use numpy::ndarray::{Array2, ArrayView3, Axis};
+
+/// A colormap interpolating linearly between evenly spaced color stops
+#[derive(Debug, Clone, PartialEq)]
+pub struct Colormap {
+    stops: Vec<[u8; 3]>,
+}
+
+impl Colormap {
+    /// A colormap from at least two color stops, from low to high values
+    pub fn from_stops(stops: Vec<[u8; 3]>) -> Result<Self, String> {
+        if stops.len() < 2 {
+            return Err("a colormap needs at least two color stops".to_string());
+        }
+        Ok(Colormap { stops })
+    }
+
+    pub fn viridis() -> Self {
+        Colormap {
+            stops: vec![
+                [68, 1, 84],
+                [72, 40, 120],
+                [62, 73, 137],
+                [49, 104, 142],
+                [38, 130, 142],
+                [31, 158, 137],
+                [53, 183, 121],
+                [110, 206, 88],
+                [181, 222, 43],
+                [253, 231, 37],
+            ],
+        }
+    }
+
+    pub fn magma() -> Self {
+        Colormap {
+            stops: vec![
+                [0, 0, 4],
+                [28, 16, 68],
+                [79, 18, 123],
+                [129, 37, 129],
+                [181, 54, 122],
+                [229, 89, 100],
+                [251, 135, 97],
+                [254, 194, 135],
+                [252, 253, 191],
+            ],
+        }
+    }
+
+    pub fn gray() -> Self {
+        Colormap {
+            stops: vec![[0, 0, 0], [255, 255, 255]],
+        }
+    }
+
+    /// The color of a value between 0 and 1. Values outside are clamped.
+    pub fn color(&self, value: f64) -> [u8; 3] {
+        let position = value.clamp(0.0, 1.0) * (self.stops.len() - 1) as f64;
+        let i = (position.floor() as usize).min(self.stops.len() - 2);
+        let t = position - i as f64;
+        let (a, b) = (self.stops[i], self.stops[i + 1]);
+        [0, 1, 2].map(|c| (a[c] as f64 + t * (b[c] as f64 - a[c] as f64)).round() as u8)
+    }
+}
+
+impl FromStr for Colormap {
+    type Err = String;
+
+    /// A colormap by name: viridis, magma or gray
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "viridis" => Ok(Colormap::viridis()),
+            "magma" => Ok(Colormap::magma()),
+            "gray" | "grey" => Ok(Colormap::gray()),
+            _ => Err(format!(
+                "unknown colormap {}, expected viridis, magma or gray",
+                name
+            )),
+        }
+    }
+}
+
+/// A 2D section through the snapshots array
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slice {
+    /// Map view of a z layer, with x to the right and y downwards
+    Z(usize),
+    /// Cross-section at a fixed x, with y to the right and z downwards
+    X(usize),
+    /// Cross-section at a fixed y, with x to the right and z downwards
+    Y(usize),
+    /// Map view of the whole plume, showing the first snapshot at which CO2 arrived in each column
+    Map,
+}
+
+impl Slice {
+    /// The (horizontal, vertical) cell values of the slice
+    fn cells(&self, snapshots: &ArrayView3<i32>) -> Result<Array2<i32>, String> {
+        let (axis, index) = match *self {
+            Slice::X(x) => (0, x),
+            Slice::Y(y) => (1, y),
+            Slice::Z(z) => (2, z),
+            Slice::Map => {
+                return Ok(snapshots.map_axis(Axis(2), |column| {
+                    column
+                        .iter()
+                        .copied()
+                        .filter(|&s| s >= 0)
+                        .min()
+                        .unwrap_or(-1)
+                }))
+            }
+        };
+        let n = snapshots.len_of(Axis(axis));
+        if index >= n {
+            return Err(format!(
+                "slice index {} is outside the {} cells along that axis",
+                index, n
+            ));
+        }
+        Ok(snapshots.index_axis(Axis(axis), index).to_owned())
+    }
+}
+
+impl FromStr for Slice {
+    type Err = String;
+
+    /// Parse a slice given as `z=10`, `x=5`, `y=3` or `map`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text.trim().eq_ignore_ascii_case("map") {
+            return Ok(Slice::Map);
+        }
+        let (axis, index) = text
+            .split_once('=')
+            .ok_or_else(|| format!("expected a slice like z=10 or map, got {}", text))?;
+        let index: usize = index
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid slice index in {}", text))?;
+        match axis.trim().to_lowercase().as_str() {
+            "x" => Ok(Slice::X(index)),
+            "y" => Ok(Slice::Y(index)),
+            "z" => Ok(Slice::Z(index)),
+            _ => Err(format!(
+                "unknown slice axis in {}, expected x, y or z",
+                text
+            )),
+        }
+    }
+}
+
+/// How to render the snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    pub colormap: Colormap,
+    /// Pixels per cell along each side
+    pub scale: u32,
+    /// Color of cells without CO2
+    pub background: [u8; 3],
+    /// Only show the cells filled up to this snapshot. None shows the whole plume.
+    pub up_to_snapshot: Option<i32>,
+    /// The snapshot mapped to the top of the colormap. None uses the last snapshot in the array.
+    pub max_snapshot: Option<i32>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            colormap: Colormap::viridis(),
+            scale: 4,
+            background: [230, 230, 230],
+            up_to_snapshot: None,
+            max_snapshot: None,
+        }
+    }
+}
+
+/// Render a slice of the snapshots array, coloring every filled cell by the snapshot at which it was filled
+pub fn render_slice(
+    snapshots: &ArrayView3<i32>,
+    slice: Slice,
+    options: &RenderOptions,
+) -> Result<RgbImage, String> {
+    if options.scale == 0 {
+        return Err("the scale must be at least 1 pixel per cell".to_string());
+    }
+    let max_snapshot = options
+        .max_snapshot
+        .unwrap_or_else(|| snapshots.iter().copied().max().unwrap_or(0))
+        .max(1);
+    let cells = slice.cells(snapshots)?;
+    let (width, height) = cells.dim();
+
+    let scale = options.scale;
+    let mut image = RgbImage::new(width as u32 * scale, height as u32 * scale);
+    for ((i, j), &snapshot) in cells.indexed_iter() {
+        let visible = snapshot >= 0 && options.up_to_snapshot.is_none_or(|last| snapshot <= last);
+        let color = if visible {
+            options
+                .colormap
+                .color(snapshot as f64 / max_snapshot as f64)
+        } else {
+            options.background
+        };
+        for dx in 0..scale {
+            for dy in 0..scale {
+                image.put_pixel(i as u32 * scale + dx, j as u32 * scale + dy, Rgb(color));
+            }
+        }
+    }
+    Ok(image)
+}
+
+/// Render a slice of the snapshots array to a PNG file
+pub fn save_slice_png(
+    path: &Path,
+    snapshots: &ArrayView3<i32>,
+    slice: Slice,
+    options: &RenderOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let image = render_slice(snapshots, slice, options)?;
+    image.save_with_format(path, image::ImageFormat::Png)?;
+    Ok(())
+}
+
+/// Render one frame per snapshot, every `snapshot_step` snapshots, showing the plume growing.
+/// All frames share the colormap range, and the last frame always shows the whole plume.
+pub fn render_frames(
+    snapshots: &ArrayView3<i32>,
+    slice: Slice,
+    options: &RenderOptions,
+    snapshot_step: usize,
+) -> Result<Vec<RgbImage>, String> {
+    if snapshot_step == 0 {
+        return Err("the snapshot step must be at least 1".to_string());
+    }
+    let last_snapshot = snapshots.iter().copied().max().unwrap_or(-1).max(0);
+    let mut frame_snapshots: Vec<i32> = (0..=last_snapshot).step_by(snapshot_step).collect();
+    if frame_snapshots.last() != Some(&last_snapshot) {
+        frame_snapshots.push(last_snapshot);
+    }
+
+    frame_snapshots
+        .into_iter()
+        .map(|snapshot| {
+            let frame_options = RenderOptions {
+                up_to_snapshot: Some(snapshot),
+                max_snapshot: Some(options.max_snapshot.unwrap_or(last_snapshot)),
+                ..options.clone()
+            };
+            render_slice(snapshots, slice, &frame_options)
+        })
+        .collect()
+}
+
+/// Write the frames as a looping animated GIF
+pub fn save_animation_gif(
+    path: &Path,
+    frames: Vec<RgbImage>,
+    frame_delay_ms: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+    encoder.set_repeat(Repeat::Infinite)?;
+    let delay = Delay::from_numer_denom_ms(frame_delay_ms, 1);
+    encoder.encode_frames(frames.into_iter().map(|frame| {
+        Frame::from_parts(DynamicImage::ImageRgb8(frame).into_rgba8(), 0, 0, delay)
+    }))?;
+    Ok(())
+}
+
+/// Write the frames as numbered PNG files (frame_0000.png, ...), e.g. for encoding a video with ffmpeg
+pub fn save_frame_sequence(
+    dir: &Path,
+    frames: &[RgbImage],
+) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(dir)?;
+    for (i, frame) in frames.iter().enumerate() {
+        frame.save_with_format(
+            dir.join(format!("frame_{:04}.png", i)),
+            image::ImageFormat::Png,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use numpy::ndarray::Array3;
+
+    #[test]
+    fn test_render_slices() {
+        let mut snapshots = Array3::from_elem((3, 2, 4), -1);
+        snapshots[[2, 1, 3]] = 0;
+        snapshots[[2, 0, 3]] = 5;
+        let options = RenderOptions {
+            scale: 2,
+            ..Default::default()
+        };
+
+        let image = render_slice(&snapshots.view(), "z=3".parse().unwrap(), &options).unwrap();
+        assert_eq!(image.dimensions(), (6, 4));
+        assert_eq!(image.get_pixel(0, 0).0, options.background);
+        // x = 2, y = 1 is the first snapshot, x = 2, y = 0 the last
+        assert_eq!(image.get_pixel(5, 3).0, Colormap::viridis().color(0.0));
+        assert_eq!(image.get_pixel(4, 0).0, Colormap::viridis().color(1.0));
+
+        let image = render_slice(&snapshots.view(), Slice::X(2), &options).unwrap();
+        assert_eq!(image.dimensions(), (4, 8));
+
+        assert!(render_slice(&snapshots.view(), Slice::Y(2), &options).is_err());
+        assert!("w=1".parse::<Slice>().is_err());
+
+        // The map view shows the first arrival in each column
+        let image = render_slice(&snapshots.view(), "map".parse().unwrap(), &options).unwrap();
+        assert_eq!(image.dimensions(), (6, 4));
+        assert_eq!(image.get_pixel(4, 2).0, Colormap::viridis().color(0.0));
+    }
+
+    #[test]
+    fn test_animation_frames() {
+        let mut snapshots = Array3::from_elem((2, 1, 1), -1);
+        snapshots[[0, 0, 0]] = 0;
+        snapshots[[1, 0, 0]] = 4;
+        let options = RenderOptions {
+            scale: 1,
+            ..Default::default()
+        };
+
+        let frames = render_frames(&snapshots.view(), Slice::Z(0), &options, 3).unwrap();
+        // Snapshots 0 and 3, plus the last one
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].get_pixel(1, 0).0, options.background);
+        assert_eq!(frames[1].get_pixel(1, 0).0, options.background);
+        assert_eq!(frames[2].get_pixel(1, 0).0, Colormap::viridis().color(1.0));
+        // The colormap range is the same in every frame
+        assert_eq!(frames[0].get_pixel(0, 0), frames[2].get_pixel(0, 0));
+    }
+
+    #[test]
+    fn test_colormap() {
+        let gray = Colormap::gray();
+        assert_eq!(gray.color(0.0), [0, 0, 0]);
+        assert_eq!(gray.color(0.5), [128, 128, 128]);
+        assert_eq!(gray.color(2.0), [255, 255, 255]);
+        assert!("jet".parse::<Colormap>().is_err());
+    }
+}
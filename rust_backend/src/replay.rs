@@ -0,0 +1,333 @@
+use std::error::Error;
+use std::path::Path;
+
+use numpy::ndarray::Array3;
+use serde_json::{json, Value};
+
+use crate::error::SimulationError;
+use crate::journal::{load_journal, JournalEntry};
+use crate::plume::Plume;
+use crate::sparse::SparseGrid;
+use crate::wells::NO_WELL;
+
+/// The state of a run rebuilt from its journal, entry by entry, without the physics. Gives the exact snapshots
+/// of a recorded run, and its state at any point of it, for debugging long runs after the fact.
+#[derive(Debug, Clone)]
+pub struct JournalReplay {
+    entries: Vec<JournalEntry>,
+    // Number of entries replayed
+    position: usize,
+    sources: Vec<(usize, usize, usize)>,
+    layers: Vec<usize>,
+    snapshots: SparseGrid<i32>,
+    // The well whose front filled each cell, when it was filled
+    wells: SparseGrid<i16>,
+    // Broken caprock cells that have not closed again, in the order they broke
+    open_breaches: Vec<(usize, usize, usize)>,
+    n_breaches: usize,
+    cells_filled: usize,
+    // The last complete snapshot, or -1
+    snapshot_index: i32,
+    finished: bool,
+}
+
+/// Check that the journal starts with the shape of the grid, that its cells are on the grid and that its counts
+/// of filled cells agree with its fills, so it can be replayed. Returns the shape.
+fn check_journal(entries: &[JournalEntry]) -> Result<(usize, usize, usize), SimulationError> {
+    let invalid = |message: String| SimulationError::InvalidValue {
+        argument: "journal".to_string(),
+        message,
+    };
+    let Some(JournalEntry::Start { shape, .. }) = entries.first() else {
+        return Err(invalid("does not start with a start entry".to_string()));
+    };
+    let (nx, ny, nz) = *shape;
+    let mut fills = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        let (cell, cells_filled) = match entry {
+            JournalEntry::Start { .. } if i > 0 => {
+                return Err(invalid(format!("entry {} starts a second run", i)))
+            }
+            JournalEntry::Fill { cell, .. } => {
+                fills += 1;
+                (Some(*cell), None)
+            }
+            JournalEntry::Breach {
+                cell, cells_filled, ..
+            }
+            | JournalEntry::Reseal { cell, cells_filled } => (Some(*cell), Some(*cells_filled)),
+            JournalEntry::Snapshot { cells_filled, .. }
+            | JournalEntry::Finish { cells_filled, .. } => (None, Some(*cells_filled)),
+            _ => (None, None),
+        };
+        if let Some((x, y, z)) = cell {
+            if x >= nx || y >= ny || z >= nz {
+                return Err(invalid(format!(
+                    "entry {} has the cell {:?} outside the grid of shape {:?}",
+                    i,
+                    (x, y, z),
+                    shape
+                )));
+            }
+        }
+        if let Some(cells_filled) = cells_filled {
+            if cells_filled != fills {
+                return Err(invalid(format!(
+                    "entry {} records {} cells filled, but {} fills precede it",
+                    i, cells_filled, fills
+                )));
+            }
+        }
+    }
+    Ok(*shape)
+}
+
+impl JournalReplay {
+    /// A replay of the entries of a journal, at its start. Fails if the journal is not consistent, see
+    /// `check_journal`.
+    pub fn new(entries: Vec<JournalEntry>) -> Result<Self, SimulationError> {
+        let shape = check_journal(&entries)?;
+        let mut replay = JournalReplay {
+            entries,
+            position: 0,
+            sources: Vec::new(),
+            layers: Vec::new(),
+            snapshots: SparseGrid::new(shape, -1),
+            wells: SparseGrid::new(shape, NO_WELL),
+            open_breaches: Vec::new(),
+            n_breaches: 0,
+            cells_filled: 0,
+            snapshot_index: -1,
+            finished: false,
+        };
+        replay.step();
+        Ok(replay)
+    }
+
+    /// Load a journal file and replay it from the start, see `load_journal`
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(JournalReplay::new(load_journal(path)?)?)
+    }
+
+    /// Apply the next entry of the journal and return it, or None at the end of the journal
+    pub fn step(&mut self) -> Option<&JournalEntry> {
+        let entry = self.entries.get(self.position)?;
+        match entry {
+            JournalEntry::Start { sources, .. } => self.sources = sources.clone(),
+            JournalEntry::Layer { layers } => self.layers = layers.clone(),
+            JournalEntry::Well { source } => self.sources.push(*source),
+            JournalEntry::Fill {
+                cell,
+                snapshot,
+                well,
+            } => {
+                self.snapshots.set(*cell, *snapshot);
+                self.wells.set(*cell, *well);
+                self.cells_filled += 1;
+            }
+            JournalEntry::Breach { cell, .. } => {
+                self.open_breaches.push(*cell);
+                self.n_breaches += 1;
+            }
+            JournalEntry::Reseal { cell, .. } => self.open_breaches.retain(|open| open != cell),
+            JournalEntry::Snapshot { index, .. } => self.snapshot_index = *index,
+            JournalEntry::Finish { .. } => self.finished = true,
+        }
+        self.position += 1;
+        Some(entry)
+    }
+
+    /// Replay until the given number of cells is filled, or to the end of the journal. Stops right after the
+    /// fill, so the entries recorded with it, e.g. the end of its snapshot, are not applied yet.
+    pub fn replay_to_cells(&mut self, cells_filled: usize) -> &mut Self {
+        while self.cells_filled < cells_filled && self.step().is_some() {}
+        self
+    }
+
+    /// Replay until the snapshot with the given index is complete, or to the end of the journal
+    pub fn replay_to_snapshot(&mut self, index: i32) -> &mut Self {
+        while self.snapshot_index < index && self.step().is_some() {}
+        self
+    }
+
+    /// Replay the rest of the journal
+    pub fn replay_all(&mut self) -> &mut Self {
+        while self.step().is_some() {}
+        self
+    }
+
+    /// Number of entries replayed so far, out of `len`
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Number of entries in the journal
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the replay reached the end of the run, which a journal of a stopped run never does
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn dim(&self) -> (usize, usize, usize) {
+        self.snapshots.dim()
+    }
+
+    /// The wells so far, including any that joined the run
+    pub fn sources(&self) -> &[(usize, usize, usize)] {
+        &self.sources
+    }
+
+    /// The layers the wells are injecting into
+    pub fn layers(&self) -> &[usize] {
+        &self.layers
+    }
+
+    pub fn cells_filled(&self) -> usize {
+        self.cells_filled
+    }
+
+    /// The last complete snapshot, or -1 before the first
+    pub fn snapshot_index(&self) -> i32 {
+        self.snapshot_index
+    }
+
+    /// Number of caprock cells broken so far, counting a cell again every time it broke
+    pub fn n_breaches(&self) -> usize {
+        self.n_breaches
+    }
+
+    /// The broken caprock cells that have not closed again, in the order they broke
+    pub fn open_breaches(&self) -> &[(usize, usize, usize)] {
+        &self.open_breaches
+    }
+
+    /// The snapshots so far, the same as `Simulation::snapshots` of the run after as many fills
+    pub fn snapshots(&self) -> Array3<i32> {
+        self.snapshots.to_dense()
+    }
+
+    pub fn plume(&self) -> Plume {
+        Plume::new(self.snapshots.clone())
+    }
+
+    /// The well whose front filled each cell, when it filled it. Cells without CO2 are NO_WELL.
+    pub fn well_attribution(&self) -> Array3<i16> {
+        let mut attribution = Array3::from_elem(self.dim(), NO_WELL);
+        for (cell, snapshot) in self.snapshots.iter() {
+            if snapshot >= 0 {
+                attribution[cell] = self.wells.get(cell);
+            }
+        }
+        attribution
+    }
+
+    /// Summary of the state of the replay
+    pub fn to_json(&self) -> Value {
+        json!({
+            "shape": self.dim(),
+            "position": self.position,
+            "entries": self.len(),
+            "finished": self.finished,
+            "sources": self.sources,
+            "layers": self.layers,
+            "cells_filled": self.cells_filled,
+            "snapshot_index": self.snapshot_index,
+            "breaches": self.n_breaches,
+            "open_breaches": self.open_breaches,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::breach::Resealing;
+    use crate::config::SimulationConfig;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use crate::injection_simulation::Simulation;
+    use numpy::ndarray::{s, Array1, Array2};
+
+    #[test]
+    fn test_replay_rebuilds_the_run() {
+        let mut reservoir = Array3::from_elem((5, 5, 8), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 1]).fill(VELOCITY_CAPROCK);
+        reservoir.slice_mut(s![.., .., 4]).fill(VELOCITY_CAPROCK);
+        let config = SimulationConfig {
+            max_column_height: Some(2),
+            total_snapshots: 10,
+            resealing: Some(Resealing { cells: 3 }),
+            journal: true,
+            ..Default::default()
+        };
+        let run = |cells: Option<usize>| {
+            let mut simulation = Simulation::try_new(
+                reservoir.view(),
+                Array1::from_iter((0..8).map(|z| z as f64)).view(),
+                Array2::zeros((5, 5)).view(),
+                (2, 2, 5),
+                &config,
+            )
+            .unwrap();
+            match cells {
+                Some(cells) => {
+                    simulation.advance(cells);
+                }
+                None => simulation.run(),
+            }
+            simulation
+        };
+        let mut simulation = run(None);
+        let entries = simulation.take_journal();
+        let mut replay = JournalReplay::new(entries.clone()).unwrap();
+        assert_eq!(replay.sources(), [(2, 2, 5)]);
+        assert_eq!(replay.cells_filled(), 0);
+
+        // Midway through the run
+        let halfway = simulation.cells_filled() / 2;
+        replay.replay_to_cells(halfway);
+        assert_eq!(replay.cells_filled(), halfway);
+        assert!(!replay.is_finished());
+        assert_eq!(replay.snapshots(), run(Some(halfway)).snapshots());
+        let next = replay.snapshot_index() + 1;
+        replay.replay_to_snapshot(next);
+        assert_eq!(replay.snapshot_index(), next);
+        let plume = replay.plume();
+        assert_eq!(
+            plume.cells_at_snapshot(next + 1),
+            plume.cells_at_snapshot(next)
+        );
+
+        replay.replay_all();
+        assert!(replay.is_finished());
+        assert_eq!(replay.position(), replay.len());
+        assert_eq!(replay.snapshots(), simulation.snapshots());
+        assert_eq!(replay.well_attribution(), simulation.well_attribution());
+        assert_eq!(replay.n_breaches(), simulation.breach_events().len());
+        let open: Vec<_> = simulation
+            .breach_events()
+            .iter()
+            .filter(|event| event.resealed_at.is_none())
+            .map(|event| event.cell)
+            .collect();
+        assert!(open.len() < replay.n_breaches());
+        assert_eq!(replay.open_breaches(), open);
+        assert!(replay.step().is_none());
+
+        // A journal whose counts disagree with its fills cannot be replayed
+        let mut broken = entries.clone();
+        let fill = broken
+            .iter()
+            .position(|entry| matches!(entry, JournalEntry::Fill { .. }))
+            .unwrap();
+        broken.remove(fill);
+        assert!(JournalReplay::new(broken).is_err());
+        assert!(JournalReplay::new(entries[1..].to_vec()).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use std::error::Error;
+use std::path::Path;
+
+use crate::checkpoint::load_checkpoint;
+use crate::config::SimulationConfig;
+use crate::error::SimulationError;
+use crate::injection_simulation::Simulation;
+use crate::time_axis::RateChange;
+
+/// A what-if continuation of a run from a branch point, e.g. a checkpoint, see `run_scenarios`
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    pub name: String,
+    /// The config for the rest of the run, e.g. with another max column height, or the config of the branch
+    /// point if None. Only the parameters `Simulation::update_config` accepts can differ.
+    pub config: Option<SimulationConfig>,
+    /// Injection rate changes after the branch point, replacing the later part of the rate schedule
+    pub rate_changes: Vec<RateChange>,
+    /// Wells that start injecting at the branch point
+    pub new_wells: Vec<(usize, usize, usize)>,
+}
+
+impl Scenario {
+    /// A copy of the simulation with the changes of the scenario, ready to run on from the branch point
+    pub fn branch(&self, simulation: &Simulation) -> Result<Simulation, SimulationError> {
+        let mut branch = simulation.clone();
+        if let Some(config) = &self.config {
+            branch.update_config(config.clone())?;
+        }
+        branch.reschedule(&self.rate_changes)?;
+        for &source in &self.new_wells {
+            branch.add_well(source)?;
+        }
+        Ok(branch)
+    }
+}
+
+/// Run every scenario on from the current state of the simulation. The run up to the branch point is shared, so
+/// each scenario only costs the part after it. All scenarios are branched before any of them runs, so an invalid
+/// scenario fails the batch early.
+pub fn run_scenarios(
+    simulation: &Simulation,
+    scenarios: &[Scenario],
+) -> Result<Vec<Simulation>, SimulationError> {
+    let mut branches = scenarios
+        .iter()
+        .map(|scenario| scenario.branch(simulation))
+        .collect::<Result<Vec<_>, _>>()?;
+    for branch in &mut branches {
+        branch.run();
+    }
+    Ok(branches)
+}
+
+/// Run every scenario on from a checkpoint, see `run_scenarios`
+pub fn run_scenarios_from_checkpoint(
+    path: &Path,
+    scenarios: &[Scenario],
+) -> Result<Vec<Simulation>, Box<dyn Error>> {
+    let checkpoint = load_checkpoint(path)?;
+    Ok(run_scenarios(&checkpoint.simulation, scenarios)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use crate::time_axis::TimeAxis;
+    use chrono::NaiveDate;
+    use numpy::ndarray::{s, Array1, Array2, Array3};
+
+    #[test]
+    fn test_scenarios_share_the_history() {
+        let mut reservoir = Array3::from_elem((8, 8, 4), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        let start = NaiveDate::from_ymd_opt(2030, 1, 1).unwrap();
+        let config = SimulationConfig {
+            max_column_height: None,
+            total_snapshots: 20,
+            time_axis: Some(TimeAxis::constant_rate(start, 1.0).unwrap()),
+            ..Default::default()
+        };
+        let mut simulation = Simulation::try_new(
+            reservoir.view(),
+            Array1::from(vec![0.0, 1.0, 2.0, 3.0]).view(),
+            Array2::zeros((8, 8)).view(),
+            (1, 1, 1),
+            &config,
+        )
+        .unwrap();
+        simulation.advance(20);
+
+        let later = NaiveDate::from_ymd_opt(2030, 6, 1).unwrap();
+        let scenarios = [
+            Scenario {
+                name: "unchanged".to_string(),
+                ..Default::default()
+            },
+            Scenario {
+                name: "second well".to_string(),
+                new_wells: vec![(6, 6, 1)],
+                ..Default::default()
+            },
+            Scenario {
+                name: "faster".to_string(),
+                rate_changes: vec![RateChange {
+                    date: later,
+                    cells_per_day: 4.0,
+                }],
+                ..Default::default()
+            },
+        ];
+        let branches = run_scenarios(&simulation, &scenarios).unwrap();
+        for branch in &branches {
+            assert!(branch.fill_order().starts_with(simulation.fill_order()));
+        }
+        let mut unchanged = simulation.clone();
+        unchanged.run();
+        assert_eq!(branches[0].snapshots(), unchanged.snapshots());
+
+        // The second well's front fills the far corner
+        let attribution = branches[1].well_attribution();
+        assert_eq!(attribution[[1, 1, 1]], 0);
+        assert_eq!(attribution[[7, 7, 1]], 1);
+
+        let time_axis = branches[2].effective_time_axis().unwrap();
+        assert_eq!(time_axis.schedule().len(), 2);
+        assert_eq!(branches[2].snapshots(), unchanged.snapshots());
+
+        // A scenario that cannot branch fails the batch
+        let invalid = Scenario {
+            new_wells: vec![(3, 3, 0)],
+            ..Default::default()
+        };
+        assert!(run_scenarios(&simulation, &[scenarios[0].clone(), invalid]).is_err());
+        let past = Scenario {
+            rate_changes: vec![RateChange {
+                date: start,
+                cells_per_day: 2.0,
+            }],
+            ..Default::default()
+        };
+        assert!(past.branch(&simulation).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use numpy::ndarray::{Array1, Array2, Array3};
+use serde_json::{json, Value};
+
+use crate::config::SimulationConfig;
+use crate::error::SimulationError;
+use crate::injection_simulation::Simulation;
+use crate::input_cache::{GridInputs, InputCache};
+use crate::sparse::SparseGrid;
+
+/// A grid kept in memory that answers simulation requests for different sources and parameters, for the
+/// `co2sim serve` command. Loading and validating a large model often takes longer than a single simulation,
+/// so front-ends that explore many injection points send their requests here instead of starting a new run.
+#[derive(Debug, Clone)]
+pub struct SimulationService {
+    grid: Arc<GridInputs>,
+    // Grids requested by their files under a root directory, if the service accepts them
+    grid_files: Option<(Arc<InputCache>, PathBuf)>,
+}
+
+impl SimulationService {
+    pub fn new(
+        reservoir_matrix: Array3<f64>,
+        depths: Array1<f64>,
+        bedrock_indices: Array2<usize>,
+    ) -> Self {
+        Self::from_grid(Arc::new(GridInputs {
+            reservoir_matrix,
+            depths,
+            bedrock_indices,
+        }))
+    }
+
+    /// A service on a grid that may be shared, e.g. with an `InputCache`
+    pub fn from_grid(grid: Arc<GridInputs>) -> Self {
+        SimulationService {
+            grid,
+            grid_files: None,
+        }
+    }
+
+    /// Also answer requests on other grids, named by their .npy files in the request like
+    /// {"grid": {"reservoir_matrix": "a.npy", "depths": "b.npy", "bedrock_indices": "c.npy"}}, with the bedrock
+    /// indices optional. The paths are relative to the root directory and cannot leave it, so clients only read
+    /// the files put there. The grids are read through the cache, so a grid is only read once.
+    pub fn with_grid_files(mut self, input_cache: Arc<InputCache>, root: &Path) -> Self {
+        self.grid_files = Some((input_cache, root.to_path_buf()));
+        self
+    }
+
+    /// The shape and layer depths of the grid
+    pub fn grid_json(&self) -> Value {
+        json!({
+            "shape": self.grid.reservoir_matrix.shape(),
+            "depths": self.grid.depths.to_vec(),
+        })
+    }
+
+    /// Run the simulation of a request like {"source": [x, y, z], "max_column_height": 10, "total_snapshots": 100}
+    /// and return the filled cells as sparse JSON. Every parameter but the source has the default of the config,
+    /// and a max_column_height of null never breaks the caprock. The grid of the service is used unless the request
+    /// names another, see `with_grid_files`.
+    pub fn simulate(&self, request: &Value) -> Result<Value, SimulationError> {
+        let invalid = |argument: &str, message: &str| SimulationError::InvalidValue {
+            argument: argument.to_string(),
+            message: message.to_string(),
+        };
+        let source = match request.get("source").and_then(Value::as_array) {
+            Some(source) if source.len() == 3 => {
+                let index = |i: usize| {
+                    source[i]
+                        .as_u64()
+                        .map(|index| index as usize)
+                        .ok_or_else(|| invalid("source", "must be three non-negative integers"))
+                };
+                (index(0)?, index(1)?, index(2)?)
+            }
+            _ => return Err(invalid("source", "must be given as [x, y, z]")),
+        };
+
+        let mut config = SimulationConfig::default();
+        match request.get("max_column_height") {
+            None => {}
+            Some(Value::Null) => config.max_column_height = None,
+            Some(height) => {
+                let height = height
+                    .as_u64()
+                    .ok_or_else(|| invalid("max_column_height", "must be an integer or null"))?;
+                config.max_column_height = Some(height as usize);
+            }
+        }
+        if let Some(total_snapshots) = request.get("total_snapshots") {
+            let total_snapshots = total_snapshots
+                .as_u64()
+                .ok_or_else(|| invalid("total_snapshots", "must be an integer"))?;
+            config.total_snapshots = total_snapshots as usize;
+        }
+
+        let grid = match request.get("grid") {
+            Some(grid) => self.load_grid(grid)?,
+            None => Arc::clone(&self.grid),
+        };
+        let mut simulation = Simulation::try_new(
+            grid.reservoir_matrix.view(),
+            grid.depths.view(),
+            grid.bedrock_indices.view(),
+            source,
+            &config,
+        )?;
+        simulation.run();
+        Ok(sparse_snapshots_json(simulation.sparse_snapshots()))
+    }
+
+    /// The grid named by the files in a request, see `with_grid_files`
+    fn load_grid(&self, grid: &Value) -> Result<Arc<GridInputs>, SimulationError> {
+        let invalid = |message: String| SimulationError::InvalidValue {
+            argument: "grid".to_string(),
+            message,
+        };
+        let Some((input_cache, root)) = &self.grid_files else {
+            return Err(invalid(
+                "this service only simulates on its own grid".to_string(),
+            ));
+        };
+        let path = |name: &str| -> Result<Option<PathBuf>, SimulationError> {
+            let Some(path) = grid.get(name).and_then(Value::as_str).map(Path::new) else {
+                return Ok(None);
+            };
+            // Only plain relative paths, so the file is under the root
+            if !path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(invalid(format!(
+                    "{} must be a path relative to the grid directory, without ..",
+                    name
+                )));
+            }
+            Ok(Some(root.join(path)))
+        };
+        let (Some(reservoir_matrix), Some(depths)) = (path("reservoir_matrix")?, path("depths")?)
+        else {
+            return Err(invalid(
+                "must give the reservoir_matrix and depths files".to_string(),
+            ));
+        };
+        input_cache
+            .load(
+                &reservoir_matrix,
+                &depths,
+                path("bedrock_indices")?.as_deref(),
+            )
+            .map_err(|err| invalid(err.to_string()))
+    }
+}
+
+/// The filled cells of the snapshots as {"shape": [nx, ny, nz], "indices": [...], "snapshots": [...]}, with the
+/// flat C-order index of every filled cell, in increasing order, and the snapshot it was filled in. A plume fills
+/// a small part of the grid, so this is much smaller than the dense array, and is built without it.
+pub fn sparse_snapshots_json(snapshots: &SparseGrid<i32>) -> Value {
+    let (nx, ny, nz) = snapshots.dim();
+    let mut filled: Vec<(usize, i32)> = snapshots
+        .iter()
+        .filter(|&(_, snapshot)| snapshot >= 0)
+        .map(|((x, y, z), snapshot)| ((x * ny + y) * nz + z, snapshot))
+        .collect();
+    filled.sort_unstable();
+    let (indices, filled): (Vec<usize>, Vec<i32>) = filled.into_iter().unzip();
+    json!({
+        "shape": [nx, ny, nz],
+        "indices": indices,
+        "snapshots": filled,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use numpy::ndarray::s;
+
+    #[test]
+    fn test_service_answers_requests() {
+        let mut reservoir = Array3::from_elem((4, 3, 3), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        let service = SimulationService::new(
+            reservoir,
+            Array1::from(vec![0.0, 1.0, 2.0]),
+            Array2::zeros((4, 3)),
+        );
+        assert_eq!(service.grid_json()["shape"], json!([4, 3, 3]));
+
+        let result = service
+            .simulate(
+                &json!({"source": [1, 1, 1], "max_column_height": null, "total_snapshots": 4}),
+            )
+            .unwrap();
+        assert_eq!(result["shape"], json!([4, 3, 3]));
+        let indices = result["indices"].as_array().unwrap();
+        assert_eq!(indices.len(), 24);
+        assert_eq!(indices.len(), result["snapshots"].as_array().unwrap().len());
+        // The source is filled first
+        let source = (3 + 1) * 3 + 1;
+        let position = indices.iter().position(|i| i == source).unwrap();
+        assert_eq!(result["snapshots"][position], 0);
+        assert!(indices
+            .windows(2)
+            .all(|pair| pair[0].as_u64() < pair[1].as_u64()));
+
+        assert!(service.simulate(&json!({"source": [1, 1]})).is_err());
+        let on_other_grid = json!({
+            "source": [1, 1, 1],
+            "grid": {"reservoir_matrix": "a.npy", "depths": "b.npy"},
+        });
+        assert!(service.simulate(&on_other_grid).is_err());
+        assert!(matches!(
+            service.simulate(&json!({"source": [9, 1, 1]})),
+            Err(SimulationError::InvalidSource(_))
+        ));
+    }
+
+    #[test]
+    fn test_service_reads_grids_under_its_root() {
+        let root = std::env::temp_dir().join(format!("co2_service_grids_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("model")).unwrap();
+        let mut reservoir = Array3::from_elem((2, 2, 3), VELOCITY_RESERVOIR);
+        reservoir.slice_mut(s![.., .., 0]).fill(VELOCITY_CAPROCK);
+        ndarray_npy::write_npy(root.join("model/reservoir.npy"), &reservoir).unwrap();
+        ndarray_npy::write_npy(
+            root.join("model/depths.npy"),
+            &Array1::from(vec![0.0, 1.0, 2.0]),
+        )
+        .unwrap();
+        let service = SimulationService::new(
+            Array3::from_elem((1, 1, 1), VELOCITY_RESERVOIR),
+            Array1::from(vec![0.0]),
+            Array2::zeros((1, 1)),
+        )
+        .with_grid_files(Arc::new(InputCache::in_memory()), &root);
+        let request = |reservoir_matrix: &str| {
+            service.simulate(&json!({
+                "source": [1, 1, 1],
+                "grid": {"reservoir_matrix": reservoir_matrix, "depths": "model/depths.npy"},
+            }))
+        };
+        let result = request("model/reservoir.npy");
+        assert_eq!(result.unwrap()["shape"], json!([2, 2, 3]));
+        // Paths that could leave the root are rejected before any file is read
+        let outside = root.join("model/reservoir.npy");
+        for path in [
+            "../model/reservoir.npy",
+            "model/../../etc/passwd",
+            outside.to_str().unwrap(),
+        ] {
+            let err = request(path).unwrap_err();
+            assert!(err.to_string().contains("relative to the grid directory"));
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+use numpy::ndarray::{Array3, ArrayView3};
+use serde::{Deserialize, Serialize};
+
+use crate::cell_state::CellState;
+use crate::error::SimulationError;
+
+/// Diffusion of the velocity model across the plume edges. The fill is binary, so the edges of the plume step
+/// from cell to cell, and these stair steps give artificial diffractions in synthetic seismic. A few explicit
+/// diffusion steps soften them. Only reservoir and CO2 cells are smoothed, so the caprock keeps sharp edges.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Smoothing {
+    /// Number of diffusion steps. Every step spreads the edges by about one cell.
+    pub iterations: usize,
+    /// Fraction of the difference to the mean of the neighbours that a cell takes on per step, in (0, 1]
+    pub strength: f64,
+}
+
+impl Default for Smoothing {
+    fn default() -> Self {
+        Smoothing {
+            iterations: 2,
+            strength: 0.5,
+        }
+    }
+}
+
+impl Smoothing {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        if !(self.strength > 0.0 && self.strength <= 1.0) {
+            return Err(SimulationError::InvalidValue {
+                argument: "smoothing".to_string(),
+                message: format!("the strength must be in (0, 1], got {}", self.strength),
+            });
+        }
+        Ok(())
+    }
+
+    /// Smooth the velocities of the reservoir and CO2 cells. Other cells neither change nor take part.
+    pub fn smooth(&self, velocities: &mut Array3<f64>, cell_states: &ArrayView3<CellState>) {
+        let (nx, ny, nz) = velocities.dim();
+        let is_fluid = |(x, y, z): (usize, usize, usize)| {
+            matches!(
+                cell_states[[x, y, z]],
+                CellState::Reservoir | CellState::Co2
+            )
+        };
+        for _ in 0..self.iterations {
+            let previous = velocities.clone();
+            for ((x, y, z), velocity) in velocities.indexed_iter_mut() {
+                if !is_fluid((x, y, z)) {
+                    continue;
+                }
+                let neighbors = [
+                    (x > 0).then(|| (x - 1, y, z)),
+                    (x + 1 < nx).then_some((x + 1, y, z)),
+                    (y > 0).then(|| (x, y - 1, z)),
+                    (y + 1 < ny).then_some((x, y + 1, z)),
+                    (z > 0).then(|| (x, y, z - 1)),
+                    (z + 1 < nz).then_some((x, y, z + 1)),
+                ];
+                let (sum, count) = neighbors
+                    .into_iter()
+                    .flatten()
+                    .filter(|&neighbor| is_fluid(neighbor))
+                    .fold((0.0, 0), |(sum, count), (nx, ny, nz)| {
+                        (sum + previous[[nx, ny, nz]], count + 1)
+                    });
+                if count > 0 {
+                    *velocity += self.strength * (sum / count as f64 - *velocity);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{VELOCITY_CO2, VELOCITY_RESERVOIR};
+
+    #[test]
+    fn test_smoothing_softens_the_plume_edge() {
+        let mut states = Array3::from_elem((4, 1, 2), CellState::Reservoir);
+        states[[0, 0, 0]] = CellState::Co2;
+        states[[1, 0, 0]] = CellState::Co2;
+        states[[3, 0, 1]] = CellState::Caprock;
+        let mut velocities = states.mapv(CellState::to_velocity);
+
+        Smoothing {
+            iterations: 1,
+            strength: 1.0,
+        }
+        .smooth(&mut velocities, &states.view());
+        // The cells at the edge move towards each other, while cells away from it keep their velocity
+        assert!(velocities[[1, 0, 0]] > VELOCITY_CO2 && velocities[[1, 0, 0]] < VELOCITY_RESERVOIR);
+        assert!(velocities[[2, 0, 0]] < VELOCITY_RESERVOIR);
+        assert_eq!(velocities[[3, 0, 0]], VELOCITY_RESERVOIR);
+        assert_eq!(velocities[[3, 0, 1]], CellState::Caprock.to_velocity());
+
+        assert!(Smoothing {
+            iterations: 1,
+            strength: 0.0
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimulationError;
+
+/// Decides when the simulation moves on to the next snapshot
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum SnapshotPolicy {
+    /// Spread `total_snapshots` snapshots evenly over the reservoir cells
+    #[default]
+    Uniform,
+    /// A new snapshot every given number of filled cells
+    EveryCells(usize),
+    /// A new snapshot each time the injected volume passes a milestone.
+    /// The injected volume is the number of filled cells times the cell volume.
+    InjectedVolume {
+        cell_volume: f64,
+        milestones: Vec<f64>,
+    },
+    /// A new snapshot every time the caprock breaks
+    OnBreach,
+    /// A new snapshot when the number of filled cells reaches each of the given counts
+    FillCounts(Vec<usize>),
+}
+
+impl SnapshotPolicy {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        let invalid = |message: &str| {
+            Err(SimulationError::InvalidValue {
+                argument: "snapshot_policy".to_string(),
+                message: message.to_string(),
+            })
+        };
+
+        match self {
+            SnapshotPolicy::EveryCells(0) => invalid("the cell interval must be at least 1"),
+            SnapshotPolicy::InjectedVolume {
+                cell_volume,
+                milestones,
+            } => {
+                if !(cell_volume.is_finite() && *cell_volume > 0.0) {
+                    return invalid("cell_volume must be positive");
+                }
+                if milestones.iter().any(|m| !(m.is_finite() && *m > 0.0))
+                    || milestones.windows(2).any(|w| w[0] >= w[1])
+                {
+                    return invalid("volume milestones must be positive and strictly increasing");
+                }
+                Ok(())
+            }
+            SnapshotPolicy::FillCounts(counts) => {
+                if counts.first() == Some(&0) || counts.windows(2).any(|w| w[0] >= w[1]) {
+                    return invalid("fill counts must be positive and strictly increasing");
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Number of cells to fill during the given snapshot before moving on to the next.
+    /// `uniform_interval` is the interval used by the uniform policy.
+    pub fn cells_in_snapshot(&self, snapshot_index: usize, uniform_interval: usize) -> usize {
+        match self {
+            SnapshotPolicy::Uniform => uniform_interval,
+            SnapshotPolicy::EveryCells(n_cells) => *n_cells,
+            SnapshotPolicy::InjectedVolume {
+                cell_volume,
+                milestones,
+            } => {
+                let fill_count = |i: usize| (milestones[i] / cell_volume).ceil() as usize;
+                interval_between_counts(milestones.len(), snapshot_index, fill_count)
+            }
+            SnapshotPolicy::OnBreach => usize::MAX,
+            SnapshotPolicy::FillCounts(counts) => {
+                interval_between_counts(counts.len(), snapshot_index, |i| counts[i])
+            }
+        }
+    }
+
+    /// Upper bound on the number of snapshots when at most `fillable_cells` cells can be filled.
+    /// Every snapshot contains at least one filled cell, except those started by a breach.
+    pub fn max_snapshots(&self, fillable_cells: usize, uniform_interval: usize) -> usize {
+        let n_started = match self {
+            SnapshotPolicy::Uniform => fillable_cells / uniform_interval.max(1),
+            SnapshotPolicy::EveryCells(n_cells) => fillable_cells / (*n_cells).max(1),
+            SnapshotPolicy::InjectedVolume { milestones, .. } => milestones.len(),
+            // Each breach converts one caprock cell, so there are fewer breaches than fillable cells
+            SnapshotPolicy::OnBreach => fillable_cells,
+            SnapshotPolicy::FillCounts(counts) => counts.len(),
+        };
+        n_started.saturating_add(1)
+    }
+
+    /// Whether a breach starts a new snapshot
+    pub fn snapshot_on_breach(&self) -> bool {
+        matches!(self, SnapshotPolicy::OnBreach)
+    }
+}
+
+/// Helper function for the number of cells between two consecutive fill counts.
+/// After the last count every remaining cell goes into the final snapshot.
+fn interval_between_counts(
+    n_counts: usize,
+    snapshot_index: usize,
+    fill_count: impl Fn(usize) -> usize,
+) -> usize {
+    if snapshot_index >= n_counts {
+        return usize::MAX;
+    }
+    let previous = if snapshot_index == 0 {
+        0
+    } else {
+        fill_count(snapshot_index - 1)
+    };
+    // Milestones closer than one cell apart still get a snapshot of one cell
+    fill_count(snapshot_index).saturating_sub(previous).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cells_in_snapshot() {
+        let counts = SnapshotPolicy::FillCounts(vec![5, 12]);
+        assert_eq!(counts.cells_in_snapshot(0, 3), 5);
+        assert_eq!(counts.cells_in_snapshot(1, 3), 7);
+        assert_eq!(counts.cells_in_snapshot(2, 3), usize::MAX);
+
+        let volume = SnapshotPolicy::InjectedVolume {
+            cell_volume: 2.0,
+            milestones: vec![9.0, 20.0],
+        };
+        assert_eq!(volume.cells_in_snapshot(0, 3), 5);
+        assert_eq!(volume.cells_in_snapshot(1, 3), 5);
+
+        assert_eq!(SnapshotPolicy::Uniform.cells_in_snapshot(7, 3), 3);
+    }
+
+    #[test]
+    fn test_max_snapshots() {
+        assert_eq!(SnapshotPolicy::Uniform.max_snapshots(100, 10), 11);
+        assert_eq!(SnapshotPolicy::EveryCells(1).max_snapshots(100, 10), 101);
+        assert_eq!(
+            SnapshotPolicy::FillCounts(vec![5, 12]).max_snapshots(100, 10),
+            3
+        );
+        assert_eq!(
+            SnapshotPolicy::EveryCells(1).max_snapshots(usize::MAX, 1),
+            usize::MAX
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(SnapshotPolicy::FillCounts(vec![3, 3]).validate().is_err());
+        assert!(SnapshotPolicy::FillCounts(vec![0, 3]).validate().is_err());
+        assert!(SnapshotPolicy::EveryCells(0).validate().is_err());
+        assert!(SnapshotPolicy::InjectedVolume {
+            cell_volume: 0.0,
+            milestones: vec![1.0]
+        }
+        .validate()
+        .is_err());
+        assert!(SnapshotPolicy::FillCounts(vec![1, 3]).validate().is_ok());
+    }
+}
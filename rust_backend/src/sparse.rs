@@ -0,0 +1,150 @@
+use numpy::ndarray::{Array3, ArrayView3};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Side length of the cubic blocks in a SparseGrid
+pub const BLOCK_SIZE: usize = 8;
+const BLOCK_CELLS: usize = BLOCK_SIZE * BLOCK_SIZE * BLOCK_SIZE;
+
+/// A 3D grid stored as hashed blocks of BLOCK_SIZE^3 cells.
+/// Only blocks containing a cell different from the fill value are allocated,
+/// so a plume occupying a small part of a large grid takes little memory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SparseGrid<T: Copy + PartialEq> {
+    dims: (usize, usize, usize),
+    fill: T,
+    blocks: HashMap<(usize, usize, usize), Box<[T]>>,
+}
+
+/// Helper function to split a cell index into its block key and the offset within the block
+#[inline]
+fn block_location((x, y, z): (usize, usize, usize)) -> ((usize, usize, usize), usize) {
+    let key = (x / BLOCK_SIZE, y / BLOCK_SIZE, z / BLOCK_SIZE);
+    let offset = ((x % BLOCK_SIZE) * BLOCK_SIZE + y % BLOCK_SIZE) * BLOCK_SIZE + z % BLOCK_SIZE;
+    (key, offset)
+}
+
+impl<T: Copy + PartialEq> SparseGrid<T> {
+    /// An empty grid where every cell has the fill value
+    pub fn new(dims: (usize, usize, usize), fill: T) -> Self {
+        SparseGrid {
+            dims,
+            fill,
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// Build a sparse grid from a dense array. Blocks containing only the fill value are not stored.
+    pub fn from_dense(array: &ArrayView3<T>, fill: T) -> Self {
+        let mut grid = SparseGrid::new(array.dim(), fill);
+        for (idx, &value) in array.indexed_iter() {
+            grid.set(idx, value);
+        }
+        grid
+    }
+
+    pub fn dim(&self) -> (usize, usize, usize) {
+        self.dims
+    }
+
+    pub fn fill_value(&self) -> T {
+        self.fill
+    }
+
+    pub fn get(&self, idx: (usize, usize, usize)) -> T {
+        let (key, offset) = block_location(idx);
+        match self.blocks.get(&key) {
+            Some(block) => block[offset],
+            None => self.fill,
+        }
+    }
+
+    /// Set the value of a cell. Setting the fill value in an unallocated block does not allocate it.
+    pub fn set(&mut self, idx: (usize, usize, usize), value: T) {
+        let (x, y, z) = idx;
+        if x >= self.dims.0 || y >= self.dims.1 || z >= self.dims.2 {
+            panic!(
+                "Index {:?} out of bounds for grid of shape {:?}",
+                idx, self.dims
+            );
+        }
+
+        let (key, offset) = block_location(idx);
+        match self.blocks.get_mut(&key) {
+            Some(block) => block[offset] = value,
+            None => {
+                if value != self.fill {
+                    let mut block = vec![self.fill; BLOCK_CELLS].into_boxed_slice();
+                    block[offset] = value;
+                    self.blocks.insert(key, block);
+                }
+            }
+        }
+    }
+
+    /// Iterate over the cells that differ from the fill value, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = ((usize, usize, usize), T)> + '_ {
+        self.blocks.iter().flat_map(move |(&(bx, by, bz), block)| {
+            block
+                .iter()
+                .enumerate()
+                .filter(move |(_, &value)| value != self.fill)
+                .map(move |(offset, &value)| {
+                    let x = bx * BLOCK_SIZE + offset / (BLOCK_SIZE * BLOCK_SIZE);
+                    let y = by * BLOCK_SIZE + (offset / BLOCK_SIZE) % BLOCK_SIZE;
+                    let z = bz * BLOCK_SIZE + offset % BLOCK_SIZE;
+                    ((x, y, z), value)
+                })
+        })
+    }
+
+    /// Convert to a dense array
+    pub fn to_dense(&self) -> Array3<T> {
+        let mut array = Array3::from_elem(self.dims, self.fill);
+        for (idx, value) in self.iter() {
+            array[idx] = value;
+        }
+        array
+    }
+
+    /// Number of allocated blocks
+    pub fn n_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Approximate memory used by the allocated blocks, in bytes
+    pub fn memory_bytes(&self) -> usize {
+        self.blocks.len() * BLOCK_CELLS * std::mem::size_of::<T>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_grid_round_trip() {
+        let mut dense = Array3::<i32>::from_elem((20, 3, 17), -1);
+        dense[[0, 0, 0]] = 4;
+        dense[[19, 2, 16]] = 0;
+        dense[[9, 1, 8]] = 2;
+
+        let grid = SparseGrid::from_dense(&dense.view(), -1);
+        assert_eq!(grid.n_blocks(), 3);
+        assert_eq!(grid.get((9, 1, 8)), 2);
+        assert_eq!(grid.get((10, 1, 8)), -1);
+        assert_eq!(grid.iter().count(), 3);
+        assert_eq!(grid.to_dense(), dense);
+    }
+
+    #[test]
+    fn test_setting_fill_value_does_not_allocate() {
+        let mut grid = SparseGrid::new((100, 100, 100), false);
+        grid.set((50, 50, 50), false);
+        assert_eq!(grid.n_blocks(), 0);
+
+        grid.set((50, 50, 50), true);
+        assert_eq!(grid.n_blocks(), 1);
+        assert_eq!(grid.memory_bytes(), BLOCK_CELLS);
+    }
+}
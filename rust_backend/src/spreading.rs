@@ -0,0 +1,156 @@
+use numpy::ndarray::Array3;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimulationError;
+
+/// Stochastic cellular-automaton rules for the lateral spreading. Instead of always spreading to every empty
+/// lateral neighbour, a filled cell only spreads in a direction with some probability. Failed attempts are
+/// retried in rounds whenever the front runs out of cells, so the plume still fills its traps, but the order
+/// and the shape of the front vary with the seed. Running the same model with different seeds gives an
+/// ensemble of plausible plume shapes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StochasticSpreading {
+    /// Probability of spreading along +x, -x, +y and -y per attempt. Diagonal and longer steps take the
+    /// geometric mean of the probabilities of their x and y components, weighted by the step length, so a
+    /// zero probability never spreads that way, not even diagonally.
+    pub probabilities: [f64; 4],
+    /// Permeability of every cell, if given. The probability of spreading into a cell is scaled by its
+    /// permeability over the highest permeability, so the most permeable cells keep the given probabilities.
+    pub permeability: Option<Array3<f64>>,
+    /// Seed for the random number generator. The same seed gives the same plume.
+    pub seed: u64,
+}
+
+impl Default for StochasticSpreading {
+    fn default() -> Self {
+        StochasticSpreading {
+            probabilities: [0.5; 4],
+            permeability: None,
+            seed: 0,
+        }
+    }
+}
+
+impl StochasticSpreading {
+    pub fn validate(&self, dims: (usize, usize, usize)) -> Result<(), SimulationError> {
+        let invalid = |message: String| {
+            Err(SimulationError::InvalidValue {
+                argument: "stochastic_spreading".to_string(),
+                message,
+            })
+        };
+        if let Some(&p) = self
+            .probabilities
+            .iter()
+            .find(|p| !(0.0..=1.0).contains(*p))
+        {
+            return invalid(format!(
+                "the probabilities must be between 0 and 1, got {}",
+                p
+            ));
+        }
+        if let Some(permeability) = &self.permeability {
+            if permeability.dim() != dims {
+                return Err(SimulationError::ShapeMismatch {
+                    argument: "permeability".to_string(),
+                    expected: format!("{:?} to match reservoir_matrix", dims),
+                    actual: format!("{:?}", permeability.dim()),
+                });
+            }
+            if permeability.iter().any(|k| !(k.is_finite() && *k >= 0.0)) {
+                return invalid("the permeability must be finite and non-negative".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// The probability of spreading along the lateral direction (dx, dy), before the permeability scaling
+    pub fn direction_probability(&self, (dx, dy): (i32, i32)) -> f64 {
+        let [px_plus, px_minus, py_plus, py_minus] = self.probabilities;
+        let px = if dx > 0 { px_plus } else { px_minus };
+        let py = if dy > 0 { py_plus } else { py_minus };
+        let (wx, wy) = (dx.unsigned_abs() as f64, dy.unsigned_abs() as f64);
+        // libm instead of powf, which calls the math library of the platform and may round differently there
+        libm::pow(px, wx / (wx + wy)) * libm::pow(py, wy / (wx + wy))
+    }
+}
+
+// A spreading attempt into a cell along a direction, by the front of a well
+type Attempt = ((usize, usize, usize), (i32, i32), i16);
+
+/// The random state of the stochastic spreading during a simulation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SpreadingState {
+    rules: StochasticSpreading,
+    // The highest permeability, so it is not searched for every attempt
+    max_permeability: f64,
+    rng: ChaCha8Rng,
+    // Failed attempts, retried when the front is empty
+    deferred: Vec<Attempt>,
+}
+
+impl SpreadingState {
+    pub(crate) fn new(rules: &StochasticSpreading) -> Self {
+        SpreadingState {
+            rules: rules.clone(),
+            max_permeability: rules
+                .permeability
+                .as_ref()
+                .map_or(0.0, |k| k.fold(0.0, |max: f64, &k| max.max(k))),
+            rng: ChaCha8Rng::seed_from_u64(rules.seed),
+            deferred: Vec::new(),
+        }
+    }
+
+    fn probability(&self, direction: (i32, i32), cell: (usize, usize, usize)) -> f64 {
+        let probability = self.rules.direction_probability(direction);
+        match &self.rules.permeability {
+            Some(_) if self.max_permeability == 0.0 => 0.0,
+            Some(permeability) => probability * permeability[cell] / self.max_permeability,
+            None => probability,
+        }
+    }
+
+    /// Roll for spreading into the cell along the direction. A failed attempt is kept for the next round,
+    /// unless the probability is zero. Returns whether the cell was reached.
+    pub(crate) fn attempt(
+        &mut self,
+        cell: (usize, usize, usize),
+        direction: (i32, i32),
+        well: i16,
+    ) -> bool {
+        let probability = self.probability(direction, cell);
+        if probability > 0.0 && self.rng.random::<f64>() < probability {
+            return true;
+        }
+        if probability > 0.0 {
+            self.deferred.push((cell, direction, well));
+        }
+        false
+    }
+
+    /// Retry the failed attempts once, and return the cells that were reached with their wells.
+    /// `is_empty` drops attempts at cells that were filled in the meantime.
+    pub(crate) fn retry(
+        &mut self,
+        is_empty: impl Fn((usize, usize, usize)) -> bool,
+    ) -> Vec<((usize, usize, usize), i16)> {
+        let deferred = std::mem::take(&mut self.deferred);
+        let mut reached = Vec::new();
+        for (cell, direction, well) in deferred {
+            if !is_empty(cell) {
+                continue;
+            }
+            if self.attempt(cell, direction, well) {
+                reached.push((cell, well));
+            }
+        }
+        reached
+    }
+
+    pub(crate) fn has_deferred(&self) -> bool {
+        !self.deferred.is_empty()
+    }
+}
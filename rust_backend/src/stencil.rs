@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimulationError;
+
+/// A user-defined set of (dx, dy, dz) offsets the front moves along, replacing the built-in rules. Offsets with
+/// a negative dz move the CO2 up and are tried first. The others, lateral or downwards, are only used when none
+/// of the upward offsets reached an empty cell, like the lateral spreading of the built-in rules.
+///
+/// Every offset has a weight that is added to the depth of the cells it reaches when they join the front, so
+/// cells reached along a heavier offset are filled after equally deep cells reached along lighter ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stencil {
+    offsets: Vec<((i32, i32, i32), f64)>,
+}
+
+impl Stencil {
+    /// A stencil of the given offsets, all with zero weight
+    pub fn new(offsets: Vec<(i32, i32, i32)>) -> Self {
+        Stencil {
+            offsets: offsets.into_iter().map(|offset| (offset, 0.0)).collect(),
+        }
+    }
+
+    /// A stencil of (offset, weight) pairs
+    pub fn weighted(offsets: Vec<((i32, i32, i32), f64)>) -> Self {
+        Stencil { offsets }
+    }
+
+    /// The stencil of the built-in rules for the given lateral directions: straight up, up and along every
+    /// direction, and along every direction
+    pub fn from_directions(directions: &[(i32, i32)]) -> Self {
+        let mut offsets = vec![(0, 0, -1)];
+        offsets.extend(directions.iter().map(|&(dx, dy)| (dx, dy, -1)));
+        offsets.extend(directions.iter().map(|&(dx, dy)| (dx, dy, 0)));
+        Stencil::new(offsets)
+    }
+
+    pub fn offsets(&self) -> &[((i32, i32, i32), f64)] {
+        &self.offsets
+    }
+
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        let invalid = |message: String| {
+            Err(SimulationError::InvalidValue {
+                argument: "stencil".to_string(),
+                message,
+            })
+        };
+        if self.offsets.is_empty() {
+            return invalid("needs at least one offset".to_string());
+        }
+        for &(offset, weight) in &self.offsets {
+            if offset == (0, 0, 0) {
+                return invalid("the offset (0, 0, 0) does not move the front".to_string());
+            }
+            if !weight.is_finite() {
+                return invalid(format!("the weight of {:?} must be finite", offset));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+
+use crate::constants::VELOCITY_CO2;
+use crate::error::SimulationError;
+
+/// A non-condensable impurity in the injected stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Impurity {
+    N2,
+    CH4,
+    Ar,
+    O2,
+    H2,
+}
+
+impl Impurity {
+    /// Parse an impurity from its chemical formula, e.g. "N2"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "N2" => Some(Impurity::N2),
+            "CH4" => Some(Impurity::CH4),
+            "AR" => Some(Impurity::Ar),
+            "O2" => Some(Impurity::O2),
+            "H2" => Some(Impurity::H2),
+            _ => None,
+        }
+    }
+
+    /// Density and velocity of the pure impurity relative to pure CO2, at typical storage conditions
+    /// (around 10 MPa and 40 °C). Rough screening values.
+    fn relative_properties(self) -> (f64, f64) {
+        match self {
+            Impurity::N2 => (0.17, 0.90),
+            Impurity::CH4 => (0.11, 0.88),
+            Impurity::Ar => (0.25, 0.92),
+            Impurity::O2 => (0.20, 0.92),
+            Impurity::H2 => (0.012, 0.80),
+        }
+    }
+}
+
+/// The composition of the injected stream. Captured CO2 is rarely pure, and the light impurities lower
+/// the density of the stream, which adds buoyancy and lowers the mass per filled cell, and change its velocity.
+/// The properties are mixed linearly by mole fraction.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Co2Stream {
+    /// Mole fraction of every impurity. The rest is CO2.
+    pub impurities: Vec<(Impurity, f64)>,
+}
+
+impl Co2Stream {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        let invalid = |message: String| {
+            Err(SimulationError::InvalidValue {
+                argument: "co2_stream".to_string(),
+                message,
+            })
+        };
+        for &(impurity, fraction) in &self.impurities {
+            if !(0.0..=1.0).contains(&fraction) {
+                return invalid(format!(
+                    "the fraction of {:?} must be between 0 and 1, got {}",
+                    impurity, fraction
+                ));
+            }
+        }
+        let total = self.impurity_fraction();
+        if total >= 1.0 {
+            return invalid(format!(
+                "the impurities must add up to less than 1, got {}",
+                total
+            ));
+        }
+        Ok(())
+    }
+
+    /// The total mole fraction of impurities
+    pub fn impurity_fraction(&self) -> f64 {
+        self.impurities.iter().map(|&(_, fraction)| fraction).sum()
+    }
+
+    /// Density of the stream over the density of pure CO2
+    pub fn density_factor(&self) -> f64 {
+        self.mix(|(density, _)| density)
+    }
+
+    /// The velocity of CO2-filled cells in the velocity model
+    pub fn co2_velocity(&self) -> f64 {
+        VELOCITY_CO2 * self.mix(|(_, velocity)| velocity)
+    }
+
+    /// The brine–stream density difference, given the difference for pure CO2 and the density of pure CO2
+    pub fn density_difference(&self, pure_difference: f64, co2_density: f64) -> f64 {
+        pure_difference + (1.0 - self.density_factor()) * co2_density
+    }
+
+    fn mix(&self, property: impl Fn((f64, f64)) -> f64) -> f64 {
+        1.0 - self
+            .impurities
+            .iter()
+            .map(|&(impurity, fraction)| {
+                fraction * (1.0 - property(impurity.relative_properties()))
+            })
+            .sum::<f64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_impurities_lighten_the_stream() {
+        assert_eq!(Co2Stream::default().density_factor(), 1.0);
+        assert_eq!(Co2Stream::default().co2_velocity(), VELOCITY_CO2);
+
+        let stream = Co2Stream {
+            impurities: vec![(Impurity::N2, 0.05), (Impurity::CH4, 0.05)],
+        };
+        assert!((stream.density_factor() - 0.914).abs() < 1e-12);
+        assert!(stream.co2_velocity() < VELOCITY_CO2);
+        // 8.6 % lighter CO2 at 600 kg/m³ adds about 52 kg/m³ of buoyancy
+        assert!((stream.density_difference(400.0, 600.0) - 451.6).abs() < 1e-9);
+
+        assert_eq!(Impurity::from_name("ch4"), Some(Impurity::CH4));
+        assert!(Co2Stream {
+            impurities: vec![(Impurity::N2, 0.6), (Impurity::Ar, 0.5)]
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use numpy::ndarray::Array3;
+use serde::{Deserialize, Serialize};
+
+use crate::cell_state::CellState;
+use crate::error::SimulationError;
+
+/// The cold zone around an injector. The CO2 arrives colder than the formation, so near the well it is denser
+/// and the CO2-filled rock has a different velocity than further out. The zone is a cylinder around the well column.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ThermalZone {
+    /// Lateral radius of the zone around each well column, in cells
+    pub radius: f64,
+    /// Density of the cold CO2 over the density at formation temperature. Scales the mass of filled cells
+    /// with mass accounting.
+    pub density_factor: f64,
+    /// Velocity of CO2-filled cells inside the zone, used in the velocity model instead of the CO2 velocity
+    pub co2_velocity: f64,
+}
+
+impl ThermalZone {
+    pub fn validate(&self) -> Result<(), SimulationError> {
+        let invalid = |message: &str| {
+            Err(SimulationError::InvalidValue {
+                argument: "thermal_zone".to_string(),
+                message: message.to_string(),
+            })
+        };
+        if !(self.radius.is_finite() && self.radius >= 0.0) {
+            return invalid("the radius must be non-negative");
+        }
+        if !(self.density_factor.is_finite() && self.density_factor > 0.0) {
+            return invalid("the density factor must be positive");
+        }
+        if !(self.co2_velocity.is_finite() && self.co2_velocity > 0.0) {
+            return invalid("the CO2 velocity must be positive");
+        }
+        Ok(())
+    }
+
+    /// Whether the column (x, y) is within the radius of any of the wells
+    pub fn contains(&self, (x, y): (usize, usize), sources: &[(usize, usize, usize)]) -> bool {
+        sources.iter().any(|&(xs, ys, _)| {
+            let dx = x as f64 - xs as f64;
+            let dy = y as f64 - ys as f64;
+            dx * dx + dy * dy <= self.radius * self.radius
+        })
+    }
+
+    /// Replace the velocity of the CO2 cells inside the zone
+    pub fn apply_to_velocities(
+        &self,
+        velocities: &mut Array3<f64>,
+        cell_states: &Array3<CellState>,
+        sources: &[(usize, usize, usize)],
+    ) {
+        for ((x, y, z), velocity) in velocities.indexed_iter_mut() {
+            if cell_states[[x, y, z]] == CellState::Co2 && self.contains((x, y), sources) {
+                *velocity = self.co2_velocity;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::VELOCITY_CO2;
+
+    #[test]
+    fn test_thermal_zone_changes_velocities_near_the_well() {
+        let zone = ThermalZone {
+            radius: 1.0,
+            density_factor: 1.2,
+            co2_velocity: 450.0,
+        };
+        let cells = Array3::from_elem((4, 1, 1), CellState::Co2);
+        let mut velocities = Array3::from_elem((4, 1, 1), VELOCITY_CO2);
+        zone.apply_to_velocities(&mut velocities, &cells, &[(1, 0, 0)]);
+        assert_eq!(
+            velocities.iter().copied().collect::<Vec<_>>(),
+            vec![450.0, 450.0, 450.0, VELOCITY_CO2]
+        );
+
+        assert!(!zone.contains((2, 1), &[(1, 0, 0)]));
+        assert!(ThermalZone {
+            radius: -1.0,
+            ..zone
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use chrono::{Days, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::error::SimulationError;
+
+/// A change of the injection rate, in filled cells per day, from the given date onwards
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateChange {
+    pub date: NaiveDate,
+    pub cells_per_day: f64,
+}
+
+/// Maps fill counts and snapshots to calendar dates using the injection start date and a rate schedule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimeAxis {
+    // The first entry is the injection start
+    schedule: Vec<RateChange>,
+}
+
+impl TimeAxis {
+    /// Inject at a constant rate from the start date
+    pub fn constant_rate(
+        start_date: NaiveDate,
+        cells_per_day: f64,
+    ) -> Result<Self, SimulationError> {
+        Self::with_schedule(vec![RateChange {
+            date: start_date,
+            cells_per_day,
+        }])
+    }
+
+    /// Inject following a schedule. The first rate change is the injection start,
+    /// and the dates must be strictly increasing. A rate of zero pauses the injection.
+    pub fn with_schedule(schedule: Vec<RateChange>) -> Result<Self, SimulationError> {
+        let invalid = |message: &str| {
+            Err(SimulationError::InvalidValue {
+                argument: "time_axis".to_string(),
+                message: message.to_string(),
+            })
+        };
+
+        if schedule.is_empty() {
+            return invalid("the rate schedule must contain the injection start");
+        }
+        if schedule
+            .iter()
+            .any(|change| !(change.cells_per_day.is_finite() && change.cells_per_day >= 0.0))
+        {
+            return invalid("injection rates must be finite and non-negative");
+        }
+        if schedule.windows(2).any(|w| w[0].date >= w[1].date) {
+            return invalid("the dates in the rate schedule must be strictly increasing");
+        }
+        Ok(TimeAxis { schedule })
+    }
+
+    pub fn start_date(&self) -> NaiveDate {
+        self.schedule[0].date
+    }
+
+    pub fn schedule(&self) -> &[RateChange] {
+        &self.schedule
+    }
+
+    /// The schedule with the given rate changes from their first date on, replacing the later changes
+    pub fn with_changes_from(&self, rate_changes: &[RateChange]) -> Result<Self, SimulationError> {
+        let Some(first) = rate_changes.first() else {
+            return Ok(self.clone());
+        };
+        let schedule = self
+            .schedule
+            .iter()
+            .filter(|change| change.date < first.date)
+            .chain(rate_changes)
+            .cloned()
+            .collect();
+        Self::with_schedule(schedule)
+    }
+
+    /// Days after the injection start at which the given number of cells has been filled.
+    /// None if the schedule ends with a zero rate before that.
+    pub fn days_to_fill(&self, cells: usize) -> Option<f64> {
+        let mut remaining = cells as f64;
+        let mut days = 0.0;
+
+        for (i, change) in self.schedule.iter().enumerate() {
+            let period_days = self
+                .schedule
+                .get(i + 1)
+                .map(|next| (next.date - change.date).num_days() as f64);
+
+            let capacity = period_days.map(|d| d * change.cells_per_day);
+            match capacity {
+                Some(capacity) if capacity < remaining => {
+                    remaining -= capacity;
+                    days += period_days.unwrap();
+                }
+                _ => {
+                    if remaining == 0.0 {
+                        return Some(days);
+                    }
+                    if change.cells_per_day == 0.0 {
+                        return None;
+                    }
+                    return Some(days + remaining / change.cells_per_day);
+                }
+            }
+        }
+        None
+    }
+
+    /// The date on which the given number of cells has been filled
+    pub fn date_at_fill_count(&self, cells: usize) -> Option<NaiveDate> {
+        let days = self.days_to_fill(cells)?;
+        self.start_date()
+            .checked_add_days(Days::new(days.floor() as u64))
+    }
+
+    /// The date on which each snapshot is complete, given the number of cells filled during each snapshot
+    pub fn snapshot_dates(&self, snapshot_cell_counts: &[usize]) -> Vec<Option<NaiveDate>> {
+        let mut cells_filled = 0;
+        snapshot_cell_counts
+            .iter()
+            .map(|&count| {
+                cells_filled += count;
+                self.date_at_fill_count(cells_filled)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_constant_rate() {
+        let axis = TimeAxis::constant_rate(date(2030, 1, 1), 10.0).unwrap();
+        assert_eq!(axis.days_to_fill(0), Some(0.0));
+        assert_eq!(axis.days_to_fill(25), Some(2.5));
+        assert_eq!(axis.date_at_fill_count(25), Some(date(2030, 1, 3)));
+        assert_eq!(
+            axis.snapshot_dates(&[310, 300]),
+            vec![Some(date(2030, 2, 1)), Some(date(2030, 3, 3))]
+        );
+    }
+
+    #[test]
+    fn test_rate_schedule_with_pause() {
+        let axis = TimeAxis::with_schedule(vec![
+            RateChange {
+                date: date(2030, 1, 1),
+                cells_per_day: 2.0,
+            },
+            RateChange {
+                date: date(2030, 1, 11),
+                cells_per_day: 0.0,
+            },
+            RateChange {
+                date: date(2030, 2, 1),
+                cells_per_day: 5.0,
+            },
+        ])
+        .unwrap();
+        // 20 cells in the first ten days, then nothing until February
+        assert_eq!(axis.days_to_fill(20), Some(10.0));
+        assert_eq!(axis.date_at_fill_count(30), Some(date(2030, 2, 3)));
+
+        let stopped = TimeAxis::with_schedule(vec![
+            RateChange {
+                date: date(2030, 1, 1),
+                cells_per_day: 1.0,
+            },
+            RateChange {
+                date: date(2030, 1, 2),
+                cells_per_day: 0.0,
+            },
+        ])
+        .unwrap();
+        assert_eq!(stopped.days_to_fill(2), None);
+
+        // A new rate from mid-January replaces the pause
+        let resumed = axis
+            .with_changes_from(&[RateChange {
+                date: date(2030, 1, 6),
+                cells_per_day: 5.0,
+            }])
+            .unwrap();
+        assert_eq!(resumed.schedule().len(), 2);
+        assert_eq!(resumed.days_to_fill(35), Some(10.0));
+    }
+
+    #[test]
+    fn test_invalid_schedule() {
+        assert!(TimeAxis::with_schedule(vec![]).is_err());
+        assert!(TimeAxis::constant_rate(date(2030, 1, 1), -1.0).is_err());
+    }
+}
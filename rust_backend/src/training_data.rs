@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::builder::{FixedSizeListBuilder, Float32Builder};
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray};
+use arrow_schema::{Field, Schema};
+use numpy::ndarray::{Array3, ArrayView1, ArrayView2, ArrayView3, Axis};
+use parquet::arrow::ArrowWriter;
+
+use crate::config::SimulationConfig;
+use crate::fingerprint::simulation_fingerprint;
+use crate::injection_simulation::run_injection_simulation;
+use crate::metadata::RunMetadata;
+
+/// Names of the entries in the parameter vector, in order
+pub const PARAMETER_NAMES: [&str; 7] = [
+    "source_x",
+    "source_y",
+    "source_z",
+    "max_column_height",
+    "total_snapshots",
+    "anisotropy_x",
+    "anisotropy_y",
+];
+
+/// Names of the entries in the summary output vector, in order
+pub const SUMMARY_NAMES: [&str; 5] = [
+    "filled_cells",
+    "footprint_cells",
+    "plume_top_z",
+    "plume_bottom_z",
+    "max_column_thickness",
+];
+
+/// A single simulation to include in the training data
+#[derive(Debug, Clone)]
+pub struct TrainingRun {
+    pub source: (usize, usize, usize),
+    pub config: SimulationConfig,
+}
+
+/// One (parameter vector -> outputs) pair
+#[derive(Debug, Clone)]
+pub struct TrainingSample {
+    pub parameters: Vec<f64>,
+    pub summary: Vec<f64>,
+    /// Fraction of the cells in each coarse block that are filled with CO2
+    pub coarse_plume: Array3<f32>,
+    /// Fingerprint of the inputs of the run, see `simulation_fingerprint`
+    pub fingerprint: String,
+}
+
+impl TrainingRun {
+    /// The parameter vector of the run, ordered as PARAMETER_NAMES
+    pub fn parameter_vector(&self) -> Vec<f64> {
+        let (xi, yi, zi) = self.source;
+        vec![
+            xi as f64,
+            yi as f64,
+            zi as f64,
+            // An unbreakable caprock is an infinite column height
+            self.config
+                .max_column_height
+                .map_or(f64::INFINITY, |height| height as f64),
+            self.config.total_snapshots as f64,
+            self.config.anisotropy.0 as f64,
+            self.config.anisotropy.1 as f64,
+        ]
+    }
+}
+
+/// Summary outputs of a run, ordered as SUMMARY_NAMES. The z extent is -1 if nothing was filled.
+pub fn summary_outputs(snapshots: &ArrayView3<i32>) -> Vec<f64> {
+    let filled_cells = snapshots.iter().filter(|&&s| s != -1).count();
+
+    let column_thickness = snapshots.map_axis(Axis(2), |column| {
+        column.iter().filter(|&&s| s != -1).count()
+    });
+    let footprint_cells = column_thickness.iter().filter(|&&n| n > 0).count();
+    let max_column_thickness = column_thickness.iter().copied().max().unwrap_or(0);
+
+    let filled_z: Vec<usize> = snapshots
+        .indexed_iter()
+        .filter(|(_, &s)| s != -1)
+        .map(|((_, _, z), _)| z)
+        .collect();
+    let plume_top_z = filled_z.iter().min().map_or(-1.0, |&z| z as f64);
+    let plume_bottom_z = filled_z.iter().max().map_or(-1.0, |&z| z as f64);
+
+    vec![
+        filled_cells as f64,
+        footprint_cells as f64,
+        plume_top_z,
+        plume_bottom_z,
+        max_column_thickness as f64,
+    ]
+}
+
+/// Coarsen the plume into blocks of the given size. Each block holds the fraction of its cells that are filled.
+pub fn coarsen_plume(snapshots: &ArrayView3<i32>, factors: (usize, usize, usize)) -> Array3<f32> {
+    let (fx, fy, fz) = factors;
+    if fx == 0 || fy == 0 || fz == 0 {
+        panic!("Coarsening factors must be positive");
+    }
+
+    let (nx, ny, nz) = snapshots.dim();
+    let coarse_dims = (nx.div_ceil(fx), ny.div_ceil(fy), nz.div_ceil(fz));
+
+    let mut filled = Array3::<f32>::zeros(coarse_dims);
+    let mut counts = Array3::<f32>::zeros(coarse_dims);
+    for ((x, y, z), &s) in snapshots.indexed_iter() {
+        let block = [x / fx, y / fy, z / fz];
+        counts[block] += 1.0;
+        if s != -1 {
+            filled[block] += 1.0;
+        }
+    }
+    filled / counts
+}
+
+/// Run every simulation and collect the training samples
+pub fn build_training_samples(
+    reservoir_matrix: ArrayView3<f64>,
+    depths: ArrayView1<f64>,
+    bedrock_indices: ArrayView2<usize>,
+    runs: &[TrainingRun],
+    coarsening: (usize, usize, usize),
+) -> Vec<TrainingSample> {
+    runs.iter()
+        .map(|run| {
+            let snapshots = run_injection_simulation(
+                reservoir_matrix,
+                depths,
+                bedrock_indices,
+                run.source,
+                &run.config,
+            );
+            TrainingSample {
+                parameters: run.parameter_vector(),
+                summary: summary_outputs(&snapshots.view()),
+                coarse_plume: coarsen_plume(&snapshots.view(), coarsening),
+                fingerprint: simulation_fingerprint(
+                    &reservoir_matrix,
+                    &depths,
+                    &bedrock_indices,
+                    &[run.source],
+                    &run.config,
+                    None,
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Min, max, mean and standard deviation of a column, as a JSON object
+fn normalization_entry(values: &[f64]) -> serde_json::Value {
+    let n = values.len().max(1) as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let std = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+    serde_json::json!({
+        "min": values.iter().copied().fold(f64::INFINITY, f64::min),
+        "max": values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        "mean": mean,
+        "std": std,
+    })
+}
+
+/// Write the samples as a single Parquet file with one row per run.
+/// Parameters and summaries get one Float64 column each (prefixed `param_` and `summary_`),
+/// and the coarsened plume is stored flattened (C order) in the `plume` column.
+/// The `fingerprint` column traces every row back to the inputs of its run.
+/// The schema metadata holds the coarse grid shape and the normalization statistics of every scalar column.
+/// The run metadata is written next to the file, e.g. training.parquet.json.
+pub fn write_training_parquet(
+    path: &Path,
+    samples: &[TrainingSample],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let coarse_shape = samples
+        .first()
+        .map(|s| s.coarse_plume.dim())
+        .ok_or("Cannot write training data without samples")?;
+    if samples.iter().any(|s| s.coarse_plume.dim() != coarse_shape) {
+        return Err("All samples must have the same coarse plume shape".into());
+    }
+
+    let mut columns: Vec<(String, ArrayRef)> = Vec::new();
+    let mut normalization = serde_json::Map::new();
+
+    let scalar_columns = PARAMETER_NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (format!("param_{name}"), i, true))
+        .chain(
+            SUMMARY_NAMES
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (format!("summary_{name}"), i, false)),
+        );
+    for (column_name, i, is_parameter) in scalar_columns {
+        let values: Vec<f64> = samples
+            .iter()
+            .map(|s| {
+                if is_parameter {
+                    s.parameters[i]
+                } else {
+                    s.summary[i]
+                }
+            })
+            .collect();
+        normalization.insert(column_name.clone(), normalization_entry(&values));
+        columns.push((column_name, Arc::new(Float64Array::from(values))));
+    }
+
+    let block_len = coarse_shape.0 * coarse_shape.1 * coarse_shape.2;
+    let mut plume_builder = FixedSizeListBuilder::new(Float32Builder::new(), block_len as i32);
+    for sample in samples {
+        plume_builder
+            .values()
+            .append_slice(&sample.coarse_plume.iter().copied().collect::<Vec<f32>>());
+        plume_builder.append(true);
+    }
+    columns.push(("plume".to_string(), Arc::new(plume_builder.finish())));
+    let fingerprints: Vec<&str> = samples.iter().map(|s| s.fingerprint.as_str()).collect();
+    columns.push((
+        "fingerprint".to_string(),
+        Arc::new(StringArray::from(fingerprints)),
+    ));
+
+    let metadata = HashMap::from([
+        (
+            "coarse_shape".to_string(),
+            serde_json::json!([coarse_shape.0, coarse_shape.1, coarse_shape.2]).to_string(),
+        ),
+        (
+            "normalization".to_string(),
+            serde_json::Value::Object(normalization).to_string(),
+        ),
+    ]);
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|(name, array)| Field::new(name, array.data_type().clone(), true))
+        .collect();
+    let schema = Arc::new(Schema::new_with_metadata(fields, metadata));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        columns.into_iter().map(|(_, array)| array).collect(),
+    )?;
+
+    let file = File::create(path)?;
+    let mut writer = ArrowWriter::try_new(file, schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    let mut run_metadata = RunMetadata::default();
+    run_metadata
+        .add_statistic("samples", serde_json::json!(samples.len()))
+        .add_statistic(
+            "coarse_shape",
+            serde_json::json!([coarse_shape.0, coarse_shape.1, coarse_shape.2]),
+        );
+    run_metadata.write_sidecar(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::sidecar_path;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_coarsen_plume_and_summary() {
+        let mut snapshots = Array3::<i32>::from_elem((4, 4, 2), -1);
+        snapshots[[0, 0, 1]] = 0;
+        snapshots[[0, 0, 0]] = 1;
+        snapshots[[1, 1, 1]] = 2;
+
+        let coarse = coarsen_plume(&snapshots.view(), (2, 2, 2));
+        assert_eq!(coarse.dim(), (2, 2, 1));
+        assert_eq!(coarse[[0, 0, 0]], 3.0 / 8.0);
+        assert_eq!(coarse[[1, 1, 0]], 0.0);
+
+        let summary = summary_outputs(&snapshots.view());
+        assert_eq!(summary, vec![3.0, 2.0, 0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_write_training_parquet() {
+        let samples: Vec<TrainingSample> = (0..3)
+            .map(|i| TrainingSample {
+                parameters: vec![i as f64; PARAMETER_NAMES.len()],
+                summary: vec![2.0 * i as f64; SUMMARY_NAMES.len()],
+                coarse_plume: Array3::<f32>::from_elem((2, 1, 1), 0.5),
+                fingerprint: format!("run{}", i),
+            })
+            .collect();
+
+        let path = std::env::temp_dir().join("co2_training_data_test.parquet");
+        write_training_parquet(&path, &samples).unwrap();
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        let metadata = builder.schema().metadata().clone();
+        let batches: Vec<RecordBatch> = builder.build().unwrap().map(|b| b.unwrap()).collect();
+        let n_rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        let sidecar: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(sidecar_path(&path)).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(sidecar_path(&path)).unwrap();
+        assert_eq!(sidecar["statistics"]["samples"], 3);
+
+        assert_eq!(n_rows, 3);
+        assert_eq!(metadata["coarse_shape"], "[2,1,1]");
+        let normalization: serde_json::Value =
+            serde_json::from_str(&metadata["normalization"]).unwrap();
+        assert_eq!(normalization["summary_filled_cells"]["max"], 4.0);
+        let fingerprints = batches[0]
+            .column_by_name("fingerprint")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap()
+            .clone();
+        assert_eq!(fingerprints.value(2), "run2");
+    }
+}
@@ -0,0 +1,55 @@
+use numpy::ndarray::{Array1, ArrayView1, Axis};
+
+/// The CO2 held by each trapping mechanism at the end of every snapshot, in cells.
+/// This is the data behind the classic trapping-inventory plot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrappingInventory {
+    /// Free CO2 held below caprock
+    pub structural: Array1<usize>,
+    /// CO2 held by residual trapping. Always zero during injection, see `Hysteresis` for the post-injection period.
+    pub residual: Array1<usize>,
+    /// CO2 dissolved in brine. Always zero during injection, see `ConvectiveDissolution` for the post-injection period.
+    pub dissolved: Array1<usize>,
+    /// CO2 turned into minerals. Always zero during injection, see `MineralTrapping` for long-term projections.
+    pub mineralized: Array1<usize>,
+}
+
+impl TrappingInventory {
+    /// Build the inventory from the number of cells filled during each snapshot.
+    /// Filled cells stay filled, so the CO2 in the grid is the running total.
+    pub fn from_snapshot_cell_counts(counts: &ArrayView1<usize>) -> Self {
+        let mut structural = counts.to_owned();
+        structural.accumulate_axis_inplace(Axis(0), |&previous, current| *current += previous);
+        let n_snapshots = structural.len();
+        TrappingInventory {
+            structural,
+            residual: Array1::zeros(n_snapshots),
+            dissolved: Array1::zeros(n_snapshots),
+            mineralized: Array1::zeros(n_snapshots),
+        }
+    }
+
+    pub fn n_snapshots(&self) -> usize {
+        self.structural.len()
+    }
+
+    /// All CO2 in the grid at the end of every snapshot
+    pub fn total(&self) -> Array1<usize> {
+        &self.structural + &self.residual + &self.dissolved + &self.mineralized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inventory_is_cumulative() {
+        let counts = Array1::from(vec![3, 0, 2, 5]);
+        let inventory = TrappingInventory::from_snapshot_cell_counts(&counts.view());
+        assert_eq!(inventory.n_snapshots(), 4);
+        assert_eq!(inventory.structural.to_vec(), vec![3, 3, 5, 10]);
+        assert_eq!(inventory.total(), inventory.structural);
+        assert!(inventory.dissolved.iter().all(|&cells| cells == 0));
+    }
+}
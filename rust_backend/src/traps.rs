@@ -0,0 +1,406 @@
+use numpy::ndarray::{Array2, ArrayView1, ArrayView2, ArrayView3};
+use serde_json::{json, Value};
+
+use crate::cell_state::CellState;
+use crate::config::SimulationConfig;
+use crate::error::SimulationError;
+use crate::grid::{Grid, RegularGrid};
+use crate::injection_simulation::{initial_reservoir_state, lateral_directions};
+use crate::validation::validate_inputs;
+
+/// A structural trap under the caprock surface
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trap {
+    /// Depth of the shallowest column of the trap
+    pub top_depth: f64,
+    /// Depth at which the trap is full and the CO2 starts to spill
+    pub spill_depth: f64,
+    /// The column the CO2 spills over, or None for a sealed compartment that never spills
+    pub spill_point: Option<(usize, usize)>,
+    /// The columns of the trap, including those of its sub-traps
+    pub columns: Vec<(usize, usize)>,
+    /// Sum of the heights between the caprock and the spill depth over the columns. Multiply by the cell
+    /// area and porosity to get the pore volume.
+    pub volume: f64,
+    /// The trap the CO2 spills into when this one is full, or None if it spills out of the grid
+    pub spills_into: Option<usize>,
+    /// The traps that fill up and merge into this one
+    pub sub_traps: Vec<usize>,
+}
+
+/// The structural traps under the caprock surface and how they spill into each other, like `trapAnalysis`
+/// in MRST. Traps that meet at a saddle both spill into a larger trap holding them both, so the traps form a
+/// tree, and traps that spill out of a larger trap are leaves. Computed without simulating any injection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrapAnalysis {
+    /// Depth of the shallowest reservoir cell under the bedrock of every column, or NaN without reservoir
+    pub top_surface: Array2<f64>,
+    /// Traps ordered by spill depth, so sub-traps come before the traps they merge into
+    pub traps: Vec<Trap>,
+    /// The innermost trap of every column, or -1 for columns outside all traps
+    pub trap_map: Array2<i64>,
+}
+
+impl TrapAnalysis {
+    /// The traps whose CO2 ends up leaving the grid or a sealed compartment, i.e. the roots of the spill tree
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.traps.len()).filter(|&i| self.traps[i].spills_into.is_none())
+    }
+
+    /// Total volume of the leaf traps, which is what the caprock holds before any CO2 spills
+    pub fn leaf_volume(&self) -> f64 {
+        self.traps
+            .iter()
+            .filter(|trap| trap.sub_traps.is_empty())
+            .map(|trap| trap.volume)
+            .sum()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let traps: Vec<Value> = self
+            .traps
+            .iter()
+            .enumerate()
+            .map(|(id, trap)| {
+                json!({
+                    "id": id,
+                    "top_depth": trap.top_depth,
+                    "spill_depth": trap.spill_depth,
+                    "spill_point": trap.spill_point,
+                    "n_columns": trap.columns.len(),
+                    "volume": trap.volume,
+                    "spills_into": trap.spills_into,
+                    "sub_traps": trap.sub_traps,
+                })
+            })
+            .collect();
+        json!({
+            "shape": self.trap_map.shape(),
+            "n_traps": self.traps.len(),
+            "leaf_volume": self.leaf_volume(),
+            "traps": traps,
+        })
+    }
+}
+
+/// A set of connected columns that has been flooded down to the current depth
+#[derive(Debug)]
+struct Basin {
+    top_depth: f64,
+    columns: Vec<(usize, usize)>,
+    /// Traps that merged at a saddle and now fill up together
+    sub_traps: Vec<usize>,
+    /// An open basin reaches the edge of the grid. Its CO2 ends up in this trap, or leaves the grid directly.
+    open: Option<Option<usize>>,
+}
+
+/// Union-find over the columns
+struct Basins {
+    parent: Vec<usize>,
+    basins: Vec<Option<Basin>>,
+}
+
+impl Basins {
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn basin(&self, root: usize) -> &Basin {
+        self.basins[root].as_ref().expect("roots have a basin")
+    }
+
+    /// Merge the basins into the first one, which takes over all their columns
+    fn union(&mut self, roots: &[usize]) -> usize {
+        let target = roots[0];
+        for &root in &roots[1..] {
+            self.parent[root] = target;
+            let basin = self.basins[root].take().expect("roots have a basin");
+            let merged = self.basins[target].as_mut().expect("roots have a basin");
+            merged.top_depth = merged.top_depth.min(basin.top_depth);
+            merged.columns.extend(basin.columns);
+        }
+        target
+    }
+}
+
+/// The depth of the shallowest reservoir cell at or below the bedrock index of every column, or NaN
+pub fn top_surface(
+    reservoir_matrix: &ArrayView3<f64>,
+    depths: &ArrayView1<f64>,
+    bedrock_indices: &ArrayView2<usize>,
+    config: &SimulationConfig,
+) -> Array2<f64> {
+    let (nx, ny, nz) = reservoir_matrix.dim();
+    let grid = RegularGrid::new(nx, ny, depths.view());
+    let reservoir = initial_reservoir_state(
+        reservoir_matrix,
+        &grid,
+        bedrock_indices,
+        config.velocity_classifier,
+    );
+    Array2::from_shape_fn((nx, ny), |(x, y)| {
+        (bedrock_indices[[x, y]]..nz)
+            .find(|&z| reservoir.state((x, y, z)) == CellState::Reservoir)
+            .map_or(f64::NAN, |z| grid.cell_depth((x, y, z)))
+    })
+}
+
+/// Find the structural traps under the caprock surface. The surface is flooded from its shallowest column
+/// downwards, so every local high starts a trap that fills until it meets another trap at a saddle, or the
+/// edge of the grid, where the CO2 leaks out. Columns without reservoir are walls. The lateral connectivity
+/// follows the anisotropy of the config.
+pub fn analyze_traps(
+    reservoir_matrix: ArrayView3<f64>,
+    depths: ArrayView1<f64>,
+    bedrock_indices: ArrayView2<usize>,
+    config: &SimulationConfig,
+) -> Result<TrapAnalysis, SimulationError> {
+    // No source is needed, and the first cell always passes the source check
+    validate_inputs(
+        &reservoir_matrix,
+        &depths,
+        &bedrock_indices,
+        (0, 0, 0),
+        config,
+    )?;
+    let (nx, ny, _) = reservoir_matrix.dim();
+    let grid = RegularGrid::new(nx, ny, depths);
+    let surface = top_surface(&reservoir_matrix, &depths, &bedrock_indices, config);
+    let directions = lateral_directions(config.anisotropy);
+
+    let index = |(x, y): (usize, usize)| x * ny + y;
+    let mut basins = Basins {
+        parent: (0..nx * ny).collect(),
+        basins: (0..nx * ny).map(|_| None).collect(),
+    };
+
+    let mut order: Vec<(usize, usize)> = surface
+        .indexed_iter()
+        .filter(|(_, depth)| !depth.is_nan())
+        .map(|(column, _)| column)
+        .collect();
+    order.sort_by(|&a, &b| surface[a].total_cmp(&surface[b]).then(a.cmp(&b)));
+
+    let mut processed = Array2::from_elem((nx, ny), false);
+    let mut traps = Vec::new();
+    for column in order {
+        let depth = surface[column];
+        let (x, y) = column;
+        let mut roots = Vec::new();
+        for (xn, yn, _) in grid.lateral_neighbors((x, y, 0), &directions) {
+            if processed[[xn, yn]] {
+                let root = basins.find(index((xn, yn)));
+                if !roots.contains(&root) {
+                    roots.push(root);
+                }
+            }
+        }
+        processed[column] = true;
+        // The CO2 leaks out over the edge of the grid, except along a dimension of a single cell,
+        // so that cross-sections work
+        let at_edge = (nx > 1 && (x == 0 || x + 1 == nx)) || (ny > 1 && (y == 0 || y + 1 == ny));
+
+        // Basins that hold CO2 above this depth spill here if they meet another one or the edge.
+        // Flat basins at this depth are simply absorbed.
+        let drains = roots
+            .iter()
+            .find_map(|&root| basins.basin(root).open)
+            .or(at_edge.then_some(None));
+        let filled: Vec<usize> = roots
+            .iter()
+            .copied()
+            .filter(|&root| {
+                let basin = basins.basin(root);
+                basin.open.is_none() && basin.top_depth < depth
+            })
+            .collect();
+        let spills = drains.is_some() || filled.len() >= 2;
+
+        let mut new_traps = Vec::new();
+        if spills {
+            for &root in &filled {
+                let basin = basins.basins[root].as_mut().expect("roots have a basin");
+                let columns: Vec<(usize, usize)> = basin
+                    .columns
+                    .iter()
+                    .copied()
+                    .filter(|&c| surface[c] < depth)
+                    .collect();
+                traps.push(Trap {
+                    top_depth: basin.top_depth,
+                    spill_depth: depth,
+                    spill_point: Some(column),
+                    volume: columns.iter().map(|&c| depth - surface[c]).sum(),
+                    columns,
+                    spills_into: drains.flatten(),
+                    sub_traps: std::mem::take(&mut basin.sub_traps),
+                });
+                new_traps.push(traps.len() - 1);
+            }
+        }
+
+        let basin = if roots.is_empty() {
+            basins.basins[index(column)] = Some(Basin {
+                top_depth: depth,
+                columns: Vec::new(),
+                sub_traps: Vec::new(),
+                open: None,
+            });
+            basins.basins[index(column)].as_mut().unwrap()
+        } else {
+            // Open basins come first so they keep their trap
+            roots.sort_by_key(|&root| basins.basin(root).open.is_none());
+            let root = basins.union(&roots);
+            basins.parent[index(column)] = root;
+            basins.basins[root].as_mut().unwrap()
+        };
+        basin.columns.push(column);
+        match drains {
+            // CO2 that later reaches an open basin ends up in the trap that spilled into it first
+            Some(drain) => basin.open = Some(drain.or(new_traps.first().copied())),
+            None if spills => basin.sub_traps = new_traps,
+            None => {}
+        }
+    }
+
+    // Merged traps spill where their parent does, but only learn their parent when it is created
+    for parent in 0..traps.len() {
+        for child in traps[parent].sub_traps.clone() {
+            traps[child].spills_into = Some(parent);
+        }
+    }
+
+    // Basins that never reached the edge or another basin are sealed compartments
+    let mut sealed: Vec<Basin> = basins
+        .basins
+        .into_iter()
+        .flatten()
+        .filter(|basin| basin.open.is_none())
+        .collect();
+    sealed.sort_by(|a, b| a.columns.cmp(&b.columns));
+    for basin in sealed {
+        let spill_depth = basin
+            .columns
+            .iter()
+            .map(|&c| surface[c])
+            .fold(f64::NEG_INFINITY, f64::max);
+        let id = traps.len();
+        for &child in &basin.sub_traps {
+            traps[child].spills_into = Some(id);
+        }
+        traps.push(Trap {
+            top_depth: basin.top_depth,
+            spill_depth,
+            spill_point: None,
+            volume: basin
+                .columns
+                .iter()
+                .map(|&c| spill_depth - surface[c])
+                .sum(),
+            columns: basin.columns,
+            spills_into: None,
+            sub_traps: basin.sub_traps,
+        });
+    }
+
+    // Sub-traps come first, so the first trap to claim a column is its innermost one
+    let mut trap_map = Array2::from_elem((nx, ny), -1);
+    for (id, trap) in traps.iter().enumerate() {
+        for &column in &trap.columns {
+            if trap_map[column] == -1 {
+                trap_map[column] = id as i64;
+            }
+        }
+    }
+
+    Ok(TrapAnalysis {
+        top_surface: surface,
+        traps,
+        trap_map,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+    use numpy::ndarray::{s, Array1, Array3};
+
+    /// A single row of columns whose caprock base follows the given layer indices
+    fn caprock_profile(layers: &[usize], nz: usize) -> Array3<f64> {
+        let mut reservoir = Array3::from_elem((layers.len(), 1, nz), VELOCITY_RESERVOIR);
+        for (x, &layer) in layers.iter().enumerate() {
+            reservoir
+                .slice_mut(s![x, 0, ..layer])
+                .fill(VELOCITY_CAPROCK);
+        }
+        reservoir
+    }
+
+    #[test]
+    fn test_spill_tree_of_two_domes() {
+        // Two domes at x = 2 and x = 4 separated by a saddle at x = 3, inside a deeper rim
+        let layers = [5, 4, 1, 3, 2, 3, 5];
+        let reservoir = caprock_profile(&layers, 6);
+        let depths = Array1::from(vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        let bedrock_indices = Array2::from_shape_fn((7, 1), |(x, _)| layers[x] - 1);
+        let config = SimulationConfig::default();
+
+        let analysis = analyze_traps(
+            reservoir.view(),
+            depths.view(),
+            bedrock_indices.view(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(analysis.top_surface[[2, 0]], 1.0);
+
+        // Both domes fill to the saddle at depth 3, then fill together until they spill over the edge
+        assert_eq!(analysis.traps.len(), 3);
+        let (left, right, merged) = (&analysis.traps[0], &analysis.traps[1], &analysis.traps[2]);
+        assert_eq!((left.top_depth, left.spill_depth), (1.0, 3.0));
+        assert_eq!(left.volume, 2.0);
+        assert_eq!((right.top_depth, right.spill_depth), (2.0, 3.0));
+        assert_eq!(left.spill_point, Some((3, 0)));
+        assert_eq!(left.spills_into, Some(2));
+        assert_eq!(merged.sub_traps, vec![0, 1]);
+        assert_eq!(merged.spill_depth, 5.0);
+        assert_eq!(merged.spill_point, Some((0, 0)));
+        assert_eq!(merged.spills_into, None);
+        assert_eq!(analysis.roots().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(
+            analysis.trap_map.column(0).to_vec(),
+            vec![-1, 2, 0, 2, 1, 2, -1]
+        );
+    }
+
+    #[test]
+    fn test_trap_spills_into_a_trap_that_leaks() {
+        // A deep dome at x = 1 spills over x = 2 up to a shallow dome at x = 4, which leaks over the edge
+        let layers = [4, 2, 3, 2, 1, 2];
+        let reservoir = caprock_profile(&layers, 5);
+        let depths = Array1::from(vec![0.0, 1.0, 2.0, 3.0, 4.0]);
+        let bedrock_indices = Array2::from_shape_fn((6, 1), |(x, _)| layers[x] - 1);
+        let config = SimulationConfig::default();
+
+        let analysis = analyze_traps(
+            reservoir.view(),
+            depths.view(),
+            bedrock_indices.view(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(analysis.traps.len(), 2);
+        let (shallow, deep) = (&analysis.traps[0], &analysis.traps[1]);
+        assert_eq!((shallow.top_depth, shallow.spill_depth), (1.0, 2.0));
+        assert_eq!(shallow.spill_point, Some((5, 0)));
+        assert_eq!(shallow.spills_into, None);
+        assert_eq!((deep.top_depth, deep.spill_depth), (2.0, 3.0));
+        assert_eq!(deep.spills_into, Some(0));
+        assert_eq!(analysis.leaf_volume(), 1.0 + 1.0);
+    }
+}
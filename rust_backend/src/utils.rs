@@ -1,5 +1,5 @@
-use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
-use numpy::ndarray::{ArrayView1, ArrayView2};
+use crate::cell_state::{CellState, VelocityClassifier};
+use numpy::ndarray::{Array2, ArrayView1, ArrayView2, ArrayView3, Axis};
 
 /// Helper function for bounds checking
 #[inline]
@@ -26,14 +26,14 @@ pub fn safe_indices(
 
 /// Helper function to check that the cell is caprock
 #[inline]
-pub fn is_caprock(val: f64) -> bool {
-    val == VELOCITY_CAPROCK
+pub fn is_caprock(state: CellState) -> bool {
+    state == CellState::Caprock
 }
 
 /// Helper function to check that the cell is unfilled
 #[inline]
-pub fn is_empty(val: f64) -> bool {
-    val == VELOCITY_RESERVOIR
+pub fn is_empty(state: CellState) -> bool {
+    state == CellState::Reservoir
 }
 
 /// Helper function to check if the cell is bedrock (the final impermeable layer)
@@ -48,17 +48,41 @@ pub fn find_height_to_caprock(zi: usize, caprock_idx: usize) -> usize {
     zi - caprock_idx
 }
 
-/// Find the index of the closest layer with VELOCITY_CAPROCK below or at zi
+/// Find the index of the closest caprock layer below or at zi
 #[inline]
-pub fn find_closest_caprock_idx(reservoir_matrix_column: ArrayView1<f64>, zi: usize) -> usize {
-    reservoir_matrix_column
+pub fn find_closest_caprock_idx(column: ArrayView1<CellState>, zi: usize) -> usize {
+    column
         .iter()
         .enumerate()
-        .rfind(|&(_idx, &val)| val == VELOCITY_CAPROCK && _idx <= zi)
+        .rfind(|&(_idx, &state)| is_caprock(state) && _idx <= zi)
         .map(|(idx, _)| idx)
         .unwrap_or(0)
 }
 
+/// Compute the bedrock index of every column from the reservoir matrix.
+/// This is the lowest cell of the topmost caprock layer, i.e. the seal the CO2 can never break through.
+/// Columns without caprock get index 0.
+pub fn compute_bedrock_indices(
+    reservoir_matrix: &ArrayView3<f64>,
+    classifier: VelocityClassifier,
+) -> Array2<usize> {
+    reservoir_matrix.map_axis(Axis(2), |column| {
+        let is_caprock_velocity = |val: f64| is_caprock(classifier.classify(val));
+        match column.iter().position(|&val| is_caprock_velocity(val)) {
+            Some(top) => {
+                column
+                    .iter()
+                    .skip(top)
+                    .take_while(|&&val| is_caprock_velocity(val))
+                    .count()
+                    + top
+                    - 1
+            }
+            None => 0,
+        }
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -85,11 +109,13 @@ mod tests {
 
     #[test]
     fn test_is_caprock_and_is_empty() {
-        assert!(is_caprock(VELOCITY_CAPROCK));
-        assert!(!is_caprock(VELOCITY_RESERVOIR));
+        assert!(is_caprock(CellState::Caprock));
+        assert!(!is_caprock(CellState::Reservoir));
 
-        assert!(is_empty(VELOCITY_RESERVOIR));
-        assert!(!is_empty(VELOCITY_CAPROCK));
+        assert!(is_empty(CellState::Reservoir));
+        assert!(!is_empty(CellState::Caprock));
+        assert!(!is_empty(CellState::Co2));
+        assert!(!is_empty(CellState::Inactive));
     }
 
     #[test]
@@ -102,11 +128,11 @@ mod tests {
     #[test]
     fn test_find_closest_layer_idx() {
         let column = array![
-            VELOCITY_RESERVOIR,
-            VELOCITY_RESERVOIR,
-            VELOCITY_CAPROCK,
-            VELOCITY_RESERVOIR,
-            VELOCITY_CAPROCK
+            CellState::Reservoir,
+            CellState::Reservoir,
+            CellState::Caprock,
+            CellState::Reservoir,
+            CellState::Caprock
         ];
 
         // should find the last caprock at or before zi = 4
@@ -121,4 +147,20 @@ mod tests {
         // zi at 0 → no caprock at/below, return 0
         assert_eq!(find_closest_caprock_idx(column.view(), 0), 0);
     }
+
+    #[test]
+    fn test_compute_bedrock_indices() {
+        use crate::constants::{VELOCITY_CAPROCK, VELOCITY_RESERVOIR};
+
+        let mut reservoir = numpy::ndarray::Array3::from_elem((2, 1, 6), VELOCITY_RESERVOIR);
+        // Top caprock layer spanning z = 1..=2, and a second caprock layer at z = 4
+        for z in [1, 2, 4] {
+            reservoir[[0, 0, z]] = VELOCITY_CAPROCK;
+        }
+
+        let bedrock_indices = compute_bedrock_indices(&reservoir.view(), VelocityClassifier::Exact);
+        assert_eq!(bedrock_indices[[0, 0]], 2);
+        // No caprock in the column
+        assert_eq!(bedrock_indices[[1, 0]], 0);
+    }
 }
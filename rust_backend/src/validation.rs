@@ -0,0 +1,338 @@
+use numpy::ndarray::{ArrayView1, ArrayView2, ArrayView3};
+
+use crate::config::SimulationConfig;
+use crate::datastucture::QueueKind;
+use crate::error::SimulationError;
+use crate::ordering::FrontOrdering;
+use crate::perforation::SweepOrder;
+
+/// Check that the shapes of the inputs are consistent with each other and that the source is inside the grid.
+/// This runs before the simulation so that bad inputs give a descriptive error instead of an out-of-bounds panic.
+pub fn validate_inputs(
+    reservoir_matrix: &ArrayView3<f64>,
+    depths: &ArrayView1<f64>,
+    bedrock_indices: &ArrayView2<usize>,
+    source: (usize, usize, usize),
+    config: &SimulationConfig,
+) -> Result<(), SimulationError> {
+    let (nx, ny, nz) = reservoir_matrix.dim();
+
+    if nx == 0 || ny == 0 || nz == 0 {
+        return Err(SimulationError::ShapeMismatch {
+            argument: "reservoir_matrix".to_string(),
+            expected: "(nx, ny, nz) with all dimensions non-zero".to_string(),
+            actual: format!("({}, {}, {})", nx, ny, nz),
+        });
+    }
+
+    if depths.len() != nz {
+        return Err(SimulationError::ShapeMismatch {
+            argument: "depths".to_string(),
+            expected: format!("({},) to match nz of reservoir_matrix", nz),
+            actual: format!("({},)", depths.len()),
+        });
+    }
+
+    // The front is ordered by depth, so NaN or unsorted depths would silently give a wrong fill order
+    if let Some(z) = depths.iter().position(|d| !d.is_finite()) {
+        return Err(SimulationError::InvalidValue {
+            argument: "depths".to_string(),
+            message: format!(
+                "depth at z = {} is {}, expected a finite value",
+                z, depths[z]
+            ),
+        });
+    }
+    if let Some(z) = (1..nz).find(|&z| depths[z] <= depths[z - 1]) {
+        return Err(SimulationError::InvalidValue {
+            argument: "depths".to_string(),
+            message: format!(
+                "must be strictly increasing with z, but depth {} at z = {} follows {} at z = {}",
+                depths[z],
+                z,
+                depths[z - 1],
+                z - 1
+            ),
+        });
+    }
+
+    validate_grid_inputs(
+        reservoir_matrix,
+        (nx, ny, nz),
+        bedrock_indices,
+        source,
+        config,
+    )
+}
+
+/// Check the inputs against the dimensions of the simulation grid
+pub fn validate_grid_inputs(
+    reservoir_matrix: &ArrayView3<f64>,
+    grid_dims: (usize, usize, usize),
+    bedrock_indices: &ArrayView2<usize>,
+    source: (usize, usize, usize),
+    config: &SimulationConfig,
+) -> Result<(), SimulationError> {
+    let (nx, ny, nz) = reservoir_matrix.dim();
+
+    if (nx, ny, nz) != grid_dims {
+        return Err(SimulationError::ShapeMismatch {
+            argument: "reservoir_matrix".to_string(),
+            expected: format!("{:?} to match the grid", grid_dims),
+            actual: format!("({}, {}, {})", nx, ny, nz),
+        });
+    }
+
+    // Neighbor offsets are computed in i32
+    if [nx, ny, nz].iter().any(|&n| n > i32::MAX as usize) {
+        return Err(SimulationError::ShapeMismatch {
+            argument: "reservoir_matrix".to_string(),
+            expected: format!("dimensions of at most {}", i32::MAX),
+            actual: format!("({}, {}, {})", nx, ny, nz),
+        });
+    }
+
+    if bedrock_indices.dim() != (nx, ny) {
+        let (bx, by) = bedrock_indices.dim();
+        return Err(SimulationError::ShapeMismatch {
+            argument: "bedrock_indices".to_string(),
+            expected: format!("({}, {}) to match (nx, ny) of reservoir_matrix", nx, ny),
+            actual: format!("({}, {})", bx, by),
+        });
+    }
+
+    if let Some(((x, y), &z)) = bedrock_indices.indexed_iter().find(|(_, &z)| z >= nz) {
+        return Err(SimulationError::InvalidValue {
+            argument: "bedrock_indices".to_string(),
+            message: format!(
+                "index {} at ({}, {}) is outside the {} layers of reservoir_matrix",
+                z, x, y, nz
+            ),
+        });
+    }
+
+    let (xi, yi, zi) = source;
+    if xi >= nx || yi >= ny || zi >= nz {
+        return Err(SimulationError::InvalidSource(format!(
+            "Source ({}, {}, {}) is outside the grid of shape ({}, {}, {})",
+            xi, yi, zi, nx, ny, nz
+        )));
+    }
+
+    if let Some(basement_indices) = &config.basement_indices {
+        if basement_indices.dim() != (nx, ny) {
+            let (bx, by) = basement_indices.dim();
+            return Err(SimulationError::ShapeMismatch {
+                argument: "basement_indices".to_string(),
+                expected: format!("({}, {}) to match (nx, ny) of reservoir_matrix", nx, ny),
+                actual: format!("({}, {})", bx, by),
+            });
+        }
+        if let Some(((x, y), &z)) = basement_indices.indexed_iter().find(|(_, &z)| z > nz) {
+            return Err(SimulationError::InvalidValue {
+                argument: "basement_indices".to_string(),
+                message: format!(
+                    "index {} at ({}, {}) is below the {} layers of reservoir_matrix",
+                    z, x, y, nz
+                ),
+            });
+        }
+        if zi >= basement_indices[[xi, yi]] {
+            return Err(SimulationError::InvalidSource(format!(
+                "Source must be above the basement of its column at z = {}",
+                basement_indices[[xi, yi]]
+            )));
+        }
+    }
+
+    if let Some(initial_plume) = &config.initial_plume {
+        if initial_plume.dim() != (nx, ny, nz) {
+            let (px, py, pz) = initial_plume.dim();
+            return Err(SimulationError::ShapeMismatch {
+                argument: "initial_plume".to_string(),
+                expected: format!("({}, {}, {}) to match reservoir_matrix", nx, ny, nz),
+                actual: format!("({}, {}, {})", px, py, pz),
+            });
+        }
+    }
+
+    if config.total_snapshots == 0 {
+        return Err(SimulationError::InvalidValue {
+            argument: "total_snapshots".to_string(),
+            message: "must be at least 1".to_string(),
+        });
+    }
+    config.snapshot_policy.validate()?;
+    config.velocity_classifier.validate()?;
+    config.breach_criterion.validate()?;
+    config.breach_geometry.validate()?;
+    if let Some(resealing) = &config.resealing {
+        resealing.validate()?;
+    }
+    if let Some(containment) = &config.containment {
+        containment.validate((nx, ny))?;
+    }
+    if let Some(lateral_limit) = &config.lateral_limit {
+        lateral_limit.validate()?;
+    }
+    if let Some(mass_accounting) = &config.mass_accounting {
+        mass_accounting.validate()?;
+    }
+    if config.front_ordering != FrontOrdering::Depth && config.queue == QueueKind::Bucket {
+        return Err(SimulationError::InvalidValue {
+            argument: "front_ordering".to_string(),
+            message: "the bucket queue orders by z index and only supports the depth ordering"
+                .to_string(),
+        });
+    }
+    if let Some(aquifer_flow) = &config.aquifer_flow {
+        aquifer_flow.validate()?;
+        if config.queue == QueueKind::Bucket {
+            return Err(SimulationError::InvalidValue {
+                argument: "aquifer_flow".to_string(),
+                message: "the bucket queue ignores depths and cannot bias the spreading"
+                    .to_string(),
+            });
+        }
+    }
+    if let Some(co2_stream) = &config.co2_stream {
+        co2_stream.validate()?;
+    }
+    if let Some(stochastic_spreading) = &config.stochastic_spreading {
+        stochastic_spreading.validate((nx, ny, nz))?;
+    }
+    if let Some(stencil) = &config.stencil {
+        stencil.validate()?;
+        if config.stochastic_spreading.is_some() {
+            return Err(SimulationError::InvalidValue {
+                argument: "stencil".to_string(),
+                message: "cannot be combined with stochastic spreading".to_string(),
+            });
+        }
+        if config.queue == QueueKind::Bucket
+            && stencil.offsets().iter().any(|&(_, weight)| weight != 0.0)
+        {
+            return Err(SimulationError::InvalidValue {
+                argument: "stencil".to_string(),
+                message: "the bucket queue ignores depths and cannot weight the offsets"
+                    .to_string(),
+            });
+        }
+    }
+    if let Some(smoothing) = &config.smoothing {
+        smoothing.validate()?;
+    }
+    if let Some(perforation) = &config.perforation {
+        perforation.validate(nz, &[source])?;
+        if perforation.fractions().is_some() && config.sweep_order != SweepOrder::TopDown {
+            return Err(SimulationError::InvalidValue {
+                argument: "sweep_order".to_string(),
+                message:
+                    "a split perforation injects into all layers at once and has no sweep order"
+                        .to_string(),
+            });
+        }
+    }
+    if let Some(thermal_zone) = &config.thermal_zone {
+        thermal_zone.validate()?;
+    }
+    if let Some(pressure_model) = &config.pressure_model {
+        pressure_model.validate()?;
+    }
+    if let Some(pressure_limit) = &config.pressure_limit {
+        pressure_limit.validate()?;
+        if config.pressure_model.is_none() || config.time_axis.is_none() {
+            return Err(SimulationError::InvalidValue {
+                argument: "pressure_limit".to_string(),
+                message: "needs both a pressure model and an injection schedule".to_string(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use numpy::ndarray::{Array1, Array2, Array3};
+
+    #[test]
+    fn test_validate_inputs() {
+        let reservoir = Array3::<f64>::zeros((4, 3, 5));
+        let depths = Array1::from_iter((0..5).map(|z| z as f64));
+        let bedrock_indices = Array2::<usize>::zeros((4, 3));
+        let config = SimulationConfig::default();
+
+        assert!(validate_inputs(
+            &reservoir.view(),
+            &depths.view(),
+            &bedrock_indices.view(),
+            (1, 1, 1),
+            &config
+        )
+        .is_ok());
+
+        let short_depths = Array1::<f64>::zeros(4);
+        let err = validate_inputs(
+            &reservoir.view(),
+            &short_depths.view(),
+            &bedrock_indices.view(),
+            (1, 1, 1),
+            &config,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "depths has shape (4,), expected (5,) to match nz of reservoir_matrix"
+        );
+
+        let transposed = Array2::<usize>::zeros((3, 4));
+        let err = validate_inputs(
+            &reservoir.view(),
+            &depths.view(),
+            &transposed.view(),
+            (1, 1, 1),
+            &config,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, SimulationError::ShapeMismatch { ref argument, .. } if argument == "bedrock_indices")
+        );
+
+        let err = validate_inputs(
+            &reservoir.view(),
+            &depths.view(),
+            &bedrock_indices.view(),
+            (4, 0, 0),
+            &config,
+        )
+        .unwrap_err();
+        assert!(matches!(err, SimulationError::InvalidSource(_)));
+    }
+
+    #[test]
+    fn test_validate_depths() {
+        let reservoir = Array3::<f64>::zeros((2, 2, 3));
+        let bedrock_indices = Array2::<usize>::zeros((2, 2));
+        let config = SimulationConfig::default();
+        let validate = |depths: Vec<f64>| {
+            validate_inputs(
+                &reservoir.view(),
+                &Array1::from(depths).view(),
+                &bedrock_indices.view(),
+                (0, 0, 1),
+                &config,
+            )
+        };
+
+        assert!(validate(vec![800.0, 805.0, 810.0]).is_ok());
+        let err = validate(vec![800.0, f64::NAN, 810.0]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid depths: depth at z = 1 is NaN, expected a finite value"
+        );
+        let err = validate(vec![800.0, 810.0, 810.0]).unwrap_err();
+        assert!(err.to_string().contains("strictly increasing"));
+    }
+}
@@ -0,0 +1,302 @@
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+use image::RgbImage;
+use numpy::ndarray::Array3;
+
+use crate::render::{render_slice, RenderOptions, Slice};
+
+/// A key press of the slice viewer, see `parse_keys`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewerKey {
+    /// Right arrow
+    NextSnapshot,
+    /// Left arrow
+    PreviousSnapshot,
+    /// Down arrow, deeper for a z slice
+    NextSlice,
+    /// Up arrow
+    PreviousSlice,
+    /// Home or g
+    FirstSnapshot,
+    /// End or G
+    LastSnapshot,
+    /// q, Esc or Ctrl+C
+    Quit,
+}
+
+/// The keys in bytes read from a terminal in raw mode. Arrow keys are escape sequences, and bytes of keys the
+/// viewer does not use are skipped.
+pub fn parse_keys(bytes: &[u8]) -> Vec<ViewerKey> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let key = match &bytes[i..] {
+            [0x1b, b'[' | b'O', code, ..] => {
+                i += 2;
+                match code {
+                    b'A' => Some(ViewerKey::PreviousSlice),
+                    b'B' => Some(ViewerKey::NextSlice),
+                    b'C' => Some(ViewerKey::NextSnapshot),
+                    b'D' => Some(ViewerKey::PreviousSnapshot),
+                    b'H' => Some(ViewerKey::FirstSnapshot),
+                    b'F' => Some(ViewerKey::LastSnapshot),
+                    _ => None,
+                }
+            }
+            [0x1b] | [b'q' | b'Q' | 0x03, ..] => Some(ViewerKey::Quit),
+            [b'g', ..] => Some(ViewerKey::FirstSnapshot),
+            [b'G', ..] => Some(ViewerKey::LastSnapshot),
+            _ => None,
+        };
+        keys.extend(key);
+        i += 1;
+    }
+    keys
+}
+
+/// A slice of the snapshots drawn as colored characters in a terminal, with the plume shown up to a snapshot
+/// that the arrow keys scrub through, see `run_viewer`
+#[derive(Debug, Clone)]
+pub struct SliceViewer {
+    snapshots: Array3<i32>,
+    slice: Slice,
+    snapshot: i32,
+    last_snapshot: i32,
+    /// Cells per character along each side, for grids wider than the terminal
+    stride: usize,
+    options: RenderOptions,
+}
+
+impl SliceViewer {
+    /// A viewer of the slice, showing the whole plume. A stride of 0 is taken as 1.
+    pub fn new(
+        snapshots: Array3<i32>,
+        slice: Slice,
+        stride: usize,
+        options: RenderOptions,
+    ) -> Result<Self, String> {
+        let last_snapshot = snapshots.iter().copied().max().unwrap_or(-1).max(0);
+        let viewer = SliceViewer {
+            snapshots,
+            slice,
+            snapshot: last_snapshot,
+            last_snapshot,
+            stride: stride.max(1),
+            options: RenderOptions {
+                scale: 1,
+                max_snapshot: Some(options.max_snapshot.unwrap_or(last_snapshot)),
+                ..options
+            },
+        };
+        viewer.image()?;
+        Ok(viewer)
+    }
+
+    pub fn slice(&self) -> Slice {
+        self.slice
+    }
+
+    /// The last snapshot shown
+    pub fn snapshot(&self) -> i32 {
+        self.snapshot
+    }
+
+    /// Apply a key press. Returns false on Quit.
+    pub fn apply(&mut self, key: ViewerKey) -> bool {
+        let (nx, ny, nz) = self.snapshots.dim();
+        let step = |index: usize, n: usize, forward: bool| {
+            if forward {
+                (index + 1).min(n - 1)
+            } else {
+                index.saturating_sub(1)
+            }
+        };
+        let move_slice = |slice: Slice, forward: bool| match slice {
+            Slice::X(x) => Slice::X(step(x, nx, forward)),
+            Slice::Y(y) => Slice::Y(step(y, ny, forward)),
+            Slice::Z(z) => Slice::Z(step(z, nz, forward)),
+            Slice::Map => Slice::Map,
+        };
+        match key {
+            ViewerKey::NextSnapshot => self.snapshot = (self.snapshot + 1).min(self.last_snapshot),
+            ViewerKey::PreviousSnapshot => self.snapshot = (self.snapshot - 1).max(0),
+            ViewerKey::FirstSnapshot => self.snapshot = 0,
+            ViewerKey::LastSnapshot => self.snapshot = self.last_snapshot,
+            ViewerKey::NextSlice => self.slice = move_slice(self.slice, true),
+            ViewerKey::PreviousSlice => self.slice = move_slice(self.slice, false),
+            ViewerKey::Quit => return false,
+        }
+        true
+    }
+
+    fn image(&self) -> Result<RgbImage, String> {
+        let options = RenderOptions {
+            up_to_snapshot: Some(self.snapshot),
+            ..self.options.clone()
+        };
+        render_slice(&self.snapshots.view(), self.slice, &options)
+    }
+
+    /// The slice as lines of text with 24-bit color escape codes, followed by a status line. Every character is
+    /// an upper half block showing two cells, one above the other.
+    pub fn frame(&self) -> Result<String, String> {
+        let image = self.image()?;
+        let stride = self.stride as u32;
+        let (width, height) = (
+            image.width().div_ceil(stride),
+            image.height().div_ceil(stride),
+        );
+        let pixel = |i: u32, j: u32| image.get_pixel(i * stride, j * stride).0;
+        let mut frame = String::new();
+        for row in (0..height).step_by(2) {
+            for i in 0..width {
+                let [r, g, b] = pixel(i, row);
+                frame.push_str(&format!("\x1b[38;2;{};{};{}m", r, g, b));
+                if row + 1 < height {
+                    let [r, g, b] = pixel(i, row + 1);
+                    frame.push_str(&format!("\x1b[48;2;{};{};{}m", r, g, b));
+                }
+                frame.push('▀');
+            }
+            frame.push_str("\x1b[0m\r\n");
+        }
+        let slice = match self.slice {
+            Slice::X(x) => format!("x={}", x),
+            Slice::Y(y) => format!("y={}", y),
+            Slice::Z(z) => format!("z={}", z),
+            Slice::Map => "map".to_string(),
+        };
+        frame.push_str(&format!(
+            "{}  snapshot {}/{}  ←/→ snapshot  ↑/↓ slice  g/G first/last  q quit\r\n",
+            slice, self.snapshot, self.last_snapshot
+        ));
+        Ok(frame)
+    }
+}
+
+/// Puts the terminal in raw mode with stty, so key presses are read as they come, and restores its settings
+/// when dropped
+struct RawMode {
+    settings: String,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let stty = |args: &[&str]| -> io::Result<String> {
+            let output = Command::new("stty")
+                .args(args)
+                .stdin(Stdio::inherit())
+                .output()?;
+            if !output.status.success() {
+                return Err(io::Error::other("stdin is not a terminal"));
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        };
+        let settings = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        Ok(RawMode { settings })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = Command::new("stty")
+            .arg(&self.settings)
+            .stdin(Stdio::inherit())
+            .status();
+    }
+}
+
+/// Show the viewer in the terminal until q is pressed. Only needs stty and a terminal that understands ANSI
+/// escape codes, so it works over plain SSH without X forwarding.
+pub fn run_viewer(mut viewer: SliceViewer) -> Result<(), Box<dyn std::error::Error>> {
+    let _raw_mode = RawMode::enable()?;
+    let mut stdout = io::stdout().lock();
+    let mut stdin = io::stdin().lock();
+    // The alternate screen keeps the shell history visible after the viewer quits
+    write!(stdout, "\x1b[?1049h\x1b[?25l")?;
+    let mut buffer = [0u8; 64];
+    // Errors break out of the loop, so the terminal is restored before they are returned
+    let result: Result<(), Box<dyn std::error::Error>> = loop {
+        let frame = match viewer.frame() {
+            Ok(frame) => frame,
+            Err(err) => break Err(err.into()),
+        };
+        if let Err(err) = write!(stdout, "\x1b[H\x1b[2J{}", frame).and_then(|_| stdout.flush()) {
+            break Err(err.into());
+        }
+        let n = match stdin.read(&mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(n) => n,
+            Err(err) => break Err(err.into()),
+        };
+        if !parse_keys(&buffer[..n])
+            .into_iter()
+            .all(|key| viewer.apply(key))
+        {
+            break Ok(());
+        }
+    };
+    write!(stdout, "\x1b[?25h\x1b[?1049l")?;
+    stdout.flush()?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slice_viewer() {
+        assert_eq!(
+            parse_keys(b"\x1b[C\x1b[Dx\x1b[Bg\x1bOAq"),
+            [
+                ViewerKey::NextSnapshot,
+                ViewerKey::PreviousSnapshot,
+                ViewerKey::NextSlice,
+                ViewerKey::FirstSnapshot,
+                ViewerKey::PreviousSlice,
+                ViewerKey::Quit,
+            ]
+        );
+        assert_eq!(parse_keys(b"\x1b"), [ViewerKey::Quit]);
+
+        let mut snapshots = Array3::from_elem((3, 2, 3), -1);
+        snapshots[[0, 0, 1]] = 0;
+        snapshots[[1, 0, 1]] = 2;
+        let mut viewer =
+            SliceViewer::new(snapshots, Slice::Z(1), 1, RenderOptions::default()).unwrap();
+        assert_eq!(viewer.snapshot(), 2);
+        let frame = viewer.frame().unwrap();
+        // Two rows of cells make one line, with the status below
+        assert_eq!(frame.lines().count(), 2);
+        assert_eq!(frame.matches('▀').count(), 3);
+        let [r, g, b] = viewer.options.colormap.color(1.0);
+        assert!(frame.contains(&format!("\x1b[38;2;{};{};{}m", r, g, b)));
+        assert!(frame.contains("z=1  snapshot 2/2"));
+
+        // Scrubbing back hides the later cells
+        assert!(viewer.apply(ViewerKey::PreviousSnapshot));
+        assert!(!viewer
+            .frame()
+            .unwrap()
+            .contains(&format!("38;2;{};{};{}m", r, g, b)));
+        viewer.apply(ViewerKey::FirstSnapshot);
+        viewer.apply(ViewerKey::PreviousSnapshot);
+        assert_eq!(viewer.snapshot(), 0);
+        for _ in 0..3 {
+            viewer.apply(ViewerKey::NextSlice);
+        }
+        assert_eq!(viewer.slice(), Slice::Z(2));
+        assert!(!viewer.apply(ViewerKey::Quit));
+
+        assert!(SliceViewer::new(
+            Array3::from_elem((2, 2, 2), -1),
+            Slice::X(2),
+            1,
+            RenderOptions::default()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use numpy::ndarray::Array3;
+use serde::{Deserialize, Serialize};
+
+use crate::sparse::SparseGrid;
+
+/// Attribution of a cell that no well's front has reached
+pub const NO_WELL: i16 = -1;
+/// Attribution of a cell reached by the fronts of more than one well
+pub const MERGED_WELLS: i16 = -2;
+
+/// Which well's front reached each cell in a multi-well run. Fronts claim the cells they push, and a cell
+/// claimed by two different wells is marked as merged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WellAttribution {
+    owners: SparseGrid<i16>,
+    n_wells: usize,
+}
+
+impl WellAttribution {
+    pub fn new(dims: (usize, usize, usize), n_wells: usize) -> Self {
+        WellAttribution {
+            owners: SparseGrid::new(dims, NO_WELL),
+            n_wells,
+        }
+    }
+
+    /// Track another well. A single well is not tracked, so the cells its front has visited are claimed for it.
+    pub fn add_well(&mut self, visited: &SparseGrid<bool>) {
+        if self.n_wells == 1 {
+            for (cell, _) in visited.iter() {
+                self.owners.set(cell, 0);
+            }
+        }
+        self.n_wells += 1;
+    }
+
+    /// The well whose front reached the cell, MERGED_WELLS or NO_WELL
+    pub fn owner(&self, cell: (usize, usize, usize)) -> i16 {
+        // With a single well everything belongs to it, so there is nothing to track
+        if self.n_wells == 1 {
+            return 0;
+        }
+        self.owners.get(cell)
+    }
+
+    /// Let a well's front claim the cell. Claims from the merged front keep the cell merged.
+    pub fn claim(&mut self, cell: (usize, usize, usize), well: i16) {
+        if self.n_wells == 1 || well == NO_WELL {
+            return;
+        }
+        let owner = self.owners.get(cell);
+        if owner == NO_WELL {
+            self.owners.set(cell, well);
+        } else if owner != well {
+            self.owners.set(cell, MERGED_WELLS);
+        }
+    }
+
+    /// The well that filled each cell. Cells without CO2 in the given snapshots are NO_WELL.
+    pub fn to_dense(&self, snapshots: &SparseGrid<i32>) -> Array3<i16> {
+        let mut attribution = Array3::from_elem(snapshots.dim(), NO_WELL);
+        for (cell, snapshot) in snapshots.iter() {
+            if snapshot >= 0 {
+                attribution[cell] = self.owner(cell);
+            }
+        }
+        attribution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claims_merge() {
+        let mut wells = WellAttribution::new((2, 1, 1), 2);
+        wells.claim((0, 0, 0), 0);
+        wells.claim((0, 0, 0), 0);
+        assert_eq!(wells.owner((0, 0, 0)), 0);
+        wells.claim((0, 0, 0), 1);
+        assert_eq!(wells.owner((0, 0, 0)), MERGED_WELLS);
+        wells.claim((0, 0, 0), 0);
+        assert_eq!(wells.owner((0, 0, 0)), MERGED_WELLS);
+
+        wells.claim((1, 0, 0), 1);
+        let mut snapshots = SparseGrid::new((2, 1, 1), -1);
+        snapshots.set((0, 0, 0), 0);
+        let attribution = wells.to_dense(&snapshots);
+        assert_eq!(attribution[[0, 0, 0]], MERGED_WELLS);
+        // Claimed but never filled
+        assert_eq!(attribution[[1, 0, 0]], NO_WELL);
+    }
+}
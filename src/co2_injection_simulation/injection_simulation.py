@@ -1,27 +1,172 @@
-from typing import Tuple
+import json
+import os
+from typing import Any, Callable, Iterator, Optional, Tuple
 
 import numpy as np
 from numpy.typing import NDArray
 
-from co2_injection_simulation.rust_backend import _injection_simulation_python_wrapper
+from co2_injection_simulation.rust_backend import (
+    SimulationInterrupted,
+    _analytic_validation_python_wrapper,
+    _benchmark_model_python_wrapper,
+    _column_fill_python_wrapper,
+    _cross_section_python_wrapper,
+    _darcy_flow_python_wrapper,
+    _flood_fill_python_wrapper,
+    _injection_simulation_iterator,
+    _injection_simulation_python_wrapper,
+    _invasion_percolation_python_wrapper,
+    _opm_deck_python_wrapper,
+    _particle_tracking_python_wrapper,
+    _repair_grid_python_wrapper,
+    _replay_journal_python_wrapper,
+    _trap_analysis_python_wrapper,
+)
+
+
+__all__ = [
+    "SimulationInterrupted",
+    "injection_simulation",
+    "injection_simulation_iter",
+    "injection_simulation_window",
+    "column_fill",
+    "darcy_flow",
+    "export_opm_deck",
+    "flood_fill",
+    "cross_section_simulation",
+    "invasion_percolation",
+    "particle_tracking",
+    "repair_grid",
+    "replay_journal",
+    "trap_analysis",
+]
+
+FloatArray = NDArray[np.float32] | NDArray[np.float64]
+IndexArray = NDArray[np.int32] | NDArray[np.int64] | NDArray[np.uint64]
 
 
 def injection_simulation(
-    reservoir_matrix: NDArray[np.float64],  # (nx, ny, nz)
-    depths: NDArray[np.float64],  # (nz,)
-    bedrock_indices: NDArray[np.int32],  # (nx, ny)
-    max_column_height: int,
-    source: Tuple[int, int, int],
+    reservoir_matrix: FloatArray,  # (nx, ny, nz)
+    depths: FloatArray,  # (nz,)
+    # (nx, ny), computed from the topmost caprock layer of each column if None
+    bedrock_indices: Optional[IndexArray],
+    max_column_height: Optional[int],  # None never breaks the caprock
+    # A list of sources injects from several wells at once
+    source: Tuple[int, int, int] | list[Tuple[int, int, int]],
     total_snapshots: int = 100,  # Number of snapshots to capture
-) -> NDArray[np.int32]:  # (nx, ny, nz)
-    # Assure the arrays are correct. Unsure if this really is needed.
-    reservoir_matrix = reservoir_matrix.astype(np.float64)
-    reservoir_matrix = np.ascontiguousarray(reservoir_matrix)
-    depths = depths.astype(np.float64)
-    depths = np.ascontiguousarray(depths)
-    bedrock_indices = bedrock_indices.astype(np.int32)
-    bedrock_indices = np.ascontiguousarray(bedrock_indices)
-
+    # Called with (fraction_done, cells_filled, snapshot_index), e.g. to update a tqdm progress bar
+    progress_callback: Optional[Callable[[float, int, int], None]] = None,
+    progress_interval: int = 1000,  # Number of filled cells between progress callbacks
+    # Return a dict with snapshots, velocity_model, saturation, well_attribution (int16 index of the well that
+    # filled each cell, -2 where fronts merged and -1 without CO2), snapshot_volumes, trapping_inventory (cells of
+    # structural, residual, dissolved and mineralized CO2 at the end of every snapshot), layer_statistics (cells_filled,
+    # first_snapshot, last_snapshot and fraction_used of the reservoir cells for every z layer), maps ((nx, ny)
+    # max_saturation, top_depth, first_arrival snapshot and thickness in cells), breach_events, accumulations (the
+    # primary pool and one secondary pool per breach, with the breach that fed it, its parent pool, its cells, the
+    # breaches in its own seal and the migration_chain of pools from the primary), nan_cells, compartment_cells (reservoir cells reachable without breaking caprock), compartment_warning (set
+    # when that is less than half of the reservoir), elapsed_seconds and
+    # fingerprint, a hash of the inputs, config and backend version identifying the run. The coordinates of the
+    # x, y, depth and snapshot axes and the dims of the arrays along them are added as well, see results_to_xarray.
+    return_extras: bool = False,
+    # List of (ISO date, filled cells per day) starting with the injection start.
+    # Adds the completion date of each snapshot to the extras as snapshot_dates.
+    injection_schedule: Optional[list[Tuple[str, float]]] = None,
+    # Accept velocities within this distance of the caprock, reservoir and CO2 velocities,
+    # e.g. for velocity cubes that have been interpolated. 0 only accepts the exact velocities.
+    velocity_tolerance: float = 0.0,
+    # Strict containment: stop as soon as CO2 is above the primary caprock (nx, ny), outside the
+    # polygon of (x, y) cell indices, or in the surface layer. The extras then hold containment_violation.
+    primary_caprock_indices: Optional[IndexArray] = None,
+    containment_polygon: Optional[list[Tuple[float, float]]] = None,
+    stop_at_surface: bool = False,
+    # Add a mass_ledger with the injected, mobile, trapped, dissolved and leaked cells of every snapshot to the extras
+    audit: bool = False,
+    # Aquifer properties in SI units (permeability, thickness, porosity, total_compressibility, brine_viscosity,
+    # cell_size as (dx, dy), cell_volume and optionally well_radius) for an analytic (Theis) estimate of the
+    # pressure buildup. Needs injection_schedule, and adds an (n_snapshots, nx, ny) overpressure map in Pa to the extras.
+    pressure_model: Optional[dict[str, Any]] = None,
+    # Throttle the injection to keep the bottomhole pressure below safety_factor (default 0.9) times the fracture
+    # pressure, given as fracture_gradient in Pa/m (with hydrostatic_gradient, default 10000 Pa/m, and step_days,
+    # default 30). Needs pressure_model and depths in metres. The snapshot_dates then follow the throttled rate,
+    # which is added to the extras as effective_schedule.
+    pressure_limit: Optional[dict[str, float]] = None,
+    # Break the caprock when the CO2 pressure (hydrostatic_gradient, default 10000 Pa/m, times depth plus the buoyancy
+    # of the column from density_difference in kg/m³, plus overpressure in Pa and the pressure_model estimate) exceeds
+    # min_horizontal_stress_gradient in Pa/m times depth, instead of using max_column_height. Needs depths in metres.
+    stress_criterion: Optional[dict[str, float]] = None,
+    # Convert filled cells to tonnes using the CO2 density at the depth of every cell: cell_volume in m³,
+    # density_table as a list of (depth in m, density in kg/m³) (default: a typical hydrostatic profile) and
+    # optionally max_injected_mass in tonnes to stop at. Adds snapshot_masses and injected_mass to the extras.
+    mass_accounting: Optional[dict[str, Any]] = None,
+    # Regional brine flow: hydraulic_gradient as the head drop per metre along (x, y) and cell_size as (dx, dy) in m.
+    # The plume then spreads preferentially downstream, more so for a larger density_ratio (brine density over
+    # the brine–CO2 density difference, default 3). drift_rate is only used by Simulation.relax.
+    aquifer_flow: Optional[dict[str, Any]] = None,
+    # The cold zone around the wells: CO2 within radius (in cells) of a well column is density_factor (default 1)
+    # times denser, which counts with mass_accounting, and gets co2_velocity in the velocity model.
+    thermal_zone: Optional[dict[str, float]] = None,
+    # Mole fractions of the impurities in the injected stream, e.g. {"N2": 0.03, "CH4": 0.01}. The lighter stream
+    # adds buoyancy with stress_criterion, lowers the mass with mass_accounting and changes the CO2 velocity.
+    co2_stream: Optional[dict[str, float]] = None,
+    # Spread laterally with random per-direction probabilities, e.g. {"probabilities": [0.8, 0.2, 0.5, 0.5],
+    # "seed": 1}, with the probabilities for +x, -x, +y and -y and an optional "permeability" array that scales them.
+    # Different seeds give an ensemble of plume shapes from one model.
+    stochastic_spreading: Optional[dict[str, Any]] = None,
+    # Smooth the plume edges in the velocity model of the extras with a few diffusion steps, e.g.
+    # {"iterations": 2, "strength": 0.5}, against artificial diffractions from stair-stepped edges in synthetic seismic
+    smoothing: Optional[dict[str, float]] = None,
+    # The layers where the wells are open: a (z_top, z_bottom) interval or a list of z indices that must include
+    # the source layer. None injects into every layer from the source down to the base of the model in turn.
+    # A dict of z index to fraction, e.g. {3: 0.2, 7: 0.8}, injects into all layers at once with these fractions
+    # of the filled cells, e.g. for thief zones.
+    perforation: Optional[Tuple[int, int] | list[int] | dict[int, float]] = None,
+    # The order in which the layers below the source, or the perforated layers, are injected into: "top_down",
+    # "bottom_up", or "interleaved" for all layers at once with equal shares. Changes which traps fill first.
+    sweep_order: str = "top_down",
+    # Replace the built-in spreading rules with a list of (dx, dy, dz) offsets, each with an optional weight that is
+    # added to the depth of the cells it reaches, e.g. [(0, 0, -1), (2, 0, 0, 0.5), (-2, 0, 0, 0.5)]. Offsets with a
+    # negative dz move the CO2 up and are tried first; the others only when none of them reached an empty cell.
+    stencil: Optional[list[Tuple[int, int, int] | Tuple[int, int, int, float]]] = None,
+    # The key the front fills its cells by, smallest first: "depth" for buoyancy-dominated fill-and-spill,
+    # "seal_potential" for the depth below the local seal, which migrates along every seal before filling deeper
+    # below it, or "well_distance" for the radial spreading of a viscous-dominated injection
+    front_ordering: str = "depth",
+    # The z index of the top of the basement in every column, shape (nx, ny), with nz for no basement. The wells
+    # stop descending above it instead of at the base of the model, and the basement is never filled.
+    basement_indices: Optional[IndexArray] = None,
+    # Which caprock cells break together: "single_cell", "chimney" for a 3x3 chimney around the broken cell, or
+    # "threshold" for the whole seal above a CO2 column of at least one cell. A (name, size) tuple sets the chimney
+    # width, e.g. ("chimney", 5), or the minimum column height of the threshold.
+    breach_geometry: str | Tuple[str, int] = "single_cell",
+    # Re-seal a broken caprock cell once this many cells have filled with CO2 that leaked through it, for fracture
+    # closure. None keeps breaches open for good. The breach_events of the extras hold resealed_at, the cells filled when it re-sealed.
+    reseal_after: Optional[int] = None,
+    # License-boundary check: the largest horizontal distance in cells from the closest well, and the largest
+    # distances along (+x, -x, +y, -y). The extras then hold lateral_exceedance, the first filled cell beyond the
+    # limit, or None. The simulation only stops there with stop_at_lateral_limit.
+    max_lateral_distance: Optional[float] = None,
+    max_lateral_offsets: Optional[Tuple[float, float, float, float]] = None,
+    stop_at_lateral_limit: bool = False,
+    # Coordinates of the cells along x and y in the extras, e.g. UTM eastings and northings. Defaults to the indices.
+    x_coordinates: Optional[NDArray[np.float64]] = None,
+    y_coordinates: Optional[NDArray[np.float64]] = None,
+    # Bit-for-bit reproducible run: cells whose depths differ only by rounding error are filled in the order they
+    # were reached, so the same inputs give the same snapshots on every platform
+    deterministic: bool = False,
+    # Warm start: the cells holding CO2 before the injection, as a boolean mask, e.g. a plume interpreted from
+    # seismic, or the snapshots of an earlier run. The new CO2 pools below and around it, and the snapshots only hold
+    # the cells filled in this run.
+    initial_plume: Optional[NDArray[np.bool_] | NDArray[np.int32]] = None,
+    # Write every fill, breach, completed snapshot and layer change to this file as line-delimited JSON while the
+    # simulation runs, as an audit trail and for replay and visualization tools
+    journal_path: Optional[str | os.PathLike[str]] = None,
+    # Check the physical invariants of the plume as the simulation runs and stop at the first broken one, which the
+    # extras then hold as invariant_violation. Slow, for debugging the engine.
+    paranoid: bool = False,
+) -> NDArray[np.int32] | dict[str, Any]:  # (nx, ny, nz)
+    # The Rust backend accepts float32/float64 and int32/int64/uint64 arrays directly,
+    # and raises a TypeError for any other dtype.
+    # Ctrl+C raises SimulationInterrupted (a KeyboardInterrupt) whose snapshots attribute holds the partial result.
     snapshots = _injection_simulation_python_wrapper(
         reservoir_matrix=reservoir_matrix,
         depths=depths,
@@ -29,6 +174,401 @@ def injection_simulation(
         max_column_height=max_column_height,
         source=source,
         total_snapshots=total_snapshots,
+        progress_callback=progress_callback,
+        progress_interval=progress_interval,
+        return_extras=return_extras,
+        injection_schedule=injection_schedule,
+        velocity_tolerance=velocity_tolerance,
+        primary_caprock_indices=primary_caprock_indices,
+        containment_polygon=containment_polygon,
+        stop_at_surface=stop_at_surface,
+        audit=audit,
+        pressure_model=pressure_model,
+        pressure_limit=pressure_limit,
+        stress_criterion=stress_criterion,
+        mass_accounting=mass_accounting,
+        aquifer_flow=aquifer_flow,
+        thermal_zone=thermal_zone,
+        co2_stream=co2_stream,
+        stochastic_spreading=stochastic_spreading,
+        smoothing=smoothing,
+        perforation=perforation,
+        sweep_order=sweep_order,
+        stencil=stencil,
+        front_ordering=front_ordering,
+        basement_indices=basement_indices,
+        breach_geometry=breach_geometry,
+        reseal_after=reseal_after,
+        max_lateral_distance=max_lateral_distance,
+        max_lateral_offsets=max_lateral_offsets,
+        stop_at_lateral_limit=stop_at_lateral_limit,
+        x_coordinates=x_coordinates,
+        y_coordinates=y_coordinates,
+        deterministic=deterministic,
+        initial_plume=initial_plume,
+        journal_path=journal_path,
+        paranoid=paranoid,
     )
 
     return snapshots
+
+
+def injection_simulation_window(
+    reservoir_matrix: FloatArray,  # (wx, wy, wz) window of the full model, e.g. a dask block
+    depths: FloatArray,  # (wz,) depths of the layers in the window
+    # (wx, wy) z indices in the full model, which are shifted into the window and clipped to it.
+    # Computed from the window if None.
+    bedrock_indices: Optional[IndexArray],
+    max_column_height: Optional[int],
+    source: Tuple[int, int, int] | list[Tuple[int, int, int]],  # Indices in the full model
+    offset: Tuple[int, int, int],  # Index in the full model of the first cell of the window
+    # Passed on to injection_simulation. Other index arguments, like perforation, are relative to the window.
+    **kwargs: Any,
+) -> dict[str, Any]:
+    # Simulate on a region-of-interest subgrid of a larger model, so dask pipelines can orchestrate many windowed
+    # runs over basin-scale datasets. Returns a dict with the snapshots of the window (and the extras with
+    # return_extras, whose cell indices are relative to the window), the offset, and the window as slices into the
+    # full model, so full[results["window"]] = results["snapshots"] places the result. With return_extras the x and
+    # y coordinates default to the indices in the full model.
+    shape = reservoir_matrix.shape
+    sources = [source] if isinstance(source, tuple) else list(source)
+    local_sources = [tuple(int(i) - o for i, o in zip(cell, offset)) for cell in sources]
+    for cell, local in zip(sources, local_sources):
+        if not all(0 <= i < n for i, n in zip(local, shape)):
+            raise ValueError(f"The source {tuple(cell)} is outside the window at {tuple(offset)} of shape {shape}")
+
+    if bedrock_indices is not None:
+        bedrock_indices = np.clip(np.asarray(bedrock_indices, dtype=np.int64) - offset[2], 0, shape[2] - 1)
+    if kwargs.get("return_extras"):
+        kwargs.setdefault("x_coordinates", np.arange(offset[0], offset[0] + shape[0], dtype=np.float64))
+        kwargs.setdefault("y_coordinates", np.arange(offset[1], offset[1] + shape[1], dtype=np.float64))
+
+    results = injection_simulation(
+        reservoir_matrix,
+        depths,
+        bedrock_indices,
+        max_column_height,
+        local_sources[0] if isinstance(source, tuple) else local_sources,
+        **kwargs,
+    )
+    if not isinstance(results, dict):
+        results = {"snapshots": results}
+    results["offset"] = tuple(offset)
+    results["window"] = tuple(slice(o, o + n) for o, n in zip(offset, shape))
+    return results
+
+
+def injection_simulation_iter(
+    reservoir_matrix: FloatArray,  # (nx, ny, nz)
+    depths: FloatArray,  # (nz,)
+    # (nx, ny), computed from the topmost caprock layer of each column if None
+    bedrock_indices: Optional[IndexArray],
+    max_column_height: Optional[int],  # None never breaks the caprock
+    source: Tuple[int, int, int],
+    total_snapshots: int = 100,  # Number of snapshots to capture
+    dense: bool = False,  # Yield the full (nx, ny, nz) fill-order array instead of the new cells
+    velocity_tolerance: float = 0.0,  # See injection_simulation
+) -> Iterator[Tuple[int, NDArray]]:
+    # Yields (snapshot_index, cells) as the simulation advances. By default cells is an (n, 3)
+    # array with the (x, y, z) indices filled during that snapshot, which is cheap enough for live plotting.
+    return _injection_simulation_iterator(
+        reservoir_matrix=reservoir_matrix,
+        depths=depths,
+        bedrock_indices=bedrock_indices,
+        max_column_height=max_column_height,
+        source=source,
+        total_snapshots=total_snapshots,
+        dense=dense,
+        velocity_tolerance=velocity_tolerance,
+    )
+
+
+def flood_fill(
+    reservoir_matrix: FloatArray,  # (nx, ny, nz)
+    depths: FloatArray,  # (nz,)
+    bedrock_indices: Optional[IndexArray],  # (nx, ny), see injection_simulation
+    source: Tuple[int, int, int] | list[Tuple[int, int, int]],
+    total_snapshots: int = 100,
+    velocity_tolerance: float = 0.0,
+) -> NDArray[np.int32]:
+    # Fast baseline that fills every reservoir cell connected to the source in breadth-first order,
+    # ignoring depth and column heights. Returns the snapshots in the same format as injection_simulation.
+    return _flood_fill_python_wrapper(
+        reservoir_matrix=reservoir_matrix,
+        depths=depths,
+        bedrock_indices=bedrock_indices,
+        source=source,
+        total_snapshots=total_snapshots,
+        velocity_tolerance=velocity_tolerance,
+    )
+
+def cross_section_simulation(
+    reservoir_matrix: FloatArray,  # (n, nz), a vertical section
+    depths: FloatArray,  # (nz,)
+    bedrock_indices: Optional[IndexArray],  # (n,), computed from the section if None
+    max_column_height: Optional[int],
+    source: Tuple[int, int],  # (i, z)
+    total_snapshots: int = 100,
+    velocity_tolerance: float = 0.0,
+) -> NDArray[np.int32]:
+    # 2D inputs and outputs for cross-sectional studies and teaching examples. The section is run by the 3D
+    # engine as a grid one cell wide with the in-plane moves only, and the snapshots come back as an (n, nz)
+    # array, the same fill as injection_simulation on an (n, 1, nz) or (1, n, nz) grid.
+    return _cross_section_python_wrapper(
+        reservoir_matrix=reservoir_matrix,
+        depths=depths,
+        bedrock_indices=bedrock_indices,
+        max_column_height=max_column_height,
+        source=source,
+        total_snapshots=total_snapshots,
+        velocity_tolerance=velocity_tolerance,
+    )
+
+def darcy_flow(
+    reservoir_matrix: FloatArray,  # (nx, ny, nz)
+    depths: FloatArray,  # (nz,) in m
+    bedrock_indices: Optional[IndexArray],  # (nx, ny), see injection_simulation
+    source: Tuple[int, int, int] | list[Tuple[int, int, int]],
+    permeability: FloatArray,  # (nx, ny, nz) in m^2
+    porosity: FloatArray,  # (nx, ny, nz)
+    cell_size: Tuple[float, float, float],  # (dx, dy, dz) in m
+    injection_rate: float,  # m^3/s of CO2 at reservoir conditions, shared between the wells
+    injection_time: float,  # s, split into total_snapshots equal intervals
+    total_snapshots: int = 100,
+    # Densities (kg/m^3), viscosities (Pa s) and the Corey exponent, e.g.
+    # {"brine_density": 1030, "co2_density": 650, "brine_viscosity": 5e-4, "co2_viscosity": 5e-5, "corey_exponent": 2}
+    fluid_properties: Optional[dict[str, float]] = None,
+    saturation_threshold: float = 0.05,  # A cell counts as filled from this CO2 saturation
+    velocity_tolerance: float = 0.0,
+    return_extras: bool = False,  # Return a dict with the final "saturation" and "pressure" as well
+) -> NDArray[np.int32] | dict[str, Any]:
+    # Pressure-driven alternative engine: incompressible two-phase flow solved with IMPES. The displaced
+    # brine leaves through the lateral edges of the grid. The snapshots have the same format as
+    # injection_simulation, so the results can be compared directly.
+    return _darcy_flow_python_wrapper(
+        reservoir_matrix=reservoir_matrix,
+        depths=depths,
+        bedrock_indices=bedrock_indices,
+        source=source,
+        permeability=permeability,
+        porosity=porosity,
+        cell_size=cell_size,
+        injection_rate=injection_rate,
+        injection_time=injection_time,
+        total_snapshots=total_snapshots,
+        fluid_properties=fluid_properties,
+        saturation_threshold=saturation_threshold,
+        velocity_tolerance=velocity_tolerance,
+        return_extras=return_extras,
+    )
+
+def export_opm_deck(
+    path: str | os.PathLike[str],  # The .DATA file to write
+    reservoir_matrix: FloatArray,  # (nx, ny, nz)
+    depths: FloatArray,  # (nz,) in m
+    bedrock_indices: Optional[IndexArray],  # (nx, ny), see injection_simulation
+    source: Tuple[int, int, int] | list[Tuple[int, int, int]],
+    permeability: FloatArray,  # (nx, ny, nz) in m^2
+    porosity: FloatArray,  # (nx, ny, nz)
+    cell_size: Tuple[float, float, float],  # (dx, dy, dz) in m
+    injection_rate: float,  # m^3/s of CO2 at reservoir conditions, shared between the wells
+    injection_time: float,  # s, split into total_snapshots report steps
+    total_snapshots: int = 100,
+    fluid_properties: Optional[dict[str, float]] = None,  # See darcy_flow
+    velocity_tolerance: float = 0.0,
+) -> None:
+    # Write an OPM Flow / Eclipse input deck of the darcy_flow scenario with the same arguments: grid, PORO/PERM,
+    # relative permeabilities, fluids, one gas injector per source and a report step per snapshot. Running it with
+    # `flow` gives a full-physics reference to compare the snapshots of the other engines with.
+    _opm_deck_python_wrapper(
+        path=path,
+        reservoir_matrix=reservoir_matrix,
+        depths=depths,
+        bedrock_indices=bedrock_indices,
+        source=source,
+        permeability=permeability,
+        porosity=porosity,
+        cell_size=cell_size,
+        injection_rate=injection_rate,
+        injection_time=injection_time,
+        total_snapshots=total_snapshots,
+        fluid_properties=fluid_properties,
+        velocity_tolerance=velocity_tolerance,
+    )
+
+def invasion_percolation(
+    reservoir_matrix: FloatArray,  # (nx, ny, nz)
+    depths: FloatArray,  # (nz,)
+    bedrock_indices: Optional[IndexArray],  # (nx, ny), see injection_simulation
+    source: Tuple[int, int, int],
+    # (nx, ny, nz) capillary entry pressure of every cell in Pa, e.g. from permeability with
+    # Leverett scaling. Cells with an infinite entry pressure are never invaded.
+    entry_pressures: Optional[FloatArray] = None,
+    density_difference: float = 0.0,  # Brine minus CO2 density in kg/m^3, penalizes deeper cells
+    total_snapshots: int = 100,
+    velocity_tolerance: float = 0.0,
+    # Generate correlated lognormal entry pressures instead, e.g.
+    # {"median": 1e4, "log_std": 1.0, "correlation_lengths": (10, 10, 2), "seed": 0}
+    random_thresholds: Optional[dict[str, Any]] = None,
+) -> NDArray[np.int32]:
+    # Alternative engine where the front always invades the accessible cell with the lowest
+    # entry pressure. Returns the snapshots in the same format as injection_simulation.
+    return _invasion_percolation_python_wrapper(
+        reservoir_matrix=reservoir_matrix,
+        depths=depths,
+        bedrock_indices=bedrock_indices,
+        source=source,
+        entry_pressures=entry_pressures,
+        density_difference=density_difference,
+        total_snapshots=total_snapshots,
+        velocity_tolerance=velocity_tolerance,
+        random_thresholds=random_thresholds,
+    )
+
+
+def particle_tracking(
+    reservoir_matrix: FloatArray,  # (nx, ny, nz)
+    depths: FloatArray,  # (nz,)
+    bedrock_indices: Optional[IndexArray],  # (nx, ny), see injection_simulation
+    source: Tuple[int, int, int],
+    n_particles: int = 10000,  # Released evenly over the snapshots
+    particles_per_cell: int = 10,  # Particles in a cell at full saturation
+    buoyancy: float = 0.9,  # Probability of a buoyant step up, or sideways under a seal, in [0, 1)
+    dispersion: float = 0.5,  # Probability of wandering on instead of settling, in [0, 1)
+    max_steps: int = 1000,  # After this a particle settles in the first cell with room
+    seed: int = 0,
+    total_snapshots: int = 100,
+    velocity_tolerance: float = 0.0,
+    return_extras: bool = False,  # Return a dict with the "saturation" of every snapshot as well
+) -> NDArray[np.int32] | dict[str, Any]:
+    # Alternative engine that moves particles from the well with buoyancy-dominated random walks.
+    # Gives dispersed, smoother plume fronts on coarse grids. The snapshots have the same format as
+    # injection_simulation.
+    return _particle_tracking_python_wrapper(
+        reservoir_matrix=reservoir_matrix,
+        depths=depths,
+        bedrock_indices=bedrock_indices,
+        source=source,
+        n_particles=n_particles,
+        particles_per_cell=particles_per_cell,
+        buoyancy=buoyancy,
+        dispersion=dispersion,
+        max_steps=max_steps,
+        seed=seed,
+        total_snapshots=total_snapshots,
+        velocity_tolerance=velocity_tolerance,
+        return_extras=return_extras,
+    )
+
+def column_fill(
+    reservoir_matrix: FloatArray,  # (nx, ny, nz)
+    depths: FloatArray,  # (nz,) in metres
+    bedrock_indices: Optional[IndexArray],  # (nx, ny), see injection_simulation
+    source: Tuple[int, int, int],
+    injected_volume: float,  # CO2 volume in cell pore volumes, injected evenly over the snapshots
+    residual_brine_saturation: float = 0.2,  # Caps the CO2 saturation at 1 minus this
+    entry_pressure: float = 5e3,  # Capillary entry pressure in Pa, 0 gives a sharp interface
+    pore_size_index: float = 2.0,  # Brooks-Corey index, larger values give a thinner transition zone
+    density_difference: float = 400.0,  # Brine minus CO2 density in kg/m3
+    total_snapshots: int = 100,
+    velocity_tolerance: float = 0.0,
+    # Return a dict with the "saturation" of every snapshot and the "stored_volume" as well
+    return_extras: bool = False,
+) -> NDArray[np.int32] | dict[str, Any]:
+    # Alternative engine that fills the columns under the local seal with a vertical-equilibrium
+    # saturation profile, so cells near the CO2-brine contact stay in the capillary transition zone.
+    # Approximates gravity segregation in thick reservoir intervals. The snapshots have the same format
+    # as injection_simulation.
+    return _column_fill_python_wrapper(
+        reservoir_matrix=reservoir_matrix,
+        depths=depths,
+        bedrock_indices=bedrock_indices,
+        source=source,
+        injected_volume=injected_volume,
+        residual_brine_saturation=residual_brine_saturation,
+        entry_pressure=entry_pressure,
+        pore_size_index=pore_size_index,
+        density_difference=density_difference,
+        total_snapshots=total_snapshots,
+        velocity_tolerance=velocity_tolerance,
+        return_extras=return_extras,
+    )
+
+def trap_analysis(
+    reservoir_matrix: FloatArray,  # (nx, ny, nz)
+    depths: FloatArray,  # (nz,)
+    bedrock_indices: Optional[IndexArray] = None,  # (nx, ny), see injection_simulation
+    velocity_tolerance: float = 0.0,
+) -> dict[str, Any]:
+    # Find the structural traps under the caprock and how they spill into each other, without
+    # simulating any injection. Returns a dict with the (nx, ny) "top_surface" depths, the (nx, ny)
+    # "trap_map" with the innermost trap of every column (or -1), and the "traps". Each trap is a dict
+    # with its top and spill depths, spill point, columns, volume (summed column heights), the trap it
+    # "spills_into" (None leaves the grid) and the "sub_traps" that merge into it.
+    return _trap_analysis_python_wrapper(
+        reservoir_matrix=reservoir_matrix,
+        depths=depths,
+        bedrock_indices=bedrock_indices,
+        velocity_tolerance=velocity_tolerance,
+    )
+
+def benchmark_model(
+    name: str,  # "flat_aquifer", "sleipner_dome", "tilted_fault_block" or "layered_thief_zone"
+    resolution: int = 32,  # Cells along x and y, with half as many layers; at least 16
+) -> dict[str, Any]:
+    # Build one of the shared reference models for comparing runs. Returns a dict with the
+    # "reservoir_matrix", "depths", "bedrock_indices" and "source", which can be passed straight
+    # on: injection_simulation(**benchmark_model("sleipner_dome", 64), max_column_height=None).
+    return _benchmark_model_python_wrapper(name=name, resolution=resolution)
+
+def analytic_validation(
+    case: str = "flat_seal",  # "flat_seal" or "paraboloid_dome"
+    resolution: int = 64,  # Cells along x and y; at least 16
+    n_points: int = 10,  # Number of plume sizes to compare at
+    front_ordering: str = "depth",  # See injection_simulation
+) -> dict[str, Any]:
+    # Compare the simulated plume with the closed-form sharp-interface solution for a flat seal or a
+    # paraboloid dome, with the well at the axis. Returns a dict with the "points" (the analytic and
+    # simulated radius and thickness, in cells and layers, at every plume size) and the relative
+    # "radius_error", "max_radius_error" and "thickness_error" as mean_abs, max_abs and rms.
+    return json.loads(
+        _analytic_validation_python_wrapper(
+            case=case,
+            resolution=resolution,
+            n_points=n_points,
+            front_ordering=front_ordering,
+        )
+    )
+
+def repair_grid(
+    reservoir_matrix: FloatArray,  # (nx, ny, nz)
+    depths: FloatArray,  # (nz,)
+    velocity_tolerance: float = 0.0,
+) -> dict[str, Any]:
+    # Find and repair the common pathologies of a grid before a run: depths that decrease with z (the layers are
+    # reversed), velocities that are not one of the rock velocities (snapped to the nearest), reservoir cells
+    # enclosed by caprock (made caprock) and caprock with no reservoir below it (made inactive, NaN). Returns a
+    # dict with the repaired "reservoir_matrix", "depths" and "bedrock_indices", and the "issues", each a dict with
+    # its "kind", the "cell" in the layer order of the input (None for the depths), the velocity "before" and
+    # "after", and whether it was "repairable".
+    return _repair_grid_python_wrapper(
+        reservoir_matrix=reservoir_matrix,
+        depths=depths,
+        velocity_tolerance=velocity_tolerance,
+    )
+
+def replay_journal(
+    journal_path: str | os.PathLike[str],  # Written by injection_simulation with journal_path
+    cells_filled: Optional[int] = None,  # Stop once this many cells are filled
+    snapshot: Optional[int] = None,  # Stop once this snapshot is complete, if cells_filled is None
+) -> dict[str, Any]:
+    # Rebuild a recorded run from its journal without re-running the physics, at the end of the run or at the given
+    # point of it, for debugging long runs after the fact. Returns a dict with the "snapshots" and the
+    # "well_attribution" so far, which match the outputs of the run, and the "cells_filled", the last complete
+    # "snapshot_index", whether the run "finished", the "sources", the current "layers", the number of "breaches"
+    # and the "open_breaches" not yet resealed.
+    return _replay_journal_python_wrapper(
+        journal_path=journal_path,
+        cells_filled=cells_filled,
+        snapshot=snapshot,
+    )
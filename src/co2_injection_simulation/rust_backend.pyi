@@ -1,13 +1,291 @@
-from typing import Tuple
+import os
+from typing import Any, Callable, Iterator, Optional, Tuple
 
 import numpy as np
 from numpy.typing import NDArray
 
+ProgressCallback = Callable[[float, int, int], None]
+FloatArray = NDArray[np.float32] | NDArray[np.float64]
+IndexArray = NDArray[np.int32] | NDArray[np.int64] | NDArray[np.uint64]
+
+class SimulationInterrupted(KeyboardInterrupt):
+    snapshots: NDArray[np.int32]
+    cells_filled: int
+    snapshot_index: int
+
 def _injection_simulation_python_wrapper(
-    reservoir_matrix: NDArray[np.float64],
-    depths: NDArray[np.float64],
-    bedrock_indices: NDArray[np.int32],
-    max_column_height: int,
+    reservoir_matrix: FloatArray,
+    depths: FloatArray,
+    bedrock_indices: Optional[IndexArray],
+    max_column_height: Optional[int],
+    source: Tuple[int, int, int] | list[Tuple[int, int, int]],
+    total_snapshots: int = 100,
+    progress_callback: Optional[ProgressCallback] = None,
+    progress_interval: int = 1000,
+    return_extras: bool = False,
+    injection_schedule: Optional[list[Tuple[str, float]]] = None,
+    velocity_tolerance: float = 0.0,
+    primary_caprock_indices: Optional[IndexArray] = None,
+    containment_polygon: Optional[list[Tuple[float, float]]] = None,
+    stop_at_surface: bool = False,
+    audit: bool = False,
+    pressure_model: Optional[dict[str, Any]] = None,
+    pressure_limit: Optional[dict[str, float]] = None,
+    stress_criterion: Optional[dict[str, float]] = None,
+    mass_accounting: Optional[dict[str, Any]] = None,
+    aquifer_flow: Optional[dict[str, Any]] = None,
+    thermal_zone: Optional[dict[str, float]] = None,
+    co2_stream: Optional[dict[str, float]] = None,
+    stochastic_spreading: Optional[dict[str, Any]] = None,
+    smoothing: Optional[dict[str, float]] = None,
+    perforation: Optional[Tuple[int, int] | list[int] | dict[int, float]] = None,
+    sweep_order: str = "top_down",
+    stencil: Optional[list[Tuple[int, int, int] | Tuple[int, int, int, float]]] = None,
+    front_ordering: str = "depth",
+    basement_indices: Optional[IndexArray] = None,
+    breach_geometry: Optional[str | Tuple[str, int]] = None,
+    reseal_after: Optional[int] = None,
+    max_lateral_distance: Optional[float] = None,
+    max_lateral_offsets: Optional[Tuple[float, float, float, float]] = None,
+    stop_at_lateral_limit: bool = False,
+    x_coordinates: Optional[FloatArray] = None,
+    y_coordinates: Optional[FloatArray] = None,
+    deterministic: bool = False,
+    initial_plume: Optional[NDArray[np.bool_] | NDArray[np.int32] | NDArray[np.int64]] = None,
+    journal_path: Optional[str | os.PathLike[str]] = None,
+    paranoid: bool = False,
+) -> NDArray[np.int32] | dict[str, Any]: ...
+
+class ArrowStream:
+    def __arrow_c_schema__(self) -> object: ...
+    def __arrow_c_stream__(self, requested_schema: object | None = None) -> object: ...
+
+class SnapshotIterator(Iterator[Tuple[int, NDArray[np.int64] | NDArray[np.int32]]]):
+    def __iter__(self) -> SnapshotIterator: ...
+    def __next__(self) -> Tuple[int, NDArray[np.int64] | NDArray[np.int32]]: ...
+
+def _injection_simulation_iterator(
+    reservoir_matrix: FloatArray,
+    depths: FloatArray,
+    bedrock_indices: Optional[IndexArray],
+    max_column_height: Optional[int],
     source: Tuple[int, int, int],
     total_snapshots: int = 100,
+    dense: bool = False,
+    velocity_tolerance: float = 0.0,
+) -> SnapshotIterator: ...
+
+def _darcy_flow_python_wrapper(
+    reservoir_matrix: FloatArray,
+    depths: FloatArray,
+    bedrock_indices: Optional[IndexArray],
+    source: Tuple[int, int, int] | list[Tuple[int, int, int]],
+    permeability: FloatArray,
+    porosity: FloatArray,
+    cell_size: Tuple[float, float, float],
+    injection_rate: float,
+    injection_time: float,
+    total_snapshots: int = 100,
+    fluid_properties: Optional[dict[str, float]] = None,
+    saturation_threshold: float = 0.05,
+    velocity_tolerance: float = 0.0,
+    return_extras: bool = False,
+) -> NDArray[np.int32] | dict[str, Any]: ...
+
+def _opm_deck_python_wrapper(
+    path: str | os.PathLike[str],
+    reservoir_matrix: FloatArray,
+    depths: FloatArray,
+    bedrock_indices: Optional[IndexArray],
+    source: Tuple[int, int, int] | list[Tuple[int, int, int]],
+    permeability: FloatArray,
+    porosity: FloatArray,
+    cell_size: Tuple[float, float, float],
+    injection_rate: float,
+    injection_time: float,
+    total_snapshots: int = 100,
+    fluid_properties: Optional[dict[str, float]] = None,
+    velocity_tolerance: float = 0.0,
+) -> None: ...
+
+def _flood_fill_python_wrapper(
+    reservoir_matrix: FloatArray,
+    depths: FloatArray,
+    bedrock_indices: Optional[IndexArray],
+    source: Tuple[int, int, int] | list[Tuple[int, int, int]],
+    total_snapshots: int = 100,
+    velocity_tolerance: float = 0.0,
 ) -> NDArray[np.int32]: ...
+
+def _cross_section_python_wrapper(
+    reservoir_matrix: FloatArray,
+    depths: FloatArray,
+    bedrock_indices: Optional[IndexArray],
+    max_column_height: Optional[int],
+    source: Tuple[int, int],
+    total_snapshots: int = 100,
+    velocity_tolerance: float = 0.0,
+) -> NDArray[np.int32]: ...
+
+def _invasion_percolation_python_wrapper(
+    reservoir_matrix: FloatArray,
+    depths: FloatArray,
+    bedrock_indices: Optional[IndexArray],
+    source: Tuple[int, int, int],
+    entry_pressures: Optional[FloatArray] = None,
+    density_difference: float = 0.0,
+    total_snapshots: int = 100,
+    velocity_tolerance: float = 0.0,
+    random_thresholds: Optional[dict[str, Any]] = None,
+) -> NDArray[np.int32]: ...
+
+def _particle_tracking_python_wrapper(
+    reservoir_matrix: FloatArray,
+    depths: FloatArray,
+    bedrock_indices: Optional[IndexArray],
+    source: Tuple[int, int, int],
+    n_particles: int = 10000,
+    particles_per_cell: int = 10,
+    buoyancy: float = 0.9,
+    dispersion: float = 0.5,
+    max_steps: int = 1000,
+    seed: int = 0,
+    total_snapshots: int = 100,
+    velocity_tolerance: float = 0.0,
+    return_extras: bool = False,
+) -> NDArray[np.int32] | dict[str, Any]: ...
+
+def _column_fill_python_wrapper(
+    reservoir_matrix: FloatArray,
+    depths: FloatArray,
+    bedrock_indices: Optional[IndexArray],
+    source: Tuple[int, int, int],
+    injected_volume: float,
+    residual_brine_saturation: float = 0.2,
+    entry_pressure: float = 5e3,
+    pore_size_index: float = 2.0,
+    density_difference: float = 400.0,
+    total_snapshots: int = 100,
+    velocity_tolerance: float = 0.0,
+    return_extras: bool = False,
+) -> NDArray[np.int32] | dict[str, Any]: ...
+
+def _trap_analysis_python_wrapper(
+    reservoir_matrix: FloatArray,
+    depths: FloatArray,
+    bedrock_indices: Optional[IndexArray] = None,
+    velocity_tolerance: float = 0.0,
+) -> dict[str, Any]: ...
+
+def _benchmark_model_python_wrapper(
+    name: str,
+    resolution: int = 32,
+) -> dict[str, Any]: ...
+
+def _analytic_validation_python_wrapper(
+    case: str,
+    resolution: int = 64,
+    n_points: int = 10,
+    front_ordering: str = "depth",
+) -> str: ...
+
+def _repair_grid_python_wrapper(
+    reservoir_matrix: FloatArray,
+    depths: FloatArray,
+    velocity_tolerance: float = 0.0,
+) -> dict[str, Any]: ...
+
+def _replay_journal_python_wrapper(
+    journal_path: str | os.PathLike[str],
+    cells_filled: Optional[int] = None,
+    snapshot: Optional[int] = None,
+) -> dict[str, Any]: ...
+
+class Simulation:
+    def __init__(
+        self,
+        reservoir_matrix: FloatArray,
+        depths: FloatArray,
+        bedrock_indices: Optional[IndexArray],
+        source: Tuple[int, int, int],
+        max_column_height: Optional[int] = 10,
+        total_snapshots: int = 100,
+        anisotropy: Tuple[int, int] = (1, 1),
+        velocity_tolerance: float = 0.0,
+        aquifer_flow: Optional[dict[str, Any]] = None,
+        rewind_points: bool = False,
+    ) -> None: ...
+    def run(
+        self,
+        progress_callback: Optional[ProgressCallback] = None,
+        progress_interval: int = 1000,
+    ) -> NDArray[np.int32]: ...
+    def step(self, n_cells: int = 1) -> bool: ...
+    def save_checkpoint(self, path: str | os.PathLike[str]) -> None: ...
+    @staticmethod
+    def restore(path: str | os.PathLike[str]) -> Simulation: ...
+    def rewound(self, snapshot_index: int) -> Simulation: ...
+    def branch(
+        self,
+        new_wells: Optional[list[Tuple[int, int, int]]] = None,
+        rate_changes: Optional[list[Tuple[str, float]]] = None,
+    ) -> Simulation: ...
+    def with_edited_cells(
+        self, edits: list[Tuple[Tuple[int, int, int], float]]
+    ) -> Simulation: ...
+    @property
+    def max_column_height(self) -> Optional[int]: ...
+    @max_column_height.setter
+    def max_column_height(self, value: Optional[int]) -> None: ...
+    def result(self) -> NDArray[np.int32]: ...
+    def saturation(self) -> NDArray[np.float32]: ...
+    def velocity_model(self) -> NDArray[np.float64]: ...
+    def export_plume_mesh(self, path: str | os.PathLike[str], snapshot: Optional[int] = None) -> None: ...
+    def export_mat(self, path: str | os.PathLike[str]) -> None: ...
+    def export_cell_events(self, path: str | os.PathLike[str]) -> None: ...
+    def cell_events_arrow(self) -> ArrowStream: ...
+    def export_grdecl(
+        self, path: str | os.PathLike[str], cell_size: Tuple[float, float] = (1.0, 1.0)
+    ) -> None: ...
+    def convective_dissolution(
+        self,
+        rate: float,
+        n_steps: int,
+        mineralization_rate: float = 0.0,
+        residual_saturation: float = 0.0,
+    ) -> dict[str, NDArray[Any]]: ...
+    def relax(
+        self,
+        rate: float,
+        n_steps: int,
+        mineralization_rate: float = 0.0,
+        residual_saturation: float = 0.0,
+    ) -> dict[str, NDArray[Any]]: ...
+    def cell_history(self) -> NDArray[np.uint8]: ...
+    def metadata_json(self) -> str: ...
+    @property
+    def finished(self) -> bool: ...
+    @property
+    def cells_filled(self) -> int: ...
+    @property
+    def fraction_filled(self) -> float: ...
+    @property
+    def nan_cells(self) -> int: ...
+    @property
+    def compartment_cells(self) -> int: ...
+    @property
+    def compartment_warning(self) -> Optional[str]: ...
+    @property
+    def snapshot_index(self) -> int: ...
+    @property
+    def current_depth_index(self) -> int: ...
+    @property
+    def footprint_cells(self) -> int: ...
+    @property
+    def plume_depth_range(self) -> Optional[Tuple[int, int]]: ...
+    @property
+    def plume_cells(self) -> int: ...
+    @property
+    def frontier_size(self) -> int: ...
+    def layer_fill_fractions(self) -> NDArray[np.float64]: ...
+    def is_filled(self, cell: Tuple[int, int, int]) -> bool: ...
@@ -1,3 +1,5 @@
+from typing import Any, Optional, Tuple
+
 import numpy as np
 from numpy.typing import NDArray
 
@@ -28,10 +30,19 @@ def get_reservoir_from_snapshot(
     caprock_matrix: NDArray[np.float64],
     snapshots: NDArray[np.float64],
     snapshot_value: int,
+    source: Optional[Tuple[int, int, int]] = None,
+    thermal_zone: Optional[dict[str, Any]] = None,
 ):
     reservoir_matrix = np.copy(caprock_matrix)
-    indexes = np.where((snapshots <= snapshot_value) & (snapshots != -1))
-    reservoir_matrix[indexes] = VELOCITY_CO2
+    filled = (snapshots <= snapshot_value) & (snapshots != -1)
+    reservoir_matrix[filled] = VELOCITY_CO2
+    # CO2 within the radius (in cells) of the well column gets the velocity of the cold CO2
+    if source is not None and thermal_zone is not None:
+        nx, ny, _ = reservoir_matrix.shape
+        x, y = np.meshgrid(np.arange(nx), np.arange(ny), indexing="ij")
+        near_well = (x - source[0]) ** 2 + (y - source[1]) ** 2 <= thermal_zone["radius"] ** 2
+        cold = filled & near_well[:, :, np.newaxis]
+        reservoir_matrix[cold] = thermal_zone.get("co2_velocity", VELOCITY_CO2)
     return reservoir_matrix
 
 
@@ -45,3 +56,16 @@ def compute_layers_idx(
     diff = np.abs(layers[..., np.newaxis] - depths)
     layers_idx = np.argmin(diff, axis=-1).astype(np.int32)
     return layers_idx + layer_thickness - 1
+
+
+def results_to_xarray(results: dict[str, Any]):
+    # Label the extras of injection_simulation(..., return_extras=True) as an xarray.Dataset with x, y, depth and
+    # snapshot coordinates. Results with an injection schedule get the completion date of each snapshot as well.
+    import xarray as xr
+
+    coords: dict[str, Any] = dict(results["coordinates"])
+    if results.get("snapshot_dates") is not None:
+        dates = [np.datetime64(date) if date is not None else np.datetime64("NaT") for date in results["snapshot_dates"]]
+        coords["snapshot_date"] = ("snapshot", np.array(dates, dtype="datetime64[D]"))
+    data_vars = {name: (dims, results[name]) for name, dims in results["dims"].items()}
+    return xr.Dataset(data_vars, coords=coords, attrs={"fingerprint": results["fingerprint"]})